@@ -0,0 +1,349 @@
+// Checks for the practical limits of the engine, warning when a plugin gets close to one of them
+// rather than only finding out once the game misbehaves.
+
+use crate::{
+    config::Config,
+    context::{Diagnostic, Severity},
+    parse::{take, ParseError},
+    progress::{Partial, Progress, ProgressState},
+    records::{common::record_flag, fields::common::FIELDH_SIZE},
+    walk::{walk_records, RawRecord},
+};
+use bstr::ByteSlice;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BudgetLimits {
+    /// Largest a field can be without being preceded by an XXXX field
+    pub field_size: usize,
+    /// Amount of masters a plugin can have, as a plugin and its masters share the 0x00..=0xFE indices
+    pub masters: usize,
+    /// Amount of new records a light master can hold (object ids 0x800..=0xFFF). Newer versions of
+    /// the game also allow 0x000..=0x7FF, for 4096
+    pub light_records: usize,
+    /// Amount of overridden references that the TES4 ONAM field can list, going by how many
+    /// fit without an XXXX field
+    pub onam_entries: usize,
+    /// Fraction of a limit at which to start warning
+    pub warn_at: f64,
+}
+impl Default for BudgetLimits {
+    fn default() -> BudgetLimits {
+        BudgetLimits {
+            field_size: u16::MAX as usize,
+            masters: 254,
+            light_records: 2048,
+            onam_entries: u16::MAX as usize / 4,
+            warn_at: 0.9,
+        }
+    }
+}
+impl BudgetLimits {
+    /// How bad [value] is in relation to [limit], if it is bad at all
+    fn rate(&self, value: usize, limit: usize) -> Option<Severity> {
+        if value > limit {
+            Some(Severity::Error)
+        } else if value as f64 >= limit as f64 * self.warn_at {
+            Some(Severity::Warning)
+        } else {
+            None
+        }
+    }
+}
+
+/// Checks the plugin in [data] against [limits]
+pub fn check_budget<'data>(
+    data: &'data [u8],
+    limits: &BudgetLimits,
+) -> Result<Vec<Diagnostic>, ParseError<'data>> {
+    check_budget_with_progress(data, limits, &mut ()).map(|x| x.value)
+}
+
+/// [check_budget], reporting to [progress] after each record. If it is cancelled, the
+/// diagnostics for the records before that are returned, without the checks of the plugin as a
+/// whole.
+pub fn check_budget_with_progress<'data>(
+    data: &'data [u8],
+    limits: &BudgetLimits,
+    progress: &mut dyn Progress,
+) -> Result<Partial<Vec<Diagnostic>>, ParseError<'data>> {
+    let mut diagnostics = Vec::new();
+    let mut masters = 0;
+    let mut light = false;
+    let mut new_records = 0;
+    let mut onam_entries = 0;
+    let mut checked = 0;
+    let mut cancelled = false;
+    let mut result = Ok(());
+
+    walk_records(data, &mut |record| {
+        if result.is_err() || cancelled {
+            return;
+        }
+        if progress.is_cancelled() {
+            cancelled = true;
+            return;
+        }
+        checked += 1;
+        progress.report(&ProgressState {
+            bytes_processed: record.data.as_ptr() as usize - data.as_ptr() as usize
+                + record.data.len(),
+            bytes_total: data.len(),
+            records_converted: checked,
+            ..ProgressState::default()
+        });
+        let diagnose = |severity, message| Diagnostic {
+            severity,
+            record: Some((record.type_name.to_string(), record.id)),
+            message,
+        };
+
+        if record.type_name == "TES4" {
+            light = record.flags & record_flag::LIGHT_MASTER != 0;
+        } else if usize::from(record.id.mod_index()) == masters {
+            new_records += 1;
+        }
+
+        if record.flags & record_flag::COMPRESSED != 0 {
+            return;
+        }
+        result = walk_fields(record, &mut |name, size, extended| {
+            if record.type_name == "TES4" && name == b"MAST" {
+                masters += 1;
+            }
+            if record.type_name == "TES4" && name == b"ONAM" {
+                onam_entries += size / 4;
+            }
+            if extended {
+                diagnostics.push(diagnose(
+                    Severity::Info,
+                    format!(
+                        "{} field is {} bytes, which needs a preceding XXXX field",
+                        name.as_bstr(),
+                        size
+                    ),
+                ));
+            } else if let Some(severity) = limits.rate(size, limits.field_size) {
+                diagnostics.push(diagnose(
+                    severity,
+                    format!(
+                        "{} field is {} bytes, the limit is {}",
+                        name.as_bstr(),
+                        size,
+                        limits.field_size
+                    ),
+                ));
+            }
+        });
+    })?;
+    result?;
+    if cancelled {
+        return Ok(Partial {
+            value: diagnostics,
+            complete: false,
+        });
+    }
+
+    if let Some(severity) = limits.rate(masters, limits.masters) {
+        diagnostics.push(Diagnostic {
+            severity,
+            record: None,
+            message: format!(
+                "Plugin has {} masters, the limit is {}",
+                masters, limits.masters
+            ),
+        });
+    }
+    if let Some(severity) = limits.rate(onam_entries, limits.onam_entries) {
+        diagnostics.push(Diagnostic {
+            severity,
+            record: None,
+            message: format!(
+                "Plugin overrides {} references in ONAM, the limit is {}",
+                onam_entries, limits.onam_entries
+            ),
+        });
+    }
+    if light {
+        if let Some(severity) = limits.rate(new_records, limits.light_records) {
+            diagnostics.push(Diagnostic {
+                severity,
+                record: None,
+                message: format!(
+                    "Light master has {} new records, the limit is {}",
+                    new_records, limits.light_records
+                ),
+            });
+        }
+    }
+
+    Ok(Partial {
+        value: diagnostics,
+        complete: true,
+    })
+}
+
+/// Calls [func] with the name and size of each field, and whether the size came from an XXXX field
+fn walk_fields<'data, F>(record: RawRecord<'data>, func: &mut F) -> Result<(), ParseError<'data>>
+where
+    F: FnMut(&'data [u8], usize, bool),
+{
+    let mut data = record.data;
+    let mut extended_size = None;
+    while !data.is_empty() {
+        let (rest, header) = take(data, FIELDH_SIZE)?;
+        let name = &header[..4];
+        let size = u16::from_le_bytes([header[4], header[5]]) as usize;
+        if name == b"XXXX" {
+            let (rest, value) = take(rest, 4)?;
+            extended_size = Some(u32::from_le_bytes([value[0], value[1], value[2], value[3]]));
+            data = rest;
+            continue;
+        }
+
+        let (size, extended) = match extended_size.take() {
+            Some(size) => (size as usize, true),
+            None => (size, false),
+        };
+        let (rest, _) = take(rest, size)?;
+        func(name, size, extended);
+        data = rest;
+    }
+    Ok(())
+}
+
+/// `vivec budget <plugin>... [--warn-at <fraction>] [--light-records <amount>]
+/// [--onam-entries <amount>]`
+pub fn budget_command(args: &[String], config: &Config) {
+    let mut limits = BudgetLimits::default();
+    if let Some(warn_at) = config.validation.budget_warn_at {
+        limits.warn_at = warn_at;
+    }
+    if let Some(light_records) = config.validation.budget_light_records {
+        limits.light_records = light_records;
+    }
+    if let Some(onam_entries) = config.validation.budget_onam_entries {
+        limits.onam_entries = onam_entries;
+    }
+    let amount = |value: Option<&String>, flag: &str| {
+        value
+            .and_then(|x| x.parse().ok())
+            .unwrap_or_else(|| panic!("Expected amount after {}", flag))
+    };
+    let mut paths = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--warn-at" => {
+                limits.warn_at = args
+                    .next()
+                    .and_then(|x| x.parse().ok())
+                    .expect("Expected fraction after --warn-at")
+            }
+            "--light-records" => limits.light_records = amount(args.next(), arg),
+            "--onam-entries" => limits.onam_entries = amount(args.next(), arg),
+            _ => paths.push(arg),
+        }
+    }
+
+    let paths = config
+        .plugin_paths(&paths)
+        .unwrap_or_else(|e| panic!("{}", e));
+    for path in paths {
+        let data = std::fs::read(&path).expect("Failed to read data from file");
+        let diagnostics = check_budget(&data, &limits)
+            .unwrap_or_else(|e| panic!("Failed to walk {}: {:?}", path.display(), e));
+        for diagnostic in diagnostics {
+            println!("{}: {}", path.display(), diagnostic);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field(name: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut field = name.to_vec();
+        field.extend_from_slice(&(data.len() as u16).to_le_bytes());
+        field.extend_from_slice(data);
+        field
+    }
+
+    fn record(type_name: &[u8], flags: u32, id: u32, fields: &[u8]) -> Vec<u8> {
+        let mut data = type_name.to_vec();
+        data.extend_from_slice(&(fields.len() as u32).to_le_bytes());
+        data.extend_from_slice(&flags.to_le_bytes());
+        data.extend_from_slice(&id.to_le_bytes());
+        data.extend_from_slice(&[0; 8]);
+        data.extend_from_slice(fields);
+        data
+    }
+
+    #[test]
+    fn test_budget() {
+        let mut fields = field(b"MAST", b"Skyrim.esm\0");
+        fields.extend(field(b"DATA", &[0; 8]));
+        fields.extend(field(b"ONAM", &[0; 4]));
+        let mut data = record(b"TES4", record_flag::LIGHT_MASTER, 0, &fields);
+
+        let mut fields = field(b"EDID", b"123456789\0");
+        fields.extend(field(b"XXXX", &20u32.to_le_bytes()));
+        fields.extend_from_slice(b"DATA\0\0");
+        fields.extend_from_slice(&[0; 20]);
+        data.extend(record(b"AACT", 0, 0x0100_0800, &fields));
+        data.extend(record(b"AACT", 0, 0x0100_0801, &[]));
+        // Override of a master's record
+        data.extend(record(b"AACT", 0, 0x0000_0801, &[]));
+
+        let limits = BudgetLimits {
+            field_size: 10,
+            masters: 2,
+            light_records: 1,
+            onam_entries: 2,
+            warn_at: 0.5,
+        };
+        let diagnostics = check_budget(&data, &limits).unwrap();
+        let severities: Vec<Severity> = diagnostics.iter().map(|x| x.severity).collect();
+        assert_eq!(
+            severities,
+            vec![
+                // MAST is 11 bytes
+                Severity::Error,
+                // DATA is 8 bytes
+                Severity::Warning,
+                // EDID is 10 bytes
+                Severity::Warning,
+                Severity::Info,
+                // 1 of 2 masters
+                Severity::Warning,
+                // 1 of 2 ONAM entries
+                Severity::Warning,
+                // 2 new records
+                Severity::Error,
+            ]
+        );
+        assert!(check_budget(&data, &BudgetLimits::default())
+            .unwrap()
+            .iter()
+            .all(|x| x.severity == Severity::Info));
+
+        // Cancelled after the first two records, keeping what was found in them
+        struct CancelAfter(usize);
+        impl Progress for CancelAfter {
+            fn report(&mut self, _state: &ProgressState<'_>) {
+                self.0 -= 1;
+            }
+
+            fn is_cancelled(&self) -> bool {
+                self.0 == 0
+            }
+        }
+        let partial = check_budget_with_progress(&data, &limits, &mut CancelAfter(2)).unwrap();
+        assert!(!partial.complete);
+        assert_eq!(partial.value, diagnostics[..4].to_vec());
+        let full =
+            check_budget_with_progress(&data, &limits, &mut CancelAfter(usize::MAX)).unwrap();
+        assert!(full.complete);
+        assert_eq!(full.value, diagnostics);
+    }
+}
@@ -0,0 +1,177 @@
+// Listing of the compressed records in a plugin, with their sizes, without inflating them.
+// A compressed record's data is the size of the data once decompressed followed by the zlib
+// stream, so the sizes are known from the first four bytes.
+
+use crate::{
+    config::Config,
+    parse::ParseError,
+    records::common::{record_flag, FormId},
+    walk::walk_records,
+};
+use bstr::BStr;
+use std::collections::BTreeMap;
+
+/// A compressed record, as found by [compressed_records]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct CompressionInfo<'data> {
+    pub type_name: &'data BStr,
+    pub id: FormId,
+    /// Size of the fields once decompressed. None if the data is too short to hold it
+    pub uncompressed_size: Option<u32>,
+    /// Size of the zlib stream
+    pub compressed_size: usize,
+}
+impl CompressionInfo<'_> {
+    /// Compressed size over uncompressed size, so smaller is better
+    pub fn ratio(&self) -> Option<f64> {
+        self.uncompressed_size
+            .filter(|x| *x != 0)
+            .map(|x| self.compressed_size as f64 / x as f64)
+    }
+}
+
+/// The compressed records in the plugin file [data], descending into groups
+pub fn compressed_records(data: &[u8]) -> Result<Vec<CompressionInfo<'_>>, ParseError<'_>> {
+    let mut found = Vec::new();
+    walk_records(data, &mut |record| {
+        if record.flags & record_flag::COMPRESSED != 0 {
+            found.push(CompressionInfo {
+                type_name: record.type_name,
+                id: record.id,
+                uncompressed_size: record
+                    .data
+                    .get(..4)
+                    .map(|x| u32::from_le_bytes([x[0], x[1], x[2], x[3]])),
+                compressed_size: record.data.len().saturating_sub(4),
+            });
+        }
+    })?;
+    Ok(found)
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct TypeCompressionStats {
+    pub records: usize,
+    pub compressed_size: usize,
+    pub uncompressed_size: u64,
+}
+
+/// Compressed records of a plugin, grouped by type
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct CompressionAudit {
+    /// Keyed by record type name
+    pub by_type: BTreeMap<String, TypeCompressionStats>,
+}
+impl CompressionAudit {
+    pub fn new() -> CompressionAudit {
+        CompressionAudit::default()
+    }
+
+    /// Adds every compressed record of a plugin file to the audit
+    pub fn add_plugin<'data>(&mut self, data: &'data [u8]) -> Result<(), ParseError<'data>> {
+        for info in compressed_records(data)? {
+            let stats = self.by_type.entry(info.type_name.to_string()).or_default();
+            stats.records += 1;
+            stats.compressed_size += info.compressed_size;
+            stats.uncompressed_size += u64::from(info.uncompressed_size.unwrap_or(0));
+        }
+        Ok(())
+    }
+
+    pub fn report(&self) -> String {
+        let mut report = String::new();
+        for (type_name, stats) in self.by_type.iter() {
+            report += &format!(
+                "{}: {} records, {} bytes compressed from {} bytes",
+                type_name, stats.records, stats.compressed_size, stats.uncompressed_size
+            );
+            if stats.uncompressed_size != 0 {
+                report += &format!(
+                    " ({:.1}%)",
+                    stats.compressed_size as f64 * 100.0 / stats.uncompressed_size as f64
+                );
+            }
+            report += "\n";
+        }
+        report
+    }
+}
+
+/// `vivec compressed <plugin>...`
+pub fn compressed_command(args: &[String], config: &Config) {
+    let mut audit = CompressionAudit::new();
+    let paths = config
+        .plugin_paths(args)
+        .unwrap_or_else(|e| panic!("{}", e));
+    for path in paths {
+        let data = std::fs::read(&path).expect("Failed to read data from file");
+        audit
+            .add_plugin(&data)
+            .unwrap_or_else(|e| panic!("Failed to walk {}: {:?}", path.display(), e));
+    }
+    print!("{}", audit.report());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::groups::common::GROUPH_SIZE;
+
+    fn record(type_name: &[u8], flags: u32, id: u32, data: &[u8]) -> Vec<u8> {
+        let mut record = type_name.to_vec();
+        record.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        record.extend_from_slice(&flags.to_le_bytes());
+        record.extend_from_slice(&id.to_le_bytes());
+        record.extend_from_slice(&[0; 8]);
+        record.extend_from_slice(data);
+        record
+    }
+
+    #[test]
+    fn test_compressed_records() {
+        let mut data = record(b"TES4", 0, 0, &[]);
+        let mut inner = record(
+            b"NPC_",
+            record_flag::COMPRESSED,
+            0x801,
+            &[40, 0, 0, 0, 1, 2],
+        );
+        inner.extend(record(b"NPC_", 0, 0x802, &[1, 2, 3]));
+        inner.extend(record(b"NPC_", record_flag::COMPRESSED, 0x803, &[]));
+        data.extend_from_slice(b"GRUP");
+        data.extend_from_slice(&((GROUPH_SIZE + inner.len()) as u32).to_le_bytes());
+        data.extend_from_slice(b"NPC_");
+        data.extend_from_slice(&[0; 12]);
+        data.extend_from_slice(&inner);
+        data.extend(record(
+            b"QUST",
+            record_flag::COMPRESSED,
+            0x804,
+            &[10, 0, 0, 0, 1],
+        ));
+
+        let found = compressed_records(&data).unwrap();
+        assert_eq!(
+            found.iter().map(|x| x.id).collect::<Vec<_>>(),
+            vec![FormId::new(0x801), FormId::new(0x803), FormId::new(0x804)]
+        );
+        assert_eq!(found[0].uncompressed_size, Some(40));
+        assert_eq!(found[0].compressed_size, 2);
+        assert_eq!(found[0].ratio(), Some(0.05));
+        assert_eq!(found[1].uncompressed_size, None);
+        assert_eq!(found[1].ratio(), None);
+
+        let mut audit = CompressionAudit::new();
+        audit.add_plugin(&data).unwrap();
+        assert_eq!(audit.by_type["NPC_"].records, 2);
+        assert_eq!(audit.by_type["QUST"].uncompressed_size, 10);
+        assert_eq!(
+            audit.report(),
+            "NPC_: 2 records, 2 bytes compressed from 40 bytes (5.0%)\n\
+             QUST: 1 records, 1 bytes compressed from 10 bytes (10.0%)\n"
+        );
+
+        let truncated = &data[..data.len() - 2];
+        assert!(compressed_records(truncated).is_err());
+    }
+}
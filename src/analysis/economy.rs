@@ -0,0 +1,262 @@
+// Report of the value and weight of every item, to help balance patch authors spot items which
+// are out of line with the rest of their type.
+
+use crate::{
+    config::Config,
+    context::{Diagnostic, ParseContext, Severity},
+    plugin::Plugin,
+    progress::{Partial, Progress, ProgressState},
+    records::{
+        common::{FormId, TypeNamed},
+        RecordRef,
+    },
+};
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ItemEntry {
+    pub type_name: String,
+    pub id: FormId,
+    pub value: Option<u32>,
+    pub weight: Option<f32>,
+    pub playable: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EconomyOptions {
+    /// Playable items worth more than this many times the median value of their type are reported
+    pub outlier_factor: f64,
+    /// Least amount of items of a type that there must be for the median to mean anything
+    pub min_items: usize,
+}
+impl Default for EconomyOptions {
+    fn default() -> EconomyOptions {
+        EconomyOptions {
+            outlier_factor: 20.0,
+            min_items: 5,
+        }
+    }
+}
+
+/// Value and weight of each record that is an item
+pub fn collect_items(records: &[RecordRef<'_, '_>]) -> Vec<ItemEntry> {
+    records
+        .iter()
+        .filter_map(|record| {
+            let item = record.as_item()?;
+            Some(ItemEntry {
+                type_name: record.type_name().to_string(),
+                id: record.formid(),
+                value: item.value().map(|x| x.amount()),
+                weight: item.weight().map(|x| x.amount()),
+                playable: item.is_playable(),
+            })
+        })
+        .collect()
+}
+
+/// Median value of the playable items of each type
+pub fn median_values(items: &[ItemEntry]) -> BTreeMap<&str, (u32, usize)> {
+    let mut values: BTreeMap<&str, Vec<u32>> = BTreeMap::new();
+    for item in items.iter().filter(|x| x.playable) {
+        if let Some(value) = item.value {
+            values.entry(&item.type_name).or_default().push(value);
+        }
+    }
+    values
+        .into_iter()
+        .map(|(type_name, mut values)| {
+            values.sort_unstable();
+            (type_name, (values[values.len() / 2], values.len()))
+        })
+        .collect()
+}
+
+/// Finds items with a zero value while being playable, a negative or non-finite weight,
+/// or a value far above the rest of their type
+pub fn audit_economy(items: &[ItemEntry], options: &EconomyOptions) -> Vec<Diagnostic> {
+    audit_economy_with_progress(items, options, &mut ()).value
+}
+
+/// [audit_economy], reporting to [progress] after each item. If it is cancelled, the
+/// diagnostics for the items before that are returned.
+pub fn audit_economy_with_progress(
+    items: &[ItemEntry],
+    options: &EconomyOptions,
+    progress: &mut dyn Progress,
+) -> Partial<Vec<Diagnostic>> {
+    let medians = median_values(items);
+    let mut diagnostics = Vec::new();
+    for (index, item) in items.iter().enumerate() {
+        if progress.is_cancelled() {
+            return Partial {
+                value: diagnostics,
+                complete: false,
+            };
+        }
+        progress.report(&ProgressState {
+            records_converted: index,
+            records_total: Some(items.len()),
+            ..ProgressState::default()
+        });
+
+        let mut diagnose = |severity, message| {
+            diagnostics.push(Diagnostic {
+                severity,
+                record: Some((item.type_name.clone(), item.id)),
+                message,
+            })
+        };
+
+        if let Some(weight) = item.weight {
+            if !weight.is_finite() {
+                diagnose(Severity::Error, format!("Weight is {}", weight));
+            } else if weight < 0.0 {
+                diagnose(Severity::Error, format!("Negative weight of {}", weight));
+            }
+        }
+        if !item.playable {
+            continue;
+        }
+        match (item.value, medians.get(item.type_name.as_str())) {
+            (Some(0), _) => diagnose(Severity::Warning, "Playable item has no value".to_string()),
+            (Some(value), Some(&(median, count)))
+                if count >= options.min_items
+                    && median != 0
+                    && value as f64 > median as f64 * options.outlier_factor =>
+            {
+                diagnose(
+                    Severity::Info,
+                    format!(
+                        "Value of {} is over {} times the median of {} across {} items",
+                        value, options.outlier_factor, median, count
+                    ),
+                )
+            }
+            _ => {}
+        }
+    }
+    progress.report(&ProgressState {
+        records_converted: items.len(),
+        records_total: Some(items.len()),
+        ..ProgressState::default()
+    });
+    Partial {
+        value: diagnostics,
+        complete: true,
+    }
+}
+
+/// `vivec economy <plugin>... [--factor <outlier factor>]`
+pub fn economy_command(args: &[String], config: &Config) {
+    let mut options = EconomyOptions::default();
+    let mut paths = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--factor" => {
+                options.outlier_factor = args
+                    .next()
+                    .and_then(|x| x.parse().ok())
+                    .expect("Expected number after --factor")
+            }
+            _ => paths.push(arg),
+        }
+    }
+
+    let paths = config
+        .plugin_paths(&paths)
+        .unwrap_or_else(|e| panic!("{}", e));
+    let files: Vec<Vec<u8>> = paths
+        .iter()
+        .map(|path| std::fs::read(path).expect("Failed to read data from file"))
+        .collect();
+    let mut items = Vec::new();
+    let mut ctx = ParseContext::new(config.parse_options());
+    for (path, data) in paths.iter().zip(files.iter()) {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let plugin = Plugin::parse(name, data, &mut ctx)
+            .unwrap_or_else(|e| panic!("Failed to parse {}: {:?}", path.display(), e));
+        config.check_game(&plugin, &mut ctx);
+        items.extend(collect_items(&plugin.records()));
+    }
+    for diagnostic in ctx.diagnostics.iter() {
+        println!("{}", diagnostic);
+    }
+
+    for (type_name, (median, count)) in median_values(&items) {
+        println!(
+            "{}: {} playable items, median value {}",
+            type_name, count, median
+        );
+    }
+    for diagnostic in audit_economy(&items, &options) {
+        println!("{}", diagnostic);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(id: u32, value: u32, weight: f32, playable: bool) -> ItemEntry {
+        ItemEntry {
+            type_name: "ARMO".to_string(),
+            id: FormId::new(id),
+            value: Some(value),
+            weight: Some(weight),
+            playable,
+        }
+    }
+
+    #[test]
+    fn test_audit_economy() {
+        let mut items: Vec<ItemEntry> = (0..5).map(|i| item(i, 10 + i, 1.0, true)).collect();
+        items.push(item(5, 1000, 1.0, true));
+        items.push(item(6, 0, 1.0, true));
+        items.push(item(7, 0, -2.0, false));
+        items.push(ItemEntry {
+            type_name: "AMMO".to_string(),
+            weight: None,
+            ..item(8, 5000, 0.0, true)
+        });
+
+        let medians = median_values(&items);
+        assert_eq!(medians.get("ARMO"), Some(&(12, 7)));
+        assert_eq!(medians.get("AMMO"), Some(&(5000, 1)));
+
+        let diagnostics = audit_economy(&items, &EconomyOptions::default());
+        let found: Vec<(u32, Severity)> = diagnostics
+            .iter()
+            .map(|x| (x.record.as_ref().unwrap().1.id, x.severity))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (5, Severity::Info),
+                (6, Severity::Warning),
+                (7, Severity::Error),
+            ]
+        );
+
+        /// Cancels after [0] reports
+        struct CancelAfter(usize);
+        impl Progress for CancelAfter {
+            fn report(&mut self, _state: &ProgressState<'_>) {
+                self.0 = self.0.saturating_sub(1);
+            }
+
+            fn is_cancelled(&self) -> bool {
+                self.0 == 0
+            }
+        }
+        // Cancelled before the item with no value
+        let partial =
+            audit_economy_with_progress(&items, &EconomyOptions::default(), &mut CancelAfter(6));
+        assert!(!partial.complete);
+        assert_eq!(partial.value, diagnostics[..1]);
+        let partial = audit_economy_with_progress(&items, &EconomyOptions::default(), &mut ());
+        assert!(partial.complete);
+        assert_eq!(partial.value, diagnostics);
+    }
+}
@@ -0,0 +1,144 @@
+// Audit of which record flag bits are actually used by each record type, so that the
+// gaps in [record_flag] can be filled in with evidence.
+
+use crate::{
+    config::Config, parse::ParseError, records::common::record_flag, walk::walk_record_headers,
+};
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct TypeFlagStats {
+    /// Number of records of this type that were seen
+    pub records: usize,
+    /// How many records had each bit set, indexed by bit position
+    pub bit_counts: [usize; 32],
+}
+impl TypeFlagStats {
+    pub fn observe(&mut self, flags: u32) {
+        self.records += 1;
+        for bit in 0..32 {
+            if flags & (1 << bit) != 0 {
+                self.bit_counts[bit] += 1;
+            }
+        }
+    }
+
+    /// Mask of every bit seen set at least once
+    pub fn observed(&self) -> u32 {
+        let mut mask = 0;
+        for bit in 0..32 {
+            if self.bit_counts[bit] != 0 {
+                mask |= 1 << bit;
+            }
+        }
+        mask
+    }
+
+    /// Bits seen set which have no meaning for [type_name] in [record_flag::TYPES]
+    pub fn undocumented(&self, type_name: &[u8]) -> u32 {
+        self.observed() & !record_flag::documented(type_name)
+    }
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct FlagAudit {
+    /// Keyed by record type name
+    pub by_type: BTreeMap<String, TypeFlagStats>,
+}
+impl FlagAudit {
+    pub fn new() -> FlagAudit {
+        FlagAudit::default()
+    }
+
+    /// Adds every record of a plugin file to the audit
+    pub fn add_plugin<'data>(&mut self, data: &'data [u8]) -> Result<(), ParseError<'data>> {
+        walk_record_headers(data, &mut |type_name, flags| {
+            self.by_type
+                .entry(type_name.to_string())
+                .or_default()
+                .observe(flags)
+        })
+    }
+
+    /// Mask of every bit seen set on a type that it has no meaning for, across all types
+    pub fn undocumented(&self) -> u32 {
+        self.by_type.iter().fold(0, |mask, (type_name, stats)| {
+            mask | stats.undocumented(type_name.as_bytes())
+        })
+    }
+
+    pub fn report(&self) -> String {
+        let mut report = String::new();
+        for (type_name, stats) in self.by_type.iter() {
+            report += &format!("{} ({} records)\n", type_name, stats.records);
+            let documented = record_flag::documented(type_name.as_bytes());
+            for bit in 0..32 {
+                let count = stats.bit_counts[bit];
+                if count == 0 {
+                    continue;
+                }
+                let flag: u32 = 1 << bit;
+                let documented = if flag & documented != 0 {
+                    ""
+                } else {
+                    " UNDOCUMENTED"
+                };
+                report += &format!("    0x{:08X}: {}{}\n", flag, count, documented);
+            }
+        }
+        report
+    }
+}
+
+/// `vivec flags <plugin>...`
+pub fn flags_command(args: &[String], config: &Config) {
+    let mut audit = FlagAudit::new();
+    let paths = config
+        .plugin_paths(args)
+        .unwrap_or_else(|e| panic!("{}", e));
+    for path in paths {
+        let data = std::fs::read(&path).expect("Failed to read data from file");
+        audit
+            .add_plugin(&data)
+            .unwrap_or_else(|e| panic!("Failed to walk {}: {:?}", path.display(), e));
+    }
+    print!("{}", audit.report());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(type_name: &[u8], flags: u32) -> Vec<u8> {
+        let mut data = type_name.to_vec();
+        data.extend_from_slice(&0u32.to_le_bytes());
+        data.extend_from_slice(&flags.to_le_bytes());
+        data.extend_from_slice(&[0; 12]);
+        data
+    }
+
+    #[test]
+    fn test_flag_audit() {
+        let mut data = record(b"TES4", record_flag::MASTER);
+        let inner = record(b"ACTI", record_flag::DANGEROUS | 0x2000);
+        data.extend_from_slice(b"GRUP");
+        data.extend_from_slice(&((24 + inner.len()) as u32).to_le_bytes());
+        data.extend_from_slice(b"ACTI");
+        data.extend_from_slice(&[0; 12]);
+        data.extend_from_slice(&inner);
+
+        let mut audit = FlagAudit::new();
+        audit.add_plugin(&data).unwrap();
+        assert_eq!(audit.by_type.len(), 2);
+        assert_eq!(audit.by_type["TES4"].observed(), record_flag::MASTER);
+        assert_eq!(audit.by_type["TES4"].undocumented(b"TES4"), 0);
+        // Bits are only documented for the types they have a meaning for
+        assert_eq!(
+            audit.by_type["TES4"].undocumented(b"ACTI"),
+            record_flag::MASTER
+        );
+        assert_eq!(audit.by_type["ACTI"].records, 1);
+        assert_eq!(audit.by_type["ACTI"].bit_counts[13], 1);
+        assert_eq!(audit.undocumented(), 0x2000);
+    }
+}
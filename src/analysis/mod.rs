@@ -0,0 +1,15 @@
+use crate::groups::common::GroupType;
+
+pub mod budget;
+pub mod compression;
+pub mod economy;
+pub mod flags;
+
+/// Record header size (type name, data size, flags, id, version control info, version, unknown)
+pub const RECORDH_SIZE: usize = 24;
+
+/// The group type out of a group header
+pub fn group_type_of(header: &[u8]) -> GroupType<'_> {
+    let group_type = i32::from_le_bytes([header[12], header[13], header[14], header[15]]);
+    GroupType::from_info(group_type, &header[8..12])
+}
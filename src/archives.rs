@@ -0,0 +1,197 @@
+// Which BSA archives the game loads and in what order, so that checking where an asset comes from
+// follows the same precedence as the game rather than guessing from archive names. The archives
+// listed by sResourceArchiveList and sResourceArchiveList2 in the [Archive] section of the game's
+// ini files load first, then the archives named after each plugin, in load order. Later archives
+// win over earlier ones, and loose files in the Data folder win over every archive.
+// Reading the contents of the archives is left to the caller.
+
+use crate::config::GameFormat;
+use std::path::Path;
+
+/// The value of [key] in [section] of the ini [text], ignoring case as the game does.
+/// If the key is given more than once then the last one is used
+pub fn ini_value<'a>(text: &'a str, section: &str, key: &str) -> Option<&'a str> {
+    let mut in_section = false;
+    let mut value = None;
+    for line in text.lines() {
+        let line = line.trim();
+        if let Some(name) = line.strip_prefix('[').and_then(|x| x.strip_suffix(']')) {
+            in_section = name.trim().eq_ignore_ascii_case(section);
+        } else if in_section && !line.starts_with(';') {
+            if let Some((name, rest)) = line.split_once('=') {
+                if name.trim().eq_ignore_ascii_case(key) {
+                    value = Some(rest.trim());
+                }
+            }
+        }
+    }
+    value
+}
+
+/// The archives that the [Archive] section of [inis] lists, in the order they load.
+/// [inis] are the text of each ini in the order the game reads them, such as Skyrim.ini then
+/// SkyrimCustom.ini, with keys of later ones replacing those of earlier ones
+pub fn ini_archives(inis: &[&str]) -> Vec<String> {
+    let mut archives: Vec<String> = Vec::new();
+    for key in ["sResourceArchiveList", "sResourceArchiveList2"].iter() {
+        let list = inis
+            .iter()
+            .rev()
+            .find_map(|text| ini_value(text, "Archive", key))
+            .unwrap_or("");
+        for name in list.split(',').map(str::trim).filter(|x| !x.is_empty()) {
+            push_archive(&mut archives, name);
+        }
+    }
+    archives
+}
+
+/// The archives that the game loads along with [plugin]. Special Edition also loads a separate
+/// archive for textures
+pub fn plugin_archives(plugin: &str, game: GameFormat) -> Vec<String> {
+    let stem = Path::new(plugin)
+        .file_stem()
+        .map(|x| x.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let mut archives = vec![format!("{}.bsa", stem)];
+    if game == GameFormat::SpecialEdition {
+        archives.push(format!("{} - Textures.bsa", stem));
+    }
+    archives
+}
+
+/// Adds [name] to [archives], unless it is already there, in which case it keeps its first
+/// position as the game only loads each archive once
+fn push_archive(archives: &mut Vec<String>, name: &str) {
+    if !archives.iter().any(|x| x.eq_ignore_ascii_case(name)) {
+        archives.push(name.to_string());
+    }
+}
+
+/// Where the game loads an asset from
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum AssetSource<'a> {
+    /// A file in the Data folder
+    Loose,
+    /// The archive with this name
+    Archive(&'a str),
+}
+
+/// Archives in the order that the game loads them, so the last one has the highest precedence
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct ArchiveOrder {
+    pub archives: Vec<String>,
+}
+impl ArchiveOrder {
+    /// The archives of [inis] followed by those of [plugins], which are in load order.
+    /// Archives that aren't in the Data folder are kept, as the game skips them anyway
+    pub fn new<S: AsRef<str>>(game: GameFormat, inis: &[&str], plugins: &[S]) -> ArchiveOrder {
+        let mut archives = ini_archives(inis);
+        for plugin in plugins {
+            for name in plugin_archives(plugin.as_ref(), game) {
+                push_archive(&mut archives, &name);
+            }
+        }
+        ArchiveOrder { archives }
+    }
+
+    /// Position of the archive [name] in the order, None if it isn't loaded
+    pub fn position(&self, name: &str) -> Option<usize> {
+        self.archives
+            .iter()
+            .position(|x| x.eq_ignore_ascii_case(name))
+    }
+
+    /// Where the game loads [asset], a path relative to [data_folder], from: a loose file if
+    /// there is one, or else the last archive that [contains] says has it.
+    /// [contains] is given the name of an archive and [asset]
+    pub fn asset_source<F>(
+        &self,
+        data_folder: &Path,
+        asset: &str,
+        mut contains: F,
+    ) -> Option<AssetSource<'_>>
+    where
+        F: FnMut(&str, &str) -> bool,
+    {
+        if data_folder.join(asset.replace('\\', "/")).is_file() {
+            return Some(AssetSource::Loose);
+        }
+        self.archives
+            .iter()
+            .rev()
+            .find(|archive| contains(archive, asset))
+            .map(|archive| AssetSource::Archive(archive))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SKYRIM_INI: &str = "[General]\nsLanguage=ENGLISH\n\n[Archive]\n\
+        sResourceArchiveList=Skyrim - Misc.bsa, Skyrim - Textures.bsa\n\
+        sResourceArchiveList2=Skyrim - Voices.bsa\n";
+
+    #[test]
+    fn test_ini_archives() {
+        assert_eq!(
+            ini_value(SKYRIM_INI, "general", "SLANGUAGE"),
+            Some("ENGLISH")
+        );
+        assert_eq!(
+            ini_value(SKYRIM_INI, "General", "sResourceArchiveList"),
+            None
+        );
+
+        let custom = "[Archive]\n;sResourceArchiveList2=Commented.bsa\n\
+                      sResourceArchiveList2=Skyrim - Voices.bsa, Extra.bsa, skyrim - misc.bsa\n";
+        assert_eq!(
+            ini_archives(&[SKYRIM_INI, custom]),
+            vec![
+                "Skyrim - Misc.bsa",
+                "Skyrim - Textures.bsa",
+                "Skyrim - Voices.bsa",
+                "Extra.bsa"
+            ]
+        );
+        assert_eq!(
+            plugin_archives("Patch.esp", GameFormat::SpecialEdition),
+            vec!["Patch.bsa", "Patch - Textures.bsa"]
+        );
+        assert_eq!(
+            plugin_archives("Patch.esp", GameFormat::LegendaryEdition),
+            vec!["Patch.bsa"]
+        );
+    }
+
+    #[test]
+    fn test_asset_source() {
+        let order = ArchiveOrder::new(
+            GameFormat::LegendaryEdition,
+            &[SKYRIM_INI],
+            &["Skyrim.esm", "First.esp", "Second.esp"],
+        );
+        assert_eq!(order.position("first.bsa"), Some(4));
+        assert_eq!(order.position("Second.bsa"), Some(5));
+
+        let contents = |archive: &str, asset: &str| match archive {
+            "Skyrim - Textures.bsa" => asset.starts_with("textures"),
+            "First.bsa" | "Second.bsa" => asset == r"textures\armor\shield.dds",
+            _ => false,
+        };
+        let data_folder = Path::new("/nonexistent");
+        assert_eq!(
+            order.asset_source(data_folder, r"textures\armor\shield.dds", contents),
+            Some(AssetSource::Archive("Second.bsa"))
+        );
+        assert_eq!(
+            order.asset_source(data_folder, r"textures\sky.dds", contents),
+            Some(AssetSource::Archive("Skyrim - Textures.bsa"))
+        );
+        assert_eq!(
+            order.asset_source(data_folder, r"meshes\sky.nif", contents),
+            None
+        );
+    }
+}
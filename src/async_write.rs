@@ -0,0 +1,113 @@
+// Writing to a tokio AsyncWrite, for services that generate plugins and stream them out without
+// blocking. Encoding stays synchronous and goes into a buffer, and only writing that buffer waits
+// on the writer. Plugins are encoded a top-level entry at a time, so that the whole file is never
+// held in memory at once.
+
+use crate::{
+    plugin::Plugin,
+    util::{DataSize, Writable, WriteError},
+};
+use std::future::Future;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+pub trait WritableAsync {
+    /// Writes to [w]. All of self is encoded into a buffer when this is called, before anything
+    /// is written
+    fn write_to_async<'a, W>(
+        &self,
+        w: &'a mut W,
+    ) -> impl Future<Output = Result<(), WriteError>> + Send + 'a
+    where
+        W: AsyncWrite + Unpin + Send + ?Sized;
+}
+impl<T> WritableAsync for T
+where
+    T: Writable + DataSize + ?Sized,
+{
+    fn write_to_async<'a, W>(
+        &self,
+        w: &'a mut W,
+    ) -> impl Future<Output = Result<(), WriteError>> + Send + 'a
+    where
+        W: AsyncWrite + Unpin + Send + ?Sized,
+    {
+        let data = self.to_vec();
+        async move {
+            w.write_all(&data?).await?;
+            Ok(())
+        }
+    }
+}
+
+impl Plugin<'_> {
+    /// Writes the plugin to [w], encoding each top-level record or group just before writing it.
+    /// The writer is flushed at the end
+    pub async fn stream_to_async<W>(&self, w: &mut W) -> Result<(), WriteError>
+    where
+        W: AsyncWrite + Unpin + ?Sized,
+    {
+        let mut buffer = Vec::new();
+        for top in self.tops_in_write_order() {
+            buffer.clear();
+            top.write_to(&mut buffer)?;
+            w.write_all(&buffer).await?;
+        }
+        w.flush().await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        records::{
+            common::{CommonRecordInfo, GeneralRecord},
+            fields::common::GeneralField,
+            Record,
+        },
+        Top,
+    };
+    use bstr::ByteSlice;
+    use std::{
+        pin::pin,
+        task::{Context, Poll, Waker},
+    };
+
+    /// Runs [future], which must not wait on anything, as writing to a Vec never does
+    fn ready<F: Future>(future: F) -> F::Output {
+        let mut cx = Context::from_waker(Waker::noop());
+        match pin!(future).poll(&mut cx) {
+            Poll::Ready(output) => output,
+            Poll::Pending => panic!("Writing to a Vec should not wait"),
+        }
+    }
+
+    #[test]
+    fn test_write_async() {
+        let record = |type_name: &'static [u8], id| {
+            let mut common = CommonRecordInfo::test_default();
+            common.id = id;
+            GeneralRecord {
+                type_name: type_name.as_bstr(),
+                common,
+                fields: vec![GeneralField::new(b"EDID".as_bstr(), b"Test\0")],
+            }
+        };
+        let header = record(b"TES4", 0);
+        let mut written = Vec::new();
+        ready(header.write_to_async(&mut written)).unwrap();
+        assert_eq!(written, header.to_vec().unwrap());
+
+        let plugin = Plugin::from_tops(
+            "Test.esp".to_string(),
+            vec![
+                Top::Record(Record::Unknown(record(b"MISC", 0x801))),
+                Top::Record(Record::Unknown(header)),
+            ],
+        );
+        let mut written = Vec::new();
+        ready(plugin.stream_to_async(&mut written)).unwrap();
+        assert_eq!(written, plugin.to_vec().unwrap());
+    }
+}
@@ -0,0 +1,1155 @@
+// Cleaning a plugin of the edits it doesn't mean to make. An override that is the same as the
+// version of the record in its masters (identical to master, or ITM) changes nothing, but still
+// wins over any plugin loaded before it that does change the record, so they are found and
+// removed. Overrides are compared with the last of the plugin's masters that has the record, as
+// that is the version that they replace.
+// A record is identical if it is written the same, byte for byte, and the masters of both plugins
+// give the form ids within it to the same plugins. Otherwise it is equivalent if it is the same
+// once the master's form ids are given by the plugin's masters, going by its flags and fields
+// alone, as the Creation Kit changes the version control info and form version of records that
+// were only looked at. The form ids within records that weren't converted can't be translated, so
+// those are only equivalent if their fields are written the same.
+// A placed reference that a plugin deletes (an undeleted reference, or UDR) crashes the game when
+// another plugin or the save still refers to it, so the usual fix is to undelete it, but disable
+// it and move it out of sight, so that it stays gone while it can still be found. A deleted
+// record keeps none of its fields, so the base object and position of an override are restored
+// from its master first.
+// Wild edits are overrides that the Creation Kit makes when a record is only looked at or nudged,
+// which are only found by guessing: those that differ from their master by little more than
+// rounding, and cells or worldspaces far from anything else that the plugin changes.
+
+use crate::{
+    groups::{
+        common::{GroupEntry, GroupType},
+        entry_units, Group,
+    },
+    parse::Parse,
+    plugin::Plugin,
+    records::{
+        common::{record_flag, FormId, FormIdVisitor, GeneralRecord, GlobalFormId, TypeNamed},
+        fields::common::GeneralField,
+        Record, RecordMut, RecordRef,
+    },
+    util::{Writable, WriteError},
+    walk::{EntryMut, EntryRef, GroupPath},
+    Top,
+};
+use bstr::ByteSlice;
+use std::collections::{HashMap, HashSet};
+
+/// Types of the references placed within cells
+const PLACED_TYPES: &[&[u8; 4]] = &[
+    b"REFR", b"ACHR", b"PGRE", b"PMIS", b"PHZD", b"PARW", b"PBAR", b"PBEA", b"PCON", b"PFLA",
+];
+
+/// Object index of the player's reference, which belongs to Skyrim.esm. As the player is always
+/// enabled, a reference whose enable parent it is with the opposite state is always disabled
+const PLAYER_REFERENCE: u32 = 0x14;
+/// Flags of an XESP whose reference has the opposite state to its enable parent
+const OPPOSITE_STATE: u32 = 1;
+
+/// How an override is the same as the version in its master
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ItmKind {
+    /// Written byte for byte the same
+    Identical,
+    /// The same going by what the record means, see the top of this module
+    Equivalent,
+}
+
+/// An override record that is the same as the version in its master
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Itm {
+    pub id: FormId,
+    pub type_name: String,
+    /// The name of the master that has the version it is the same as
+    pub master: String,
+    pub kind: ItmKind,
+}
+
+/// Why an override looks like it was made by accident, see [Plugin::wild_edits]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum WildEditKind {
+    /// Only its version control info or form version differ from its master, which makes it an
+    /// [ItmKind::Equivalent] ITM as well
+    VersionControl,
+    /// Its fields only differ from its master by floats that are nearly the same, as left by
+    /// moving something and moving it back
+    FloatNoise,
+    /// A cell or worldspace that is far from anything else that the plugin changes
+    OutOfScope,
+}
+
+/// An override record that looks like it was made by accident
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct WildEdit {
+    pub id: FormId,
+    pub type_name: String,
+    pub kind: WildEditKind,
+}
+
+/// How [Plugin::wild_edits] tells what is an accident
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct WildEditOptions {
+    /// Largest difference between two floats, relative to the larger of them, that is noise
+    pub tolerance: f32,
+    /// How many cells an exterior cell can be from the nearest cell that the plugin adds or
+    /// places references in, before it is out of scope
+    pub distance: i32,
+}
+impl Default for WildEditOptions {
+    fn default() -> WildEditOptions {
+        WildEditOptions {
+            tolerance: 1e-5,
+            distance: 3,
+        }
+    }
+}
+
+/// A placed reference that is flagged as deleted
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct DeletedReference {
+    pub id: FormId,
+    pub type_name: String,
+    /// The cell that it is placed in
+    pub cell: FormId,
+}
+
+/// What [Plugin::fix_deleted_references] did with the references found by
+/// [Plugin::deleted_references]
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct UdrFixes {
+    pub fixed: Vec<DeletedReference>,
+    /// Overrides whose master isn't loaded, or whose base object belongs to a plugin that isn't
+    /// a master of the plugin, so what to restore isn't known. They are left deleted
+    pub skipped: Vec<DeletedReference>,
+}
+
+/// How [Plugin::fix_deleted_references] disables references
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct UdrOptions {
+    /// Whether to make the player the enable parent, with the opposite state, so that scripts
+    /// enabling the reference can't bring it back
+    pub enable_parent: bool,
+    /// Height to move the reference to, if any
+    pub z: Option<f32>,
+}
+impl Default for UdrOptions {
+    fn default() -> UdrOptions {
+        UdrOptions {
+            enable_parent: true,
+            z: Some(-30000.0),
+        }
+    }
+}
+
+/// [record] as a deleted reference, if it is one
+fn deleted_reference(record: &GeneralRecord<'_>, cell: Option<FormId>) -> Option<DeletedReference> {
+    if !record.common.flags.is(record_flag::DELETED)
+        || !PLACED_TYPES.iter().any(|x| record.type_name == &x[..])
+    {
+        return None;
+    }
+    Some(DeletedReference {
+        id: FormId::new(record.common.id),
+        type_name: record.type_name.to_string(),
+        cell: cell?,
+    })
+}
+
+/// The base object and position of a reference, as the data of its NAME and DATA
+#[derive(Debug, Default)]
+struct Placement {
+    name: Option<Vec<u8>>,
+    position: Option<Vec<u8>>,
+}
+
+/// The placement of the reference [id] of [plugin] in the last of [masters] that has it, with the
+/// base object given by the masters of [plugin]. None if no loaded master has the reference, or
+/// if its base object belongs to a plugin that [plugin] doesn't have as a master
+fn master_placement(plugin: &Plugin<'_>, masters: &[Plugin<'_>], id: FormId) -> Option<Placement> {
+    let global = plugin.global_formid(id)?;
+    let (master, record) = plugin
+        .masters()
+        .iter()
+        .rev()
+        .filter_map(|name| masters.iter().find(|x| x.is_named(name)))
+        .find_map(|master| Some((master, master.find(master.local_formid(&global)?)?)))?;
+    let mut buffer = Vec::new();
+    let record = record.to_general(&mut buffer)?;
+    let field = |name: &str| {
+        record
+            .fields
+            .iter()
+            .find(|x| x.type_name == name)
+            .map(|x| x.data)
+    };
+    let name = match field("NAME") {
+        Some(&[a, b, c, d]) => {
+            let base = FormId::new(u32::from_le_bytes([a, b, c, d]));
+            let base = master.global_formid(base)?;
+            Some(plugin.local_formid(&base)?.id.to_le_bytes().to_vec())
+        }
+        _ => None,
+    };
+    let position = field("DATA").filter(|x| x.len() == 24).map(<[u8]>::to_vec);
+    Some(Placement { name, position })
+}
+
+fn entry_bytes(entry: EntryRef<'_, '_>) -> Result<Vec<u8>, WriteError> {
+    match entry {
+        EntryRef::Record(record) => record.to_vec(),
+        EntryRef::Compressed(record) => record.to_vec(),
+    }
+}
+
+/// [entry] as a general record, decompressed if it is compressed
+fn general<'b>(entry: EntryRef<'_, 'b>, buffer: &'b mut Vec<u8>) -> Option<GeneralRecord<'b>> {
+    match entry {
+        EntryRef::Record(record) => record.to_general(buffer),
+        EntryRef::Compressed(record) => record.decompress(buffer).ok(),
+    }
+}
+
+/// [entry] of [master] as a general record, with the form ids that can be seen given by the
+/// masters of [plugin]. None if it refers to a plugin that [plugin] doesn't have as a master, or
+/// can't be read field by field
+fn translated<'b>(
+    entry: EntryRef<'_, 'b>,
+    master: &Plugin<'_>,
+    plugin: &Plugin<'_>,
+    buffer: &'b mut Vec<u8>,
+) -> Option<GeneralRecord<'b>> {
+    let mut missing = false;
+    let mut translate = |id: &mut FormId| match master
+        .global_formid(*id)
+        .and_then(|x| plugin.local_formid(&x))
+    {
+        Some(local) => *id = local,
+        // Null references stay as they are
+        None => missing |= id.id != 0,
+    };
+    let mut id = entry.formid();
+    translate(&mut id);
+    let mut record = match entry {
+        EntryRef::Record(record) => {
+            let mut record = record.to_record();
+            record.visit_formids(&mut translate);
+            record.as_record_ref().to_general(buffer)?
+        }
+        EntryRef::Compressed(record) => record.decompress(buffer).ok()?,
+    };
+    record.common.id = id.id;
+    if missing {
+        None
+    } else {
+        Some(record)
+    }
+}
+
+/// Whether every form id within [entry] of [master] that can be seen, its own included, belongs
+/// to the same plugin by the masters of [plugin], so that its bytes mean the same in both
+fn same_masters(entry: EntryRef<'_, '_>, master: &Plugin<'_>, plugin: &Plugin<'_>) -> bool {
+    let mut ids = vec![entry.formid()];
+    if let EntryRef::Record(record) = entry {
+        ids.extend(record.formids());
+    }
+    ids.into_iter()
+        .filter(|id| id.id != 0)
+        .all(|id| master.global_formid(id) == plugin.global_formid(id))
+}
+
+/// Whether [record] is the same record as [master] with the same flags, going by what is written
+fn same_header(record: &GeneralRecord<'_>, master: &GeneralRecord<'_>) -> bool {
+    let flags = |record: &GeneralRecord<'_>| record.common.flags.flags & !record_flag::COMPRESSED;
+    record.type_name == master.type_name
+        && record.common.id == master.common.id
+        && flags(record) == flags(master)
+}
+
+/// Whether [record] means the same as [master]
+fn equivalent(record: &GeneralRecord<'_>, master: &GeneralRecord<'_>) -> bool {
+    same_header(record, master) && record.fields == master.fields
+}
+
+/// Whether the fields of [record] only differ from those of [master] by floats within
+/// [tolerance] of each other. Fields are read as floats every four bytes from their start, and
+/// values that aren't normal floats, such as small integers, are never noise
+fn float_noise(record: &GeneralRecord<'_>, master: &GeneralRecord<'_>, tolerance: f32) -> bool {
+    if !same_header(record, master) || record.fields.len() != master.fields.len() {
+        return false;
+    }
+    let mut noise = false;
+    for (field, original) in record.fields.iter().zip(master.fields.iter()) {
+        if field.type_name != original.type_name || field.data.len() != original.data.len() {
+            return false;
+        }
+        for (a, b) in field.data.chunks(4).zip(original.data.chunks(4)) {
+            if a == b {
+                continue;
+            }
+            let (a, b) = match (f32::parse(a), f32::parse(b)) {
+                (Ok((_, a)), Ok((_, b))) => (a, b),
+                _ => return false,
+            };
+            if !a.is_normal() || !b.is_normal() || (a - b).abs() > tolerance * a.abs().max(b.abs())
+            {
+                return false;
+            }
+            noise = true;
+        }
+    }
+    noise
+}
+
+/// The cell whose children [path] is within
+fn cell_of(path: &GroupPath<'_>) -> Option<FormId> {
+    path.groups
+        .iter()
+        .rev()
+        .find_map(|group_type| match group_type {
+            GroupType::CellChildren(id)
+            | GroupType::CellPersistentChildren(id)
+            | GroupType::CellTemporaryChildren(id) => Some(*id),
+            _ => None,
+        })
+}
+
+/// The worldspace whose children [path] is within
+fn world_of(path: &GroupPath<'_>) -> Option<FormId> {
+    path.groups.iter().find_map(|group_type| match group_type {
+        GroupType::WorldChildren(id) => Some(*id),
+        _ => None,
+    })
+}
+
+/// Grid position of an exterior cell, from its XCLC
+fn grid(record: &GeneralRecord<'_>) -> Option<(i32, i32)> {
+    let field = record.fields.iter().find(|x| x.type_name == "XCLC")?;
+    let (data, x) = i32::parse(field.data).ok()?;
+    let (_, y) = i32::parse(data).ok()?;
+    Some((x, y))
+}
+
+/// The overridden exterior cells of [plugin] that are more than [distance] cells from every cell
+/// that it adds or places references in, and the overridden worldspaces that it adds or places
+/// nothing in. Cells are only compared with those of the same worldspace
+fn out_of_scope(plugin: &Plugin<'_>, distance: i32) -> HashSet<FormId> {
+    let own_index = plugin.masters().len();
+    let own = |id: FormId| usize::from(id.mod_index()) >= own_index;
+    let held: HashSet<FormId> = plugin
+        .iter_all_records()
+        .filter_map(|(_, path)| cell_of(&path))
+        .collect();
+
+    let mut buffer = Vec::new();
+    // Each exterior cell, with its worldspace and grid position, and the worldspaces with
+    // something of the plugin in them
+    let mut cells = Vec::new();
+    let mut worlds = HashSet::new();
+    for (entry, path) in plugin.iter_all_records() {
+        let world = match world_of(&path) {
+            Some(world) => world,
+            None => continue,
+        };
+        let id = entry.formid();
+        if entry.type_name() != "CELL" {
+            worlds.insert(world);
+            continue;
+        }
+        if own(id) {
+            worlds.insert(world);
+        }
+        if let Some(position) = general(entry, &mut buffer).as_ref().and_then(grid) {
+            cells.push((id, world, position));
+        }
+    }
+
+    let scope: Vec<(FormId, (i32, i32))> = cells
+        .iter()
+        .filter(|(id, _, _)| own(*id) || held.contains(id))
+        .map(|(_, world, position)| (*world, *position))
+        .collect();
+    let near = |world: FormId, (x, y): (i32, i32)| {
+        scope.iter().any(|(other, (other_x, other_y))| {
+            *other == world && (x - other_x).abs().max((y - other_y).abs()) <= distance
+        })
+    };
+    let mut ids: HashSet<FormId> = cells
+        .iter()
+        .filter(|(id, world, position)| !own(*id) && !held.contains(id) && !near(*world, *position))
+        .map(|(id, _, _)| *id)
+        .collect();
+    ids.extend(
+        plugin
+            .iter_all_records()
+            .map(|(entry, _)| entry)
+            .filter(|entry| entry.type_name() == "WRLD")
+            .map(|entry| entry.formid())
+            .filter(|id| !own(*id) && !worlds.contains(id)),
+    );
+    ids
+}
+
+/// Every record of [plugin] apart from the TES4 record, by form id
+fn entries_by_formid<'a, 'data>(plugin: &'a Plugin<'data>) -> HashMap<FormId, EntryRef<'a, 'data>> {
+    plugin
+        .iter_all_records()
+        .filter(|(entry, _)| entry.type_name() != "TES4")
+        .map(|(entry, _)| (entry.formid(), entry))
+        .collect()
+}
+
+/// The override records of [plugin] apart from the TES4 record, in the order they are written,
+/// each with the last of its [masters] that has the record and the version there. Records of
+/// masters that aren't loaded are left out
+fn overrides<'a, 'data, 'm, 'md>(
+    plugin: &'a Plugin<'data>,
+    masters: &'m [Plugin<'md>],
+) -> Vec<(EntryRef<'a, 'data>, &'m Plugin<'md>, EntryRef<'m, 'md>)> {
+    let own_index = plugin.masters().len();
+    // The loaded masters in the order of the plugin's list, from the last
+    let loaded: Vec<(&Plugin<'_>, HashMap<FormId, EntryRef<'_, '_>>)> = plugin
+        .masters()
+        .iter()
+        .rev()
+        .filter_map(|name| masters.iter().find(|x| x.is_named(name)))
+        .map(|master| (master, entries_by_formid(master)))
+        .collect();
+
+    plugin
+        .iter_all_records()
+        .filter(|(entry, _)| {
+            usize::from(entry.formid().mod_index()) < own_index && entry.type_name() != "TES4"
+        })
+        .filter_map(|(entry, _)| {
+            let global = plugin.global_formid(entry.formid())?;
+            loaded.iter().find_map(|(master, entries)| {
+                let local = master.local_formid(&global)?;
+                Some((entry, *master, *entries.get(&local)?))
+            })
+        })
+        .collect()
+}
+
+/// Removes the records of [entries] that are in [remove], adding their ids to [removed]. The
+/// groups of a record's children are removed along with it, unless they still hold something, in
+/// which case the record is kept as their parent. Groups emptied by the removal are removed, while
+/// those that were already empty are left
+fn remove_entries<'data>(
+    entries: Vec<GroupEntry<'data>>,
+    remove: &HashSet<FormId>,
+    removed: &mut HashSet<FormId>,
+) -> Vec<GroupEntry<'data>> {
+    let mut kept = Vec::new();
+    for unit in entry_units(entries) {
+        let mut unit_kept = Vec::new();
+        let mut has_children = false;
+        for entry in unit {
+            match entry {
+                GroupEntry::Group(mut group) => {
+                    let held = !group.entries.is_empty();
+                    group.entries = remove_entries(group.entries, remove, removed);
+                    if held && group.entries.is_empty() {
+                        continue;
+                    }
+                    has_children |= !group.entries.is_empty();
+                    unit_kept.push(GroupEntry::Group(group));
+                }
+                record => unit_kept.push(record),
+            }
+        }
+        match unit_kept.first().and_then(GroupEntry::formid) {
+            Some(id) if remove.contains(&id) && !has_children => {
+                removed.insert(id);
+            }
+            _ => kept.extend(unit_kept),
+        }
+    }
+    kept
+}
+
+impl<'data> Plugin<'data> {
+    /// The override records of the plugin that are the same as the version in the last of its
+    /// masters that has them, in the order they are written. [masters] are the loaded masters,
+    /// in any order; records of masters that aren't loaded aren't checked
+    pub fn itms(&self, masters: &[Plugin<'_>]) -> Result<Vec<Itm>, WriteError> {
+        let mut itms = Vec::new();
+        let (mut buffer, mut master_buffer) = (Vec::new(), Vec::new());
+        for (entry, master, original) in overrides(self, masters) {
+            let id = entry.formid();
+            let kind = if same_masters(original, master, self)
+                && entry_bytes(entry)? == entry_bytes(original)?
+            {
+                ItmKind::Identical
+            } else {
+                let record = general(entry, &mut buffer);
+                let original = translated(original, master, self, &mut master_buffer);
+                match (record, original) {
+                    (Some(record), Some(original)) if equivalent(&record, &original) => {
+                        ItmKind::Equivalent
+                    }
+                    _ => continue,
+                }
+            };
+            itms.push(Itm {
+                id,
+                type_name: entry.type_name().to_string(),
+                master: master.name.clone(),
+                kind,
+            });
+        }
+        Ok(itms)
+    }
+
+    /// Removes the records found by [Plugin::itms], and refreshes the header. A record that
+    /// owns a group of children that still holds other overrides, such as a CELL whose references
+    /// were moved, is kept, as they need it. Returns the records that were removed
+    pub fn remove_itms(&mut self, masters: &[Plugin<'_>]) -> Result<Vec<Itm>, WriteError> {
+        let itms = self.itms(masters)?;
+        let remove: HashSet<FormId> = itms.iter().map(|x| x.id).collect();
+        let mut removed = HashSet::new();
+        let tops = std::mem::take(self.tops_mut());
+        for top in tops {
+            let top = match top {
+                Top::Record(record) if !matches!(record, Record::TES4(_)) => {
+                    let id = record.as_record_ref().formid();
+                    if remove.contains(&id) {
+                        removed.insert(id);
+                        continue;
+                    }
+                    Top::Record(record)
+                }
+                Top::Record(record) => Top::Record(record),
+                Top::Group(mut group) => {
+                    let emptied = match &mut group {
+                        Group::Unknown(group) => {
+                            let held = !group.entries.is_empty();
+                            let entries = std::mem::take(&mut group.entries);
+                            group.entries = remove_entries(entries, &remove, &mut removed);
+                            held && group.entries.is_empty()
+                        }
+                        Group::UnknownTop(group) => {
+                            let held = !group.entries.is_empty();
+                            let entries = std::mem::take(&mut group.entries);
+                            group.entries = remove_entries(entries, &remove, &mut removed);
+                            held && group.entries.is_empty()
+                        }
+                        group => {
+                            let held = !group.records().is_empty();
+                            group.retain_records(|record| {
+                                let id = record.formid();
+                                if remove.contains(&id) {
+                                    removed.insert(id);
+                                    false
+                                } else {
+                                    true
+                                }
+                            });
+                            held && group.records().is_empty()
+                        }
+                    };
+                    if emptied {
+                        continue;
+                    }
+                    Top::Group(group)
+                }
+            };
+            self.push_top(top);
+        }
+        self.refresh_header();
+        Ok(itms
+            .into_iter()
+            .filter(|x| removed.contains(&x.id))
+            .collect())
+    }
+
+    /// The override records of the plugin that look like the Creation Kit made them by accident,
+    /// in the order they are written, which are worth looking over before removing. Records are
+    /// compared with the last of [masters] that has them, as with [Plugin::itms], while records
+    /// that are identical to their master are left to it. A record can be found for more than one
+    /// reason
+    pub fn wild_edits(&self, masters: &[Plugin<'_>], options: WildEditOptions) -> Vec<WildEdit> {
+        let mut compared = HashMap::new();
+        let (mut buffer, mut master_buffer) = (Vec::new(), Vec::new());
+        for (entry, master, original) in overrides(self, masters) {
+            let record = general(entry, &mut buffer);
+            let original = translated(original, master, self, &mut master_buffer);
+            let (record, original) = match (record, original) {
+                (Some(record), Some(original)) => (record, original),
+                _ => continue,
+            };
+            let kind = if equivalent(&record, &original) {
+                if record.common.version_control_info == original.common.version_control_info
+                    && record.common.version == original.common.version
+                {
+                    continue;
+                }
+                WildEditKind::VersionControl
+            } else if float_noise(&record, &original, options.tolerance) {
+                WildEditKind::FloatNoise
+            } else {
+                continue;
+            };
+            compared.insert(entry.formid(), kind);
+        }
+
+        let out_of_scope = out_of_scope(self, options.distance);
+        let mut edits = Vec::new();
+        for (entry, _) in self.iter_all_records() {
+            let id = entry.formid();
+            let scope = Some(WildEditKind::OutOfScope).filter(|_| out_of_scope.contains(&id));
+            for kind in compared.get(&id).copied().into_iter().chain(scope) {
+                edits.push(WildEdit {
+                    id,
+                    type_name: entry.type_name().to_string(),
+                    kind,
+                });
+            }
+        }
+        edits
+    }
+
+    /// The placed references within cells that are flagged as deleted, in the order they are
+    /// written. Compressed records aren't looked at
+    pub fn deleted_references(&self) -> Vec<DeletedReference> {
+        self.iter_all_records()
+            .filter_map(|(entry, path)| match entry {
+                EntryRef::Record(record) => match record {
+                    RecordRef::Deleted(record) => deleted_reference(record, path.parent()),
+                    _ => None,
+                },
+                EntryRef::Compressed(_) => None,
+            })
+            .collect()
+    }
+
+    /// Fixes the references found by [Plugin::deleted_references] the way that xEdit cleans
+    /// them. The base object and position of an override that it doesn't have are restored from
+    /// the last of [masters] that has it, which are the loaded masters in any order. Then they
+    /// are undeleted and flagged as initially disabled, and given the enable parent and height of
+    /// [options]. The enable parent is only given if the plugin has Skyrim.esm, which the player
+    /// belongs to, as a master. The new fields are written into [buffer], which they borrow from
+    pub fn fix_deleted_references(
+        &mut self,
+        masters: &[Plugin<'_>],
+        options: UdrOptions,
+        buffer: &'data mut Vec<u8>,
+    ) -> UdrFixes {
+        buffer.clear();
+        let own_index = self.masters().len();
+        let player = GlobalFormId::new("Skyrim.esm", PLAYER_REFERENCE);
+        let xesp = match self.local_formid(&player) {
+            Some(player) if options.enable_parent => {
+                buffer.extend_from_slice(&player.id.to_le_bytes());
+                buffer.extend_from_slice(&OPPOSITE_STATE.to_le_bytes());
+                Some(0)
+            }
+            _ => None,
+        };
+        let mut fixes = UdrFixes::default();
+        // Where the base object and position given to each reference start in [buffer]
+        let mut placements = HashMap::new();
+        for (entry, path) in self.iter_all_records() {
+            let record = match entry {
+                EntryRef::Record(RecordRef::Deleted(record)) => record,
+                _ => continue,
+            };
+            let reference = match deleted_reference(record, path.parent()) {
+                Some(reference) => reference,
+                None => continue,
+            };
+            let master = if usize::from(reference.id.mod_index()) < own_index {
+                match master_placement(self, masters, reference.id) {
+                    Some(placement) => placement,
+                    None => {
+                        fixes.skipped.push(reference);
+                        continue;
+                    }
+                }
+            } else {
+                Placement::default()
+            };
+            let own = |name: &str| record.fields.iter().find(|x| x.type_name == name);
+            let name = match (own("NAME"), master.name) {
+                (None, Some(name)) => {
+                    buffer.extend_from_slice(&name);
+                    Some(buffer.len() - 4)
+                }
+                _ => None,
+            };
+            let own_position = own("DATA").filter(|x| x.data.len() == 24);
+            let restored = own_position.is_none();
+            let position = own_position.map(|x| x.data.to_vec()).or(master.position);
+            let position = match (position, options.z) {
+                (Some(mut position), z) if restored || z.is_some() => {
+                    if let Some(z) = z {
+                        position[8..12].copy_from_slice(&z.to_le_bytes());
+                    }
+                    buffer.extend_from_slice(&position);
+                    Some(buffer.len() - 24)
+                }
+                _ => None,
+            };
+            placements.insert(reference.id, (name, position));
+            fixes.fixed.push(reference);
+        }
+
+        let buffer: &'data [u8] = buffer;
+        for (entry, _) in self.iter_all_records_mut() {
+            let record = match entry {
+                EntryMut::Record(RecordMut::Deleted(record)) => record,
+                _ => continue,
+            };
+            let (name, position) = match placements.get(&FormId::new(record.common.id)) {
+                Some(placement) => *placement,
+                None => continue,
+            };
+            record.common.flags.flags &= !(record_flag::DELETED | record_flag::IGNORED);
+            record.common.flags.flags |= record_flag::INITIALLY_DISABLED;
+            if let Some(start) = name {
+                let name = GeneralField::new(b"NAME".as_bstr(), &buffer[start..start + 4]);
+                // The base object follows the editor id and scripts
+                let index = record
+                    .fields
+                    .iter()
+                    .position(|x| x.type_name != "EDID" && x.type_name != "VMAD")
+                    .unwrap_or(record.fields.len());
+                record.fields.insert(index, name);
+            }
+            if let Some(start) = xesp {
+                let xesp = GeneralField::new(b"XESP".as_bstr(), &buffer[start..start + 8]);
+                match record.fields.iter().position(|x| x.type_name == "XESP") {
+                    Some(index) => record.fields[index] = xesp,
+                    // The position is written last
+                    None => match record.fields.iter().position(|x| x.type_name == "DATA") {
+                        Some(index) => record.fields.insert(index, xesp),
+                        None => record.fields.push(xesp),
+                    },
+                }
+            }
+            if let Some(start) = position {
+                let data = &buffer[start..start + 24];
+                match record
+                    .fields
+                    .iter_mut()
+                    .find(|x| x.type_name == "DATA" && x.data.len() == 24)
+                {
+                    Some(position) => position.data = data,
+                    None => record
+                        .fields
+                        .push(GeneralField::new(b"DATA".as_bstr(), data)),
+                }
+            }
+        }
+        fixes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        context::ParseContext,
+        fixtures::{general, plugin, top},
+        groups::common::GeneralGroup,
+        records::{common::CompressedRecord, fields::common::GeneralField},
+    };
+    use bstr::ByteSlice;
+
+    fn data(type_name: &'static [u8], id: u32, data: &'static [u8]) -> GeneralRecord<'static> {
+        general(
+            type_name,
+            id,
+            vec![GeneralField::new(b"DATA".as_bstr(), data)],
+        )
+    }
+
+    fn list(id: u32, entry: &'static [u8]) -> Top<'static> {
+        let record = general(
+            b"FLST",
+            id,
+            vec![GeneralField::new(b"LNAM".as_bstr(), entry)],
+        );
+        let record = Record::from_general(record, &mut ParseContext::default()).unwrap();
+        Top::Group(Group::from_record(record).unwrap())
+    }
+
+    fn topic(id: u32, response: GeneralRecord<'static>) -> Vec<GroupEntry<'static>> {
+        vec![
+            general(b"DIAL", id, Vec::new()).into(),
+            GroupEntry::Group(GeneralGroup::new(
+                GroupType::TopicChildren(FormId::new(id)),
+                vec![response.into()],
+            )),
+        ]
+    }
+
+    fn found(itms: &[Itm]) -> Vec<(u32, &str, ItmKind)> {
+        itms.iter()
+            .map(|x| (x.id.id, x.type_name.as_str(), x.kind))
+            .collect()
+    }
+
+    #[test]
+    fn test_itms() {
+        let base = plugin(
+            "Base.esm",
+            &[],
+            vec![
+                list(0x800, &[1, 8, 0, 0]),
+                top(
+                    b"MISC",
+                    vec![
+                        data(b"MISC", 0x801, b"a").into(),
+                        data(b"MISC", 0x802, b"b").into(),
+                        data(b"MISC", 0x803, b"c").into(),
+                    ],
+                ),
+                top(b"DIAL", topic(0x804, data(b"INFO", 0x805, b"d"))),
+            ],
+        );
+        let masters = [base];
+
+        let mut looked_at = data(b"MISC", 0x801, b"a");
+        looked_at.common.version_control_info.day = 12;
+        looked_at.common.version = 44;
+        let compressed = CompressedRecord::compress(&data(b"MISC", 0x803, b"c"), 9).unwrap();
+        let mut patch = plugin(
+            "Patch.esp",
+            &["Base.esm"],
+            vec![
+                list(0x800, &[1, 8, 0, 0]),
+                top(
+                    b"MISC",
+                    vec![
+                        looked_at.into(),
+                        data(b"MISC", 0x802, b"changed").into(),
+                        compressed.into(),
+                        data(b"MISC", 0x0100_0806, b"a").into(),
+                    ],
+                ),
+                top(b"DIAL", topic(0x804, data(b"INFO", 0x805, b"changed"))),
+            ],
+        );
+        assert_eq!(
+            found(&patch.itms(&masters).unwrap()),
+            vec![
+                (0x800, "FLST", ItmKind::Identical),
+                (0x801, "MISC", ItmKind::Equivalent),
+                (0x803, "MISC", ItmKind::Equivalent),
+                (0x804, "DIAL", ItmKind::Identical),
+            ]
+        );
+        // Without its masters, nothing can be compared
+        assert!(patch.itms(&[]).unwrap().is_empty());
+
+        let removed = patch.remove_itms(&masters).unwrap();
+        assert_eq!(removed.len(), 3);
+        assert!(removed.iter().all(|x| x.master == "Base.esm"));
+        // The topic is kept for its changed response
+        assert_eq!(
+            found(&patch.itms(&masters).unwrap()),
+            vec![(0x804, "DIAL", ItmKind::Identical)]
+        );
+        let ids: Vec<u32> = patch
+            .iter_all_records()
+            .map(|(record, _)| record.formid().id)
+            .collect();
+        assert_eq!(ids, vec![0, 0x802, 0x0100_0806, 0x804, 0x805]);
+        assert_eq!(patch.header().unwrap().header().record_count, 7);
+
+        // The master's references are given by the plugin's masters before comparing
+        let other = plugin(
+            "Other.esp",
+            &["Extra.esm", "Base.esm"],
+            vec![
+                list(0x0100_0800, &[1, 8, 0, 1]),
+                list(0x0100_0801, &[1, 8, 0, 0]),
+            ],
+        );
+        assert_eq!(
+            found(&other.itms(&masters).unwrap()),
+            vec![(0x0100_0800, "FLST", ItmKind::Equivalent)]
+        );
+
+        // The same bytes aren't the same record when the masters give them to other plugins
+        let base = plugin(
+            "Base.esm",
+            &["Skyrim.esm"],
+            vec![
+                list(0x0100_0800, &[1, 8, 0, 0]),
+                list(0x0100_0801, &[2, 8, 0, 1]),
+            ],
+        );
+        let other = plugin(
+            "Other.esp",
+            &["Update.esm", "Base.esm", "Skyrim.esm"],
+            vec![
+                list(0x0100_0800, &[1, 8, 0, 0]),
+                list(0x0100_0801, &[2, 8, 0, 1]),
+            ],
+        );
+        assert_eq!(
+            found(&other.itms(&[base]).unwrap()),
+            vec![(0x0100_0801, "FLST", ItmKind::Identical)]
+        );
+    }
+
+    fn cell(
+        id: u32,
+        grid: &'static [u8],
+        children: Vec<GroupEntry<'static>>,
+    ) -> Vec<GroupEntry<'static>> {
+        let mut entries = vec![general(
+            b"CELL",
+            id,
+            vec![GeneralField::new(b"XCLC".as_bstr(), grid)],
+        )
+        .into()];
+        if !children.is_empty() {
+            entries.push(GroupEntry::Group(GeneralGroup::new(
+                GroupType::CellChildren(FormId::new(id)),
+                vec![GroupEntry::Group(GeneralGroup::new(
+                    GroupType::CellTemporaryChildren(FormId::new(id)),
+                    children,
+                ))],
+            )));
+        }
+        entries
+    }
+
+    fn world(id: u32, cells: Vec<Vec<GroupEntry<'static>>>) -> Vec<GroupEntry<'static>> {
+        vec![
+            general(b"WRLD", id, Vec::new()).into(),
+            GroupEntry::Group(GeneralGroup::new(
+                GroupType::WorldChildren(FormId::new(id)),
+                cells.into_iter().flatten().collect(),
+            )),
+        ]
+    }
+
+    #[test]
+    fn test_wild_edits() {
+        let base = plugin(
+            "Base.esm",
+            &[],
+            vec![top(
+                b"MISC",
+                vec![
+                    data(b"MISC", 0x801, b"a").into(),
+                    data(b"MISC", 0x802, &[0, 0, 0x80, 0x3F, 1, 0, 0, 0]).into(),
+                    data(b"MISC", 0x803, &[0, 0, 0x80, 0x3F, 1, 0, 0, 0]).into(),
+                    data(b"MISC", 0x804, &[0, 0, 0x80, 0x3F, 1, 0, 0, 0]).into(),
+                ],
+            )],
+        );
+        let masters = [base];
+
+        let mut looked_at = data(b"MISC", 0x801, b"a");
+        looked_at.common.version_control_info.day = 12;
+        let origin: &[u8] = &[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        let next: &[u8] = &[1, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0];
+        let far: &[u8] = &[10, 0, 0, 0, 0xFC, 0xFF, 0xFF, 0xFF, 0, 0, 0, 0];
+        let patch = plugin(
+            "Patch.esp",
+            &["Base.esm"],
+            vec![
+                top(
+                    b"MISC",
+                    vec![
+                        looked_at.into(),
+                        // Nudged by the smallest amount
+                        data(b"MISC", 0x802, &[1, 0, 0x80, 0x3F, 1, 0, 0, 0]).into(),
+                        data(b"MISC", 0x803, &[0, 0, 0, 0x40, 1, 0, 0, 0]).into(),
+                        data(b"MISC", 0x804, &[0, 0, 0x80, 0x3F, 2, 0, 0, 0]).into(),
+                    ],
+                ),
+                top(
+                    b"WRLD",
+                    [
+                        world(
+                            0x810,
+                            vec![
+                                cell(0x811, origin, vec![data(b"REFR", 0x0100_0900, b"").into()]),
+                                cell(0x812, next, Vec::new()),
+                                cell(0x813, far, Vec::new()),
+                            ],
+                        ),
+                        world(0x820, vec![cell(0x821, origin, Vec::new())]),
+                    ]
+                    .concat(),
+                ),
+            ],
+        );
+        let edits: Vec<(u32, WildEditKind)> = patch
+            .wild_edits(&masters, WildEditOptions::default())
+            .iter()
+            .map(|x| (x.id.id, x.kind))
+            .collect();
+        assert_eq!(
+            edits,
+            vec![
+                (0x801, WildEditKind::VersionControl),
+                (0x802, WildEditKind::FloatNoise),
+                (0x813, WildEditKind::OutOfScope),
+                (0x820, WildEditKind::OutOfScope),
+                (0x821, WildEditKind::OutOfScope),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_deleted_references() {
+        const POSITION: &[u8] = &[
+            0, 0, 0x80, 0x3F, 0, 0, 0, 0x40, 0, 0, 0x40, 0x40, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        let reference = |type_name, id, flags, fields: &[(&'static [u8], &'static [u8])]| {
+            let mut record = general(
+                type_name,
+                id,
+                fields
+                    .iter()
+                    .map(|(name, data)| GeneralField::new(name.as_bstr(), data))
+                    .collect(),
+            );
+            record.common.flags.flags = flags;
+            record.into()
+        };
+        let cell = |id, references| {
+            top(
+                b"CELL",
+                vec![
+                    general(b"CELL", id, Vec::new()).into(),
+                    GroupEntry::Group(GeneralGroup::new(
+                        GroupType::CellChildren(FormId::new(id)),
+                        vec![GroupEntry::Group(GeneralGroup::new(
+                            GroupType::CellTemporaryChildren(FormId::new(id)),
+                            references,
+                        ))],
+                    )),
+                ],
+            )
+        };
+        let deleted = record_flag::DELETED;
+        let base = plugin(
+            "Base.esm",
+            &["Skyrim.esm"],
+            vec![cell(
+                0x0100_0900,
+                vec![
+                    reference(
+                        b"REFR",
+                        0x0100_0901,
+                        0,
+                        &[(b"NAME", &[1, 8, 0, 0]), (b"DATA", POSITION)],
+                    ),
+                    reference(
+                        b"ACHR",
+                        0x0100_0902,
+                        0,
+                        &[(b"NAME", &[7, 0, 0, 0]), (b"DATA", POSITION)],
+                    ),
+                ],
+            )],
+        );
+        let masters = [base];
+        let mut buffer = Vec::new();
+        let mut patch = plugin(
+            "Patch.esp",
+            &["Base.esm", "Skyrim.esm"],
+            vec![
+                cell(
+                    0x900,
+                    vec![
+                        reference(
+                            b"REFR",
+                            0x901,
+                            deleted,
+                            &[(b"NAME", &[1, 8, 0, 0]), (b"DATA", POSITION)],
+                        ),
+                        reference(
+                            b"ACHR",
+                            0x902,
+                            deleted,
+                            &[(b"XESP", &[3, 9, 0, 0, 0, 0, 0, 0])],
+                        ),
+                        reference(b"REFR", 0x903, 0, &[(b"DATA", POSITION)]),
+                        // Skyrim.esm isn't loaded
+                        reference(b"REFR", 0x0100_0905, deleted, &[]),
+                        // The plugin's own, which has no master to restore from
+                        reference(b"REFR", 0x0200_0906, deleted, &[]),
+                    ],
+                ),
+                // Deleted records that aren't references are left alone
+                top(b"MISC", vec![reference(b"MISC", 0x904, deleted, &[])]),
+            ],
+        );
+        let found = |ids: &[u32]| -> Vec<DeletedReference> {
+            ids.iter()
+                .map(|id| DeletedReference {
+                    id: FormId::new(*id),
+                    type_name: if *id == 0x902 { "ACHR" } else { "REFR" }.to_string(),
+                    cell: FormId::new(0x900),
+                })
+                .collect()
+        };
+        assert_eq!(
+            patch.deleted_references(),
+            found(&[0x901, 0x902, 0x0100_0905, 0x0200_0906])
+        );
+
+        let fixes = patch.fix_deleted_references(&masters, UdrOptions::default(), &mut buffer);
+        assert_eq!(fixes.fixed, found(&[0x901, 0x902, 0x0200_0906]));
+        assert_eq!(fixes.skipped, found(&[0x0100_0905]));
+        assert_eq!(patch.deleted_references(), fixes.skipped);
+        let records: Vec<_> = patch
+            .iter_all_records()
+            .filter_map(|(entry, _)| match entry {
+                EntryRef::Record(RecordRef::Unknown(record)) => Some((
+                    record.common.id,
+                    record.common.flags.flags,
+                    record
+                        .fields
+                        .iter()
+                        .map(|x| (x.type_name.to_string(), x.data.to_vec()))
+                        .collect(),
+                )),
+                _ => None,
+            })
+            .collect();
+        let mut moved = POSITION.to_vec();
+        moved[8..12].copy_from_slice(&(-30000f32).to_le_bytes());
+        let field = |name: &str, data: &[u8]| (name.to_string(), data.to_vec());
+        let disabled = record_flag::INITIALLY_DISABLED;
+        // The player is given by the plugin's masters
+        let xesp = field("XESP", &[0x14, 0, 0, 1, 1, 0, 0, 0]);
+        assert_eq!(
+            records,
+            vec![
+                (0x900, 0, Vec::new()),
+                (
+                    0x901,
+                    disabled,
+                    vec![
+                        field("NAME", &[1, 8, 0, 0]),
+                        xesp.clone(),
+                        field("DATA", &moved),
+                    ]
+                ),
+                // The base object and position are restored from the master
+                (
+                    0x902,
+                    disabled,
+                    vec![
+                        field("NAME", &[7, 0, 0, 1]),
+                        xesp.clone(),
+                        field("DATA", &moved),
+                    ]
+                ),
+                (0x903, 0, vec![field("DATA", POSITION)]),
+                (0x0200_0906, disabled, vec![xesp]),
+            ]
+        );
+    }
+}
@@ -0,0 +1,199 @@
+// Making a plugin fit to be flagged light. A light plugin shares its load order slot with other
+// light plugins, so its new records can only use the object ids 0x800 to 0xFFF. Compacting
+// renumbers the new records outside of that range into the ids within it that are free, and
+// changes every reference to them to match, see Plugin::remap_formids. Other plugins that refer
+// to the renumbered records aren't changed, so this is meant for plugins nothing depends on yet.
+// Records that weren't converted could refer to the renumbered records without it being seen, so
+// plugins with any are refused.
+
+use crate::{
+    edit::EditError,
+    plugin::Plugin,
+    records::common::{FormId, FIRST_OBJECT_INDEX, LAST_LIGHT_OBJECT_INDEX},
+    remap::FormIdMap,
+};
+use std::collections::HashSet;
+
+/// Whether a plugin's new records let it be flagged light, see [Plugin::light_eligibility]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum LightEligibility {
+    /// Every new record is within [Plugin::light_range]
+    Eligible,
+    /// These new records are outside of the range, but can be renumbered into it with
+    /// [Plugin::compact_light]
+    NeedsCompaction(Vec<FormId>),
+    /// There are more new records than the range holds
+    TooManyRecords { records: usize, max: usize },
+}
+
+impl Plugin<'_> {
+    /// Form ids of the records that are new in this plugin, rather than overrides
+    fn new_formids(&self) -> Vec<FormId> {
+        let own_index = self.masters().len();
+        self.formids_and_groups()
+            .0
+            .into_iter()
+            .filter(|id| usize::from(id.mod_index()) == own_index)
+            .collect()
+    }
+
+    /// Checks whether the new records of the plugin fit a light plugin, or could be made to
+    pub fn light_eligibility(&self) -> LightEligibility {
+        let outside = self.outside_light_range();
+        if outside.is_empty() {
+            return LightEligibility::Eligible;
+        }
+        let range = self.light_range();
+        let max = (range.end() - range.start() + 1) as usize;
+        let records = self.new_formids().len();
+        if records > max {
+            LightEligibility::TooManyRecords { records, max }
+        } else {
+            LightEligibility::NeedsCompaction(outside)
+        }
+    }
+
+    /// Renumbers the new records outside of [Plugin::light_range] into the free object ids from
+    /// 0x800 to 0xFFF, in the order of their current ids, and changes the references to them.
+    /// Headers that allow ids below 0x800 have those used once the others run out.
+    /// The next object id of the HEDR is set to follow the highest new record, so that it stays
+    /// within the range. Returns the ids that were changed.
+    /// Fails without changing anything if there aren't enough free ids, or if some records
+    /// weren't converted, as their references to the renumbered records can't be changed
+    pub fn compact_light(&mut self) -> Result<FormIdMap, EditError> {
+        let hidden = self.hidden_records();
+        if hidden != 0 {
+            return Err(EditError::HiddenReferences { records: hidden });
+        }
+        let own_index = self.masters().len() as u8;
+        let range = self.light_range();
+        let new_ids = self.new_formids();
+        let used: HashSet<u32> = new_ids.iter().map(|id| id.object_index()).collect();
+        let mut outside = self.outside_light_range();
+        outside.sort_by_key(|id| id.id);
+        outside.dedup();
+
+        let mut free = (FIRST_OBJECT_INDEX..=LAST_LIGHT_OBJECT_INDEX)
+            .chain(*range.start()..FIRST_OBJECT_INDEX)
+            .filter(|index| !used.contains(index));
+        let mut map = FormIdMap::new();
+        for id in outside.iter() {
+            match free.next() {
+                Some(index) => map.insert(*id, FormId::from_parts(own_index, index)),
+                None => {
+                    return Err(EditError::TooManyLightRecords {
+                        records: new_ids.len(),
+                        max: (range.end() - range.start() + 1) as usize,
+                    })
+                }
+            };
+        }
+
+        self.remap_formids(&map);
+        let next_object_id = self
+            .new_formids()
+            .iter()
+            .map(|id| id.object_index() + 1)
+            .fold(FIRST_OBJECT_INDEX, u32::max);
+        if let Some(header) = self.header_mut() {
+            header.header_mut().next_object_id = next_object_id;
+        }
+        Ok(map)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fixtures::{general, list, top},
+        records::RecordRef,
+    };
+    use bstr::BString;
+
+    /// The amount of object ids from 0x800 to 0xFFF
+    const LIGHT_OBJECT_COUNT: usize = (LAST_LIGHT_OBJECT_INDEX - FIRST_OBJECT_INDEX + 1) as usize;
+
+    #[test]
+    fn test_compact_light() {
+        let mut plugin = Plugin::new("Patch.esp");
+        plugin
+            .header_mut()
+            .unwrap()
+            .push_master(BString::from("Base.esm"));
+        plugin.push_top(list(0x0100_0800, &[&[0, 0x20, 0, 1]]));
+        plugin.push_top(list(0x0100_2000, &[&[0, 0x08, 0, 1]]));
+        plugin.push_top(list(0x0100_1000, &[&[0, 0x20, 0, 1]]));
+        // An override of a record of the master, which stays as it is
+        plugin.push_top(list(0x0000_5000, &[&[0, 0x10, 0, 1]]));
+        plugin.refresh_header();
+        assert_eq!(
+            plugin.light_eligibility(),
+            LightEligibility::NeedsCompaction(vec![
+                FormId::new(0x0100_2000),
+                FormId::new(0x0100_1000)
+            ])
+        );
+
+        let map = plugin.compact_light().unwrap();
+        assert_eq!(map.len(), 2);
+        assert_eq!(map[&FormId::new(0x0100_1000)], FormId::new(0x0100_0801));
+        assert_eq!(map[&FormId::new(0x0100_2000)], FormId::new(0x0100_0802));
+        assert_eq!(plugin.light_eligibility(), LightEligibility::Eligible);
+        assert_eq!(plugin.header().unwrap().header().next_object_id, 0x803);
+        let lists: Vec<(u32, Vec<u32>)> = plugin
+            .records()
+            .into_iter()
+            .filter_map(|record| match record {
+                RecordRef::FLST(flst) => {
+                    Some((record.formid().id, flst.entries().map(|x| x.id).collect()))
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            lists,
+            vec![
+                (0x0100_0800, vec![0x0100_0802]),
+                (0x0100_0802, vec![0x0100_0800]),
+                (0x0100_0801, vec![0x0100_0802]),
+                (0x0000_5000, vec![0x0100_0801]),
+            ]
+        );
+        plugin.set_light(true).unwrap();
+
+        // A record that wasn't converted could refer to those that would be renumbered
+        plugin.push_top(list(0x0100_3000, &[]));
+        plugin.push_top(top(
+            b"MISC",
+            vec![general(b"MISC", 0x0100_0900, Vec::new()).into()],
+        ));
+        let before = plugin.clone();
+        assert_eq!(
+            plugin.compact_light(),
+            Err(EditError::HiddenReferences { records: 1 })
+        );
+        assert_eq!(plugin, before);
+    }
+
+    #[test]
+    fn test_too_many_light_records() {
+        let mut plugin = Plugin::new("Patch.esp");
+        for index in 0..=LIGHT_OBJECT_COUNT as u32 {
+            plugin.push_top(list(0x1000 + index, &[]));
+        }
+        assert_eq!(
+            plugin.light_eligibility(),
+            LightEligibility::TooManyRecords {
+                records: LIGHT_OBJECT_COUNT + 1,
+                max: LIGHT_OBJECT_COUNT
+            }
+        );
+        let before = plugin.clone();
+        assert!(matches!(
+            plugin.compact_light(),
+            Err(EditError::TooManyLightRecords { .. })
+        ));
+        assert_eq!(plugin, before);
+    }
+}
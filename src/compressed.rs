@@ -0,0 +1,245 @@
+// Parsing compressed records like any other, and compressing them again when writing. The fields
+// of a compressed record can't be borrowed from the plugin's data, so the whole file is first
+// decompressed into a buffer that the parsed plugin borrows from instead. The original records are
+// kept alongside it, so that writing can copy the compressed data of those that are unchanged, or
+// compress them anew, following [WriteOptions::recompression].
+
+use crate::{
+    context::{ParseContext, WriteOptions},
+    groups::common::{entry_size, GROUPH_SIZE},
+    parse::{Parse, ParseError},
+    plugin::{write_entry_replacing, Plugin},
+    records::common::{record_flag, CompressedRecord, FormId, GeneralRecord, Recompression},
+    util::{Writable, WriteError},
+    walk::EntryRef,
+    GeneralError,
+};
+use std::{borrow::Cow, collections::HashMap, convert::TryFrom, io::Write};
+
+/// A plugin file with each of its compressed records decompressed in place, see
+/// [decompress_plugin]
+#[derive(Debug, Clone)]
+pub struct DecompressedPlugin<'data> {
+    /// The plugin file, with every record uncompressed. Parse this rather than the original
+    pub data: Vec<u8>,
+    /// The records that were compressed, by their id. Records that share an id with another
+    /// are compressed anew, as which is which can't be told
+    originals: HashMap<FormId, Option<CompressedRecord<'data>>>,
+}
+
+/// Decompresses each compressed record of the plugin file [data], so that it can be parsed into
+/// typed records
+pub fn decompress_plugin(data: &[u8]) -> Result<DecompressedPlugin<'_>, ParseError<'_>> {
+    let mut decompressed = DecompressedPlugin {
+        data: Vec::with_capacity(data.len()),
+        originals: HashMap::new(),
+    };
+    let mut buffer = Vec::new();
+    decompress_entries(data, &mut decompressed, &mut buffer)?;
+    Ok(decompressed)
+}
+
+/// Writes [data] to the end of [decompressed], decompressing its records and fixing the size of
+/// its groups to match
+fn decompress_entries<'data>(
+    mut data: &'data [u8],
+    decompressed: &mut DecompressedPlugin<'data>,
+    buffer: &mut Vec<u8>,
+) -> Result<(), ParseError<'data>> {
+    while !data.is_empty() {
+        let size = entry_size(data).ok_or(ParseError::UnexpectedEOF)?;
+        if data.len() < size {
+            return Err(ParseError::UnexpectedEOF);
+        }
+        let (entry, rest) = data.split_at(size);
+        data = rest;
+
+        if &entry[..4] == b"GRUP" {
+            let start = decompressed.data.len();
+            decompressed.data.extend_from_slice(&entry[..GROUPH_SIZE]);
+            decompress_entries(&entry[GROUPH_SIZE..], decompressed, buffer)?;
+            let size = decompressed.data.len() - start;
+            let size = u32::try_from(size).map_err(|_| {
+                ParseError::Decompress(format!("A group would be {} bytes decompressed", size))
+            })?;
+            decompressed.data[start + 4..start + 8].copy_from_slice(&size.to_le_bytes());
+            continue;
+        }
+
+        let flags = u32::from_le_bytes([entry[8], entry[9], entry[10], entry[11]]);
+        if flags & record_flag::COMPRESSED == 0 {
+            decompressed.data.extend_from_slice(entry);
+            continue;
+        }
+        let (_, record) = CompressedRecord::parse(entry)?;
+        // Errors borrow the decompressed data, which is about to be reused
+        let general = record
+            .decompress(buffer)
+            .map_err(|error| ParseError::Decompress(error.to_string()))?;
+        general
+            .write_to(&mut decompressed.data)
+            .map_err(|error| ParseError::Decompress(error.to_string()))?;
+        decompressed
+            .originals
+            .entry(FormId::new(record.common.id))
+            .and_modify(|x| *x = None)
+            .or_insert(Some(record));
+    }
+    Ok(())
+}
+
+impl<'data> DecompressedPlugin<'data> {
+    /// Amount of records that were compressed
+    pub fn compressed(&self) -> usize {
+        self.originals.len()
+    }
+
+    /// Parses the decompressed plugin, see [Plugin::parse]
+    pub fn parse<S: Into<String>>(
+        &self,
+        name: S,
+        ctx: &mut ParseContext,
+    ) -> Result<Plugin<'_>, GeneralError<'_>> {
+        Plugin::parse(name, &self.data, ctx)
+    }
+
+    /// Writes [plugin], which was parsed from this, compressing the records that were compressed
+    /// following [WriteOptions::recompression]. Other records are written as they are, apart from
+    /// [WriteOptions::encoding], see [Plugin::write_with_options]
+    pub fn write<T: Write>(
+        &self,
+        plugin: &Plugin<'_>,
+        options: &WriteOptions,
+        w: &mut T,
+    ) -> Result<(), WriteError> {
+        let mode = options.recompression;
+        plugin.write_entries_with(w, |entry| {
+            let data = match entry {
+                EntryRef::Record(record) if options.encoding == plugin.encoding => {
+                    record.to_vec()?
+                }
+                entry => write_entry_replacing(entry, mode, |general| {
+                    plugin.transcode_fields(general, options.encoding)
+                })?,
+            };
+            if !self.originals.contains_key(&entry.formid()) {
+                return Ok(Cow::Owned(data));
+            }
+            let (_, general) = GeneralRecord::parse(&data)
+                .expect("Failed to read back a record that was just written");
+            Ok(match self.recompress(&general, mode)? {
+                Some(compressed) => Cow::Owned(compressed),
+                None => Cow::Owned(data),
+            })
+        })
+    }
+
+    /// [record] compressed following [mode], if it was compressed and isn't any more
+    fn recompress(
+        &self,
+        record: &GeneralRecord<'_>,
+        mode: Recompression,
+    ) -> Result<Option<Vec<u8>>, WriteError> {
+        let original = match self.originals.get(&FormId::new(record.common.id)) {
+            Some(original) if !record.common.flags.is(record_flag::COMPRESSED) => original,
+            _ => return Ok(None),
+        };
+        let compressed = match (original, mode) {
+            (Some(original), mode) => original.recompress(record, mode)?,
+            (None, Recompression::KeepUnchanged(level) | Recompression::Level(level)) => {
+                CompressedRecord::compress(record, level)?
+            }
+        };
+        compressed.to_vec().map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        groups::{common::GroupEntry, Group},
+        records::{common::CommonRecordInfo, fields::common::GeneralField},
+        Top,
+    };
+    use bstr::ByteSlice;
+
+    #[test]
+    fn test_decompressed_plugin() {
+        let record = |type_name: &'static [u8], id| {
+            let mut common = CommonRecordInfo::test_default();
+            common.flags.flags = 0;
+            common.id = id;
+            GeneralRecord {
+                type_name: type_name.as_bstr(),
+                common,
+                fields: vec![GeneralField::new(b"EDID".as_bstr(), b"Compressed\0")],
+            }
+        };
+        let group = |label: &[u8], entries: Vec<u8>| {
+            let mut data = b"GRUP".to_vec();
+            data.extend_from_slice(&((GROUPH_SIZE + entries.len()) as u32).to_le_bytes());
+            data.extend_from_slice(label);
+            data.extend_from_slice(&[0; 12]);
+            data.extend(entries);
+            data
+        };
+        let mut header = record(b"TES4", 0);
+        header.fields = vec![GeneralField::new(b"HEDR".as_bstr(), &[0; 12])];
+        let mut data = header.to_vec().unwrap();
+        let mut actions = CompressedRecord::compress(&record(b"AACT", 0x801), 9)
+            .unwrap()
+            .to_vec()
+            .unwrap();
+        actions.extend(record(b"AACT", 0x802).to_vec().unwrap());
+        data.extend(group(b"AACT", actions));
+        let cell = CompressedRecord::compress(&record(b"CELL", 0x803), 9).unwrap();
+        data.extend(group(b"CELL", cell.to_vec().unwrap()));
+
+        let decompressed = decompress_plugin(&data).unwrap();
+        assert_eq!(decompressed.compressed(), 2);
+        let plugin = decompressed
+            .parse("Test.esp", &mut ParseContext::default())
+            .unwrap();
+        match &plugin.tops()[1] {
+            Top::Group(Group::AACT(group)) => {
+                assert_eq!(group.records.len(), 2);
+                assert!(group.kept.is_empty());
+            }
+            x => panic!("Unexpected entry {:?}", x),
+        }
+        match &plugin.tops()[2] {
+            Top::Group(Group::UnknownTop(group)) => {
+                assert_eq!(
+                    group.entries,
+                    vec![GroupEntry::Record(record(b"CELL", 0x803))]
+                )
+            }
+            x => panic!("Unexpected entry {:?}", x),
+        }
+
+        // Unchanged records keep their compressed data
+        let mut written = Vec::new();
+        let options = WriteOptions::default();
+        decompressed.write(&plugin, &options, &mut written).unwrap();
+        assert_eq!(written, data);
+
+        // Or are compressed anew, which reads back the same
+        let options = WriteOptions {
+            recompression: Recompression::Level(0),
+            ..WriteOptions::default()
+        };
+        let mut written = Vec::new();
+        decompressed.write(&plugin, &options, &mut written).unwrap();
+        assert_ne!(written, data);
+        assert_eq!(decompress_plugin(&written).unwrap().data, decompressed.data);
+
+        let mut corrupt = data.clone();
+        let length = corrupt.len();
+        corrupt[length - 2] ^= 0xFF;
+        assert!(matches!(
+            decompress_plugin(&corrupt),
+            Err(ParseError::Decompress(_))
+        ));
+    }
+}
@@ -0,0 +1,241 @@
+// Evaluation of CTDA conditions.
+// The game has hundreds of condition functions that depend on its runtime state, so the
+// evaluator only knows about the functions and globals that the caller supplies. Anything else
+// is left as unknown, which still allows simplifying conditions that are always true or false.
+
+use crate::records::{
+    common::FormId,
+    fields::ctda::{ComparisonValue, FunctionIndex, CTDA},
+};
+use std::collections::HashMap;
+
+/// Result of evaluating a condition, where the value of the function or global may not be known
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Truth {
+    True,
+    False,
+    Unknown,
+}
+impl Truth {
+    pub fn and(self, other: Truth) -> Truth {
+        match (self, other) {
+            (Truth::False, _) | (_, Truth::False) => Truth::False,
+            (Truth::True, Truth::True) => Truth::True,
+            _ => Truth::Unknown,
+        }
+    }
+
+    pub fn or(self, other: Truth) -> Truth {
+        match (self, other) {
+            (Truth::True, _) | (_, Truth::True) => Truth::True,
+            (Truth::False, Truth::False) => Truth::False,
+            _ => Truth::Unknown,
+        }
+    }
+}
+impl From<bool> for Truth {
+    fn from(value: bool) -> Truth {
+        if value {
+            Truth::True
+        } else {
+            Truth::False
+        }
+    }
+}
+
+/// Computes the value of a condition function, None if it can't be known
+pub type FunctionHandler<'a> = Box<dyn Fn(&CTDA) -> Option<f32> + 'a>;
+
+/// Conditions after removing the parts that are known
+#[derive(Debug, Clone, PartialEq)]
+pub enum Simplified {
+    /// The conditions always (or never) pass
+    Always(bool),
+    /// The conditions that could not be evaluated, which still have to pass
+    Conditional(Vec<CTDA>),
+}
+
+#[derive(Default)]
+pub struct ConditionEvaluator<'a> {
+    functions: HashMap<FunctionIndex, FunctionHandler<'a>>,
+    globals: HashMap<FormId, f32>,
+}
+impl<'a> ConditionEvaluator<'a> {
+    pub fn new() -> ConditionEvaluator<'a> {
+        ConditionEvaluator::default()
+    }
+
+    /// Uses [handler] for conditions that call the function at [index].
+    /// Replaces any handler that was already set for it.
+    pub fn set_function<F>(&mut self, index: FunctionIndex, handler: F)
+    where
+        F: Fn(&CTDA) -> Option<f32> + 'a,
+    {
+        self.functions.insert(index, Box::new(handler));
+    }
+
+    /// Value of the GLOB [id], for conditions that compare against it
+    pub fn set_global(&mut self, id: FormId, value: f32) {
+        self.globals.insert(id, value);
+    }
+
+    pub fn evaluate(&self, condition: &CTDA) -> Truth {
+        let left = self
+            .functions
+            .get(&condition.function_index)
+            .and_then(|handler| handler(condition));
+        let right = match condition.comp_value {
+            ComparisonValue::Float(value) => Some(value),
+            ComparisonValue::Glob(id) => self.globals.get(&id).copied(),
+        };
+        match (left, right) {
+            (Some(left), Some(right)) => condition.op_data.operator.compare(left, right).into(),
+            _ => Truth::Unknown,
+        }
+    }
+
+    /// Evaluates a list of conditions as the game does: a condition with the OR flag is ORed
+    /// with the one after it, and those runs are then ANDed together. So `A or B and C` is
+    /// `(A or B) and C`.
+    pub fn evaluate_all(&self, conditions: &[CTDA]) -> Truth {
+        or_groups(conditions).fold(Truth::True, |result, group| {
+            let group = group
+                .iter()
+                .fold(Truth::False, |group, x| group.or(self.evaluate(x)));
+            result.and(group)
+        })
+    }
+
+    /// Removes the conditions whose result is known, keeping those that still matter
+    pub fn simplify(&self, conditions: &[CTDA]) -> Simplified {
+        let mut remaining = Vec::new();
+        for group in or_groups(conditions) {
+            let truths: Vec<Truth> = group.iter().map(|x| self.evaluate(x)).collect();
+            if truths.contains(&Truth::True) {
+                continue;
+            }
+            let mut kept: Vec<CTDA> = group
+                .iter()
+                .zip(truths)
+                .filter(|(_, truth)| *truth == Truth::Unknown)
+                .map(|(x, _)| x.clone())
+                .collect();
+            match kept.last_mut() {
+                // The last of a run does not continue into the next run
+                Some(last) => last.op_data.flags.flags &= !0b1,
+                None => return Simplified::Always(false),
+            }
+            remaining.extend(kept);
+        }
+        if remaining.is_empty() {
+            Simplified::Always(true)
+        } else {
+            Simplified::Conditional(remaining)
+        }
+    }
+}
+
+/// Splits the conditions into the runs that are ORed together
+fn or_groups(conditions: &[CTDA]) -> impl Iterator<Item = &[CTDA]> {
+    let mut rest = conditions;
+    std::iter::from_fn(move || {
+        if rest.is_empty() {
+            return None;
+        }
+        let end = rest
+            .iter()
+            .position(|x| !x.op_data.flags.or())
+            .map(|x| x + 1)
+            .unwrap_or_else(|| rest.len());
+        let (group, next) = rest.split_at(end);
+        rest = next;
+        Some(group)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::records::fields::ctda::{Flags, Operator, OperatorData, Parameters, RunOn};
+
+    fn condition(function_index: u16, operator: Operator, value: f32, or: bool) -> CTDA {
+        CTDA {
+            op_data: OperatorData {
+                operator,
+                flags: Flags::from_byte(or as u8),
+            },
+            unknown: [0; 3],
+            comp_value: ComparisonValue::Float(value),
+            function_index,
+            padding: 0,
+            parameters: Parameters::Normal {
+                first: 0,
+                second: 0,
+            },
+            run_on: RunOn::Subject,
+            reference: FormId::new(0),
+            unknown2: -1,
+        }
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let mut evaluator = ConditionEvaluator::new();
+        // GetLevel
+        evaluator.set_function(80, |_| Some(10.0));
+        evaluator.set_global(FormId::new(0x39), 20.0);
+
+        let level = condition(80, Operator::GreaterThanEqual, 5.0, false);
+        assert_eq!(evaluator.evaluate(&level), Truth::True);
+        let mut glob = condition(80, Operator::LessThan, 0.0, false);
+        glob.comp_value = ComparisonValue::Glob(FormId::new(0x39));
+        assert_eq!(evaluator.evaluate(&glob), Truth::True);
+        glob.comp_value = ComparisonValue::Glob(FormId::new(0x40));
+        assert_eq!(evaluator.evaluate(&glob), Truth::Unknown);
+        let unknown = condition(1, Operator::Equal, 1.0, false);
+        assert_eq!(evaluator.evaluate(&unknown), Truth::Unknown);
+
+        let low = condition(80, Operator::LessThan, 5.0, false);
+        let mut low_or = low.clone();
+        low_or.op_data.flags = Flags::from_byte(1);
+        // (level < 5 or unknown) and level >= 5
+        let conditions = vec![low_or.clone(), unknown.clone(), level.clone()];
+        assert_eq!(evaluator.evaluate_all(&conditions), Truth::Unknown);
+        assert_eq!(
+            evaluator.simplify(&conditions),
+            Simplified::Conditional(vec![unknown.clone()])
+        );
+        // level < 5 or level >= 5
+        let mut level_or = level.clone();
+        level_or.op_data.flags = Flags::from_byte(1);
+        assert_eq!(
+            evaluator.evaluate_all(&[low_or.clone(), level.clone()]),
+            Truth::True
+        );
+        assert_eq!(
+            evaluator.simplify(&[low_or, level.clone()]),
+            Simplified::Always(true)
+        );
+        // unknown and level < 5
+        assert_eq!(
+            evaluator.evaluate_all(&[unknown.clone(), low.clone()]),
+            Truth::False
+        );
+        assert_eq!(
+            evaluator.simplify(&[unknown.clone(), low]),
+            Simplified::Always(false)
+        );
+        // (unknown or level >= 5 or unknown), the trailing OR is dropped
+        let mut unknown_or = unknown.clone();
+        unknown_or.op_data.flags = Flags::from_byte(1);
+        assert_eq!(
+            evaluator.simplify(&[unknown_or.clone(), level_or, unknown_or.clone()]),
+            Simplified::Always(true)
+        );
+        assert_eq!(
+            evaluator.simplify(&[unknown_or]),
+            Simplified::Conditional(vec![unknown])
+        );
+        assert_eq!(evaluator.evaluate_all(&[]), Truth::True);
+    }
+}
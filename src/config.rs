@@ -0,0 +1,481 @@
+// Project settings read from a vivec.toml, so that a team can commit the settings that the CLI
+// runs with alongside their mod. Relative paths in the file are relative to the directory that
+// it is in.
+
+use crate::{
+    context::{
+        DuplicateFieldPolicy, FormVersion, ParseContext, ParseOptions, WriteOptions,
+        FORM_VERSION_LE, FORM_VERSION_SE,
+    },
+    data_folder::{read_plugin_list, DataFolder},
+    edit::MasterEditPolicy,
+    load_order::OpenError,
+    plugin::Plugin,
+    records::common::TextEncoding,
+    strings::{Localization, StringsError, DEFAULT_LANGUAGE},
+};
+use std::{
+    fmt::{Display, Formatter},
+    path::{Path, PathBuf},
+};
+use toml::Value;
+
+pub const CONFIG_NAME: &str = "vivec.toml";
+
+/// Which release of the game plugins are made for
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum GameFormat {
+    /// `game = "le"`
+    LegendaryEdition,
+    /// `game = "se"`
+    SpecialEdition,
+}
+impl GameFormat {
+    /// The newest form version that the game reads
+    pub fn form_version(self) -> u16 {
+        match self {
+            GameFormat::LegendaryEdition => FORM_VERSION_LE,
+            GameFormat::SpecialEdition => FORM_VERSION_SE,
+        }
+    }
+}
+
+/// The `[validation]` table
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidationConfig {
+    /// `require_editor_id`, see [ParseOptions::require_editor_id]
+    pub require_editor_id: bool,
+    /// `skip_malformed`, see [ParseOptions::skip_malformed]
+    pub skip_malformed: bool,
+    /// `duplicate_fields`, either "error" or "keep-first"
+    pub duplicate_fields: DuplicateFieldPolicy,
+    /// `watch`, whether `vivec watch` parses plugins as they change
+    pub watch: bool,
+    /// `budget_warn_at`, the fraction of a limit that `vivec budget` warns at
+    pub budget_warn_at: Option<f64>,
+    /// `budget_light_records`, the amount of new records `vivec budget` allows a light master
+    pub budget_light_records: Option<usize>,
+    /// `budget_onam_entries`, the amount of ONAM entries `vivec budget` allows
+    pub budget_onam_entries: Option<usize>,
+}
+impl Default for ValidationConfig {
+    fn default() -> ValidationConfig {
+        let options = ParseOptions::default();
+        ValidationConfig {
+            require_editor_id: options.require_editor_id,
+            skip_malformed: options.skip_malformed,
+            duplicate_fields: options.duplicate_fields,
+            watch: false,
+            budget_warn_at: None,
+            budget_light_records: None,
+            budget_onam_entries: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Config {
+    /// The directory that the config was read from, or the directory it was looked for in
+    pub root: PathBuf,
+    /// `data_folder`, the game's Data directory. Plugins given by name are looked for in it
+    pub data_folder: Option<PathBuf>,
+    /// `load_order`, a plugins.txt or loadorder.txt, giving the plugins to use when a subcommand
+    /// isn't given any
+    pub load_order: Option<PathBuf>,
+    /// `output_dir`, where relative output paths are written to
+    pub output_dir: Option<PathBuf>,
+    /// `game`
+    pub game: Option<GameFormat>,
+    /// `language`, which string tables LStrings are looked up in, such as "french"
+    pub language: Option<String>,
+    /// `allow_master_edits`, whether records that belong to a master can be edited in place,
+    /// see [MasterEditPolicy]
+    pub allow_master_edits: bool,
+    /// `encoding`, how the text of string fields is encoded, such as "utf-8" or "windows-1251".
+    /// See [TextEncoding::from_label]
+    pub encoding: TextEncoding,
+    pub validation: ValidationConfig,
+}
+impl Config {
+    /// Reads the vivec.toml in [dir] or the closest of its parents. If there is none, the
+    /// default config is used
+    pub fn discover(dir: &Path) -> Result<Config, ConfigError> {
+        for dir in dir.ancestors() {
+            let path = dir.join(CONFIG_NAME);
+            if path.is_file() {
+                return Config::read(&path);
+            }
+        }
+        Ok(Config {
+            root: dir.to_path_buf(),
+            ..Config::default()
+        })
+    }
+
+    pub fn read(path: &Path) -> Result<Config, ConfigError> {
+        let text = std::fs::read_to_string(path).map_err(|error| ConfigError::Io {
+            path: path.to_path_buf(),
+            error,
+        })?;
+        let root = path.parent().unwrap_or_else(|| Path::new(""));
+        Config::parse(&text, root)
+    }
+
+    /// Parses the text of a vivec.toml which is in [root]
+    pub fn parse(text: &str, root: &Path) -> Result<Config, ConfigError> {
+        let mut config = Config {
+            root: root.to_path_buf(),
+            ..Config::default()
+        };
+        for (key, value) in parse_toml(text)? {
+            let invalid = |expected: &str| ConfigError::Invalid {
+                key: key.clone(),
+                expected: expected.to_string(),
+            };
+            let path = |value: &Value| match value {
+                Value::String(path) => Ok(root.join(path)),
+                _ => Err(invalid("a path")),
+            };
+            let flag = |value: &Value| match value {
+                Value::Boolean(value) => Ok(*value),
+                _ => Err(invalid("true or false")),
+            };
+            let amount = |value: &Value| match value {
+                Value::Integer(x) if *x >= 0 => Ok(*x as usize),
+                _ => Err(invalid("an amount")),
+            };
+            let validation = &mut config.validation;
+            match key.as_str() {
+                "data_folder" => config.data_folder = Some(path(&value)?),
+                "language" => match &value {
+                    Value::String(x) if !x.is_empty() => {
+                        config.language = Some(x.to_ascii_lowercase())
+                    }
+                    _ => return Err(invalid("a language")),
+                },
+                "allow_master_edits" => config.allow_master_edits = flag(&value)?,
+                "encoding" => {
+                    config.encoding = match &value {
+                        Value::String(x) => TextEncoding::from_label(x),
+                        _ => None,
+                    }
+                    .ok_or_else(|| invalid("an encoding"))?
+                }
+                "load_order" => config.load_order = Some(path(&value)?),
+                "output_dir" => config.output_dir = Some(path(&value)?),
+                "game" => {
+                    config.game = Some(match &value {
+                        Value::String(x) if x.eq_ignore_ascii_case("le") => {
+                            GameFormat::LegendaryEdition
+                        }
+                        Value::String(x) if x.eq_ignore_ascii_case("se") => {
+                            GameFormat::SpecialEdition
+                        }
+                        _ => return Err(invalid("\"le\" or \"se\"")),
+                    })
+                }
+                "validation.require_editor_id" => validation.require_editor_id = flag(&value)?,
+                "validation.skip_malformed" => validation.skip_malformed = flag(&value)?,
+                "validation.watch" => validation.watch = flag(&value)?,
+                "validation.duplicate_fields" => {
+                    validation.duplicate_fields = match &value {
+                        Value::String(x) if x == "error" => DuplicateFieldPolicy::Error,
+                        Value::String(x) if x == "keep-first" => DuplicateFieldPolicy::KeepFirst,
+                        _ => return Err(invalid("\"error\" or \"keep-first\"")),
+                    }
+                }
+                "validation.budget_warn_at" => {
+                    validation.budget_warn_at = Some(match value {
+                        Value::Float(x) => x,
+                        Value::Integer(x) => x as f64,
+                        _ => return Err(invalid("a number")),
+                    })
+                }
+                "validation.budget_light_records" => {
+                    validation.budget_light_records = Some(amount(&value)?)
+                }
+                "validation.budget_onam_entries" => {
+                    validation.budget_onam_entries = Some(amount(&value)?)
+                }
+                _ => return Err(ConfigError::UnknownKey { key }),
+            }
+        }
+        Ok(config)
+    }
+
+    /// The language of the string tables to use, [DEFAULT_LANGUAGE] if none was chosen
+    pub fn language(&self) -> &str {
+        self.language.as_deref().unwrap_or(DEFAULT_LANGUAGE)
+    }
+
+    pub fn edit_policy(&self) -> MasterEditPolicy {
+        if self.allow_master_edits {
+            MasterEditPolicy::Allow
+        } else {
+            MasterEditPolicy::Refuse
+        }
+    }
+
+    /// [Plugin::localization] in [Config::language], with the string tables read from the data
+    /// folder, or the config's directory if there is none
+    pub fn localization(&self, plugin: &Plugin<'_>) -> Result<Localization, StringsError> {
+        let data_folder = self.data_folder.as_deref().unwrap_or(&self.root);
+        plugin.localization(data_folder, self.language())
+    }
+
+    /// [ParseOptions] following the `[validation]` table
+    pub fn parse_options(&self) -> ParseOptions {
+        ParseOptions {
+            duplicate_fields: self.validation.duplicate_fields,
+            require_editor_id: self.validation.require_editor_id,
+            skip_malformed: self.validation.skip_malformed,
+            encoding: self.encoding,
+            ..ParseOptions::default()
+        }
+    }
+
+    pub fn write_options(&self) -> WriteOptions {
+        WriteOptions {
+            encoding: self.encoding,
+            ..WriteOptions::default()
+        }
+    }
+
+    /// Where the plugin given as [arg] is. A plugin that doesn't exist relative to the current
+    /// directory is looked for in the data folder
+    pub fn plugin_path(&self, arg: &str) -> PathBuf {
+        let path = PathBuf::from(arg);
+        match &self.data_folder {
+            Some(data_folder) if path.is_relative() && !path.exists() => data_folder.join(path),
+            _ => path,
+        }
+    }
+
+    /// Paths of the plugins given as [args]. If there are none, the plugins that
+    /// [Config::load_order] makes active in [Config::data_folder] are used instead
+    pub fn plugin_paths<S: AsRef<str>>(
+        &self,
+        args: &[S],
+    ) -> Result<Vec<PathBuf>, OpenError<'static>> {
+        match (&self.data_folder, &self.load_order) {
+            (Some(data_folder), Some(load_order)) if args.is_empty() => {
+                let game = self.game.unwrap_or(GameFormat::SpecialEdition);
+                let folder = DataFolder::new(data_folder);
+                let entries = read_plugin_list(load_order, game)?;
+                Ok(folder
+                    .active_plugins(game, &entries)?
+                    .into_iter()
+                    .map(|name| data_folder.join(name))
+                    .collect())
+            }
+            _ => Ok(args.iter().map(|x| self.plugin_path(x.as_ref())).collect()),
+        }
+    }
+
+    /// Where to write the output given as [arg], which is within the output directory if it is
+    /// relative
+    pub fn output_path(&self, arg: &str) -> PathBuf {
+        let path = PathBuf::from(arg);
+        match &self.output_dir {
+            Some(output_dir) if path.is_relative() => output_dir.join(path),
+            _ => path,
+        }
+    }
+
+    /// Warns about records of [plugin] whose form version is newer than [Config::game] reads
+    pub fn check_game(&self, plugin: &Plugin<'_>, ctx: &mut ParseContext) {
+        let game = match self.game {
+            Some(game) => game,
+            None => return,
+        };
+        let newer: usize = plugin
+            .form_versions()
+            .iter()
+            .filter(|(version, _)| version.value() > game.form_version())
+            .map(|(_, count)| count)
+            .sum();
+        if newer != 0 {
+            ctx.warn(format!(
+                "{} has {} records newer than {}, which the game can't read",
+                plugin.name,
+                newer,
+                FormVersion::from_u16(game.form_version())
+            ));
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    /// The file isn't valid TOML
+    Syntax(toml::de::Error),
+    UnknownKey {
+        key: String,
+    },
+    /// The key has a value of the wrong type
+    Invalid {
+        key: String,
+        expected: String,
+    },
+}
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::Io { path, error } => {
+                write!(f, "Failed to read {}: {}", path.display(), error)
+            }
+            ConfigError::Syntax(error) => write!(f, "{}: {}", CONFIG_NAME, error),
+            ConfigError::UnknownKey { key } => {
+                write!(f, "{}: Unknown key {}", CONFIG_NAME, key)
+            }
+            ConfigError::Invalid { key, expected } => {
+                write!(f, "{}: Expected {} for {}", CONFIG_NAME, expected, key)
+            }
+        }
+    }
+}
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConfigError::Io { error, .. } => Some(error),
+            ConfigError::Syntax(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// Each key of [text], prefixed by the table that it is in, such as `validation.watch`
+fn parse_toml(text: &str) -> Result<Vec<(String, Value)>, ConfigError> {
+    let table = match text.parse().map_err(ConfigError::Syntax)? {
+        Value::Table(table) => table,
+        _ => unreachable!("A TOML document is a table"),
+    };
+    let mut entries = Vec::new();
+    for (key, value) in table {
+        match value {
+            Value::Table(table) => {
+                entries.extend(
+                    table
+                        .into_iter()
+                        .map(|(name, value)| (format!("{}.{}", key, name), value)),
+                );
+            }
+            value => entries.push((key, value)),
+        }
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config() {
+        let text = r#"
+# Shared settings
+game = "se"
+language = "French"
+data_folder = 'C:\Games\Skyrim\Data'
+output_dir = "build" # Relative to this file
+allow_master_edits = true
+encoding = "windows-1251"
+
+[validation]
+require_editor_id = true
+duplicate_fields = "keep-first"
+budget_warn_at = 0.8
+budget_light_records = 4096
+"#;
+        let root = Path::new("project");
+        let config = Config::parse(text, root).unwrap();
+        assert_eq!(config.game, Some(GameFormat::SpecialEdition));
+        assert_eq!(config.language(), "french");
+        assert_eq!(config.data_folder, Some(root.join(r"C:\Games\Skyrim\Data")));
+        assert_eq!(config.load_order, None);
+        assert_eq!(config.edit_policy(), MasterEditPolicy::Allow);
+        assert_eq!(
+            config.output_path("Patch.esp"),
+            root.join("build/Patch.esp")
+        );
+        assert_eq!(config.validation.budget_warn_at, Some(0.8));
+        assert_eq!(config.validation.budget_light_records, Some(4096));
+        assert_eq!(config.validation.budget_onam_entries, None);
+        assert!(!config.validation.watch);
+        let options = config.parse_options();
+        assert!(options.require_editor_id);
+        assert_eq!(options.duplicate_fields, DuplicateFieldPolicy::KeepFirst);
+        assert!(!options.skip_malformed);
+        assert_eq!(options.encoding.to_string(), "windows-1251");
+        assert_eq!(config.write_options().encoding, options.encoding);
+        assert_eq!(options.decode(b"\xC4\xEE\xEC"), "Дом");
+
+        let config = Config::parse("", root).unwrap();
+        assert_eq!(config.parse_options(), ParseOptions::default());
+        assert_eq!(config.language(), DEFAULT_LANGUAGE);
+        assert_eq!(config.edit_policy(), MasterEditPolicy::Refuse);
+        assert_eq!(config.output_path("Patch.esp"), PathBuf::from("Patch.esp"));
+
+        let error = |text: &str| Config::parse(text, root).unwrap_err().to_string();
+        assert_eq!(
+            error("game = \"fo4\""),
+            "vivec.toml: Expected \"le\" or \"se\" for game"
+        );
+        assert_eq!(
+            error("\n[validation]\nwatch = 1"),
+            "vivec.toml: Expected true or false for validation.watch"
+        );
+        assert_eq!(
+            error("encoding = \"utf-16\""),
+            "vivec.toml: Expected an encoding for encoding"
+        );
+        assert_eq!(
+            error("data_dir = \"Data\""),
+            "vivec.toml: Unknown key data_dir"
+        );
+        assert_eq!(
+            error("[paths]\ndata = \"Data\""),
+            "vivec.toml: Unknown key paths.data"
+        );
+        assert!(matches!(
+            Config::parse("game = \"se", root),
+            Err(ConfigError::Syntax(_))
+        ));
+        assert_eq!(
+            error("game = \"se\"\ngame = \"le\""),
+            "vivec.toml: duplicate key: `game` at line 1 column 1"
+        );
+    }
+
+    #[test]
+    fn test_check_game() {
+        use crate::records::{
+            common::{CommonRecordInfo, GeneralRecord},
+            Record,
+        };
+        use crate::Top;
+        use bstr::ByteSlice;
+
+        let mut common = CommonRecordInfo::test_default();
+        common.version = FORM_VERSION_SE;
+        let plugin = Plugin::from_tops(
+            "Test.esp".to_string(),
+            vec![Top::Record(Record::Unknown(GeneralRecord {
+                type_name: b"MISC".as_bstr(),
+                common,
+                fields: Vec::new(),
+            }))],
+        );
+        let mut ctx = ParseContext::default();
+        let mut config = Config::parse("game = \"se\"", Path::new("")).unwrap();
+        config.check_game(&plugin, &mut ctx);
+        assert!(ctx.diagnostics.is_empty());
+        config.game = Some(GameFormat::LegendaryEdition);
+        config.check_game(&plugin, &mut ctx);
+        assert_eq!(ctx.diagnostics.len(), 1);
+    }
+}
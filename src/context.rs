@@ -0,0 +1,472 @@
+use crate::groups::common::GroupEntry;
+use crate::records::{
+    common::{record_flag, FormId, GeneralRecord, Recompression, TextEncoding, TypeNamed},
+    fields::common::GeneralField,
+    RecordMut,
+};
+use crate::util::WriteError;
+use crate::walk::is_deleted;
+use bstr::{BString, ByteSlice};
+use std::{
+    fmt,
+    sync::{Arc, Mutex, PoisonError},
+};
+
+/// Form version used by Skyrim (Legendary Edition)
+pub const FORM_VERSION_LE: u16 = 43;
+/// Form version used by Skyrim Special Edition
+pub const FORM_VERSION_SE: u16 = 44;
+
+/// Typed form version, from the header of a record
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum FormVersion {
+    /// Older than [FORM_VERSION_LE]. Records that were last saved by older versions of the
+    /// Creation Kit have these, and the vanilla masters still contain many of them.
+    Old(u16),
+    /// [FORM_VERSION_LE]
+    LegendaryEdition,
+    /// [FORM_VERSION_SE]
+    SpecialEdition,
+    /// Newer than any known version
+    Unknown(u16),
+}
+impl FormVersion {
+    pub fn from_u16(version: u16) -> FormVersion {
+        match version {
+            FORM_VERSION_LE => FormVersion::LegendaryEdition,
+            FORM_VERSION_SE => FormVersion::SpecialEdition,
+            x if x < FORM_VERSION_LE => FormVersion::Old(x),
+            x => FormVersion::Unknown(x),
+        }
+    }
+
+    pub fn value(self) -> u16 {
+        match self {
+            FormVersion::LegendaryEdition => FORM_VERSION_LE,
+            FormVersion::SpecialEdition => FORM_VERSION_SE,
+            FormVersion::Old(x) | FormVersion::Unknown(x) => x,
+        }
+    }
+}
+impl fmt::Display for FormVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.value())
+    }
+}
+
+/// What to do when a field that should only appear once in a record appears again
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum DuplicateFieldPolicy {
+    /// Fail the whole record with [FromRecordError::DuplicateField]
+    Error,
+    /// Keep the first occurrence as the typed field, and store any later occurrences as Unknown
+    /// fields so that they are still written back out.
+    /// Note: keeping the last occurrence instead is not possible, as the raw data of the first
+    /// occurrence is gone once it has been converted.
+    KeepFirst,
+}
+
+/// What to do with a field matched by a [FieldRule]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum FieldPolicy {
+    /// Keep the field. Fields borrow from the plugin's data, so the bytes are never copied, but
+    /// record types that know the field still decode it
+    Keep,
+    /// Keep the field as the raw data it was read as, by leaving the record that holds it
+    /// unconverted. None of the record's fields are decoded, and they are written back out as
+    /// they were
+    Raw,
+    /// Remove the field from the record before it is converted, so it is neither decoded nor
+    /// written back out
+    Drop,
+}
+
+/// Picks out fields, such as WRLD OFST or LAND VHGT, which hold a lot of data that is rarely
+/// needed
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct FieldRule {
+    /// Type of the record the field is in, None for every record type
+    pub record: Option<[u8; 4]>,
+    pub field: [u8; 4],
+    /// Only fields with at least this many bytes of data match
+    pub min_size: usize,
+    pub policy: FieldPolicy,
+}
+impl FieldRule {
+    pub fn new(record: Option<[u8; 4]>, field: [u8; 4], policy: FieldPolicy) -> FieldRule {
+        FieldRule {
+            record,
+            field,
+            min_size: 0,
+            policy,
+        }
+    }
+
+    pub fn matches(&self, record_type: &[u8], field: &GeneralField<'_>) -> bool {
+        self.record.map_or(true, |x| x == record_type)
+            && field.type_name == &self.field[..]
+            && field.data.len() >= self.min_size
+    }
+}
+
+/// Knobs that control how strict parsing is
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ParseOptions {
+    pub duplicate_fields: DuplicateFieldPolicy,
+    /// Fail records that are missing an EDID, even though the vanilla masters have records
+    /// without one
+    pub require_editor_id: bool,
+    /// Skip over records and groups that fail to parse, going by the size in their header, and
+    /// keep those that fail to convert unconverted, rather than failing the whole file.
+    /// Each one gets an error diagnostic.
+    pub skip_malformed: bool,
+    /// What to do with particular fields. The first rule that matches a field applies, and
+    /// fields that match no rule are kept
+    pub field_rules: Vec<FieldRule>,
+    /// How the text of string fields is read, such as by [ParseOptions::decode]
+    pub encoding: TextEncoding,
+}
+impl ParseOptions {
+    /// Accepts the sloppy output of some tools, emitting diagnostics rather than failing
+    pub fn lenient() -> ParseOptions {
+        ParseOptions {
+            duplicate_fields: DuplicateFieldPolicy::KeepFirst,
+            require_editor_id: false,
+            skip_malformed: true,
+            field_rules: Vec::new(),
+            encoding: TextEncoding::default(),
+        }
+    }
+
+    /// The text of a string field's [data], in [ParseOptions::encoding]
+    pub fn decode(&self, data: &[u8]) -> String {
+        self.encoding.decode_lossy(data)
+    }
+
+    /// The policy for [field] of a record of type [record_type]
+    pub fn field_policy(&self, record_type: &[u8], field: &GeneralField<'_>) -> FieldPolicy {
+        self.field_rules
+            .iter()
+            .find(|x| x.matches(record_type, field))
+            .map_or(FieldPolicy::Keep, |x| x.policy)
+    }
+}
+impl Default for ParseOptions {
+    fn default() -> ParseOptions {
+        ParseOptions {
+            duplicate_fields: DuplicateFieldPolicy::Error,
+            require_editor_id: false,
+            skip_malformed: false,
+            field_rules: Vec::new(),
+            encoding: TextEncoding::default(),
+        }
+    }
+}
+
+/// Knobs that control how values are written
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct WriteOptions {
+    /// How text is turned into the data of string fields, such as by [WriteOptions::encode]
+    pub encoding: TextEncoding,
+    /// How records that were read compressed are written, see
+    /// [crate::compressed::DecompressedPlugin::write]
+    pub recompression: Recompression,
+    /// Whether the records within each group are written in form id order, as the Creation Kit
+    /// writes them, see [crate::plugin::Plugin::sort_records]
+    pub sort_records: bool,
+}
+impl WriteOptions {
+    /// The data of a string field holding [text], in [WriteOptions::encoding]
+    pub fn encode(&self, text: &str) -> Result<BString, WriteError> {
+        self.encoding.encode(text)
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Info => write!(f, "info"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// A problem that was noticed while parsing, but which did not stop parsing
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Type name and form id of the record being converted, if any
+    pub record: Option<(String, FormId)>,
+    pub message: String,
+}
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.record {
+            Some((type_name, id)) => write!(
+                f,
+                "{}: {} [{:08X}]: {}",
+                self.severity, type_name, id.id, self.message
+            ),
+            None => write!(f, "{}: {}", self.severity, self.message),
+        }
+    }
+}
+
+/// What a hook decides to do with the record it was given
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum HookAction {
+    Keep,
+    /// Leave the record out of the parsed plugin. Later hooks don't see it
+    Veto,
+}
+
+type Hook = dyn for<'a, 'data> FnMut(RecordMut<'a, 'data>) -> HookAction + Send;
+type SharedHook = Arc<Mutex<Hook>>;
+
+/// Callbacks run on each record as soon as it is converted into its typed form, so that
+/// applications can inspect, amend or drop records during the parse rather than going over the
+/// plugin again afterwards. Records that are left unconverted, such as those within cells, are
+/// given as [RecordMut::Unknown]. Clones share the same callbacks
+#[derive(Clone, Default)]
+pub struct RecordHooks {
+    /// Run in the order they were added, on records of the given type or on every record
+    hooks: Vec<(Option<[u8; 4]>, SharedHook)>,
+}
+impl RecordHooks {
+    /// Adds [hook] for records of type [record], or every record if that is None
+    pub fn add<F>(&mut self, record: Option<[u8; 4]>, hook: F)
+    where
+        F: for<'a, 'data> FnMut(RecordMut<'a, 'data>) -> HookAction + Send + 'static,
+    {
+        self.hooks.push((record, Arc::new(Mutex::new(hook))));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hooks.is_empty()
+    }
+
+    /// Runs the hooks that apply to [record]. Returns false if one of them vetoed it
+    pub fn run<'data, T>(&self, record: &mut T) -> bool
+    where
+        for<'a> &'a mut T: Into<RecordMut<'a, 'data>>,
+    {
+        let type_name = Into::<RecordMut>::into(&mut *record).type_name();
+        for (filter, hook) in self.hooks.iter() {
+            if filter.map_or(false, |x| x[..] != type_name[..]) {
+                continue;
+            }
+            // Hooks are only locked while they run, so a poisoned one panicked in an earlier parse
+            let mut hook = hook.lock().unwrap_or_else(PoisonError::into_inner);
+            if (*hook)((&mut *record).into()) == HookAction::Veto {
+                return false;
+            }
+        }
+        true
+    }
+}
+impl fmt::Debug for RecordHooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(
+                self.hooks
+                    .iter()
+                    .map(|(filter, _)| filter.as_ref().map(|x| x.as_bstr())),
+            )
+            .finish()
+    }
+}
+
+/// State threaded through conversion of records
+#[derive(Debug, Clone, Default)]
+pub struct ParseContext {
+    pub options: ParseOptions,
+    pub diagnostics: Vec<Diagnostic>,
+    pub hooks: RecordHooks,
+    /// The record currently being converted, which diagnostics are attributed to
+    record: Option<(String, FormId)>,
+    /// Form version (the version in the header) of the record currently being converted.
+    /// Fields whose layout changed between versions use this to decide how to parse.
+    pub form_version: Option<u16>,
+    /// Whether the plugin being parsed is localized, so that lstrings are indices into the
+    /// string tables rather than inline strings. Set by the TES4 record
+    pub localized: bool,
+}
+impl ParseContext {
+    pub fn new(options: ParseOptions) -> ParseContext {
+        ParseContext {
+            options,
+            diagnostics: Vec::new(),
+            hooks: RecordHooks::default(),
+            record: None,
+            form_version: None,
+            localized: false,
+        }
+    }
+
+    /// Should be called before converting [record], so that diagnostics can refer to it
+    pub fn enter_record(&mut self, record: &GeneralRecord<'_>) {
+        self.record = Some((record.type_name.to_string(), FormId::new(record.common.id)));
+        self.form_version = Some(record.common.version);
+        if record.type_name == "TES4" {
+            self.localized = record.common.flags.is(record_flag::LOCALIZED);
+        }
+        if let FormVersion::Unknown(version) = record.common.form_version() {
+            self.warn(format!("Unknown form version {}", version));
+        }
+    }
+
+    /// Applies [ParseOptions::field_rules] to [record], before it is converted. Returns whether
+    /// it has a [FieldPolicy::Raw] field, and so should be left unconverted
+    pub fn apply_field_rules(&self, record: &mut GeneralRecord<'_>) -> bool {
+        if self.options.field_rules.is_empty() {
+            return false;
+        }
+        let options = &self.options;
+        let type_name = record.type_name;
+        let mut raw = false;
+        record
+            .fields
+            .retain(|field| match options.field_policy(type_name, field) {
+                FieldPolicy::Keep => true,
+                FieldPolicy::Raw => {
+                    raw = true;
+                    true
+                }
+                FieldPolicy::Drop => false,
+            });
+        raw
+    }
+
+    /// Applies [ParseContext::apply_field_rules] and runs [ParseContext::hooks] on each of the
+    /// records within [entries], and within the groups among them, which are left unconverted.
+    /// Records that a hook vetoes are removed
+    pub fn process_unconverted(&self, entries: &mut Vec<GroupEntry<'_>>) {
+        if self.options.field_rules.is_empty() && self.hooks.is_empty() {
+            return;
+        }
+        entries.retain_mut(|entry| match entry {
+            // Deleted records are kept as they are, like in typed groups
+            GroupEntry::Record(record) if is_deleted(record) => true,
+            GroupEntry::Record(record) => {
+                self.apply_field_rules(record);
+                self.hooks.run(record)
+            }
+            GroupEntry::Group(group) => {
+                self.process_unconverted(&mut group.entries);
+                true
+            }
+            // Its fields can't be read without decompressing it
+            GroupEntry::Compressed(_) => true,
+        });
+    }
+
+    /// Should be called after the records are converted, so that later diagnostics are not
+    /// attributed to the last one
+    pub fn leave_record(&mut self) {
+        self.record = None;
+        self.form_version = None;
+    }
+
+    /// Whether the current record uses the Special Edition layout, None if that is not known
+    pub fn is_special_edition(&self) -> Option<bool> {
+        self.form_version.map(|version| version >= FORM_VERSION_SE)
+    }
+
+    pub fn diagnose<S: Into<String>>(&mut self, severity: Severity, message: S) {
+        self.diagnostics.push(Diagnostic {
+            severity,
+            record: self.record.clone(),
+            message: message.into(),
+        });
+    }
+
+    pub fn warn<S: Into<String>>(&mut self, message: S) {
+        self.diagnose(Severity::Warning, message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        groups::common::{convert_all_records_into, GeneralGroup, GroupType},
+        records::{aact::AACTRecord, common::CommonRecordInfo, Record},
+        stream::{parse_record_stream_with, write_record_stream},
+    };
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn test_record_hooks() {
+        let record = |type_name: &'static [u8], id| {
+            let mut common = CommonRecordInfo::test_default();
+            common.flags.flags = 0;
+            common.id = id;
+            GeneralRecord {
+                type_name: type_name.as_bstr(),
+                common,
+                fields: vec![GeneralField::new(b"EDID".as_bstr(), b"Name\0")],
+            }
+        };
+        let records = vec![record(b"AACT", 1), record(b"AACT", 2), record(b"FLST", 3)];
+        let mut data = Vec::new();
+        write_record_stream(&records, &mut data).unwrap();
+
+        let seen = Arc::new(AtomicUsize::new(0));
+        let mut ctx = ParseContext::default();
+        let counter = seen.clone();
+        ctx.hooks.add(None, move |_| {
+            counter.fetch_add(1, Ordering::Relaxed);
+            HookAction::Keep
+        });
+        ctx.hooks.add(Some(*b"AACT"), |record| {
+            if record.formid() == FormId::new(2) {
+                HookAction::Veto
+            } else {
+                HookAction::Keep
+            }
+        });
+        ctx.hooks.add(Some(*b"FLST"), |record| {
+            if let RecordMut::FLST(flst) = record {
+                flst.add_entry(FormId::new(0x14));
+            }
+            HookAction::Keep
+        });
+
+        let parsed = parse_record_stream_with(&data, &mut ctx).unwrap();
+        assert_eq!(seen.load(Ordering::Relaxed), 3);
+        assert_eq!(parsed.len(), 2);
+        match &parsed[1] {
+            Record::FLST(flst) => assert!(flst.contains(FormId::new(0x14))),
+            x => panic!("Expected FLST, got {:?}", x),
+        }
+
+        // Groups leave out vetoed records too
+        let converted: Vec<AACTRecord> =
+            convert_all_records_into(records[..2].to_vec(), &mut ctx).unwrap();
+        assert_eq!(converted.len(), 1);
+        assert_eq!(seen.load(Ordering::Relaxed), 5);
+
+        // As do groups that aren't converted, such as the children of a cell
+        let mut entries = vec![GroupEntry::Group(GeneralGroup::new(
+            GroupType::CellTemporaryChildren(FormId::new(0x10)),
+            records.iter().cloned().map(GroupEntry::Record).collect(),
+        ))];
+        ctx.process_unconverted(&mut entries);
+        assert_eq!(seen.load(Ordering::Relaxed), 8);
+        match &entries[..] {
+            [GroupEntry::Group(group)] => assert_eq!(group.entries.len(), 2),
+            x => panic!("Unexpected entries {:?}", x),
+        }
+        assert_eq!(
+            format!("{:?}", ctx.hooks),
+            r#"[None, Some("AACT"), Some("FLST")]"#
+        );
+    }
+}
@@ -0,0 +1,828 @@
+// Copying records between plugins. A record is copied into another plugin as an override: it
+// keeps the form id it has in the load order, but the form ids within it are written relative to
+// the masters of the plugin it was read from. So each of them is taken through the plugin that it
+// belongs to, adding that plugin as a master of the destination if it isn't one, and given the mod
+// index that the destination knows it by. Records are placed in the top group of their type, which
+// is made if the destination doesn't have one yet. Records that are held within the children of a
+// CELL, WRLD or DIAL aren't copied, as the record above them may not be in the destination. They
+// can be placed under a parent with Plugin::insert_child, which makes the groups between them.
+// A record can also be duplicated within a plugin as a new record, taking the next object id that
+// the TES4 record's HEDR hands out.
+
+use crate::{
+    edit::EditError,
+    groups::{
+        cell::{is_persistent, Cell, InteriorCells},
+        common::{ChildRecord, CommonGroupInfo, GeneralGroup, GroupStructureError, GroupType},
+        ConflictPolicy, Group,
+    },
+    plugin::Plugin,
+    progress::{Partial, Progress, ProgressState},
+    records::{
+        common::{
+            FormId, FormIdVisitor, GlobalFormId, TypeNamed, FIRST_OBJECT_INDEX, LAST_OBJECT_INDEX,
+        },
+        Record,
+    },
+    Top,
+};
+use bstr::BString;
+use std::collections::HashSet;
+
+/// Record types that are held within the children of another record, rather than a top group
+const CHILD_TYPES: &[&[u8; 4]] = &[
+    b"CELL", b"ACHR", b"REFR", b"PGRE", b"PHZD", b"PMIS", b"PARW", b"PBAR", b"PBEA", b"PCON",
+    b"PFLA", b"NAVM", b"LAND", b"INFO",
+];
+
+/// Fails for records that [Plugin::insert_record] can't place
+fn check_top_level(record: &Record<'_>) -> Result<(), EditError> {
+    let type_name = record.type_name();
+    if matches!(record, Record::TES4(_)) || CHILD_TYPES.iter().any(|x| type_name == &x[..]) {
+        return Err(EditError::NotTopLevel(type_name.to_string()));
+    }
+    Ok(())
+}
+
+impl<'data> Plugin<'data> {
+    /// Adds [record] to the end of the top group of its type, making the group at the end of the
+    /// plugin if there isn't one. Nothing else is changed, so the form ids of the record should
+    /// already be relative to this plugin's masters, and [Plugin::refresh_header] called
+    /// afterwards. Fails for the TES4 record and for records that aren't held by top groups
+    pub fn insert_record(&mut self, record: Record<'data>) -> Result<(), EditError> {
+        check_top_level(&record)?;
+        let mut record = record;
+        // Only the top group of the record's type could take it
+        let label = GroupType::Top(record.type_name());
+        let groups: Vec<usize> = (0..self.tops().len())
+            .filter(
+                |index| matches!(&self.tops()[*index], Top::Group(x) if x.group_type() == label),
+            )
+            .collect();
+        for index in groups {
+            if let Some(Top::Group(group)) = self.top_mut(index) {
+                match group.push_record(record) {
+                    Some(back) => record = back,
+                    None => return Ok(()),
+                }
+            }
+        }
+        let type_name = record.type_name().to_string();
+        let group = Group::from_record(record).ok_or(EditError::NotTopLevel(type_name))?;
+        self.push_top(Top::Group(group));
+        Ok(())
+    }
+
+    /// Merges the records of [other] into this plugin. Each top group is merged into the group of
+    /// the same type and label, as [Group::merge] does, or added to the end if there is none.
+    /// The TES4 record of [other] is left out, and its form ids are taken as they are, so it
+    /// should list the same masters in the same order. As with [Plugin::insert_record],
+    /// [Plugin::refresh_header] should be called afterwards
+    pub fn merge(&mut self, other: Plugin<'data>, policy: ConflictPolicy) -> Result<(), EditError> {
+        self.merge_with_progress(other, policy, &mut ()).map(|_| ())
+    }
+
+    /// [Plugin::merge], reporting to [progress] after each top group of [other]. If it is
+    /// cancelled, the groups before that have been merged and the rest are left out
+    pub fn merge_with_progress(
+        &mut self,
+        other: Plugin<'data>,
+        policy: ConflictPolicy,
+        progress: &mut dyn Progress,
+    ) -> Result<Partial<()>, EditError> {
+        let masters = self.masters();
+        if let Some(master) = other
+            .masters()
+            .iter()
+            .enumerate()
+            .find(|(index, x)| {
+                masters
+                    .get(*index)
+                    .map_or(true, |y| !y.eq_ignore_ascii_case(x))
+            })
+            .map(|(_, x)| x.to_string())
+        {
+            return Err(EditError::UnknownMaster(master));
+        }
+
+        // Without the TES4 record
+        let records_total = other.iter_all_records().count() - other.header().iter().count();
+        let mut merged = 0;
+        for top in other.into_tops() {
+            if progress.is_cancelled() {
+                return Ok(Partial {
+                    value: (),
+                    complete: false,
+                });
+            }
+            let group = match top {
+                Top::Record(Record::TES4(_)) => continue,
+                // Not held by a top group
+                Top::Record(record @ Record::ACHR(_)) => {
+                    self.push_top(Top::Record(record));
+                    merged += 1;
+                    continue;
+                }
+                Top::Record(record) => match Group::from_record(record) {
+                    Some(group) => group,
+                    None => continue,
+                },
+                Top::Group(group) => group,
+            };
+            let label = group.group_type();
+            merged += group.record_count();
+            let existing = self
+                .tops()
+                .iter()
+                .position(|top| matches!(top, Top::Group(x) if x.group_type() == label));
+            match existing.and_then(|index| self.top_mut(index)) {
+                Some(Top::Group(existing)) => existing.merge(group, policy)?,
+                _ => self.push_top(Top::Group(group)),
+            }
+            progress.report(&ProgressState {
+                records_converted: merged.min(records_total),
+                records_total: Some(records_total),
+                group: match label {
+                    GroupType::Top(label) => Some(label),
+                    _ => None,
+                },
+                ..ProgressState::default()
+            });
+        }
+        Ok(Partial {
+            value: (),
+            complete: true,
+        })
+    }
+
+    /// Adds [record] under [parent], making the groups that should hold it if they don't exist:
+    ///  - An interior CELL has no parent, and is placed in the block and sub-block of its id
+    ///  - An exterior CELL is placed in the block and sub-block of its grid within the WRLD
+    ///    [parent], or with the cells outside of the blocks if it has no grid
+    ///  - The references, navmeshes and landscape of a cell are placed in its persistent or
+    ///    temporary children, going by whether they are flagged as persistent. The persistent
+    ///    references of an exterior cell are held by the persistent cell of its worldspace
+    ///  - An INFO is placed after the other responses of the DIAL [parent]
+    ///
+    /// The groups are changed where the plugin holds them, without reading the others. As with
+    /// [Plugin::insert_record], nothing else is changed. Fails if the parent can't be found,
+    /// and for records that are held by top groups
+    pub fn insert_child(
+        &mut self,
+        record: ChildRecord<'data>,
+        parent: Option<FormId>,
+    ) -> Result<(), EditError> {
+        let is_child = CHILD_TYPES.iter().any(|x| record.type_name() == &x[..]);
+        let type_name = record.type_name().to_string();
+        if !is_child {
+            return Err(EditError::NotChild(type_name));
+        }
+        let parent = match (type_name.as_str(), parent) {
+            ("CELL", None) => {
+                if self.interior_cells_mut().is_none() {
+                    self.set_interior_cells(InteriorCells::new());
+                }
+                self.interior_cells_mut()
+                    .expect("The CELL group was just made")
+                    .insert(Cell {
+                        record,
+                        children: None,
+                    });
+                return Ok(());
+            }
+            (_, Some(parent)) => parent,
+            (_, None) => return Err(EditError::NotTopLevel(type_name)),
+        };
+        match type_name.as_str() {
+            "CELL" => {
+                self.make_children(parent, "WRLD", GroupType::WorldChildren(parent))?;
+                self.world_children_mut(parent)
+                    .map_err(EditError::Groups)?
+                    .ok_or(EditError::NotFound(parent))?
+                    .insert(Cell {
+                        record,
+                        children: None,
+                    });
+            }
+            "INFO" => {
+                self.make_children(parent, "DIAL", GroupType::TopicChildren(parent))?;
+                self.topic_children_mut(parent)
+                    .map_err(EditError::Groups)?
+                    .ok_or(EditError::NotFound(parent))?
+                    .push(record);
+            }
+            _ => {
+                let parent = if is_persistent(&record) {
+                    self.persistent_cell_of(parent)?
+                } else {
+                    parent
+                };
+                self.make_children(parent, "CELL", GroupType::CellChildren(parent))?;
+                self.cell_children_mut(parent)
+                    .map_err(EditError::Groups)?
+                    .ok_or(EditError::NotFound(parent))?
+                    .push(record);
+            }
+        }
+        Ok(())
+    }
+
+    /// Makes the group of [group_type] right after the [type_name] record [parent], to hold its
+    /// children, if it has none. A CELL must be held by a block or by the children of a WRLD
+    fn make_children(
+        &mut self,
+        parent: FormId,
+        type_name: &str,
+        group_type: GroupType<'data>,
+    ) -> Result<(), EditError> {
+        let (top, mut path) = self
+            .record_path(parent)
+            .ok_or(EditError::NotFound(parent))?;
+        let index = path.pop().ok_or(EditError::NotFound(parent))?;
+        let entry = self
+            .entries_in(top, &path)
+            .and_then(|entries| entries.get(index));
+        if !entry
+            .and_then(|entry| entry.type_name())
+            .map_or(false, |x| x == type_name)
+        {
+            return Err(EditError::NotFound(parent));
+        }
+        if type_name == "CELL" {
+            let holder = self.group_in(top, &path).map(|group| group.group_type);
+            if !matches!(
+                holder,
+                Some(GroupType::WorldChildren(_))
+                    | Some(GroupType::InteriorSubCellBlock(_))
+                    | Some(GroupType::ExteriorCellSubBlock(_))
+            ) {
+                let error = GroupStructureError::UnexpectedRecord(type_name.to_string());
+                return Err(EditError::Groups(error));
+            }
+        }
+        if self.find_group(&group_type).is_some() {
+            return Ok(());
+        }
+        let group = GeneralGroup {
+            group_type,
+            common: CommonGroupInfo::new(),
+            entries: Vec::new(),
+        };
+        self.entries_in_mut(top, &path)
+            .ok_or(EditError::NotFound(parent))?
+            .insert(index + 1, group.into());
+        Ok(())
+    }
+
+    /// The cell that holds the persistent references of [cell]. That is the persistent cell of
+    /// the worldspace for a cell within its blocks, and [cell] itself otherwise
+    fn persistent_cell_of(&self, cell: FormId) -> Result<FormId, EditError> {
+        let (top, mut path) = self.record_path(cell).ok_or(EditError::NotFound(cell))?;
+        path.pop();
+        let holder = self.group_in(top, &path).map(|group| group.group_type);
+        if !matches!(holder, Some(GroupType::ExteriorCellSubBlock(_))) {
+            return Ok(cell);
+        }
+        let world = (0..path.len()).rev().find_map(|len| {
+            match self.group_in(top, &path[..len])?.group_type {
+                GroupType::WorldChildren(world) => Some(world),
+                _ => None,
+            }
+        });
+        let world = match world {
+            Some(world) => world,
+            None => return Ok(cell),
+        };
+        self.world_children(world)
+            .map_err(EditError::Groups)?
+            .and_then(|children| children.persistent_cell())
+            .map(|cell| cell.formid())
+            .ok_or(EditError::NoPersistentCell(world))
+    }
+
+    /// Copies the record with [id] in [source] into this plugin as an override. The plugins that
+    /// it and the form ids within it belong to are added as masters where needed, and its form
+    /// ids are renumbered to match this plugin's masters. Returns the id of the copy.
+    /// Fails without changing anything if this plugin already has the record, if it isn't held
+    /// by a top group, or if some of its form ids have a mod index past [source]'s own. Records
+    /// that weren't converted are refused, as the form ids within them can't be renumbered
+    pub fn copy_override(
+        &mut self,
+        source: &Plugin<'data>,
+        id: FormId,
+    ) -> Result<FormId, EditError> {
+        let mut record = source.find(id).ok_or(EditError::NotFound(id))?.to_record();
+        check_top_level(&record)?;
+        if matches!(record, Record::Unknown(_) | Record::Deleted(_)) {
+            return Err(EditError::HiddenReferences { records: 1 });
+        }
+        let global = |id: FormId| -> Result<Option<GlobalFormId>, EditError> {
+            if id.id == 0 {
+                Ok(None)
+            } else {
+                source
+                    .global_formid(id)
+                    .map(Some)
+                    .ok_or(EditError::BadModIndex(id))
+            }
+        };
+        let own = global(id)?.ok_or(EditError::NotFound(id))?;
+        if let Some(existing) = self.local_formid(&own) {
+            // Unlike Plugin::find, this also sees compressed records
+            if self.formids_and_groups().0.contains(&existing) {
+                return Err(EditError::Exists(existing));
+            }
+        }
+        let mut refs = Vec::new();
+        for id in record.formids() {
+            refs.push(global(id)?);
+        }
+
+        // Checked up front, so that failing doesn't leave some of the masters added
+        let mut missing = Vec::new();
+        for target in std::iter::once(&own).chain(refs.iter().flatten()) {
+            let plugin = &target.plugin;
+            let known = self.is_named(plugin)
+                || self.master_index(plugin).is_some()
+                || missing
+                    .iter()
+                    .any(|x: &&BString| x.eq_ignore_ascii_case(plugin));
+            if !known {
+                missing.push(plugin);
+            }
+        }
+        if !missing.is_empty() {
+            self.can_add_masters(missing.len())?;
+        }
+        for master in missing {
+            self.add_master(master.clone())?;
+        }
+        // Every plugin is now this one or a master, so each has a local id
+        let local = |target: &GlobalFormId| self.local_formid(target).unwrap();
+        let new_id = local(&own);
+        record.as_record_mut().common_mut().id = new_id.id;
+        let mut refs = refs
+            .iter()
+            .map(|target| target.as_ref().map_or(FormId::new(0), local));
+        record.visit_formids(&mut |id| *id = refs.next().unwrap());
+
+        self.insert_record(record)?;
+        self.refresh_header();
+        Ok(new_id)
+    }
+
+    /// Copies the record with [id] as a new record of this plugin, placed in the top group of its
+    /// type, and returns its id. The id is the next object id of the HEDR, which is moved past it,
+    /// skipping any that are already used. Light plugins only take ids within
+    /// [Plugin::light_range], going back to the free ids before the next object id once it is
+    /// past the range. If [editor_id_suffix] is given, it is appended to the editor id of the
+    /// copy so that the two can be told apart. Records that weren't converted keep their editor
+    /// id, as their fields can't be changed
+    pub fn duplicate_record(
+        &mut self,
+        id: FormId,
+        editor_id_suffix: Option<&str>,
+    ) -> Result<FormId, EditError> {
+        let mut record = self.find(id).ok_or(EditError::NotFound(id))?.to_record();
+        check_top_level(&record)?;
+
+        let own_index = self.masters().len() as u8;
+        let (ids, _) = self.formids_and_groups();
+        let used: HashSet<u32> = ids
+            .iter()
+            .filter(|id| id.mod_index() == own_index)
+            .map(|id| id.object_index())
+            .collect();
+        let next = self
+            .header()
+            .map_or(FIRST_OBJECT_INDEX, |header| header.header().next_object_id);
+        let object_index = if self.is_light() {
+            let range = self.light_range();
+            let next = next.max(*range.start());
+            (next..=*range.end())
+                .chain(*range.start()..next)
+                .find(|index| !used.contains(index))
+                .ok_or(EditError::TooManyLightRecords {
+                    records: used.len() + 1,
+                    max: (range.end() - range.start() + 1) as usize,
+                })?
+        } else {
+            (next.max(FIRST_OBJECT_INDEX)..=LAST_OBJECT_INDEX)
+                .find(|index| !used.contains(index))
+                .ok_or(EditError::NoFreeObjectId)?
+        };
+        let new_id = FormId::from_parts(own_index, object_index);
+        if let Some(header) = self.header_mut() {
+            let hedr = header.header_mut();
+            hedr.next_object_id = hedr.next_object_id.max(object_index + 1);
+        }
+
+        let mut record_mut = record.as_record_mut();
+        record_mut.common_mut().id = new_id.id;
+        if let (Some(suffix), Some(editor_id)) = (editor_id_suffix, record_mut.editor_id_mut()) {
+            editor_id
+                .value
+                .to_mut()
+                .extend_from_slice(suffix.as_bytes());
+        }
+        self.insert_record(record)?;
+        self.refresh_header();
+        Ok(new_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        context::ParseContext,
+        error::{ErrorCode, VivecError},
+        fixtures::{self, general, list},
+        groups::{cell::ReferenceGroup, common::GroupEntry},
+        records::{
+            common::{record_flag, CompressedRecord, GeneralRecord, LAST_LIGHT_OBJECT_INDEX},
+            fields::common::GeneralField,
+        },
+        util::{Position, Writable},
+    };
+    use bstr::{BString, ByteSlice};
+
+    #[test]
+    fn test_copy_override() {
+        let mut source = Plugin::new("Patch.esp");
+        for master in ["Skyrim.esm", "Update.esm"] {
+            source
+                .header_mut()
+                .unwrap()
+                .push_master(BString::from(master));
+        }
+        // Refers to a record of each master, of the plugin itself, and to nothing
+        source.push_top(list(
+            0x0100_0800,
+            &[&[1, 0, 0, 0], &[0, 9, 0, 2], &[0, 0, 0, 0], &[0, 8, 0, 1]],
+        ));
+        source.push_top(list(0x0200_0900, &[]));
+        source.push_top(list(0x0300_0900, &[]));
+
+        let mut dest = Plugin::new("Other.esp");
+        dest.header_mut()
+            .unwrap()
+            .push_master(BString::from("Update.esm"));
+        dest.push_top(list(0x0100_0800, &[]));
+        let id = dest
+            .copy_override(&source, FormId::new(0x0100_0800))
+            .unwrap();
+        assert_eq!(id, FormId::new(0x0000_0800));
+        assert_eq!(
+            dest.masters(),
+            vec![
+                b"Update.esm".as_bstr(),
+                b"Skyrim.esm".as_bstr(),
+                b"Patch.esp".as_bstr()
+            ]
+        );
+        // The record that was already there moved past the new masters
+        assert!(dest.find(FormId::new(0x0300_0800)).is_some());
+        match &dest.tops()[2] {
+            Top::Group(Group::FLST(group)) => {
+                let ids: Vec<(u32, Vec<u32>)> = group
+                    .records
+                    .iter()
+                    .map(|record| (record.common.id, record.entries().map(|x| x.id).collect()))
+                    .collect();
+                assert_eq!(
+                    ids,
+                    vec![(0x0000_0800, vec![0x0100_0001, 0x0200_0900, 0, 0x0000_0800])]
+                );
+            }
+            top => panic!("Expected an FLST group, got {:?}", top),
+        }
+        // Both records and the new group
+        assert_eq!(dest.header().unwrap().header().record_count, 3);
+
+        assert_eq!(
+            dest.copy_override(&source, FormId::new(0x0100_0800)),
+            Err(EditError::Exists(FormId::new(0x0000_0800)))
+        );
+        assert_eq!(
+            dest.copy_override(&source, FormId::new(0x0300_0900))
+                .unwrap_err()
+                .code(),
+            ErrorCode::BadModIndex
+        );
+        assert_eq!(
+            dest.copy_override(&source, FormId::new(0x0100_0801)),
+            Err(EditError::NotFound(FormId::new(0x0100_0801)))
+        );
+    }
+
+    #[test]
+    fn test_copy_unconverted() {
+        let mut source = Plugin::new("Patch.esp");
+        source.push_top(top(b"MISC", vec![child(b"MISC", 0x800, 0, Vec::new())]));
+        source.push_top(list(0x801, &[]));
+        let mut dest = Plugin::new("Other.esp");
+        assert_eq!(
+            dest.copy_override(&source, FormId::new(0x800)),
+            Err(EditError::HiddenReferences { records: 1 })
+        );
+        assert_eq!(dest.masters().len(), 0);
+
+        // A compressed override in the destination is still found
+        let compressed = CompressedRecord::compress(&general(b"FLST", 0x801, Vec::new()), 9);
+        let compressed = fixtures::top(b"FLST", vec![compressed.unwrap().into()]);
+        let mut dest = fixtures::plugin("Other.esp", &["Patch.esp"], vec![compressed]);
+        assert_eq!(
+            dest.copy_override(&source, FormId::new(0x801)),
+            Err(EditError::Exists(FormId::new(0x801)))
+        );
+    }
+
+    #[test]
+    fn test_duplicate_record() {
+        let mut plugin = Plugin::new("Patch.esp");
+        plugin
+            .header_mut()
+            .unwrap()
+            .push_master(BString::from("Skyrim.esm"));
+        plugin.push_top(list(0x0100_0800, &[&[0, 9, 0, 0]]));
+        plugin.push_top(list(0x0000_0900, &[]));
+
+        let id = plugin
+            .duplicate_record(FormId::new(0x0100_0800), Some("Copy"))
+            .unwrap();
+        assert_eq!(id, FormId::new(0x0100_0801));
+        let copy = plugin.find(id).unwrap();
+        assert_eq!(copy.editor_id(), Some("ListCopy".to_string()));
+        assert_eq!(copy.formids(), vec![FormId::new(0x900)]);
+        assert!(matches!(
+            plugin.tops().last(),
+            Some(Top::Group(Group::FLST(_)))
+        ));
+
+        // Overrides can be duplicated too, becoming records of this plugin
+        let id = plugin
+            .duplicate_record(FormId::new(0x0000_0900), None)
+            .unwrap();
+        assert_eq!(id, FormId::new(0x0100_0802));
+        assert_eq!(
+            plugin.find(id).unwrap().editor_id(),
+            Some("List".to_string())
+        );
+        assert_eq!(plugin.header().unwrap().header().next_object_id, 0x803);
+        assert_eq!(plugin.tops().len(), 4);
+
+        assert_eq!(
+            plugin.duplicate_record(FormId::new(0x0100_0900), None),
+            Err(EditError::NotFound(FormId::new(0x0100_0900)))
+        );
+        assert_eq!(
+            plugin
+                .duplicate_record(FormId::new(0), None)
+                .unwrap_err()
+                .code(),
+            ErrorCode::NotTopLevel
+        );
+    }
+
+    #[test]
+    fn test_duplicate_record_ids() {
+        // Compressed records hold their ids too
+        let compressed = CompressedRecord::compress(&general(b"FLST", 0x801, Vec::new()), 9);
+        let compressed = fixtures::top(b"FLST", vec![compressed.unwrap().into()]);
+        let mut plugin = fixtures::plugin("Patch.esp", &[], vec![list(0x800, &[]), compressed]);
+        let id = plugin.duplicate_record(FormId::new(0x800), None).unwrap();
+        assert_eq!(id, FormId::new(0x802));
+
+        // Light plugins go back to the free ids once they run out
+        plugin.set_light(true).unwrap();
+        plugin.header_mut().unwrap().header_mut().next_object_id = 0xFFF;
+        let id = plugin.duplicate_record(FormId::new(0x800), None).unwrap();
+        assert_eq!(id, FormId::new(0xFFF));
+        let id = plugin.duplicate_record(FormId::new(0x800), None).unwrap();
+        assert_eq!(id, FormId::new(0x803));
+        assert_eq!(plugin.header().unwrap().header().next_object_id, 0x1000);
+        let mut full = fixtures::plugin("Full.esp", &[], vec![]);
+        full.set_light(true).unwrap();
+        for index in FIRST_OBJECT_INDEX..=LAST_LIGHT_OBJECT_INDEX {
+            full.push_top(list(index, &[]));
+        }
+        assert_eq!(
+            full.duplicate_record(FormId::new(0x800), None),
+            Err(EditError::TooManyLightRecords {
+                records: 0x801,
+                max: 0x800
+            })
+        );
+
+        plugin.set_light(false).unwrap();
+        plugin.header_mut().unwrap().header_mut().next_object_id = LAST_OBJECT_INDEX;
+        let id = plugin.duplicate_record(FormId::new(0x800), None).unwrap();
+        assert_eq!(id, FormId::new(LAST_OBJECT_INDEX));
+        assert_eq!(
+            plugin.duplicate_record(FormId::new(0x800), None),
+            Err(EditError::NoFreeObjectId)
+        );
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut plugin = Plugin::new("Patch.esp");
+        if let Top::Record(record) = list(0x800, &[]) {
+            plugin.insert_record(record).unwrap();
+        }
+        let mut other = Plugin::new("Other.esp");
+        other.push_top(list(0x800, &[&[0, 9, 0, 0]]));
+        other.push_top(list(0x801, &[]));
+        other.push_top(top(b"MISC", vec![child(b"MISC", 0x802, 0, vec![])]));
+
+        let mut reports = Vec::new();
+        let mut progress = |state: &ProgressState<'_>| {
+            reports.push((state.records_converted, state.records_total))
+        };
+        let merged = plugin
+            .merge_with_progress(other.clone(), ConflictPolicy::KeepLast, &mut progress)
+            .unwrap();
+        assert!(merged.complete);
+        assert_eq!(reports, vec![(1, Some(3)), (2, Some(3)), (3, Some(3))]);
+        // The lists were put in the same group, the later one replacing the first
+        assert_eq!(plugin.tops().len(), 3);
+        assert_eq!(
+            plugin.find(FormId::new(0x800)).unwrap().formids(),
+            vec![FormId::new(0x900)]
+        );
+        assert!(plugin.find(FormId::new(0x801)).is_some());
+        assert!(plugin.find(FormId::new(0x802)).is_some());
+
+        let mut plugin = Plugin::new("Patch.esp");
+        assert_eq!(plugin.merge(other.clone(), ConflictPolicy::Error), Ok(()));
+        assert_eq!(
+            plugin.merge(other.clone(), ConflictPolicy::Error),
+            Err(EditError::Exists(FormId::new(0x800)))
+        );
+
+        other
+            .header_mut()
+            .unwrap()
+            .push_master(BString::from("Skyrim.esm"));
+        assert_eq!(
+            plugin.merge(other, ConflictPolicy::KeepLast),
+            Err(EditError::UnknownMaster("Skyrim.esm".to_string()))
+        );
+    }
+
+    fn child<'data>(
+        type_name: &'static [u8],
+        id: u32,
+        flags: u32,
+        fields: Vec<GeneralField<'data>>,
+    ) -> GeneralRecord<'data> {
+        let mut record = general(type_name, id, fields);
+        record.common.flags.flags = flags;
+        record
+    }
+
+    fn top<'data>(label: &'static [u8], records: Vec<GeneralRecord<'data>>) -> Top<'data> {
+        fixtures::top(label, records.into_iter().map(GroupEntry::Record).collect())
+    }
+
+    #[test]
+    fn test_insert_child() {
+        let mut plugin = Plugin::new("Base.esm");
+        plugin.push_top(top(b"WRLD", vec![child(b"WRLD", 0x810, 0, Vec::new())]));
+        plugin.push_top(top(b"DIAL", vec![child(b"DIAL", 0x820, 0, Vec::new())]));
+        let persistent = record_flag::PERSISTENT_REFERENCE;
+
+        // Object 2049 is in block 9, sub-block 4
+        let cell = child(b"CELL", 0x801, 0, Vec::new());
+        plugin.insert_child(cell.into(), None).unwrap();
+        let refr = child(b"REFR", 0x802, persistent, Vec::new());
+        plugin
+            .insert_child(refr.into(), Some(FormId::new(0x801)))
+            .unwrap();
+        let achr = child(b"ACHR", 0x803, 0, Vec::new());
+        plugin
+            .insert_child(achr.into(), Some(FormId::new(0x801)))
+            .unwrap();
+
+        let grid = [3, 0, 0, 0, 0xFE, 0xFF, 0xFF, 0xFF, 0, 0, 0, 0];
+        let xclc = GeneralField::new(b"XCLC".as_bstr(), &grid);
+        let cell = child(b"CELL", 0x811, 0, vec![xclc]);
+        plugin
+            .insert_child(cell.into(), Some(FormId::new(0x810)))
+            .unwrap();
+        let land = child(b"LAND", 0x812, 0, Vec::new());
+        plugin
+            .insert_child(land.into(), Some(FormId::new(0x811)))
+            .unwrap();
+        let info = child(b"INFO", 0x821, 0, Vec::new());
+        plugin
+            .insert_child(info.into(), Some(FormId::new(0x820)))
+            .unwrap();
+
+        // Everything is where it would be read from
+        plugin.refresh_header();
+        let data = plugin.to_vec().unwrap();
+        let plugin = Plugin::parse("Base.esm", &data, &mut ParseContext::default()).unwrap();
+        let cells = plugin.interior_cells().unwrap().unwrap();
+        assert_eq!(cells.blocks.len(), 1);
+        assert_eq!(cells.blocks[0].number, 9);
+        assert_eq!(cells.blocks[0].sub_blocks[0].number, 4);
+        let children = plugin.cell_children(FormId::new(0x801)).unwrap().unwrap();
+        let children = children.to_children();
+        let ids = |group: &Option<ReferenceGroup<'_>>| -> Vec<u32> {
+            group
+                .iter()
+                .flat_map(|x| x.records.iter())
+                .map(|x| x.formid().id)
+                .collect()
+        };
+        assert_eq!(ids(&children.persistent), vec![0x802]);
+        assert_eq!(ids(&children.temporary), vec![0x803]);
+
+        let tree = plugin.worldspaces().unwrap().unwrap();
+        let cell = tree
+            .cell_at(FormId::new(0x810), Position::new(3, -2))
+            .unwrap();
+        assert_eq!(cell.formid(), FormId::new(0x811));
+        assert_eq!(ids(&cell.children.as_ref().unwrap().temporary), vec![0x812]);
+        let topics = plugin.topics().unwrap().unwrap();
+        let topic = topics.topic(FormId::new(0x820)).unwrap();
+        assert_eq!(topic.responses().count(), 1);
+    }
+
+    #[test]
+    fn test_insert_persistent_reference() {
+        let mut plugin = Plugin::new("Base.esm");
+        plugin.push_top(top(b"WRLD", vec![child(b"WRLD", 0x810, 0, Vec::new())]));
+        let persistent = record_flag::PERSISTENT_REFERENCE;
+        let grid = [3, 0, 0, 0, 0xFE, 0xFF, 0xFF, 0xFF, 0, 0, 0, 0];
+        let xclc = GeneralField::new(b"XCLC".as_bstr(), &grid);
+        let world = Some(FormId::new(0x810));
+        let cell = child(b"CELL", 0x811, 0, vec![xclc]);
+        plugin.insert_child(cell.into(), world).unwrap();
+        let refr = || child(b"REFR", 0x812, persistent, Vec::new()).into();
+        assert_eq!(
+            plugin.insert_child(refr(), Some(FormId::new(0x811))),
+            Err(EditError::NoPersistentCell(FormId::new(0x810)))
+        );
+
+        // The persistent cell is the first cell outside of the blocks
+        let cell = child(b"CELL", 0x813, 0, Vec::new());
+        plugin.insert_child(cell.into(), world).unwrap();
+        plugin
+            .insert_child(refr(), Some(FormId::new(0x811)))
+            .unwrap();
+        let temporary = child(b"REFR", 0x814, 0, Vec::new());
+        plugin
+            .insert_child(temporary.into(), Some(FormId::new(0x811)))
+            .unwrap();
+        let children = plugin.world_children(FormId::new(0x810)).unwrap().unwrap();
+        let cell = children.persistent_cell().unwrap();
+        assert_eq!(cell.formid(), FormId::new(0x813));
+        let ids = |cell: FormId| -> Vec<u32> {
+            let children = plugin.cell_children(cell).unwrap().unwrap();
+            children
+                .records()
+                .filter_map(|x| x.formid())
+                .map(|x| x.id)
+                .collect()
+        };
+        assert_eq!(ids(FormId::new(0x813)), vec![0x812]);
+        assert_eq!(ids(FormId::new(0x811)), vec![0x814]);
+        let cell = children.cell_at(Position::new(3, -2)).unwrap();
+        let children = cell.children().unwrap().unwrap();
+        assert!(children.persistent().is_none());
+    }
+
+    #[test]
+    fn test_insert_child_errors() {
+        let mut plugin = Plugin::new("Base.esm");
+        let refr = || child(b"REFR", 0x802, 0, Vec::new()).into();
+        assert_eq!(
+            plugin.insert_child(refr(), Some(FormId::new(0x801))),
+            Err(EditError::NotFound(FormId::new(0x801)))
+        );
+        assert_eq!(
+            plugin.insert_child(refr(), None),
+            Err(EditError::NotTopLevel("REFR".to_string()))
+        );
+        let list = child(b"FLST", 0x803, 0, Vec::new());
+        assert_eq!(
+            plugin.insert_child(list.into(), None),
+            Err(EditError::NotChild("FLST".to_string()))
+        );
+        let cell = child(b"CELL", 0x804, 0, Vec::new());
+        assert_eq!(
+            plugin.insert_child(cell.into(), Some(FormId::new(0x810))),
+            Err(EditError::NotFound(FormId::new(0x810)))
+        );
+        // A group that can't be read is reported rather than replaced
+        plugin.push_top(top(b"CELL", vec![child(b"CELL", 0x801, 0, Vec::new())]));
+        let error = plugin
+            .insert_child(refr(), Some(FormId::new(0x801)))
+            .unwrap_err();
+        assert_eq!(error.code(), ErrorCode::GroupStructure);
+        assert!(error.inner().is_some());
+    }
+}
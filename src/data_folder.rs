@@ -0,0 +1,347 @@
+// Finding the game's Data folder and reading the plugins.txt and loadorder.txt files that say
+// which plugins are active and the order they load in, so that a LoadOrder can be opened with the
+// plugins that the game would load. Looking up the registry or the game's ini is left to the
+// caller, which passes the directories it finds as hints.
+
+use crate::{
+    config::GameFormat,
+    load_order::{OpenError, PluginFile},
+    records::common::record_flag,
+    watch::is_plugin_path,
+};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+/// The plugin that every Data folder has
+pub const GAME_MASTER: &str = "Skyrim.esm";
+
+/// Plugins that [game] loads first, whether or not plugins.txt lists them
+pub fn implicit_plugins(game: GameFormat) -> &'static [&'static str] {
+    match game {
+        GameFormat::LegendaryEdition => &["Skyrim.esm", "Update.esm"],
+        GameFormat::SpecialEdition => &[
+            "Skyrim.esm",
+            "Update.esm",
+            "Dawnguard.esm",
+            "HearthFires.esm",
+            "Dragonborn.esm",
+        ],
+    }
+}
+
+/// A plugin listed in plugins.txt
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct PluginEntry {
+    pub name: String,
+    pub active: bool,
+}
+
+/// Parses a plugins.txt. Special Edition lists every plugin, with a `*` before the active ones,
+/// while Legendary Edition only lists the active ones. Plugins listed more than once keep their
+/// first position
+pub fn parse_plugins_txt(text: &str, game: GameFormat) -> Vec<PluginEntry> {
+    let mut entries: Vec<PluginEntry> = Vec::new();
+    for line in plugin_lines(text) {
+        let (name, active) = match (game, line.strip_prefix('*')) {
+            (GameFormat::SpecialEdition, Some(name)) => (name.trim(), true),
+            (GameFormat::SpecialEdition, None) => (line, false),
+            (GameFormat::LegendaryEdition, _) => (line, true),
+        };
+        if !name.is_empty() && !entries.iter().any(|x| x.name.eq_ignore_ascii_case(name)) {
+            entries.push(PluginEntry {
+                name: name.to_string(),
+                active,
+            });
+        }
+    }
+    entries
+}
+
+/// Parses a loadorder.txt, which lists every plugin in the order they load, whether or not it is
+/// active
+pub fn parse_loadorder_txt(text: &str) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    for name in plugin_lines(text) {
+        if !names.iter().any(|x| x.eq_ignore_ascii_case(name)) {
+            names.push(name.to_string());
+        }
+    }
+    names
+}
+
+/// Lines of a plugin list, without the byte order mark, comments and blank lines
+fn plugin_lines(text: &str) -> impl Iterator<Item = &str> {
+    text.trim_start_matches('\u{feff}')
+        .lines()
+        .map(str::trim)
+        .filter(|x| !x.is_empty() && !x.starts_with('#'))
+}
+
+/// Puts [entries] in the order of [order], which came from a loadorder.txt. Entries that it
+/// doesn't list go after the ones that it does, keeping their order
+pub fn apply_loadorder(entries: &mut [PluginEntry], order: &[String]) {
+    entries.sort_by_key(|entry| {
+        order
+            .iter()
+            .position(|x| x.eq_ignore_ascii_case(&entry.name))
+            .unwrap_or(order.len())
+    });
+}
+
+/// Reads the plugin list at [path], which is either a plugins.txt or a loadorder.txt.
+/// Legendary Edition's plugins.txt isn't in load order, so a loadorder.txt next to it gives the
+/// order. A loadorder.txt takes which plugins are active from a plugins.txt next to it, or else
+/// counts all of them as active
+pub fn read_plugin_list(
+    path: &Path,
+    game: GameFormat,
+) -> Result<Vec<PluginEntry>, OpenError<'static>> {
+    let is_loadorder = path
+        .file_name()
+        .map_or(false, |x| x.eq_ignore_ascii_case("loadorder.txt"));
+    if is_loadorder {
+        let order = parse_loadorder_txt(&read_text(path)?);
+        let plugins_txt = path.with_file_name("plugins.txt");
+        let active = if plugins_txt.is_file() {
+            Some(parse_plugins_txt(&read_text(&plugins_txt)?, game))
+        } else {
+            None
+        };
+        return Ok(order
+            .into_iter()
+            .map(|name| PluginEntry {
+                active: active.as_ref().map_or(true, |entries| {
+                    entries
+                        .iter()
+                        .any(|x| x.active && x.name.eq_ignore_ascii_case(&name))
+                }),
+                name,
+            })
+            .collect());
+    }
+
+    let mut entries = parse_plugins_txt(&read_text(path)?, game);
+    let loadorder_txt = path.with_file_name("loadorder.txt");
+    if game == GameFormat::LegendaryEdition && loadorder_txt.is_file() {
+        apply_loadorder(
+            &mut entries,
+            &parse_loadorder_txt(&read_text(&loadorder_txt)?),
+        );
+    }
+    Ok(entries)
+}
+
+/// Plugin lists are written in the system's codepage, so anything that isn't utf-8 is replaced
+fn read_text(path: &Path) -> Result<String, OpenError<'static>> {
+    match std::fs::read(path) {
+        Ok(data) => Ok(String::from_utf8_lossy(&data).into_owned()),
+        Err(error) => Err(OpenError::Io {
+            path: path.to_path_buf(),
+            error,
+        }),
+    }
+}
+
+/// Whether the game loads [file] among the masters, which come before every other plugin
+fn is_master(file: &PluginFile) -> bool {
+    let extension = Path::new(&file.name)
+        .extension()
+        .and_then(|x| x.to_str())
+        .unwrap_or("");
+    let flags = file
+        .data
+        .get(8..12)
+        .map_or(0, |x| u32::from_le_bytes([x[0], x[1], x[2], x[3]]));
+    extension.eq_ignore_ascii_case("esm")
+        || extension.eq_ignore_ascii_case("esl")
+        || flags & record_flag::MASTER != 0
+}
+
+/// The game's Data folder
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct DataFolder {
+    pub path: PathBuf,
+}
+impl DataFolder {
+    pub fn new<P: Into<PathBuf>>(path: P) -> DataFolder {
+        DataFolder { path: path.into() }
+    }
+
+    /// The first of [hints] that is a Data folder, or is a game folder with a Data folder in it.
+    /// Hints would be the install path found in the registry, the folder of the game's ini, and
+    /// such. A Data folder is recognized by having [GAME_MASTER] in it
+    pub fn find<P: AsRef<Path>>(hints: &[P]) -> Option<DataFolder> {
+        hints.iter().find_map(|hint| {
+            let hint = hint.as_ref();
+            [hint.to_path_buf(), hint.join("Data")]
+                .iter()
+                .find(|path| {
+                    plugin_names(path).map_or(false, |names| {
+                        names.contains_key(&GAME_MASTER.to_ascii_lowercase())
+                    })
+                })
+                .cloned()
+                .map(DataFolder::new)
+        })
+    }
+
+    /// Names of the plugins that load with [entries], in load order, spelled as they are in the
+    /// folder: the plugins that [game] always loads, then the active ones of [entries].
+    /// Plugins that aren't in the folder are left out, as the game skips them
+    pub fn active_plugins(
+        &self,
+        game: GameFormat,
+        entries: &[PluginEntry],
+    ) -> Result<Vec<String>, OpenError<'static>> {
+        let files = plugin_names(&self.path).map_err(|error| OpenError::Io {
+            path: self.path.clone(),
+            error,
+        })?;
+        let active = entries.iter().filter(|x| x.active).map(|x| x.name.as_str());
+        let mut names: Vec<String> = Vec::new();
+        for name in implicit_plugins(game).iter().copied().chain(active) {
+            if let Some(name) = files.get(&name.to_ascii_lowercase()) {
+                if !names.contains(name) {
+                    names.push(name.clone());
+                }
+            }
+        }
+        Ok(names)
+    }
+
+    /// Reads [names] from the folder, ready for [crate::load_order::LoadOrder::open]. Masters
+    /// are moved before the other plugins, as the game does
+    pub fn read_plugins(&self, names: &[String]) -> Result<Vec<PluginFile>, OpenError<'static>> {
+        let paths: Vec<PathBuf> = names.iter().map(|x| self.path.join(x)).collect();
+        let mut files = PluginFile::read_all(&paths)?;
+        files.sort_by_key(|x| !is_master(x));
+        Ok(files)
+    }
+
+    /// Reads the plugins that the plugin list at [list] makes active, in the order they load
+    pub fn read_load_order(
+        &self,
+        game: GameFormat,
+        list: &Path,
+    ) -> Result<Vec<PluginFile>, OpenError<'static>> {
+        let entries = read_plugin_list(list, game)?;
+        self.read_plugins(&self.active_plugins(game, &entries)?)
+    }
+}
+
+/// The plugins in [dir], keyed by their lowercase name, as the game doesn't care about case
+fn plugin_names(dir: &Path) -> std::io::Result<HashMap<String, String>> {
+    let mut names = HashMap::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if let Some(name) = path.file_name().and_then(|x| x.to_str()) {
+            if path.is_file() && is_plugin_path(&path) {
+                names.insert(name.to_ascii_lowercase(), name.to_string());
+            }
+        }
+    }
+    Ok(names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(name: &str, active: bool) -> PluginEntry {
+        PluginEntry {
+            name: name.to_string(),
+            active,
+        }
+    }
+
+    #[test]
+    fn test_parse_plugins_txt() {
+        let text = "\u{feff}# This file is used by the game\r\n*Unofficial Patch.esp\r\n\
+                    Disabled.esp\r\n\r\n*Patch.esp\r\n*unofficial patch.esp\r\n";
+        assert_eq!(
+            parse_plugins_txt(text, GameFormat::SpecialEdition),
+            vec![
+                entry("Unofficial Patch.esp", true),
+                entry("Disabled.esp", false),
+                entry("Patch.esp", true),
+            ]
+        );
+        assert_eq!(
+            parse_plugins_txt("Dawnguard.esm\nPatch.esp\n", GameFormat::LegendaryEdition),
+            vec![entry("Dawnguard.esm", true), entry("Patch.esp", true)]
+        );
+
+        let mut entries = parse_plugins_txt("B.esp\nA.esp\nC.esp\n", GameFormat::LegendaryEdition);
+        let order = parse_loadorder_txt("Skyrim.esm\na.esp\nb.esp\n");
+        apply_loadorder(&mut entries, &order);
+        assert_eq!(
+            entries,
+            vec![
+                entry("A.esp", true),
+                entry("B.esp", true),
+                entry("C.esp", true)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_data_folder() {
+        let dir = std::env::temp_dir().join(format!("vivec_data_folder_{}", std::process::id()));
+        let data = dir.join("Data");
+        std::fs::create_dir_all(&data).unwrap();
+        let header = |flags: u32| {
+            let mut header = b"TES4".to_vec();
+            header.extend_from_slice(&0u32.to_le_bytes());
+            header.extend_from_slice(&flags.to_le_bytes());
+            header.extend_from_slice(&[0; 12]);
+            header
+        };
+        std::fs::write(data.join("skyrim.esm"), header(record_flag::MASTER)).unwrap();
+        std::fs::write(data.join("Patch.esp"), header(0)).unwrap();
+        std::fs::write(data.join("Flagged.esp"), header(record_flag::MASTER)).unwrap();
+        std::fs::write(data.join("Disabled.esp"), header(0)).unwrap();
+        let plugins_txt = dir.join("plugins.txt");
+        std::fs::write(
+            &plugins_txt,
+            "*Patch.esp\nDisabled.esp\n*Missing.esp\n*Flagged.esp\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            DataFolder::find(&[dir.join("Missing"), data.clone()]),
+            Some(DataFolder::new(&data))
+        );
+        let folder = DataFolder::find(&[&dir]).unwrap();
+        assert_eq!(folder.path, data);
+
+        let game = GameFormat::SpecialEdition;
+        let entries = read_plugin_list(&plugins_txt, game).unwrap();
+        assert_eq!(
+            folder.active_plugins(game, &entries).unwrap(),
+            vec!["skyrim.esm", "Patch.esp", "Flagged.esp"]
+        );
+        let files = folder.read_load_order(game, &plugins_txt).unwrap();
+        assert_eq!(
+            files.iter().map(|x| x.name.as_str()).collect::<Vec<_>>(),
+            vec!["skyrim.esm", "Flagged.esp", "Patch.esp"]
+        );
+
+        std::fs::write(
+            dir.join("loadorder.txt"),
+            "Skyrim.esm\nFlagged.esp\nDisabled.esp\nPatch.esp\n",
+        )
+        .unwrap();
+        assert_eq!(
+            read_plugin_list(&dir.join("loadorder.txt"), game).unwrap(),
+            vec![
+                entry("Skyrim.esm", false),
+                entry("Flagged.esp", true),
+                entry("Disabled.esp", false),
+                entry("Patch.esp", true),
+            ]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
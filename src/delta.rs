@@ -0,0 +1,305 @@
+// Patches made from edits. Given a plugin as it was loaded and an edited copy of it, the records
+// of the copy that differ are written as a new plugin that has the original as its last master,
+// so that they override it, rather than the original itself being written over.
+// Records are compared by the bytes that they are written as, so a record that was changed and
+// then changed back isn't carried over.
+
+use crate::{
+    groups::{
+        common::{walk_group_entries, GroupEntry},
+        Group,
+    },
+    plugin::Plugin,
+    records::{common::FormId, tes4::TES4Record, Record, RecordRef},
+    util::{Writable, WriteError},
+    Top,
+};
+use bstr::BString;
+use std::collections::HashMap;
+
+/// A record of a plugin, either converted or held by a group that wasn't converted
+#[derive(Debug, Copy, Clone)]
+enum Original<'a, 'data> {
+    Record(RecordRef<'a, 'data>),
+    Entry(&'a GroupEntry<'data>),
+}
+impl Original<'_, '_> {
+    fn to_vec(self) -> Result<Vec<u8>, WriteError> {
+        match self {
+            Original::Record(record) => record.to_vec(),
+            Original::Entry(entry) => entry.to_vec(),
+        }
+    }
+}
+
+/// Every record of [plugin] apart from the TES4 record, by form id
+fn originals<'a, 'data>(plugin: &'a Plugin<'data>) -> HashMap<FormId, Original<'a, 'data>> {
+    let mut originals = HashMap::new();
+    for top in plugin.tops().iter() {
+        let entries = match top {
+            Top::Record(Record::TES4(_)) => continue,
+            Top::Record(record) => {
+                let record = record.as_record_ref();
+                originals.insert(record.formid(), Original::Record(record));
+                continue;
+            }
+            Top::Group(Group::Unknown(group)) => &group.entries,
+            Top::Group(Group::UnknownTop(group)) => &group.entries,
+            Top::Group(group) => {
+                for record in group.records() {
+                    originals.insert(record.formid(), Original::Record(record));
+                }
+                continue;
+            }
+        };
+        walk_group_entries(entries, &mut |entry| {
+            if let Some(id) = entry.formid() {
+                originals.insert(id, Original::Entry(entry));
+            }
+        });
+    }
+    originals
+}
+
+/// Keeps the records of [entries] that are in [renumber], giving them their new ids.
+/// Groups are kept if they still hold anything, along with the record just before them, which is
+/// the CELL, WRLD or DIAL that they are the children of
+fn retain_entries<'data>(
+    entries: Vec<GroupEntry<'data>>,
+    renumber: &HashMap<FormId, FormId>,
+) -> Vec<GroupEntry<'data>> {
+    let mut kept = Vec::new();
+    let mut parent = None;
+    for entry in entries {
+        match entry {
+            GroupEntry::Group(mut group) => {
+                group.entries = retain_entries(group.entries, renumber);
+                if !group.entries.is_empty() {
+                    kept.extend(parent.take());
+                    kept.push(GroupEntry::Group(group));
+                }
+                parent = None;
+            }
+            mut record => match record.formid().and_then(|id| renumber.get(&id)) {
+                Some(new_id) => {
+                    match &mut record {
+                        GroupEntry::Record(x) => x.common.id = new_id.id,
+                        GroupEntry::Compressed(x) => x.common.id = new_id.id,
+                        GroupEntry::Group(_) => {}
+                    }
+                    kept.push(record);
+                    parent = None;
+                }
+                None => parent = Some(record),
+            },
+        }
+    }
+    kept
+}
+
+impl<'data> Plugin<'data> {
+    /// A plugin named [name] holding the records of this plugin that differ from those of [base],
+    /// which this plugin should be an edited copy of. The masters of [base] followed by [base]
+    /// itself become the masters of the new plugin, so the records override those of [base].
+    /// Records that aren't in [base] are kept as new records of the new plugin, and references to
+    /// them are renumbered to match, see [Plugin::remap_formids]. Records removed from [base]
+    /// aren't recorded.
+    pub fn delta<S: Into<String>>(
+        &self,
+        base: &Plugin<'_>,
+        name: S,
+    ) -> Result<Plugin<'data>, WriteError> {
+        let base_index = base.masters().len() as u8;
+        let base_records = originals(base);
+
+        // Ids of the records that differ, along with the ids they have in the new plugin
+        let mut renumber = HashMap::new();
+        for (id, edited) in originals(self) {
+            let changed = match base_records.get(&id) {
+                Some(original) => original.to_vec()? != edited.to_vec()?,
+                None => true,
+            };
+            if !changed {
+                continue;
+            }
+            let new_id = if id.mod_index() == base_index && !base_records.contains_key(&id) {
+                id.with_mod_index(base_index + 1)
+            } else {
+                id
+            };
+            renumber.insert(id, new_id);
+        }
+
+        let mut header = TES4Record::new();
+        for master in base.masters() {
+            header.push_master(master.to_owned());
+        }
+        header.push_master(BString::from(base.name.as_str()));
+        let mut delta = Plugin::from_tops(name.into(), vec![Top::Record(Record::TES4(header))]);
+        for top in self.tops().iter() {
+            let top = match top {
+                Top::Record(Record::TES4(_)) => continue,
+                Top::Record(record) if renumber.contains_key(&record.as_record_ref().formid()) => {
+                    top.clone()
+                }
+                Top::Record(_) => continue,
+                Top::Group(Group::Unknown(group)) => {
+                    let mut group = group.clone();
+                    group.entries = retain_entries(group.entries, &renumber);
+                    if group.entries.is_empty() {
+                        continue;
+                    }
+                    Top::Group(Group::Unknown(group))
+                }
+                Top::Group(Group::UnknownTop(group)) => {
+                    let mut group = group.clone();
+                    group.entries = retain_entries(group.entries, &renumber);
+                    if group.entries.is_empty() {
+                        continue;
+                    }
+                    Top::Group(Group::UnknownTop(group))
+                }
+                Top::Group(group) => {
+                    let mut group = group.clone();
+                    group.retain_records(|record| renumber.contains_key(&record.formid()));
+                    if group.records().is_empty() {
+                        continue;
+                    }
+                    Top::Group(group)
+                }
+            };
+            delta.push_top(top);
+        }
+        delta.remap_formids(&renumber);
+        delta.refresh_header();
+        Ok(delta)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        context::ParseContext,
+        records::{
+            common::{CommonRecordInfo, GeneralRecord},
+            fields::common::GeneralField,
+            RecordMut,
+        },
+    };
+    use bstr::ByteSlice;
+
+    fn record(type_name: &'static [u8], id: u32, fields: Vec<GeneralField<'static>>) -> Vec<u8> {
+        let mut common = CommonRecordInfo::test_default();
+        common.flags.flags = 0;
+        common.id = id;
+        let record = GeneralRecord {
+            type_name: type_name.as_bstr(),
+            common,
+            fields,
+        };
+        record.to_vec().unwrap()
+    }
+
+    fn group(label: &[u8], group_type: i32, contents: &[Vec<u8>]) -> Vec<u8> {
+        let contents = contents.concat();
+        let mut data = b"GRUP".to_vec();
+        data.extend_from_slice(&(24 + contents.len() as u32).to_le_bytes());
+        data.extend_from_slice(label);
+        data.extend_from_slice(&group_type.to_le_bytes());
+        data.extend_from_slice(&[0; 8]);
+        data.extend_from_slice(&contents);
+        data
+    }
+
+    #[test]
+    fn test_delta() {
+        let list = |id, entry: &'static [u8]| {
+            record(
+                b"FLST",
+                id,
+                vec![
+                    GeneralField::new(b"EDID".as_bstr(), b"List\0"),
+                    GeneralField::new(b"LNAM".as_bstr(), entry),
+                ],
+            )
+        };
+        let refr = |id, data: &'static [u8]| {
+            record(
+                b"REFR",
+                id,
+                vec![GeneralField::new(b"DATA".as_bstr(), data)],
+            )
+        };
+        let mut base = Plugin::new("Base.esm");
+        base.refresh_header();
+        let data = [
+            base.to_vec().unwrap(),
+            group(
+                b"FLST",
+                0,
+                &[list(0x801, &[1, 8, 0, 0]), list(0x802, &[2, 8, 0, 0])],
+            ),
+            group(
+                b"CELL",
+                0,
+                &[
+                    record(b"CELL", 0x803, Vec::new()),
+                    group(
+                        &0x803u32.to_le_bytes(),
+                        6,
+                        &[refr(0x804, &[1]), refr(0x805, &[2])],
+                    ),
+                ],
+            ),
+        ]
+        .concat();
+        let mut ctx = ParseContext::default();
+        let base = Plugin::parse("Base.esm", &data, &mut ctx).unwrap();
+        let mut edited = Plugin::parse("Base.esm", &data, &mut ctx).unwrap();
+        // Only the TES4 record
+        assert_eq!(edited.delta(&base, "Patch.esp").unwrap().records().len(), 1);
+
+        if let Some(RecordMut::FLST(flst)) = edited.find_mut(FormId::new(0x801)) {
+            flst.add_entry(FormId::new(0x900));
+        }
+        if let Some(Top::Group(Group::FLST(group))) = edited.top_mut(1) {
+            let mut new = group.records[1].clone();
+            new.common.id = 0x900;
+            group.records.push(new);
+        }
+        if let Some(Top::Group(Group::UnknownTop(group))) = edited.top_mut(2) {
+            if let GroupEntry::Group(children) = &mut group.entries[1] {
+                if let GroupEntry::Record(refr) = &mut children.entries[1] {
+                    refr.fields[0].data = &[3];
+                }
+            }
+        }
+
+        let delta = edited.delta(&base, "Patch.esp").unwrap();
+        assert_eq!(delta.masters(), vec![b"Base.esm".as_bstr()]);
+        let ids: Vec<u32> = delta.records().iter().map(|x| x.formid().id).collect();
+        assert_eq!(ids, vec![0, 0x801, 0x0100_0900]);
+        assert_eq!(delta.header().unwrap().header().record_count, 7);
+        let last_entry = match delta.find(FormId::new(0x801)) {
+            Some(RecordRef::FLST(flst)) => flst.entries().last(),
+            _ => None,
+        };
+        assert_eq!(last_entry, Some(FormId::new(0x0100_0900)));
+        match delta.tops().get(2) {
+            Some(Top::Group(Group::UnknownTop(group))) => {
+                let mut ids = Vec::new();
+                walk_group_entries(&group.entries, &mut |x| ids.push(x.formid()));
+                assert_eq!(
+                    ids,
+                    vec![Some(FormId::new(0x803)), None, Some(FormId::new(0x805))]
+                );
+            }
+            top => panic!("Expected the CELL group, got {:?}", top),
+        }
+
+        let data = delta.to_vec().unwrap();
+        let parsed = Plugin::parse("Patch.esp", &data, &mut ctx).unwrap();
+        assert_eq!(parsed, delta);
+    }
+}
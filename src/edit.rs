@@ -0,0 +1,295 @@
+// Guards against editing records that belong to a master. A plugin that overrides records of its
+// masters holds copies of them, and changing those copies in place is easy to do by accident when
+// what was meant was a new override, such as when a patch is built from records of several
+// plugins. So by default records are only given out to be changed, by [Plugin::edit] and
+// [Plugin::edit_records], if their form id belongs to the plugin itself, and the record should be
+// copied into the plugin that is meant to hold the change instead. Changes to the plugin as a
+// whole, such as renumbering its records, aren't edits of single records so aren't refused.
+
+use crate::{
+    error::{ErrorCode, VivecError},
+    groups::common::GroupStructureError,
+    masters::MAX_MASTERS,
+    plugin::Plugin,
+    records::{common::FormId, RecordMut},
+    walk::EntryMut,
+};
+use bstr::BStr;
+use std::fmt::{Display, Formatter};
+
+/// Whether [Plugin::edit] and [Plugin::edit_records] give out records that belong to a master
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub enum MasterEditPolicy {
+    /// Fail with [EditError::MasterRecord]
+    #[default]
+    Refuse,
+    /// Edit them in place, for those who know that the plugin is the one that should change
+    Allow,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum EditError {
+    /// There is no record with the id
+    NotFound(FormId),
+    /// The record belongs to [master], and the plugin's policy is [MasterEditPolicy::Refuse]
+    MasterRecord { id: FormId, master: String },
+    /// The plugin has no master with the filename
+    UnknownMaster(String),
+    /// Records of the plugin belong to or refer to the master, [uses] times
+    MasterInUse { master: String, uses: usize },
+    /// [records] records weren't converted, so the masters they refer to can't be known
+    HiddenReferences { records: usize },
+    /// Records of the type aren't held by top groups, so can't be placed on their own
+    NotTopLevel(String),
+    /// The form id has a mod index past that of the plugin holding it
+    BadModIndex(FormId),
+    /// The plugin already has a record with the id
+    Exists(FormId),
+    /// Records of the type are held by top groups, so don't have a parent
+    NotChild(String),
+    /// The groups that would hold the record can't be read
+    Groups(GroupStructureError),
+    /// Groups of different types or labels can't be merged
+    GroupMismatch { group: String, other: String },
+    /// The plugin has more new records than a light plugin can hold, so they can't be compacted
+    /// into its range
+    TooManyLightRecords { records: usize, max: usize },
+    /// The plugin would have this many masters, more than it can hold
+    TooManyMasters(usize),
+    /// Every object id that the plugin could give a new record is used
+    NoFreeObjectId,
+    /// The worldspace has no persistent cell to hold the persistent references of its cells
+    NoPersistentCell(FormId),
+}
+impl Display for EditError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EditError::NotFound(id) => write!(f, "There is no record [{:08X}]", id.id),
+            EditError::MasterRecord { id, master } => write!(
+                f,
+                "Record [{:08X}] belongs to {}, copy it as an override into the plugin that \
+                 should change it rather than editing it in place",
+                id.id, master
+            ),
+            EditError::UnknownMaster(master) => write!(f, "{} is not a master", master),
+            EditError::MasterInUse { master, uses } => write!(
+                f,
+                "{} can't be removed, as the plugin's records use it {} times",
+                master, uses
+            ),
+            EditError::HiddenReferences { records } => write!(
+                f,
+                "{} records weren't converted, so what they refer to isn't known",
+                records
+            ),
+            EditError::NotTopLevel(type_name) => write!(
+                f,
+                "{} records aren't held by top groups, so can't be placed on their own",
+                type_name
+            ),
+            EditError::BadModIndex(id) => {
+                write!(f, "[{:08X}] has a mod index past the plugin's own", id.id)
+            }
+            EditError::Exists(id) => write!(f, "There is already a record [{:08X}]", id.id),
+            EditError::NotChild(type_name) => write!(
+                f,
+                "{} records are held by top groups rather than by a parent",
+                type_name
+            ),
+            EditError::Groups(_) => write!(f, "Failed to read the groups to place the record in"),
+            EditError::GroupMismatch { group, other } => {
+                write!(
+                    f,
+                    "Can't merge the {} group with the {} group",
+                    group, other
+                )
+            }
+            EditError::TooManyLightRecords { records, max } => write!(
+                f,
+                "Plugin has {} new records, more than the {} a light plugin can hold",
+                records, max
+            ),
+            EditError::TooManyMasters(masters) => write!(
+                f,
+                "A plugin can't have {} masters, the most it can have is {}",
+                masters, MAX_MASTERS
+            ),
+            EditError::NoFreeObjectId => {
+                write!(f, "Every object id that a new record could have is used")
+            }
+            EditError::NoPersistentCell(world) => write!(
+                f,
+                "Worldspace [{:08X}] has no persistent cell to hold persistent references",
+                world.id
+            ),
+        }
+    }
+}
+impl std::error::Error for EditError {}
+impl VivecError for EditError {
+    fn code(&self) -> ErrorCode {
+        match self {
+            EditError::NotFound(_) => ErrorCode::RecordNotFound,
+            EditError::MasterRecord { .. } => ErrorCode::MasterRecord,
+            EditError::UnknownMaster(_) => ErrorCode::UnknownMaster,
+            EditError::MasterInUse { .. } => ErrorCode::MasterInUse,
+            EditError::HiddenReferences { .. } => ErrorCode::HiddenReferences,
+            EditError::NotTopLevel(_) => ErrorCode::NotTopLevel,
+            EditError::BadModIndex(_) => ErrorCode::BadModIndex,
+            EditError::Exists(_) => ErrorCode::RecordExists,
+            EditError::NotChild(_) => ErrorCode::NotChild,
+            EditError::Groups(error) => error.code(),
+            EditError::GroupMismatch { .. } => ErrorCode::GroupMismatch,
+            EditError::TooManyLightRecords { .. } => ErrorCode::TooManyLightRecords,
+            EditError::TooManyMasters(_) => ErrorCode::TooManyMasters,
+            EditError::NoFreeObjectId => ErrorCode::NoFreeObjectId,
+            EditError::NoPersistentCell(_) => ErrorCode::NoPersistentCell,
+        }
+    }
+
+    fn inner(&self) -> Option<&dyn VivecError> {
+        match self {
+            EditError::Groups(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl<'data> Plugin<'data> {
+    /// The master that [id] belongs to, None if it belongs to this plugin itself
+    pub fn owning_master(&self, id: FormId) -> Option<&BStr> {
+        self.masters().get(usize::from(id.mod_index())).copied()
+    }
+
+    pub fn edit_policy(&self) -> MasterEditPolicy {
+        self.edit_policy
+    }
+
+    pub fn set_edit_policy(&mut self, policy: MasterEditPolicy) {
+        self.edit_policy = policy;
+    }
+
+    /// Whether [Plugin::edit_policy] allows changing the record with [id] in place
+    pub fn is_editable(&self, id: FormId) -> bool {
+        self.edit_policy == MasterEditPolicy::Allow || self.owning_master(id).is_none()
+    }
+
+    /// The record with [id] to be changed, including those within groups that weren't
+    /// converted. Fails for records that belong to a master unless [Plugin::edit_policy] allows
+    /// editing them
+    pub fn edit(&mut self, id: FormId) -> Result<RecordMut<'_, 'data>, EditError> {
+        if !self.is_editable(id) {
+            let master = self.owning_master(id).unwrap_or_default();
+            return Err(EditError::MasterRecord {
+                id,
+                master: master.to_string(),
+            });
+        }
+        self.find_mut(id).ok_or(EditError::NotFound(id))
+    }
+
+    /// Every record of the plugin that [Plugin::edit] would give out, in the order they are
+    /// written, leaving out those that belong to a master unless the policy allows editing them
+    pub fn edit_records(&mut self) -> Vec<EntryMut<'_, 'data>> {
+        let policy = self.edit_policy;
+        let own = self.masters().len();
+        self.iter_all_records_mut()
+            .map(|(record, _)| record)
+            .filter(|record| {
+                policy == MasterEditPolicy::Allow || usize::from(record.formid().mod_index()) >= own
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        context::ParseContext,
+        groups::{
+            common::{CommonGroupInfo, GroupEntry, TopGroup},
+            Group,
+        },
+        records::{
+            common::{CommonRecordInfo, FromRecord, GeneralRecord},
+            fields::common::GeneralField,
+            tes4::TES4Record,
+            Record,
+        },
+        Top,
+    };
+    use bstr::ByteSlice;
+
+    #[test]
+    fn test_edit_policy() {
+        let header = GeneralRecord {
+            type_name: b"TES4".as_bstr(),
+            common: CommonRecordInfo::test_default(),
+            fields: vec![
+                GeneralField::new(b"HEDR".as_bstr(), &[0; 12]),
+                GeneralField::new(b"MAST".as_bstr(), b"Skyrim.esm\0"),
+                GeneralField::new(b"DATA".as_bstr(), &[0; 8]),
+            ],
+        };
+        let (_, header) = TES4Record::from_record(header, &mut ParseContext::default()).unwrap();
+        let record = |id| {
+            let mut common = CommonRecordInfo::test_default();
+            common.id = id;
+            Top::Record(Record::Unknown(GeneralRecord {
+                type_name: b"MISC".as_bstr(),
+                common,
+                fields: vec![GeneralField::new(b"EDID".as_bstr(), b"Test\0")],
+            }))
+        };
+        let mut plugin = Plugin::from_tops(
+            "Patch.esp".to_string(),
+            vec![
+                Top::Record(Record::TES4(header)),
+                record(0x0000_0d00),
+                record(0x0100_0d00),
+            ],
+        );
+        let master_id = FormId::new(0x0000_0d00);
+        let own_id = FormId::new(0x0100_0d00);
+        assert_eq!(
+            plugin.owning_master(master_id),
+            Some(b"Skyrim.esm".as_bstr())
+        );
+        assert_eq!(plugin.owning_master(own_id), None);
+
+        assert_eq!(plugin.edit_policy(), MasterEditPolicy::Refuse);
+        let error = plugin.edit(master_id).unwrap_err();
+        assert_eq!(error.code(), ErrorCode::MasterRecord);
+        assert!(error.to_string().contains("Skyrim.esm"));
+        assert!(plugin.edit(own_id).is_ok());
+        assert_eq!(
+            plugin.edit(FormId::new(0x0100_0e00)).unwrap_err(),
+            EditError::NotFound(FormId::new(0x0100_0e00))
+        );
+
+        // Records within groups that weren't converted are guarded the same way
+        let cell = |id| {
+            let mut common = CommonRecordInfo::test_default();
+            common.id = id;
+            GroupEntry::Record(GeneralRecord {
+                type_name: b"CELL".as_bstr(),
+                common,
+                fields: Vec::new(),
+            })
+        };
+        plugin.push_top(Top::Group(Group::UnknownTop(TopGroup {
+            label: b"CELL".as_bstr(),
+            common: CommonGroupInfo::new(),
+            entries: vec![cell(0x0000_0e00), cell(0x0100_0e01)],
+        })));
+        assert!(plugin.edit(FormId::new(0x0000_0e00)).is_err());
+        assert!(plugin.edit(FormId::new(0x0100_0e01)).is_ok());
+        let editable: Vec<_> = plugin.edit_records().iter().map(|x| x.formid()).collect();
+        assert_eq!(editable, vec![own_id, FormId::new(0x0100_0e01)]);
+
+        plugin.set_edit_policy(MasterEditPolicy::Allow);
+        assert!(plugin.edit(master_id).is_ok());
+        assert_eq!(plugin.edit_records().len(), 5);
+    }
+}
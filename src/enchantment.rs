@@ -0,0 +1,252 @@
+// Flattened view of the magic effects on an enchanted item.
+// ENCH and MGEF records are not parsed into their own types yet, so the fields that are needed
+// are decoded out of their general records.
+
+use crate::{
+    load_order::{LoadOrder, SourcedRecord},
+    records::{
+        alch::{EFID, EFIT},
+        common::{FormId, GeneralRecord},
+        fields::{
+            common::{item::Enchantable, FromFieldError},
+            edid,
+        },
+        RecordRef,
+    },
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnchantmentEffect {
+    /// MGEF form id, as written in the plugin that the enchantment is from
+    pub effect_id: FormId,
+    /// Editor id of the magic effect, if the MGEF could be found
+    pub effect_editor_id: Option<String>,
+    pub magnitude: f32,
+    pub area_of_effect: u32,
+    /// 0 = instant
+    pub duration: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnchantmentView {
+    /// ENCH form id, as written in the plugin that the item is from
+    pub enchantment_id: FormId,
+    /// Position of the plugin that the winning ENCH record is from
+    pub plugin: usize,
+    /// Enchantment amount (EAMT) of the item
+    pub amount: Option<u16>,
+    pub effects: Vec<EnchantmentEffect>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EnchantmentError<'data> {
+    /// No record with the form id was found
+    Unresolved(FormId),
+    /// The form id refers to a record that is not an ENCH
+    NotEnchantment(FormId),
+    Field(FromFieldError<'data>),
+}
+impl<'data> From<FromFieldError<'data>> for EnchantmentError<'data> {
+    fn from(error: FromFieldError<'data>) -> EnchantmentError<'data> {
+        EnchantmentError::Field(error)
+    }
+}
+
+/// Resolves the enchantment of [item] into its magic effects.
+/// [plugin] is the position of the plugin that [item] is from.
+/// Returns None if the item is not enchanted.
+pub fn resolve_enchantment<'data>(
+    item: &dyn Enchantable,
+    load_order: &LoadOrder<'data>,
+    plugin: usize,
+) -> Result<Option<EnchantmentView>, EnchantmentError<'data>> {
+    let (enchantment_id, amount) = match item.enchantment_id() {
+        Some(enchantment) => enchantment,
+        None => return Ok(None),
+    };
+    let (ench_plugin, ench) = match load_order.resolve(plugin, enchantment_id) {
+        Some(SourcedRecord {
+            source,
+            record: RecordRef::Unknown(record),
+        }) if record.type_name == "ENCH" => (source.index, record),
+        Some(_) => return Err(EnchantmentError::NotEnchantment(enchantment_id)),
+        None => return Err(EnchantmentError::Unresolved(enchantment_id)),
+    };
+
+    let mut effects = Vec::new();
+    let mut effect_id = None;
+    for field in ench.fields.iter() {
+        if field.type_name == "EFID" {
+            effect_id = Some(field.decode_as::<EFID>()?.formid);
+        } else if field.type_name == "EFIT" {
+            // An EFIT without an EFID before it has no effect to apply to
+            let effect_id = match effect_id.take() {
+                Some(effect_id) => effect_id,
+                None => continue,
+            };
+            let efit = field.decode_as::<EFIT>()?;
+            effects.push(EnchantmentEffect {
+                effect_id,
+                effect_editor_id: magic_effect_editor_id(load_order, ench_plugin, effect_id)?,
+                magnitude: efit.magnitude,
+                area_of_effect: efit.area_of_effect,
+                duration: efit.duration,
+            });
+        }
+    }
+
+    Ok(Some(EnchantmentView {
+        enchantment_id,
+        plugin: ench_plugin,
+        amount,
+        effects,
+    }))
+}
+
+fn magic_effect_editor_id<'data>(
+    load_order: &LoadOrder<'data>,
+    plugin: usize,
+    effect_id: FormId,
+) -> Result<Option<String>, EnchantmentError<'data>> {
+    match load_order.resolve(plugin, effect_id) {
+        Some(SourcedRecord {
+            record: RecordRef::Unknown(record),
+            ..
+        }) if record.type_name == "MGEF" => editor_id(record),
+        _ => Ok(None),
+    }
+}
+
+fn editor_id<'data>(
+    record: &GeneralRecord<'data>,
+) -> Result<Option<String>, EnchantmentError<'data>> {
+    match record.fields.iter().find(|x| x.type_name == "EDID") {
+        Some(field) => Ok(Some(field.decode_as::<edid::EDID>()?.id.value.to_string())),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        groups::{common::TopGroup, Group},
+        plugin::Plugin,
+        records::{common::CommonRecordInfo, fields::common::GeneralField, Record},
+        Top,
+    };
+    use bstr::{BString, ByteSlice};
+
+    struct Enchanted(Option<(FormId, Option<u16>)>);
+    impl Enchantable for Enchanted {
+        fn enchantment_id(&self) -> Option<(FormId, Option<u16>)> {
+            self.0
+        }
+    }
+
+    fn general<'a>(
+        type_name: &'a [u8],
+        id: u32,
+        fields: Vec<GeneralField<'a>>,
+    ) -> GeneralRecord<'a> {
+        let mut common = CommonRecordInfo::test_default();
+        common.id = id;
+        GeneralRecord {
+            type_name: type_name.as_bstr(),
+            common,
+            fields,
+        }
+    }
+
+    fn record<'a>(type_name: &'a [u8], id: u32, fields: Vec<GeneralField<'a>>) -> Top<'a> {
+        Top::Record(Record::Unknown(general(type_name, id, fields)))
+    }
+
+    /// A group of records that aren't converted, as they are after parsing
+    fn group<'a>(type_name: &'a [u8], records: Vec<GeneralRecord<'a>>) -> Top<'a> {
+        let records = records.into_iter().map(Record::Unknown).collect();
+        let group = TopGroup::from_records(type_name.as_bstr(), records).unwrap();
+        Top::Group(Group::UnknownTop(group))
+    }
+
+    #[test]
+    fn test_resolve_enchantment() {
+        let mut efit = 25f32.to_le_bytes().to_vec();
+        efit.extend_from_slice(&0u32.to_le_bytes());
+        efit.extend_from_slice(&60u32.to_le_bytes());
+
+        let mut patch = Plugin::new("Patch.esp");
+        patch
+            .header_mut()
+            .unwrap()
+            .push_master(BString::from("Test.esp"));
+        // Overrides are looked up within the groups of every plugin
+        patch.push_top(group(
+            b"MGEF",
+            vec![general(
+                b"MGEF",
+                0x200,
+                vec![GeneralField::new(b"EDID".as_bstr(), b"FortifyHealth\0")],
+            )],
+        ));
+        let load_order = LoadOrder::new(vec![
+            Plugin::from_tops(
+                "Test.esp".to_string(),
+                vec![
+                    group(
+                        b"ENCH",
+                        vec![general(
+                            b"ENCH",
+                            0x100,
+                            vec![
+                                GeneralField::new(b"EFID".as_bstr(), &[0x00, 0x02, 0, 0]),
+                                GeneralField::new(b"EFIT".as_bstr(), &efit),
+                                GeneralField::new(b"EFID".as_bstr(), &[0x01, 0x02, 0, 0]),
+                                GeneralField::new(b"EFIT".as_bstr(), &efit),
+                            ],
+                        )],
+                    ),
+                    group(
+                        b"MGEF",
+                        vec![general(
+                            b"MGEF",
+                            0x200,
+                            vec![GeneralField::new(b"EDID".as_bstr(), b"Unnamed\0")],
+                        )],
+                    ),
+                    record(b"MISC", 0x300, vec![]),
+                ],
+            ),
+            patch,
+        ]);
+
+        let item = Enchanted(Some((FormId::new(0x100), Some(500))));
+        let view = resolve_enchantment(&item, &load_order, 0).unwrap().unwrap();
+        assert_eq!(view.amount, Some(500));
+        assert_eq!(view.effects.len(), 2);
+        assert_eq!(
+            view.effects[0],
+            EnchantmentEffect {
+                effect_id: FormId::new(0x200),
+                effect_editor_id: Some("FortifyHealth".to_string()),
+                magnitude: 25.0,
+                area_of_effect: 0,
+                duration: 60,
+            }
+        );
+        assert_eq!(view.effects[1].effect_editor_id, None);
+
+        assert_eq!(
+            resolve_enchantment(&Enchanted(None), &load_order, 0),
+            Ok(None)
+        );
+        assert_eq!(
+            resolve_enchantment(&Enchanted(Some((FormId::new(0x300), None))), &load_order, 0),
+            Err(EnchantmentError::NotEnchantment(FormId::new(0x300)))
+        );
+        assert_eq!(
+            resolve_enchantment(&Enchanted(Some((FormId::new(0x400), None))), &load_order, 0),
+            Err(EnchantmentError::Unresolved(FormId::new(0x400)))
+        );
+    }
+}
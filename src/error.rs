@@ -0,0 +1,166 @@
+// Error codes shared by every error that the library returns, so that applications can match on
+// the kind of failure without matching on each error type, and show a stable code with it.
+// Errors that wrap another error, such as a record failing because one of its fields did, expose
+// the wrapped error through [VivecError::inner]. std's `source` can't be used for that, as most
+// of the errors borrow from the plugin's data and so aren't 'static.
+
+use std::fmt::{Display, Formatter};
+
+/// The broad kind of failure
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum ErrorCategory {
+    /// Reading or writing a file failed
+    Io,
+    /// The operation was asked to stop
+    Cancelled,
+    /// The data is malformed
+    Parse,
+    /// The data is well formed, but doesn't hold what a record or field should
+    Conversion,
+    /// A value can't be represented in the file format
+    Write,
+    /// An edit to a plugin was refused
+    Edit,
+}
+
+/// Stable code of a failure. The numbers are never reused or changed, new failures get new ones
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum ErrorCode {
+    Io = 1,
+    Cancelled = 2,
+
+    ExpectedBytes = 100,
+    UnexpectedEof = 101,
+    ExpectedEof = 102,
+    InvalidEnumerationValue = 103,
+    ExpectedExact = 104,
+    InvalidByteCount = 105,
+    GroupSize = 106,
+    CountTooLarge = 107,
+    UnexpectedGroup = 108,
+    UnexpectedCompressed = 109,
+    Decompress = 110,
+    Undecodable = 111,
+    InvalidFormId = 112,
+
+    UnexpectedEndOfFields = 200,
+    ExpectedField = 201,
+    ExpectedFieldGot = 202,
+    UnexpectedField = 203,
+    DuplicateField = 204,
+    GroupStructure = 205,
+
+    RecordTooLarge = 300,
+    GroupTooLarge = 301,
+    FieldTooLarge = 302,
+    TooLong = 303,
+    Encoding = 304,
+    Unverified = 305,
+    LightRange = 306,
+    ExtensionFlags = 307,
+
+    RecordNotFound = 400,
+    MasterRecord = 401,
+    UnknownMaster = 402,
+    MasterInUse = 403,
+    HiddenReferences = 404,
+    NotTopLevel = 405,
+    BadModIndex = 406,
+    RecordExists = 407,
+    NotChild = 408,
+    GroupMismatch = 409,
+    TooManyLightRecords = 410,
+    TooManyMasters = 411,
+    NoFreeObjectId = 412,
+    NoPersistentCell = 413,
+}
+impl ErrorCode {
+    pub fn number(self) -> u16 {
+        self as u16
+    }
+
+    pub fn category(self) -> ErrorCategory {
+        match self.number() {
+            1 => ErrorCategory::Io,
+            2 => ErrorCategory::Cancelled,
+            100..=199 => ErrorCategory::Parse,
+            200..=299 => ErrorCategory::Conversion,
+            400..=499 => ErrorCategory::Edit,
+            _ => ErrorCategory::Write,
+        }
+    }
+}
+impl Display for ErrorCode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "V{:04}", self.number())
+    }
+}
+
+pub trait VivecError: std::error::Error {
+    /// Code of the underlying failure. Errors that only add context return the code of their cause
+    fn code(&self) -> ErrorCode;
+
+    /// The error that this one adds context to
+    fn inner(&self) -> Option<&dyn VivecError> {
+        None
+    }
+
+    fn category(&self) -> ErrorCategory {
+        self.code().category()
+    }
+}
+
+/// [error] followed by each of its causes
+pub fn chain(error: &dyn VivecError) -> impl Iterator<Item = &dyn VivecError> {
+    std::iter::successors(Some(error), |x| x.inner())
+}
+
+/// A message for [error] including each of its causes, prefixed by its code.
+/// Such as "V0101: Failed to convert record: Failed to parse: Unexpected end of data"
+pub fn report(error: &dyn VivecError) -> String {
+    let messages: Vec<String> = chain(error).map(|x| x.to_string()).collect();
+    format!("{}: {}", error.code(), messages.join(": "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        parse::ParseError, records::common::FromRecordError,
+        records::fields::common::FromFieldError, util::WriteError, GeneralError,
+    };
+    use bstr::ByteSlice;
+
+    #[test]
+    fn test_error_chain() {
+        let error = GeneralError::Record(FromRecordError::FromField(FromFieldError::ParseError(
+            ParseError::Element {
+                index: 2,
+                offset: 8,
+                error: Box::new(ParseError::UnexpectedEOF),
+            },
+        )));
+        assert_eq!(error.code(), ErrorCode::UnexpectedEof);
+        assert_eq!(error.category(), ErrorCategory::Parse);
+        assert_eq!(chain(&error).count(), 5);
+        assert_eq!(
+            report(&error),
+            "V0101: Failed to convert record: Failed to convert field: Failed to parse: \
+             Element 2, at offset 8, failed to parse: Unexpected end of data"
+        );
+
+        let error = FromRecordError::DuplicateField(b"EDID".as_bstr());
+        assert_eq!(error.code(), ErrorCode::DuplicateField);
+        assert_eq!(error.category(), ErrorCategory::Conversion);
+        assert_eq!(report(&error), "V0204: Found more than one EDID field");
+
+        let error = WriteError::TooLong {
+            what: "List",
+            length: 70000,
+            max: 65535,
+        };
+        assert_eq!(error.category(), ErrorCategory::Write);
+        assert_eq!(ErrorCode::Cancelled.to_string(), "V0002");
+        assert_eq!(GeneralError::Cancelled.category(), ErrorCategory::Cancelled);
+    }
+}
@@ -0,0 +1,85 @@
+// Records, groups and plugins for tests to build upon, so that each test only spells out what it
+// is about.
+
+use crate::{
+    context::ParseContext,
+    groups::{
+        common::{CommonGroupInfo, GroupEntry, TopGroup, GROUPH_SIZE},
+        Group,
+    },
+    plugin::Plugin,
+    records::{
+        common::{CommonRecordInfo, GeneralRecord},
+        fields::common::GeneralField,
+        Record,
+    },
+    util::Writable,
+    Top,
+};
+use bstr::{BString, ByteSlice};
+
+/// A [type_name] record with the id [id], no flags and [fields]
+pub fn general<'data>(
+    type_name: &'static [u8],
+    id: u32,
+    fields: Vec<GeneralField<'data>>,
+) -> GeneralRecord<'data> {
+    let mut common = CommonRecordInfo::test_default();
+    common.flags.flags = 0;
+    common.id = id;
+    GeneralRecord {
+        type_name: type_name.as_bstr(),
+        common,
+        fields,
+    }
+}
+
+/// A FLST record at the top level, holding each of [entries] as a form id
+pub fn list<'data>(id: u32, entries: &[&'data [u8]]) -> Top<'data> {
+    let mut fields = vec![GeneralField::new(b"EDID".as_bstr(), b"List\0")];
+    for entry in entries {
+        fields.push(GeneralField::new(b"LNAM".as_bstr(), entry));
+    }
+    let record = general(b"FLST", id, fields);
+    Top::Record(Record::from_general(record, &mut ParseContext::default()).unwrap())
+}
+
+/// A top group labelled [label] that wasn't converted, holding [entries]
+pub fn top<'data>(label: &'static [u8], entries: Vec<GroupEntry<'data>>) -> Top<'data> {
+    Top::Group(Group::UnknownTop(TopGroup {
+        label: label.as_bstr(),
+        common: CommonGroupInfo::new(),
+        entries,
+    }))
+}
+
+/// A plugin called [name] with [masters], holding [tops] after its header
+pub fn plugin<'data>(name: &str, masters: &[&str], tops: Vec<Top<'data>>) -> Plugin<'data> {
+    let mut plugin = Plugin::new(name);
+    for master in masters {
+        plugin
+            .header_mut()
+            .unwrap()
+            .push_master(BString::from(*master));
+    }
+    plugin.tops_mut().extend(tops);
+    plugin.refresh_header();
+    plugin
+}
+
+/// The bytes of a [type_name] record with the id [id], no flags and [fields]
+pub fn record(type_name: &'static [u8], id: u32, fields: Vec<GeneralField<'_>>) -> Vec<u8> {
+    general(type_name, id, fields).to_vec().unwrap()
+}
+
+/// The bytes of a group with [label] and [group_type], holding [contents]
+pub fn group(label: &[u8], group_type: i32, contents: &[Vec<u8>]) -> Vec<u8> {
+    let contents = contents.concat();
+    let mut data = b"GRUP".to_vec();
+    data.extend_from_slice(&((GROUPH_SIZE + contents.len()) as u32).to_le_bytes());
+    data.extend_from_slice(label);
+    data.extend_from_slice(&group_type.to_le_bytes());
+    data.extend_from_slice(&[0; 8]);
+    data.extend_from_slice(&contents);
+    data
+}
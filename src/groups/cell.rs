@@ -0,0 +1,1127 @@
+// The children of cells. Each CELL record is followed by a group holding its children (type 6,
+// labelled with the CELL's form id), which holds up to two groups of its own: the persistent
+// references (type 8), then the temporary ones (type 9), which also hold the cell's navmeshes and
+// landscape. A plugin keeps these as the general groups that it read. They can be looked at and
+// added to where they are, through CellChildrenRef and CellChildrenMut, or converted to and from
+// CellChildren, which copies them.
+// The CELL top group holds the interior cells, bucketed into ten blocks (type 2) of ten sub-blocks
+// (type 3) each, by the last and second to last decimal digits of their object index. Exterior
+// cells are held by their worldspace instead, see groups::wrld. A cell is found where it is held
+// through Plugin::cell, and interior cells are added in place through InteriorCellsMut, while
+// Plugin::interior_cells copies the whole group into InteriorCells.
+
+use super::common::{
+    expect_group, wrong_group, ChildRecord, CommonGroupInfo, GeneralGroup, GroupEntry,
+    GroupStructureError, GroupType, TopGroup,
+};
+use crate::{
+    parse::Parse,
+    plugin::Plugin,
+    records::common::{record_flag, FormId, GeneralRecord, TypeNamed},
+    util::Position,
+};
+use bstr::ByteSlice;
+
+/// The persistent or temporary children of a cell
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReferenceGroup<'data> {
+    pub common: CommonGroupInfo,
+    pub records: Vec<ChildRecord<'data>>,
+}
+impl<'data> ReferenceGroup<'data> {
+    pub fn new() -> ReferenceGroup<'data> {
+        ReferenceGroup {
+            common: CommonGroupInfo::new(),
+            records: Vec::new(),
+        }
+    }
+
+    /// Reads the records of [group], which must hold nothing but records
+    fn from_group(group: GeneralGroup<'data>) -> ReferenceGroup<'data> {
+        ReferenceGroup {
+            common: group.common,
+            records: group
+                .entries
+                .into_iter()
+                .filter_map(|entry| ChildRecord::from_entry(entry).ok())
+                .collect(),
+        }
+    }
+
+    fn into_group(self, group_type: GroupType<'data>) -> GeneralGroup<'data> {
+        GeneralGroup {
+            group_type,
+            common: self.common,
+            entries: self.records.into_iter().map(GroupEntry::from).collect(),
+        }
+    }
+}
+
+/// The group holding the children of the CELL with the id [CellChildren::cell]
+#[derive(Debug, Clone, PartialEq)]
+pub struct CellChildren<'data> {
+    pub cell: FormId,
+    pub common: CommonGroupInfo,
+    pub persistent: Option<ReferenceGroup<'data>>,
+    pub temporary: Option<ReferenceGroup<'data>>,
+}
+impl<'data> CellChildren<'data> {
+    /// The children of [cell], without any
+    pub fn new(cell: FormId) -> CellChildren<'data> {
+        CellChildren {
+            cell,
+            common: CommonGroupInfo::new(),
+            persistent: None,
+            temporary: None,
+        }
+    }
+
+    /// Reads the children from their group, which must be of type 6 and hold nothing but a
+    /// group of each of types 8 and 9 for the same cell
+    pub fn from_group(
+        group: GeneralGroup<'data>,
+    ) -> Result<CellChildren<'data>, GroupStructureError> {
+        let cell = check_children(&group)?;
+        let mut children = CellChildren {
+            cell,
+            common: group.common,
+            persistent: None,
+            temporary: None,
+        };
+        for entry in group.entries {
+            if let GroupEntry::Group(group) = entry {
+                let slot = match group.group_type {
+                    GroupType::CellPersistentChildren(_) => &mut children.persistent,
+                    _ => &mut children.temporary,
+                };
+                *slot = Some(ReferenceGroup::from_group(group));
+            }
+        }
+        Ok(children)
+    }
+
+    /// The group of the children, with the persistent ones before the temporary ones as the
+    /// game expects. Every group is labelled with [CellChildren::cell]
+    pub fn into_group(self) -> GeneralGroup<'data> {
+        let cell = self.cell;
+        let mut entries = Vec::new();
+        if let Some(persistent) = self.persistent {
+            entries.push(
+                persistent
+                    .into_group(GroupType::CellPersistentChildren(cell))
+                    .into(),
+            );
+        }
+        if let Some(temporary) = self.temporary {
+            entries.push(
+                temporary
+                    .into_group(GroupType::CellTemporaryChildren(cell))
+                    .into(),
+            );
+        }
+        GeneralGroup {
+            group_type: GroupType::CellChildren(cell),
+            common: self.common,
+            entries,
+        }
+    }
+
+    /// Adds [record] to the end of the persistent or temporary children, going by whether it
+    /// is flagged as a persistent reference, making the group if there isn't one
+    pub fn push(&mut self, record: ChildRecord<'data>) {
+        let group = if is_persistent(&record) {
+            &mut self.persistent
+        } else {
+            &mut self.temporary
+        };
+        group
+            .get_or_insert_with(ReferenceGroup::new)
+            .records
+            .push(record);
+    }
+
+    /// The persistent children, then the temporary ones
+    pub fn records(&self) -> impl Iterator<Item = &ChildRecord<'data>> {
+        self.persistent
+            .iter()
+            .chain(self.temporary.iter())
+            .flat_map(|group| group.records.iter())
+    }
+}
+
+/// Whether [record] is flagged as a persistent reference, and so belongs with the persistent
+/// children of its cell
+pub fn is_persistent(record: &ChildRecord<'_>) -> bool {
+    record.common().flags.flags & record_flag::PERSISTENT_REFERENCE != 0
+}
+
+/// Checks that [group] has the structure that [CellChildren::from_group] reads, without copying
+/// it. Returns the id of the cell
+fn check_children(group: &GeneralGroup<'_>) -> Result<FormId, GroupStructureError> {
+    let cell = match group.group_type {
+        GroupType::CellChildren(cell) => cell,
+        group_type => return Err(wrong_group(6, group_type)),
+    };
+    let (mut persistent, mut temporary) = (false, false);
+    for entry in group.entries.iter() {
+        let group = match entry {
+            GroupEntry::Group(group) => group,
+            GroupEntry::Record(record) => {
+                return Err(GroupStructureError::UnexpectedRecord(
+                    record.type_name.to_string(),
+                ))
+            }
+            GroupEntry::Compressed(record) => {
+                return Err(GroupStructureError::UnexpectedRecord(
+                    record.type_name().to_string(),
+                ))
+            }
+        };
+        let seen = match group.group_type {
+            GroupType::CellPersistentChildren(id) if id == cell => &mut persistent,
+            GroupType::CellTemporaryChildren(id) if id == cell => &mut temporary,
+            GroupType::CellPersistentChildren(id) | GroupType::CellTemporaryChildren(id) => {
+                return Err(GroupStructureError::Orphan { parent: id })
+            }
+            group_type => return Err(GroupStructureError::UnexpectedGroup(group_type.code())),
+        };
+        if *seen {
+            return Err(GroupStructureError::UnexpectedGroup(
+                group.group_type.code(),
+            ));
+        }
+        *seen = true;
+        for entry in group.entries.iter() {
+            if let GroupEntry::Group(group) = entry {
+                return Err(GroupStructureError::UnexpectedGroup(
+                    group.group_type.code(),
+                ));
+            }
+        }
+    }
+    Ok(cell)
+}
+
+/// The children of a cell where a plugin holds them, see [Plugin::cell_children]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct CellChildrenRef<'a, 'data> {
+    cell: FormId,
+    group: &'a GeneralGroup<'data>,
+}
+impl<'a, 'data> CellChildrenRef<'a, 'data> {
+    /// Looks at the children within [group], which must have the structure that
+    /// [CellChildren::from_group] reads
+    pub fn new(
+        group: &'a GeneralGroup<'data>,
+    ) -> Result<CellChildrenRef<'a, 'data>, GroupStructureError> {
+        let cell = check_children(group)?;
+        Ok(CellChildrenRef { cell, group })
+    }
+
+    pub fn cell(self) -> FormId {
+        self.cell
+    }
+
+    /// The group of type 8
+    pub fn persistent(self) -> Option<&'a GeneralGroup<'data>> {
+        self.child(GroupType::CellPersistentChildren(self.cell))
+    }
+
+    /// The group of type 9
+    pub fn temporary(self) -> Option<&'a GeneralGroup<'data>> {
+        self.child(GroupType::CellTemporaryChildren(self.cell))
+    }
+
+    fn child(self, group_type: GroupType<'_>) -> Option<&'a GeneralGroup<'data>> {
+        self.group.entries.iter().find_map(|entry| match entry {
+            GroupEntry::Group(group) if group.group_type == group_type => Some(group),
+            _ => None,
+        })
+    }
+
+    /// The persistent children, then the temporary ones
+    pub fn records(self) -> impl Iterator<Item = &'a GroupEntry<'data>> {
+        self.persistent()
+            .into_iter()
+            .chain(self.temporary())
+            .flat_map(|group| group.entries.iter())
+    }
+
+    /// A copy of the children, to be changed and given back to [Plugin::set_cell_children]
+    pub fn to_children(self) -> CellChildren<'data> {
+        CellChildren::from_group(self.group.clone()).expect("The children were checked")
+    }
+}
+
+/// Mutable version of [CellChildrenRef], see [Plugin::cell_children_mut]
+#[derive(Debug, PartialEq)]
+pub struct CellChildrenMut<'a, 'data> {
+    cell: FormId,
+    group: &'a mut GeneralGroup<'data>,
+}
+impl<'a, 'data> CellChildrenMut<'a, 'data> {
+    /// Changes the children within [group], which must have the structure that
+    /// [CellChildren::from_group] reads
+    pub fn new(
+        group: &'a mut GeneralGroup<'data>,
+    ) -> Result<CellChildrenMut<'a, 'data>, GroupStructureError> {
+        let cell = check_children(group)?;
+        Ok(CellChildrenMut { cell, group })
+    }
+
+    pub fn cell(&self) -> FormId {
+        self.cell
+    }
+
+    /// Adds [record] to the end of the persistent or temporary children, see
+    /// [CellChildren::push]. A new group of persistent children goes before the temporary ones
+    pub fn push(&mut self, record: ChildRecord<'data>) {
+        let persistent = is_persistent(&record);
+        let group_type = if persistent {
+            GroupType::CellPersistentChildren(self.cell)
+        } else {
+            GroupType::CellTemporaryChildren(self.cell)
+        };
+        let entries = &mut self.group.entries;
+        let index = match entries
+            .iter()
+            .position(|entry| matches!(entry, GroupEntry::Group(x) if x.group_type == group_type))
+        {
+            Some(index) => index,
+            None => {
+                let index = if persistent { 0 } else { entries.len() };
+                let group = GeneralGroup {
+                    group_type,
+                    common: CommonGroupInfo::new(),
+                    entries: Vec::new(),
+                };
+                entries.insert(index, group.into());
+                index
+            }
+        };
+        if let GroupEntry::Group(group) = &mut entries[index] {
+            group.entries.push(record.into());
+        }
+    }
+}
+
+/// A CELL record along with its children
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cell<'data> {
+    pub record: ChildRecord<'data>,
+    pub children: Option<CellChildren<'data>>,
+}
+impl<'data> Cell<'data> {
+    pub fn formid(&self) -> FormId {
+        self.record.formid()
+    }
+
+    /// The grid coordinates of an exterior cell, from its XCLC. None for interior cells, and for
+    /// cells whose record can't be read
+    pub fn grid(&self) -> Option<Position<i32>> {
+        match &self.record {
+            ChildRecord::Record(record) => grid_of(record),
+            ChildRecord::Compressed(record) => {
+                let mut buffer = Vec::new();
+                grid_of(&record.decompress(&mut buffer).ok()?)
+            }
+        }
+    }
+
+    /// Adds [record] to the children of the cell, making the group of children if there isn't
+    /// one, see [CellChildren::push]
+    pub fn push_child(&mut self, record: ChildRecord<'data>) {
+        let cell = self.formid();
+        self.children
+            .get_or_insert_with(|| CellChildren::new(cell))
+            .push(record);
+    }
+
+    /// The record followed by the group of its children, labelled with the record's id
+    pub fn into_entries(self) -> Vec<GroupEntry<'data>> {
+        let cell = self.record.formid();
+        let mut entries = vec![self.record.into()];
+        if let Some(mut children) = self.children {
+            children.cell = cell;
+            entries.push(children.into_group().into());
+        }
+        entries
+    }
+}
+
+/// The grid coordinates from the XCLC of a CELL [record], see [Cell::grid]
+fn grid_of(record: &GeneralRecord<'_>) -> Option<Position<i32>> {
+    let field = record.fields.iter().find(|x| x.type_name == "XCLC")?;
+    let (data, x) = i32::parse(field.data).ok()?;
+    let (_, y) = i32::parse(data).ok()?;
+    Some(Position::new(x, y))
+}
+
+/// A CELL record along with the group of its children, where a plugin holds them
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct CellRef<'a, 'data> {
+    pub record: &'a GroupEntry<'data>,
+    pub children: Option<&'a GeneralGroup<'data>>,
+}
+impl<'a, 'data> CellRef<'a, 'data> {
+    pub fn formid(self) -> FormId {
+        self.record.formid().unwrap_or_else(|| FormId::new(0))
+    }
+
+    /// See [Cell::grid]
+    pub fn grid(self) -> Option<Position<i32>> {
+        match self.record {
+            GroupEntry::Record(record) => grid_of(record),
+            GroupEntry::Compressed(record) => {
+                let mut buffer = Vec::new();
+                grid_of(&record.decompress(&mut buffer).ok()?)
+            }
+            GroupEntry::Group(_) => None,
+        }
+    }
+
+    /// The children of the cell, failing if their group doesn't have the right structure
+    pub fn children(self) -> Result<Option<CellChildrenRef<'a, 'data>>, GroupStructureError> {
+        self.children.map(CellChildrenRef::new).transpose()
+    }
+}
+
+/// The CELL records of [entries], each along with the group of children after it. Other groups
+/// are skipped
+pub fn cell_refs<'a, 'data>(
+    entries: &'a [GroupEntry<'data>],
+) -> impl Iterator<Item = CellRef<'a, 'data>> {
+    entries
+        .iter()
+        .enumerate()
+        .filter(|(_, entry)| !matches!(entry, GroupEntry::Group(_)))
+        .map(move |(index, record)| CellRef {
+            record,
+            children: match (entries.get(index + 1), record.formid()) {
+                (Some(GroupEntry::Group(group)), Some(cell))
+                    if group.group_type == GroupType::CellChildren(cell) =>
+                {
+                    Some(group)
+                }
+                _ => None,
+            },
+        })
+}
+
+/// Reads the CELL records of [entries] along with the group of children after each
+pub fn cells_from_entries(
+    entries: Vec<GroupEntry<'_>>,
+) -> Result<Vec<Cell<'_>>, GroupStructureError> {
+    let mut cells: Vec<Cell<'_>> = Vec::new();
+    for entry in entries {
+        match ChildRecord::from_entry(entry) {
+            Ok(record) if record.type_name() == "CELL" => cells.push(Cell {
+                record,
+                children: None,
+            }),
+            Ok(record) => {
+                return Err(GroupStructureError::UnexpectedRecord(
+                    record.type_name().to_string(),
+                ))
+            }
+            Err(group) => {
+                let children = CellChildren::from_group(group)?;
+                match cells.last_mut() {
+                    Some(cell) if cell.children.is_none() && cell.formid() == children.cell => {
+                        cell.children = Some(children)
+                    }
+                    _ => {
+                        return Err(GroupStructureError::Orphan {
+                            parent: children.cell,
+                        })
+                    }
+                }
+            }
+        }
+    }
+    Ok(cells)
+}
+
+/// The entries of [cells], each followed by its children
+pub fn cells_into_entries(cells: Vec<Cell<'_>>) -> Vec<GroupEntry<'_>> {
+    cells.into_iter().flat_map(Cell::into_entries).collect()
+}
+
+/// The interior block that the cell with [id] belongs in, the last decimal digit of its object
+/// index
+pub fn interior_block_of(id: FormId) -> u32 {
+    id.object_index() % 10
+}
+
+/// The interior sub-block that the cell with [id] belongs in, the second to last decimal digit of
+/// its object index
+pub fn interior_sub_block_of(id: FormId) -> u32 {
+    id.object_index() / 10 % 10
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct InteriorSubBlock<'data> {
+    pub number: u32,
+    pub common: CommonGroupInfo,
+    pub cells: Vec<Cell<'data>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct InteriorBlock<'data> {
+    pub number: u32,
+    pub common: CommonGroupInfo,
+    pub sub_blocks: Vec<InteriorSubBlock<'data>>,
+}
+impl<'data> InteriorBlock<'data> {
+    fn from_group(group: GeneralGroup<'data>) -> Result<InteriorBlock<'data>, GroupStructureError> {
+        let number = match group.group_type {
+            GroupType::InteriorCellBlock(number) => number,
+            group_type => return Err(wrong_group(2, group_type)),
+        };
+        let mut sub_blocks = Vec::new();
+        for entry in group.entries {
+            let group = expect_group(entry)?;
+            match group.group_type {
+                GroupType::InteriorSubCellBlock(number) => sub_blocks.push(InteriorSubBlock {
+                    number,
+                    common: group.common,
+                    cells: cells_from_entries(group.entries)?,
+                }),
+                group_type => return Err(wrong_group(3, group_type)),
+            }
+        }
+        Ok(InteriorBlock {
+            number,
+            common: group.common,
+            sub_blocks,
+        })
+    }
+
+    fn into_group(self) -> GeneralGroup<'data> {
+        GeneralGroup {
+            group_type: GroupType::InteriorCellBlock(self.number),
+            common: self.common,
+            entries: self
+                .sub_blocks
+                .into_iter()
+                .map(|sub_block| {
+                    GroupEntry::Group(GeneralGroup {
+                        group_type: GroupType::InteriorSubCellBlock(sub_block.number),
+                        common: sub_block.common,
+                        entries: cells_into_entries(sub_block.cells),
+                    })
+                })
+                .collect(),
+        }
+    }
+}
+
+/// The CELL top group, with the interior cells in their blocks and sub-blocks
+#[derive(Debug, Clone, PartialEq)]
+pub struct InteriorCells<'data> {
+    pub common: CommonGroupInfo,
+    pub blocks: Vec<InteriorBlock<'data>>,
+}
+impl<'data> InteriorCells<'data> {
+    pub fn new() -> InteriorCells<'data> {
+        InteriorCells {
+            common: CommonGroupInfo::new(),
+            blocks: Vec::new(),
+        }
+    }
+
+    /// Reads the cells from the CELL top group, which must hold nothing but blocks. Cells that
+    /// are in the wrong block are read as they are, see [InteriorCells::misplaced]
+    pub fn from_top(group: TopGroup<'data>) -> Result<InteriorCells<'data>, GroupStructureError> {
+        let mut blocks = Vec::new();
+        for entry in group.entries {
+            blocks.push(InteriorBlock::from_group(expect_group(entry)?)?);
+        }
+        Ok(InteriorCells {
+            common: group.common,
+            blocks,
+        })
+    }
+
+    pub fn into_top(self) -> TopGroup<'data> {
+        TopGroup {
+            label: b"CELL".as_bstr(),
+            common: self.common,
+            entries: self
+                .blocks
+                .into_iter()
+                .map(|block| GroupEntry::Group(block.into_group()))
+                .collect(),
+        }
+    }
+
+    /// Every cell, in the order they are written
+    pub fn cells(&self) -> impl Iterator<Item = &Cell<'data>> {
+        self.blocks
+            .iter()
+            .flat_map(|block| block.sub_blocks.iter())
+            .flat_map(|sub_block| sub_block.cells.iter())
+    }
+
+    /// The cell with [id], looking in every block so that misplaced cells are found too
+    pub fn find(&self, id: FormId) -> Option<&Cell<'data>> {
+        self.cells().find(|cell| cell.formid() == id)
+    }
+
+    /// Mutable version of [InteriorCells::find]
+    pub fn find_mut(&mut self, id: FormId) -> Option<&mut Cell<'data>> {
+        self.blocks
+            .iter_mut()
+            .flat_map(|block| block.sub_blocks.iter_mut())
+            .flat_map(|sub_block| sub_block.cells.iter_mut())
+            .find(|cell| cell.formid() == id)
+    }
+
+    /// Ids of the cells that aren't in the block and sub-block that their id calls for, such as
+    /// after being renumbered
+    pub fn misplaced(&self) -> Vec<FormId> {
+        let mut misplaced = Vec::new();
+        for block in self.blocks.iter() {
+            for sub_block in block.sub_blocks.iter() {
+                misplaced.extend(sub_block.cells.iter().map(Cell::formid).filter(|id| {
+                    interior_block_of(*id) != block.number
+                        || interior_sub_block_of(*id) != sub_block.number
+                }));
+            }
+        }
+        misplaced
+    }
+
+    /// Adds [cell] to the end of the sub-block that its id calls for, making the block and
+    /// sub-block if they don't exist, in order of their numbers
+    pub fn insert(&mut self, cell: Cell<'data>) {
+        let id = cell.formid();
+        let number = interior_block_of(id);
+        let index = match self.blocks.iter().position(|x| x.number >= number) {
+            Some(index) if self.blocks[index].number == number => index,
+            position => {
+                let index = position.unwrap_or(self.blocks.len());
+                let block = InteriorBlock {
+                    number,
+                    common: CommonGroupInfo::new(),
+                    sub_blocks: Vec::new(),
+                };
+                self.blocks.insert(index, block);
+                index
+            }
+        };
+        let sub_blocks = &mut self.blocks[index].sub_blocks;
+        let number = interior_sub_block_of(id);
+        let index = match sub_blocks.iter().position(|x| x.number >= number) {
+            Some(index) if sub_blocks[index].number == number => index,
+            position => {
+                let index = position.unwrap_or(sub_blocks.len());
+                let sub_block = InteriorSubBlock {
+                    number,
+                    common: CommonGroupInfo::new(),
+                    cells: Vec::new(),
+                };
+                sub_blocks.insert(index, sub_block);
+                index
+            }
+        };
+        sub_blocks[index].cells.push(cell);
+    }
+
+    /// Moves the misplaced cells into the blocks and sub-blocks that their ids call for, then
+    /// removes the sub-blocks and blocks left empty. Returns how many cells were moved
+    pub fn rebucket(&mut self) -> usize {
+        let mut moved = Vec::new();
+        for block in self.blocks.iter_mut() {
+            for sub_block in block.sub_blocks.iter_mut() {
+                let (number, sub_number) = (block.number, sub_block.number);
+                let (stay, leave) = sub_block.cells.drain(..).partition(|cell| {
+                    interior_block_of(cell.formid()) == number
+                        && interior_sub_block_of(cell.formid()) == sub_number
+                });
+                sub_block.cells = stay;
+                moved.extend::<Vec<_>>(leave);
+            }
+        }
+        let count = moved.len();
+        for cell in moved {
+            self.insert(cell);
+        }
+        for block in self.blocks.iter_mut() {
+            block.sub_blocks.retain(|x| !x.cells.is_empty());
+        }
+        self.blocks.retain(|x| !x.sub_blocks.is_empty());
+        count
+    }
+}
+
+/// The number of an interior block or sub-block group
+fn block_number(entry: &GroupEntry<'_>) -> Option<u32> {
+    match entry {
+        GroupEntry::Group(group) => match group.group_type {
+            GroupType::InteriorCellBlock(number) | GroupType::InteriorSubCellBlock(number) => {
+                Some(number)
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// The block or sub-block of [group_type] with [number] within [entries], making it before the
+/// first one with a higher number if there isn't one
+fn numbered_group<'a, 'data>(
+    entries: &'a mut Vec<GroupEntry<'data>>,
+    group_type: GroupType<'data>,
+    number: u32,
+) -> &'a mut GeneralGroup<'data> {
+    let position = entries
+        .iter()
+        .position(|entry| block_number(entry).map_or(false, |x| x >= number));
+    let index = match position {
+        Some(index) if matches!(&entries[index], GroupEntry::Group(x) if x.group_type == group_type) => {
+            index
+        }
+        position => {
+            let index = position.unwrap_or(entries.len());
+            let group = GeneralGroup {
+                group_type,
+                common: CommonGroupInfo::new(),
+                entries: Vec::new(),
+            };
+            entries.insert(index, group.into());
+            index
+        }
+    };
+    match &mut entries[index] {
+        GroupEntry::Group(group) => group,
+        _ => unreachable!("The entry was found or made as a group"),
+    }
+}
+
+/// The CELL top group where a plugin holds it, to add interior cells to without reading the
+/// others, see [Plugin::interior_cells_mut]
+#[derive(Debug, PartialEq)]
+pub struct InteriorCellsMut<'a, 'data> {
+    top: &'a mut TopGroup<'data>,
+}
+impl<'a, 'data> InteriorCellsMut<'a, 'data> {
+    /// Adds [cell] where [InteriorCells::insert] would
+    pub fn insert(&mut self, cell: Cell<'data>) {
+        let id = cell.formid();
+        let number = interior_block_of(id);
+        let block = numbered_group(
+            &mut self.top.entries,
+            GroupType::InteriorCellBlock(number),
+            number,
+        );
+        let number = interior_sub_block_of(id);
+        let sub_block = numbered_group(
+            &mut block.entries,
+            GroupType::InteriorSubCellBlock(number),
+            number,
+        );
+        sub_block.entries.extend(cell.into_entries());
+    }
+}
+
+fn is_cell(entry: &GroupEntry<'_>) -> bool {
+    entry.type_name().map_or(false, |x| x == "CELL")
+}
+
+impl<'data> Plugin<'data> {
+    /// The CELL with [id], interior or exterior, along with its children where the plugin holds
+    /// them. Found through the index, or searched for if the record is compressed
+    pub fn cell(&self, id: FormId) -> Option<CellRef<'_, 'data>> {
+        let (entries, index) = self.entries_around(id)?;
+        cell_refs(&entries[index..])
+            .next()
+            .filter(|cell| is_cell(cell.record))
+    }
+
+    /// The CELL top group, to add interior cells to. None if the plugin has no CELL group
+    pub fn interior_cells_mut(&mut self) -> Option<InteriorCellsMut<'_, 'data>> {
+        self.unknown_top_mut(b"CELL")
+            .map(|top| InteriorCellsMut { top })
+    }
+
+    /// A copy of the interior cells of the CELL top group, to be changed as a whole and given
+    /// back to [Plugin::set_interior_cells]. None if the plugin has none. See [Plugin::cell] to
+    /// look at a cell without copying the group
+    pub fn interior_cells(&self) -> Result<Option<InteriorCells<'data>>, GroupStructureError> {
+        self.unknown_top(b"CELL")
+            .map(|group| InteriorCells::from_top(group.clone()))
+            .transpose()
+    }
+
+    /// Replaces the CELL top group with [cells], adding it to the end if there isn't one
+    pub fn set_interior_cells(&mut self, cells: InteriorCells<'data>) {
+        self.set_unknown_top(cells.into_top())
+    }
+
+    /// The children of the CELL with the id [cell], as they are held. None if it has no group of
+    /// children
+    pub fn cell_children(
+        &self,
+        cell: FormId,
+    ) -> Result<Option<CellChildrenRef<'_, 'data>>, GroupStructureError> {
+        self.find_group(&GroupType::CellChildren(cell))
+            .map(CellChildrenRef::new)
+            .transpose()
+    }
+
+    /// Mutable version of [Plugin::cell_children]
+    pub fn cell_children_mut(
+        &mut self,
+        cell: FormId,
+    ) -> Result<Option<CellChildrenMut<'_, 'data>>, GroupStructureError> {
+        self.find_group_mut(&GroupType::CellChildren(cell))
+            .map(CellChildrenMut::new)
+            .transpose()
+    }
+
+    /// Replaces the group of the children of [children]'s cell with them. Returns false if the
+    /// cell has no group of children to replace
+    pub fn set_cell_children(&mut self, children: CellChildren<'data>) -> bool {
+        match self.find_group_mut(&GroupType::CellChildren(children.cell)) {
+            Some(group) => {
+                *group = children.into_group();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        context::ParseContext,
+        fixtures,
+        groups::{common::FromGeneralGroup, Group},
+        records::common::{CommonRecordInfo, CompressedRecord},
+        util::Writable,
+        Top,
+    };
+    use bstr::ByteSlice;
+
+    fn general(type_name: &'static [u8], id: u32, flags: u32) -> GeneralRecord<'static> {
+        let mut common = CommonRecordInfo::test_default();
+        common.flags.flags = flags;
+        common.id = id;
+        GeneralRecord {
+            type_name: type_name.as_bstr(),
+            common,
+            fields: Vec::new(),
+        }
+    }
+
+    fn record(type_name: &'static [u8], id: u32, flags: u32) -> Vec<u8> {
+        general(type_name, id, flags).to_vec().unwrap()
+    }
+
+    fn group(label: u32, group_type: i32, contents: &[Vec<u8>]) -> Vec<u8> {
+        let contents = contents.concat();
+        let mut data = b"GRUP".to_vec();
+        data.extend_from_slice(&(24 + contents.len() as u32).to_le_bytes());
+        data.extend_from_slice(&label.to_le_bytes());
+        data.extend_from_slice(&group_type.to_le_bytes());
+        data.extend_from_slice(&[0; 8]);
+        data.extend_from_slice(&contents);
+        data
+    }
+
+    #[test]
+    fn test_cell_children() {
+        let persistent = record_flag::PERSISTENT_REFERENCE;
+        let children = group(
+            0x801,
+            6,
+            &[
+                group(0x801, 8, &[record(b"ACHR", 0x802, persistent)]),
+                group(
+                    0x801,
+                    9,
+                    &[record(b"REFR", 0x803, 0), record(b"LAND", 0x804, 0)],
+                ),
+            ],
+        );
+        let mut header = Plugin::new("Base.esm");
+        header.refresh_header();
+        let data = [
+            header.to_vec().unwrap(),
+            group(
+                u32::from_le_bytes(*b"CELL"),
+                0,
+                &[group(
+                    1,
+                    2,
+                    &[group(0, 3, &[record(b"CELL", 0x801, 0), children])],
+                )],
+            ),
+        ]
+        .concat();
+        let mut ctx = ParseContext::default();
+        let mut plugin = Plugin::parse("Base.esm", &data, &mut ctx).unwrap();
+
+        assert_eq!(plugin.cell_children(FormId::new(0x805)), Ok(None));
+        let children = plugin.cell_children(FormId::new(0x801)).unwrap().unwrap();
+        let ids = |children: CellChildrenRef<'_, '_>| -> Vec<u32> {
+            children
+                .records()
+                .filter_map(|x| x.formid())
+                .map(|x| x.id)
+                .collect()
+        };
+        assert_eq!(ids(children), vec![0x802, 0x803, 0x804]);
+        assert_eq!(children.persistent().unwrap().entries.len(), 1);
+        let mut children = children.to_children();
+
+        // Written back unchanged, the plugin is as it was read
+        assert!(plugin.set_cell_children(children.clone()));
+        assert_eq!(plugin.to_vec().unwrap(), data);
+
+        children.push(general(b"REFR", 0x806, persistent).into());
+        children.temporary = None;
+        assert!(plugin.set_cell_children(children.clone()));
+        let found = plugin.cell_children(FormId::new(0x801)).unwrap().unwrap();
+        assert_eq!(ids(found), vec![0x802, 0x806]);
+        assert!(found.temporary().is_none());
+        match &plugin.tops()[1] {
+            Top::Group(Group::UnknownTop(top)) => {
+                let mut entries = top.entries.clone();
+                let sub_block = match entries.pop() {
+                    Some(GroupEntry::Group(block)) => block.entries[0].clone(),
+                    entry => panic!("Expected a block, got {:?}", entry),
+                };
+                let cells = match sub_block {
+                    GroupEntry::Group(sub_block) => cells_from_entries(sub_block.entries).unwrap(),
+                    entry => panic!("Expected a sub-block, got {:?}", entry),
+                };
+                assert_eq!(cells.len(), 1);
+                assert_eq!(cells[0].formid(), FormId::new(0x801));
+                assert_eq!(cells[0].children, Some(children));
+            }
+            top => panic!("Expected the CELL group, got {:?}", top),
+        }
+    }
+
+    #[test]
+    fn test_cell_children_mut() {
+        fn group<'data>(
+            group_type: GroupType<'data>,
+            entries: Vec<GroupEntry<'data>>,
+        ) -> GroupEntry<'data> {
+            GroupEntry::Group(GeneralGroup {
+                group_type,
+                common: CommonGroupInfo::new(),
+                entries,
+            })
+        }
+        let (cell, other) = (FormId::new(0x801), FormId::new(0x811));
+        let temporary = group(
+            GroupType::CellTemporaryChildren(cell),
+            vec![general(b"REFR", 0x803, 0).into()],
+        );
+        let compressed = CompressedRecord::compress(&general(b"CELL", 0x811, 0), 9).unwrap();
+        let sub_block = group(
+            GroupType::InteriorSubCellBlock(4),
+            vec![
+                general(b"CELL", 0x801, 0).into(),
+                group(GroupType::CellChildren(cell), vec![temporary]),
+                compressed.into(),
+                group(GroupType::CellChildren(other), Vec::new()),
+            ],
+        );
+        let block = group(GroupType::InteriorCellBlock(9), vec![sub_block]);
+        let top = fixtures::top(b"CELL", vec![block]);
+        let mut plugin = fixtures::plugin("Base.esm", &[], vec![top]);
+
+        // A persistent reference gets a group of its own, before the temporary one
+        let mut children = plugin.cell_children_mut(cell).unwrap().unwrap();
+        let persistent = record_flag::PERSISTENT_REFERENCE;
+        children.push(general(b"REFR", 0x802, persistent).into());
+        children.push(general(b"REFR", 0x804, 0).into());
+        let children = plugin.cell_children(cell).unwrap().unwrap();
+        let ids: Vec<u32> = children
+            .records()
+            .filter_map(|x| x.formid())
+            .map(|x| x.id)
+            .collect();
+        assert_eq!(ids, vec![0x802, 0x803, 0x804]);
+        let found = plugin.find_group(&GroupType::CellChildren(cell)).unwrap();
+        let types: Vec<i32> = found
+            .entries
+            .iter()
+            .filter_map(|entry| match entry {
+                GroupEntry::Group(group) => Some(group.group_type.code()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(types, vec![8, 9]);
+        let found = plugin.find_group(&GroupType::CellTemporaryChildren(cell));
+        assert_eq!(found.unwrap().entries.len(), 2);
+
+        // Compressed records aren't indexed, so the children of the cell are searched for
+        assert!(plugin.cell_children(other).unwrap().is_some());
+        assert_eq!(plugin.cell_children(FormId::new(0x812)), Ok(None));
+    }
+
+    #[test]
+    fn test_cell_structure() {
+        fn parse(data: &[u8]) -> GeneralGroup<'_> {
+            GeneralGroup::parse(data).unwrap().1
+        }
+        // A record directly within the children
+        let group_data = group(0x801, 6, &[record(b"REFR", 0x802, 0)]);
+        assert_eq!(
+            CellChildren::from_group(parse(&group_data)),
+            Err(GroupStructureError::UnexpectedRecord("REFR".to_string()))
+        );
+        // Temporary children of another cell
+        let group_data = group(0x801, 6, &[group(0x900, 9, &[])]);
+        assert_eq!(
+            CellChildren::from_group(parse(&group_data)),
+            Err(GroupStructureError::Orphan {
+                parent: FormId::new(0x900)
+            })
+        );
+        let group_data = group(0x801, 8, &[]);
+        assert_eq!(
+            CellChildren::from_group(parse(&group_data)),
+            Err(GroupStructureError::GroupType {
+                expected: 6,
+                found: 8
+            })
+        );
+
+        let group_data = group(0x801, 6, &[]);
+        let entries = vec![
+            GroupEntry::Group(parse(&group_data)),
+            GroupEntry::Record(general(b"CELL", 0x801, 0)),
+        ];
+        assert_eq!(
+            cells_from_entries(entries),
+            Err(GroupStructureError::Orphan {
+                parent: FormId::new(0x801)
+            })
+        );
+    }
+
+    #[test]
+    fn test_interior_cells() {
+        let cell_label = u32::from_le_bytes(*b"CELL");
+        // 0x801 is object 2049, 0x80B is 2059 and 0x802 is 2050
+        let data = [
+            group(0, 2, &[group(5, 3, &[record(b"CELL", 0x802, 0)])]),
+            group(
+                9,
+                2,
+                &[
+                    group(
+                        4,
+                        3,
+                        &[
+                            record(b"CELL", 0x801, 0),
+                            group(0x801, 6, &[group(0x801, 9, &[record(b"REFR", 0x803, 0)])]),
+                        ],
+                    ),
+                    group(5, 3, &[record(b"CELL", 0x80B, 0)]),
+                ],
+            ),
+        ];
+        let mut header = Plugin::new("Base.esm");
+        header.refresh_header();
+        let data = [header.to_vec().unwrap(), group(cell_label, 0, &data)].concat();
+        let mut ctx = ParseContext::default();
+        let mut plugin = Plugin::parse("Base.esm", &data, &mut ctx).unwrap();
+
+        let mut cells = plugin.interior_cells().unwrap().unwrap();
+        let ids: Vec<u32> = cells.cells().map(|x| x.formid().id).collect();
+        assert_eq!(ids, vec![0x802, 0x801, 0x80B]);
+        assert!(cells.misplaced().is_empty());
+        assert!(cells.find(FormId::new(0x801)).unwrap().children.is_some());
+        plugin.set_interior_cells(cells.clone());
+        assert_eq!(plugin.to_vec().unwrap(), data);
+
+        // Object 2060 belongs in block 0, sub-block 6
+        let cell = &mut cells.blocks[1].sub_blocks[1].cells[0];
+        cell.record.common_mut().id = 0x80C;
+        assert_eq!(cells.misplaced(), vec![FormId::new(0x80C)]);
+        assert_eq!(cells.rebucket(), 1);
+        assert!(cells.misplaced().is_empty());
+        let numbers = |cells: &InteriorCells<'_>| -> Vec<(u32, Vec<u32>)> {
+            cells
+                .blocks
+                .iter()
+                .map(|block| {
+                    let sub_blocks = block.sub_blocks.iter().map(|x| x.number).collect();
+                    (block.number, sub_blocks)
+                })
+                .collect()
+        };
+        assert_eq!(numbers(&cells), vec![(0, vec![5, 6]), (9, vec![4])]);
+
+        // Object 2067 goes in block 7, sub-block 6
+        cells.insert(Cell {
+            record: general(b"CELL", 0x813, 0).into(),
+            children: None,
+        });
+        assert_eq!(
+            numbers(&cells),
+            vec![(0, vec![5, 6]), (7, vec![6]), (9, vec![4])]
+        );
+        assert_eq!(cells.rebucket(), 0);
+        plugin.set_interior_cells(cells.clone());
+        assert_eq!(plugin.interior_cells().unwrap(), Some(cells.clone()));
+
+        // Cells are looked at and added to where they are held
+        let cell = plugin.cell(FormId::new(0x801)).unwrap();
+        assert_eq!(cell.children().unwrap().unwrap().records().count(), 1);
+        assert!(plugin.cell(FormId::new(0x80C)).unwrap().children.is_none());
+        assert!(plugin.cell(FormId::new(0x803)).is_none());
+        assert!(plugin.cell(FormId::new(0x900)).is_none());
+        // Object 2069 goes in block 9, sub-block 6, and 2047 in block 7, sub-block 4
+        for id in [0x815, 0x7FF] {
+            let cell = || Cell {
+                record: general(b"CELL", id, 0).into(),
+                children: None,
+            };
+            plugin.interior_cells_mut().unwrap().insert(cell());
+            cells.insert(cell());
+        }
+        assert_eq!(
+            numbers(&cells),
+            vec![(0, vec![5, 6]), (7, vec![4, 6]), (9, vec![4, 6])]
+        );
+        assert_eq!(plugin.interior_cells().unwrap(), Some(cells));
+    }
+
+    #[test]
+    fn test_interior_structure() {
+        let cell_label = u32::from_le_bytes(*b"CELL");
+        fn parse(data: &[u8]) -> Result<InteriorCells<'_>, GroupStructureError> {
+            let group = GeneralGroup::parse(data).unwrap().1;
+            InteriorCells::from_top(TopGroup::from_general_group(group))
+        }
+        assert_eq!(
+            parse(&group(cell_label, 0, &[record(b"CELL", 0x801, 0)])),
+            Err(GroupStructureError::UnexpectedRecord("CELL".to_string()))
+        );
+        assert_eq!(
+            parse(&group(cell_label, 0, &[group(1, 3, &[])])),
+            Err(GroupStructureError::GroupType {
+                expected: 2,
+                found: 3
+            })
+        );
+        assert_eq!(
+            parse(&group(cell_label, 0, &[group(1, 2, &[group(0, 2, &[])])])),
+            Err(GroupStructureError::GroupType {
+                expected: 3,
+                found: 2
+            })
+        );
+    }
+}
@@ -1,30 +1,60 @@
 use crate::{
+    analysis::RECORDH_SIZE,
+    context::{ParseContext, Severity},
+    error::{ErrorCode, VivecError},
     impl_static_data_size,
-    parse::{tag, take, PResult, Parse, ParseError},
+    parse::{many_indexed, tag, take, PResult, Parse, ParseError},
     records::common::{
-        FormId, FromRecord, FromRecordError, GeneralRecord, RecordName, TypeNamed,
-        VersionControlInfo,
+        record_flag, CommonRecordInfo, CompressedRecord, FormId, FormIdVisitor, FromRecord,
+        FromRecordError, GeneralRecord, RecordName, TypeNamed, VersionControlInfo,
     },
-    util::{byte, DataSize, Position, Writable},
+    records::{Record, RecordMut},
+    util::{byte, DataSize, Position, Writable, WriteError},
 };
 use bstr::{BStr, ByteSlice};
 use derive_more::From;
-use std::io::Write;
+use std::{
+    convert::TryFrom,
+    fmt::{Display, Formatter},
+    io::Write,
+};
 
 pub const GROUPH_SIZE: usize = 24;
 
+/// The part of a group's header after its type and label
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub struct CommonGroupInfo {
-    version_control_info: VersionControlInfo,
-    /// Values are different than in records
-    /// All top-level groups have a 0 here, except for CELL, which can have 1 in some addons.
-    /// Topic children have single-byte values or 0xcc..
-    /// Interior cells have 0 or 0cc.. Some addons have value of 1
-    /// Some cell/world related groups have a wde array of small to large values that are not form ids. May be 0xCC..
-    unknown: u32,
+    pub version_control_info: VersionControlInfo,
+    /// Values are different than in records, and what they mean isn't known. By group type:
+    ///  - Top groups (0) have 0, except for CELL, which can have 1 in some addons
+    ///  - Interior cell blocks and sub-blocks (2, 3) have 0 or 0xCC.., and 1 in some addons
+    ///  - Topic children (7) have single-byte values or 0xCC..
+    ///  - Other cell and world related groups (1, 4, 5, 6, 8, 9) have a wide range of small to
+    ///    large values that aren't form ids, or 0xCC..
+    ///
+    /// The game doesn't seem to read it, so 0 is fine for new groups
+    pub unknown: u32,
+}
+impl CommonGroupInfo {
+    /// Info for a new group, without any version control info
+    pub fn new() -> CommonGroupInfo {
+        CommonGroupInfo::from_parts(VersionControlInfo::new(0, 0, 0, 0), 0)
+    }
+
+    pub fn from_parts(version_control_info: VersionControlInfo, unknown: u32) -> CommonGroupInfo {
+        CommonGroupInfo {
+            version_control_info,
+            unknown,
+        }
+    }
+}
+impl Default for CommonGroupInfo {
+    fn default() -> CommonGroupInfo {
+        CommonGroupInfo::new()
+    }
 }
 impl Writable for CommonGroupInfo {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -33,12 +63,349 @@ impl Writable for CommonGroupInfo {
     }
 }
 
+/// A record or subgroup within a group
+#[derive(Debug, Clone, PartialEq, From)]
+pub enum GroupEntry<'data> {
+    Record(GeneralRecord<'data>),
+    Compressed(CompressedRecord<'data>),
+    Group(GeneralGroup<'data>),
+}
+impl<'data> GroupEntry<'data> {
+    /// The id of the record, if this is one
+    pub fn formid(&self) -> Option<FormId> {
+        match self {
+            GroupEntry::Record(record) => Some(FormId::new(record.common.id)),
+            GroupEntry::Compressed(record) => Some(FormId::new(record.common.id)),
+            GroupEntry::Group(_) => None,
+        }
+    }
+
+    /// The type of the record, if this is one
+    pub fn type_name(&self) -> Option<&'data BStr> {
+        match self {
+            GroupEntry::Record(record) => Some(record.type_name),
+            GroupEntry::Compressed(record) => Some(record.type_name()),
+            GroupEntry::Group(_) => None,
+        }
+    }
+}
+impl<'data> Parse<'data> for GroupEntry<'data> {
+    fn parse(data: &'data [u8]) -> PResult<Self> {
+        let (_, header) = take(data, 12)?;
+        if &header[..4] == b"GRUP" {
+            let (data, group) = GeneralGroup::parse(data)?;
+            return Ok((data, group.into()));
+        }
+        let flags = u32::from_le_bytes(byte::as_4_bytes(&header[8..12]));
+        if flags & record_flag::COMPRESSED != 0 {
+            let (data, record) = CompressedRecord::parse(data)?;
+            Ok((data, record.into()))
+        } else {
+            let (data, record) = GeneralRecord::parse(data)?;
+            Ok((data, record.into()))
+        }
+    }
+}
+impl DataSize for GroupEntry<'_> {
+    fn data_size(&self) -> usize {
+        match self {
+            GroupEntry::Record(x) => x.data_size(),
+            GroupEntry::Compressed(x) => x.data_size(),
+            GroupEntry::Group(x) => x.data_size(),
+        }
+    }
+}
+impl Writable for GroupEntry<'_> {
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
+    where
+        T: Write,
+    {
+        match self {
+            GroupEntry::Record(x) => x.write_to(w),
+            GroupEntry::Compressed(x) => x.write_to(w),
+            GroupEntry::Group(x) => x.write_to(w),
+        }
+    }
+}
+
+/// A record held below the top groups, such as a reference placed in a cell. Their fields aren't
+/// converted, and some of them, like navmeshes, are usually compressed
+#[derive(Debug, Clone, PartialEq, From)]
+pub enum ChildRecord<'data> {
+    Record(GeneralRecord<'data>),
+    Compressed(CompressedRecord<'data>),
+}
+impl<'data> ChildRecord<'data> {
+    pub fn common(&self) -> &CommonRecordInfo {
+        match self {
+            ChildRecord::Record(record) => &record.common,
+            ChildRecord::Compressed(record) => &record.common,
+        }
+    }
+
+    pub fn common_mut(&mut self) -> &mut CommonRecordInfo {
+        match self {
+            ChildRecord::Record(record) => &mut record.common,
+            ChildRecord::Compressed(record) => &mut record.common,
+        }
+    }
+
+    pub fn formid(&self) -> FormId {
+        FormId::new(self.common().id)
+    }
+
+    /// The record held by [entry], or the group given back
+    pub fn from_entry(entry: GroupEntry<'data>) -> Result<ChildRecord<'data>, GeneralGroup<'data>> {
+        match entry {
+            GroupEntry::Record(record) => Ok(record.into()),
+            GroupEntry::Compressed(record) => Ok(record.into()),
+            GroupEntry::Group(group) => Err(group),
+        }
+    }
+}
+impl<'data> TypeNamed<'data> for ChildRecord<'data> {
+    fn type_name(&self) -> &'data BStr {
+        match self {
+            ChildRecord::Record(record) => record.type_name(),
+            ChildRecord::Compressed(record) => record.type_name(),
+        }
+    }
+}
+impl<'data> From<ChildRecord<'data>> for GroupEntry<'data> {
+    fn from(record: ChildRecord<'data>) -> GroupEntry<'data> {
+        match record {
+            ChildRecord::Record(record) => record.into(),
+            ChildRecord::Compressed(record) => record.into(),
+        }
+    }
+}
+
+/// A group whose contents don't have the structure that its type calls for
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum GroupStructureError {
+    /// A group of type [found] is where one of type [expected] should be
+    GroupType { expected: i32, found: i32 },
+    /// A [0] record is where it can't be
+    UnexpectedRecord(String),
+    /// A group of type [0] is where it can't be
+    UnexpectedGroup(i32),
+    /// The group of the children of [parent] isn't right after its record
+    Orphan { parent: FormId },
+}
+impl Display for GroupStructureError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GroupStructureError::GroupType { expected, found } => write!(
+                f,
+                "Found a group of type {} where one of type {} should be",
+                found, expected
+            ),
+            GroupStructureError::UnexpectedRecord(type_name) => {
+                write!(f, "Found a {} record where it can't be", type_name)
+            }
+            GroupStructureError::UnexpectedGroup(group_type) => {
+                write!(f, "Found a group of type {} where it can't be", group_type)
+            }
+            GroupStructureError::Orphan { parent } => write!(
+                f,
+                "The children of [{}] aren't right after their parent",
+                parent
+            ),
+        }
+    }
+}
+impl std::error::Error for GroupStructureError {}
+impl VivecError for GroupStructureError {
+    fn code(&self) -> ErrorCode {
+        ErrorCode::GroupStructure
+    }
+}
+
+/// A group of [found] where one of the type numbered [expected] should be
+pub(crate) fn wrong_group(expected: i32, found: GroupType<'_>) -> GroupStructureError {
+    GroupStructureError::GroupType {
+        expected,
+        found: found.code(),
+    }
+}
+
+/// The group of [entry], failing if it is a record
+pub(crate) fn expect_group(entry: GroupEntry<'_>) -> Result<GeneralGroup<'_>, GroupStructureError> {
+    match ChildRecord::from_entry(entry) {
+        Ok(record) => Err(GroupStructureError::UnexpectedRecord(
+            record.type_name().to_string(),
+        )),
+        Err(group) => Ok(group),
+    }
+}
+
+/// Finds the first group of [group_type] within [entries], searching within each group after it.
+/// Pushes the position of the group within each group down to it onto [path], and returns
+/// whether it was found
+pub fn find_group_path(
+    entries: &[GroupEntry<'_>],
+    group_type: &GroupType<'_>,
+    path: &mut Vec<usize>,
+) -> bool {
+    for (index, entry) in entries.iter().enumerate() {
+        if let GroupEntry::Group(group) = entry {
+            path.push(index);
+            if group.group_type == *group_type || find_group_path(&group.entries, group_type, path)
+            {
+                return true;
+            }
+            path.pop();
+        }
+    }
+    false
+}
+
+/// Finds the record with [id] within [entries] or the groups within them, pushing the position
+/// of each group down to it onto [path], and then that of the record. Returns false if there is
+/// none
+pub fn find_record_path(entries: &[GroupEntry<'_>], id: FormId, path: &mut Vec<usize>) -> bool {
+    for (index, entry) in entries.iter().enumerate() {
+        path.push(index);
+        let found = match entry {
+            GroupEntry::Group(group) => find_record_path(&group.entries, id, path),
+            record => record.formid() == Some(id),
+        };
+        if found {
+            return true;
+        }
+        path.pop();
+    }
+    false
+}
+
+/// The group at [path] within [entries], see [find_group_path]
+pub fn group_at<'a, 'data>(
+    entries: &'a [GroupEntry<'data>],
+    path: &[usize],
+) -> Option<&'a GeneralGroup<'data>> {
+    let (first, rest) = path.split_first()?;
+    let mut group = match entries.get(*first)? {
+        GroupEntry::Group(group) => group,
+        _ => return None,
+    };
+    for index in rest {
+        group = match group.entries.get(*index)? {
+            GroupEntry::Group(group) => group,
+            _ => return None,
+        };
+    }
+    Some(group)
+}
+
+/// Mutable version of [group_at]
+pub fn group_at_mut<'a, 'data>(
+    entries: &'a mut [GroupEntry<'data>],
+    path: &[usize],
+) -> Option<&'a mut GeneralGroup<'data>> {
+    let (first, rest) = path.split_first()?;
+    let mut group = match entries.get_mut(*first)? {
+        GroupEntry::Group(group) => group,
+        _ => return None,
+    };
+    for index in rest {
+        group = match group.entries.get_mut(*index)? {
+            GroupEntry::Group(group) => group,
+            _ => return None,
+        };
+    }
+    Some(group)
+}
+
+/// Pushes the size of each group within [entries] onto [sizes], in the order that they are
+/// written, and returns the size of [entries]. This lets nested groups be written after
+/// computing every size once, rather than each group computing the size of its subgroups again
+fn group_sizes(entries: &[GroupEntry<'_>], sizes: &mut Vec<usize>) -> usize {
+    entries
+        .iter()
+        .map(|entry| match entry {
+            GroupEntry::Group(group) => {
+                let index = sizes.len();
+                sizes.push(0);
+                sizes[index] = GROUPH_SIZE + group_sizes(&group.entries, sizes);
+                sizes[index]
+            }
+            entry => entry.data_size(),
+        })
+        .sum()
+}
+
+/// Writes [entries], taking the size of each group within them from [sizes], as made by
+/// [group_sizes]
+fn write_entries<'a, T, I>(
+    entries: &[GroupEntry<'_>],
+    sizes: &mut I,
+    w: &mut T,
+) -> Result<(), WriteError>
+where
+    T: Write,
+    I: Iterator<Item = &'a usize>,
+{
+    for entry in entries {
+        match entry {
+            GroupEntry::Group(group) => {
+                let size = *sizes.next().expect("Missing size of group");
+                write_group_size(size, w)?;
+                group.group_type.write_to(w)?;
+                group.common.write_to(w)?;
+                write_entries(&group.entries, sizes, w)?;
+            }
+            entry => entry.write_to(w)?,
+        }
+    }
+    Ok(())
+}
+
+/// Writes a group holding [entries], computing the size of it and its subgroups in one pass
+fn write_group_entries<T: Write>(
+    group_type: &GroupType<'_>,
+    common: &CommonGroupInfo,
+    entries: &[GroupEntry<'_>],
+    w: &mut T,
+) -> Result<(), WriteError> {
+    let mut sizes = Vec::new();
+    write_group_size(GROUPH_SIZE + group_sizes(entries, &mut sizes), w)?;
+    group_type.write_to(w)?;
+    common.write_to(w)?;
+    write_entries(entries, &mut sizes.iter(), w)
+}
+
+/// Calls [func] with each of [entries], descending into each group after passing it
+pub fn walk_group_entries<'a, 'data, F>(entries: &'a [GroupEntry<'data>], func: &mut F)
+where
+    F: FnMut(&'a GroupEntry<'data>),
+{
+    for entry in entries {
+        func(entry);
+        if let GroupEntry::Group(group) = entry {
+            walk_group_entries(&group.entries, func);
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct GeneralGroup<'data> {
     pub group_type: GroupType<'data>,
     pub common: CommonGroupInfo,
-    /// Records and subgroups
-    pub data: &'data [u8],
+    /// Records and subgroups. The size of the group is computed from these when it is written
+    pub entries: Vec<GroupEntry<'data>>,
+}
+impl<'data> GeneralGroup<'data> {
+    /// A group of [group_type] holding [entries], without any version control info
+    pub fn new(
+        group_type: GroupType<'data>,
+        entries: Vec<GroupEntry<'data>>,
+    ) -> GeneralGroup<'data> {
+        GeneralGroup {
+            group_type,
+            common: CommonGroupInfo::new(),
+            entries,
+        }
+    }
 }
 impl<'data> Parse<'data> for GeneralGroup<'data> {
     fn parse(data: &'data [u8]) -> PResult<Self> {
@@ -48,7 +415,9 @@ impl<'data> Parse<'data> for GeneralGroup<'data> {
         let (data, group_type) = GroupType::parse(data)?;
         let (data, version_control_info) = VersionControlInfo::parse(data)?;
         let (data, unknown) = u32::parse(data)?;
-        let (data, group_data) = take(data, group_size as usize - GROUPH_SIZE)?;
+        let content_size = check_group_size(&group_type, group_size as usize, data.len())?;
+        let (data, contents) = take(data, content_size)?;
+        let (_, entries) = many_indexed(contents, GroupEntry::parse)?;
 
         Ok((
             data,
@@ -58,7 +427,137 @@ impl<'data> Parse<'data> for GeneralGroup<'data> {
                     version_control_info,
                     unknown,
                 },
-                data: group_data,
+                entries,
+            },
+        ))
+    }
+}
+impl<'data> GeneralGroup<'data> {
+    /// [GeneralGroup::parse], skipping over the entries within it that fail to parse, going by
+    /// the size in their header, rather than failing the whole group. [skipped] is given the
+    /// offset of each, from the start of [data], along with why it failed.
+    /// Fails only if the header of the group itself is malformed
+    pub fn parse_recovering(
+        data: &'data [u8],
+        skipped: &mut dyn FnMut(usize, ParseError<'data>),
+    ) -> PResult<'data, Self> {
+        GeneralGroup::parse_recovering_at(data, 0, skipped)
+    }
+
+    /// [GeneralGroup::parse], calling [parsed] after each entry within it, with the offset (from
+    /// the start of [data]) that the entry ends at, so that the progress through a large group
+    /// can be followed. The entries of the groups within it are passed on their own, down to
+    /// [depth] groups deep, and the groups deeper than that as a whole. Stops with the error that
+    /// [parsed] returns
+    pub fn parse_visiting<E>(
+        data: &'data [u8],
+        depth: usize,
+        parsed: &mut dyn FnMut(usize, &GroupEntry<'data>) -> Result<(), E>,
+    ) -> Result<(&'data [u8], Self), E>
+    where
+        E: From<ParseError<'data>>,
+    {
+        GeneralGroup::parse_visiting_at(data, 0, depth, parsed)
+    }
+
+    fn parse_visiting_at<E>(
+        data: &'data [u8],
+        offset: usize,
+        depth: usize,
+        parsed: &mut dyn FnMut(usize, &GroupEntry<'data>) -> Result<(), E>,
+    ) -> Result<(&'data [u8], Self), E>
+    where
+        E: From<ParseError<'data>>,
+    {
+        let (data, _) = tag(data, b"GRUP")?;
+        let (data, group_size) = u32::parse(data)?;
+        let (data, group_type) = GroupType::parse(data)?;
+        let (data, version_control_info) = VersionControlInfo::parse(data)?;
+        let (data, unknown) = u32::parse(data)?;
+        let content_size = check_group_size(&group_type, group_size as usize, data.len())?;
+        let (data, contents) = take(data, content_size)?;
+
+        let mut entries = Vec::new();
+        let mut rest = contents;
+        while !rest.is_empty() {
+            let position = offset + GROUPH_SIZE + (contents.len() - rest.len());
+            if depth > 0 && rest.starts_with(b"GRUP") {
+                let (after, group) =
+                    GeneralGroup::parse_visiting_at(rest, position, depth - 1, parsed)?;
+                entries.push(group.into());
+                rest = after;
+            } else {
+                let (after, entry) = GroupEntry::parse(rest)?;
+                rest = after;
+                parsed(offset + GROUPH_SIZE + (contents.len() - rest.len()), &entry)?;
+                entries.push(entry);
+            }
+        }
+
+        Ok((
+            data,
+            Self {
+                group_type,
+                common: CommonGroupInfo {
+                    version_control_info,
+                    unknown,
+                },
+                entries,
+            },
+        ))
+    }
+
+    fn parse_recovering_at(
+        data: &'data [u8],
+        offset: usize,
+        skipped: &mut dyn FnMut(usize, ParseError<'data>),
+    ) -> PResult<'data, Self> {
+        let (data, _) = tag(data, b"GRUP")?;
+        let (data, group_size) = u32::parse(data)?;
+        let (data, group_type) = GroupType::parse(data)?;
+        let (data, version_control_info) = VersionControlInfo::parse(data)?;
+        let (data, unknown) = u32::parse(data)?;
+        let content_size = check_group_size(&group_type, group_size as usize, data.len())?;
+        let (data, contents) = take(data, content_size)?;
+
+        let mut entries = Vec::new();
+        let mut rest = contents;
+        while !rest.is_empty() {
+            let position = offset + GROUPH_SIZE + (contents.len() - rest.len());
+            let error = match GroupEntry::parse(rest) {
+                Ok((after, entry)) => {
+                    entries.push(entry);
+                    rest = after;
+                    continue;
+                }
+                Err(error) => error,
+            };
+            if rest.starts_with(b"GRUP") {
+                if let Ok((after, group)) =
+                    GeneralGroup::parse_recovering_at(rest, position, skipped)
+                {
+                    entries.push(group.into());
+                    rest = after;
+                    continue;
+                }
+            }
+            skipped(position, error);
+            match entry_size(rest) {
+                Some(size) if size <= rest.len() => rest = &rest[size..],
+                // There's no way to know where the next entry starts
+                _ => break,
+            }
+        }
+
+        Ok((
+            data,
+            Self {
+                group_type,
+                common: CommonGroupInfo {
+                    version_control_info,
+                    unknown,
+                },
+                entries,
             },
         ))
     }
@@ -66,26 +565,67 @@ impl<'data> Parse<'data> for GeneralGroup<'data> {
 impl<'data> DataSize for GeneralGroup<'data> {
     fn data_size(&self) -> usize {
         // same as value of group_size field, due to that containing header size
-        GROUPH_SIZE + self.data.len()
+        GROUPH_SIZE + self.entries.data_size()
     }
 }
 impl<'data> Writable for GeneralGroup<'data> {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
-        write_group_header(self, w)?;
-        self.group_type.write_to(w)?;
-        self.common.write_to(w)?;
-        self.data.write_to(w)
+        write_group_entries(&self.group_type, &self.common, &self.entries, w)
+    }
+}
+
+/// Amount of records within [entries], including those within their groups
+pub fn count_records(entries: &[GroupEntry<'_>]) -> usize {
+    entries
+        .iter()
+        .map(|entry| match entry {
+            GroupEntry::Group(group) => count_records(&group.entries),
+            _ => 1,
+        })
+        .sum()
+}
+
+/// Size of the record or group at the start of [data], including its header, going only by the
+/// header
+pub fn entry_size(data: &[u8]) -> Option<usize> {
+    if data.len() < RECORDH_SIZE {
+        return None;
+    }
+    let size = u32::from_le_bytes(byte::as_4_bytes(&data[4..8])) as usize;
+    if &data[..4] == b"GRUP" {
+        Some(size).filter(|size| *size >= GROUPH_SIZE)
+    } else {
+        Some(RECORDH_SIZE + size)
     }
 }
 
-pub fn write_group_header<T: DataSize, W: Write>(group: &T, w: &mut W) -> std::io::Result<()> {
+/// Checks that a group of [group_size] (including the header) fits in the [remaining] bytes
+/// after its header, returning the size of its contents.
+pub fn check_group_size<'data>(
+    group_type: &GroupType<'_>,
+    group_size: usize,
+    remaining: usize,
+) -> Result<usize, ParseError<'data>> {
+    match group_size.checked_sub(GROUPH_SIZE) {
+        Some(content_size) if content_size <= remaining => Ok(content_size),
+        _ => Err(ParseError::GroupSize {
+            label: group_type.get_label(),
+            group_type: group_type.code(),
+            expected: group_size,
+            available: GROUPH_SIZE + remaining,
+        }),
+    }
+}
+
+/// Writes the start of a group header, for a group of [size] including the header
+pub(crate) fn write_group_size<W: Write>(size: usize, w: &mut W) -> Result<(), WriteError> {
     b"GRUP".as_bstr().write_to(w)?;
-    // TODO: assert that data size fits within u32
-    // data size is equivalent to group size in file format
-    (group.data_size() as u32).write_to(w)?;
+    u32::try_from(size)
+        .map_err(|_| WriteError::GroupTooLarge { size })?
+        .write_to(w)?;
 
     Ok(())
 }
@@ -99,7 +639,46 @@ pub trait FromGeneralGroup<'data> {
 pub struct TopGroup<'data> {
     pub label: RecordName<'data>,
     pub common: CommonGroupInfo,
-    pub data: &'data [u8],
+    pub entries: Vec<GroupEntry<'data>>,
+}
+impl<'data> TopGroup<'data> {
+    /// A top group labelled [label] holding [records], with the default group info. Each must
+    /// have the type [label], and not be converted, as a top group holds records as they were
+    /// read. Converted records are held by the group of their type, see [Group::from_record]
+    pub fn from_records(
+        label: RecordName<'data>,
+        records: Vec<Record<'data>>,
+    ) -> Result<Self, GroupStructureError> {
+        let entries = records
+            .into_iter()
+            .map(|record| match record {
+                Record::Unknown(record) | Record::Deleted(record) if record.type_name == label => {
+                    Ok(record.into())
+                }
+                record => Err(GroupStructureError::UnexpectedRecord(
+                    record.type_name().to_string(),
+                )),
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(TopGroup {
+            label,
+            common: CommonGroupInfo::new(),
+            entries,
+        })
+    }
+
+    /// The records of a group which can only hold uncompressed records, such as the groups of
+    /// most record types
+    pub fn records(self) -> Result<Vec<GeneralRecord<'data>>, ParseError<'data>> {
+        self.entries
+            .into_iter()
+            .map(|entry| match entry {
+                GroupEntry::Record(record) => Ok(record),
+                GroupEntry::Compressed(_) => Err(ParseError::UnexpectedCompressed),
+                GroupEntry::Group(_) => Err(ParseError::UnexpectedGroup),
+            })
+            .collect()
+    }
 }
 impl<'data> FromGeneralGroup<'data> for TopGroup<'data> {
     fn from_general_group(group: GeneralGroup<'data>) -> Self {
@@ -107,7 +686,7 @@ impl<'data> FromGeneralGroup<'data> for TopGroup<'data> {
             Self {
                 label,
                 common: group.common,
-                data: group.data,
+                entries: group.entries,
             }
         } else {
             panic!(
@@ -124,18 +703,15 @@ impl<'data> TypeNamed<'data> for TopGroup<'data> {
 }
 impl<'data> DataSize for TopGroup<'data> {
     fn data_size(&self) -> usize {
-        GROUPH_SIZE + self.data.len()
+        GROUPH_SIZE + self.entries.data_size()
     }
 }
 impl<'data> Writable for TopGroup<'data> {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
-        write_group_header(self, w)?;
-        GroupType::Top(self.label).write_to(w)?;
-        self.common.write_to(w)?;
-        self.data.write_to(w)
+        write_group_entries(&GroupType::Top(self.label), &self.common, &self.entries, w)
     }
 }
 
@@ -145,9 +721,36 @@ pub enum FromTopGroupError<'data> {
     RecordError(FromRecordError<'data>),
     ParseError(ParseError<'data>),
 }
+impl std::fmt::Display for FromTopGroupError<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FromTopGroupError::RecordError(_) => write!(f, "Failed to convert record"),
+            FromTopGroupError::ParseError(_) => write!(f, "Failed to parse"),
+        }
+    }
+}
+impl std::error::Error for FromTopGroupError<'_> {}
+impl VivecError for FromTopGroupError<'_> {
+    fn code(&self) -> ErrorCode {
+        match self {
+            FromTopGroupError::RecordError(error) => error.code(),
+            FromTopGroupError::ParseError(error) => error.code(),
+        }
+    }
+
+    fn inner(&self) -> Option<&dyn VivecError> {
+        match self {
+            FromTopGroupError::RecordError(error) => Some(error),
+            FromTopGroupError::ParseError(error) => Some(error),
+        }
+    }
+}
 
 pub trait FromTopGroup<'data>: Sized {
-    fn from_top_group(group: TopGroup<'data>) -> PResult<Self, FromTopGroupError<'data>>;
+    fn from_top_group(
+        group: TopGroup<'data>,
+        ctx: &mut ParseContext,
+    ) -> PResult<'data, Self, FromTopGroupError<'data>>;
 }
 
 // TODO: this label storing behavior doesn't match What Record does
@@ -164,9 +767,11 @@ pub enum GroupType<'data> {
     /// Sub-block number; 3
     InteriorSubCellBlock(u32),
     /// Grid Y, X; 4
-    ExteriorCellBlock(Position<u16>),
+    /// Block coordinates, see [exterior_block_of]
+    ExteriorCellBlock(Position<i16>),
     /// Grid Y, X; 5
-    ExteriorCellSubBlock(Position<u16>),
+    /// Sub-block coordinates, see [exterior_sub_block_of]
+    ExteriorCellSubBlock(Position<i16>),
     /// Parent (CELL); 6
     CellChildren(FormId),
     /// Parent (DIAL); 7
@@ -192,14 +797,14 @@ impl<'data> GroupType<'data> {
             2 => GT::InteriorCellBlock(u32::from_le_bytes(byte::as_4_bytes(value))),
             3 => GT::InteriorSubCellBlock(u32::from_le_bytes(byte::as_4_bytes(value))),
             4 => GT::ExteriorCellBlock(Position::new(
-                // it's reversed
-                u16::from_le_bytes([value[2], value[3]]),
-                u16::from_le_bytes([value[0], value[1]]),
+                // it's reversed, Y comes first
+                i16::from_le_bytes([value[2], value[3]]),
+                i16::from_le_bytes([value[0], value[1]]),
             )),
             5 => GT::ExteriorCellSubBlock(Position::new(
                 // reversed
-                u16::from_le_bytes([value[2], value[3]]),
-                u16::from_le_bytes([value[0], value[1]]),
+                i16::from_le_bytes([value[2], value[3]]),
+                i16::from_le_bytes([value[0], value[1]]),
             )),
             6 => GT::CellChildren(FormId::from_bytes(byte::as_4_bytes(value))),
             7 => GT::TopicChildren(FormId::from_bytes(byte::as_4_bytes(value))),
@@ -212,6 +817,16 @@ impl<'data> GroupType<'data> {
         }
     }
 
+    /// A group type that isn't one of those above, such as those of newer games or tools. Its
+    /// label is kept as it is, and written back unchanged. None if [group_type] is known, as
+    /// [GroupType::from_info] would read its label as something else
+    pub fn unknown(group_type: i32, label: [u8; 4]) -> Option<GroupType<'static>> {
+        if (0..=9).contains(&group_type) {
+            return None;
+        }
+        Some(GroupType::Unknown { group_type, label })
+    }
+
     pub fn get_label(&self) -> [u8; 4] {
         match self {
             GroupType::Top(label) => {
@@ -257,6 +872,67 @@ impl<'data> GroupType<'data> {
         }
     }
 }
+/// Visits the form id of the parent that the group holds the children of
+impl FormIdVisitor for GroupType<'_> {
+    fn visit_formids(&mut self, visit: &mut dyn FnMut(&mut FormId)) {
+        match self {
+            GroupType::WorldChildren(id)
+            | GroupType::CellChildren(id)
+            | GroupType::TopicChildren(id)
+            | GroupType::CellPersistentChildren(id)
+            | GroupType::CellTemporaryChildren(id) => visit(id),
+            _ => {}
+        }
+    }
+
+    fn for_each_formid(&self, visit: &mut dyn FnMut(FormId)) {
+        match self {
+            GroupType::WorldChildren(id)
+            | GroupType::CellChildren(id)
+            | GroupType::TopicChildren(id)
+            | GroupType::CellPersistentChildren(id)
+            | GroupType::CellTemporaryChildren(id) => visit(*id),
+            _ => {}
+        }
+    }
+}
+/// Width and height, in cells, of an exterior cell block
+pub const CELLS_PER_BLOCK: i16 = 32;
+/// Width and height, in cells, of an exterior cell sub-block
+pub const CELLS_PER_SUB_BLOCK: i16 = 8;
+
+/// The block holding the exterior cell at [cell]. Rounds towards negative infinity, so cell -1
+/// is in block -1 rather than block 0
+pub fn exterior_block_of(cell: Position<i16>) -> Position<i16> {
+    Position::new(
+        cell.x.div_euclid(CELLS_PER_BLOCK),
+        cell.y.div_euclid(CELLS_PER_BLOCK),
+    )
+}
+
+/// The sub-block holding the exterior cell at [cell]. Sub-block coordinates are not relative to
+/// their block
+pub fn exterior_sub_block_of(cell: Position<i16>) -> Position<i16> {
+    Position::new(
+        cell.x.div_euclid(CELLS_PER_SUB_BLOCK),
+        cell.y.div_euclid(CELLS_PER_SUB_BLOCK),
+    )
+}
+
+/// The block holding the sub-block at [sub_block]
+pub fn exterior_block_of_sub_block(sub_block: Position<i16>) -> Position<i16> {
+    let ratio = CELLS_PER_BLOCK / CELLS_PER_SUB_BLOCK;
+    Position::new(sub_block.x.div_euclid(ratio), sub_block.y.div_euclid(ratio))
+}
+
+/// The cell at the lowest corner of the sub-block at [sub_block]. It covers the
+/// [CELLS_PER_SUB_BLOCK] cells from there along each axis. Sub-blocks far enough out have
+/// corners past what an i16 cell coordinate can hold, so this is an i32
+pub fn exterior_sub_block_origin(sub_block: Position<i16>) -> Position<i32> {
+    let size = i32::from(CELLS_PER_SUB_BLOCK);
+    Position::new(i32::from(sub_block.x) * size, i32::from(sub_block.y) * size)
+}
+
 impl<'data> Parse<'data> for GroupType<'data> {
     fn parse(data: &'data [u8]) -> PResult<Self> {
         let (data, label) = take(data, 4)?;
@@ -270,7 +946,7 @@ impl_static_data_size!(
     i32::static_data_size() // group type enum value
 );
 impl<'data> Writable for GroupType<'data> {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -279,20 +955,216 @@ impl<'data> Writable for GroupType<'data> {
     }
 }
 
+/// What became of a record of a typed group
+enum Converted<'data, T> {
+    Record(T),
+    /// [ParseContext::hooks] vetoed it
+    Vetoed,
+    /// It has a [crate::context::FieldPolicy::Raw] field, so is left unconverted
+    Raw(GeneralRecord<'data>),
+}
+
+/// Converts [record], unless the field rules say to leave it raw and [keep_raw] is true
+fn convert_record<'data, T>(
+    mut record: GeneralRecord<'data>,
+    keep_raw: bool,
+    ctx: &mut ParseContext,
+) -> Result<Converted<'data, T>, FromTopGroupError<'data>>
+where
+    T: FromRecord<'data>,
+    for<'a> &'a mut T: Into<RecordMut<'a, 'data>>,
+{
+    ctx.enter_record(&record);
+    if ctx.apply_field_rules(&mut record) && keep_raw {
+        let mut raw = Record::Unknown(record);
+        return Ok(match (ctx.hooks.run::<Record>(&mut raw), raw) {
+            (true, Record::Unknown(record)) => Converted::Raw(record),
+            _ => Converted::Vetoed,
+        });
+    }
+    let (_, mut record) = T::from_record(record, ctx)?;
+    Ok(if ctx.hooks.run(&mut record) {
+        Converted::Record(record)
+    } else {
+        Converted::Vetoed
+    })
+}
+
+/// Converts each of [records], leaving out those that [ParseContext::hooks] veto. As there is
+/// nowhere to keep records unconverted, those with a [crate::context::FieldPolicy::Raw] field
+/// are converted as well
 pub fn convert_all_records_into<'data, T>(
     records: Vec<GeneralRecord<'data>>,
-) -> Result<Vec<T>, FromTopGroupError>
+    ctx: &mut ParseContext,
+) -> Result<Vec<T>, FromTopGroupError<'data>>
+where
+    T: FromRecord<'data>,
+    for<'a> &'a mut T: Into<RecordMut<'a, 'data>>,
+{
+    let mut converted = Vec::with_capacity(records.len());
+    for record in records {
+        if let Converted::Record(record) = convert_record(record, false, ctx)? {
+            converted.push(record);
+        }
+    }
+    Ok(converted)
+}
+
+/// An entry of a typed group that isn't converted, along with where it is in the group
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeptEntry<'data> {
+    /// The amount of the group's converted records that are written before it
+    pub before: usize,
+    /// A record flagged as deleted or ignored, which often can't be converted (see
+    /// [crate::records::Record::Deleted]), a record that failed to convert with
+    /// [crate::context::ParseOptions::skip_malformed] or that has a
+    /// [crate::context::FieldPolicy::Raw] field, or a compressed record
+    pub entry: GroupEntry<'data>,
+}
+
+/// Converts the records of a typed group as [convert_all_records_into] does, keeping the records
+/// flagged as deleted or ignored, and compressed records, as they are. Records that fail to
+/// convert are kept as well with [crate::context::ParseOptions::skip_malformed], rather than
+/// failing the group
+pub fn convert_group_entries<'data, T>(
+    entries: Vec<GroupEntry<'data>>,
+    ctx: &mut ParseContext,
+) -> Result<(Vec<T>, Vec<KeptEntry<'data>>), FromTopGroupError<'data>>
 where
     T: FromRecord<'data>,
+    for<'a> &'a mut T: Into<RecordMut<'a, 'data>>,
 {
-    let records: Result<Vec<T>, FromTopGroupError> = records
-        .into_iter()
-        .map(T::from_record)
-        .map(|x| x.map(|x| x.1))
-        .map(|x| x.map_err(|e| e.into()))
-        .collect();
+    let mut records = Vec::with_capacity(entries.len());
+    let mut kept = Vec::new();
+    for entry in entries {
+        match entry {
+            GroupEntry::Record(record)
+                if !record
+                    .common
+                    .flags
+                    .is(record_flag::DELETED | record_flag::IGNORED) =>
+            {
+                // Records borrow their fields' data, so this only copies the list of fields
+                let original = ctx.options.skip_malformed.then(|| record.clone());
+                match convert_record(record, true, ctx) {
+                    Ok(Converted::Record(record)) => records.push(record),
+                    Ok(Converted::Vetoed) => {}
+                    Ok(Converted::Raw(record)) => kept.push(KeptEntry {
+                        before: records.len(),
+                        entry: record.into(),
+                    }),
+                    Err(err) if original.is_some() => {
+                        ctx.diagnose(
+                            Severity::Error,
+                            format!("Kept unconverted, as it failed to convert: {:?}", err),
+                        );
+                        kept.push(KeptEntry {
+                            before: records.len(),
+                            entry: original.unwrap().into(),
+                        });
+                    }
+                    Err(err) => return Err(err),
+                }
+            }
+            GroupEntry::Group(_) => return Err(ParseError::UnexpectedGroup.into()),
+            entry => kept.push(KeptEntry {
+                before: records.len(),
+                entry,
+            }),
+        }
+    }
+    Ok((records, kept))
+}
 
-    records
+/// The converted records and kept entries of a typed group, in the order they are written in
+#[derive(Debug)]
+pub(crate) enum TypedEntry<'data, T> {
+    Record(T),
+    Kept(GroupEntry<'data>),
+}
+impl<'data, T> TypedEntry<'data, T> {
+    pub(crate) fn formid(&self) -> Option<FormId>
+    where
+        for<'a> &'a T: Into<crate::records::RecordRef<'a, 'data>>,
+    {
+        match self {
+            TypedEntry::Record(record) => Some(record.into().formid()),
+            TypedEntry::Kept(entry) => entry.formid(),
+        }
+    }
+
+    /// Puts [records] and [kept] back into the order they are written in
+    pub(crate) fn join(records: Vec<T>, kept: Vec<KeptEntry<'data>>) -> Vec<TypedEntry<'data, T>> {
+        let mut entries = Vec::with_capacity(records.len() + kept.len());
+        let mut kept = kept.into_iter().peekable();
+        for (index, record) in records.into_iter().enumerate() {
+            while let Some(entry) = kept.next_if(|x| x.before <= index) {
+                entries.push(TypedEntry::Kept(entry.entry));
+            }
+            entries.push(TypedEntry::Record(record));
+        }
+        entries.extend(kept.map(|x| TypedEntry::Kept(x.entry)));
+        entries
+    }
+
+    /// Splits [entries] into the converted records and the kept entries
+    pub(crate) fn split(entries: Vec<TypedEntry<'data, T>>) -> (Vec<T>, Vec<KeptEntry<'data>>) {
+        let mut records = Vec::with_capacity(entries.len());
+        let mut kept = Vec::new();
+        for entry in entries {
+            match entry {
+                TypedEntry::Record(record) => records.push(record),
+                TypedEntry::Kept(entry) => kept.push(KeptEntry {
+                    before: records.len(),
+                    entry,
+                }),
+            }
+        }
+        (records, kept)
+    }
+}
+
+/// Writes [records] with [kept] between them, in the order that they were read in
+pub fn write_typed_entries<T, W>(
+    records: &[T],
+    kept: &[KeptEntry<'_>],
+    w: &mut W,
+) -> Result<(), WriteError>
+where
+    T: Writable,
+    W: Write,
+{
+    let mut kept = kept.iter().peekable();
+    for (index, record) in records.iter().enumerate() {
+        while let Some(entry) = kept.next_if(|x| x.before <= index) {
+            entry.entry.write_to(w)?;
+        }
+        record.write_to(w)?;
+    }
+    kept.try_for_each(|x| x.entry.write_to(w))
+}
+
+/// Writes a top group labelled [label] holding [records] and [kept]. The entries are written out
+/// first, so that the size of each record is only computed as it is written, rather than once
+/// more for the size of the group
+pub fn write_typed_group<T, W>(
+    label: RecordName<'_>,
+    common: &CommonGroupInfo,
+    records: &[T],
+    kept: &[KeptEntry<'_>],
+    w: &mut W,
+) -> Result<(), WriteError>
+where
+    T: Writable,
+    W: Write,
+{
+    let mut entries = Vec::new();
+    write_typed_entries(records, kept, &mut entries)?;
+    write_group_size(GROUPH_SIZE + entries.len(), w)?;
+    GroupType::Top(label).write_to(w)?;
+    common.write_to(w)?;
+    w.write_all(&entries)?;
+    Ok(())
 }
 
 // This would be easier if I could concatenate identifiers, but it's simply anyway
@@ -304,22 +1176,31 @@ macro_rules! make_simple_top_group {
         pub struct $group_name<$life> {
             pub common: $crate::groups::common::CommonGroupInfo,
             pub records: Vec<$record_name<$life>>,
+            /// Deleted and compressed records, which aren't converted
+            pub kept: Vec<$crate::groups::common::KeptEntry<$life>>,
         }
-        impl<$life> $crate::FromTopGroup<$life> for $group_name<$life> {
-            fn from_top_group(group: $crate::groups::common::TopGroup<$life>) -> crate::parse::PResult<Self, crate::groups::common::FromTopGroupError> {
-                use $crate::parse::Parse;
-                let (data, records) = crate::parse::many(group.data, $crate::records::common::GeneralRecord::parse)?;
-                if !data.is_empty() {
-                    return Err(crate::parse::ParseError::ExpectedEOF.into());
+        impl<$life> $group_name<$life> {
+            /// A group holding [records], with the default group info
+            pub fn from_records(records: Vec<$record_name<$life>>) -> Self {
+                Self {
+                    common: $crate::groups::common::CommonGroupInfo::new(),
+                    records,
+                    kept: Vec::new(),
                 }
-
-                let records = $crate::groups::common::convert_all_records_into(records)?;
+            }
+        }
+        impl<$life> $crate::groups::common::FromTopGroup<$life> for $group_name<$life> {
+            fn from_top_group(group: $crate::groups::common::TopGroup<$life>, ctx: &mut $crate::context::ParseContext) -> $crate::parse::PResult<$life, Self, $crate::groups::common::FromTopGroupError<$life>> {
+                let common = group.common;
+                let (records, kept) =
+                    $crate::groups::common::convert_group_entries(group.entries, ctx)?;
 
                 Ok((
-                    data,
+                    &[],
                     Self {
-                        common: group.common,
+                        common,
                         records,
+                        kept,
                     },
                 ))
             }
@@ -332,19 +1213,24 @@ macro_rules! make_simple_top_group {
         }
         impl<$life> $crate::util::DataSize for $group_name<$life> {
             fn data_size(&self) -> usize {
-                $crate::groups::common::GROUPH_SIZE + self.records.data_size()
+                $crate::groups::common::GROUPH_SIZE
+                    + self.records.data_size()
+                    + self.kept.iter().map(|x| x.entry.data_size()).sum::<usize>()
             }
         }
         impl<$life> $crate::util::Writable for $group_name<$life> {
-            fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+            fn write_to<T>(&self, w: &mut T) -> Result<(), $crate::util::WriteError>
             where
                 T: std::io::Write,
             {
 				use $crate::records::common::TypeNamed;
-                $crate::groups::common::write_group_header(self, w)?;
-                $crate::groups::common::GroupType::Top(self.type_name()).write_to(w)?;
-                self.common.write_to(w)?;
-                self.records.write_to(w)
+                $crate::groups::common::write_typed_group(
+                    self.type_name(),
+                    &self.common,
+                    &self.records,
+                    &self.kept,
+                    w,
+                )
             }
         }
     };
@@ -369,4 +1255,270 @@ mod tests {
         assert_eq!(data[6], 0x00);
         assert_eq!(data[7], 0x00);
     }
+
+    #[test]
+    fn test_exterior_cell_blocks() {
+        // Block (X -1, Y 2), with Y stored first
+        let label = [2, 0, 0xFF, 0xFF];
+        let group = GroupType::from_info(4, &label);
+        assert_eq!(group, GroupType::ExteriorCellBlock(Position::new(-1, 2)));
+        assert_eq!(group.get_label(), label);
+        let group = GroupType::from_info(5, &[0xF8, 0xFF, 3, 0]);
+        assert_eq!(group, GroupType::ExteriorCellSubBlock(Position::new(3, -8)));
+
+        let cell = Position::new(-1, 40);
+        assert_eq!(exterior_block_of(cell), Position::new(-1, 1));
+        assert_eq!(exterior_sub_block_of(cell), Position::new(-1, 5));
+        assert_eq!(
+            exterior_block_of(Position::new(-32, 31)),
+            Position::new(-1, 0)
+        );
+        assert_eq!(
+            exterior_block_of(Position::new(-33, 32)),
+            Position::new(-2, 1)
+        );
+        assert_eq!(
+            exterior_block_of_sub_block(Position::new(-1, 5)),
+            Position::new(-1, 1)
+        );
+        assert_eq!(
+            exterior_sub_block_origin(Position::new(-1, 5)),
+            Position::new(-8, 40)
+        );
+        assert_eq!(
+            exterior_sub_block_origin(Position::new(i16::MIN, i16::MAX)),
+            Position::new(-262144, 262136)
+        );
+    }
+
+    #[test]
+    fn test_group_size() {
+        let mut data = b"GRUP".to_vec();
+        data.extend_from_slice(&40u32.to_le_bytes());
+        data.extend_from_slice(b"AACT");
+        data.extend_from_slice(&0i32.to_le_bytes());
+        data.extend_from_slice(&[0; 8]);
+        data.extend_from_slice(&[0; 10]);
+        assert_eq!(
+            GeneralGroup::parse(&data),
+            Err(ParseError::GroupSize {
+                label: *b"AACT",
+                group_type: 0,
+                expected: 40,
+                available: 34,
+            })
+        );
+
+        data[4..8].copy_from_slice(&8u32.to_le_bytes());
+        assert!(matches!(
+            GeneralGroup::parse(&data),
+            Err(ParseError::GroupSize { expected: 8, .. })
+        ));
+
+        data[4..8].copy_from_slice(&30u32.to_le_bytes());
+        assert!(matches!(
+            GeneralGroup::parse(&data),
+            Err(ParseError::Element { index: 0, .. })
+        ));
+
+        data[4..8].copy_from_slice(&24u32.to_le_bytes());
+        let (rest, group) = GeneralGroup::parse(&data).unwrap();
+        assert!(group.entries.is_empty());
+        assert_eq!(rest.len(), 10);
+    }
+
+    #[test]
+    fn test_group_entries() {
+        use crate::records::{
+            common::{record_flag, CommonRecordInfo, RecordFlags},
+            fields::common::GeneralField,
+        };
+
+        let record = |id| {
+            let mut common = CommonRecordInfo::test_default();
+            common.id = id;
+            GeneralRecord {
+                type_name: b"REFR".as_bstr(),
+                common,
+                fields: vec![GeneralField::new(b"NAME".as_bstr(), &[1, 0, 0, 0])],
+            }
+        };
+        let mut common = CommonRecordInfo::test_default();
+        common.flags = RecordFlags::new(record_flag::COMPRESSED);
+        let compressed = CompressedRecord {
+            type_name: b"NPC_".as_bstr(),
+            common,
+            data: (&[4, 0, 0, 0, 0x78, 0x9C][..]).into(),
+        };
+        let children = GeneralGroup {
+            group_type: GroupType::CellTemporaryChildren(FormId::new(0x420)),
+            common: CommonGroupInfo {
+                version_control_info: VersionControlInfo::new(0, 0, 0, 0),
+                unknown: 0,
+            },
+            entries: vec![record(0x801).into(), compressed.into()],
+        };
+        let mut group = TopGroup {
+            label: b"CELL".as_bstr(),
+            common: children.common,
+            entries: vec![record(0x800).into(), children.into()],
+        };
+
+        let mut data = Vec::new();
+        group.write_to(&mut data).unwrap();
+        assert_eq!(data.len(), group.data_size());
+        let (_, parsed) = GeneralGroup::parse(&data).unwrap();
+        assert_eq!(TopGroup::from_general_group(parsed), group);
+
+        // Sizes follow the entries once they change
+        if let GroupEntry::Group(children) = &mut group.entries[1] {
+            children.entries.push(record(0x802).into());
+        }
+        let mut data = Vec::new();
+        group.write_to(&mut data).unwrap();
+        assert_eq!(data.len(), group.data_size());
+        assert_eq!(&data[4..8], &(data.len() as u32).to_le_bytes());
+        let (_, parsed) = GeneralGroup::parse(&data).unwrap();
+        let mut ids = Vec::new();
+        walk_group_entries(&parsed.entries, &mut |entry| ids.push(entry.formid()));
+        assert_eq!(
+            ids,
+            vec![
+                Some(FormId::new(0x800)),
+                None,
+                Some(FormId::new(0x801)),
+                Some(FormId::new(0x420)),
+                Some(FormId::new(0x802)),
+            ]
+        );
+        assert_eq!(group.records(), Err(ParseError::UnexpectedGroup));
+    }
+
+    #[test]
+    fn test_nested_group_sizes() {
+        let common = CommonGroupInfo {
+            version_control_info: VersionControlInfo::new(0, 0, 0, 0),
+            unknown: 0,
+        };
+        let group = |entries: Vec<GroupEntry<'static>>| GeneralGroup {
+            group_type: GroupType::CellChildren(FormId::new(0x420)),
+            common,
+            entries,
+        };
+        let record = GeneralRecord {
+            type_name: b"REFR".as_bstr(),
+            common: crate::records::common::CommonRecordInfo::test_default(),
+            fields: Vec::new(),
+        };
+        // Groups are nested unevenly, so that a size taken out of order would be wrong
+        let deep = group(vec![group(vec![record.clone().into()]).into()]);
+        let outer = group(vec![
+            deep.into(),
+            group(Vec::new()).into(),
+            record.into(),
+            group(vec![group(Vec::new()).into()]).into(),
+        ]);
+
+        let mut sizes = Vec::new();
+        let size = group_sizes(&outer.entries, &mut sizes);
+        assert_eq!(size + GROUPH_SIZE, outer.data_size());
+        assert_eq!(sizes, vec![72, 48, 24, 48, 24]);
+
+        let mut data = Vec::new();
+        outer.write_to(&mut data).unwrap();
+        assert_eq!(data.len(), outer.data_size());
+        let (_, parsed) = GeneralGroup::parse(&data).unwrap();
+        assert_eq!(parsed, outer);
+    }
+
+    #[test]
+    fn test_common_group_info() {
+        assert_eq!(CommonGroupInfo::default(), CommonGroupInfo::new());
+        let mut common = CommonGroupInfo::from_parts(VersionControlInfo::new(1, 2, 3, 4), 0);
+        common.unknown = 0xCCCC_CCCC;
+        let group = GeneralGroup {
+            group_type: GroupType::TopicChildren(FormId::new(0x420)),
+            common,
+            entries: Vec::new(),
+        };
+        let data = assert_size_output!(group);
+        assert_eq!(&data[16..], &[1, 2, 3, 4, 0xCC, 0xCC, 0xCC, 0xCC]);
+        assert_eq!(GeneralGroup::parse(&data).unwrap().1, group);
+    }
+
+    #[test]
+    fn test_unknown_group_type() {
+        assert_eq!(GroupType::unknown(6, [1, 0, 0, 0]), None);
+        assert_eq!(GroupType::unknown(0, *b"GMST"), None);
+        let group_type = GroupType::unknown(10, *b"ABCD").unwrap();
+        assert_eq!(group_type.code(), 10);
+        assert_eq!(group_type.get_label(), *b"ABCD");
+        // Labels aren't read as form ids, so they aren't renumbered
+        assert!(group_type.formids().is_empty());
+
+        let record = GeneralRecord {
+            type_name: b"REFR".as_bstr(),
+            common: CommonRecordInfo::test_default(),
+            fields: Vec::new(),
+        };
+        let inner = GeneralGroup::new(
+            GroupType::unknown(-3, [0xFF; 4]).unwrap(),
+            vec![record.clone().into()],
+        );
+        let mut group = GeneralGroup::new(group_type, vec![record.into(), inner.into()]);
+        group.common.unknown = 0xCCCC_CCCC;
+        let data = assert_size_output!(group);
+        assert_eq!(&data[8..16], &[b'A', b'B', b'C', b'D', 10, 0, 0, 0]);
+        let (rest, parsed) = GeneralGroup::parse(&data).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(parsed, group);
+        let mut written = Vec::new();
+        parsed.write_to(&mut written).unwrap();
+        assert_eq!(written, data);
+    }
+
+    #[test]
+    fn test_from_records() {
+        let record = |id| {
+            let mut common = CommonRecordInfo::test_default();
+            common.flags.flags = 0;
+            common.id = id;
+            GeneralRecord {
+                type_name: b"FLST".as_bstr(),
+                common,
+                fields: Vec::new(),
+            }
+        };
+        let records = vec![
+            Record::Unknown(record(0x801)),
+            Record::Unknown(record(0x802)),
+        ];
+        let group = TopGroup::from_records(b"FLST".as_bstr(), records).unwrap();
+        assert_eq!(group.common, CommonGroupInfo::new());
+        let data = assert_size_output!(group);
+        let parsed = TopGroup::from_general_group(GeneralGroup::parse(&data).unwrap().1);
+        assert_eq!(parsed, group);
+
+        let mut ctx = ParseContext::default();
+        let records = group.clone().records().unwrap();
+        let lists = convert_all_records_into(records, &mut ctx).unwrap();
+        let lists = crate::groups::flst::FLSTGroup::from_records(lists);
+        assert_eq!(assert_size_output!(lists), data);
+        let (_, converted) =
+            crate::groups::flst::FLSTGroup::from_top_group(group, &mut ctx).unwrap();
+        assert_eq!(converted, lists);
+
+        // The records must be of the group's type, and not converted
+        let list = Record::Unknown(record(0x803));
+        assert_eq!(
+            TopGroup::from_records(b"MISC".as_bstr(), vec![list.clone()]),
+            Err(GroupStructureError::UnexpectedRecord("FLST".to_string()))
+        );
+        let converted = Record::from_general(record(0x803), &mut ctx).unwrap();
+        assert!(matches!(converted, Record::FLST(_)));
+        assert_eq!(
+            TopGroup::from_records(b"FLST".as_bstr(), vec![list, converted]),
+            Err(GroupStructureError::UnexpectedRecord("FLST".to_string()))
+        );
+    }
 }
@@ -0,0 +1,465 @@
+// The responses of dialogue topics. Each DIAL record in the DIAL top group is followed by a group
+// holding its INFO records (type 7, labelled with the DIAL's form id), in the order the game
+// considers them. A plugin keeps these as the general groups that it read. The responses of a topic
+// can be looked at and added to where they are, through TopicChildrenRef and TopicChildrenMut,
+// while Plugin::topics copies the whole group into Topics.
+
+use super::common::{
+    wrong_group, ChildRecord, CommonGroupInfo, GeneralGroup, GroupEntry, GroupStructureError,
+    GroupType, TopGroup,
+};
+use crate::{
+    plugin::Plugin,
+    records::common::{FormId, TypeNamed},
+};
+use bstr::ByteSlice;
+
+/// The group holding the responses of the DIAL with the id [TopicChildren::topic]
+#[derive(Debug, Clone, PartialEq)]
+pub struct TopicChildren<'data> {
+    pub topic: FormId,
+    pub common: CommonGroupInfo,
+    pub responses: Vec<ChildRecord<'data>>,
+}
+impl<'data> TopicChildren<'data> {
+    /// The children of [topic], without any
+    pub fn new(topic: FormId) -> TopicChildren<'data> {
+        TopicChildren {
+            topic,
+            common: CommonGroupInfo::new(),
+            responses: Vec::new(),
+        }
+    }
+
+    /// Reads the responses from their group, which must be of type 7 and hold nothing but INFO
+    /// records
+    pub fn from_group(
+        group: GeneralGroup<'data>,
+    ) -> Result<TopicChildren<'data>, GroupStructureError> {
+        let topic = check_responses(&group)?;
+        Ok(TopicChildren {
+            topic,
+            common: group.common,
+            responses: group
+                .entries
+                .into_iter()
+                .filter_map(|entry| ChildRecord::from_entry(entry).ok())
+                .collect(),
+        })
+    }
+
+    /// The group of the responses, labelled with [TopicChildren::topic]
+    pub fn into_group(self) -> GeneralGroup<'data> {
+        GeneralGroup {
+            group_type: GroupType::TopicChildren(self.topic),
+            common: self.common,
+            entries: self.responses.into_iter().map(GroupEntry::from).collect(),
+        }
+    }
+}
+
+/// Checks that [group] has the structure that [TopicChildren::from_group] reads, without copying
+/// it. Returns the id of the topic
+fn check_responses(group: &GeneralGroup<'_>) -> Result<FormId, GroupStructureError> {
+    let topic = match group.group_type {
+        GroupType::TopicChildren(topic) => topic,
+        group_type => return Err(wrong_group(7, group_type)),
+    };
+    for entry in group.entries.iter() {
+        let type_name = match entry {
+            GroupEntry::Record(record) => record.type_name,
+            GroupEntry::Compressed(record) => record.type_name(),
+            GroupEntry::Group(group) => {
+                return Err(GroupStructureError::UnexpectedGroup(
+                    group.group_type.code(),
+                ))
+            }
+        };
+        if type_name != "INFO" {
+            return Err(GroupStructureError::UnexpectedRecord(type_name.to_string()));
+        }
+    }
+    Ok(topic)
+}
+
+/// The responses of a topic where a plugin holds them, see [Plugin::topic_children]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TopicChildrenRef<'a, 'data> {
+    topic: FormId,
+    group: &'a GeneralGroup<'data>,
+}
+impl<'a, 'data> TopicChildrenRef<'a, 'data> {
+    /// Looks at the responses within [group], which must have the structure that
+    /// [TopicChildren::from_group] reads
+    pub fn new(
+        group: &'a GeneralGroup<'data>,
+    ) -> Result<TopicChildrenRef<'a, 'data>, GroupStructureError> {
+        let topic = check_responses(group)?;
+        Ok(TopicChildrenRef { topic, group })
+    }
+
+    pub fn topic(self) -> FormId {
+        self.topic
+    }
+
+    /// The INFO records, in order
+    pub fn responses(self) -> impl Iterator<Item = &'a GroupEntry<'data>> {
+        self.group.entries.iter()
+    }
+}
+
+/// Mutable version of [TopicChildrenRef], see [Plugin::topic_children_mut]
+#[derive(Debug, PartialEq)]
+pub struct TopicChildrenMut<'a, 'data> {
+    topic: FormId,
+    group: &'a mut GeneralGroup<'data>,
+}
+impl<'a, 'data> TopicChildrenMut<'a, 'data> {
+    /// Changes the responses within [group], which must have the structure that
+    /// [TopicChildren::from_group] reads
+    pub fn new(
+        group: &'a mut GeneralGroup<'data>,
+    ) -> Result<TopicChildrenMut<'a, 'data>, GroupStructureError> {
+        let topic = check_responses(group)?;
+        Ok(TopicChildrenMut { topic, group })
+    }
+
+    pub fn topic(&self) -> FormId {
+        self.topic
+    }
+
+    /// Adds [record] to the end of the responses
+    pub fn push(&mut self, record: ChildRecord<'data>) {
+        self.group.entries.push(record.into());
+    }
+}
+
+/// A DIAL record along with the INFO records that it owns
+#[derive(Debug, Clone, PartialEq)]
+pub struct DIALRecord<'data> {
+    pub record: ChildRecord<'data>,
+    pub children: Option<TopicChildren<'data>>,
+}
+impl<'data> DIALRecord<'data> {
+    pub fn formid(&self) -> FormId {
+        self.record.formid()
+    }
+
+    /// The INFO records of the topic, in order
+    pub fn responses(&self) -> impl Iterator<Item = &ChildRecord<'data>> {
+        self.children
+            .iter()
+            .flat_map(|children| children.responses.iter())
+    }
+
+    /// Adds [record] to the end of the responses, making the group of children if there isn't
+    /// one
+    pub fn push_response(&mut self, record: ChildRecord<'data>) {
+        let topic = self.formid();
+        self.children
+            .get_or_insert_with(|| TopicChildren::new(topic))
+            .responses
+            .push(record);
+    }
+
+    /// The record followed by the group of its responses, labelled with the record's id
+    pub fn into_entries(self) -> Vec<GroupEntry<'data>> {
+        let topic = self.record.formid();
+        let mut entries = vec![self.record.into()];
+        if let Some(mut children) = self.children {
+            children.topic = topic;
+            entries.push(children.into_group().into());
+        }
+        entries
+    }
+}
+
+/// The DIAL top group, with each topic owning its responses
+#[derive(Debug, Clone, PartialEq)]
+pub struct Topics<'data> {
+    pub common: CommonGroupInfo,
+    pub topics: Vec<DIALRecord<'data>>,
+}
+impl<'data> Topics<'data> {
+    pub fn new() -> Topics<'data> {
+        Topics {
+            common: CommonGroupInfo::new(),
+            topics: Vec::new(),
+        }
+    }
+
+    /// Reads the DIAL records of the top group along with the group of responses after each
+    pub fn from_top(group: TopGroup<'data>) -> Result<Topics<'data>, GroupStructureError> {
+        let mut topics: Vec<DIALRecord<'data>> = Vec::new();
+        for entry in group.entries {
+            match ChildRecord::from_entry(entry) {
+                Ok(record) if record.type_name() == "DIAL" => topics.push(DIALRecord {
+                    record,
+                    children: None,
+                }),
+                Ok(record) => {
+                    return Err(GroupStructureError::UnexpectedRecord(
+                        record.type_name().to_string(),
+                    ))
+                }
+                Err(group) => {
+                    let children = TopicChildren::from_group(group)?;
+                    match topics.last_mut() {
+                        Some(topic)
+                            if topic.children.is_none() && topic.formid() == children.topic =>
+                        {
+                            topic.children = Some(children)
+                        }
+                        _ => {
+                            return Err(GroupStructureError::Orphan {
+                                parent: children.topic,
+                            })
+                        }
+                    }
+                }
+            }
+        }
+        Ok(Topics {
+            common: group.common,
+            topics,
+        })
+    }
+
+    pub fn into_top(self) -> TopGroup<'data> {
+        TopGroup {
+            label: b"DIAL".as_bstr(),
+            common: self.common,
+            entries: self
+                .topics
+                .into_iter()
+                .flat_map(DIALRecord::into_entries)
+                .collect(),
+        }
+    }
+
+    /// The topic with [id]
+    pub fn topic(&self, id: FormId) -> Option<&DIALRecord<'data>> {
+        self.topics.iter().find(|topic| topic.formid() == id)
+    }
+
+    /// Mutable version of [Topics::topic]
+    pub fn topic_mut(&mut self, id: FormId) -> Option<&mut DIALRecord<'data>> {
+        self.topics.iter_mut().find(|topic| topic.formid() == id)
+    }
+}
+
+impl<'data> Plugin<'data> {
+    /// A copy of the DIAL top group, with the responses of each topic, to be changed as a whole
+    /// and given back to [Plugin::set_topics]. None if the plugin has no topics. See
+    /// [Plugin::topic_children] to look at a topic's responses without copying the group
+    pub fn topics(&self) -> Result<Option<Topics<'data>>, GroupStructureError> {
+        self.unknown_top(b"DIAL")
+            .map(|group| Topics::from_top(group.clone()))
+            .transpose()
+    }
+
+    /// Replaces the DIAL top group with [topics], adding it to the end if there isn't one
+    pub fn set_topics(&mut self, topics: Topics<'data>) {
+        self.set_unknown_top(topics.into_top())
+    }
+
+    /// The responses of the DIAL with the id [topic], as they are held. None if it has no group
+    /// of responses
+    pub fn topic_children(
+        &self,
+        topic: FormId,
+    ) -> Result<Option<TopicChildrenRef<'_, 'data>>, GroupStructureError> {
+        self.find_group(&GroupType::TopicChildren(topic))
+            .map(TopicChildrenRef::new)
+            .transpose()
+    }
+
+    /// Mutable version of [Plugin::topic_children]
+    pub fn topic_children_mut(
+        &mut self,
+        topic: FormId,
+    ) -> Result<Option<TopicChildrenMut<'_, 'data>>, GroupStructureError> {
+        self.find_group_mut(&GroupType::TopicChildren(topic))
+            .map(TopicChildrenMut::new)
+            .transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        context::ParseContext,
+        groups::common::FromGeneralGroup,
+        parse::Parse,
+        records::common::{CommonRecordInfo, GeneralRecord},
+        util::Writable,
+    };
+
+    fn general(type_name: &'static [u8], id: u32) -> GeneralRecord<'static> {
+        let mut common = CommonRecordInfo::test_default();
+        common.flags.flags = 0;
+        common.id = id;
+        GeneralRecord {
+            type_name: type_name.as_bstr(),
+            common,
+            fields: Vec::new(),
+        }
+    }
+
+    fn record(type_name: &'static [u8], id: u32) -> Vec<u8> {
+        general(type_name, id).to_vec().unwrap()
+    }
+
+    fn group(label: u32, group_type: i32, contents: &[Vec<u8>]) -> Vec<u8> {
+        let contents = contents.concat();
+        let mut data = b"GRUP".to_vec();
+        data.extend_from_slice(&(24 + contents.len() as u32).to_le_bytes());
+        data.extend_from_slice(&label.to_le_bytes());
+        data.extend_from_slice(&group_type.to_le_bytes());
+        data.extend_from_slice(&[0; 8]);
+        data.extend_from_slice(&contents);
+        data
+    }
+
+    fn ids<'a>(records: impl Iterator<Item = &'a ChildRecord<'a>>) -> Vec<u32> {
+        records.map(|x| x.formid().id).collect()
+    }
+
+    #[test]
+    fn test_topics() {
+        let mut header = Plugin::new("Base.esm");
+        header.refresh_header();
+        let data = [
+            header.to_vec().unwrap(),
+            group(
+                u32::from_le_bytes(*b"DIAL"),
+                0,
+                &[
+                    record(b"DIAL", 0x801),
+                    group(
+                        0x801,
+                        7,
+                        &[
+                            record(b"INFO", 0x804),
+                            record(b"INFO", 0x802),
+                            record(b"INFO", 0x803),
+                        ],
+                    ),
+                    record(b"DIAL", 0x805),
+                ],
+            ),
+        ]
+        .concat();
+        let mut ctx = ParseContext::default();
+        let mut plugin = Plugin::parse("Base.esm", &data, &mut ctx).unwrap();
+
+        let mut topics = plugin.topics().unwrap().unwrap();
+        assert_eq!(topics.topics.len(), 2);
+        let topic = topics.topic(FormId::new(0x801)).unwrap();
+        assert_eq!(ids(topic.responses()), vec![0x804, 0x802, 0x803]);
+        assert_eq!(
+            topics
+                .topic(FormId::new(0x805))
+                .unwrap()
+                .responses()
+                .count(),
+            0
+        );
+        assert!(topics.topic(FormId::new(0x802)).is_none());
+
+        plugin.set_topics(topics.clone());
+        assert_eq!(plugin.to_vec().unwrap(), data);
+
+        // The group follows its topic's id, and is made for topics without responses
+        let topic = topics.topic_mut(FormId::new(0x801)).unwrap();
+        topic.record.common_mut().id = 0x810;
+        let topic = topics.topic_mut(FormId::new(0x805)).unwrap();
+        topic.push_response(general(b"INFO", 0x806).into());
+        plugin.set_topics(topics);
+        let top = plugin.topics().unwrap().unwrap().into_top();
+        let labels: Vec<GroupType<'_>> = top
+            .entries
+            .iter()
+            .filter_map(|entry| match entry {
+                GroupEntry::Group(group) => Some(group.group_type),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            labels,
+            vec![
+                GroupType::TopicChildren(FormId::new(0x810)),
+                GroupType::TopicChildren(FormId::new(0x805))
+            ]
+        );
+        let topics = plugin.topics().unwrap().unwrap();
+        assert_eq!(
+            ids(topics.topic(FormId::new(0x805)).unwrap().responses()),
+            vec![0x806]
+        );
+
+        // Responses are looked at and added to where they are held
+        let topic = FormId::new(0x805);
+        let mut responses = plugin.topic_children_mut(topic).unwrap().unwrap();
+        responses.push(general(b"INFO", 0x807).into());
+        let responses = plugin.topic_children(topic).unwrap().unwrap();
+        let ids: Vec<u32> = responses
+            .responses()
+            .filter_map(GroupEntry::formid)
+            .map(|x| x.id)
+            .collect();
+        assert_eq!(ids, vec![0x806, 0x807]);
+        assert_eq!(plugin.topic_children(FormId::new(0x801)), Ok(None));
+        let mut topics = plugin.topics().unwrap().unwrap();
+        topics.topic_mut(FormId::new(0x810)).unwrap().children = None;
+        plugin.set_topics(topics);
+        assert_eq!(plugin.topic_children(FormId::new(0x810)), Ok(None));
+    }
+
+    #[test]
+    fn test_topic_structure() {
+        fn parse(data: &[u8]) -> Result<Topics<'_>, GroupStructureError> {
+            let group = GeneralGroup::parse(data).unwrap().1;
+            Topics::from_top(TopGroup::from_general_group(group))
+        }
+        let label = u32::from_le_bytes(*b"DIAL");
+        assert_eq!(
+            parse(&group(label, 0, &[record(b"INFO", 0x801)])),
+            Err(GroupStructureError::UnexpectedRecord("INFO".to_string()))
+        );
+        // Responses of another topic
+        assert_eq!(
+            parse(&group(
+                label,
+                0,
+                &[record(b"DIAL", 0x801), group(0x802, 7, &[])]
+            )),
+            Err(GroupStructureError::Orphan {
+                parent: FormId::new(0x802)
+            })
+        );
+        assert_eq!(
+            parse(&group(
+                label,
+                0,
+                &[record(b"DIAL", 0x801), group(0x801, 6, &[])]
+            )),
+            Err(GroupStructureError::GroupType {
+                expected: 7,
+                found: 6
+            })
+        );
+        assert_eq!(
+            parse(&group(
+                label,
+                0,
+                &[
+                    record(b"DIAL", 0x801),
+                    group(0x801, 7, &[record(b"DIAL", 0x802)])
+                ]
+            )),
+            Err(GroupStructureError::UnexpectedRecord("DIAL".to_string()))
+        );
+    }
+}
@@ -0,0 +1,3 @@
+use crate::{make_simple_top_group, records::flst::FLSTRecord};
+
+make_simple_top_group!(FLSTGroup, FLST, FLSTRecord, 'data);
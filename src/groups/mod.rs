@@ -1,8 +1,17 @@
 use crate::{
+    context::ParseContext,
     dispatch_all,
-    util::{DataSize, Writable},
+    edit::EditError,
+    records::{
+        common::{FormId, GeneralRecord, TypeNamed},
+        Record, RecordMut, RecordRef,
+    },
+    util::{DataSize, Writable, WriteError},
+    walk::{is_deleted, EntryMut, EntryRef},
 };
+use common::{FromTopGroup, GroupEntry, GroupType, TypedEntry};
 use derive_more::From;
+use std::collections::HashMap;
 
 pub mod aact;
 pub mod acti;
@@ -18,9 +27,227 @@ pub mod aspc;
 pub mod astp;
 pub mod avif;
 pub mod book;
+pub mod cell;
+pub mod dial;
+pub mod flst;
+pub mod wrld;
 
 pub mod common;
 
+/// Which record [Group::merge] keeps when both groups have one with the same form id
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub enum ConflictPolicy {
+    /// The record of the group merged in, as it is the later override
+    #[default]
+    KeepLast,
+    /// The record already in the group
+    KeepFirst,
+    /// Fail with [EditError::Exists]
+    Error,
+}
+
+/// Merges [other] into [items], where [formid] gives the form id that an item is known by.
+/// Items that replace others keep their position, and the rest are added to the end
+fn merge_by_formid<T>(
+    items: &mut Vec<T>,
+    other: Vec<T>,
+    policy: ConflictPolicy,
+    formid: impl Fn(&T) -> Option<FormId>,
+) -> Result<(), EditError> {
+    let mut positions: HashMap<FormId, usize> = items
+        .iter()
+        .enumerate()
+        .filter_map(|(index, item)| Some((formid(item)?, index)))
+        .collect();
+    if policy == ConflictPolicy::Error {
+        if let Some(id) = other
+            .iter()
+            .filter_map(&formid)
+            .find(|id| positions.contains_key(id))
+        {
+            return Err(EditError::Exists(id));
+        }
+    }
+    for item in other {
+        match formid(&item).map(|id| (id, positions.get(&id).copied())) {
+            Some((_, Some(index))) => {
+                if policy != ConflictPolicy::KeepFirst {
+                    items[index] = item;
+                }
+            }
+            Some((id, None)) => {
+                positions.insert(id, items.len());
+                items.push(item);
+            }
+            None => items.push(item),
+        }
+    }
+    Ok(())
+}
+
+/// Splits the entries of a group that wasn't converted into each record along with the groups
+/// after it, which hold its children. Groups before the first record are a unit of their own
+pub(crate) fn entry_units(entries: Vec<GroupEntry<'_>>) -> Vec<Vec<GroupEntry<'_>>> {
+    let mut units: Vec<Vec<GroupEntry<'_>>> = Vec::new();
+    for entry in entries {
+        match (&entry, units.last_mut()) {
+            (GroupEntry::Group(_), Some(unit)) => unit.push(entry),
+            _ => units.push(vec![entry]),
+        }
+    }
+    units
+}
+
+/// What an entry of a group that wasn't converted is matched by when merging: records by their
+/// form id, and groups by their type and label
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+enum EntryKey {
+    Record(FormId),
+    Group(i32, [u8; 4]),
+}
+impl EntryKey {
+    fn of(entry: &GroupEntry<'_>) -> EntryKey {
+        match entry {
+            GroupEntry::Group(group) => {
+                EntryKey::Group(group.group_type.code(), group.group_type.get_label())
+            }
+            // Records always have an id
+            record => EntryKey::Record(record.formid().unwrap()),
+        }
+    }
+}
+
+/// Position of each entry within [entries], by what it is matched by
+fn entry_positions(entries: &[GroupEntry<'_>]) -> HashMap<EntryKey, usize> {
+    entries
+        .iter()
+        .enumerate()
+        .map(|(index, entry)| (EntryKey::of(entry), index))
+        .collect()
+}
+
+/// The first record of [other] that has the same form id as one within [entries], looking
+/// within the groups that both have
+fn find_conflict(entries: &[GroupEntry<'_>], other: &[GroupEntry<'_>]) -> Option<FormId> {
+    let positions = entry_positions(entries);
+    other.iter().find_map(|entry| {
+        let position = *positions.get(&EntryKey::of(entry))?;
+        match (&entries[position], entry) {
+            (GroupEntry::Group(group), GroupEntry::Group(other)) => {
+                find_conflict(&group.entries, &other.entries)
+            }
+            (_, record) => record.formid(),
+        }
+    })
+}
+
+/// Merges the entries of a group that wasn't converted. Groups with the same type and label are
+/// merged in turn, so that the children of a record are kept whichever version of it is, and
+/// [policy] decides between records with the same form id. Groups that are new are placed after
+/// the record they follow in [other], and new records are added to the end
+fn merge_entries<'data>(
+    entries: &mut Vec<GroupEntry<'data>>,
+    other: Vec<GroupEntry<'data>>,
+    policy: ConflictPolicy,
+) -> Result<(), EditError> {
+    if policy == ConflictPolicy::Error {
+        if let Some(id) = find_conflict(entries, &other) {
+            return Err(EditError::Exists(id));
+        }
+    }
+    let positions = entry_positions(entries);
+    // New groups, by the position of the record that they follow, None for those before the
+    // first record
+    let mut added: HashMap<Option<usize>, Vec<GroupEntry<'data>>> = HashMap::new();
+    let mut appended = Vec::new();
+    // Position of the last record of [other] within [entries], None if it was appended
+    let mut after = Some(None);
+    for entry in other {
+        match (positions.get(&EntryKey::of(&entry)), entry) {
+            (Some(&position), GroupEntry::Group(group)) => {
+                if let GroupEntry::Group(merged) = &mut entries[position] {
+                    merge_entries(&mut merged.entries, group.entries, policy)?;
+                }
+            }
+            (None, group @ GroupEntry::Group(_)) => match after {
+                Some(position) => added.entry(position).or_default().push(group),
+                None => appended.push(group),
+            },
+            (Some(&position), record) => {
+                if policy == ConflictPolicy::KeepLast {
+                    entries[position] = record;
+                }
+                after = Some(Some(position));
+            }
+            (None, record) => {
+                appended.push(record);
+                after = None;
+            }
+        }
+    }
+    let mut merged = Vec::with_capacity(entries.len() + appended.len());
+    let mut record = None;
+    for (index, entry) in std::mem::take(entries).into_iter().enumerate() {
+        if !matches!(entry, GroupEntry::Group(_)) {
+            merged.extend(added.remove(&record).into_iter().flatten());
+            record = Some(index);
+        }
+        merged.push(entry);
+    }
+    merged.extend(added.remove(&record).into_iter().flatten());
+    merged.extend(appended);
+    *entries = merged;
+    Ok(())
+}
+
+/// Sorts the entries of a group that wasn't converted, and those of the groups within it, keeping
+/// each record's children after it. The responses of a topic are left in their order, as that is
+/// the order that the game tries them in
+fn sort_entries(entries: &mut Vec<GroupEntry<'_>>) {
+    for entry in entries.iter_mut() {
+        if let GroupEntry::Group(group) = entry {
+            if !matches!(group.group_type, GroupType::TopicChildren(_)) {
+                sort_entries(&mut group.entries);
+            }
+        }
+    }
+    let mut units = entry_units(std::mem::take(entries));
+    // Groups without a record before them have no form id, and so stay first
+    units.sort_by_key(|unit| unit.first().and_then(GroupEntry::formid).map(|x| x.id));
+    *entries = units.into_iter().flatten().collect();
+}
+
+/// A record kept unconverted within a typed group, as [RecordRef::Deleted] if it is flagged as
+/// such and [RecordRef::Unknown] if it failed to convert
+fn kept_record_ref<'a, 'data>(record: &'a GeneralRecord<'data>) -> RecordRef<'a, 'data> {
+    if is_deleted(record) {
+        RecordRef::Deleted(record)
+    } else {
+        RecordRef::Unknown(record)
+    }
+}
+
+/// Mutable version of [kept_record_ref]
+fn kept_record_mut<'a, 'data>(record: &'a mut GeneralRecord<'data>) -> RecordMut<'a, 'data> {
+    if is_deleted(record) {
+        RecordMut::Deleted(record)
+    } else {
+        RecordMut::Unknown(record)
+    }
+}
+
+/// How [group_type] is named in errors
+fn describe(group_type: GroupType<'_>) -> String {
+    match group_type {
+        GroupType::Top(label) => label.to_string(),
+        group_type => format!(
+            "type {} [{:08X}]",
+            group_type.code(),
+            u32::from_le_bytes(group_type.get_label())
+        ),
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, From)]
 pub enum Group<'data> {
     AACT(aact::AACTGroup<'data>),
@@ -37,9 +264,567 @@ pub enum Group<'data> {
     ASTP(astp::ASTPGroup<'data>),
     AVIF(avif::AVIFGroup<'data>),
     BOOK(book::BOOKGroup<'data>),
+    FLST(flst::FLSTGroup<'data>),
     Unknown(common::GeneralGroup<'data>),
     UnknownTop(common::TopGroup<'data>),
 }
+impl<'data> Group<'data> {
+    /// Converts [group] into its typed form, if we know of one
+    pub fn from_top(
+        group: common::TopGroup<'data>,
+        ctx: &mut ParseContext,
+    ) -> Result<Group<'data>, common::FromTopGroupError<'data>> {
+        Ok(match group.label.as_ref() {
+            b"AACT" => aact::AACTGroup::from_top_group(group, ctx)?.1.into(),
+            b"ACTI" => acti::ACTIGroup::from_top_group(group, ctx)?.1.into(),
+            b"ADDN" => addn::ADDNGroup::from_top_group(group, ctx)?.1.into(),
+            b"ALCH" => alch::ALCHGroup::from_top_group(group, ctx)?.1.into(),
+            b"AMMO" => ammo::AMMOGroup::from_top_group(group, ctx)?.1.into(),
+            b"ANIO" => anio::ANIOGroup::from_top_group(group, ctx)?.1.into(),
+            b"APPA" => appa::APPAGroup::from_top_group(group, ctx)?.1.into(),
+            b"ARMA" => arma::ARMAGroup::from_top_group(group, ctx)?.1.into(),
+            b"ARMO" => armo::ARMOGroup::from_top_group(group, ctx)?.1.into(),
+            b"ARTO" => arto::ARTOGroup::from_top_group(group, ctx)?.1.into(),
+            b"ASPC" => aspc::ASPCGroup::from_top_group(group, ctx)?.1.into(),
+            b"ASTP" => astp::ASTPGroup::from_top_group(group, ctx)?.1.into(),
+            b"AVIF" => avif::AVIFGroup::from_top_group(group, ctx)?.1.into(),
+            b"BOOK" => book::BOOKGroup::from_top_group(group, ctx)?.1.into(),
+            b"FLST" => flst::FLSTGroup::from_top_group(group, ctx)?.1.into(),
+            _ => {
+                let mut group = group;
+                ctx.process_unconverted(&mut group.entries);
+                group.into()
+            }
+        })
+    }
+
+    /// A new top group for the type of [record], holding it. Records of types that we don't
+    /// convert the groups of get an unconverted group. None for the TES4 record and ACHRs, as they
+    /// aren't held by top groups
+    pub fn from_record(record: Record<'data>) -> Option<Group<'data>> {
+        Some(match record {
+            Record::AACT(x) => aact::AACTGroup::from_records(vec![x]).into(),
+            Record::ACTI(x) => acti::ACTIGroup::from_records(vec![x]).into(),
+            Record::ADDN(x) => addn::ADDNGroup::from_records(vec![x]).into(),
+            Record::ALCH(x) => alch::ALCHGroup::from_records(vec![x]).into(),
+            Record::AMMO(x) => ammo::AMMOGroup::from_records(vec![x]).into(),
+            Record::ANIO(x) => anio::ANIOGroup::from_records(vec![x]).into(),
+            Record::APPA(x) => appa::APPAGroup::from_records(vec![x]).into(),
+            Record::ARMA(x) => arma::ARMAGroup::from_records(vec![x]).into(),
+            Record::ARMO(x) => armo::ARMOGroup::from_records(vec![x]).into(),
+            Record::ARTO(x) => arto::ARTOGroup::from_records(vec![x]).into(),
+            Record::ASPC(x) => aspc::ASPCGroup::from_records(vec![x]).into(),
+            Record::ASTP(x) => astp::ASTPGroup::from_records(vec![x]).into(),
+            Record::AVIF(x) => avif::AVIFGroup::from_records(vec![x]).into(),
+            Record::BOOK(x) => book::BOOKGroup::from_records(vec![x]).into(),
+            Record::FLST(x) => flst::FLSTGroup::from_records(vec![x]).into(),
+            record @ (Record::Deleted(_) | Record::Unknown(_)) => {
+                let deleted = matches!(record, Record::Deleted(_));
+                let group = common::TopGroup::from_records(record.type_name(), vec![record])
+                    .expect("The group is labelled with the type of its record");
+                if !deleted {
+                    return Some(group.into());
+                }
+                // Deleted records aren't converted, so this can't fail
+                Group::from_top(group.clone(), &mut ParseContext::default())
+                    .unwrap_or_else(|_| group.into())
+            }
+            Record::TES4(_) | Record::ACHR(_) => return None,
+        })
+    }
+
+    /// Amount of records within the group, including those that aren't converted and those
+    /// within the groups of unknown groups
+    pub fn record_count(&self) -> usize {
+        match self {
+            Group::Unknown(x) => common::count_records(&x.entries),
+            Group::UnknownTop(x) => common::count_records(&x.entries),
+            // The records include those that are kept, so only the compressed ones are missing
+            group => {
+                group.records().len()
+                    + group
+                        .kept()
+                        .iter()
+                        .filter(|x| matches!(x.entry, GroupEntry::Compressed(_)))
+                        .count()
+            }
+        }
+    }
+
+    /// The entries of a typed group that aren't converted. Unknown groups have none, as none of
+    /// their entries are converted
+    pub fn kept(&self) -> &[common::KeptEntry<'data>] {
+        match self {
+            Group::AACT(x) => &x.kept,
+            Group::ACTI(x) => &x.kept,
+            Group::ADDN(x) => &x.kept,
+            Group::ALCH(x) => &x.kept,
+            Group::AMMO(x) => &x.kept,
+            Group::ANIO(x) => &x.kept,
+            Group::APPA(x) => &x.kept,
+            Group::ARMA(x) => &x.kept,
+            Group::ARMO(x) => &x.kept,
+            Group::ARTO(x) => &x.kept,
+            Group::ASPC(x) => &x.kept,
+            Group::ASTP(x) => &x.kept,
+            Group::AVIF(x) => &x.kept,
+            Group::BOOK(x) => &x.kept,
+            Group::FLST(x) => &x.kept,
+            Group::Unknown(_) | Group::UnknownTop(_) => &[],
+        }
+    }
+
+    /// The amount of converted records of a typed group, along with its kept entries
+    fn kept_mut(&mut self) -> Option<(usize, &mut Vec<common::KeptEntry<'data>>)> {
+        match self {
+            Group::AACT(x) => Some((x.records.len(), &mut x.kept)),
+            Group::ACTI(x) => Some((x.records.len(), &mut x.kept)),
+            Group::ADDN(x) => Some((x.records.len(), &mut x.kept)),
+            Group::ALCH(x) => Some((x.records.len(), &mut x.kept)),
+            Group::AMMO(x) => Some((x.records.len(), &mut x.kept)),
+            Group::ANIO(x) => Some((x.records.len(), &mut x.kept)),
+            Group::APPA(x) => Some((x.records.len(), &mut x.kept)),
+            Group::ARMA(x) => Some((x.records.len(), &mut x.kept)),
+            Group::ARMO(x) => Some((x.records.len(), &mut x.kept)),
+            Group::ARTO(x) => Some((x.records.len(), &mut x.kept)),
+            Group::ASPC(x) => Some((x.records.len(), &mut x.kept)),
+            Group::ASTP(x) => Some((x.records.len(), &mut x.kept)),
+            Group::AVIF(x) => Some((x.records.len(), &mut x.kept)),
+            Group::BOOK(x) => Some((x.records.len(), &mut x.kept)),
+            Group::FLST(x) => Some((x.records.len(), &mut x.kept)),
+            Group::Unknown(_) | Group::UnknownTop(_) => None,
+        }
+    }
+
+    /// Adds [record] to the end of the group if it is the top group of its type. Otherwise the
+    /// record is given back
+    pub fn push_record(&mut self, record: Record<'data>) -> Option<Record<'data>> {
+        if let Record::Deleted(x) = &record {
+            if self.group_type() == GroupType::Top(x.type_name) {
+                if let Some((before, kept)) = self.kept_mut() {
+                    if let Record::Deleted(x) = record {
+                        kept.push(common::KeptEntry {
+                            before,
+                            entry: x.into(),
+                        });
+                    }
+                    return None;
+                }
+            }
+        }
+        match (self, record) {
+            (Group::AACT(group), Record::AACT(x)) => group.records.push(x),
+            (Group::ACTI(group), Record::ACTI(x)) => group.records.push(x),
+            (Group::ADDN(group), Record::ADDN(x)) => group.records.push(x),
+            (Group::ALCH(group), Record::ALCH(x)) => group.records.push(x),
+            (Group::AMMO(group), Record::AMMO(x)) => group.records.push(x),
+            (Group::ANIO(group), Record::ANIO(x)) => group.records.push(x),
+            (Group::APPA(group), Record::APPA(x)) => group.records.push(x),
+            (Group::ARMA(group), Record::ARMA(x)) => group.records.push(x),
+            (Group::ARMO(group), Record::ARMO(x)) => group.records.push(x),
+            (Group::ARTO(group), Record::ARTO(x)) => group.records.push(x),
+            (Group::ASPC(group), Record::ASPC(x)) => group.records.push(x),
+            (Group::ASTP(group), Record::ASTP(x)) => group.records.push(x),
+            (Group::AVIF(group), Record::AVIF(x)) => group.records.push(x),
+            (Group::BOOK(group), Record::BOOK(x)) => group.records.push(x),
+            (Group::FLST(group), Record::FLST(x)) => group.records.push(x),
+            (Group::UnknownTop(group), Record::Unknown(x) | Record::Deleted(x))
+                if group.label == x.type_name =>
+            {
+                group.entries.push(x.into())
+            }
+            (_, record) => return Some(record),
+        }
+        None
+    }
+
+    /// The records held by the group. Unknown groups have not been parsed, and so have none
+    pub fn records(&self) -> Vec<RecordRef<'_, 'data>> {
+        self.entries()
+            .into_iter()
+            .filter_map(|entry| match entry {
+                EntryRef::Record(record) => Some(record),
+                EntryRef::Compressed(_) => None,
+            })
+            .collect()
+    }
+
+    /// [Group::records], along with the compressed records that the group keeps as they are
+    pub(crate) fn entries(&self) -> Vec<EntryRef<'_, 'data>> {
+        fn refs<'a, 'data, T>(
+            records: &'a [T],
+            kept: &'a [common::KeptEntry<'data>],
+        ) -> Vec<EntryRef<'a, 'data>>
+        where
+            &'a T: Into<RecordRef<'a, 'data>>,
+        {
+            records
+                .iter()
+                .map(|record| EntryRef::Record(record.into()))
+                .chain(kept.iter().filter_map(|x| match &x.entry {
+                    GroupEntry::Record(record) => Some(EntryRef::Record(kept_record_ref(record))),
+                    GroupEntry::Compressed(record) => Some(EntryRef::Compressed(record)),
+                    GroupEntry::Group(_) => None,
+                }))
+                .collect()
+        }
+
+        match self {
+            Group::AACT(x) => refs(&x.records, &x.kept),
+            Group::ACTI(x) => refs(&x.records, &x.kept),
+            Group::ADDN(x) => refs(&x.records, &x.kept),
+            Group::ALCH(x) => refs(&x.records, &x.kept),
+            Group::AMMO(x) => refs(&x.records, &x.kept),
+            Group::ANIO(x) => refs(&x.records, &x.kept),
+            Group::APPA(x) => refs(&x.records, &x.kept),
+            Group::ARMA(x) => refs(&x.records, &x.kept),
+            Group::ARMO(x) => refs(&x.records, &x.kept),
+            Group::ARTO(x) => refs(&x.records, &x.kept),
+            Group::ASPC(x) => refs(&x.records, &x.kept),
+            Group::ASTP(x) => refs(&x.records, &x.kept),
+            Group::AVIF(x) => refs(&x.records, &x.kept),
+            Group::BOOK(x) => refs(&x.records, &x.kept),
+            Group::FLST(x) => refs(&x.records, &x.kept),
+            Group::Unknown(_) | Group::UnknownTop(_) => Vec::new(),
+        }
+    }
+
+    /// Mutable version of [Group::records]
+    pub fn records_mut(&mut self) -> Vec<RecordMut<'_, 'data>> {
+        self.entries_mut()
+            .into_iter()
+            .filter_map(|entry| match entry {
+                EntryMut::Record(record) => Some(record),
+                EntryMut::Compressed(_) => None,
+            })
+            .collect()
+    }
+
+    /// Mutable version of [Group::entries]
+    pub(crate) fn entries_mut(&mut self) -> Vec<EntryMut<'_, 'data>> {
+        fn muts<'a, 'data, T>(
+            records: &'a mut [T],
+            kept: &'a mut [common::KeptEntry<'data>],
+        ) -> Vec<EntryMut<'a, 'data>>
+        where
+            &'a mut T: Into<RecordMut<'a, 'data>>,
+        {
+            records
+                .iter_mut()
+                .map(|record| EntryMut::Record(record.into()))
+                .chain(kept.iter_mut().filter_map(|x| match &mut x.entry {
+                    GroupEntry::Record(record) => Some(EntryMut::Record(kept_record_mut(record))),
+                    GroupEntry::Compressed(record) => Some(EntryMut::Compressed(record)),
+                    GroupEntry::Group(_) => None,
+                }))
+                .collect()
+        }
+
+        match self {
+            Group::AACT(x) => muts(&mut x.records, &mut x.kept),
+            Group::ACTI(x) => muts(&mut x.records, &mut x.kept),
+            Group::ADDN(x) => muts(&mut x.records, &mut x.kept),
+            Group::ALCH(x) => muts(&mut x.records, &mut x.kept),
+            Group::AMMO(x) => muts(&mut x.records, &mut x.kept),
+            Group::ANIO(x) => muts(&mut x.records, &mut x.kept),
+            Group::APPA(x) => muts(&mut x.records, &mut x.kept),
+            Group::ARMA(x) => muts(&mut x.records, &mut x.kept),
+            Group::ARMO(x) => muts(&mut x.records, &mut x.kept),
+            Group::ARTO(x) => muts(&mut x.records, &mut x.kept),
+            Group::ASPC(x) => muts(&mut x.records, &mut x.kept),
+            Group::ASTP(x) => muts(&mut x.records, &mut x.kept),
+            Group::AVIF(x) => muts(&mut x.records, &mut x.kept),
+            Group::BOOK(x) => muts(&mut x.records, &mut x.kept),
+            Group::FLST(x) => muts(&mut x.records, &mut x.kept),
+            Group::Unknown(_) | Group::UnknownTop(_) => Vec::new(),
+        }
+    }
+
+    /// The record at [index] within [Group::records], without gathering the others
+    pub fn record(&self, index: usize) -> Option<RecordRef<'_, 'data>> {
+        fn get<'a, 'data, T>(
+            records: &'a [T],
+            kept: &'a [common::KeptEntry<'data>],
+            index: usize,
+        ) -> Option<RecordRef<'a, 'data>>
+        where
+            &'a T: Into<RecordRef<'a, 'data>>,
+        {
+            match records.get(index) {
+                Some(record) => Some(record.into()),
+                None => kept
+                    .iter()
+                    .filter_map(|x| match &x.entry {
+                        GroupEntry::Record(record) => Some(kept_record_ref(record)),
+                        _ => None,
+                    })
+                    .nth(index - records.len()),
+            }
+        }
+
+        match self {
+            Group::AACT(x) => get(&x.records, &x.kept, index),
+            Group::ACTI(x) => get(&x.records, &x.kept, index),
+            Group::ADDN(x) => get(&x.records, &x.kept, index),
+            Group::ALCH(x) => get(&x.records, &x.kept, index),
+            Group::AMMO(x) => get(&x.records, &x.kept, index),
+            Group::ANIO(x) => get(&x.records, &x.kept, index),
+            Group::APPA(x) => get(&x.records, &x.kept, index),
+            Group::ARMA(x) => get(&x.records, &x.kept, index),
+            Group::ARMO(x) => get(&x.records, &x.kept, index),
+            Group::ARTO(x) => get(&x.records, &x.kept, index),
+            Group::ASPC(x) => get(&x.records, &x.kept, index),
+            Group::ASTP(x) => get(&x.records, &x.kept, index),
+            Group::AVIF(x) => get(&x.records, &x.kept, index),
+            Group::BOOK(x) => get(&x.records, &x.kept, index),
+            Group::FLST(x) => get(&x.records, &x.kept, index),
+            Group::Unknown(_) | Group::UnknownTop(_) => None,
+        }
+    }
+
+    /// Mutable version of [Group::record]
+    pub fn record_mut(&mut self, index: usize) -> Option<RecordMut<'_, 'data>> {
+        fn get<'a, 'data, T>(
+            records: &'a mut [T],
+            kept: &'a mut [common::KeptEntry<'data>],
+            index: usize,
+        ) -> Option<RecordMut<'a, 'data>>
+        where
+            &'a mut T: Into<RecordMut<'a, 'data>>,
+        {
+            let len = records.len();
+            match records.get_mut(index) {
+                Some(record) => Some(record.into()),
+                None => kept
+                    .iter_mut()
+                    .filter_map(|x| match &mut x.entry {
+                        GroupEntry::Record(record) => Some(kept_record_mut(record)),
+                        _ => None,
+                    })
+                    .nth(index - len),
+            }
+        }
+
+        match self {
+            Group::AACT(x) => get(&mut x.records, &mut x.kept, index),
+            Group::ACTI(x) => get(&mut x.records, &mut x.kept, index),
+            Group::ADDN(x) => get(&mut x.records, &mut x.kept, index),
+            Group::ALCH(x) => get(&mut x.records, &mut x.kept, index),
+            Group::AMMO(x) => get(&mut x.records, &mut x.kept, index),
+            Group::ANIO(x) => get(&mut x.records, &mut x.kept, index),
+            Group::APPA(x) => get(&mut x.records, &mut x.kept, index),
+            Group::ARMA(x) => get(&mut x.records, &mut x.kept, index),
+            Group::ARMO(x) => get(&mut x.records, &mut x.kept, index),
+            Group::ARTO(x) => get(&mut x.records, &mut x.kept, index),
+            Group::ASPC(x) => get(&mut x.records, &mut x.kept, index),
+            Group::ASTP(x) => get(&mut x.records, &mut x.kept, index),
+            Group::AVIF(x) => get(&mut x.records, &mut x.kept, index),
+            Group::BOOK(x) => get(&mut x.records, &mut x.kept, index),
+            Group::FLST(x) => get(&mut x.records, &mut x.kept, index),
+            Group::Unknown(_) | Group::UnknownTop(_) => None,
+        }
+    }
+
+    /// Keeps only the records that [keep] returns true for. Unknown groups are left as they are
+    pub fn retain_records<F>(&mut self, mut keep: F)
+    where
+        F: FnMut(RecordRef<'_, 'data>) -> bool,
+    {
+        fn retain<'data, T, F>(
+            records: &mut Vec<T>,
+            kept: &mut Vec<common::KeptEntry<'data>>,
+            keep: &mut F,
+        ) where
+            for<'a> &'a T: Into<RecordRef<'a, 'data>>,
+            F: FnMut(RecordRef<'_, 'data>) -> bool,
+        {
+            let mut entries = TypedEntry::join(std::mem::take(records), std::mem::take(kept));
+            entries.retain(|entry| match entry {
+                TypedEntry::Record(x) => keep(x.into()),
+                TypedEntry::Kept(GroupEntry::Record(x)) => keep(kept_record_ref(x)),
+                TypedEntry::Kept(_) => true,
+            });
+            let (new_records, new_kept) = TypedEntry::split(entries);
+            *records = new_records;
+            *kept = new_kept;
+        }
+
+        match self {
+            Group::AACT(x) => retain(&mut x.records, &mut x.kept, &mut keep),
+            Group::ACTI(x) => retain(&mut x.records, &mut x.kept, &mut keep),
+            Group::ADDN(x) => retain(&mut x.records, &mut x.kept, &mut keep),
+            Group::ALCH(x) => retain(&mut x.records, &mut x.kept, &mut keep),
+            Group::AMMO(x) => retain(&mut x.records, &mut x.kept, &mut keep),
+            Group::ANIO(x) => retain(&mut x.records, &mut x.kept, &mut keep),
+            Group::APPA(x) => retain(&mut x.records, &mut x.kept, &mut keep),
+            Group::ARMA(x) => retain(&mut x.records, &mut x.kept, &mut keep),
+            Group::ARMO(x) => retain(&mut x.records, &mut x.kept, &mut keep),
+            Group::ARTO(x) => retain(&mut x.records, &mut x.kept, &mut keep),
+            Group::ASPC(x) => retain(&mut x.records, &mut x.kept, &mut keep),
+            Group::ASTP(x) => retain(&mut x.records, &mut x.kept, &mut keep),
+            Group::AVIF(x) => retain(&mut x.records, &mut x.kept, &mut keep),
+            Group::BOOK(x) => retain(&mut x.records, &mut x.kept, &mut keep),
+            Group::FLST(x) => retain(&mut x.records, &mut x.kept, &mut keep),
+            Group::Unknown(_) | Group::UnknownTop(_) => {}
+        }
+    }
+
+    /// Adds the records of [other], which must be of the same type and label, to the end of this
+    /// group. Records with the same form id are overrides of one another, and [policy] decides
+    /// which is kept. The groups within groups that weren't converted are merged with those of
+    /// the same type and label, see [merge_entries]. Fails without changing anything if the
+    /// groups differ, or if [policy] is [ConflictPolicy::Error] and there are such records
+    pub fn merge(&mut self, other: Group<'data>, policy: ConflictPolicy) -> Result<(), EditError> {
+        fn merge<'data, T>(
+            records: &mut Vec<T>,
+            kept: &mut Vec<common::KeptEntry<'data>>,
+            other: (Vec<T>, Vec<common::KeptEntry<'data>>),
+            policy: ConflictPolicy,
+        ) -> Result<(), EditError>
+        where
+            for<'a> &'a T: Into<RecordRef<'a, 'data>>,
+        {
+            let mut entries = TypedEntry::join(std::mem::take(records), std::mem::take(kept));
+            let other = TypedEntry::join(other.0, other.1);
+            let result = merge_by_formid(&mut entries, other, policy, TypedEntry::formid);
+            let (new_records, new_kept) = TypedEntry::split(entries);
+            *records = new_records;
+            *kept = new_kept;
+            result
+        }
+
+        match (self, other) {
+            (Group::AACT(x), Group::AACT(y)) => {
+                merge(&mut x.records, &mut x.kept, (y.records, y.kept), policy)
+            }
+            (Group::ACTI(x), Group::ACTI(y)) => {
+                merge(&mut x.records, &mut x.kept, (y.records, y.kept), policy)
+            }
+            (Group::ADDN(x), Group::ADDN(y)) => {
+                merge(&mut x.records, &mut x.kept, (y.records, y.kept), policy)
+            }
+            (Group::ALCH(x), Group::ALCH(y)) => {
+                merge(&mut x.records, &mut x.kept, (y.records, y.kept), policy)
+            }
+            (Group::AMMO(x), Group::AMMO(y)) => {
+                merge(&mut x.records, &mut x.kept, (y.records, y.kept), policy)
+            }
+            (Group::ANIO(x), Group::ANIO(y)) => {
+                merge(&mut x.records, &mut x.kept, (y.records, y.kept), policy)
+            }
+            (Group::APPA(x), Group::APPA(y)) => {
+                merge(&mut x.records, &mut x.kept, (y.records, y.kept), policy)
+            }
+            (Group::ARMA(x), Group::ARMA(y)) => {
+                merge(&mut x.records, &mut x.kept, (y.records, y.kept), policy)
+            }
+            (Group::ARMO(x), Group::ARMO(y)) => {
+                merge(&mut x.records, &mut x.kept, (y.records, y.kept), policy)
+            }
+            (Group::ARTO(x), Group::ARTO(y)) => {
+                merge(&mut x.records, &mut x.kept, (y.records, y.kept), policy)
+            }
+            (Group::ASPC(x), Group::ASPC(y)) => {
+                merge(&mut x.records, &mut x.kept, (y.records, y.kept), policy)
+            }
+            (Group::ASTP(x), Group::ASTP(y)) => {
+                merge(&mut x.records, &mut x.kept, (y.records, y.kept), policy)
+            }
+            (Group::AVIF(x), Group::AVIF(y)) => {
+                merge(&mut x.records, &mut x.kept, (y.records, y.kept), policy)
+            }
+            (Group::BOOK(x), Group::BOOK(y)) => {
+                merge(&mut x.records, &mut x.kept, (y.records, y.kept), policy)
+            }
+            (Group::FLST(x), Group::FLST(y)) => {
+                merge(&mut x.records, &mut x.kept, (y.records, y.kept), policy)
+            }
+            (Group::Unknown(x), Group::Unknown(y)) if x.group_type == y.group_type => {
+                merge_entries(&mut x.entries, y.entries, policy)
+            }
+            (Group::UnknownTop(x), Group::UnknownTop(y)) if x.label == y.label => {
+                merge_entries(&mut x.entries, y.entries, policy)
+            }
+            (group, other) => Err(EditError::GroupMismatch {
+                group: describe(group.group_type()),
+                other: describe(other.group_type()),
+            }),
+        }
+    }
+
+    /// Sorts the records of the group into form id order, which is the order that the Creation
+    /// Kit writes them in. Records keep the groups of their children after them, and the
+    /// records within those are sorted as well
+    pub fn sort_records(&mut self) {
+        fn sort<'data, T>(records: &mut Vec<T>, kept: &mut Vec<common::KeptEntry<'data>>)
+        where
+            for<'a> &'a T: Into<RecordRef<'a, 'data>>,
+        {
+            let mut entries = TypedEntry::join(std::mem::take(records), std::mem::take(kept));
+            entries.sort_by_key(|x| x.formid().map(|x| x.id));
+            let (new_records, new_kept) = TypedEntry::split(entries);
+            *records = new_records;
+            *kept = new_kept;
+        }
+
+        match self {
+            Group::AACT(x) => sort(&mut x.records, &mut x.kept),
+            Group::ACTI(x) => sort(&mut x.records, &mut x.kept),
+            Group::ADDN(x) => sort(&mut x.records, &mut x.kept),
+            Group::ALCH(x) => sort(&mut x.records, &mut x.kept),
+            Group::AMMO(x) => sort(&mut x.records, &mut x.kept),
+            Group::ANIO(x) => sort(&mut x.records, &mut x.kept),
+            Group::APPA(x) => sort(&mut x.records, &mut x.kept),
+            Group::ARMA(x) => sort(&mut x.records, &mut x.kept),
+            Group::ARMO(x) => sort(&mut x.records, &mut x.kept),
+            Group::ARTO(x) => sort(&mut x.records, &mut x.kept),
+            Group::ASPC(x) => sort(&mut x.records, &mut x.kept),
+            Group::ASTP(x) => sort(&mut x.records, &mut x.kept),
+            Group::AVIF(x) => sort(&mut x.records, &mut x.kept),
+            Group::BOOK(x) => sort(&mut x.records, &mut x.kept),
+            Group::FLST(x) => sort(&mut x.records, &mut x.kept),
+            Group::Unknown(x) => sort_entries(&mut x.entries),
+            Group::UnknownTop(x) => sort_entries(&mut x.entries),
+        }
+    }
+}
+impl<'data> Group<'data> {
+    pub fn common(&self) -> &common::CommonGroupInfo {
+        dispatch_all!(
+            Group,
+            self,
+            [
+                AACT, ACTI, ADDN, ALCH, AMMO, ANIO, APPA, ARMA, ARMO, ARTO, ASPC, ASTP, AVIF, BOOK,
+                FLST, Unknown, UnknownTop
+            ],
+            x,
+            { &x.common }
+        )
+    }
+
+    /// The type and label that the group is written with
+    pub fn group_type(&self) -> common::GroupType<'data> {
+        match self {
+            Group::AACT(x) => common::GroupType::Top(x.type_name()),
+            Group::ACTI(x) => common::GroupType::Top(x.type_name()),
+            Group::ADDN(x) => common::GroupType::Top(x.type_name()),
+            Group::ALCH(x) => common::GroupType::Top(x.type_name()),
+            Group::AMMO(x) => common::GroupType::Top(x.type_name()),
+            Group::ANIO(x) => common::GroupType::Top(x.type_name()),
+            Group::APPA(x) => common::GroupType::Top(x.type_name()),
+            Group::ARMA(x) => common::GroupType::Top(x.type_name()),
+            Group::ARMO(x) => common::GroupType::Top(x.type_name()),
+            Group::ARTO(x) => common::GroupType::Top(x.type_name()),
+            Group::ASPC(x) => common::GroupType::Top(x.type_name()),
+            Group::ASTP(x) => common::GroupType::Top(x.type_name()),
+            Group::AVIF(x) => common::GroupType::Top(x.type_name()),
+            Group::BOOK(x) => common::GroupType::Top(x.type_name()),
+            Group::FLST(x) => common::GroupType::Top(x.type_name()),
+            Group::Unknown(x) => x.group_type,
+            Group::UnknownTop(x) => common::GroupType::Top(x.label),
+        }
+    }
+}
 /*impl<'data> TypeNamed<'data> for Group<'data> {
     fn type_name(&self) -> &'data BStr {
         dispatch_all!(Group, self, [AACT, Unknown], x, { x.type_name() })
@@ -52,7 +837,7 @@ impl<'data> DataSize for Group<'data> {
             self,
             [
                 AACT, ACTI, ADDN, ALCH, AMMO, ANIO, APPA, ARMA, ARMO, ARTO, ASPC, ASTP, AVIF, BOOK,
-                Unknown, UnknownTop
+                FLST, Unknown, UnknownTop
             ],
             x,
             { x.data_size() }
@@ -60,7 +845,7 @@ impl<'data> DataSize for Group<'data> {
     }
 }
 impl<'data> Writable for Group<'data> {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: std::io::Write,
     {
@@ -69,10 +854,302 @@ impl<'data> Writable for Group<'data> {
             self,
             [
                 AACT, ACTI, ADDN, ALCH, AMMO, ANIO, APPA, ARMA, ARMO, ARTO, ASPC, ASTP, AVIF, BOOK,
-                Unknown, UnknownTop
+                FLST, Unknown, UnknownTop
             ],
             x,
             { x.write_to(w) }
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        error::{ErrorCode, VivecError},
+        records::common::{CommonRecordInfo, GeneralRecord},
+        records::fields::common::GeneralField,
+    };
+    use bstr::ByteSlice;
+    use common::{CommonGroupInfo, GeneralGroup, TopGroup};
+
+    fn general<'data>(
+        type_name: &'static [u8],
+        id: u32,
+        fields: Vec<GeneralField<'data>>,
+    ) -> GeneralRecord<'data> {
+        let mut common = CommonRecordInfo::test_default();
+        common.flags.flags = 0;
+        common.id = id;
+        GeneralRecord {
+            type_name: type_name.as_bstr(),
+            common,
+            fields,
+        }
+    }
+
+    fn lists<'data>(lists: &[(u32, &'data [u8])]) -> Group<'data> {
+        let mut group = None;
+        for (id, entry) in lists {
+            let fields = vec![GeneralField::new(b"LNAM".as_bstr(), entry)];
+            let record = general(b"FLST", *id, fields);
+            let record = Record::from_general(record, &mut ParseContext::default()).unwrap();
+            match &mut group {
+                None => group = Group::from_record(record),
+                Some(group) => assert!(group.push_record(record).is_none()),
+            }
+        }
+        group.unwrap()
+    }
+
+    fn entries(group: &Group<'_>) -> Vec<(u32, Vec<u32>)> {
+        group
+            .records()
+            .into_iter()
+            .map(|x| {
+                (
+                    x.formid().id,
+                    x.formids().into_iter().map(|x| x.id).collect(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_merge() {
+        let first = lists(&[(0x800, &[1, 0, 0, 0]), (0x801, &[2, 0, 0, 0])]);
+        let second = lists(&[(0x801, &[3, 0, 0, 0]), (0x802, &[4, 0, 0, 0])]);
+
+        let mut group = first.clone();
+        group
+            .merge(second.clone(), ConflictPolicy::default())
+            .unwrap();
+        assert_eq!(
+            entries(&group),
+            vec![(0x800, vec![1]), (0x801, vec![3]), (0x802, vec![4])]
+        );
+        let mut group = first.clone();
+        group
+            .merge(second.clone(), ConflictPolicy::KeepFirst)
+            .unwrap();
+        assert_eq!(
+            entries(&group),
+            vec![(0x800, vec![1]), (0x801, vec![2]), (0x802, vec![4])]
+        );
+        let mut group = first.clone();
+        assert_eq!(
+            group.merge(second, ConflictPolicy::Error),
+            Err(EditError::Exists(FormId::new(0x801)))
+        );
+        assert_eq!(group, first);
+
+        let other = Group::UnknownTop(TopGroup {
+            label: b"DIAL".as_bstr(),
+            common: CommonGroupInfo::new(),
+            entries: Vec::new(),
+        });
+        let error = group.merge(other, ConflictPolicy::KeepLast).unwrap_err();
+        assert_eq!(error.code(), ErrorCode::GroupMismatch);
+        assert_eq!(
+            error.to_string(),
+            "Can't merge the FLST group with the DIAL group"
+        );
+    }
+
+    #[test]
+    fn test_merge_children() {
+        fn topics<'data>(topics: &[(u32, &[u32])]) -> Group<'data> {
+            let mut entries = Vec::new();
+            for (id, responses) in topics {
+                entries.push(general(b"DIAL", *id, Vec::new()).into());
+                if !responses.is_empty() {
+                    entries.push(GroupEntry::Group(GeneralGroup {
+                        group_type: GroupType::TopicChildren(FormId::new(*id)),
+                        common: CommonGroupInfo::new(),
+                        entries: responses
+                            .iter()
+                            .map(|x| general(b"INFO", *x, Vec::new()).into())
+                            .collect(),
+                    }));
+                }
+            }
+            Group::UnknownTop(TopGroup {
+                label: b"DIAL".as_bstr(),
+                common: CommonGroupInfo::new(),
+                entries,
+            })
+        }
+
+        let mut group = topics(&[(0x820, &[0x821]), (0x822, &[])]);
+        group
+            .merge(
+                topics(&[(0x820, &[0x823]), (0x824, &[0x825])]),
+                ConflictPolicy::KeepLast,
+            )
+            .unwrap();
+        // The responses of both versions of a topic are kept
+        assert_eq!(
+            group,
+            topics(&[(0x820, &[0x821, 0x823]), (0x822, &[]), (0x824, &[0x825])])
+        );
+        group
+            .merge(topics(&[(0x822, &[0x826])]), ConflictPolicy::KeepFirst)
+            .unwrap();
+        assert_eq!(
+            group,
+            topics(&[
+                (0x820, &[0x821, 0x823]),
+                (0x822, &[0x826]),
+                (0x824, &[0x825])
+            ])
+        );
+
+        // Records conflict only with those of the same id within the same group
+        let before = group.clone();
+        assert_eq!(
+            group.merge(topics(&[(0x830, &[0x826])]), ConflictPolicy::Error),
+            Ok(())
+        );
+        assert_eq!(
+            group.merge(before.clone(), ConflictPolicy::Error),
+            Err(EditError::Exists(FormId::new(0x820)))
+        );
+        let mut group = before.clone();
+        let other = Group::UnknownTop(TopGroup {
+            label: b"DIAL".as_bstr(),
+            common: CommonGroupInfo::new(),
+            entries: vec![GroupEntry::Group(GeneralGroup {
+                group_type: GroupType::TopicChildren(FormId::new(0x824)),
+                common: CommonGroupInfo::new(),
+                entries: vec![general(b"INFO", 0x825, Vec::new()).into()],
+            })],
+        });
+        assert_eq!(
+            group.merge(other, ConflictPolicy::Error),
+            Err(EditError::Exists(FormId::new(0x825)))
+        );
+        assert_eq!(group, before);
+    }
+
+    #[test]
+    fn test_merge_blocks() {
+        fn cells<'data>(blocks: &[(u32, u32)]) -> Group<'data> {
+            let mut top = Vec::<GroupEntry<'data>>::new();
+            for (number, id) in blocks {
+                let sub_block = GroupEntry::Group(GeneralGroup {
+                    group_type: GroupType::InteriorSubCellBlock(0),
+                    common: CommonGroupInfo::new(),
+                    entries: vec![general(b"CELL", *id, Vec::new()).into()],
+                });
+                top.push(GroupEntry::Group(GeneralGroup {
+                    group_type: GroupType::InteriorCellBlock(*number),
+                    common: CommonGroupInfo::new(),
+                    entries: vec![sub_block],
+                }));
+            }
+            Group::UnknownTop(TopGroup {
+                label: b"CELL".as_bstr(),
+                common: CommonGroupInfo::new(),
+                entries: top,
+            })
+        }
+
+        // Blocks with the same number become one, holding the cells of both
+        let mut group = cells(&[(0, 0x800), (1, 0x801)]);
+        group
+            .merge(cells(&[(1, 0x811), (2, 0x812)]), ConflictPolicy::KeepLast)
+            .unwrap();
+        let top = match &group {
+            Group::UnknownTop(top) => top,
+            _ => unreachable!(),
+        };
+        assert_eq!(top.entries.len(), 3);
+        let mut ids = Vec::new();
+        walk_ids(&top.entries, &mut ids);
+        assert_eq!(ids, vec![0x800, 0x801, 0x811, 0x812]);
+    }
+
+    fn walk_ids(entries: &[GroupEntry<'_>], ids: &mut Vec<u32>) {
+        for entry in entries {
+            match entry {
+                GroupEntry::Group(group) => walk_ids(&group.entries, ids),
+                record => ids.extend(record.formid().map(|x| x.id)),
+            }
+        }
+    }
+
+    #[test]
+    fn test_sort_records() {
+        let mut group = lists(&[
+            (0x803, &[1, 0, 0, 0]),
+            (0x801, &[2, 0, 0, 0]),
+            (0x802, &[3, 0, 0, 0]),
+        ]);
+        group.sort_records();
+        let ids: Vec<u32> = group.records().iter().map(|x| x.formid().id).collect();
+        assert_eq!(ids, vec![0x801, 0x802, 0x803]);
+
+        let children = |id, responses: &[u32]| {
+            GroupEntry::Group(GeneralGroup {
+                group_type: GroupType::TopicChildren(FormId::new(id)),
+                common: CommonGroupInfo::new(),
+                entries: responses
+                    .iter()
+                    .map(|x| general(b"INFO", *x, Vec::new()).into())
+                    .collect(),
+            })
+        };
+        let topic = |id| GroupEntry::from(general(b"DIAL", id, Vec::new()));
+        let top = |entries| {
+            Group::UnknownTop(TopGroup {
+                label: b"DIAL".as_bstr(),
+                common: CommonGroupInfo::new(),
+                entries,
+            })
+        };
+        let mut group = top(vec![
+            topic(0x822),
+            children(0x822, &[0x826, 0x825]),
+            topic(0x821),
+            topic(0x820),
+            children(0x820, &[0x824, 0x823]),
+        ]);
+        group.sort_records();
+        // Topics take their responses along, which keep their order
+        assert_eq!(
+            group,
+            top(vec![
+                topic(0x820),
+                children(0x820, &[0x824, 0x823]),
+                topic(0x821),
+                topic(0x822),
+                children(0x822, &[0x826, 0x825]),
+            ])
+        );
+
+        let cell = FormId::new(0x830);
+        let reference = |id| GroupEntry::from(general(b"REFR", id, Vec::new()));
+        let block = |references| {
+            Group::Unknown(GeneralGroup {
+                group_type: GroupType::InteriorCellBlock(0),
+                common: CommonGroupInfo::new(),
+                entries: vec![
+                    GroupEntry::Group(GeneralGroup {
+                        group_type: GroupType::InteriorSubCellBlock(0),
+                        common: CommonGroupInfo::new(),
+                        entries: Vec::new(),
+                    }),
+                    general(b"CELL", cell.id, Vec::new()).into(),
+                    GroupEntry::Group(GeneralGroup {
+                        group_type: GroupType::CellTemporaryChildren(cell),
+                        common: CommonGroupInfo::new(),
+                        entries: references,
+                    }),
+                ],
+            })
+        };
+        let mut group = block(vec![reference(0x832), reference(0x831)]);
+        group.sort_records();
+        assert_eq!(group, block(vec![reference(0x831), reference(0x832)]));
+    }
+}
@@ -0,0 +1,749 @@
+// The worldspaces of a plugin. The WRLD top group holds each WRLD record followed by a group of
+// its children (type 1, labelled with the WRLD's form id). That holds the worldspace's persistent
+// cell, then its exterior cells bucketed into blocks (type 4) of 32 by 32 cells, each split into
+// sub-blocks (type 5) of 8 by 8 cells, see exterior_block_of. Like the children of cells, these are
+// kept by a plugin as the general groups that it read. The children of a worldspace can be looked
+// at and added to where they are, through WorldChildrenRef and WorldChildrenMut, while
+// Plugin::worldspaces copies the whole group into a WorldspaceTree.
+
+use super::{
+    cell::{cell_refs, cells_from_entries, cells_into_entries, Cell, CellRef},
+    common::{
+        expect_group, exterior_block_of, exterior_sub_block_of, wrong_group, ChildRecord,
+        CommonGroupInfo, GeneralGroup, GroupEntry, GroupStructureError, GroupType, TopGroup,
+    },
+};
+use crate::{
+    plugin::Plugin,
+    records::common::{FormId, TypeNamed},
+    util::Position,
+};
+use bstr::ByteSlice;
+use std::convert::TryFrom;
+
+/// A sub-block of exterior cells, see [exterior_sub_block_of]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExteriorSubBlock<'data> {
+    pub position: Position<i16>,
+    pub common: CommonGroupInfo,
+    pub cells: Vec<Cell<'data>>,
+}
+
+/// A block of exterior cells, see [exterior_block_of]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExteriorBlock<'data> {
+    pub position: Position<i16>,
+    pub common: CommonGroupInfo,
+    pub sub_blocks: Vec<ExteriorSubBlock<'data>>,
+}
+impl<'data> ExteriorBlock<'data> {
+    fn from_group(group: GeneralGroup<'data>) -> Result<ExteriorBlock<'data>, GroupStructureError> {
+        let position = match group.group_type {
+            GroupType::ExteriorCellBlock(position) => position,
+            group_type => return Err(wrong_group(4, group_type)),
+        };
+        let mut sub_blocks = Vec::new();
+        for entry in group.entries {
+            let group = expect_group(entry)?;
+            match group.group_type {
+                GroupType::ExteriorCellSubBlock(position) => sub_blocks.push(ExteriorSubBlock {
+                    position,
+                    common: group.common,
+                    cells: cells_from_entries(group.entries)?,
+                }),
+                group_type => return Err(wrong_group(5, group_type)),
+            }
+        }
+        Ok(ExteriorBlock {
+            position,
+            common: group.common,
+            sub_blocks,
+        })
+    }
+
+    fn into_group(self) -> GeneralGroup<'data> {
+        GeneralGroup {
+            group_type: GroupType::ExteriorCellBlock(self.position),
+            common: self.common,
+            entries: self
+                .sub_blocks
+                .into_iter()
+                .map(|sub_block| {
+                    GroupEntry::Group(GeneralGroup {
+                        group_type: GroupType::ExteriorCellSubBlock(sub_block.position),
+                        common: sub_block.common,
+                        entries: cells_into_entries(sub_block.cells),
+                    })
+                })
+                .collect(),
+        }
+    }
+}
+
+/// The group of the children of a WRLD
+#[derive(Debug, Clone, PartialEq)]
+pub struct WorldChildren<'data> {
+    pub common: CommonGroupInfo,
+    /// Cells held outside of the blocks, which is where the persistent cell is
+    pub cells: Vec<Cell<'data>>,
+    pub blocks: Vec<ExteriorBlock<'data>>,
+}
+impl<'data> WorldChildren<'data> {
+    pub fn new() -> WorldChildren<'data> {
+        WorldChildren {
+            common: CommonGroupInfo::new(),
+            cells: Vec::new(),
+            blocks: Vec::new(),
+        }
+    }
+
+    /// Reads the children of the WRLD with the id [world] from their group. Cells outside of the
+    /// blocks are read as coming before them, which is where they are written back
+    pub fn from_group(
+        group: GeneralGroup<'data>,
+        world: FormId,
+    ) -> Result<WorldChildren<'data>, GroupStructureError> {
+        match group.group_type {
+            GroupType::WorldChildren(id) if id == world => {}
+            GroupType::WorldChildren(id) => return Err(GroupStructureError::Orphan { parent: id }),
+            group_type => return Err(wrong_group(1, group_type)),
+        }
+        let mut cells = Vec::new();
+        let mut blocks = Vec::new();
+        for entry in group.entries {
+            match entry {
+                GroupEntry::Group(group)
+                    if matches!(group.group_type, GroupType::ExteriorCellBlock(_)) =>
+                {
+                    blocks.push(ExteriorBlock::from_group(group)?)
+                }
+                entry => cells.push(entry),
+            }
+        }
+        Ok(WorldChildren {
+            common: group.common,
+            cells: cells_from_entries(cells)?,
+            blocks,
+        })
+    }
+
+    /// The group of the children of the WRLD with the id [world]
+    pub fn into_group(self, world: FormId) -> GeneralGroup<'data> {
+        let mut entries = cells_into_entries(self.cells);
+        entries.extend(
+            self.blocks
+                .into_iter()
+                .map(|block| GroupEntry::Group(block.into_group())),
+        );
+        GeneralGroup {
+            group_type: GroupType::WorldChildren(world),
+            common: self.common,
+            entries,
+        }
+    }
+
+    /// Every cell, those outside of the blocks first
+    pub fn cells(&self) -> impl Iterator<Item = &Cell<'data>> {
+        self.cells.iter().chain(
+            self.blocks
+                .iter()
+                .flat_map(|block| block.sub_blocks.iter())
+                .flat_map(|sub_block| sub_block.cells.iter()),
+        )
+    }
+
+    /// Mutable version of [WorldChildren::cells]
+    pub fn cells_mut(&mut self) -> impl Iterator<Item = &mut Cell<'data>> {
+        self.cells.iter_mut().chain(
+            self.blocks
+                .iter_mut()
+                .flat_map(|block| block.sub_blocks.iter_mut())
+                .flat_map(|sub_block| sub_block.cells.iter_mut()),
+        )
+    }
+
+    /// Adds [cell] to the end of the sub-block that holds its grid, making the block and
+    /// sub-block at the end if they don't exist. Cells without a grid, or with one too far out to
+    /// be in a block, are added to those outside of the blocks
+    pub fn insert(&mut self, cell: Cell<'data>) {
+        let grid = match sub_block_grid(&cell) {
+            Some(grid) => grid,
+            None => return self.cells.push(cell),
+        };
+        let position = exterior_block_of(grid);
+        let index = match self.blocks.iter().position(|x| x.position == position) {
+            Some(index) => index,
+            None => {
+                self.blocks.push(ExteriorBlock {
+                    position,
+                    common: CommonGroupInfo::new(),
+                    sub_blocks: Vec::new(),
+                });
+                self.blocks.len() - 1
+            }
+        };
+        let sub_blocks = &mut self.blocks[index].sub_blocks;
+        let position = exterior_sub_block_of(grid);
+        let index = match sub_blocks.iter().position(|x| x.position == position) {
+            Some(index) => index,
+            None => {
+                sub_blocks.push(ExteriorSubBlock {
+                    position,
+                    common: CommonGroupInfo::new(),
+                    cells: Vec::new(),
+                });
+                sub_blocks.len() - 1
+            }
+        };
+        sub_blocks[index].cells.push(cell);
+    }
+
+    /// The exterior cell at [grid], looking only in the sub-block that should hold it
+    pub fn cell_at(&self, grid: Position<i32>) -> Option<&Cell<'data>> {
+        let cell = Position::new(i16::try_from(grid.x).ok()?, i16::try_from(grid.y).ok()?);
+        let block = exterior_block_of(cell);
+        let sub_block = exterior_sub_block_of(cell);
+        self.blocks
+            .iter()
+            .filter(|x| x.position == block)
+            .flat_map(|x| x.sub_blocks.iter())
+            .filter(|x| x.position == sub_block)
+            .flat_map(|x| x.cells.iter())
+            .find(|x| x.grid() == Some(grid))
+    }
+}
+
+/// The position of the sub-block that holds [cell], or None if it has no grid or one too far out
+/// to be in a block
+fn sub_block_grid(cell: &Cell<'_>) -> Option<Position<i16>> {
+    let grid = cell.grid()?;
+    Some(Position::new(
+        i16::try_from(grid.x).ok()?,
+        i16::try_from(grid.y).ok()?,
+    ))
+}
+
+/// Checks that [group] is the group of the children of a WRLD, returning the WRLD's id
+fn check_world_children(group: &GeneralGroup<'_>) -> Result<FormId, GroupStructureError> {
+    match group.group_type {
+        GroupType::WorldChildren(world) => Ok(world),
+        group_type => Err(wrong_group(1, group_type)),
+    }
+}
+
+/// The children of a WRLD where a plugin holds them, see [Plugin::world_children]. Only the
+/// groups that are looked at need the structure that [WorldChildren::from_group] reads, and
+/// entries that don't fit are skipped
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct WorldChildrenRef<'a, 'data> {
+    world: FormId,
+    group: &'a GeneralGroup<'data>,
+}
+impl<'a, 'data> WorldChildrenRef<'a, 'data> {
+    pub fn new(
+        group: &'a GeneralGroup<'data>,
+    ) -> Result<WorldChildrenRef<'a, 'data>, GroupStructureError> {
+        let world = check_world_children(group)?;
+        Ok(WorldChildrenRef { world, group })
+    }
+
+    pub fn world(self) -> FormId {
+        self.world
+    }
+
+    /// Cells held outside of the blocks
+    pub fn outside_cells(self) -> impl Iterator<Item = CellRef<'a, 'data>> {
+        cell_refs(&self.group.entries)
+    }
+
+    /// The persistent cell, which holds the persistent references of the whole worldspace. It
+    /// is the first of the cells outside of the blocks
+    pub fn persistent_cell(self) -> Option<CellRef<'a, 'data>> {
+        self.outside_cells().next()
+    }
+
+    fn sub_blocks(self) -> impl Iterator<Item = &'a GeneralGroup<'data>> {
+        groups_of(&self.group.entries, |x| {
+            matches!(x, GroupType::ExteriorCellBlock(_))
+        })
+        .flat_map(|block| {
+            groups_of(&block.entries, |x| {
+                matches!(x, GroupType::ExteriorCellSubBlock(_))
+            })
+        })
+    }
+
+    /// Every cell, those outside of the blocks first
+    pub fn cells(self) -> impl Iterator<Item = CellRef<'a, 'data>> {
+        self.outside_cells().chain(
+            self.sub_blocks()
+                .flat_map(|sub_block| cell_refs(&sub_block.entries)),
+        )
+    }
+
+    /// The exterior cell at [grid], looking only in the sub-block that should hold it
+    pub fn cell_at(self, grid: Position<i32>) -> Option<CellRef<'a, 'data>> {
+        let cell = Position::new(i16::try_from(grid.x).ok()?, i16::try_from(grid.y).ok()?);
+        let block = GroupType::ExteriorCellBlock(exterior_block_of(cell));
+        let sub_block = GroupType::ExteriorCellSubBlock(exterior_sub_block_of(cell));
+        groups_of(&self.group.entries, move |x| *x == block)
+            .flat_map(move |block| groups_of(&block.entries, move |x| *x == sub_block))
+            .flat_map(|sub_block| cell_refs(&sub_block.entries))
+            .find(|x| x.grid() == Some(grid))
+    }
+}
+
+/// The groups within [entries] whose type [filter] returns true for
+fn groups_of<'a, 'data, F>(
+    entries: &'a [GroupEntry<'data>],
+    filter: F,
+) -> impl Iterator<Item = &'a GeneralGroup<'data>>
+where
+    F: Fn(&GroupType<'data>) -> bool,
+{
+    entries.iter().filter_map(move |entry| match entry {
+        GroupEntry::Group(group) if filter(&group.group_type) => Some(group),
+        _ => None,
+    })
+}
+
+/// The group of [group_type] within [entries], adding it to the end if there isn't one
+fn group_or_push<'a, 'data>(
+    entries: &'a mut Vec<GroupEntry<'data>>,
+    group_type: GroupType<'data>,
+) -> &'a mut GeneralGroup<'data> {
+    let index = match entries
+        .iter()
+        .position(|entry| matches!(entry, GroupEntry::Group(x) if x.group_type == group_type))
+    {
+        Some(index) => index,
+        None => {
+            entries.push(
+                GeneralGroup {
+                    group_type,
+                    common: CommonGroupInfo::new(),
+                    entries: Vec::new(),
+                }
+                .into(),
+            );
+            entries.len() - 1
+        }
+    };
+    match &mut entries[index] {
+        GroupEntry::Group(group) => group,
+        _ => unreachable!("The entry was found or made as a group"),
+    }
+}
+
+/// Mutable version of [WorldChildrenRef], see [Plugin::world_children_mut]
+#[derive(Debug, PartialEq)]
+pub struct WorldChildrenMut<'a, 'data> {
+    world: FormId,
+    group: &'a mut GeneralGroup<'data>,
+}
+impl<'a, 'data> WorldChildrenMut<'a, 'data> {
+    pub fn new(
+        group: &'a mut GeneralGroup<'data>,
+    ) -> Result<WorldChildrenMut<'a, 'data>, GroupStructureError> {
+        let world = check_world_children(group)?;
+        Ok(WorldChildrenMut { world, group })
+    }
+
+    pub fn world(&self) -> FormId {
+        self.world
+    }
+
+    /// Adds [cell] where [WorldChildren::insert] would, without reading the other cells
+    pub fn insert(&mut self, cell: Cell<'data>) {
+        let entries = &mut self.group.entries;
+        let grid = match sub_block_grid(&cell) {
+            Some(grid) => grid,
+            None => {
+                let index = entries
+                    .iter()
+                    .position(|entry| {
+                        matches!(entry, GroupEntry::Group(x)
+                            if matches!(x.group_type, GroupType::ExteriorCellBlock(_)))
+                    })
+                    .unwrap_or(entries.len());
+                entries.splice(index..index, cell.into_entries());
+                return;
+            }
+        };
+        let block = group_or_push(
+            entries,
+            GroupType::ExteriorCellBlock(exterior_block_of(grid)),
+        );
+        let sub_block = group_or_push(
+            &mut block.entries,
+            GroupType::ExteriorCellSubBlock(exterior_sub_block_of(grid)),
+        );
+        sub_block.entries.extend(cell.into_entries());
+    }
+}
+
+/// A WRLD record along with its children
+#[derive(Debug, Clone, PartialEq)]
+pub struct Worldspace<'data> {
+    pub record: ChildRecord<'data>,
+    pub children: Option<WorldChildren<'data>>,
+}
+impl<'data> Worldspace<'data> {
+    pub fn formid(&self) -> FormId {
+        self.record.formid()
+    }
+}
+
+/// The WRLD top group, with each worldspace, its blocks, sub-blocks, cells and their children
+#[derive(Debug, Clone, PartialEq)]
+pub struct WorldspaceTree<'data> {
+    pub common: CommonGroupInfo,
+    pub worldspaces: Vec<Worldspace<'data>>,
+}
+impl<'data> WorldspaceTree<'data> {
+    pub fn from_top(group: TopGroup<'data>) -> Result<WorldspaceTree<'data>, GroupStructureError> {
+        let mut worldspaces: Vec<Worldspace<'data>> = Vec::new();
+        for entry in group.entries {
+            match ChildRecord::from_entry(entry) {
+                Ok(record) if record.type_name() == "WRLD" => worldspaces.push(Worldspace {
+                    record,
+                    children: None,
+                }),
+                Ok(record) => {
+                    return Err(GroupStructureError::UnexpectedRecord(
+                        record.type_name().to_string(),
+                    ))
+                }
+                Err(group) => match worldspaces.last_mut() {
+                    Some(world) if world.children.is_none() => {
+                        world.children = Some(WorldChildren::from_group(group, world.formid())?)
+                    }
+                    _ => {
+                        return Err(match group.group_type {
+                            GroupType::WorldChildren(parent) => {
+                                GroupStructureError::Orphan { parent }
+                            }
+                            group_type => GroupStructureError::UnexpectedGroup(group_type.code()),
+                        })
+                    }
+                },
+            }
+        }
+        Ok(WorldspaceTree {
+            common: group.common,
+            worldspaces,
+        })
+    }
+
+    /// The WRLD top group, with every group labelled by the record that it is under
+    pub fn into_top(self) -> TopGroup<'data> {
+        let mut entries = Vec::new();
+        for world in self.worldspaces {
+            let id = world.formid();
+            entries.push(world.record.into());
+            if let Some(children) = world.children {
+                entries.push(children.into_group(id).into());
+            }
+        }
+        TopGroup {
+            label: b"WRLD".as_bstr(),
+            common: self.common,
+            entries,
+        }
+    }
+
+    pub fn worldspace(&self, id: FormId) -> Option<&Worldspace<'data>> {
+        self.worldspaces.iter().find(|x| x.formid() == id)
+    }
+
+    pub fn worldspace_mut(&mut self, id: FormId) -> Option<&mut Worldspace<'data>> {
+        self.worldspaces.iter_mut().find(|x| x.formid() == id)
+    }
+
+    /// The cell with [id], in any of the worldspaces
+    pub fn find_cell_mut(&mut self, id: FormId) -> Option<&mut Cell<'data>> {
+        self.worldspaces
+            .iter_mut()
+            .filter_map(|world| world.children.as_mut())
+            .flat_map(WorldChildren::cells_mut)
+            .find(|cell| cell.formid() == id)
+    }
+
+    /// The exterior cell of the worldspace [world] at [grid]
+    pub fn cell_at(&self, world: FormId, grid: Position<i32>) -> Option<&Cell<'data>> {
+        self.worldspace(world)?.children.as_ref()?.cell_at(grid)
+    }
+}
+
+/// A group of [found] where one of the type numbered [expected] should be
+impl<'data> Plugin<'data> {
+    /// A copy of the WRLD top group as a tree, to be changed as a whole and given back to
+    /// [Plugin::set_worldspaces]. None if the plugin has no worldspaces. See
+    /// [Plugin::world_children] to look at a worldspace without copying it
+    pub fn worldspaces(&self) -> Result<Option<WorldspaceTree<'data>>, GroupStructureError> {
+        self.unknown_top(b"WRLD")
+            .map(|group| WorldspaceTree::from_top(group.clone()))
+            .transpose()
+    }
+
+    /// Replaces the WRLD top group with [tree], adding it to the end if there isn't one
+    pub fn set_worldspaces(&mut self, tree: WorldspaceTree<'data>) {
+        self.set_unknown_top(tree.into_top())
+    }
+
+    /// The children of the WRLD with the id [world], as they are held. None if it has no group
+    /// of children
+    pub fn world_children(
+        &self,
+        world: FormId,
+    ) -> Result<Option<WorldChildrenRef<'_, 'data>>, GroupStructureError> {
+        self.find_group(&GroupType::WorldChildren(world))
+            .map(WorldChildrenRef::new)
+            .transpose()
+    }
+
+    /// Mutable version of [Plugin::world_children]
+    pub fn world_children_mut(
+        &mut self,
+        world: FormId,
+    ) -> Result<Option<WorldChildrenMut<'_, 'data>>, GroupStructureError> {
+        self.find_group_mut(&GroupType::WorldChildren(world))
+            .map(WorldChildrenMut::new)
+            .transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        context::ParseContext,
+        fixtures,
+        records::{
+            common::{CommonRecordInfo, GeneralRecord},
+            fields::common::GeneralField,
+        },
+        util::Writable,
+    };
+
+    fn record(type_name: &'static [u8], id: u32, fields: Vec<GeneralField<'static>>) -> Vec<u8> {
+        let mut common = CommonRecordInfo::test_default();
+        common.flags.flags = 0;
+        common.id = id;
+        let record = GeneralRecord {
+            type_name: type_name.as_bstr(),
+            common,
+            fields,
+        };
+        record.to_vec().unwrap()
+    }
+
+    fn group(label: u32, group_type: i32, contents: &[Vec<u8>]) -> Vec<u8> {
+        let contents = contents.concat();
+        let mut data = b"GRUP".to_vec();
+        data.extend_from_slice(&(24 + contents.len() as u32).to_le_bytes());
+        data.extend_from_slice(&label.to_le_bytes());
+        data.extend_from_slice(&group_type.to_le_bytes());
+        data.extend_from_slice(&[0; 8]);
+        data.extend_from_slice(&contents);
+        data
+    }
+
+    /// The label of the block or sub-block at [x], [y], which is stored Y first
+    fn grid_label(x: i16, y: i16) -> u32 {
+        u32::from(y as u16) | (u32::from(x as u16) << 16)
+    }
+
+    #[test]
+    fn test_worldspace_tree() {
+        // Cell -3, 9, which is in block -1, 0 and sub-block -1, 1
+        const XCLC: &[u8] = &[0xFD, 0xFF, 0xFF, 0xFF, 9, 0, 0, 0, 0, 0, 0, 0];
+        let exterior = vec![GeneralField::new(b"XCLC".as_bstr(), XCLC)];
+        let children = group(
+            0x801,
+            1,
+            &[
+                record(b"CELL", 0x802, Vec::new()),
+                group(
+                    0x802,
+                    6,
+                    &[group(0x802, 8, &[record(b"REFR", 0x803, Vec::new())])],
+                ),
+                group(
+                    grid_label(-1, 0),
+                    4,
+                    &[group(
+                        grid_label(-1, 1),
+                        5,
+                        &[record(b"CELL", 0x804, exterior)],
+                    )],
+                ),
+            ],
+        );
+        let mut header = Plugin::new("Base.esm");
+        header.refresh_header();
+        let data = [
+            header.to_vec().unwrap(),
+            group(
+                u32::from_le_bytes(*b"WRLD"),
+                0,
+                &[record(b"WRLD", 0x801, Vec::new()), children],
+            ),
+        ]
+        .concat();
+        let mut ctx = ParseContext::default();
+        let mut plugin = Plugin::parse("Base.esm", &data, &mut ctx).unwrap();
+
+        let tree = plugin.worldspaces().unwrap().unwrap();
+        assert_eq!(tree.worldspaces.len(), 1);
+        let world = FormId::new(0x801);
+        let children = tree.worldspace(world).unwrap().children.as_ref().unwrap();
+        let cells: Vec<u32> = children.cells().map(|x| x.formid().id).collect();
+        assert_eq!(cells, vec![0x802, 0x804]);
+        assert_eq!(
+            children.cells[0]
+                .children
+                .as_ref()
+                .unwrap()
+                .records()
+                .count(),
+            1
+        );
+        assert_eq!(children.blocks[0].position, Position::new(-1, 0));
+        assert_eq!(
+            children.blocks[0].sub_blocks[0].position,
+            Position::new(-1, 1)
+        );
+
+        let cell = tree.cell_at(world, Position::new(-3, 9)).unwrap();
+        assert_eq!(cell.formid(), FormId::new(0x804));
+        assert_eq!(cell.grid(), Some(Position::new(-3, 9)));
+        assert!(tree.cell_at(world, Position::new(-3, 10)).is_none());
+        assert!(tree
+            .cell_at(FormId::new(0x802), Position::new(-3, 9))
+            .is_none());
+
+        plugin.set_worldspaces(tree);
+        assert_eq!(plugin.to_vec().unwrap(), data);
+    }
+
+    #[test]
+    fn test_world_children_ref() {
+        // Cell 2, 3 is in block 0, 0 and sub-block 0, 0
+        const XCLC: &[u8] = &[2, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0];
+        let exterior = vec![GeneralField::new(b"XCLC".as_bstr(), XCLC)];
+        let children = group(
+            0x801,
+            1,
+            &[
+                record(b"CELL", 0x802, Vec::new()),
+                group(0x802, 6, &[group(0x802, 8, &[])]),
+                group(
+                    0,
+                    4,
+                    &[group(0, 5, &[record(b"CELL", 0x804, exterior.clone())])],
+                ),
+            ],
+        );
+        let mut header = Plugin::new("Base.esm");
+        header.refresh_header();
+        let data = [
+            header.to_vec().unwrap(),
+            group(
+                u32::from_le_bytes(*b"WRLD"),
+                0,
+                &[record(b"WRLD", 0x801, Vec::new()), children],
+            ),
+        ]
+        .concat();
+        let mut ctx = ParseContext::default();
+        let mut plugin = Plugin::parse("Base.esm", &data, &mut ctx).unwrap();
+        let world = FormId::new(0x801);
+
+        let children = plugin.world_children(world).unwrap().unwrap();
+        let cells: Vec<u32> = children.cells().map(|x| x.formid().id).collect();
+        assert_eq!(cells, vec![0x802, 0x804]);
+        let persistent = children.persistent_cell().unwrap();
+        assert_eq!(persistent.formid(), FormId::new(0x802));
+        assert!(persistent.children().unwrap().is_some());
+        let cell = children.cell_at(Position::new(2, 3)).unwrap();
+        assert_eq!(cell.formid(), FormId::new(0x804));
+        assert!(cell.children.is_none());
+        assert!(children.cell_at(Position::new(2, 4)).is_none());
+        assert_eq!(plugin.world_children(FormId::new(0x802)), Ok(None));
+
+        // Cells are placed as they would be in the tree
+        let mut tree = plugin.worldspaces().unwrap().unwrap();
+        let far = [40, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        let cells = || {
+            vec![
+                Cell {
+                    record: fixtures::general(
+                        b"CELL",
+                        0x805,
+                        vec![GeneralField::new(b"XCLC".as_bstr(), &far)],
+                    )
+                    .into(),
+                    children: None,
+                },
+                Cell {
+                    record: fixtures::general(b"CELL", 0x806, exterior.clone()).into(),
+                    children: None,
+                },
+                Cell {
+                    record: fixtures::general(b"CELL", 0x807, Vec::new()).into(),
+                    children: None,
+                },
+            ]
+        };
+        let world_children = tree.worldspace_mut(world).unwrap().children.as_mut();
+        let world_children = world_children.unwrap();
+        let mut children = plugin.world_children_mut(world).unwrap().unwrap();
+        for cell in cells() {
+            children.insert(cell);
+        }
+        for cell in cells() {
+            world_children.insert(cell);
+        }
+        assert_eq!(plugin.worldspaces().unwrap(), Some(tree));
+    }
+
+    #[test]
+    fn test_worldspace_structure() {
+        let top = |entries: Vec<GroupEntry<'static>>| TopGroup {
+            label: b"WRLD".as_bstr(),
+            common: CommonGroupInfo::new(),
+            entries,
+        };
+        let world = |id| {
+            let mut common = CommonRecordInfo::test_default();
+            common.id = id;
+            GroupEntry::Record(GeneralRecord {
+                type_name: b"WRLD".as_bstr(),
+                common,
+                fields: Vec::new(),
+            })
+        };
+        let children = |id| {
+            GroupEntry::Group(GeneralGroup {
+                group_type: GroupType::WorldChildren(FormId::new(id)),
+                common: CommonGroupInfo::new(),
+                entries: Vec::new(),
+            })
+        };
+
+        assert!(WorldspaceTree::from_top(top(vec![world(0x801), children(0x801)])).is_ok());
+        assert_eq!(
+            WorldspaceTree::from_top(top(vec![world(0x801), children(0x802)])),
+            Err(GroupStructureError::Orphan {
+                parent: FormId::new(0x802)
+            })
+        );
+        assert_eq!(
+            WorldspaceTree::from_top(top(vec![children(0x801), world(0x801)])),
+            Err(GroupStructureError::Orphan {
+                parent: FormId::new(0x801)
+            })
+        );
+    }
+}
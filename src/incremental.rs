@@ -0,0 +1,268 @@
+// Writing a plugin by copying the original bytes of what hasn't changed since it was read, so
+// that editing a handful of records in a large master doesn't re-encode all of them.
+// The bytes of each top-level entry are kept, and those whose revision is unchanged are copied
+// as a whole. Within groups that did change, a record is copied if it still reads the same as
+// its original bytes do, which are only parsed again when it comes to that.
+
+use crate::{
+    context::{FieldPolicy, ParseContext, ParseOptions},
+    groups::{common::entry_size, Group},
+    parse::Parse,
+    plugin::{write_top_with, Plugin},
+    records::{
+        common::{FormId, GeneralRecord},
+        Record, RecordRef,
+    },
+    util::{Writable, WriteError},
+    walk::walk_records,
+    Top, TypeNamed,
+};
+use std::{borrow::Cow, collections::HashMap, io::Write};
+
+/// The bytes of a plugin as it was read, by top-level entry and by record
+#[derive(Debug, Clone, Default)]
+pub struct OriginalRecords<'data> {
+    /// Bytes of each top-level entry, along with the revision it had when read. Empty if they
+    /// couldn't be matched up with the plugin's entries
+    tops: Vec<(u64, &'data [u8])>,
+    /// None for ids that appear more than once, which are always re-encoded
+    records: HashMap<FormId, Option<&'data [u8]>>,
+    /// What the plugin was parsed with, to parse the original bytes of records again
+    options: ParseOptions,
+}
+impl<'data> OriginalRecords<'data> {
+    /// Remembers the bytes of [plugin], which was just parsed from [data] with [options].
+    /// If [options] drops any fields then nothing is remembered, as the records no longer match
+    /// their bytes.
+    pub fn new(
+        plugin: &mut Plugin<'data>,
+        data: &'data [u8],
+        options: &ParseOptions,
+    ) -> OriginalRecords<'data> {
+        let mut original = OriginalRecords {
+            options: options.clone(),
+            ..OriginalRecords::default()
+        };
+        if options
+            .field_rules
+            .iter()
+            .any(|x| x.policy == FieldPolicy::Drop)
+        {
+            return original;
+        }
+
+        // Trailing data after the plugin can fail to walk, the records before it are still found
+        let _ = walk_records(data, &mut |record| {
+            original
+                .records
+                .entry(record.id)
+                .and_modify(|x| *x = None)
+                .or_insert(Some(record.raw));
+        });
+
+        let mut entries = Vec::new();
+        let mut rest = data;
+        while let Some(size) = entry_size(rest).filter(|size| *size <= rest.len()) {
+            entries.push(&rest[..size]);
+            rest = &rest[size..];
+        }
+        let len = plugin.tops().len();
+        // Entries that were left out when parsing, such as those vetoed by a hook, leave the rest
+        // without a known position
+        let matches = entries.len() == len
+            && plugin
+                .tops()
+                .iter()
+                .zip(entries.iter())
+                .all(|(top, bytes)| {
+                    let type_name: &[u8] = match top {
+                        Top::Record(record) => record.type_name(),
+                        Top::Group(_) => b"GRUP",
+                    };
+                    bytes.starts_with(type_name)
+                });
+        if matches {
+            let revisions = plugin.revisions.current(len);
+            original.tops = revisions.iter().copied().zip(entries).collect();
+        }
+        original
+    }
+
+    /// Amount of records whose bytes can be reused
+    pub fn len(&self) -> usize {
+        self.records.values().filter(|x| x.is_some()).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Writes [plugin], copying the original bytes of the top-level entries and records that are
+    /// unchanged. Groups that aren't converted are written as they are.
+    pub fn write<T: Write>(
+        &self,
+        plugin: &Plugin<'data>,
+        w: &mut T,
+    ) -> Result<IncrementalStats, WriteError> {
+        let mut stats = IncrementalStats::default();
+        let mut ctx = ParseContext::new(self.options.clone());
+        for index in plugin.write_order() {
+            let top = &plugin.tops()[index];
+            match self.tops.get(index) {
+                Some((revision, bytes)) if plugin.revisions.get(index) == Some(*revision) => {
+                    w.write_all(bytes)?;
+                    stats.reused += match top {
+                        Top::Record(_) => 1,
+                        Top::Group(group) => group.record_count(),
+                    };
+                }
+                _ => {
+                    write_top_with(top, w, &mut |record| {
+                        self.record_bytes(record, &mut ctx, &mut stats)
+                    })?;
+                    if let Top::Group(group @ (Group::Unknown(_) | Group::UnknownTop(_))) = top {
+                        stats.encoded += group.record_count();
+                    }
+                }
+            }
+        }
+        Ok(stats)
+    }
+
+    fn record_bytes(
+        &self,
+        record: RecordRef<'_, 'data>,
+        ctx: &mut ParseContext,
+        stats: &mut IncrementalStats,
+    ) -> Result<Cow<'data, [u8]>, WriteError> {
+        if let Some(Some(bytes)) = self.records.get(&record.formid()) {
+            let original = GeneralRecord::parse(bytes)
+                .ok()
+                .and_then(|(_, general)| Record::from_general(general, ctx).ok());
+            if original.map_or(false, |original| original.as_record_ref() == record) {
+                stats.reused += 1;
+                return Ok(Cow::Borrowed(bytes));
+            }
+        }
+        let data = record.to_vec()?;
+        stats.encoded += 1;
+        Ok(Cow::Owned(data))
+    }
+}
+
+/// What [OriginalRecords::write] did with the plugin's records
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct IncrementalStats {
+    /// Records whose original bytes were copied
+    pub reused: usize,
+    /// Records that were changed or new, and so were encoded
+    pub encoded: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        context::ParseContext,
+        groups::{common::GROUPH_SIZE, Group},
+        records::{
+            common::{CommonRecordInfo, GeneralRecord},
+            fields::common::GeneralField,
+        },
+        Top,
+    };
+    use bstr::ByteSlice;
+
+    #[test]
+    fn test_incremental_write() {
+        let mut common = CommonRecordInfo::test_default();
+        common.flags.flags = 0;
+        let header = GeneralRecord {
+            type_name: b"TES4".as_bstr(),
+            common: common.clone(),
+            fields: vec![GeneralField::new(b"HEDR".as_bstr(), &[0; 12])],
+        };
+        let action = |id, name: &'static [u8]| {
+            let mut common = common.clone();
+            common.id = id;
+            GeneralRecord {
+                type_name: b"AACT".as_bstr(),
+                common,
+                fields: vec![
+                    GeneralField::new(b"EDID".as_bstr(), name),
+                    GeneralField::new(b"CNAM".as_bstr(), &[1, 2, 3, 0]),
+                ],
+            }
+        };
+        let mut records = Vec::new();
+        action(0x801, b"First\0").write_to(&mut records).unwrap();
+        action(0x802, b"Second\0").write_to(&mut records).unwrap();
+        let mut data = Vec::new();
+        header.write_to(&mut data).unwrap();
+        data.extend_from_slice(b"GRUP");
+        data.extend_from_slice(&((GROUPH_SIZE + records.len()) as u32).to_le_bytes());
+        data.extend_from_slice(b"AACT");
+        data.extend_from_slice(&[0; 12]);
+        data.extend_from_slice(&records);
+        let mut cell = Vec::new();
+        action(0x805, b"Cell\0").write_to(&mut cell).unwrap();
+        cell[..4].copy_from_slice(b"CELL");
+        data.extend_from_slice(b"GRUP");
+        data.extend_from_slice(&((GROUPH_SIZE + cell.len()) as u32).to_le_bytes());
+        data.extend_from_slice(b"CELL");
+        data.extend_from_slice(&[0; 12]);
+        data.extend_from_slice(&cell);
+
+        let mut ctx = ParseContext::default();
+        let mut plugin = Plugin::parse("Test.esp", &data, &mut ctx).unwrap();
+        assert!(matches!(plugin.tops()[2], Top::Group(Group::UnknownTop(_))));
+        let original = OriginalRecords::new(&mut plugin, &data, &ctx.options);
+        assert_eq!(original.len(), 4);
+
+        let mut written = Vec::new();
+        let stats = original.write(&plugin, &mut written).unwrap();
+        assert_eq!(
+            stats,
+            IncrementalStats {
+                reused: 4,
+                encoded: 0
+            }
+        );
+        assert_eq!(written, data);
+
+        if let Some(Top::Group(Group::AACT(group))) = plugin.top_mut(1) {
+            group.records[1].common.id = 0x803;
+            let mut added = group.records[0].clone();
+            added.common.id = 0x804;
+            group.records.push(added);
+        }
+        let mut written = Vec::new();
+        let stats = original.write(&plugin, &mut written).unwrap();
+        assert_eq!(
+            stats,
+            IncrementalStats {
+                reused: 3,
+                encoded: 2
+            }
+        );
+        let mut expected = Vec::new();
+        plugin.write_to(&mut expected).unwrap();
+        assert_eq!(written, expected);
+        assert!(written.ends_with(&data[data.len() - GROUPH_SIZE - cell.len()..]));
+
+        // A changed group that isn't converted is written as it is, rather than from its bytes
+        if let Some(Top::Group(Group::UnknownTop(group))) = plugin.top_mut(2) {
+            group.entries.clear();
+        }
+        let mut written = Vec::new();
+        let stats = original.write(&plugin, &mut written).unwrap();
+        assert_eq!(
+            stats,
+            IncrementalStats {
+                reused: 2,
+                encoded: 2
+            }
+        );
+        assert!(written.ends_with(b"CELL\0\0\0\0\0\0\0\0\0\0\0\0"));
+    }
+}
@@ -0,0 +1,420 @@
+// Finding records by form id without walking the whole plugin. An index maps each form id to
+// where its record is within the plugin's tops, including records within groups that weren't
+// converted. Each plugin keeps one for Plugin::find, which it builds on the first lookup. Changes
+// to Plugin::tops go through Plugin::tops_mut and the like, which mark the index as stale. A
+// stale index is still used for records that it finds with the id that was asked for, and is
+// built again when it misses, so that it follows records that were added, removed or moved
+// since. A miss on an index that isn't stale is taken as it is, without building it again.
+// Plugin::index gives a separate index, which is only updated through FormIdIndex::insert and
+// FormIdIndex::remove. A stale one misses rather than giving the wrong record.
+
+use crate::{
+    groups::{common::GroupEntry, Group},
+    plugin::Plugin,
+    records::{common::FormId, RecordMut, RecordRef},
+    walk::is_deleted,
+    Top,
+};
+use std::{
+    collections::HashMap,
+    fmt::{self, Debug, Formatter},
+    sync::{Mutex, PoisonError},
+};
+
+/// Where a record is within a plugin
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct RecordHandle {
+    /// Position within [Plugin::tops]
+    pub top: usize,
+    /// Position of the record within the group at [RecordHandle::top]. For typed groups this is
+    /// its position within their records, while for groups that weren't converted it is the
+    /// position of the entry within each group down to the record, outermost first. Empty if the
+    /// top is the record itself
+    pub entries: Vec<usize>,
+}
+impl RecordHandle {
+    pub fn get<'a, 'data>(&self, plugin: &'a Plugin<'data>) -> Option<RecordRef<'a, 'data>> {
+        match (plugin.tops().get(self.top)?, self.entries.as_slice()) {
+            (Top::Record(record), []) => Some(record.as_record_ref()),
+            (Top::Group(Group::Unknown(group)), entries) => entry_at(&group.entries, entries),
+            (Top::Group(Group::UnknownTop(group)), entries) => entry_at(&group.entries, entries),
+            (Top::Group(group), [index]) => group.record(*index),
+            _ => None,
+        }
+    }
+
+    pub fn get_mut<'a, 'data>(
+        &self,
+        plugin: &'a mut Plugin<'data>,
+    ) -> Option<RecordMut<'a, 'data>> {
+        match (plugin.top_mut(self.top)?, self.entries.as_slice()) {
+            (Top::Record(record), []) => Some(record.as_record_mut()),
+            (Top::Group(Group::Unknown(group)), entries) => {
+                entry_at_mut(&mut group.entries, entries)
+            }
+            (Top::Group(Group::UnknownTop(group)), entries) => {
+                entry_at_mut(&mut group.entries, entries)
+            }
+            (Top::Group(group), [index]) => group.record_mut(*index),
+            _ => None,
+        }
+    }
+}
+
+/// The record at [path] within [entries], see [RecordHandle::entries]
+fn entry_at<'a, 'data>(
+    entries: &'a [GroupEntry<'data>],
+    path: &[usize],
+) -> Option<RecordRef<'a, 'data>> {
+    let (last, groups) = path.split_last()?;
+    let mut entries = entries;
+    for index in groups {
+        match entries.get(*index)? {
+            GroupEntry::Group(group) => entries = &group.entries,
+            _ => return None,
+        }
+    }
+    match entries.get(*last)? {
+        GroupEntry::Record(record) if is_deleted(record) => Some(RecordRef::Deleted(record)),
+        GroupEntry::Record(record) => Some(RecordRef::Unknown(record)),
+        _ => None,
+    }
+}
+
+/// Mutable version of [entry_at]
+fn entry_at_mut<'a, 'data>(
+    entries: &'a mut [GroupEntry<'data>],
+    path: &[usize],
+) -> Option<RecordMut<'a, 'data>> {
+    let (last, groups) = path.split_last()?;
+    let mut entries = entries;
+    for index in groups {
+        match entries.get_mut(*index)? {
+            GroupEntry::Group(group) => entries = &mut group.entries,
+            _ => return None,
+        }
+    }
+    match entries.get_mut(*last)? {
+        GroupEntry::Record(record) => Some(if is_deleted(record) {
+            RecordMut::Deleted(record)
+        } else {
+            RecordMut::Unknown(record)
+        }),
+        _ => None,
+    }
+}
+
+/// The records of a plugin by form id, see [Plugin::index]
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct FormIdIndex {
+    handles: HashMap<FormId, RecordHandle>,
+}
+impl FormIdIndex {
+    pub fn contains(&self, id: FormId) -> bool {
+        self.handles.contains_key(&id)
+    }
+
+    pub fn handle(&self, id: FormId) -> Option<&RecordHandle> {
+        self.handles.get(&id)
+    }
+
+    /// The record of [plugin] with [id]. None if it isn't indexed, or if the index is out of
+    /// date and the record has moved
+    pub fn get<'a, 'data>(
+        &self,
+        plugin: &'a Plugin<'data>,
+        id: FormId,
+    ) -> Option<RecordRef<'a, 'data>> {
+        self.handle(id)?
+            .get(plugin)
+            .filter(|record| record.formid() == id)
+    }
+
+    /// Mutable version of [FormIdIndex::get]
+    pub fn get_mut<'a, 'data>(
+        &self,
+        plugin: &'a mut Plugin<'data>,
+        id: FormId,
+    ) -> Option<RecordMut<'a, 'data>> {
+        let record = self.handle(id)?.get_mut(plugin)?;
+        if record.formid() == id {
+            Some(record)
+        } else {
+            None
+        }
+    }
+
+    /// Records that the record with [id] is at [handle], such as after adding it. Returns the
+    /// handle that it had before
+    pub fn insert(&mut self, id: FormId, handle: RecordHandle) -> Option<RecordHandle> {
+        self.handles.insert(id, handle)
+    }
+
+    /// Forgets the record with [id], such as after removing it. Records after it in the same
+    /// group have moved, so their handles should be updated too
+    pub fn remove(&mut self, id: FormId) -> Option<RecordHandle> {
+        self.handles.remove(&id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.handles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.handles.is_empty()
+    }
+
+    /// Adds the records within [entries] of the group at [top], where [path] leads to the group
+    fn add_entries(&mut self, entries: &[GroupEntry<'_>], top: usize, path: &mut Vec<usize>) {
+        for (position, entry) in entries.iter().enumerate() {
+            path.push(position);
+            match entry {
+                GroupEntry::Record(record) => {
+                    let handle = RecordHandle {
+                        top,
+                        entries: path.clone(),
+                    };
+                    self.handles
+                        .entry(FormId::new(record.common.id))
+                        .or_insert(handle);
+                }
+                GroupEntry::Compressed(_) => {}
+                GroupEntry::Group(group) => self.add_entries(&group.entries, top, path),
+            }
+            path.pop();
+        }
+    }
+}
+
+/// The index that a plugin keeps for [Plugin::find]. It isn't part of what the plugin holds, so
+/// clones start without one and it is left out when comparing plugins
+#[derive(Default)]
+pub(crate) struct IndexCache(Mutex<Option<CachedIndex>>);
+
+struct CachedIndex {
+    index: FormIdIndex,
+    /// Whether the plugin may have changed since [index] was built
+    stale: bool,
+}
+
+impl IndexCache {
+    /// Where the record of [plugin] with [id] is. The index is built again only if it is stale
+    /// and doesn't give a record with [id]
+    pub(crate) fn handle(&self, plugin: &Plugin<'_>, id: FormId) -> Option<RecordHandle> {
+        let mut cached = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        match cached.as_ref() {
+            Some(CachedIndex {
+                index,
+                stale: false,
+            }) => return index.handle(id).cloned(),
+            Some(CachedIndex { index, stale: true }) => {
+                if let Some(handle) = index.handle(id) {
+                    if handle
+                        .get(plugin)
+                        .map_or(false, |record| record.formid() == id)
+                    {
+                        return Some(handle.clone());
+                    }
+                }
+            }
+            None => {}
+        }
+        let index = plugin.index();
+        let handle = index.handle(id).cloned();
+        *cached = Some(CachedIndex {
+            index,
+            stale: false,
+        });
+        handle
+    }
+
+    /// Marks the index as stale, as the plugin may have changed
+    pub(crate) fn mark_changed(&mut self) {
+        let cached = self.0.get_mut().unwrap_or_else(PoisonError::into_inner);
+        if let Some(cached) = cached {
+            cached.stale = true;
+        }
+    }
+}
+impl Clone for IndexCache {
+    fn clone(&self) -> IndexCache {
+        IndexCache::default()
+    }
+}
+impl PartialEq for IndexCache {
+    fn eq(&self, _other: &IndexCache) -> bool {
+        true
+    }
+}
+impl Debug for IndexCache {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str("IndexCache")
+    }
+}
+
+impl Plugin<'_> {
+    /// Indexes the records of the plugin by form id, so that they can be found without searching
+    /// through every group. Records within groups that weren't converted are indexed as well,
+    /// apart from those that are compressed. If two records share an id, the first is kept, as
+    /// with [Plugin::find]
+    pub fn index(&self) -> FormIdIndex {
+        let mut index = FormIdIndex::default();
+        for (top_index, top) in self.tops().iter().enumerate() {
+            let entries = match top {
+                Top::Record(record) => {
+                    let handle = RecordHandle {
+                        top: top_index,
+                        entries: Vec::new(),
+                    };
+                    let id = record.as_record_ref().formid();
+                    index.handles.entry(id).or_insert(handle);
+                    continue;
+                }
+                Top::Group(Group::Unknown(group)) => &group.entries,
+                Top::Group(Group::UnknownTop(group)) => &group.entries,
+                Top::Group(group) => {
+                    for (record_index, record) in group.records().into_iter().enumerate() {
+                        let handle = RecordHandle {
+                            top: top_index,
+                            entries: vec![record_index],
+                        };
+                        index.handles.entry(record.formid()).or_insert(handle);
+                    }
+                    continue;
+                }
+            };
+            index.add_entries(entries, top_index, &mut Vec::new());
+        }
+        index
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        context::ParseContext,
+        groups::common::{GeneralGroup, GroupType, TopGroup},
+        records::{
+            common::{CommonRecordInfo, GeneralRecord},
+            fields::common::GeneralField,
+            Record,
+        },
+        util::Writable,
+    };
+    use bstr::ByteSlice;
+
+    fn list(id: u32) -> Vec<u8> {
+        let mut common = CommonRecordInfo::test_default();
+        common.flags.flags = 0;
+        common.id = id;
+        let record = GeneralRecord {
+            type_name: b"FLST".as_bstr(),
+            common,
+            fields: vec![GeneralField::new(b"EDID".as_bstr(), b"List\0")],
+        };
+        record.to_vec().unwrap()
+    }
+
+    #[test]
+    fn test_index() {
+        let mut header = Plugin::new("Base.esm");
+        header.refresh_header();
+        let contents = [list(0x801), list(0x802)].concat();
+        let mut data = header.to_vec().unwrap();
+        data.extend_from_slice(b"GRUP");
+        data.extend_from_slice(&(24 + contents.len() as u32).to_le_bytes());
+        data.extend_from_slice(b"FLST");
+        data.extend_from_slice(&[0; 12]);
+        data.extend_from_slice(&contents);
+        let mut ctx = ParseContext::default();
+        let mut plugin = Plugin::parse("Base.esm", &data, &mut ctx).unwrap();
+
+        let index = plugin.index();
+        assert_eq!(index.len(), 3);
+        assert!(index.contains(FormId::new(0)));
+        assert!(!index.contains(FormId::new(0x803)));
+        assert_eq!(
+            index.handle(FormId::new(0x802)),
+            Some(&RecordHandle {
+                top: 1,
+                entries: vec![1]
+            })
+        );
+        assert_eq!(
+            index.get(&plugin, FormId::new(0x802)),
+            plugin.find(FormId::new(0x802))
+        );
+        if let Some(RecordMut::FLST(flst)) = index.get_mut(&mut plugin, FormId::new(0x801)) {
+            flst.add_entry(FormId::new(0x802));
+        }
+        match index.get(&plugin, FormId::new(0x801)) {
+            Some(RecordRef::FLST(flst)) => assert!(flst.contains(FormId::new(0x802))),
+            record => panic!("Expected the FLST, got {:?}", record),
+        }
+
+        // Once records move, the index misses them until it is updated
+        if let Some(Top::Group(Group::FLST(group))) = plugin.top_mut(1) {
+            group.records.remove(0);
+        }
+        assert_eq!(index.get(&plugin, FormId::new(0x802)), None);
+        let mut index = index;
+        index.remove(FormId::new(0x801));
+        index.insert(
+            FormId::new(0x802),
+            RecordHandle {
+                top: 1,
+                entries: vec![0],
+            },
+        );
+        assert_eq!(index, plugin.index());
+        assert!(index.get(&plugin, FormId::new(0x802)).is_some());
+    }
+
+    #[test]
+    fn test_find() {
+        let general = |type_name: &'static [u8], id| {
+            let mut common = CommonRecordInfo::test_default();
+            common.flags.flags = 0;
+            common.id = id;
+            GeneralRecord {
+                type_name: type_name.as_bstr(),
+                common,
+                fields: Vec::new(),
+            }
+        };
+        let mut plugin = Plugin::new("Base.esm");
+        let cell = FormId::new(0x801);
+        let children = GeneralGroup::new(
+            GroupType::CellChildren(cell),
+            vec![general(b"REFR", 0x802).into()],
+        );
+        let cells = vec![Record::Unknown(general(b"CELL", 0x801))];
+        let cells = TopGroup::from_records(b"CELL".as_bstr(), cells).unwrap();
+        plugin.push_top(Top::Group(Group::UnknownTop(cells)));
+        assert!(plugin.find(FormId::new(0x802)).is_none());
+        if let Some(Top::Group(Group::UnknownTop(group))) = plugin.top_mut(1) {
+            group.entries.push(GroupEntry::Group(children));
+        }
+
+        // The index that find keeps follows records added through the tops
+        let index = plugin.index();
+        assert_eq!(
+            index.handle(FormId::new(0x802)),
+            Some(&RecordHandle {
+                top: 1,
+                entries: vec![1, 0]
+            })
+        );
+        assert!(matches!(
+            plugin.find(FormId::new(0x802)),
+            Some(RecordRef::Unknown(x)) if x.type_name == "REFR"
+        ));
+        if let Some(RecordMut::Unknown(record)) = plugin.find_mut(FormId::new(0x801)) {
+            record.common.id = 0x803;
+        }
+        assert!(plugin.find(FormId::new(0x801)).is_none());
+        assert!(plugin.find(FormId::new(0x803)).is_some());
+        assert_eq!(index.get(&plugin, FormId::new(0x801)), None);
+    }
+}
@@ -0,0 +1,206 @@
+// Records injected into a master. A plugin can add a record whose form id belongs to one of its
+// masters, even though that master doesn't have it, so that other plugins can refer to it as if
+// the master did. These look like overrides from the plugin alone, so finding them takes the
+// masters being loaded, so the load order tells each plugin which of its records are injected.
+// When renumbering, an injected record's id is what other plugins know it by, so it is kept
+// unless the plugin's policy says otherwise. Every record is looked at, including those in
+// groups that weren't converted.
+
+use crate::{
+    index::RecordHandle,
+    load_order::LoadOrder,
+    plugin::Plugin,
+    records::common::FormId,
+    remap::{FormIdMap, RemapStats},
+};
+use std::collections::{HashMap, HashSet};
+
+/// Whether [Plugin::remap_formids] renumbers the records that are known to be injected, see
+/// [Plugin::set_injected]
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub enum InjectedPolicy {
+    /// Leave them with the ids that other plugins know them by
+    #[default]
+    Preserve,
+    /// Renumber them like any other record
+    Remap,
+}
+
+impl LoadOrder<'_> {
+    /// Whether [id], as written in the plugin at [plugin], belongs to a master that doesn't have
+    /// a record with it. False if the master isn't loaded, as it can't be known
+    pub fn is_injected(&self, plugin: usize, id: FormId) -> bool {
+        self.injected_in(plugin, id, &mut HashMap::new())
+    }
+
+    /// [LoadOrder::is_injected], reusing the form ids of masters that have been looked at
+    fn injected_in(
+        &self,
+        plugin: usize,
+        id: FormId,
+        indices: &mut HashMap<usize, HashSet<FormId>>,
+    ) -> bool {
+        let masters = match self.plugins.get(plugin) {
+            Some(plugin) => plugin.masters().len(),
+            None => return false,
+        };
+        if usize::from(id.mod_index()) >= masters {
+            return false;
+        }
+        let (origin, object) = match self.origin(plugin, id) {
+            Some(origin) => origin,
+            None => return false,
+        };
+        let origin_plugin = &self.plugins[origin];
+        let own_id = FormId::from_parts(origin_plugin.masters().len() as u8, object);
+        !indices
+            .entry(origin)
+            .or_insert_with(|| origin_plugin.formids_and_groups().0.into_iter().collect())
+            .contains(&own_id)
+    }
+
+    /// Ids of the records of the plugin at [plugin] that are injected into its masters
+    pub fn injected_records(&self, plugin: usize) -> Vec<FormId> {
+        let mut indices = HashMap::new();
+        match self.plugins.get(plugin) {
+            Some(source) => source
+                .formids_and_groups()
+                .0
+                .into_iter()
+                .filter(|id| self.injected_in(plugin, *id, &mut indices))
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Tells the plugin at [plugin] which of its records are injected into its masters, see
+    /// [Plugin::set_injected]
+    pub fn mark_injected(&mut self, plugin: usize) {
+        let injected = self.injected_records(plugin);
+        if let Some(plugin) = self.plugins.get_mut(plugin) {
+            plugin.set_injected(injected);
+        }
+    }
+
+    /// [Plugin::remap_formids] on the plugin at [plugin], after finding the records it injects
+    /// into its masters, so that they follow its [Plugin::injected_policy]
+    pub fn remap_formids(&mut self, plugin: usize, map: &FormIdMap) -> RemapStats {
+        self.mark_injected(plugin);
+        match self.plugins.get_mut(plugin) {
+            Some(plugin) => plugin.remap_formids(map),
+            None => RemapStats::default(),
+        }
+    }
+}
+
+impl Plugin<'_> {
+    pub fn injected_policy(&self) -> InjectedPolicy {
+        self.injected_policy
+    }
+
+    pub fn set_injected_policy(&mut self, policy: InjectedPolicy) {
+        self.injected_policy = policy;
+    }
+
+    /// Ids of the records known to be injected into a master
+    pub fn injected(&self) -> &HashSet<FormId> {
+        &self.injected
+    }
+
+    /// Records which of the plugin's records are injected into a master, which can only be told
+    /// with the masters loaded, see [LoadOrder::mark_injected]. The ids follow the records as
+    /// they are renumbered, until they are renumbered into the plugin itself
+    pub fn set_injected<I>(&mut self, ids: I)
+    where
+        I: IntoIterator<Item = FormId>,
+    {
+        self.injected = ids.into_iter().collect();
+    }
+}
+
+impl RecordHandle {
+    /// Whether the record at this handle, in the plugin at [plugin] of [load_order], is injected
+    /// into one of its masters, see [LoadOrder::is_injected]
+    pub fn is_injected(&self, load_order: &LoadOrder<'_>, plugin: usize) -> bool {
+        load_order
+            .plugins
+            .get(plugin)
+            .and_then(|source| self.get(source))
+            .map_or(false, |record| {
+                load_order.is_injected(plugin, record.formid())
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{general, list, top};
+    use bstr::BString;
+
+    #[test]
+    fn test_injected() {
+        let mut base = Plugin::new("Base.esm");
+        base.push_top(list(0x801, &[]));
+        let mut patch = Plugin::new("Patch.esp");
+        patch
+            .header_mut()
+            .unwrap()
+            .push_master(BString::from("Base.esm"));
+        patch.push_top(list(0x801, &[]));
+        patch.push_top(list(0x900, &[&[0, 8, 0, 1]]));
+        patch.push_top(list(0x0100_0800, &[&[0, 9, 0, 0]]));
+        patch.push_top(top(
+            b"MISC",
+            vec![general(b"MISC", 0x901, Vec::new()).into()],
+        ));
+        let mut load_order = LoadOrder::new(vec![base, patch]);
+
+        assert_eq!(
+            load_order.injected_records(1),
+            vec![FormId::new(0x900), FormId::new(0x901)]
+        );
+        assert!(!load_order.is_injected(1, FormId::new(0x801)));
+        assert!(!load_order.is_injected(0, FormId::new(0x900)));
+        let index = load_order.plugins[1].index();
+        assert!(index
+            .handle(FormId::new(0x900))
+            .unwrap()
+            .is_injected(&load_order, 1));
+        assert!(!index
+            .handle(FormId::new(0x0100_0800))
+            .unwrap()
+            .is_injected(&load_order, 1));
+
+        let map: FormIdMap = vec![
+            (FormId::new(0x900), FormId::new(0x0100_0900)),
+            (FormId::new(0x0100_0800), FormId::new(0x0100_0802)),
+        ]
+        .into_iter()
+        .collect();
+        let stats = load_order.remap_formids(1, &map);
+        assert_eq!(stats.records, 1);
+        assert_eq!(stats.references, 1);
+        assert!(load_order.plugins[1].find(FormId::new(0x900)).is_some());
+        assert_eq!(
+            load_order.plugins[1]
+                .find(FormId::new(0x0100_0802))
+                .unwrap()
+                .formids(),
+            vec![FormId::new(0x900)]
+        );
+
+        // The plugin keeps what it was told, so renumbering it on its own follows the policy too
+        let patch = &mut load_order.plugins[1];
+        assert_eq!(patch.injected().len(), 2);
+        assert_eq!(patch.remap_formids(&map).records, 0);
+        patch.set_injected_policy(InjectedPolicy::Remap);
+        let stats = patch.remap_formids(&map);
+        assert_eq!(stats.records, 1);
+        assert!(patch.find(FormId::new(0x900)).is_none());
+        assert_eq!(
+            patch.injected().iter().collect::<Vec<_>>(),
+            vec![&FormId::new(0x901)]
+        );
+    }
+}
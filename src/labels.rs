@@ -0,0 +1,356 @@
+// Whether the labels of groups match what they hold. A top group is labelled with the type of the
+// records directly within it, and the groups holding the children of a WRLD, CELL or DIAL are
+// labelled with the form id of the record right before them, as are the persistent and temporary
+// children of a cell with that of the group holding them. Plugins edited by hand, or records
+// renumbered without their groups, can leave these out of step, which the game reads as records
+// without a parent.
+// Typed top groups can only hold records of their own type, so only the groups that weren't
+// converted are looked at. A group that isn't a top group but is at the top level of the plugin
+// has no parent to be judged by, so only the groups within it are.
+
+use crate::{
+    groups::{
+        common::{GroupEntry, GroupType, TopGroup},
+        Group,
+    },
+    plugin::Plugin,
+    records::common::{FormId, FormIdVisitor, RecordName},
+    Top,
+};
+use std::fmt::{Display, Formatter};
+
+/// A group whose label doesn't match what it holds
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum LabelIssue {
+    /// A record of [found] directly within the top group labelled [label]
+    StrayRecord {
+        label: String,
+        found: String,
+        id: FormId,
+    },
+    /// A group of the children of [label], which should be labelled [expected] going by where it
+    /// is. None if there is no record or group before it that could be its parent
+    WrongParent {
+        group_type: i32,
+        label: FormId,
+        expected: Option<FormId>,
+    },
+}
+impl Display for LabelIssue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LabelIssue::StrayRecord { label, found, id } => write!(
+                f,
+                "{} record [{:08X}] is within the {} group",
+                found, id.id, label
+            ),
+            LabelIssue::WrongParent {
+                group_type,
+                label,
+                expected: Some(expected),
+            } => write!(
+                f,
+                "Group of type {} is labelled [{:08X}] but is held by [{:08X}]",
+                group_type, label.id, expected.id
+            ),
+            LabelIssue::WrongParent {
+                group_type, label, ..
+            } => write!(
+                f,
+                "Group of type {} is labelled [{:08X}] but has no parent",
+                group_type, label.id
+            ),
+        }
+    }
+}
+
+/// The form id that the group is labelled with, for groups holding the children of a record
+fn parent_label(group_type: &GroupType<'_>) -> Option<FormId> {
+    let mut label = None;
+    let mut group_type = *group_type;
+    group_type.visit_formids(&mut |id| label = Some(*id));
+    label
+}
+
+/// The type of the record that groups of [group_type] follow, for those that do
+fn parent_type(group_type: &GroupType<'_>) -> Option<&'static [u8]> {
+    match group_type {
+        GroupType::WorldChildren(_) => Some(b"WRLD"),
+        GroupType::CellChildren(_) => Some(b"CELL"),
+        GroupType::TopicChildren(_) => Some(b"DIAL"),
+        _ => None,
+    }
+}
+
+fn entry_type<'data>(entry: &GroupEntry<'data>) -> Option<RecordName<'data>> {
+    match entry {
+        GroupEntry::Record(record) => Some(record.type_name),
+        GroupEntry::Compressed(record) => Some(record.type_name),
+        GroupEntry::Group(_) => None,
+    }
+}
+
+/// What the group at [index] of [entries] should be labelled with. [enclosing] is what the group
+/// holding [entries] should be labelled with. Some(None) for groups that should have a parent
+/// but have none, and None for groups that aren't labelled with a parent
+fn expected_parent(
+    entries: &[GroupEntry<'_>],
+    index: usize,
+    enclosing: Option<FormId>,
+) -> Option<Option<FormId>> {
+    let group_type = match &entries[index] {
+        GroupEntry::Group(group) => &group.group_type,
+        _ => return None,
+    };
+    match group_type {
+        GroupType::CellPersistentChildren(_) | GroupType::CellTemporaryChildren(_) => {
+            Some(enclosing)
+        }
+        group_type => {
+            let parent_type = parent_type(group_type)?;
+            let previous = index.checked_sub(1).map(|x| &entries[x]);
+            Some(
+                previous
+                    .filter(|x| entry_type(x).map_or(false, |x| x == parent_type))
+                    .and_then(GroupEntry::formid),
+            )
+        }
+    }
+}
+
+fn check_entries(
+    entries: &[GroupEntry<'_>],
+    enclosing: Option<FormId>,
+    issues: &mut Vec<LabelIssue>,
+) {
+    for (index, entry) in entries.iter().enumerate() {
+        let group = match entry {
+            GroupEntry::Group(group) => group,
+            _ => continue,
+        };
+        let label = parent_label(&group.group_type);
+        let expected = expected_parent(entries, index, enclosing);
+        if let (Some(label), Some(expected)) = (label, expected) {
+            if expected != Some(label) {
+                issues.push(LabelIssue::WrongParent {
+                    group_type: group.group_type.code(),
+                    label,
+                    expected,
+                });
+            }
+        }
+        // The children within are judged by what this group should be labelled with
+        check_entries(&group.entries, expected.flatten().or(label), issues);
+    }
+}
+
+fn repair_entries(entries: &mut [GroupEntry<'_>], enclosing: Option<FormId>) -> usize {
+    let mut fixed = 0;
+    for index in 0..entries.len() {
+        let expected = expected_parent(entries, index, enclosing).flatten();
+        if let GroupEntry::Group(group) = &mut entries[index] {
+            if let Some(expected) = expected {
+                group.group_type.visit_formids(&mut |id| {
+                    if *id != expected {
+                        *id = expected;
+                        fixed += 1;
+                    }
+                });
+            }
+            let label = parent_label(&group.group_type);
+            fixed += repair_entries(&mut group.entries, label);
+        }
+    }
+    fixed
+}
+
+fn stray_records(group: &TopGroup<'_>, issues: &mut Vec<LabelIssue>) {
+    for entry in group.entries.iter() {
+        if let (Some(found), Some(id)) = (entry_type(entry), entry.formid()) {
+            if found != group.label {
+                issues.push(LabelIssue::StrayRecord {
+                    label: group.label.to_string(),
+                    found: found.to_string(),
+                    id,
+                });
+            }
+        }
+    }
+}
+
+/// The type that every record directly within [group] has, if it isn't the group's label
+fn relabelled<'data>(group: &TopGroup<'data>) -> Option<RecordName<'data>> {
+    let mut types = group.entries.iter().filter_map(entry_type);
+    let first = types.next()?;
+    if first != group.label && types.all(|x| x == first) {
+        Some(first)
+    } else {
+        None
+    }
+}
+
+impl<'data> Plugin<'data> {
+    /// Groups whose labels don't match what they hold, and records within a top group of another
+    /// type
+    pub fn label_issues(&self) -> Vec<LabelIssue> {
+        let mut issues = Vec::new();
+        for top in self.tops().iter() {
+            match top {
+                Top::Group(Group::UnknownTop(group)) => {
+                    stray_records(group, &mut issues);
+                    check_entries(&group.entries, None, &mut issues);
+                }
+                Top::Group(Group::Unknown(group)) => {
+                    let label = parent_label(&group.group_type);
+                    check_entries(&group.entries, label, &mut issues);
+                }
+                _ => {}
+            }
+        }
+        issues
+    }
+
+    /// Relabels the groups of children with the parent that they are held by, and top groups
+    /// whose records are all of another type with that type, unless there is already a group of
+    /// it. Returns how many labels were changed. Groups without a parent, and top groups holding
+    /// records of several types, are left as they are, so [Plugin::label_issues] may still
+    /// report them
+    pub fn repair_labels(&mut self) -> usize {
+        let mut fixed = 0;
+        for index in 0..self.tops().len() {
+            let label = match &self.tops()[index] {
+                Top::Group(Group::UnknownTop(group)) => relabelled(group),
+                _ => None,
+            };
+            let taken = |label: RecordName<'_>| {
+                self.tops()
+                    .iter()
+                    .filter_map(|top| match top {
+                        Top::Group(group) => Some(group.group_type()),
+                        _ => None,
+                    })
+                    .any(|x| x == GroupType::Top(label))
+            };
+            let label = label.filter(|x| !taken(x));
+            match self.top_mut(index) {
+                Some(Top::Group(Group::UnknownTop(group))) => {
+                    if let Some(label) = label {
+                        group.label = label;
+                        fixed += 1;
+                    }
+                    fixed += repair_entries(&mut group.entries, None);
+                }
+                Some(Top::Group(Group::Unknown(group))) => {
+                    let label = parent_label(&group.group_type);
+                    fixed += repair_entries(&mut group.entries, label);
+                }
+                _ => {}
+            }
+        }
+        fixed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        context::ParseContext,
+        records::common::{CommonRecordInfo, GeneralRecord},
+        util::Writable,
+    };
+    use bstr::ByteSlice;
+
+    fn record(type_name: &'static [u8], id: u32) -> Vec<u8> {
+        let mut common = CommonRecordInfo::test_default();
+        common.flags.flags = 0;
+        common.id = id;
+        let record = GeneralRecord {
+            type_name: type_name.as_bstr(),
+            common,
+            fields: Vec::new(),
+        };
+        record.to_vec().unwrap()
+    }
+
+    fn group(label: u32, group_type: i32, contents: &[Vec<u8>]) -> Vec<u8> {
+        let contents = contents.concat();
+        let mut data = b"GRUP".to_vec();
+        data.extend_from_slice(&(24 + contents.len() as u32).to_le_bytes());
+        data.extend_from_slice(&label.to_le_bytes());
+        data.extend_from_slice(&group_type.to_le_bytes());
+        data.extend_from_slice(&[0; 8]);
+        data.extend_from_slice(&contents);
+        data
+    }
+
+    fn top(label: &[u8; 4], contents: &[Vec<u8>]) -> Vec<u8> {
+        group(u32::from_le_bytes(*label), 0, contents)
+    }
+
+    #[test]
+    fn test_labels() {
+        let mut header = Plugin::new("Base.esm");
+        header.refresh_header();
+        let sub_block = group(
+            4,
+            3,
+            &[
+                group(0x805, 6, &[]),
+                record(b"CELL", 0x801),
+                group(0x900, 6, &[group(0x900, 8, &[]), group(0x801, 9, &[])]),
+            ],
+        );
+        let data = [
+            header.to_vec().unwrap(),
+            top(b"WEAP", &[record(b"WEAP", 0x810), record(b"MISC", 0x811)]),
+            top(b"KYWD", &[record(b"SPEL", 0x812), record(b"SPEL", 0x813)]),
+            top(b"CELL", &[group(9, 2, &[sub_block])]),
+            top(b"DIAL", &[record(b"DIAL", 0x820), group(0x821, 7, &[])]),
+        ]
+        .concat();
+        let mut ctx = ParseContext::default();
+        let mut plugin = Plugin::parse("Base.esm", &data, &mut ctx).unwrap();
+
+        let stray = |label: &str, found: &str, id| LabelIssue::StrayRecord {
+            label: label.to_string(),
+            found: found.to_string(),
+            id: FormId::new(id),
+        };
+        let wrong = |group_type, label, expected: Option<u32>| LabelIssue::WrongParent {
+            group_type,
+            label: FormId::new(label),
+            expected: expected.map(FormId::new),
+        };
+        let issues = plugin.label_issues();
+        assert_eq!(
+            issues,
+            vec![
+                stray("WEAP", "MISC", 0x811),
+                stray("KYWD", "SPEL", 0x812),
+                stray("KYWD", "SPEL", 0x813),
+                wrong(6, 0x805, None),
+                // The persistent children are judged by the cell, not the wrong label above them
+                wrong(6, 0x900, Some(0x801)),
+                wrong(8, 0x900, Some(0x801)),
+                wrong(7, 0x821, Some(0x820)),
+            ]
+        );
+        assert_eq!(
+            issues[4].to_string(),
+            "Group of type 6 is labelled [00000900] but is held by [00000801]"
+        );
+
+        assert_eq!(plugin.repair_labels(), 4);
+        assert_eq!(
+            plugin.label_issues(),
+            vec![stray("WEAP", "MISC", 0x811), wrong(6, 0x805, None)]
+        );
+        assert_eq!(plugin.repair_labels(), 0);
+        assert!(plugin.unknown_top(b"SPEL").is_some());
+        let children = plugin.cell_children(FormId::new(0x801)).unwrap().unwrap();
+        assert!(children.persistent().is_some() && children.temporary().is_some());
+        let topics = plugin.topics().unwrap().unwrap();
+        assert!(topics.topic(FormId::new(0x820)).unwrap().children.is_some());
+    }
+}
@@ -0,0 +1,535 @@
+use crate::{
+    context::{Diagnostic, ParseContext, ParseOptions},
+    error::{ErrorCode, VivecError},
+    plugin::Plugin,
+    progress::{Progress, ProgressState},
+    records::{common::FormId, RecordRef},
+    GeneralError,
+};
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+/// A plugin file read into memory, which the parsed [Plugin] borrows from
+#[derive(Debug, Clone, PartialEq)]
+pub struct PluginFile {
+    /// Filename, such as `Skyrim.esm`
+    pub name: String,
+    pub data: Vec<u8>,
+}
+impl PluginFile {
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<PluginFile, OpenError<'static>> {
+        let path = path.as_ref();
+        let name = path
+            .file_name()
+            .map(|x| x.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        match std::fs::read(path) {
+            Ok(data) => Ok(PluginFile { name, data }),
+            Err(error) => Err(OpenError::Io {
+                path: path.to_path_buf(),
+                error,
+            }),
+        }
+    }
+
+    /// Reads the files at [paths] concurrently, keeping their order
+    pub fn read_all<P>(paths: &[P]) -> Result<Vec<PluginFile>, OpenError<'static>>
+    where
+        P: AsRef<Path> + Sync,
+    {
+        parallel_map(paths, default_threads(), |path| {
+            PluginFile::read(path.as_ref())
+        })
+        .into_iter()
+        .collect()
+    }
+}
+
+#[derive(Debug)]
+pub enum OpenError<'data> {
+    Io {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    Parse {
+        name: String,
+        error: GeneralError<'data>,
+    },
+}
+impl std::fmt::Display for OpenError<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OpenError::Io { path, error } => {
+                write!(f, "Failed to read {}: {}", path.display(), error)
+            }
+            OpenError::Parse { name, .. } => write!(f, "Failed to load {}", name),
+        }
+    }
+}
+impl std::error::Error for OpenError<'_> {}
+impl VivecError for OpenError<'_> {
+    fn code(&self) -> ErrorCode {
+        match self {
+            OpenError::Io { .. } => ErrorCode::Io,
+            OpenError::Parse { error, .. } => error.code(),
+        }
+    }
+
+    fn inner(&self) -> Option<&dyn VivecError> {
+        match self {
+            OpenError::Io { .. } => None,
+            OpenError::Parse { error, .. } => Some(error),
+        }
+    }
+}
+
+/// The plugin of a [LoadOrder] that a record was read from. This differs from
+/// [LoadOrder::origin] for overrides, which come from a later plugin than the one defining them
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct Source<'a> {
+    /// Position of the plugin in the load order
+    pub index: usize,
+    pub name: &'a str,
+}
+
+/// A record along with the plugin that it is from
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SourcedRecord<'a, 'data> {
+    pub source: Source<'a>,
+    pub record: RecordRef<'a, 'data>,
+}
+
+/// Plugins in the order that the game loads them, with later plugins overriding earlier ones
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct LoadOrder<'data> {
+    pub plugins: Vec<Plugin<'data>>,
+}
+impl<'data> LoadOrder<'data> {
+    pub fn new(plugins: Vec<Plugin<'data>>) -> LoadOrder<'data> {
+        LoadOrder { plugins }
+    }
+
+    /// Parses [files] concurrently, using a thread for each available core.
+    /// Returns the diagnostics of each plugin, in the same order as the plugins.
+    pub fn open(
+        files: &'data [PluginFile],
+        options: ParseOptions,
+    ) -> Result<(LoadOrder<'data>, Vec<Vec<Diagnostic>>), OpenError<'data>> {
+        LoadOrder::open_with_threads(files, options, default_threads())
+    }
+
+    /// [LoadOrder::open] using at most [threads] threads.
+    /// Masters are checked once every plugin is parsed, with a warning for each master that is
+    /// not loaded before the plugin that uses it.
+    pub fn open_with_threads(
+        files: &'data [PluginFile],
+        options: ParseOptions,
+        threads: usize,
+    ) -> Result<(LoadOrder<'data>, Vec<Vec<Diagnostic>>), OpenError<'data>> {
+        LoadOrder::open_with_progress(files, options, threads, &mut ())
+    }
+
+    /// [LoadOrder::open_with_threads], reporting to [progress] with the totals over all of the
+    /// plugins. If [progress] asks to stop, the plugins still being parsed stop and
+    /// [GeneralError::Cancelled] is returned.
+    pub fn open_with_progress(
+        files: &'data [PluginFile],
+        options: ParseOptions,
+        threads: usize,
+        progress: &mut (dyn Progress + Send),
+    ) -> Result<(LoadOrder<'data>, Vec<Vec<Diagnostic>>), OpenError<'data>> {
+        let shared = Mutex::new(SharedProgress {
+            progress,
+            bytes_total: files.iter().map(|x| x.data.len()).sum(),
+            bytes_processed: vec![0; files.len()],
+            records_converted: vec![0; files.len()],
+        });
+        let indices: Vec<usize> = (0..files.len()).collect();
+        let parsed = parallel_map(&indices, threads, |&index| {
+            let file = &files[index];
+            let mut ctx = ParseContext::new(options.clone());
+            let mut progress = PluginProgress {
+                shared: &shared,
+                index,
+            };
+            Plugin::parse_with_progress(file.name.clone(), &file.data, &mut ctx, &mut progress)
+                .map(|plugin| (plugin, ctx))
+                .map_err(|error| OpenError::Parse {
+                    name: file.name.clone(),
+                    error,
+                })
+        });
+
+        let mut plugins = Vec::with_capacity(parsed.len());
+        let mut contexts = Vec::with_capacity(parsed.len());
+        for result in parsed {
+            let (plugin, ctx) = result?;
+            plugins.push(plugin);
+            contexts.push(ctx);
+        }
+
+        let load_order = LoadOrder::new(plugins);
+        for (index, ctx) in contexts.iter_mut().enumerate() {
+            for master in load_order.plugins[index].masters() {
+                match load_order.index_of(master) {
+                    Some(master_index) if master_index < index => {}
+                    Some(_) => ctx.warn(format!("Master {} is loaded after its dependent", master)),
+                    None => ctx.warn(format!("Master {} is not loaded", master)),
+                }
+            }
+        }
+        let diagnostics = contexts.into_iter().map(|ctx| ctx.diagnostics).collect();
+        Ok((load_order, diagnostics))
+    }
+
+    /// Position of the plugin called [name]
+    pub fn index_of(&self, name: &[u8]) -> Option<usize> {
+        self.plugins.iter().position(|x| x.is_named(name))
+    }
+
+    /// The plugin at [index]
+    pub fn source(&self, index: usize) -> Option<Source<'_>> {
+        self.plugins.get(index).map(|plugin| Source {
+            index,
+            name: &plugin.name,
+        })
+    }
+
+    /// Every record of every plugin, in load order
+    pub fn records(&self) -> Vec<SourcedRecord<'_, 'data>> {
+        self.plugins
+            .iter()
+            .enumerate()
+            .flat_map(|(index, plugin)| {
+                let source = Source {
+                    index,
+                    name: &plugin.name,
+                };
+                plugin
+                    .records()
+                    .into_iter()
+                    .map(move |record| SourcedRecord { source, record })
+            })
+            .collect()
+    }
+
+    /// Where [id] (as written in the plugin at [plugin]) was first defined: the position of that
+    /// plugin, and the object id within it.
+    /// None if [id] refers to a master that is not loaded.
+    pub fn origin(&self, plugin: usize, id: FormId) -> Option<(usize, u32)> {
+        let masters = self.plugins.get(plugin)?.masters();
+        let index = usize::from(id.mod_index());
+        let object = id.object_index();
+        if index == masters.len() {
+            Some((plugin, object))
+        } else {
+            let master = masters.get(index)?;
+            Some((self.index_of(master)?, object))
+        }
+    }
+
+    /// The form id that the plugin at [plugin] uses for [object] of the plugin at [origin].
+    /// None if [origin] is neither [plugin] nor one of its masters.
+    pub fn local_id(&self, plugin: usize, origin: usize, object: u32) -> Option<FormId> {
+        let masters = self.plugins.get(plugin)?.masters();
+        let index = if origin == plugin {
+            masters.len()
+        } else {
+            let origin_name = self.plugins.get(origin)?.name.as_bytes();
+            masters
+                .iter()
+                .position(|x| x.eq_ignore_ascii_case(origin_name))?
+        };
+        Some(FormId::from_parts(index as u8, object))
+    }
+
+    /// Finds the winning version of the record that [id] (as written in the plugin at [plugin])
+    /// refers to, which is the one in the last plugin that has it.
+    pub fn resolve(&self, plugin: usize, id: FormId) -> Option<SourcedRecord<'_, 'data>> {
+        let (origin, object) = self.origin(plugin, id)?;
+        (origin..self.plugins.len())
+            .rev()
+            .find_map(|index| self.version_in(index, origin, object))
+    }
+
+    /// Every version of the record that [id] (as written in the plugin at [plugin]) refers to,
+    /// in load order: the record that defines it followed by each override of it
+    pub fn versions(&self, plugin: usize, id: FormId) -> Vec<SourcedRecord<'_, 'data>> {
+        let (origin, object) = match self.origin(plugin, id) {
+            Some(origin) => origin,
+            None => return Vec::new(),
+        };
+        (origin..self.plugins.len())
+            .filter_map(|index| self.version_in(index, origin, object))
+            .collect()
+    }
+
+    /// The version that the plugin at [index] has of the record numbered [object] in the plugin
+    /// at [origin], if it has one
+    fn version_in(
+        &self,
+        index: usize,
+        origin: usize,
+        object: u32,
+    ) -> Option<SourcedRecord<'_, 'data>> {
+        let plugin = &self.plugins[index];
+        let masters = plugin.masters();
+        let master_index = if index == origin {
+            masters.len()
+        } else {
+            let origin_name = self.plugins[origin].name.as_bytes();
+            masters
+                .iter()
+                .position(|x| x.eq_ignore_ascii_case(origin_name))?
+        };
+        let local = FormId::new(((master_index as u32) << 24) | object);
+        let record = plugin.find(local)?;
+        let source = Source {
+            index,
+            name: &plugin.name,
+        };
+        Some(SourcedRecord { source, record })
+    }
+}
+
+/// Progress over all of the plugins being opened
+struct SharedProgress<'p> {
+    progress: &'p mut (dyn Progress + Send),
+    bytes_total: usize,
+    /// By plugin
+    bytes_processed: Vec<usize>,
+    records_converted: Vec<usize>,
+}
+
+/// Reports the progress of a single plugin to the [SharedProgress]
+struct PluginProgress<'a, 'p> {
+    shared: &'a Mutex<SharedProgress<'p>>,
+    index: usize,
+}
+impl Progress for PluginProgress<'_, '_> {
+    fn report(&mut self, state: &ProgressState<'_>) {
+        let mut shared = self.shared.lock().unwrap();
+        shared.bytes_processed[self.index] = state.bytes_processed;
+        shared.records_converted[self.index] = state.records_converted;
+        let total = ProgressState {
+            bytes_processed: shared.bytes_processed.iter().sum(),
+            bytes_total: shared.bytes_total,
+            records_converted: shared.records_converted.iter().sum(),
+            group: state.group,
+            records_total: None,
+        };
+        shared.progress.report(&total);
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.shared.lock().unwrap().progress.is_cancelled()
+    }
+}
+
+fn default_threads() -> usize {
+    std::thread::available_parallelism()
+        .map(|x| x.get())
+        .unwrap_or(1)
+}
+
+/// Applies [func] to each of [items] on a pool of at most [threads] threads, which each take the
+/// next unprocessed item until none remain. The results are in the same order as [items].
+fn parallel_map<'a, T, R, F>(items: &'a [T], threads: usize, func: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&'a T) -> R + Sync,
+{
+    let next = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<R>>> = Mutex::new(items.iter().map(|_| None).collect());
+    std::thread::scope(|scope| {
+        for _ in 0..threads.clamp(1, items.len().max(1)) {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let item = match items.get(index) {
+                    Some(item) => item,
+                    None => break,
+                };
+                let result = func(item);
+                results.lock().unwrap()[index] = Some(result);
+            });
+        }
+    });
+    results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|x| x.expect("Every item is processed"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        records::common::{CommonRecordInfo, GeneralRecord},
+        records::fields::common::GeneralField,
+        util::Writable,
+    };
+    use bstr::ByteSlice;
+
+    fn plugin_file(name: &str, masters: &[&str]) -> PluginFile {
+        let mut fields = vec![GeneralField::new(b"HEDR".as_bstr(), &[0; 12])];
+        let masters: Vec<Vec<u8>> = masters
+            .iter()
+            .map(|x| x.bytes().chain(std::iter::once(0)).collect())
+            .collect();
+        for master in masters.iter() {
+            fields.push(GeneralField::new(b"MAST".as_bstr(), master));
+            fields.push(GeneralField::new(b"DATA".as_bstr(), &[0; 8]));
+        }
+        let mut common = CommonRecordInfo::test_default();
+        common.flags.flags = 0;
+        let header = GeneralRecord {
+            type_name: b"TES4".as_bstr(),
+            common,
+            fields,
+        };
+        let mut data = Vec::new();
+        header.write_to(&mut data).unwrap();
+        PluginFile {
+            name: name.to_string(),
+            data,
+        }
+    }
+
+    #[test]
+    fn test_open() {
+        let files = vec![
+            plugin_file("Skyrim.esm", &[]),
+            plugin_file("Patch.esp", &["Skyrim.esm", "Mod.esp", "Missing.esm"]),
+            plugin_file("Mod.esp", &["Skyrim.esm"]),
+        ];
+        for threads in 1..=4 {
+            let (load_order, diagnostics) =
+                LoadOrder::open_with_threads(&files, ParseOptions::default(), threads).unwrap();
+            let names: Vec<&str> = load_order.plugins.iter().map(|x| x.name.as_str()).collect();
+            assert_eq!(names, vec!["Skyrim.esm", "Patch.esp", "Mod.esp"]);
+            assert_eq!(load_order.plugins[1].masters().len(), 3);
+            assert!(diagnostics[0].is_empty());
+            assert!(diagnostics[2].is_empty());
+            let messages: Vec<&str> = diagnostics[1].iter().map(|x| x.message.as_str()).collect();
+            assert_eq!(
+                messages,
+                vec![
+                    "Master Mod.esp is loaded after its dependent",
+                    "Master Missing.esm is not loaded"
+                ]
+            );
+        }
+
+        let mut reports = Vec::new();
+        let mut progress = |state: &ProgressState<'_>| {
+            reports.push((
+                state.bytes_processed,
+                state.bytes_total,
+                state.records_converted,
+            ))
+        };
+        LoadOrder::open_with_progress(&files, ParseOptions::default(), 2, &mut progress).unwrap();
+        let total: usize = files.iter().map(|x| x.data.len()).sum();
+        assert_eq!(reports.len(), 3);
+        assert!(reports.windows(2).all(|x| x[0].0 < x[1].0));
+        assert_eq!(reports.last(), Some(&(total, total, 3)));
+
+        struct Cancelled;
+        impl Progress for Cancelled {
+            fn report(&mut self, _state: &ProgressState<'_>) {}
+
+            fn is_cancelled(&self) -> bool {
+                true
+            }
+        }
+        match LoadOrder::open_with_progress(&files, ParseOptions::default(), 2, &mut Cancelled) {
+            Err(OpenError::Parse { error, .. }) => assert_eq!(error, GeneralError::Cancelled),
+            x => panic!("Expected cancellation, got {:?}", x),
+        }
+
+        let mut broken = plugin_file("Broken.esp", &[]);
+        broken.data.truncate(10);
+        let files = vec![plugin_file("Skyrim.esm", &[]), broken];
+        match LoadOrder::open(&files, ParseOptions::default()) {
+            Err(OpenError::Parse { name, .. }) => assert_eq!(name, "Broken.esp"),
+            x => panic!("Expected a parse error, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn test_sources() {
+        let with_records = |mut file: PluginFile, ids: &[u32]| {
+            for id in ids {
+                let mut common = CommonRecordInfo::test_default();
+                common.flags.flags = 0;
+                common.id = *id;
+                let record = GeneralRecord {
+                    type_name: b"MISC".as_bstr(),
+                    common,
+                    fields: Vec::new(),
+                };
+                record.write_to(&mut file.data).unwrap();
+            }
+            file
+        };
+        let files = vec![
+            with_records(plugin_file("Skyrim.esm", &[]), &[0x800]),
+            with_records(
+                plugin_file("Mod.esp", &["Skyrim.esm"]),
+                &[0x800, 0x0100_0801],
+            ),
+            with_records(
+                plugin_file("Patch.esp", &["Skyrim.esm", "Mod.esp"]),
+                &[0x800],
+            ),
+        ];
+        let (load_order, _) = LoadOrder::open(&files, ParseOptions::default()).unwrap();
+        assert_eq!(
+            load_order.source(1),
+            Some(Source {
+                index: 1,
+                name: "Mod.esp"
+            })
+        );
+        assert_eq!(load_order.source(3), None);
+
+        fn sources<'a>(versions: Vec<SourcedRecord<'a, '_>>) -> Vec<&'a str> {
+            versions.iter().map(|x| x.source.name).collect()
+        }
+        assert_eq!(
+            sources(load_order.versions(0, FormId::new(0x800))),
+            vec!["Skyrim.esm", "Mod.esp", "Patch.esp"]
+        );
+        assert_eq!(
+            sources(load_order.versions(2, FormId::new(0x0100_0801))),
+            vec!["Mod.esp"]
+        );
+        let winner = load_order.resolve(1, FormId::new(0x800)).unwrap();
+        assert_eq!(winner.source.index, 2);
+        assert_eq!(winner.record.formid(), FormId::new(0x800));
+        assert!(load_order.resolve(0, FormId::new(0x0100_0801)).is_none());
+
+        let records = load_order.records();
+        assert_eq!(records.len(), 7);
+        assert_eq!(
+            sources(records),
+            vec![
+                "Skyrim.esm",
+                "Skyrim.esm",
+                "Mod.esp",
+                "Mod.esp",
+                "Mod.esp",
+                "Patch.esp",
+                "Patch.esp"
+            ]
+        );
+    }
+}
@@ -0,0 +1,261 @@
+// Moving the text of a plugin's lstrings between its fields and its string tables.
+// Delocalizing writes the text from the string tables into the fields as null terminated strings
+// and clears the LOCALIZED flag, so the plugin works without its Strings folder. Localizing does
+// the reverse, moving the text into new string tables and leaving their ids in the fields.
+// Both are done as the plugin is written, as the changed fields can't borrow from the plugin's
+// data. Records within groups that weren't converted, such as INFO, are read as general records
+// to have their lstrings replaced, and compressed records are decompressed for it.
+
+use crate::{
+    plugin::{write_entry_replacing, Plugin},
+    records::common::{
+        lstring::{lstring_kind, LString},
+        record_flag, Recompression,
+    },
+    strings::{strings_path, Localization, StringTable, StringsKind},
+    util::{Writable, WriteError},
+    walk::EntryRef,
+};
+use bstr::ByteSlice;
+use std::{borrow::Cow, io::Write, path::Path};
+
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct LocalizeStats {
+    /// Fields whose text was moved
+    pub fields: usize,
+    /// Lstrings whose id isn't in the string tables. They are delocalized as empty strings, as
+    /// the game would show them
+    pub missing: usize,
+}
+
+/// Writes [entry] with each of its lstring fields replaced by what [replace] returns for its
+/// data, or as it is if there are none. The TES4 record is written with [localized] as its
+/// LOCALIZED flag. Compressed records are decompressed, and compressed again if they changed
+fn write_replacing<F>(
+    entry: EntryRef<'_, '_>,
+    localized: bool,
+    mut replace: F,
+) -> Result<Vec<u8>, WriteError>
+where
+    F: FnMut(&[u8], StringsKind) -> Vec<u8>,
+{
+    write_entry_replacing(entry, Recompression::default(), |general| {
+        if general.type_name == "TES4" {
+            let flags = &mut general.common.flags.flags;
+            if localized {
+                *flags |= record_flag::LOCALIZED;
+            } else {
+                *flags &= !record_flag::LOCALIZED;
+            }
+        }
+        let type_name = general.type_name;
+        Ok(general
+            .fields
+            .iter()
+            .map(|field| {
+                lstring_kind(type_name, field.type_name).map(|kind| replace(field.data, kind))
+            })
+            .collect())
+    })
+}
+
+impl Plugin<'_> {
+    /// Writes the plugin with the text of its lstrings, looked up in [localization], within the
+    /// fields themselves, and without the LOCALIZED flag
+    pub fn write_delocalized_to<T: Write>(
+        &self,
+        w: &mut T,
+        localization: &Localization,
+    ) -> Result<LocalizeStats, WriteError> {
+        let mut stats = LocalizeStats::default();
+        let localized = self.is_localized();
+        self.write_entries_with(w, |entry| {
+            let data = write_replacing(entry, false, |data, kind| {
+                if !localized {
+                    return data.as_bytes().to_vec();
+                }
+                stats.fields += 1;
+                let mut text = match LString::parse_as(data, true) {
+                    Ok((_, LString::Index(0))) => Vec::new(),
+                    Ok((_, lstring)) => match localization.resolve(&lstring, kind) {
+                        Some(text) => text.as_bytes().to_vec(),
+                        None => {
+                            stats.missing += 1;
+                            Vec::new()
+                        }
+                    },
+                    Err(_) => {
+                        stats.missing += 1;
+                        Vec::new()
+                    }
+                };
+                text.push(0);
+                text
+            })?;
+            Ok(Cow::Owned(data))
+        })?;
+        Ok(stats)
+    }
+
+    /// Writes the plugin with the text of its lstrings moved into new string tables, which are
+    /// returned in the order of [StringsKind::ALL], and with the LOCALIZED flag.
+    /// Ids are given out from 1 in the order the strings are written, and empty strings get the
+    /// id 0, which the game shows as nothing
+    pub fn write_localized_to<T: Write>(
+        &self,
+        w: &mut T,
+    ) -> Result<([StringTable; 3], LocalizeStats), WriteError> {
+        let mut tables = [
+            StringTable::new(StringsKind::Strings),
+            StringTable::new(StringsKind::DlStrings),
+            StringTable::new(StringsKind::IlStrings),
+        ];
+        let mut stats = LocalizeStats::default();
+        let mut next_id = 1u32;
+        let localized = self.is_localized();
+        self.write_entries_with(w, |entry| {
+            let data = write_replacing(entry, true, |data, kind| {
+                if localized {
+                    return data.as_bytes().to_vec();
+                }
+                stats.fields += 1;
+                let end = data.find_byte(0).unwrap_or(data.len());
+                if end == 0 {
+                    return 0u32.to_le_bytes().into();
+                }
+                let id = next_id;
+                next_id += 1;
+                let table = tables.iter_mut().find(|x| x.kind == kind);
+                if let Some(table) = table {
+                    table.insert(id, &data[..end]);
+                }
+                id.to_le_bytes().into()
+            })?;
+            Ok(Cow::Owned(data))
+        })?;
+        Ok((tables, stats))
+    }
+}
+
+/// Writes [tables] as the string tables of [plugin] in [language], within the Strings folder of
+/// [data_folder]
+pub fn write_string_tables(
+    data_folder: &Path,
+    plugin: &str,
+    language: &str,
+    tables: &[StringTable],
+) -> Result<(), WriteError> {
+    std::fs::create_dir_all(data_folder.join("Strings"))?;
+    for table in tables {
+        let path = strings_path(data_folder, plugin, language, table.kind);
+        std::fs::write(path, table.to_vec()?)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        context::ParseContext,
+        groups::{
+            common::{CommonGroupInfo, GroupEntry, TopGroup},
+            Group,
+        },
+        records::{
+            common::{CommonRecordInfo, CompressedRecord, GeneralRecord},
+            fields::common::GeneralField,
+            Record,
+        },
+        Top,
+    };
+    use flate2::Compression;
+
+    #[test]
+    fn test_localize() {
+        let mut plugin = Plugin::new("Patch.esp");
+        let mut common = CommonRecordInfo::test_default();
+        common.flags.flags = 0;
+        plugin.push_top(Top::Record(Record::Unknown(GeneralRecord {
+            type_name: b"MESG".as_bstr(),
+            common: common.clone(),
+            fields: vec![
+                GeneralField::new(b"EDID".as_bstr(), b"Journal\0"),
+                GeneralField::new(b"FULL".as_bstr(), b"Journal\0"),
+                GeneralField::new(b"DESC".as_bstr(), b"Dear diary\0"),
+                GeneralField::new(b"ITXT".as_bstr(), b"\0"),
+            ],
+        })));
+        // Records within groups that weren't converted, compressed or not
+        let cell = |id, name: &'static [u8]| {
+            let mut common = common.clone();
+            common.id = id;
+            GeneralRecord {
+                type_name: b"CELL".as_bstr(),
+                common,
+                fields: vec![GeneralField::new(b"FULL".as_bstr(), name)],
+            }
+        };
+        let level = Compression::default().level();
+        let compressed = CompressedRecord::compress(&cell(0x802, b"Riverwood\0"), level).unwrap();
+        plugin.push_top(Top::Group(Group::UnknownTop(TopGroup {
+            label: b"CELL".as_bstr(),
+            common: CommonGroupInfo::new(),
+            entries: vec![
+                GroupEntry::Record(cell(0x801, b"Whiterun\0")),
+                GroupEntry::Compressed(compressed),
+            ],
+        })));
+
+        let mut data = Vec::new();
+        let (tables, stats) = plugin.write_localized_to(&mut data).unwrap();
+        assert_eq!(stats.fields, 5);
+        assert_eq!(tables[0].get(1), Some(b"Journal".as_bstr()));
+        assert_eq!(tables[0].get(3), Some(b"Whiterun".as_bstr()));
+        assert_eq!(tables[0].get(4), Some(b"Riverwood".as_bstr()));
+        assert_eq!(tables[1].get(2), Some(b"Dear diary".as_bstr()));
+        assert!(tables[2].strings.is_empty());
+        let mut ctx = ParseContext::default();
+        let localized = Plugin::parse("Patch.esp", &data, &mut ctx).unwrap();
+        assert!(localized.is_localized());
+        let mut buffer = Vec::new();
+        let message = localized.records()[1].to_general(&mut buffer).unwrap();
+        assert_eq!(message.fields[0].data, b"Journal\0");
+        assert_eq!(message.fields[1].data, &[1, 0, 0, 0]);
+        assert_eq!(message.fields[2].data, &[2, 0, 0, 0]);
+        assert_eq!(message.fields[3].data, &[0, 0, 0, 0]);
+        match &localized.tops()[2] {
+            Top::Group(Group::UnknownTop(group)) => {
+                match &group.entries[0] {
+                    GroupEntry::Record(record) => assert_eq!(record.fields[0].data, &[3, 0, 0, 0]),
+                    x => panic!("Unexpected entry {:?}", x),
+                }
+                match &group.entries[1] {
+                    GroupEntry::Compressed(record) => {
+                        let record = record.decompress(&mut buffer).unwrap();
+                        assert_eq!(record.fields[0].data, &[4, 0, 0, 0]);
+                    }
+                    x => panic!("Unexpected entry {:?}", x),
+                }
+            }
+            x => panic!("Unexpected entry {:?}", x),
+        }
+
+        let dir = std::env::temp_dir().join(format!("vivec_localize_{}", std::process::id()));
+        write_string_tables(&dir, "Patch.esp", "english", &tables).unwrap();
+        let localization = localized.localization(&dir, "english").unwrap();
+        let mut delocalized = Vec::new();
+        let stats = localized
+            .write_delocalized_to(&mut delocalized, &localization)
+            .unwrap();
+        assert_eq!(
+            stats,
+            LocalizeStats {
+                fields: 5,
+                missing: 0
+            }
+        );
+        assert_eq!(delocalized, plugin.to_vec().unwrap());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
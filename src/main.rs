@@ -1,28 +1,117 @@
+use bstr::BStr;
+use context::{ParseContext, Severity};
 use derive_more::From;
+use error::{ErrorCode, VivecError};
 use groups::{
-    common::{FromGeneralGroup, FromTopGroup, FromTopGroupError, GeneralGroup, GroupType},
+    common::{FromGeneralGroup, FromTopGroupError, GeneralGroup, GroupType},
     Group,
 };
-use parse::{many, take, PResult, Parse, ParseError};
-use records::common::{FromRecord, FromRecordError, GeneralRecord, TypeNamed};
-use util::{DataSize, Writable};
+use parse::{take, PResult, Parse, ParseError};
+use progress::{Progress, ProgressState};
+use records::common::{FromRecordError, GeneralRecord, TypeNamed};
+use util::{DataSize, Writable, WriteError};
 
+mod analysis;
+mod archives;
+#[cfg(feature = "async")]
+mod async_write;
+mod clean;
+mod compact;
+mod compressed;
+mod condition;
+mod config;
+mod context;
+mod copy;
+mod data_folder;
+mod delta;
+mod edit;
+mod enchantment;
+mod error;
+#[cfg(test)]
+mod fixtures;
 mod groups;
+mod incremental;
+mod index;
+mod injected;
+mod labels;
+mod load_order;
+mod localize;
+mod masters;
+mod membership;
 mod parse;
+mod plugin;
+mod progress;
 mod records;
+mod redact;
+mod references;
+mod remap;
+mod search;
+mod snapshot;
+mod spill;
+mod stream;
+mod strings;
 mod util;
+mod validate;
+mod verify;
+mod walk;
+mod watch;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum GeneralTop<'data> {
     Record(GeneralRecord<'data>),
     Group(GeneralGroup<'data>),
 }
+impl<'data> GeneralTop<'data> {
+    /// The label of the group, if this is a top group
+    pub fn top_label(&self) -> Option<&'data BStr> {
+        match self {
+            GeneralTop::Group(GeneralGroup {
+                group_type: GroupType::Top(label),
+                ..
+            }) => Some(label),
+            _ => None,
+        }
+    }
+}
 
 #[derive(Debug, Clone, From, PartialEq)]
-enum GeneralError<'data> {
+pub enum GeneralError<'data> {
     TopGroup(FromTopGroupError<'data>),
     Record(FromRecordError<'data>),
     ParseError(ParseError<'data>),
+    /// The [progress::Progress] that was passed in asked to stop
+    #[from(ignore)]
+    Cancelled,
+}
+impl std::fmt::Display for GeneralError<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GeneralError::TopGroup(_) => write!(f, "Failed to convert group"),
+            GeneralError::Record(_) => write!(f, "Failed to convert record"),
+            GeneralError::ParseError(_) => write!(f, "Failed to parse"),
+            GeneralError::Cancelled => write!(f, "Cancelled"),
+        }
+    }
+}
+impl std::error::Error for GeneralError<'_> {}
+impl VivecError for GeneralError<'_> {
+    fn code(&self) -> ErrorCode {
+        match self {
+            GeneralError::TopGroup(error) => error.code(),
+            GeneralError::Record(error) => error.code(),
+            GeneralError::ParseError(error) => error.code(),
+            GeneralError::Cancelled => ErrorCode::Cancelled,
+        }
+    }
+
+    fn inner(&self) -> Option<&dyn VivecError> {
+        match self {
+            GeneralError::TopGroup(error) => Some(error),
+            GeneralError::Record(error) => Some(error),
+            GeneralError::ParseError(error) => Some(error),
+            GeneralError::Cancelled => None,
+        }
+    }
 }
 
 fn parse_top_level<'data>(data: &'data [u8]) -> PResult<GeneralTop<'data>, GeneralError<'data>> {
@@ -44,7 +133,7 @@ pub enum Top<'data> {
     Group(groups::Group<'data>),
 }
 impl<'data> Writable for Top<'data> {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: std::io::Write,
     {
@@ -64,77 +153,358 @@ impl<'data> DataSize for Top<'data> {
 }
 
 fn parse_file(data: &[u8]) -> PResult<Vec<Top>, GeneralError> {
-    let (data, general_top) = many(data, parse_top_level)?;
+    parse_file_with(data, &mut ParseContext::default())
+}
 
-    println!(
-        "After parsing general top level, there was {} bytes left",
-        data.len()
-    );
+fn parse_file_with<'data>(
+    data: &'data [u8],
+    ctx: &mut ParseContext,
+) -> PResult<'data, Vec<Top<'data>>, GeneralError<'data>> {
+    parse_file_with_progress(data, ctx, &mut ())
+}
 
-    let mut spec_top = Vec::new();
+/// How many groups deep the entries of a top group are reported to a [Progress] on their own,
+/// which for CELL and WRLD is down to the blocks of cells
+const PROGRESS_DEPTH: usize = 2;
+
+/// [parse_file_with], reporting to [progress] after each top-level entry is converted. The
+/// entries within top groups are reported as they are read, see [PROGRESS_DEPTH]
+fn parse_file_with_progress<'data>(
+    data: &'data [u8],
+    ctx: &mut ParseContext,
+    progress: &mut dyn Progress,
+) -> PResult<'data, Vec<Top<'data>>, GeneralError<'data>> {
+    let mut tracker = Tracker::new(data.len(), progress);
+    if ctx.options.skip_malformed {
+        return parse_file_recovering(data, ctx, &mut tracker);
+    }
 
+    let mut rest = data;
+    let mut spec_top = Vec::new();
+    let mut first = true;
     // Note: we parse record fields as if the order doesn't matter, but It probably does, but we can't be sure it does :(
-    for top in general_top {
-        match top {
-            GeneralTop::Record(record) => {
-                if record
-                    .common
-                    .flags
-                    .is(records::common::record_flag::COMPRESSED)
-                {
-                    println!("{} is compressed", record.type_name);
-                }
+    while !rest.is_empty() && !is_end_of_plugin(rest, first) {
+        first = false;
+        tracker.check_cancelled()?;
+        let start = data.len() - rest.len();
+        let (after, top) = if rest.starts_with(b"GRUP") {
+            let (after, group) =
+                GeneralGroup::parse_visiting(rest, PROGRESS_DEPTH, &mut |end, entry| {
+                    tracker.parsed(start + end, entry)
+                })?;
+            (after, GeneralTop::Group(group))
+        } else {
+            parse_top_level(rest)?
+        };
+        rest = after;
+        let label = top.top_label();
+        if let Some(top) = convert_top(top, ctx)? {
+            tracker.converted(data.len() - rest.len(), label, &top);
+            spec_top.push(top);
+        }
+    }
+    warn_trailing(rest, ctx);
 
-                spec_top.push(Top::Record(match record.type_name.as_ref() {
-                    b"TES4" => records::tes4::TES4Record::from_record(record)?.1.into(),
-                    b"AACT" => records::aact::AACTRecord::from_record(record)?.1.into(),
-                    b"ADDN" => records::addn::ADDNRecord::from_record(record)?.1.into(),
-                    b"ACHR" => records::achr::ACHRRecord::from_record(record)?.1.into(),
-                    b"ACTI" => records::acti::ACTIRecord::from_record(record)?.1.into(),
-                    b"ALCH" => records::alch::ALCHRecord::from_record(record)?.1.into(),
-                    b"AMMO" => records::ammo::AMMORecord::from_record(record)?.1.into(),
-                    b"ANIO" => records::anio::ANIORecord::from_record(record)?.1.into(),
-                    b"APPA" => records::appa::APPARecord::from_record(record)?.1.into(),
-                    b"ARMA" => records::arma::ARMARecord::from_record(record)?.1.into(),
-                    b"ARMO" => records::armo::ARMORecord::from_record(record)?.1.into(),
-                    b"ARTO" => records::arto::ARTORecord::from_record(record)?.1.into(),
-                    b"ASPC" => records::aspc::ASPCRecord::from_record(record)?.1.into(),
-                    b"ASTP" => records::astp::ASTPRecord::from_record(record)?.1.into(),
-                    b"AVIF" => records::avif::AVIFRecord::from_record(record)?.1.into(),
-                    b"BOOK" => records::book::BOOKRecord::from_record(record)?.1.into(),
-                    _ => record.into(),
-                }));
-            }
-            GeneralTop::Group(group) => spec_top.push(Top::Group(match group.group_type {
-                GroupType::Top(_) => {
-                    let group = groups::common::TopGroup::from_general_group(group);
-                    match group.label.as_ref() {
-                        b"AACT" => groups::aact::AACTGroup::from_top_group(group)?.1.into(),
-                        b"ACTI" => groups::acti::ACTIGroup::from_top_group(group)?.1.into(),
-                        b"ADDN" => groups::addn::ADDNGroup::from_top_group(group)?.1.into(),
-                        b"ALCH" => groups::alch::ALCHGroup::from_top_group(group)?.1.into(),
-                        b"AMMO" => groups::ammo::AMMOGroup::from_top_group(group)?.1.into(),
-                        b"ANIO" => groups::anio::ANIOGroup::from_top_group(group)?.1.into(),
-                        b"APPA" => groups::appa::APPAGroup::from_top_group(group)?.1.into(),
-                        b"ARMA" => groups::arma::ARMAGroup::from_top_group(group)?.1.into(),
-                        b"ARMO" => groups::armo::ARMOGroup::from_top_group(group)?.1.into(),
-                        b"ARTO" => groups::arto::ARTOGroup::from_top_group(group)?.1.into(),
-                        b"ASPC" => groups::aspc::ASPCGroup::from_top_group(group)?.1.into(),
-                        b"ASTP" => groups::astp::ASTPGroup::from_top_group(group)?.1.into(),
-                        b"AVIF" => groups::avif::AVIFGroup::from_top_group(group)?.1.into(),
-                        b"BOOK" => groups::book::BOOKGroup::from_top_group(group)?.1.into(),
-                        _ => group.into(),
+    Ok((rest, spec_top))
+}
+
+/// [parse_file_with] for [context::ParseOptions::skip_malformed]
+fn parse_file_recovering<'data>(
+    data: &'data [u8],
+    ctx: &mut ParseContext,
+    tracker: &mut Tracker<'_>,
+) -> PResult<'data, Vec<Top<'data>>, GeneralError<'data>> {
+    let mut iter = TopIterator::new(data);
+    let mut tops = Vec::new();
+    while !is_end_of_plugin(iter.remaining(), iter.offset() == 0) {
+        tracker.check_cancelled()?;
+        let start = iter.offset();
+        let visited = if iter.remaining().starts_with(b"GRUP") {
+            match GeneralGroup::parse_visiting(
+                iter.remaining(),
+                PROGRESS_DEPTH,
+                &mut |end, entry| tracker.parsed(start + end, entry),
+            ) {
+                Ok((rest, group)) => {
+                    iter.advance(rest);
+                    Some(Ok(GeneralTop::Group(group)))
+                }
+                Err(GeneralError::Cancelled) => return Err(GeneralError::Cancelled),
+                // Parsed again below, to skip what is malformed
+                Err(_) => {
+                    tracker.records_parsed = 0;
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        let top = match visited.or_else(|| iter.next()) {
+            Some(Err(TopError { offset, .. })) if data[offset..].starts_with(b"GRUP") => {
+                // Keep what can be parsed of the group
+                let mut skipped = Vec::new();
+                let group = GeneralGroup::parse_recovering(&data[offset..], &mut |at, error| {
+                    skipped.push((offset + at, error))
+                });
+                ctx.leave_record();
+                for (at, error) in skipped {
+                    ctx.diagnose(
+                        Severity::Error,
+                        format!("Skipped malformed entry at offset {}: {:?}", at, error),
+                    );
+                }
+                match group {
+                    Ok((_, group)) => Ok(GeneralTop::Group(group)),
+                    Err(error) => Err(TopError {
+                        offset,
+                        error: error.into(),
+                    }),
+                }
+            }
+            Some(top) => top,
+            None => break,
+        };
+        match top {
+            Ok(top) => {
+                let label = top.top_label();
+                let converted = match convert_top(top.clone(), ctx) {
+                    Ok(Some(converted)) => converted,
+                    Ok(None) => continue,
+                    Err(err) => {
+                        ctx.diagnose(
+                            Severity::Error,
+                            format!("Kept unconverted, as it failed to convert: {:?}", err),
+                        );
+                        unconverted(top)
                     }
+                };
+                tracker.converted(iter.offset(), label, &converted);
+                tops.push(converted);
+            }
+            Err(TopError { offset, error }) => {
+                ctx.leave_record();
+                ctx.diagnose(
+                    Severity::Error,
+                    format!("Skipped malformed entry at offset {}: {:?}", offset, error),
+                );
+            }
+        }
+    }
+    ctx.leave_record();
+    warn_trailing(iter.remaining(), ctx);
+    Ok((iter.remaining(), tops))
+}
+
+/// Counts what has been converted, for reporting to a [Progress]
+struct Tracker<'p> {
+    progress: &'p mut dyn Progress,
+    bytes_total: usize,
+    records_converted: usize,
+    /// Records read within the top group that is being read, which are counted as converted
+    /// once it is
+    records_parsed: usize,
+}
+impl<'p> Tracker<'p> {
+    fn new(bytes_total: usize, progress: &'p mut dyn Progress) -> Tracker<'p> {
+        Tracker {
+            progress,
+            bytes_total,
+            records_converted: 0,
+            records_parsed: 0,
+        }
+    }
+
+    fn check_cancelled<'data>(&self) -> Result<(), GeneralError<'data>> {
+        if self.progress.is_cancelled() {
+            Err(GeneralError::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// [entry], within a top group, was read and ended at [end] in the data
+    fn parsed<'data>(
+        &mut self,
+        end: usize,
+        entry: &groups::common::GroupEntry<'_>,
+    ) -> Result<(), GeneralError<'data>> {
+        self.records_parsed += groups::common::count_records(std::slice::from_ref(entry));
+        self.report(end, None);
+        self.check_cancelled()
+    }
+
+    /// [top], the top group with [label] if it is one, was converted and ended at [end] in the
+    /// data
+    fn converted(&mut self, end: usize, label: Option<&BStr>, top: &Top<'_>) {
+        self.records_converted += match top {
+            Top::Record(_) => 1,
+            Top::Group(group) => group.record_count(),
+        };
+        self.records_parsed = 0;
+        self.report(end, label);
+    }
+
+    fn report(&mut self, end: usize, label: Option<&BStr>) {
+        self.progress.report(&ProgressState {
+            bytes_processed: end,
+            bytes_total: self.bytes_total,
+            records_converted: self.records_converted + self.records_parsed,
+            group: label,
+            records_total: None,
+        });
+    }
+}
+
+/// Whether [data], which follows the entries of a plugin, is not part of the plugin: a second
+/// plugin concatenated onto it (another TES4 record) or padding.
+/// [first] is whether [data] is at the start of the plugin.
+fn is_end_of_plugin(data: &[u8], first: bool) -> bool {
+    if data.is_empty() {
+        return false;
+    }
+    (!first && data.starts_with(b"TES4")) || data.iter().all(|x| *x == 0)
+}
+
+fn warn_trailing(data: &[u8], ctx: &mut ParseContext) {
+    if !data.is_empty() {
+        ctx.warn(format!(
+            "There was {} bytes of trailing data after the last entry",
+            data.len()
+        ));
+    }
+}
+
+/// Keeps a top-level entry as it was parsed, without converting it
+fn unconverted(top: GeneralTop<'_>) -> Top<'_> {
+    match top {
+        GeneralTop::Record(record) => Top::Record(records::Record::Unknown(record)),
+        GeneralTop::Group(group) => Top::Group(match group.group_type {
+            GroupType::Top(_) => groups::common::TopGroup::from_general_group(group).into(),
+            _ => group.into(),
+        }),
+    }
+}
+
+/// Converts a top-level entry into its typed form, if we know of one.
+/// None if it is a record that [ParseContext::hooks] vetoed
+fn convert_top<'data>(
+    top: GeneralTop<'data>,
+    ctx: &mut ParseContext,
+) -> Result<Option<Top<'data>>, GeneralError<'data>> {
+    Ok(Some(match top {
+        GeneralTop::Record(mut record) => {
+            ctx.enter_record(&record);
+            let mut record = if ctx.apply_field_rules(&mut record) {
+                records::Record::Unknown(record)
+            } else {
+                records::Record::from_general(record, ctx)?
+            };
+            if !ctx.hooks.run(&mut record) {
+                return Ok(None);
+            }
+            Top::Record(record)
+        }
+        GeneralTop::Group(group) => Top::Group(match group.group_type {
+            GroupType::Top(_) => {
+                let group = groups::common::TopGroup::from_general_group(group);
+                // Deleted and compressed records are kept as they are within the typed group
+                Group::from_top(group, ctx)?
+            }
+            _ => {
+                let mut group = group;
+                ctx.process_unconverted(&mut group.entries);
+                group.into()
+            }
+        }),
+    }))
+}
+
+/// Yields each top-level record or group in turn, without converting them.
+/// When an entry fails to parse the error is yielded, and the entry is skipped if its header
+/// could still be read, so that one broken record does not hide the rest of the plugin.
+#[derive(Debug, Clone)]
+pub struct TopIterator<'data> {
+    data: &'data [u8],
+    /// Offset of [data] within the data the iterator was created with
+    offset: usize,
+}
+impl<'data> TopIterator<'data> {
+    pub fn new(data: &'data [u8]) -> TopIterator<'data> {
+        TopIterator { data, offset: 0 }
+    }
+
+    /// Offset of the next entry
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Data which has not been iterated over yet
+    pub fn remaining(&self) -> &'data [u8] {
+        self.data
+    }
+
+    fn advance(&mut self, rest: &'data [u8]) {
+        self.offset += self.data.len() - rest.len();
+        self.data = rest;
+    }
+}
+impl<'data> Iterator for TopIterator<'data> {
+    type Item = Result<GeneralTop<'data>, TopError<'data>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.is_empty() {
+            return None;
+        }
+
+        let offset = self.offset;
+        match parse_top_level(self.data) {
+            Ok((rest, top)) => {
+                self.advance(rest);
+                Some(Ok(top))
+            }
+            Err(error) => {
+                match groups::common::entry_size(self.data) {
+                    Some(size) if size <= self.data.len() => self.advance(&self.data[size..]),
+                    // There's no way to know where the next entry starts
+                    _ => self.advance(&[]),
                 }
-                _ => group.into(),
-            })),
+                Some(Err(TopError { offset, error }))
+            }
         }
     }
+}
 
-    Ok((data, spec_top))
+#[derive(Debug, Clone, PartialEq)]
+pub struct TopError<'data> {
+    /// Offset of the entry which failed to parse
+    pub offset: usize,
+    pub error: GeneralError<'data>,
 }
 
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let command: Option<fn(&[String], &config::Config)> = match args.first().map(String::as_str) {
+        Some("watch") => Some(watch::watch_command),
+        Some("flags") => Some(analysis::flags::flags_command),
+        Some("budget") => Some(analysis::budget::budget_command),
+        Some("compressed") => Some(analysis::compression::compressed_command),
+        Some("economy") => Some(analysis::economy::economy_command),
+        Some("redact") => Some(redact::redact_command),
+        Some("merge") => Some(spill::merge_command),
+        _ => None,
+    };
+    if let Some(command) = command {
+        let dir = std::env::current_dir().expect("Failed to get current directory");
+        let config = match config::Config::discover(&dir) {
+            Ok(config) => config,
+            Err(error) => {
+                eprintln!("{}", error);
+                std::process::exit(1);
+            }
+        };
+        return command(&args[1..], &config);
+    }
+
     println!("Starting");
     let data = std::fs::read("./ex/Dawnguard.esm").expect("Failed to read data from file");
     let (_data, result) = parse_file(data.as_slice()).expect("Failed to parse");
@@ -163,6 +533,7 @@ fn main() {
                     Group::ASTP(group) => println!("ASTP group: {} entries", group.records.len()),
                     Group::AVIF(group) => println!("AVIF group: {} entries", group.records.len()),
                     Group::BOOK(group) => println!("BOOK group: {} entries", group.records.len()),
+                    Group::FLST(group) => println!("FLST group: {} entries", group.records.len()),
                     Group::Unknown(_) => print!("GU, "),
                     Group::UnknownTop(_) => print!("GT, "),
                 },
@@ -176,50 +547,291 @@ mod tests {
     use super::*;
 
     #[test]
-    fn test_equivalent_writeback() {
-        let data = std::fs::read("./ex/Skyrim.esm").expect("Failed to read data from file");
-        let (_data, result) = parse_file(data.as_slice()).expect("Failed to parse");
-        let mut stored = Vec::new();
-        stored.reserve(data.len());
-        println!("Writing top level data");
-        let mut start_i: usize = 0;
-        for top in result {
-            top.write_to(&mut stored).unwrap();
-            let mut i = start_i;
-            let end = stored.len();
-            loop {
-                if i == end {
-                    break;
-                }
+    fn test_top_iterator_recovery() {
+        use bstr::ByteSlice;
+        use records::fields::common::GeneralField;
+        let edid = GeneralField::new(b"EDID".as_bstr(), b"Action\0");
+        let record = fixtures::general(b"AACT", 0x420, vec![edid]);
+        let mut data = record.to_vec().unwrap();
+        let first_len = data.len();
+        // A record whose fields can't be parsed
+        data.extend_from_slice(b"AACT");
+        data.extend_from_slice(&3u32.to_le_bytes());
+        data.extend_from_slice(&[0; 16]);
+        data.extend_from_slice(b"abc");
+        record.write_to(&mut data).unwrap();
 
-                if data[i] != stored[i] {
-                    panic!(
-                        "(i={}) Data[i] = ({:x?}) did not equal Stored[i] = ({:x?}), while dealing with: {:?}",
-                        i, data[i], stored[i], top
-                    );
-                }
+        let mut iter = TopIterator::new(&data);
+        assert_eq!(iter.next(), Some(Ok(GeneralTop::Record(record.clone()))));
+        assert!(matches!(iter.next(), Some(Err(TopError { offset, .. })) if offset == first_len));
+        assert_eq!(iter.offset(), first_len + 27);
+        assert_eq!(iter.next(), Some(Ok(GeneralTop::Record(record))));
+        assert_eq!(iter.next(), None);
 
-                i += 1;
-            }
-            start_i = end;
-        }
-        println!("Wrote data.");
-        println!("Original data size: {}", data.len());
-        println!("New      data size: {}", stored.len());
-        // Perform a full equality check
-        if data.len() == stored.len() {
-            println!("Equivalent sizes.");
-            for i in 0..data.len() {
-                if data[i] != stored[i] {
-                    panic!(
-                        "Data[{}] ({:x?}) did not equal Stored[{}] ({:x?})",
-                        i, data[i], i, stored[i]
-                    );
+        // A truncated header can't be skipped over
+        let mut iter = TopIterator::new(b"AACT\x05");
+        assert!(matches!(iter.next(), Some(Err(_))));
+        assert_eq!(iter.next(), None);
+    }
+
+    #[test]
+    fn test_skip_malformed() {
+        use bstr::ByteSlice;
+        use records::{fields::common::GeneralField, Record};
+        let edid = GeneralField::new(b"EDID".as_bstr(), b"Action\0");
+        let record = fixtures::general(b"AACT", 0x420, vec![edid]);
+        // Parses, but its CNAM is too short to be converted
+        let mut broken = record.clone();
+        broken
+            .fields
+            .push(GeneralField::new(b"CNAM".as_bstr(), &[1, 2]));
+        let mut data = Vec::new();
+        record.write_to(&mut data).unwrap();
+        broken.write_to(&mut data).unwrap();
+        // A record whose fields can't be parsed
+        let mut garbage = b"AACT".to_vec();
+        garbage.extend_from_slice(&3u32.to_le_bytes());
+        garbage.extend_from_slice(&[0; 16]);
+        garbage.extend_from_slice(b"abc");
+        data.extend_from_slice(&garbage);
+        record.write_to(&mut data).unwrap();
+        // The same within a group, which keeps the rest of its records
+        let mut contents = Vec::new();
+        record.write_to(&mut contents).unwrap();
+        broken.write_to(&mut contents).unwrap();
+        let garbage_offset = contents.len();
+        contents.extend_from_slice(&garbage);
+        record.write_to(&mut contents).unwrap();
+        let group_offset = data.len();
+        data.extend_from_slice(&fixtures::group(b"AACT", 0, &[contents]));
+
+        assert!(parse_file_with(&data, &mut ParseContext::default()).is_err());
+
+        let mut ctx = ParseContext::new(context::ParseOptions::lenient());
+        let (rest, tops) = parse_file_with(&data, &mut ctx).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(tops.len(), 4);
+        assert!(matches!(tops[0], Top::Record(Record::AACT(_))));
+        assert_eq!(tops[1], Top::Record(Record::Unknown(broken.clone())));
+        assert!(matches!(tops[2], Top::Record(Record::AACT(_))));
+        match &tops[3] {
+            Top::Group(group @ Group::AACT(_)) => {
+                let records = group.records();
+                assert_eq!(records.len(), 3);
+                assert_eq!(records[2], records::RecordRef::Unknown(&broken));
+            }
+            top => panic!("Expected AACT group, got {:?}", top),
+        }
+        let errors: Vec<_> = ctx
+            .diagnostics
+            .iter()
+            .filter(|x| x.severity == Severity::Error)
+            .collect();
+        assert_eq!(errors.len(), 4);
+        assert!(errors[0].record.is_some());
+        assert!(errors[1].message.starts_with("Skipped malformed entry"));
+        assert_eq!(errors[1].record, None);
+        assert!(errors[2].message.starts_with(&format!(
+            "Skipped malformed entry at offset {}:",
+            group_offset + groups::common::GROUPH_SIZE + garbage_offset
+        )));
+        assert!(errors[3].message.starts_with("Kept unconverted"));
+        assert!(errors[3].record.is_some());
+    }
+
+    #[test]
+    fn test_parse_progress() {
+        use bstr::ByteSlice;
+        use groups::common::{CommonGroupInfo, GeneralGroup, GroupEntry, TopGroup};
+        use progress::{Progress, ProgressState};
+        let cell = |id| GroupEntry::Record(fixtures::general(b"CELL", id, Vec::new()));
+        let block = |number, id| {
+            GroupEntry::Group(GeneralGroup {
+                group_type: GroupType::InteriorCellBlock(number),
+                common: CommonGroupInfo::new(),
+                entries: vec![cell(id)],
+            })
+        };
+        let cells = TopGroup {
+            label: b"CELL".as_bstr(),
+            common: CommonGroupInfo::new(),
+            entries: vec![block(0, 0x800), block(1, 0x801), block(2, 0x802)],
+        };
+        let mut data = Vec::new();
+        cells.write_to(&mut data).unwrap();
+
+        // Each block is reported as it is read, rather than the whole group at once
+        for options in [
+            context::ParseOptions::default(),
+            context::ParseOptions::lenient(),
+        ] {
+            let mut reports = Vec::new();
+            let mut progress = |state: &ProgressState<'_>| {
+                reports.push((state.bytes_processed, state.records_converted))
+            };
+            let mut ctx = ParseContext::new(options);
+            parse_file_with_progress(&data, &mut ctx, &mut progress).unwrap();
+            assert_eq!(reports.len(), 4);
+            assert!(reports.windows(2).all(|x| x[0] <= x[1]));
+            assert_eq!(reports.last(), Some(&(data.len(), 3)));
+        }
+
+        struct CancelAfter(usize);
+        impl Progress for CancelAfter {
+            fn report(&mut self, _state: &ProgressState<'_>) {
+                self.0 = self.0.saturating_sub(1);
+            }
+
+            fn is_cancelled(&self) -> bool {
+                self.0 == 0
+            }
+        }
+        let mut ctx = ParseContext::default();
+        assert_eq!(
+            parse_file_with_progress(&data, &mut ctx, &mut CancelAfter(1)).unwrap_err(),
+            GeneralError::Cancelled
+        );
+    }
+
+    #[test]
+    fn test_field_rules() {
+        use bstr::ByteSlice;
+        use context::{FieldPolicy, FieldRule, ParseOptions};
+        use groups::common::GroupEntry;
+        use records::{fields::common::GeneralField, Record};
+        let offsets = [0; 100];
+        let world = fixtures::general(
+            b"WRLD",
+            0x420,
+            vec![
+                GeneralField::new(b"EDID".as_bstr(), b"Tamriel\0"),
+                GeneralField::new(b"OFST".as_bstr(), &offsets),
+            ],
+        );
+        let action = fixtures::general(
+            b"AACT",
+            0x420,
+            vec![
+                GeneralField::new(b"EDID".as_bstr(), b"Action\0"),
+                GeneralField::new(b"CNAM".as_bstr(), &[1, 2, 3, 0]),
+            ],
+        );
+        let mut data = Vec::new();
+        world.write_to(&mut data).unwrap();
+        action.write_to(&mut data).unwrap();
+        // The same records again, within their top groups
+        for record in [&world, &action] {
+            let entry = record.to_vec().unwrap();
+            data.extend_from_slice(&fixtures::group(record.type_name, 0, &[entry]));
+        }
+
+        let mut options = ParseOptions {
+            field_rules: vec![
+                FieldRule {
+                    min_size: 64,
+                    ..FieldRule::new(Some(*b"WRLD"), *b"OFST", FieldPolicy::Drop)
+                },
+                FieldRule::new(None, *b"CNAM", FieldPolicy::Drop),
+            ],
+            ..ParseOptions::default()
+        };
+        let mut ctx = ParseContext::new(options.clone());
+        let (_, tops) = parse_file_with(&data, &mut ctx).unwrap();
+        match &tops[..] {
+            [Top::Record(Record::Unknown(world)), Top::Record(Record::AACT(action)), Top::Group(Group::UnknownTop(worlds)), Top::Group(Group::AACT(actions))] =>
+            {
+                assert_eq!(world.fields.len(), 1);
+                assert_eq!(action.rgb_index, None);
+                assert_eq!(action.fields.len(), 1);
+                match &worlds.entries[..] {
+                    [GroupEntry::Record(world)] => assert_eq!(world.fields.len(), 1),
+                    x => panic!("Unexpected entries {:?}", x),
                 }
+                assert_eq!(actions.records[0].rgb_index, None);
             }
-            println!("The two files are equal!");
-        } else {
-            println!("They did not have equivalent sizes :(");
+            x => panic!("Unexpected entries {:?}", x),
+        }
+
+        // Records with a raw field are left unconverted, so are kept as they were
+        options.field_rules[1].policy = FieldPolicy::Raw;
+        let mut ctx = ParseContext::new(options.clone());
+        let (_, tops) = parse_file_with(&data, &mut ctx).unwrap();
+        assert_eq!(tops[1], Top::Record(Record::Unknown(action.clone())));
+        match &tops[3] {
+            Top::Group(Group::AACT(actions)) => {
+                assert!(actions.records.is_empty());
+                assert_eq!(actions.kept.len(), 1);
+                assert_eq!(actions.kept[0].entry, GroupEntry::Record(action.clone()));
+            }
+            x => panic!("Unexpected entry {:?}", x),
+        }
+
+        // Too small to be dropped
+        options.field_rules[0].min_size = 101;
+        let mut ctx = ParseContext::new(options);
+        let (_, tops) = parse_file_with(&data, &mut ctx).unwrap();
+        assert_eq!(tops[0], Top::Record(Record::Unknown(world.clone())));
+        match &tops[2] {
+            Top::Group(Group::UnknownTop(worlds)) => {
+                assert_eq!(worlds.entries, vec![GroupEntry::Record(world)])
+            }
+            x => panic!("Unexpected entry {:?}", x),
+        }
+    }
+
+    #[test]
+    fn test_deleted_records() {
+        use bstr::ByteSlice;
+        use records::{
+            common::{record_flag, CommonRecordInfo, GeneralRecord, RecordFlags},
+            Record,
+        };
+        let mut armo = fixtures::general(b"ARMO", 0x420, Vec::new());
+        armo.common.flags = RecordFlags::new(record_flag::DELETED);
+        let mut data = armo.to_vec().unwrap();
+        let record_len = data.len();
+        data.extend_from_slice(&fixtures::group(b"ARMO", 0, &[data.clone()]));
+
+        let mut ctx = ParseContext::default();
+        let (_, tops) = parse_file_with(&data, &mut ctx).unwrap();
+        assert_eq!(tops[0], Top::Record(Record::Deleted(armo.clone())));
+        // The group stays typed, holding the deleted record as it was
+        match &tops[1] {
+            Top::Group(Group::ARMO(group)) => {
+                assert!(group.records.is_empty());
+                assert_eq!(group.kept.len(), 1);
+            }
+            top => panic!("Expected ARMO group, got {:?}", top),
         }
+        assert!(ctx.diagnostics.is_empty());
+        let mut written = Vec::new();
+        tops[1].write_to(&mut written).unwrap();
+        assert_eq!(written, &data[record_len..]);
+
+        // Undeleting still needs the record to be valid
+        let deleted = Record::Deleted(armo);
+        assert!(deleted.clone().undelete(&mut ctx).is_err());
+
+        let mut common = CommonRecordInfo::test_default();
+        common.flags = RecordFlags::new(record_flag::IGNORED | record_flag::COMPRESSED);
+        let aact = GeneralRecord {
+            type_name: b"AACT".as_bstr(),
+            common,
+            fields: vec![],
+        };
+        let record = Record::from_general(aact, &mut ctx).unwrap();
+        assert!(matches!(record, Record::Deleted(_)));
+        match record.undelete(&mut ctx).unwrap() {
+            Record::AACT(aact) => assert_eq!(aact.common.flags.flags, record_flag::COMPRESSED),
+            record => panic!("Expected AACT, got {:?}", record),
+        }
+    }
+
+    #[test]
+    fn test_equivalent_writeback() {
+        let data = std::fs::read("./ex/Skyrim.esm").expect("Failed to read data from file");
+        let report = verify::verify_roundtrip(&data, 10).expect("Failed to parse");
+        println!("{}", report);
+        assert!(report.is_equivalent());
     }
 }
@@ -0,0 +1,292 @@
+// Changing the masters of a plugin. The mod index of a form id is its position in the list of
+// masters, with the plugin itself after them, so adding or removing a master moves the form ids
+// of the masters after it and of the plugin's own records. These are renumbered along with the
+// list, see Plugin::remap_formids_with.
+// Whether a master is still needed is judged by the form ids that can be seen: the ids of
+// records, the references of converted records, and the parents of groups. Records that weren't
+// converted may refer to masters without it being known, and their form ids can't be renumbered,
+// so changing the masters refuses plugins that have them.
+
+use crate::{
+    edit::EditError,
+    groups::{
+        common::{walk_group_entries, GroupEntry},
+        Group,
+    },
+    plugin::Plugin,
+    records::{
+        common::{FormId, FormIdVisitor},
+        RecordRef,
+    },
+    Top,
+};
+use bstr::{BStr, BString, ByteSlice};
+
+/// The most masters a plugin can have. Its own records take the mod index after them, and 0xFF
+/// is kept for the records that the game makes as it runs
+pub const MAX_MASTERS: usize = 0xFE;
+
+impl Plugin<'_> {
+    /// How many times each master is used by the form ids that can be seen, along with the
+    /// amount of records whose references can't be seen
+    fn master_uses(&self) -> (Vec<usize>, usize) {
+        let mut uses = vec![0; self.masters().len()];
+        let mut count = |id: FormId| {
+            if id.id != 0 {
+                if let Some(uses) = uses.get_mut(usize::from(id.mod_index())) {
+                    *uses += 1;
+                }
+            }
+        };
+
+        let mut hidden = 0;
+        for record in self.records() {
+            count(record.formid());
+            record.formids().into_iter().for_each(&mut count);
+            if matches!(record, RecordRef::Unknown(_) | RecordRef::Deleted(_)) {
+                hidden += 1;
+            }
+        }
+        for top in self.tops().iter() {
+            let entries = match top {
+                Top::Group(Group::Unknown(group)) => {
+                    group.group_type.formids().into_iter().for_each(&mut count);
+                    &group.entries
+                }
+                Top::Group(Group::UnknownTop(group)) => &group.entries,
+                Top::Group(group) => {
+                    for kept in group.kept() {
+                        if let GroupEntry::Compressed(record) = &kept.entry {
+                            count(FormId::new(record.common.id));
+                            hidden += 1;
+                        }
+                    }
+                    continue;
+                }
+                _ => continue,
+            };
+            walk_group_entries(entries, &mut |entry| match entry {
+                GroupEntry::Group(group) => {
+                    group.group_type.formids().into_iter().for_each(&mut count)
+                }
+                entry => {
+                    entry.formid().into_iter().for_each(&mut count);
+                    hidden += 1;
+                }
+            });
+        }
+        (uses, hidden)
+    }
+
+    /// Position of the master called [name]
+    pub(crate) fn master_index(&self, name: &[u8]) -> Option<usize> {
+        self.masters()
+            .iter()
+            .position(|master| master.eq_ignore_ascii_case(name))
+    }
+
+    /// Whether [count] masters can be added, which renumbers the plugin's own records. Fails if
+    /// some records weren't converted, as their references to them wouldn't be renumbered, or
+    /// if the plugin would have more than [MAX_MASTERS]
+    pub(crate) fn can_add_masters(&self, count: usize) -> Result<(), EditError> {
+        let masters = self.masters().len() + count;
+        if masters > MAX_MASTERS {
+            return Err(EditError::TooManyMasters(masters));
+        }
+        let hidden = self.hidden_records();
+        if hidden != 0 {
+            return Err(EditError::HiddenReferences { records: hidden });
+        }
+        Ok(())
+    }
+
+    /// Adds [filename] to the end of the masters, returning its mod index. The form ids of this
+    /// plugin's own records move up to make room for it. If it is already a master, nothing is
+    /// changed and its mod index is returned. Fails without changing anything as with
+    /// [Plugin::can_add_masters]
+    pub fn add_master(&mut self, filename: BString) -> Result<u8, EditError> {
+        if let Some(index) = self.master_index(&filename) {
+            return Ok(index as u8);
+        }
+        self.can_add_masters(1)?;
+        let index = self.masters().len() as u8;
+        match self.header_mut() {
+            Some(header) => header.push_master(filename),
+            None => return Ok(index),
+        }
+        self.remap_formids_with(|id| {
+            if id.id != 0 && id.mod_index() >= index && id.mod_index() < 0xFF {
+                Some(id.with_mod_index(id.mod_index() + 1))
+            } else {
+                None
+            }
+        });
+        Ok(index)
+    }
+
+    /// Removes the master called [name], moving the form ids of the masters after it and of this
+    /// plugin down to match. Fails if anything is known to use it, or if some records weren't
+    /// converted, as they may use it and their references wouldn't be renumbered
+    pub fn remove_master(&mut self, name: &[u8]) -> Result<(), EditError> {
+        let index = self
+            .master_index(name)
+            .ok_or_else(|| EditError::UnknownMaster(name.to_str_lossy().into_owned()))?;
+        let (uses, hidden) = self.master_uses();
+        if hidden != 0 {
+            return Err(EditError::HiddenReferences { records: hidden });
+        }
+        let uses = uses[index];
+        if uses != 0 {
+            return Err(EditError::MasterInUse {
+                master: name.to_str_lossy().into_owned(),
+                uses,
+            });
+        }
+        self.remove_master_at(index);
+        Ok(())
+    }
+
+    fn remove_master_at(&mut self, index: usize) {
+        if let Some(header) = self.header_mut() {
+            header.remove_master(index);
+        }
+        let index = index as u8;
+        self.remap_formids_with(|id| {
+            if id.mod_index() > index {
+                Some(id.with_mod_index(id.mod_index() - 1))
+            } else {
+                None
+            }
+        });
+    }
+
+    /// Masters that none of the form ids that can be seen belong to. Records that weren't
+    /// converted may still refer to them, see [Plugin::clean_masters]
+    pub fn unused_masters(&self) -> Vec<&BStr> {
+        let (uses, _) = self.master_uses();
+        self.masters()
+            .into_iter()
+            .zip(uses)
+            .filter(|(_, uses)| *uses == 0)
+            .map(|(master, _)| master)
+            .collect()
+    }
+
+    /// Removes every master that isn't used, renumbering the form ids after them, and returns
+    /// their filenames. Fails without changing anything if some records weren't converted, as
+    /// the masters they refer to aren't known
+    pub fn clean_masters(&mut self) -> Result<Vec<BString>, EditError> {
+        let (uses, hidden) = self.master_uses();
+        if hidden != 0 {
+            return Err(EditError::HiddenReferences { records: hidden });
+        }
+        let unused: Vec<usize> = (0..uses.len()).filter(|x| uses[*x] == 0).collect();
+        let masters: Vec<BString> = self.masters().into_iter().map(BStr::to_owned).collect();
+        // From the last, so the positions of those before it don't change
+        for index in unused.iter().rev() {
+            self.remove_master_at(*index);
+        }
+        Ok(unused.into_iter().map(|x| masters[x].clone()).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        error::{ErrorCode, VivecError},
+        fixtures::list,
+        records::{
+            common::{CommonRecordInfo, GeneralRecord},
+            Record,
+        },
+    };
+
+    fn ids(plugin: &Plugin<'_>) -> Vec<(u32, Vec<u32>)> {
+        plugin
+            .records()
+            .into_iter()
+            .skip(1)
+            .map(|record| {
+                let refs = record.formids().into_iter().map(|x| x.id).collect();
+                (record.formid().id, refs)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_masters() {
+        let mut plugin = Plugin::new("Patch.esp");
+        assert_eq!(plugin.add_master(BString::from("Skyrim.esm")), Ok(0));
+        plugin.push_top(list(0x0100_0800, &[&[1, 0, 0, 0], &[0, 8, 0, 1]]));
+        assert_eq!(plugin.add_master(BString::from("Update.esm")), Ok(1));
+        assert_eq!(plugin.add_master(BString::from("skyrim.esm")), Ok(0));
+        assert_eq!(plugin.add_master(BString::from("Unused.esm")), Ok(2));
+        assert_eq!(
+            plugin.masters(),
+            vec![
+                b"Skyrim.esm".as_bstr(),
+                b"Update.esm".as_bstr(),
+                b"Unused.esm".as_bstr()
+            ]
+        );
+        assert_eq!(ids(&plugin), vec![(0x0300_0800, vec![1, 0x0300_0800])]);
+        // The TES4 record keeps its null id
+        assert_eq!(plugin.records()[0].formid(), FormId::new(0));
+
+        plugin.push_top(list(0x0100_0900, &[]));
+        assert_eq!(plugin.unused_masters(), vec![b"Unused.esm".as_bstr()]);
+        let error = plugin.remove_master(b"Update.esm").unwrap_err();
+        assert_eq!(error.code(), ErrorCode::MasterInUse);
+        assert_eq!(
+            plugin.remove_master(b"Missing.esm"),
+            Err(EditError::UnknownMaster("Missing.esm".to_string()))
+        );
+
+        plugin.remove_master(b"unused.esm").unwrap();
+        assert_eq!(
+            ids(&plugin),
+            vec![(0x0200_0800, vec![1, 0x0200_0800]), (0x0100_0900, vec![])]
+        );
+        plugin.tops_mut().pop();
+        assert_eq!(
+            plugin.clean_masters().unwrap(),
+            vec![BString::from("Update.esm")]
+        );
+        assert_eq!(plugin.masters(), vec![b"Skyrim.esm".as_bstr()]);
+        assert_eq!(ids(&plugin), vec![(0x0100_0800, vec![1, 0x0100_0800])]);
+
+        plugin.push_top(Top::Record(Record::Unknown(GeneralRecord {
+            type_name: b"MISC".as_bstr(),
+            common: CommonRecordInfo::test_default(),
+            fields: Vec::new(),
+        })));
+        assert_eq!(
+            plugin.clean_masters(),
+            Err(EditError::HiddenReferences { records: 1 })
+        );
+        // Their form ids can't be renumbered either
+        assert_eq!(
+            plugin.add_master(BString::from("Dawnguard.esm")),
+            Err(EditError::HiddenReferences { records: 1 })
+        );
+        assert_eq!(
+            plugin.remove_master(b"Skyrim.esm"),
+            Err(EditError::HiddenReferences { records: 1 })
+        );
+        assert_eq!(plugin.masters(), vec![b"Skyrim.esm".as_bstr()]);
+    }
+
+    #[test]
+    fn test_too_many_masters() {
+        let mut plugin = Plugin::new("Patch.esp");
+        for index in 0..MAX_MASTERS {
+            let index = plugin.add_master(BString::from(format!("{}.esm", index)));
+            assert!(index.is_ok());
+        }
+        let error = plugin.add_master(BString::from("Last.esm")).unwrap_err();
+        assert_eq!(error, EditError::TooManyMasters(MAX_MASTERS + 1));
+        assert_eq!(error.code(), ErrorCode::TooManyMasters);
+        assert_eq!(plugin.masters().len(), MAX_MASTERS);
+    }
+}
@@ -0,0 +1,529 @@
+// Keyword (KWDA) and form list (FLST) memberships as CSV, keyed by editor id, so that they can be
+// edited in a spreadsheet and applied back to the plugin.
+// Each row is `kind,list,member`: for keywords the list is the keyword and the member is the
+// record that has it, for form lists the list is the FLST and the member is an entry of it.
+
+use crate::{
+    load_order::LoadOrder,
+    records::{
+        common::{FormId, GeneralRecord},
+        RecordMut, RecordRef,
+    },
+    walk::{EntryMut, EntryRef},
+};
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    io::{self, Write},
+};
+
+const CSV_HEADER: &str = "kind,list,member";
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum MembershipKind {
+    Keyword,
+    FormList,
+}
+impl MembershipKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            MembershipKind::Keyword => "keyword",
+            MembershipKind::FormList => "formlist",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<MembershipKind> {
+        match name {
+            "keyword" => Some(MembershipKind::Keyword),
+            "formlist" => Some(MembershipKind::FormList),
+            _ => None,
+        }
+    }
+}
+
+/// A single row. Records without an editor id are written as their form id, such as `0x0001A2B3`
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Membership {
+    pub kind: MembershipKind,
+    pub list: String,
+    pub member: String,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum MembershipError {
+    /// A CSV row that could not be read, with its line number (starting at 1)
+    InvalidRow(usize),
+    /// No loaded record has this editor id
+    UnknownEditorId(String),
+    /// The record is from a plugin that is not a master of the plugin being edited
+    NotMaster(String),
+}
+
+/// What [apply_memberships] changed
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct MembershipReport {
+    pub added: usize,
+    pub removed: usize,
+    /// Rows that could not be applied, because the record is not in the plugin, wasn't
+    /// converted, or has no keywords field to add to
+    pub unapplied: Vec<Membership>,
+}
+
+/// The keyword and form list memberships of the records in the plugin at [plugin], including
+/// those within groups that weren't converted
+pub fn export_memberships(load_order: &LoadOrder<'_>, plugin: usize) -> Vec<Membership> {
+    let editor_ids = EditorIds::new(load_order);
+    let name = |id| editor_ids.name_of(load_order, plugin, id);
+    let mut rows = Vec::new();
+    for (record, _) in load_order.plugins[plugin].iter_all_records() {
+        let record = match record {
+            EntryRef::Record(record) => record,
+            EntryRef::Compressed(_) => continue,
+        };
+        let (kind, values) = match record {
+            RecordRef::FLST(flst) => (MembershipKind::FormList, flst.entries().collect()),
+            RecordRef::Unknown(general) | RecordRef::Deleted(general) => unconverted(general),
+            record => match record.as_keyworded() {
+                Some(record) if !record.keyword_collections().is_empty() => {
+                    (MembershipKind::Keyword, record.keywords())
+                }
+                _ => continue,
+            },
+        };
+        let own = name(record.formid());
+        rows.extend(values.into_iter().map(|value| {
+            let (list, member) = match kind {
+                MembershipKind::Keyword => (name(value), own.clone()),
+                MembershipKind::FormList => (own.clone(), name(value)),
+            };
+            Membership { kind, list, member }
+        }));
+    }
+    rows
+}
+
+/// The memberships of a record that wasn't converted, read from its LNAM fields if it is a form
+/// list and its KWDA field otherwise
+fn unconverted(record: &GeneralRecord<'_>) -> (MembershipKind, Vec<FormId>) {
+    let ids = |data: &[u8]| {
+        data.chunks_exact(4)
+            .map(|x| FormId::new(u32::from_le_bytes(<[u8; 4]>::try_from(x).unwrap())))
+            .collect::<Vec<_>>()
+    };
+    let (kind, field) = if record.type_name == "FLST" {
+        (MembershipKind::FormList, "LNAM")
+    } else {
+        (MembershipKind::Keyword, "KWDA")
+    };
+    let values = record
+        .fields
+        .iter()
+        .filter(|x| x.type_name == field)
+        .flat_map(|x| ids(x.data))
+        .collect();
+    (kind, values)
+}
+
+pub fn write_csv<W: Write>(rows: &[Membership], w: &mut W) -> io::Result<()> {
+    writeln!(w, "{}", CSV_HEADER)?;
+    for row in rows {
+        writeln!(w, "{},{},{}", row.kind.name(), row.list, row.member)?;
+    }
+    Ok(())
+}
+
+/// Reads rows written by [write_csv]. The header and empty lines are skipped
+pub fn read_csv(text: &str) -> Result<Vec<Membership>, MembershipError> {
+    let mut rows = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line == CSV_HEADER {
+            continue;
+        }
+        let columns: Vec<&str> = line.split(',').map(str::trim).collect();
+        match columns.as_slice() {
+            [kind, list, member] if !list.is_empty() && !member.is_empty() => {
+                rows.push(Membership {
+                    kind: MembershipKind::from_name(kind)
+                        .ok_or(MembershipError::InvalidRow(index + 1))?,
+                    list: list.to_string(),
+                    member: member.to_string(),
+                })
+            }
+            _ => return Err(MembershipError::InvalidRow(index + 1)),
+        }
+    }
+    Ok(rows)
+}
+
+/// Makes the keywords and form lists of the records in the plugin at [plugin] match [rows].
+/// Records of the plugin that are not mentioned lose all their keywords and entries, so [rows]
+/// should be a full (edited) export. Nothing is changed if a name can't be resolved.
+pub fn apply_memberships(
+    load_order: &mut LoadOrder<'_>,
+    plugin: usize,
+    rows: &[Membership],
+) -> Result<MembershipReport, MembershipError> {
+    // Wanted members of each record, by kind
+    let mut wanted: HashMap<(MembershipKind, FormId), Vec<(FormId, &Membership)>> = HashMap::new();
+    {
+        let editor_ids = EditorIds::new(load_order);
+        let resolve = |name: &str| resolve_name(load_order, plugin, &editor_ids, name);
+        for row in rows {
+            let list = resolve(&row.list)?;
+            let member = resolve(&row.member)?;
+            let (record, value) = match row.kind {
+                MembershipKind::Keyword => (member, list),
+                MembershipKind::FormList => (list, member),
+            };
+            wanted
+                .entry((row.kind, record))
+                .or_default()
+                .push((value, row));
+        }
+    }
+
+    let mut report = MembershipReport::default();
+    for (record, _) in load_order.plugins[plugin].iter_all_records_mut() {
+        let record = match record {
+            EntryMut::Record(record) => record,
+            EntryMut::Compressed(_) => continue,
+        };
+        let id = record.formid();
+        match record {
+            // Can't be changed, so rows that would change them are left unapplied
+            RecordMut::Unknown(general) | RecordMut::Deleted(general) => {
+                let (kind, current) = unconverted(general);
+                let unchanged = wanted.get(&(kind, id)).map_or(false, |values| {
+                    values.iter().all(|(x, _)| current.contains(x))
+                        && current.iter().all(|x| values.iter().any(|(y, _)| x == y))
+                });
+                if unchanged {
+                    wanted.remove(&(kind, id));
+                }
+            }
+            RecordMut::FLST(flst) => {
+                let entries = wanted
+                    .remove(&(MembershipKind::FormList, id))
+                    .unwrap_or_default();
+                let current: Vec<FormId> = flst.entries().collect();
+                for entry in current {
+                    if !entries.iter().any(|(x, _)| *x == entry) && flst.remove_entry(entry) {
+                        report.removed += 1;
+                    }
+                }
+                for (entry, _) in entries {
+                    if flst.add_entry(entry) {
+                        report.added += 1;
+                    }
+                }
+            }
+            record => {
+                let record = match record.into_keyworded() {
+                    Some(record) if !record.keyword_collections().is_empty() => record,
+                    _ => continue,
+                };
+                let wanted_keywords = wanted
+                    .remove(&(MembershipKind::Keyword, id))
+                    .unwrap_or_default();
+                for keyword in record.keywords() {
+                    if !wanted_keywords.iter().any(|(x, _)| *x == keyword)
+                        && record.remove_keyword(keyword)
+                    {
+                        report.removed += 1;
+                    }
+                }
+                for (keyword, _) in wanted_keywords {
+                    if record.add_keyword(keyword) {
+                        report.added += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    report.unapplied = wanted
+        .into_values()
+        .flat_map(|values| values.into_iter().map(|(_, row)| row.clone()))
+        .collect();
+    // Keep the order of the input, rather than that of the map
+    report
+        .unapplied
+        .sort_by_key(|row| rows.iter().position(|x| x == row));
+    Ok(report)
+}
+
+/// The editor ids of every record of a load order, by the plugin that defines the record and its
+/// object id, and the other way around
+struct EditorIds {
+    /// The record that each editor id (lowercased) belongs to. Later plugins win when the same
+    /// editor id is used for different records
+    records: HashMap<String, (usize, u32)>,
+    /// The editor id of each record, as the last plugin with it has it
+    names: HashMap<(usize, u32), String>,
+}
+impl EditorIds {
+    fn new(load_order: &LoadOrder<'_>) -> EditorIds {
+        let mut editor_ids = EditorIds {
+            records: HashMap::new(),
+            names: HashMap::new(),
+        };
+        for (position, plugin) in load_order.plugins.iter().enumerate() {
+            for (record, _) in plugin.iter_all_records() {
+                let record = match record {
+                    EntryRef::Record(record) => record,
+                    EntryRef::Compressed(_) => continue,
+                };
+                let editor_id = match record.editor_id() {
+                    Some(editor_id) => editor_id,
+                    None => continue,
+                };
+                if let Some(origin) = load_order.origin(position, record.formid()) {
+                    editor_ids.records.insert(editor_id.to_lowercase(), origin);
+                    editor_ids.names.insert(origin, editor_id);
+                }
+            }
+        }
+        editor_ids
+    }
+
+    /// Editor id of the record that [id] (as written in the plugin at [plugin]) refers to, or
+    /// the form id if it has none
+    fn name_of(&self, load_order: &LoadOrder<'_>, plugin: usize, id: FormId) -> String {
+        load_order
+            .origin(plugin, id)
+            .and_then(|origin| self.names.get(&origin))
+            .cloned()
+            .unwrap_or_else(|| format!("0x{}", id))
+    }
+}
+
+fn resolve_name(
+    load_order: &LoadOrder<'_>,
+    plugin: usize,
+    editor_ids: &EditorIds,
+    name: &str,
+) -> Result<FormId, MembershipError> {
+    if let Some(&(origin, object)) = editor_ids.records.get(&name.to_lowercase()) {
+        return load_order
+            .local_id(plugin, origin, object)
+            .ok_or_else(|| MembershipError::NotMaster(name.to_string()));
+    }
+    Some(name)
+        .filter(|x| x.starts_with("0x"))
+        .and_then(|x| x.parse().ok())
+        .ok_or_else(|| MembershipError::UnknownEditorId(name.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        context::ParseContext,
+        groups::{common::TopGroup, Group},
+        plugin::Plugin,
+        records::{common::CommonRecordInfo, fields::common::GeneralField, Record},
+        Top,
+    };
+    use bstr::ByteSlice;
+
+    fn general<'a>(
+        type_name: &'a [u8],
+        id: u32,
+        fields: Vec<GeneralField<'a>>,
+    ) -> GeneralRecord<'a> {
+        let mut common = CommonRecordInfo::test_default();
+        common.flags.flags = 0;
+        common.id = id;
+        GeneralRecord {
+            type_name: type_name.as_bstr(),
+            common,
+            fields,
+        }
+    }
+
+    fn record<'a>(type_name: &'a [u8], id: u32, fields: Vec<GeneralField<'a>>) -> Top<'a> {
+        let record = general(type_name, id, fields);
+        Top::Record(Record::from_general(record, &mut ParseContext::default()).unwrap())
+    }
+
+    /// A group that isn't converted
+    fn group<'a>(type_name: &'a [u8], records: Vec<GeneralRecord<'a>>) -> Top<'a> {
+        let records = records.into_iter().map(Record::Unknown).collect();
+        let group = TopGroup::from_records(type_name.as_bstr(), records).unwrap();
+        Top::Group(Group::UnknownTop(group))
+    }
+
+    #[test]
+    fn test_memberships() {
+        let obnd = [0; 12];
+        let mut load_order = LoadOrder::new(vec![Plugin::from_tops(
+            "Test.esp".to_string(),
+            vec![
+                group(
+                    b"KYWD",
+                    vec![
+                        general(
+                            b"KYWD",
+                            0x10,
+                            vec![GeneralField::new(b"EDID".as_bstr(), b"KwA\0")],
+                        ),
+                        general(
+                            b"KYWD",
+                            0x11,
+                            vec![GeneralField::new(b"EDID".as_bstr(), b"KwB\0")],
+                        ),
+                    ],
+                ),
+                record(
+                    b"ACTI",
+                    0x20,
+                    vec![
+                        GeneralField::new(b"EDID".as_bstr(), b"Lever\0"),
+                        GeneralField::new(b"OBND".as_bstr(), &obnd),
+                        GeneralField::new(b"KSIZ".as_bstr(), &[2, 0, 0, 0]),
+                        GeneralField::new(b"KWDA".as_bstr(), &[0x10, 0, 0, 0, 0x12, 0, 0, 0]),
+                    ],
+                ),
+                record(
+                    b"ACTI",
+                    0x21,
+                    vec![
+                        GeneralField::new(b"EDID".as_bstr(), b"Chain\0"),
+                        GeneralField::new(b"OBND".as_bstr(), &obnd),
+                    ],
+                ),
+                record(
+                    b"FLST",
+                    0x30,
+                    vec![
+                        GeneralField::new(b"EDID".as_bstr(), b"Pullables\0"),
+                        GeneralField::new(b"LNAM".as_bstr(), &[0x20, 0, 0, 0]),
+                    ],
+                ),
+                group(
+                    b"WEAP",
+                    vec![general(
+                        b"WEAP",
+                        0x40,
+                        vec![
+                            GeneralField::new(b"EDID".as_bstr(), b"Axe\0"),
+                            GeneralField::new(b"KWDA".as_bstr(), &[0x11, 0, 0, 0]),
+                        ],
+                    )],
+                ),
+            ],
+        )]);
+
+        let rows = export_memberships(&load_order, 0);
+        let mut csv = Vec::new();
+        write_csv(&rows, &mut csv).unwrap();
+        assert_eq!(
+            csv.to_str().unwrap(),
+            "kind,list,member\n\
+             keyword,KwA,Lever\n\
+             keyword,0x00000012,Lever\n\
+             formlist,Pullables,Lever\n\
+             keyword,KwB,Axe\n"
+        );
+        assert_eq!(read_csv(csv.to_str().unwrap()).unwrap(), rows);
+        assert_eq!(read_csv("keyword,KwA"), Err(MembershipError::InvalidRow(1)));
+
+        // Applying an unedited export changes nothing
+        let report = apply_memberships(&mut load_order, 0, &rows).unwrap();
+        assert_eq!(report, MembershipReport::default());
+
+        let edited = read_csv(
+            "kind,list,member\n\
+             keyword,kwb,Lever\n\
+             keyword,KwA,Chain\n\
+             formlist,Pullables,Lever\n\
+             formlist,Pullables,Chain\n\
+             keyword,KwB,Axe\n\
+             keyword,KwA,Axe\n",
+        )
+        .unwrap();
+        let report = apply_memberships(&mut load_order, 0, &edited).unwrap();
+        assert_eq!(report.added, 2);
+        assert_eq!(report.removed, 2);
+        // Chain has no keywords field to add to, and Axe wasn't converted
+        assert_eq!(
+            report.unapplied,
+            vec![edited[1].clone(), edited[4].clone(), edited[5].clone()]
+        );
+        assert_eq!(export_memberships(&load_order, 0), {
+            let mut expected = edited.clone();
+            expected.truncate(5);
+            expected.remove(1);
+            // Written as the editor id is cased, rather than as it was in the CSV
+            expected[0].list = "KwB".to_string();
+            expected
+        });
+
+        assert_eq!(
+            apply_memberships(
+                &mut load_order,
+                0,
+                &[Membership {
+                    kind: MembershipKind::Keyword,
+                    list: "Missing".to_string(),
+                    member: "Lever".to_string(),
+                }]
+            ),
+            Err(MembershipError::UnknownEditorId("Missing".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_keyword_runs() {
+        let keyword = |id, name| {
+            general(
+                b"KYWD",
+                id,
+                vec![GeneralField::new(b"EDID".as_bstr(), name)],
+            )
+        };
+        // Two keywords fields, which aren't next to each other
+        let mut load_order = LoadOrder::new(vec![Plugin::from_tops(
+            "Test.esp".to_string(),
+            vec![
+                group(
+                    b"KYWD",
+                    vec![keyword(0x10, b"KwA\0"), keyword(0x11, b"KwB\0")],
+                ),
+                record(
+                    b"ACTI",
+                    0x20,
+                    vec![
+                        GeneralField::new(b"EDID".as_bstr(), b"Lever\0"),
+                        GeneralField::new(b"OBND".as_bstr(), &[0; 12]),
+                        GeneralField::new(b"KSIZ".as_bstr(), &[1, 0, 0, 0]),
+                        GeneralField::new(b"KWDA".as_bstr(), &[0x10, 0, 0, 0]),
+                        GeneralField::new(b"PNAM".as_bstr(), &[0; 4]),
+                        GeneralField::new(b"KSIZ".as_bstr(), &[1, 0, 0, 0]),
+                        GeneralField::new(b"KWDA".as_bstr(), &[0x11, 0, 0, 0]),
+                    ],
+                ),
+            ],
+        )]);
+        let row = |list: &str| Membership {
+            kind: MembershipKind::Keyword,
+            list: list.to_string(),
+            member: "Lever".to_string(),
+        };
+
+        assert_eq!(
+            export_memberships(&load_order, 0),
+            vec![row("KwA"), row("KwB")]
+        );
+        let report = apply_memberships(&mut load_order, 0, &[row("KwB")]).unwrap();
+        assert_eq!((report.added, report.removed), (0, 1));
+        assert_eq!(export_memberships(&load_order, 0), vec![row("KwB")]);
+        let report = apply_memberships(&mut load_order, 0, &[row("KwA"), row("KwB")]).unwrap();
+        assert_eq!((report.added, report.removed), (1, 0));
+        assert_eq!(
+            export_memberships(&load_order, 0),
+            vec![row("KwB"), row("KwA")]
+        );
+    }
+}
@@ -1,3 +1,6 @@
+use crate::error::{ErrorCode, VivecError};
+use bstr::ByteSlice;
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum ParseError<'data> {
     /// Expected specific bytes
@@ -13,6 +16,123 @@ pub enum ParseError<'data> {
     ExpectedExact { expected: usize, found: usize },
     /// More general version of above, for when the amount of bytes was invalid
     InvalidByteCount { found: usize },
+    /// A group's size (which includes its header) is too small to hold its header, or larger than
+    /// the data that is [available] from the start of the group
+    GroupSize {
+        label: [u8; 4],
+        group_type: i32,
+        expected: usize,
+        available: usize,
+    },
+    /// A count of [amount] elements, of at least [min_size] bytes each, can't fit in the
+    /// [available] bytes
+    CountTooLarge {
+        amount: usize,
+        min_size: usize,
+        available: usize,
+    },
+    /// A group was found where only records are allowed
+    UnexpectedGroup,
+    /// A compressed record was found where its fields have to be read
+    UnexpectedCompressed,
+    /// The data of a compressed record isn't a valid zlib stream of the size that it gives
+    Decompress(String),
+    /// Text has [byte] at [position], which its encoding leaves undefined
+    Undecodable { byte: u8, position: usize },
+    /// The element at [index] of a list, which started [offset] bytes into the list's data,
+    /// failed to parse
+    Element {
+        index: usize,
+        offset: usize,
+        error: Box<ParseError<'data>>,
+    },
+}
+
+impl std::fmt::Display for ParseError<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::ExpectedBytes(bytes) => {
+                write!(f, "Expected the bytes {:?}", bytes.as_bstr())
+            }
+            ParseError::UnexpectedEOF => write!(f, "Unexpected end of data"),
+            ParseError::ExpectedEOF => write!(f, "Expected the data to end"),
+            ParseError::InvalidEnumerationValue => write!(f, "Invalid enumeration value"),
+            ParseError::ExpectedExact { expected, found } => {
+                write!(f, "Expected {} bytes, found {}", expected, found)
+            }
+            ParseError::InvalidByteCount { found } => {
+                write!(f, "Invalid amount of bytes: {}", found)
+            }
+            ParseError::GroupSize {
+                label,
+                group_type,
+                expected,
+                available,
+            } => write!(
+                f,
+                "Group {:?} of type {} has size {}, but {} bytes are available",
+                label.as_bstr(),
+                group_type,
+                expected,
+                available
+            ),
+            ParseError::CountTooLarge {
+                amount,
+                min_size,
+                available,
+            } => write!(
+                f,
+                "{} elements of at least {} bytes can't fit in {} bytes",
+                amount, min_size, available
+            ),
+            ParseError::UnexpectedGroup => {
+                write!(f, "Found a group where only records are allowed")
+            }
+            ParseError::UnexpectedCompressed => {
+                write!(f, "Found a compressed record, whose fields can't be read")
+            }
+            ParseError::Decompress(message) => write!(f, "Failed to decompress: {}", message),
+            ParseError::Undecodable { byte, position } => write!(
+                f,
+                "The byte {:#04X} at {} has no character in the text's encoding",
+                byte, position
+            ),
+            ParseError::Element { index, offset, .. } => {
+                write!(
+                    f,
+                    "Element {}, at offset {}, failed to parse",
+                    index, offset
+                )
+            }
+        }
+    }
+}
+impl std::error::Error for ParseError<'_> {}
+impl VivecError for ParseError<'_> {
+    fn code(&self) -> ErrorCode {
+        match self {
+            ParseError::ExpectedBytes(_) => ErrorCode::ExpectedBytes,
+            ParseError::UnexpectedEOF => ErrorCode::UnexpectedEof,
+            ParseError::ExpectedEOF => ErrorCode::ExpectedEof,
+            ParseError::InvalidEnumerationValue => ErrorCode::InvalidEnumerationValue,
+            ParseError::ExpectedExact { .. } => ErrorCode::ExpectedExact,
+            ParseError::InvalidByteCount { .. } => ErrorCode::InvalidByteCount,
+            ParseError::GroupSize { .. } => ErrorCode::GroupSize,
+            ParseError::CountTooLarge { .. } => ErrorCode::CountTooLarge,
+            ParseError::UnexpectedGroup => ErrorCode::UnexpectedGroup,
+            ParseError::UnexpectedCompressed => ErrorCode::UnexpectedCompressed,
+            ParseError::Decompress(_) => ErrorCode::Decompress,
+            ParseError::Undecodable { .. } => ErrorCode::Undecodable,
+            ParseError::Element { error, .. } => error.code(),
+        }
+    }
+
+    fn inner(&self) -> Option<&dyn VivecError> {
+        match self {
+            ParseError::Element { error, .. } => Some(error.as_ref()),
+            _ => None,
+        }
+    }
 }
 
 pub type PResult<'data, V, E = ParseError<'data>> = Result<(&'data [u8], V), E>;
@@ -26,7 +146,7 @@ pub fn single(data: &[u8]) -> PResult<u8> {
 }
 
 /// Returns slice with exactly [amount] entries
-pub fn take<'data>(data: &'data [u8], amount: usize) -> PResult<&'data [u8]> {
+pub fn take(data: &[u8], amount: usize) -> PResult<'_, &[u8]> {
     if data.len() < amount {
         Err(ParseError::UnexpectedEOF)
     } else {
@@ -34,6 +154,54 @@ pub fn take<'data>(data: &'data [u8], amount: usize) -> PResult<&'data [u8]> {
     }
 }
 
+/// Takes exactly [N] bytes as an array
+pub fn take_array<const N: usize>(data: &[u8]) -> PResult<'_, [u8; N]> {
+    let (data, bytes) = take(data, N)?;
+    let mut array = [0; N];
+    array.copy_from_slice(bytes);
+    Ok((data, array))
+}
+
+/// Integer types that are used as the count or length before a list or string
+pub trait CountPrefix<'data>: Parse<'data> {
+    fn to_count(self) -> usize;
+}
+impl CountPrefix<'_> for u8 {
+    fn to_count(self) -> usize {
+        self as usize
+    }
+}
+impl CountPrefix<'_> for u16 {
+    fn to_count(self) -> usize {
+        self as usize
+    }
+}
+impl CountPrefix<'_> for u32 {
+    fn to_count(self) -> usize {
+        self as usize
+    }
+}
+
+/// Takes bytes whose length is stored before them as a [C], such as a u16 length string
+pub fn take_counted<'data, C>(data: &'data [u8]) -> PResult<'data, &'data [u8]>
+where
+    C: CountPrefix<'data>,
+{
+    let (data, length) = C::parse(data)?;
+    take(data, length.to_count())
+}
+
+/// Parses a list of [T] whose amount is stored before it as a [C].
+/// Each element is assumed to be at least a byte, see [count_bounded]
+pub fn parse_counted<'data, C, T>(data: &'data [u8]) -> PResult<'data, Vec<T>>
+where
+    C: CountPrefix<'data>,
+    T: Parse<'data>,
+{
+    let (data, amount) = C::parse(data)?;
+    count_bounded(data, T::parse, amount.to_count(), 1)
+}
+
 pub fn le_u16(data: &[u8]) -> PResult<u16> {
     let (data, v) = take(data, 2)?;
     Ok((data, u16::from_le_bytes([v[0], v[1]])))
@@ -137,6 +305,98 @@ where
     Ok((data, result))
 }
 
+/// [count], but fails before parsing anything if [amount] elements of at least [min_size] bytes
+/// each could not fit in [data]. Avoids looping (and allocating) over huge corrupt counts.
+pub fn count_bounded<'data, F, R, V>(
+    data: &'data [u8],
+    func: F,
+    amount: usize,
+    min_size: usize,
+) -> Result<(&'data [u8], Vec<V>), R>
+where
+    R: From<ParseError<'data>>,
+    F: Fn(&'data [u8]) -> Result<(&'data [u8], V), R>,
+{
+    match amount.checked_mul(min_size) {
+        Some(size) if size <= data.len() => count(data, func, amount),
+        _ => Err(ParseError::CountTooLarge {
+            amount,
+            min_size,
+            available: data.len(),
+        }
+        .into()),
+    }
+}
+
+/// Parses elements until [until] returns true for the remaining data, which is not consumed.
+/// Fails if the data runs out first.
+pub fn many_till<'data, F, U, R, V>(
+    mut data: &'data [u8],
+    func: F,
+    until: U,
+) -> Result<(&'data [u8], Vec<V>), R>
+where
+    R: From<ParseError<'data>>,
+    F: Fn(&'data [u8]) -> Result<(&'data [u8], V), R>,
+    U: Fn(&'data [u8]) -> bool,
+{
+    let mut result = Vec::new();
+    while !until(data) {
+        if data.is_empty() {
+            return Err(ParseError::UnexpectedEOF.into());
+        }
+        let (data_val, value) = func(data)?;
+        data = data_val;
+        result.push(value);
+    }
+    Ok((data, result))
+}
+
+/// Wraps the error of the element at [index], which starts [offset] bytes into the list
+fn element_error(index: usize, offset: usize, error: ParseError<'_>) -> ParseError<'_> {
+    ParseError::Element {
+        index,
+        offset,
+        error: Box::new(error),
+    }
+}
+
+/// [count], with errors wrapped in [ParseError::Element]
+pub fn count_indexed<'data, F, V>(
+    input: &'data [u8],
+    func: F,
+    amount: usize,
+) -> PResult<'data, Vec<V>>
+where
+    F: Fn(&'data [u8]) -> PResult<'data, V>,
+{
+    let mut data = input;
+    let mut result = Vec::new();
+    for index in 0..amount {
+        let offset = input.len() - data.len();
+        let (data_val, value) = func(data).map_err(|e| element_error(index, offset, e))?;
+        data = data_val;
+        result.push(value);
+    }
+    Ok((data, result))
+}
+
+/// [many], with errors wrapped in [ParseError::Element]
+pub fn many_indexed<'data, F, V>(input: &'data [u8], func: F) -> PResult<'data, Vec<V>>
+where
+    F: Fn(&'data [u8]) -> PResult<'data, V>,
+{
+    let mut data = input;
+    let mut result = Vec::new();
+    while !data.is_empty() {
+        let offset = input.len() - data.len();
+        let (data_val, value) = func(data).map_err(|e| element_error(result.len(), offset, e))?;
+        data = data_val;
+        result.push(value);
+    }
+    Ok((data, result))
+}
+
 pub trait Parse<'aleph>: Sized {
     fn parse(data: &'aleph [u8]) -> PResult<'aleph, Self>;
 }
@@ -248,4 +508,85 @@ mod tests {
         assert_eq!(bytes[3], &[0xa, 0xb, 0xc]);
         assert_eq!(bytes[4], &[0xd, 0xe, 0xf]);
     }
+
+    #[test]
+    fn test_count_bounded() {
+        let (res, bytes) = count_bounded(DATA, |x| take(x, 2), 3, 2).unwrap();
+        assert_eq!(bytes.len(), 3);
+        assert_eq!(res.len(), 9);
+        let err: Result<(&[u8], Vec<&[u8]>), ParseError> =
+            count_bounded(DATA, |x| take(x, 2), usize::MAX, 2);
+        assert_eq!(
+            err,
+            Err(ParseError::CountTooLarge {
+                amount: usize::MAX,
+                min_size: 2,
+                available: 15,
+            })
+        );
+    }
+
+    #[test]
+    fn test_many_till() {
+        let (res, bytes) = many_till(DATA, single, |x| x.first() == Some(&0x4)).unwrap();
+        assert_eq!(bytes, vec![0x1, 0x2, 0x3]);
+        assert_eq!(res.len(), 12);
+        assert_eq!(
+            many_till(DATA, single, |_| false),
+            Err(ParseError::UnexpectedEOF)
+        );
+    }
+
+    #[test]
+    fn test_indexed() {
+        let (res, values) = count_indexed(DATA, le_u32, 3).unwrap();
+        assert_eq!(values[1], 0x0807_0605);
+        assert_eq!(res.len(), 3);
+        assert_eq!(
+            count_indexed(DATA, le_u32, 4),
+            Err(ParseError::Element {
+                index: 3,
+                offset: 12,
+                error: Box::new(ParseError::UnexpectedEOF),
+            })
+        );
+        assert_eq!(
+            many_indexed(DATA, le_u16),
+            Err(ParseError::Element {
+                index: 7,
+                offset: 14,
+                error: Box::new(ParseError::UnexpectedEOF),
+            })
+        );
+        assert_eq!(many_indexed(&DATA[..4], le_u16).unwrap().1.len(), 2);
+    }
+
+    #[test]
+    fn test_take_array() {
+        let (res, bytes) = take_array::<3>(DATA).unwrap();
+        assert_eq!(bytes, [0x1, 0x2, 0x3]);
+        assert_eq!(res.len(), 12);
+        assert_eq!(take_array::<16>(DATA), Err(ParseError::UnexpectedEOF));
+    }
+
+    #[test]
+    fn test_counted() {
+        let data: &[u8] = &[2, 0, b'h', b'i', 3];
+        let (res, bytes) = take_counted::<u16>(data).unwrap();
+        assert_eq!(bytes, b"hi");
+        assert_eq!(res, &[3]);
+
+        let data: &[u8] = &[2, 0x1, 0x0, 0x2, 0x0, 0x9];
+        let (res, values) = parse_counted::<u8, u16>(data).unwrap();
+        assert_eq!(values, vec![1, 2]);
+        assert_eq!(res, &[0x9]);
+        assert_eq!(
+            parse_counted::<u32, u8>(&[0xff, 0xff, 0xff, 0xff, 0x1]),
+            Err(ParseError::CountTooLarge {
+                amount: 0xffff_ffff,
+                min_size: 1,
+                available: 1,
+            })
+        );
+    }
 }
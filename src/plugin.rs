@@ -0,0 +1,1623 @@
+use crate::{
+    context::{FormVersion, ParseContext, ParseOptions, Severity, WriteOptions},
+    edit::MasterEditPolicy,
+    groups::{
+        common::{
+            find_group_path, find_record_path, group_at, group_at_mut, walk_group_entries,
+            write_group_size, CommonGroupInfo, GeneralGroup, GroupEntry, GroupType, TopGroup,
+            GROUPH_SIZE,
+        },
+        Group,
+    },
+    index::IndexCache,
+    injected::InjectedPolicy,
+    parse::Parse,
+    parse_file_with, parse_file_with_progress,
+    progress::Progress,
+    records::{
+        canonical,
+        common::{
+            lstring::lstring_kind, record_flag, FormId, GeneralRecord, GlobalFormId, Recompression,
+            TextEncoding, FIRST_OBJECT_INDEX, LAST_LIGHT_OBJECT_INDEX,
+        },
+        fields::common::GeneralField,
+        tes4::TES4Record,
+        Record, RecordMut, RecordRef,
+    },
+    search::{StringLookup, TextHit, TextIndex, TextSource},
+    snapshot::Revisions,
+    util::{DataSize, Writable, WriteError},
+    verify::{verify_record, WriteMismatch},
+    walk::{is_deleted, EntryRef},
+    GeneralError, Top,
+};
+use bstr::{BStr, BString, ByteSlice};
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashSet},
+    convert::TryFrom,
+    fs::File,
+    io::{BufWriter, Write},
+    ops::RangeInclusive,
+    path::Path,
+};
+
+/// A parsed plugin file, along with the name that other plugins refer to it by
+#[derive(Debug, Clone, PartialEq)]
+pub struct Plugin<'data> {
+    /// Filename, such as `Skyrim.esm`
+    pub name: String,
+    /// Top-level records and groups, in the order they are written. Changes to them go through
+    /// [Plugin::tops_mut] and the like, so that [revisions] can follow them
+    tops: Vec<Top<'data>>,
+    /// How the text of its string fields is encoded, from [ParseOptions::encoding] when parsed
+    pub encoding: TextEncoding,
+    /// Index used by [Plugin::find], see [IndexCache]
+    index: IndexCache,
+    /// Revision of each of [tops], so that what changed can be told, see [Revisions]
+    pub(crate) revisions: Revisions,
+    /// Whether records that belong to a master can be edited in place, see [Plugin::edit]
+    pub(crate) edit_policy: MasterEditPolicy,
+    /// Whether [Plugin::remap_formids] renumbers the records of [injected]
+    pub(crate) injected_policy: InjectedPolicy,
+    /// Ids of the records known to be injected into a master, see [Plugin::set_injected]
+    pub(crate) injected: HashSet<FormId>,
+}
+impl<'data> Plugin<'data> {
+    /// A plugin with nothing but a new TES4 record, see [TES4Record::new].
+    /// Call [Plugin::refresh_header] after adding records, before writing it
+    pub fn new<S: Into<String>>(name: S) -> Plugin<'data> {
+        Plugin::from_tops(name, vec![Top::Record(Record::TES4(TES4Record::new()))])
+    }
+
+    /// A plugin holding [tops] as they are
+    pub fn from_tops<S: Into<String>>(name: S, tops: Vec<Top<'data>>) -> Plugin<'data> {
+        Plugin {
+            name: name.into(),
+            tops,
+            encoding: TextEncoding::default(),
+            index: IndexCache::default(),
+            revisions: Revisions::default(),
+            edit_policy: MasterEditPolicy::default(),
+            injected_policy: InjectedPolicy::default(),
+            injected: HashSet::new(),
+        }
+    }
+
+    /// The top-level records and groups
+    pub fn tops(&self) -> &[Top<'data>] {
+        &self.tops
+    }
+
+    /// Mutable version of [Plugin::tops]. As any of them could be changed through this, they are
+    /// all taken to have changed, see [Revisions]. This doesn't follow [Plugin::edit_policy], so
+    /// is only for changes to the plugin as a whole
+    pub(crate) fn tops_mut(&mut self) -> &mut Vec<Top<'data>> {
+        self.revisions.mark_all();
+        self.index.mark_changed();
+        &mut self.tops
+    }
+
+    /// The top-level record or group at [index], to be changed
+    pub(crate) fn top_mut(&mut self, index: usize) -> Option<&mut Top<'data>> {
+        self.revisions.mark(index);
+        self.index.mark_changed();
+        self.tops.get_mut(index)
+    }
+
+    /// Adds [top] after the other top-level records and groups
+    pub fn push_top(&mut self, top: Top<'data>) {
+        self.revisions.mark(self.tops.len());
+        self.index.mark_changed();
+        self.tops.push(top);
+    }
+
+    pub fn into_tops(self) -> Vec<Top<'data>> {
+        self.tops
+    }
+
+    /// Parses the plugin at the start of [data].
+    /// Any trailing data is ignored after warning about it, see [Plugin::parse_with_trailing].
+    pub fn parse<S: Into<String>>(
+        name: S,
+        data: &'data [u8],
+        ctx: &mut ParseContext,
+    ) -> Result<Plugin<'data>, GeneralError<'data>> {
+        Plugin::parse_with_progress(name, data, ctx, &mut ())
+    }
+
+    /// [Plugin::parse], reporting to [progress] as it goes.
+    /// Returns [GeneralError::Cancelled] if [progress] asks to stop.
+    pub fn parse_with_progress<S: Into<String>>(
+        name: S,
+        data: &'data [u8],
+        ctx: &mut ParseContext,
+        progress: &mut dyn Progress,
+    ) -> Result<Plugin<'data>, GeneralError<'data>> {
+        let (_, tops) = parse_file_with_progress(data, ctx, progress)?;
+        let mut plugin = Plugin::from_tops(name.into(), tops);
+        plugin.encoding = ctx.options.encoding;
+        ctx.leave_record();
+        plugin.check_form_versions(ctx);
+        Ok(plugin)
+    }
+
+    /// [Plugin::parse], also returning the data after the plugin, if there is any.
+    /// If that data is another plugin, such as from files being concatenated, it is parsed with
+    /// the same name.
+    pub fn parse_with_trailing<S: Into<String>>(
+        name: S,
+        data: &'data [u8],
+        ctx: &mut ParseContext,
+    ) -> Result<(Plugin<'data>, Option<Trailing<'data>>), GeneralError<'data>> {
+        Plugin::parse_at(name.into(), data, 0, ctx)
+    }
+
+    fn parse_at(
+        name: String,
+        data: &'data [u8],
+        offset: usize,
+        ctx: &mut ParseContext,
+    ) -> Result<(Plugin<'data>, Option<Trailing<'data>>), GeneralError<'data>> {
+        let (rest, tops) = parse_file_with(&data[offset..], ctx)?;
+        let mut plugin = Plugin::from_tops(name.clone(), tops);
+        plugin.encoding = ctx.options.encoding;
+        ctx.leave_record();
+        plugin.check_form_versions(ctx);
+
+        let offset = data.len() - rest.len();
+        let trailing = if rest.is_empty() {
+            None
+        } else if rest.starts_with(b"TES4") {
+            let (next, next_trailing) = Plugin::parse_at(name, data, offset, ctx)?;
+            Some(Trailing::Plugin {
+                offset,
+                plugin: Box::new(next),
+                trailing: next_trailing.map(Box::new),
+            })
+        } else {
+            Some(Trailing::Data { offset, data: rest })
+        };
+        Ok((plugin, trailing))
+    }
+
+    /// The TES4 record, which should be the first entry of every plugin
+    pub fn header(&self) -> Option<&TES4Record<'data>> {
+        match self.tops.first() {
+            Some(Top::Record(Record::TES4(header))) => Some(header),
+            _ => None,
+        }
+    }
+
+    /// Mutable version of [Plugin::header]. The TES4 record is found anywhere in [Plugin::tops],
+    /// as it is always written first
+    pub fn header_mut(&mut self) -> Option<&mut TES4Record<'data>> {
+        let index = self
+            .tops
+            .iter()
+            .position(|top| matches!(top, Top::Record(Record::TES4(_))))?;
+        match self.top_mut(index) {
+            Some(Top::Record(Record::TES4(header))) => Some(header),
+            _ => None,
+        }
+    }
+
+    /// Filenames of the masters. The top byte of a form id is an index into these, with an
+    /// index equal to the amount of masters referring to this plugin itself.
+    pub fn masters(&self) -> Vec<&BStr> {
+        self.header()
+            .and_then(|header| header.masters())
+            .map(|masters| masters.filenames().collect())
+            .unwrap_or_default()
+    }
+
+    /// Every record, both top-level and within (parsed) groups
+    pub fn records(&self) -> Vec<RecordRef<'_, 'data>> {
+        let mut records = Vec::new();
+        for top in self.tops.iter() {
+            match top {
+                Top::Record(record) => records.push(record.as_record_ref()),
+                Top::Group(group) => records.extend(group.records()),
+            }
+        }
+        records
+    }
+
+    /// Finds the record with [id], which is relative to this plugin's masters, including those
+    /// within groups that weren't converted. Compressed records aren't found, as they can't be
+    /// given as a [RecordRef]. Lookups go through an index, see [IndexCache]
+    pub fn find(&self, id: FormId) -> Option<RecordRef<'_, 'data>> {
+        self.index.handle(self, id)?.get(self)
+    }
+
+    /// Mutable version of [Plugin::records], regardless of [Plugin::edit_policy]
+    pub(crate) fn records_mut(&mut self) -> Vec<RecordMut<'_, 'data>> {
+        let mut records = Vec::new();
+        for top in self.tops_mut().iter_mut() {
+            match top {
+                Top::Record(record) => records.push(record.as_record_mut()),
+                Top::Group(group) => records.extend(group.records_mut()),
+            }
+        }
+        records
+    }
+
+    /// Mutable version of [Plugin::find], regardless of [Plugin::edit_policy]. See [Plugin::edit]
+    pub(crate) fn find_mut(&mut self, id: FormId) -> Option<RecordMut<'_, 'data>> {
+        self.index.handle(self, id)?.get_mut(self)
+    }
+
+    /// The group of [group_type] within the groups that weren't converted, such as the children
+    /// of a CELL. Top groups aren't looked at, only the groups within them. The children of a
+    /// WRLD, CELL or DIAL are looked for right after their record, which is found through the
+    /// index, while other groups are searched for, giving the first
+    pub fn find_group(&self, group_type: &GroupType<'_>) -> Option<&GeneralGroup<'data>> {
+        let (top, path) = self.group_path(group_type)?;
+        self.group_in(top, &path)
+    }
+
+    /// Mutable version of [Plugin::find_group]
+    pub fn find_group_mut(
+        &mut self,
+        group_type: &GroupType<'_>,
+    ) -> Option<&mut GeneralGroup<'data>> {
+        let (top, path) = self.group_path(group_type)?;
+        match (self.top_mut(top)?, path.as_slice()) {
+            (Top::Group(Group::Unknown(group)), []) => Some(group),
+            (Top::Group(Group::Unknown(group)), path) => group_at_mut(&mut group.entries, path),
+            (Top::Group(Group::UnknownTop(group)), path) => group_at_mut(&mut group.entries, path),
+            _ => None,
+        }
+    }
+
+    /// The entries that hold the record with [id], along with its position within them
+    pub(crate) fn entries_around(&self, id: FormId) -> Option<(&[GroupEntry<'data>], usize)> {
+        let (top, mut path) = self.record_path(id)?;
+        let index = path.pop()?;
+        Some((self.entries_in(top, &path)?, index))
+    }
+
+    /// Where the record with [id] is held within the groups that weren't converted, as the
+    /// position of its top, the position of each group down to it and then its own position.
+    /// Found through the index, or searched for if the record is compressed
+    pub(crate) fn record_path(&self, id: FormId) -> Option<(usize, Vec<usize>)> {
+        if let Some(handle) = self.index.handle(self, id) {
+            return Some((handle.top, handle.entries));
+        }
+        self.tops.iter().enumerate().find_map(|(index, top)| {
+            let mut path = Vec::new();
+            let found = match top {
+                Top::Group(Group::Unknown(group)) => {
+                    find_record_path(&group.entries, id, &mut path)
+                }
+                Top::Group(Group::UnknownTop(group)) => {
+                    find_record_path(&group.entries, id, &mut path)
+                }
+                _ => false,
+            };
+            if found {
+                Some((index, path))
+            } else {
+                None
+            }
+        })
+    }
+
+    /// The entries of the group at [path] within the top at [top], or of the top itself if
+    /// [path] is empty
+    pub(crate) fn entries_in(&self, top: usize, path: &[usize]) -> Option<&[GroupEntry<'data>]> {
+        match (self.tops.get(top)?, path) {
+            (Top::Group(Group::UnknownTop(group)), []) => Some(&group.entries),
+            (_, path) => Some(&self.group_in(top, path)?.entries),
+        }
+    }
+
+    /// Mutable version of [Plugin::entries_in]
+    pub(crate) fn entries_in_mut(
+        &mut self,
+        top: usize,
+        path: &[usize],
+    ) -> Option<&mut Vec<GroupEntry<'data>>> {
+        match (self.top_mut(top)?, path) {
+            (Top::Group(Group::Unknown(group)), []) => Some(&mut group.entries),
+            (Top::Group(Group::UnknownTop(group)), []) => Some(&mut group.entries),
+            (Top::Group(Group::Unknown(group)), path) => {
+                group_at_mut(&mut group.entries, path).map(|group| &mut group.entries)
+            }
+            (Top::Group(Group::UnknownTop(group)), path) => {
+                group_at_mut(&mut group.entries, path).map(|group| &mut group.entries)
+            }
+            _ => None,
+        }
+    }
+
+    /// The group at [path] within the top at [top], see [Plugin::group_path]
+    pub(crate) fn group_in(&self, top: usize, path: &[usize]) -> Option<&GeneralGroup<'data>> {
+        match (self.tops.get(top)?, path) {
+            (Top::Group(Group::Unknown(group)), []) => Some(group),
+            (Top::Group(Group::Unknown(group)), path) => group_at(&group.entries, path),
+            (Top::Group(Group::UnknownTop(group)), path) => group_at(&group.entries, path),
+            _ => None,
+        }
+    }
+
+    /// Where the group of [group_type] is, as the position of its top and the position of the
+    /// group within each group down to it. Empty if the top is the group itself
+    fn group_path(&self, group_type: &GroupType<'_>) -> Option<(usize, Vec<usize>)> {
+        match group_type {
+            GroupType::WorldChildren(id)
+            | GroupType::CellChildren(id)
+            | GroupType::TopicChildren(id) => {
+                // Compressed records aren't indexed, so their children are searched for instead
+                if let Some(handle) = self.index.handle(self, *id) {
+                    let (top, path) = match handle.entries.split_last() {
+                        Some((last, groups)) => {
+                            let mut path = groups.to_vec();
+                            path.push(last + 1);
+                            (handle.top, path)
+                        }
+                        None => (handle.top + 1, Vec::new()),
+                    };
+                    return self
+                        .group_in(top, &path)
+                        .filter(|group| group.group_type == *group_type)
+                        .map(|_| (top, path));
+                }
+            }
+            GroupType::CellPersistentChildren(id) | GroupType::CellTemporaryChildren(id) => {
+                let children = GroupType::CellChildren(*id);
+                let (top, mut path) = self.group_path(&children)?;
+                let index = self.group_in(top, &path)?.entries.iter().position(
+                    |entry| matches!(entry, GroupEntry::Group(x) if x.group_type == *group_type),
+                )?;
+                path.push(index);
+                return Some((top, path));
+            }
+            _ => {}
+        }
+        self.tops.iter().enumerate().find_map(|(index, top)| {
+            let mut path = Vec::new();
+            let found = match top {
+                Top::Group(Group::Unknown(group)) => {
+                    group.group_type == *group_type
+                        || find_group_path(&group.entries, group_type, &mut path)
+                }
+                Top::Group(Group::UnknownTop(group)) => {
+                    find_group_path(&group.entries, group_type, &mut path)
+                }
+                _ => false,
+            };
+            if found {
+                Some((index, path))
+            } else {
+                None
+            }
+        })
+    }
+
+    /// The top group labelled [label] that wasn't converted, such as that of the CELLs
+    pub(crate) fn unknown_top(&self, label: &[u8]) -> Option<&TopGroup<'data>> {
+        self.tops.iter().find_map(|top| match top {
+            Top::Group(Group::UnknownTop(group)) if group.label == label => Some(group),
+            _ => None,
+        })
+    }
+
+    /// Mutable version of [Plugin::unknown_top]
+    pub(crate) fn unknown_top_mut(&mut self, label: &[u8]) -> Option<&mut TopGroup<'data>> {
+        let position = self
+            .tops
+            .iter()
+            .position(|top| matches!(top, Top::Group(Group::UnknownTop(x)) if x.label == label))?;
+        match self.top_mut(position)? {
+            Top::Group(Group::UnknownTop(group)) => Some(group),
+            _ => None,
+        }
+    }
+
+    /// Replaces the top group that wasn't converted with the label of [group] with it, or adds
+    /// it to the end if there isn't one
+    pub(crate) fn set_unknown_top(&mut self, group: TopGroup<'data>) {
+        let position = self.tops.iter().position(
+            |top| matches!(top, Top::Group(Group::UnknownTop(x)) if x.label == group.label),
+        );
+        let group = Top::Group(Group::UnknownTop(group));
+        match position.and_then(|position| self.top_mut(position)) {
+            Some(top) => *top = group,
+            None => self.push_top(group),
+        }
+    }
+
+    /// Amount of records with each form version
+    pub fn form_versions(&self) -> BTreeMap<FormVersion, usize> {
+        let mut versions = BTreeMap::new();
+        for (record, _) in self.iter_all_records() {
+            *versions.entry(record.common().form_version()).or_insert(0) += 1;
+        }
+        versions
+    }
+
+    /// Warns if the plugin has Special Edition records while its header is from the Legendary
+    /// Edition, which usually means that it was only partly converted back. Special Edition
+    /// plugins, including the masters, normally mix both, as only the records that were saved
+    /// again have the newer version
+    pub fn check_form_versions(&self, ctx: &mut ParseContext) {
+        let header = match self.header() {
+            Some(header) => header.common.form_version(),
+            None => return,
+        };
+        if header >= FormVersion::SpecialEdition {
+            return;
+        }
+        let newer: usize = self
+            .form_versions()
+            .range(FormVersion::SpecialEdition..)
+            .map(|(_, count)| count)
+            .sum();
+        if newer > 0 {
+            ctx.diagnose(
+                Severity::Warning,
+                format!(
+                    "{} has {} records with form version {} or newer, while its header has {}",
+                    self.name,
+                    newer,
+                    FormVersion::SpecialEdition,
+                    header
+                ),
+            );
+        }
+    }
+
+    /// Whether the plugin's strings are in separate string tables
+    pub fn is_localized(&self) -> bool {
+        self.header()
+            .map(|header| header.common.flags.is(record_flag::LOCALIZED))
+            .unwrap_or(false)
+    }
+
+    /// Every EDID and string within a record that contains [query], ignoring case.
+    /// Strings in the string tables are skipped, see [Plugin::search_text_with].
+    /// Each call reads every record, so use [Plugin::text_index] to search more than once.
+    pub fn search_text(&self, query: &str) -> Vec<TextHit> {
+        self.text_index(None).search(query)
+    }
+
+    /// [Plugin::search_text], also searching strings in the string tables through [strings]
+    pub fn search_text_with(&self, query: &str, strings: StringLookup<'_>) -> Vec<TextHit> {
+        self.text_index(Some(strings)).search(query)
+    }
+
+    /// The text of every record, to be searched with [TextIndex::search]. Lstrings are looked
+    /// up with [strings], and skipped without it
+    pub fn text_index(&self, strings: Option<StringLookup<'_>>) -> TextIndex {
+        let source = TextSource {
+            localized: self.is_localized(),
+            encoding: self.encoding,
+            strings,
+        };
+        TextIndex::new(self, source)
+    }
+
+    /// Whether this plugin is called [name], ignoring case as the game does
+    pub fn is_named(&self, name: &[u8]) -> bool {
+        self.name.as_bytes().eq_ignore_ascii_case(name)
+    }
+
+    /// The plugin that [id] belongs to, going by this plugin's masters, and its object index.
+    /// None if its mod index is past this plugin's own
+    pub fn global_formid(&self, id: FormId) -> Option<GlobalFormId> {
+        let masters = self.masters();
+        let index = usize::from(id.mod_index());
+        let plugin = match masters.get(index) {
+            Some(master) => BString::from(master.as_bytes()),
+            None if index == masters.len() => BString::from(self.name.as_str()),
+            None => return None,
+        };
+        Some(GlobalFormId::new(plugin, id.object_index()))
+    }
+
+    /// The form id that this plugin refers to [id] by. None if it belongs to a plugin that
+    /// isn't this one or one of its masters
+    pub fn local_formid(&self, id: &GlobalFormId) -> Option<FormId> {
+        let masters = self.masters();
+        let index = if self.is_named(&id.plugin) {
+            masters.len()
+        } else {
+            masters
+                .iter()
+                .position(|master| master.eq_ignore_ascii_case(&id.plugin))?
+        };
+        Some(FormId::from_parts(index as u8, id.object_index))
+    }
+
+    /// Updates the record count and next object id of the TES4 record's HEDR to match the
+    /// plugin, such as after records were added or removed. Returns false if there is no TES4
+    /// record. The next object id is never lowered, so the ids of deleted records aren't
+    /// handed out again.
+    pub fn refresh_header(&mut self) -> bool {
+        let own_index = self.masters().len();
+        let (ids, groups) = self.formids_and_groups();
+        let next_object_id = ids
+            .iter()
+            .filter(|id| usize::from(id.mod_index()) == own_index)
+            .map(|id| id.object_index() + 1)
+            .fold(FIRST_OBJECT_INDEX, u32::max);
+
+        match self.header_mut() {
+            Some(header) => {
+                let hedr = header.header_mut();
+                hedr.record_count = (ids.len() + groups) as u32;
+                hedr.next_object_id = hedr.next_object_id.max(next_object_id);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Form ids of every record apart from the TES4 record, including those in groups that
+    /// aren't converted, along with the amount of groups
+    pub(crate) fn formids_and_groups(&self) -> (Vec<FormId>, usize) {
+        let mut groups = 0;
+        let mut ids = Vec::new();
+        for top in self.tops.iter() {
+            match top {
+                Top::Record(Record::TES4(_)) => {}
+                Top::Record(record) => ids.push(record.as_record_ref().formid()),
+                Top::Group(group) => {
+                    groups += 1;
+                    let entries = match group {
+                        Group::Unknown(group) => &group.entries,
+                        Group::UnknownTop(group) => &group.entries,
+                        group => {
+                            ids.extend(group.records().iter().map(|x| x.formid()));
+                            ids.extend(group.kept().iter().filter_map(|x| match &x.entry {
+                                GroupEntry::Compressed(record) => {
+                                    Some(FormId::new(record.common.id))
+                                }
+                                _ => None,
+                            }));
+                            continue;
+                        }
+                    };
+                    walk_group_entries(entries, &mut |entry| match entry.formid() {
+                        Some(id) => ids.push(id),
+                        None => groups += 1,
+                    });
+                }
+            }
+        }
+        (ids, groups)
+    }
+
+    fn has_flag(&self, flag: u32) -> bool {
+        self.header()
+            .map_or(false, |header| header.common.flags.is(flag))
+    }
+
+    /// Sets or clears [flag] on the TES4 record, returning false if there is none
+    fn set_flag(&mut self, flag: u32, value: bool) -> bool {
+        match self.header_mut() {
+            Some(header) => {
+                let flags = &mut header.common.flags.flags;
+                if value {
+                    *flags |= flag;
+                } else {
+                    *flags &= !flag;
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Whether the TES4 record has the MASTER flag, which loads the plugin with the masters
+    pub fn is_master(&self) -> bool {
+        self.has_flag(record_flag::MASTER)
+    }
+
+    /// Whether the TES4 record has the LIGHT_MASTER flag, see [Plugin::set_light]
+    pub fn is_light(&self) -> bool {
+        self.has_flag(record_flag::LIGHT_MASTER)
+    }
+
+    /// Sets or clears the MASTER flag. Returns false if there is no TES4 record
+    pub fn set_master(&mut self, master: bool) -> bool {
+        self.set_flag(record_flag::MASTER, master)
+    }
+
+    /// Sets or clears the LIGHT_MASTER flag. Returns false if there is no TES4 record.
+    /// A light plugin shares its load order index with other light plugins, so its new records
+    /// have to be within [Plugin::light_range], and setting it fails if they aren't.
+    pub fn set_light(&mut self, light: bool) -> Result<bool, WriteError> {
+        if light {
+            let outside = self.outside_light_range();
+            if !outside.is_empty() {
+                return Err(WriteError::LightRange(outside));
+            }
+        }
+        Ok(self.set_flag(record_flag::LIGHT_MASTER, light))
+    }
+
+    /// Object ids that new records of a light plugin can have. Headers of version 1.71 and later
+    /// allow ids below 0x800
+    pub fn light_range(&self) -> RangeInclusive<u32> {
+        match self.header() {
+            Some(header) if header.header().version >= 1.71 => 0x001..=LAST_LIGHT_OBJECT_INDEX,
+            _ => FIRST_OBJECT_INDEX..=LAST_LIGHT_OBJECT_INDEX,
+        }
+    }
+
+    /// Form ids of the new records, rather than overrides, that are outside of
+    /// [Plugin::light_range]
+    pub fn outside_light_range(&self) -> Vec<FormId> {
+        let own_index = self.masters().len();
+        let range = self.light_range();
+        self.formids_and_groups()
+            .0
+            .into_iter()
+            .filter(|id| {
+                usize::from(id.mod_index()) == own_index && !range.contains(&id.object_index())
+            })
+            .collect()
+    }
+
+    /// Checks that the flags of the TES4 record agree with the plugin being written to a file
+    /// with [extension]. The game loads .esm and .esl files as masters, and .esl files as light,
+    /// whatever their flags say, so they have to have those flags for other tools to agree.
+    /// Light plugins also have to keep their new records within [Plugin::light_range]
+    pub fn check_flags(&self, extension: &str) -> Result<(), WriteError> {
+        let extension = extension.to_ascii_lowercase();
+        let needed: &[(u32, &'static str)] = match extension.as_str() {
+            "esm" => &[(record_flag::MASTER, "MASTER")],
+            "esl" => &[
+                (record_flag::MASTER, "MASTER"),
+                (record_flag::LIGHT_MASTER, "LIGHT_MASTER"),
+            ],
+            _ => &[],
+        };
+        if let Some((_, flag)) = needed.iter().find(|(flag, _)| !self.has_flag(*flag)) {
+            return Err(WriteError::ExtensionFlags { extension, flag });
+        }
+        if self.is_light() {
+            let outside = self.outside_light_range();
+            if !outside.is_empty() {
+                return Err(WriteError::LightRange(outside));
+            }
+        }
+        Ok(())
+    }
+
+    /// Sorts the records within every group into form id order, see [Group::sort_records], so
+    /// that a plugin whose records were added out of order is written as the Creation Kit would.
+    /// The groups themselves and the records at the top level keep their order
+    pub fn sort_records(&mut self) {
+        for top in self.tops_mut().iter_mut() {
+            if let Top::Group(group) = top {
+                group.sort_records();
+            }
+        }
+    }
+
+    /// Writes the plugin to the file at [path], replacing it, and waits for it to reach the disk.
+    /// It is written to a temporary file next to [path] first, which is then renamed over it, so
+    /// that a failed write leaves the file that was there as it was.
+    /// The TES4 record is written first even if it is elsewhere in [Plugin::tops].
+    /// Call [Plugin::refresh_header] first if records were added or removed, and set
+    /// [WriteOptions::sort_records] to write them in form id order.
+    /// Nothing is written if the flags don't suit the extension of [path], see
+    /// [Plugin::check_flags]. The plugin is written following [options], see
+    /// [Plugin::write_with_options].
+    pub fn write_to_file<P: AsRef<Path>>(
+        &self,
+        path: P,
+        options: &WriteOptions,
+    ) -> Result<WriteStats, WriteError> {
+        let path = path.as_ref();
+        if let Some(extension) = path.extension() {
+            self.check_flags(&extension.to_string_lossy())?;
+        }
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let temporary = path.with_file_name(format!(".{}.{}.tmp", name, std::process::id()));
+        let written = self
+            .write_to_path(&temporary, options)
+            .and_then(|written| Ok((written, std::fs::rename(&temporary, path)?)));
+        match written {
+            Ok((written, _)) => Ok(WriteStats {
+                bytes: written,
+                records: self.iter_all_records().count(),
+            }),
+            Err(error) => {
+                let _ = std::fs::remove_file(&temporary);
+                Err(error)
+            }
+        }
+    }
+
+    /// Writes the plugin to a new file at [path], returning the amount of bytes written
+    fn write_to_path(&self, path: &Path, options: &WriteOptions) -> Result<u64, WriteError> {
+        let mut w = CountingWriter {
+            inner: BufWriter::new(File::create(path)?),
+            written: 0,
+        };
+        self.write_with_options(&mut w, options)?;
+        let file = w.inner.into_inner().map_err(|x| x.into_error())?;
+        file.sync_all()?;
+        Ok(w.written)
+    }
+
+    /// [Plugin::write_to_file], first checking that every record reads back the same with
+    /// [parse_options]. If any doesn't, nothing is written and [WriteError::Unverified] is
+    /// returned. Unknown groups aren't checked, as their records are kept as they were read.
+    pub fn write_to_file_verified<P: AsRef<Path>>(
+        &self,
+        path: P,
+        parse_options: &ParseOptions,
+        write_options: &WriteOptions,
+    ) -> Result<WriteStats, WriteError> {
+        let mismatches = self.verify_writes(parse_options)?;
+        if !mismatches.is_empty() {
+            return Err(WriteError::Unverified(mismatches));
+        }
+        self.write_to_file(path, write_options)
+    }
+
+    /// [Plugin::write_to], following [options]. If [WriteOptions::encoding] isn't
+    /// [Plugin::encoding], the text of editor ids, and of lstrings when the plugin isn't
+    /// localized, is encoded anew in it. Text that doesn't read as [Plugin::encoding], or can't be
+    /// written in the new one, fails with [WriteError::Encoding]. If [WriteOptions::sort_records]
+    /// is set, a copy of the plugin sorted by [Plugin::sort_records] is written, leaving this one
+    /// as it is
+    pub fn write_with_options<T: Write>(
+        &self,
+        w: &mut T,
+        options: &WriteOptions,
+    ) -> Result<(), WriteError> {
+        if options.sort_records {
+            let mut sorted = self.clone();
+            sorted.sort_records();
+            let options = WriteOptions {
+                sort_records: false,
+                ..options.clone()
+            };
+            return sorted.write_with_options(w, &options);
+        }
+        if options.encoding == self.encoding {
+            return self.write_to(w);
+        }
+        self.write_entries_with(w, |entry| {
+            let data = write_entry_replacing(entry, options.recompression, |general| {
+                self.transcode_fields(general, options.encoding)
+            })?;
+            Ok(Cow::Owned(data))
+        })
+    }
+
+    /// The text fields of [record], which is of this plugin, encoded anew in [to], for
+    /// [write_entry_replacing]. See [Plugin::write_with_options]
+    pub(crate) fn transcode_fields(
+        &self,
+        record: &GeneralRecord<'_>,
+        to: TextEncoding,
+    ) -> Result<Vec<Option<Vec<u8>>>, WriteError> {
+        let localized = self.is_localized();
+        record
+            .fields
+            .iter()
+            .map(|field| {
+                let lstring = lstring_kind(record.type_name, field.type_name).is_some();
+                if field.type_name == "EDID" || (lstring && !localized) {
+                    self.encoding.transcode(field.data, to)
+                } else {
+                    Ok(None)
+                }
+            })
+            .collect()
+    }
+
+    /// Every record that doesn't read back the same as it was written, see [verify_record]
+    pub fn verify_writes(&self, options: &ParseOptions) -> Result<Vec<WriteMismatch>, WriteError> {
+        let mut mismatches = Vec::new();
+        for record in self.records() {
+            mismatches.extend(verify_record(record, options)?);
+        }
+        Ok(mismatches)
+    }
+
+    /// [Plugin::write_to], with the fields of each record in the order that the Creation Kit
+    /// writes them in, see [canonical::field_order]. Records within unknown groups are written as
+    /// they are
+    pub fn write_canonical_to<T: Write>(&self, w: &mut T) -> Result<(), WriteError> {
+        self.write_records_with(w, |record| {
+            let mut data = Vec::new();
+            canonical::write_canonical(record, &mut data)?;
+            Ok(Cow::Owned(data))
+        })
+    }
+
+    /// [Plugin::write_to], except that each record of a top-level entry or a typed group is
+    /// written as [record_bytes] returns. Unknown groups are written as they are
+    pub(crate) fn write_records_with<'b, T, F>(
+        &self,
+        w: &mut T,
+        mut record_bytes: F,
+    ) -> Result<(), WriteError>
+    where
+        T: Write,
+        F: FnMut(RecordRef<'_, 'data>) -> Result<Cow<'b, [u8]>, WriteError>,
+    {
+        for top in self.tops_in_write_order() {
+            write_top_with(top, w, &mut record_bytes)?;
+        }
+        Ok(())
+    }
+
+    /// [Plugin::write_to], except that every record, including those within unknown groups and
+    /// those that are compressed, is written as [entry_bytes] returns
+    pub(crate) fn write_entries_with<'b, T, F>(
+        &self,
+        w: &mut T,
+        mut entry_bytes: F,
+    ) -> Result<(), WriteError>
+    where
+        T: Write,
+        F: FnMut(EntryRef<'_, 'data>) -> Result<Cow<'b, [u8]>, WriteError>,
+    {
+        for top in self.tops_in_write_order() {
+            match top {
+                Top::Record(record) => {
+                    w.write_all(&entry_bytes(EntryRef::Record(record.as_record_ref()))?)?
+                }
+                Top::Group(Group::Unknown(group)) => write_group_with(
+                    &group.group_type,
+                    &group.common,
+                    w,
+                    &mut |w: &mut Vec<u8>| {
+                        group
+                            .entries
+                            .iter()
+                            .try_for_each(|entry| write_entry_with(entry, w, &mut entry_bytes))
+                    },
+                )?,
+                Top::Group(Group::UnknownTop(group)) => write_group_with(
+                    &GroupType::Top(group.label),
+                    &group.common,
+                    w,
+                    &mut |w: &mut Vec<u8>| {
+                        group
+                            .entries
+                            .iter()
+                            .try_for_each(|entry| write_entry_with(entry, w, &mut entry_bytes))
+                    },
+                )?,
+                Top::Group(group) => {
+                    let kept = group.kept();
+                    let records = group.records();
+                    let kept_records = kept
+                        .iter()
+                        .filter(|x| matches!(x.entry, GroupEntry::Record(_)))
+                        .count();
+                    let converted = &records[..records.len() - kept_records];
+                    write_group_with(
+                        &group.group_type(),
+                        group.common(),
+                        w,
+                        &mut |w: &mut Vec<u8>| {
+                            let mut kept = kept.iter().peekable();
+                            for (index, record) in converted.iter().enumerate() {
+                                while let Some(entry) = kept.next_if(|x| x.before <= index) {
+                                    write_entry_with(&entry.entry, w, &mut entry_bytes)?;
+                                }
+                                w.write_all(&entry_bytes(EntryRef::Record(*record))?)?;
+                            }
+                            kept.try_for_each(|x| write_entry_with(&x.entry, w, &mut entry_bytes))
+                        },
+                    )?
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The TES4 record first, followed by the other entries in order
+    pub(crate) fn tops_in_write_order(&self) -> impl Iterator<Item = &Top<'data>> {
+        self.write_order().map(move |index| &self.tops[index])
+    }
+
+    /// Positions of the entries of [Plugin::tops_in_write_order]
+    pub(crate) fn write_order(&self) -> impl Iterator<Item = usize> + '_ {
+        let is_header =
+            move |index: &usize| matches!(self.tops[*index], Top::Record(Record::TES4(_)));
+        let header = (0..self.tops.len()).find(is_header);
+        let rest = (0..self.tops.len()).filter(move |x| !is_header(x));
+        header.into_iter().chain(rest)
+    }
+}
+/// Writes [entry] with the data of each of its fields replaced by what [replace] returns for it, if
+/// anything. [replace] is given the record as a general record, so that it can change its header
+/// as well. Compressed records are decompressed for it, and compressed again following
+/// [recompression]. Records that can't be read as general records are written as they are
+pub(crate) fn write_entry_replacing<F>(
+    entry: EntryRef<'_, '_>,
+    recompression: Recompression,
+    replace: F,
+) -> Result<Vec<u8>, WriteError>
+where
+    F: FnOnce(&mut GeneralRecord<'_>) -> Result<Vec<Option<Vec<u8>>>, WriteError>,
+{
+    let mut buffer = Vec::new();
+    let (mut general, compressed) = match entry {
+        EntryRef::Record(record) => {
+            buffer = record.to_vec()?;
+            if record.common().flags.is(record_flag::COMPRESSED) {
+                return Ok(buffer);
+            }
+            match GeneralRecord::parse(&buffer) {
+                Ok((_, general)) => (general, None),
+                Err(_) => return Ok(buffer),
+            }
+        }
+        EntryRef::Compressed(record) => match record.decompress(&mut buffer) {
+            Ok(general) => (general, Some(record)),
+            Err(_) => return record.to_vec(),
+        },
+    };
+
+    let replaced = replace(&mut general)?;
+    general.fields = general
+        .fields
+        .iter()
+        .zip(replaced.iter().chain(std::iter::repeat(&None)))
+        .map(|(field, replaced)| match replaced {
+            Some(data) => GeneralField::new(field.type_name, data),
+            None => field.clone(),
+        })
+        .collect();
+    match compressed {
+        Some(record) => record.recompress(&general, recompression)?.to_vec(),
+        None => general.to_vec(),
+    }
+}
+
+/// Writes a group of [group_type] holding what [write_entries] writes
+fn write_group_with<T, F>(
+    group_type: &GroupType<'_>,
+    common: &CommonGroupInfo,
+    w: &mut T,
+    write_entries: &mut F,
+) -> Result<(), WriteError>
+where
+    T: Write,
+    F: FnMut(&mut Vec<u8>) -> Result<(), WriteError>,
+{
+    let mut entries = Vec::new();
+    write_entries(&mut entries)?;
+    write_group_size(GROUPH_SIZE + entries.len(), w)?;
+    group_type.write_to(w)?;
+    common.write_to(w)?;
+    w.write_all(&entries)?;
+    Ok(())
+}
+
+/// Writes [entry] of a group as [Plugin::write_entries_with] does
+fn write_entry_with<'b, 'data, T, F>(
+    entry: &GroupEntry<'data>,
+    w: &mut T,
+    entry_bytes: &mut F,
+) -> Result<(), WriteError>
+where
+    T: Write,
+    F: FnMut(EntryRef<'_, 'data>) -> Result<Cow<'b, [u8]>, WriteError>,
+{
+    match entry {
+        GroupEntry::Record(record) => {
+            let record = if is_deleted(record) {
+                RecordRef::Deleted(record)
+            } else {
+                RecordRef::Unknown(record)
+            };
+            w.write_all(&entry_bytes(EntryRef::Record(record))?)?;
+        }
+        GroupEntry::Compressed(record) => {
+            w.write_all(&entry_bytes(EntryRef::Compressed(record))?)?
+        }
+        GroupEntry::Group(group) => {
+            write_group_with(&group.group_type, &group.common, w, &mut |w: &mut Vec<
+                u8,
+            >| {
+                group
+                    .entries
+                    .iter()
+                    .try_for_each(|entry| write_entry_with(entry, w, entry_bytes))
+            })?
+        }
+    }
+    Ok(())
+}
+
+/// Writes [top] as [Plugin::write_records_with] does
+pub(crate) fn write_top_with<'b, 'data, T, F>(
+    top: &Top<'data>,
+    w: &mut T,
+    record_bytes: &mut F,
+) -> Result<(), WriteError>
+where
+    T: Write,
+    F: FnMut(RecordRef<'_, 'data>) -> Result<Cow<'b, [u8]>, WriteError>,
+{
+    match top {
+        Top::Record(record) => w.write_all(&record_bytes(record.as_record_ref())?)?,
+        Top::Group(group @ (Group::Unknown(_) | Group::UnknownTop(_))) => group.write_to(w)?,
+        Top::Group(group) => {
+            let records = group
+                .records()
+                .into_iter()
+                .map(record_bytes)
+                .collect::<Result<Vec<_>, WriteError>>()?;
+            let size = GROUPH_SIZE + records.iter().map(|x| x.len()).sum::<usize>();
+            let size = u32::try_from(size).map_err(|_| WriteError::GroupTooLarge { size })?;
+            w.write_all(b"GRUP")?;
+            size.write_to(w)?;
+            group.group_type().write_to(w)?;
+            group.common().write_to(w)?;
+            for record in records {
+                w.write_all(&record)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+impl Writable for Plugin<'_> {
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
+    where
+        T: Write,
+    {
+        for top in self.tops_in_write_order() {
+            top.write_to(w)?;
+        }
+        Ok(())
+    }
+}
+impl DataSize for Plugin<'_> {
+    fn data_size(&self) -> usize {
+        self.tops.iter().map(DataSize::data_size).sum()
+    }
+}
+
+/// What [Plugin::write_to_file] wrote
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct WriteStats {
+    pub bytes: u64,
+    /// Records that were written, counted as in [Plugin::iter_all_records], so including the
+    /// TES4 record and those within groups that weren't converted
+    pub records: usize,
+}
+
+struct CountingWriter<W> {
+    inner: W,
+    written: u64,
+}
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let amount = self.inner.write(buf)?;
+        self.written += amount as u64;
+        Ok(amount)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// What came after the last entry of a plugin
+#[derive(Debug, Clone, PartialEq)]
+pub enum Trailing<'data> {
+    /// Another plugin, which starts at [offset] in the data
+    Plugin {
+        offset: usize,
+        plugin: Box<Plugin<'data>>,
+        /// What came after that plugin
+        trailing: Option<Box<Trailing<'data>>>,
+    },
+    /// Data that is not part of a plugin, such as padding or an entry that was cut off
+    Data { offset: usize, data: &'data [u8] },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::records::common::{CommonRecordInfo, GeneralRecord};
+    use bstr::ByteSlice;
+
+    fn record(version: u16) -> Top<'static> {
+        let mut common = CommonRecordInfo::test_default();
+        common.version = version;
+        Top::Record(Record::Unknown(GeneralRecord {
+            type_name: b"MISC".as_bstr(),
+            common,
+            fields: Vec::new(),
+        }))
+    }
+
+    #[test]
+    fn test_trailing() {
+        use crate::{records::fields::common::GeneralField, util::Writable};
+
+        let mut common = CommonRecordInfo::test_default();
+        common.flags.flags = 0;
+        let header = GeneralRecord {
+            type_name: b"TES4".as_bstr(),
+            common: common.clone(),
+            fields: vec![GeneralField::new(b"HEDR".as_bstr(), &[0; 12])],
+        };
+        let misc = GeneralRecord {
+            type_name: b"MISC".as_bstr(),
+            common,
+            fields: Vec::new(),
+        };
+        let mut data = Vec::new();
+        header.write_to(&mut data).unwrap();
+        misc.write_to(&mut data).unwrap();
+        let first_len = data.len();
+
+        let mut ctx = ParseContext::default();
+        let (plugin, trailing) = Plugin::parse_with_trailing("Test.esp", &data, &mut ctx).unwrap();
+        assert_eq!(plugin.tops.len(), 2);
+        assert_eq!(trailing, None);
+        assert!(ctx.diagnostics.is_empty());
+
+        header.write_to(&mut data).unwrap();
+        // Padding
+        data.extend_from_slice(&[0; 6]);
+        let (plugin, trailing) = Plugin::parse_with_trailing("Test.esp", &data, &mut ctx).unwrap();
+        assert_eq!(plugin.tops.len(), 2);
+        match trailing {
+            Some(Trailing::Plugin {
+                offset,
+                plugin,
+                trailing,
+            }) => {
+                assert_eq!(offset, first_len);
+                assert_eq!(plugin.tops.len(), 1);
+                assert_eq!(
+                    trailing,
+                    Some(Box::new(Trailing::Data {
+                        offset: data.len() - 6,
+                        data: &[0; 6],
+                    }))
+                );
+            }
+            x => panic!("Expected a concatenated plugin, got {:?}", x),
+        }
+        assert_eq!(ctx.diagnostics.len(), 2);
+
+        let mut ctx = ParseContext::default();
+        Plugin::parse("Test.esp", &data, &mut ctx).unwrap();
+        assert_eq!(
+            ctx.diagnostics[0].message,
+            format!(
+                "There was {} bytes of trailing data after the last entry",
+                data.len() - first_len
+            )
+        );
+    }
+
+    #[test]
+    fn test_write_to_file() {
+        use crate::{records::fields::common::GeneralField, util::DataSize};
+
+        let mut common = CommonRecordInfo::test_default();
+        common.flags.flags = 0;
+        let header = GeneralRecord {
+            type_name: b"TES4".as_bstr(),
+            common,
+            fields: vec![GeneralField::new(b"HEDR".as_bstr(), &[0; 12])],
+        };
+        let mut data = Vec::new();
+        header.write_to(&mut data).unwrap();
+        let mut ctx = ParseContext::default();
+        let header = Plugin::parse("Header.esp", &data, &mut ctx).unwrap().tops[0].clone();
+
+        // The header is moved to the front
+        let mut plugin =
+            Plugin::from_tops("Test.esp".to_string(), vec![record(44), header, record(44)]);
+        let path = std::env::temp_dir().join(format!("vivec_write_{}.esp", std::process::id()));
+        let stats = plugin
+            .write_to_file(&path, &WriteOptions::default())
+            .unwrap();
+        let written = std::fs::read(&path).unwrap();
+
+        assert_eq!(stats.records, 3);
+        assert_eq!(stats.bytes as usize, written.len());
+        assert_eq!(written.len(), plugin.tops.data_size());
+        assert!(written.starts_with(b"TES4"));
+        let parsed = Plugin::parse("Test.esp", &written, &mut ctx).unwrap();
+        assert_eq!(parsed.tops[0], plugin.tops[1]);
+        assert_eq!(parsed.tops[1..], [record(44), record(44)]);
+
+        // Records of groups that weren't converted are counted
+        let general = GeneralRecord {
+            type_name: b"CELL".as_bstr(),
+            common: CommonRecordInfo::test_default(),
+            fields: Vec::new(),
+        };
+        let cells = vec![Record::Unknown(general.clone()), Record::Unknown(general)];
+        let cells = TopGroup::from_records(b"CELL".as_bstr(), cells).unwrap();
+        plugin.push_top(Top::Group(Group::UnknownTop(cells)));
+        assert_eq!(
+            plugin
+                .write_to_file(&path, &WriteOptions::default())
+                .unwrap()
+                .records,
+            5
+        );
+        std::fs::remove_file(&path).unwrap();
+
+        // A write that fails leaves what was there, and no temporary file
+        std::fs::create_dir(&path).unwrap();
+        assert!(plugin
+            .write_to_file(&path, &WriteOptions::default())
+            .is_err());
+        assert!(path.is_dir());
+        std::fs::remove_dir(&path).unwrap();
+        let leftover = std::fs::read_dir(std::env::temp_dir())
+            .unwrap()
+            .filter_map(Result::ok)
+            .any(|x| {
+                let name = x.file_name().to_string_lossy().into_owned();
+                name.starts_with(&format!(".vivec_write_{}", std::process::id()))
+            });
+        assert!(!leftover);
+    }
+
+    #[test]
+    fn test_refresh_header() {
+        use crate::{records::fields::common::GeneralField, util::Writable};
+
+        let mut common = CommonRecordInfo::test_default();
+        common.flags.flags = 0;
+        let header = GeneralRecord {
+            type_name: b"TES4".as_bstr(),
+            common: common.clone(),
+            fields: vec![GeneralField::new(b"HEDR".as_bstr(), &[0; 12])],
+        };
+        let misc = GeneralRecord {
+            type_name: b"MISC".as_bstr(),
+            common: common.clone(),
+            fields: Vec::new(),
+        };
+        common.id = 0xA00;
+        let weap = GeneralRecord {
+            type_name: b"WEAP".as_bstr(),
+            common,
+            fields: Vec::new(),
+        };
+        let mut weap_data = Vec::new();
+        weap.write_to(&mut weap_data).unwrap();
+
+        let mut data = Vec::new();
+        header.write_to(&mut data).unwrap();
+        misc.write_to(&mut data).unwrap();
+        // A group we don't convert, so it is counted from its entries
+        data.extend_from_slice(b"GRUP");
+        data.extend_from_slice(&(24 + weap_data.len() as u32).to_le_bytes());
+        data.extend_from_slice(b"WEAP");
+        data.extend_from_slice(&[0; 12]);
+        data.extend_from_slice(&weap_data);
+
+        let mut ctx = ParseContext::default();
+        let mut plugin = Plugin::parse("Test.esp", &data, &mut ctx).unwrap();
+        assert!(plugin.refresh_header());
+        let hedr = plugin.header().unwrap().header();
+        assert_eq!(hedr.record_count, 3);
+        assert_eq!(hedr.next_object_id, 0xA01);
+
+        plugin.tops.remove(2);
+        assert!(plugin.refresh_header());
+        let hedr = plugin.header().unwrap().header();
+        assert_eq!(hedr.record_count, 1);
+        assert_eq!(hedr.next_object_id, 0xA01);
+
+        plugin.tops.remove(0);
+        assert!(!plugin.refresh_header());
+    }
+
+    #[test]
+    fn test_new_plugin() {
+        let mut plugin = Plugin::new("MyPatch.esp");
+        plugin
+            .header_mut()
+            .unwrap()
+            .set_author(b"Someone".as_bstr());
+        plugin.tops.push(record(44));
+        assert!(plugin.refresh_header());
+        let data = plugin.to_vec().unwrap();
+
+        let mut ctx = ParseContext::default();
+        let parsed = Plugin::parse("MyPatch.esp", &data, &mut ctx).unwrap();
+        assert!(ctx.diagnostics.is_empty());
+        assert_eq!(parsed, plugin);
+        let header = parsed.header().unwrap();
+        assert_eq!(header.header().record_count, 1);
+        assert_eq!(header.author().unwrap().author.value, b"Someone".as_bstr());
+        assert!(parsed.masters().is_empty());
+    }
+
+    #[test]
+    fn test_plugin_flags() {
+        let mut plugin = Plugin::new("Patch.esl");
+        assert!(!plugin.is_master() && !plugin.is_light());
+        let mut misc = record(44);
+        if let Top::Record(Record::Unknown(misc)) = &mut misc {
+            misc.common.id = 0x1000;
+        }
+        plugin.tops.push(misc);
+
+        assert!(matches!(
+            plugin.check_flags("ESM"),
+            Err(WriteError::ExtensionFlags { flag: "MASTER", .. })
+        ));
+        assert!(plugin.set_master(true));
+        assert!(plugin.is_master());
+        plugin.check_flags("esm").unwrap();
+        plugin.check_flags("esp").unwrap();
+        assert!(matches!(
+            plugin.check_flags("esl"),
+            Err(WriteError::ExtensionFlags {
+                flag: "LIGHT_MASTER",
+                ..
+            })
+        ));
+
+        match plugin.set_light(true) {
+            Err(WriteError::LightRange(ids)) => assert_eq!(ids, vec![FormId::new(0x1000)]),
+            x => panic!("Expected the id to be out of range, got {:?}", x),
+        }
+        assert!(!plugin.is_light());
+        if let Top::Record(Record::Unknown(misc)) = &mut plugin.tops[1] {
+            misc.common.id = 0x0100;
+        }
+        assert!(plugin.set_light(true).is_err());
+        plugin.header_mut().unwrap().header_mut().version = 1.71;
+        assert!(plugin.set_light(true).unwrap());
+        plugin.check_flags("esl").unwrap();
+
+        let path = std::env::temp_dir().join(format!("vivec_flags_{}.esm", std::process::id()));
+        plugin.set_master(false);
+        assert!(plugin
+            .write_to_file(&path, &WriteOptions::default())
+            .is_err());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_write_to_file_verified() {
+        use crate::records::flst::{FLSTField, FLSTRecord, LNAM};
+
+        let list = |editor_id_index| {
+            Top::Record(Record::FLST(FLSTRecord {
+                common: CommonRecordInfo::test_default(),
+                editor_id_index,
+                fields: vec![FLSTField::LNAM(LNAM::new(FormId::new(0x201)))],
+            }))
+        };
+        let path = std::env::temp_dir().join(format!("vivec_verify_{}.esp", std::process::id()));
+        let options = ParseOptions::default();
+        let plugin = Plugin::from_tops("Test.esp".to_string(), vec![list(None)]);
+        let stats = plugin
+            .write_to_file_verified(&path, &options, &WriteOptions::default())
+            .unwrap();
+        assert_eq!(stats.records, 1);
+        std::fs::remove_file(&path).unwrap();
+
+        // Points at an EDID that isn't there
+        let plugin = Plugin::from_tops("Test.esp".to_string(), vec![list(Some(0))]);
+        match plugin.write_to_file_verified(&path, &options, &WriteOptions::default()) {
+            Err(WriteError::Unverified(mismatches)) => assert_eq!(mismatches.len(), 1),
+            x => panic!("Expected the write to fail verification, got {:?}", x),
+        }
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_write_with_options() {
+        use crate::records::fields::common::GeneralField;
+
+        let mut plugin = Plugin::new("Patch.esp");
+        plugin.encoding = TextEncoding::Utf8;
+        let mut common = CommonRecordInfo::test_default();
+        common.flags.flags = 0;
+        let house = GeneralRecord {
+            type_name: b"CELL".as_bstr(),
+            common,
+            fields: vec![
+                GeneralField::new(b"EDID".as_bstr(), b"House\0"),
+                GeneralField::new(b"FULL".as_bstr(), "Дом\0".as_bytes()),
+                GeneralField::new(b"XCLW".as_bstr(), &[0xC4, 0, 0, 0]),
+            ],
+        };
+        let cells = TopGroup::from_records(b"CELL".as_bstr(), vec![Record::Unknown(house)]);
+        plugin.push_top(Top::Group(Group::UnknownTop(cells.unwrap())));
+
+        let mut written = Vec::new();
+        let options = WriteOptions::default();
+        assert!(matches!(
+            plugin.write_with_options(&mut written, &options),
+            Err(WriteError::Encoding(_))
+        ));
+        let options = WriteOptions {
+            encoding: TextEncoding::from_label("windows-1251").unwrap(),
+            ..WriteOptions::default()
+        };
+        let mut written = Vec::new();
+        plugin.write_with_options(&mut written, &options).unwrap();
+        assert!(written.find(b"House\0").is_some());
+        assert!(written.find(b"\xC4\xEE\xEC\0").is_some());
+        assert!(written.find("Дом".as_bytes()).is_none());
+        // Only text fields are changed
+        assert!(written.ends_with(&[0xC4, 0, 0, 0]));
+        assert_eq!(written.len(), plugin.to_vec().unwrap().len() - 3);
+
+        let options = WriteOptions {
+            encoding: TextEncoding::Utf8,
+            ..WriteOptions::default()
+        };
+        let mut written = Vec::new();
+        plugin.write_with_options(&mut written, &options).unwrap();
+        assert_eq!(written, plugin.to_vec().unwrap());
+    }
+
+    #[test]
+    fn test_write_sorted() {
+        let mut plugin = Plugin::new("Test.esp");
+        for id in [0x802, 0x800, 0x801] {
+            if let Top::Record(record) = crate::fixtures::list(id, &[]) {
+                plugin.insert_record(record).unwrap();
+            }
+        }
+        let unsorted = plugin.to_vec().unwrap();
+        let options = WriteOptions {
+            sort_records: true,
+            ..WriteOptions::default()
+        };
+        let mut written = Vec::new();
+        plugin.write_with_options(&mut written, &options).unwrap();
+        assert_eq!(plugin.to_vec().unwrap(), unsorted);
+        plugin.sort_records();
+        assert_ne!(written, unsorted);
+        assert_eq!(written, plugin.to_vec().unwrap());
+    }
+
+    #[test]
+    fn test_find_index() {
+        let mut plugin = Plugin::new("Test.esp");
+        plugin.push_top(crate::fixtures::list(0x800, &[]));
+        assert!(plugin.find(FormId::new(0x800)).is_some());
+        // Added without going through Plugin::tops_mut, so only a rebuilt index would find it
+        plugin.tops.push(crate::fixtures::list(0x801, &[]));
+        assert!(plugin.find(FormId::new(0x801)).is_none());
+        plugin.tops_mut();
+        assert!(plugin.find(FormId::new(0x801)).is_some());
+        plugin.top_mut(1);
+        assert!(plugin.find(FormId::new(0x800)).is_some());
+        assert!(plugin.find(FormId::new(0x802)).is_none());
+    }
+
+    #[test]
+    fn test_form_versions() {
+        assert_eq!(FormVersion::from_u16(43), FormVersion::LegendaryEdition);
+        assert_eq!(FormVersion::from_u16(44), FormVersion::SpecialEdition);
+        assert_eq!(FormVersion::from_u16(40), FormVersion::Old(40));
+        assert_eq!(FormVersion::from_u16(45), FormVersion::Unknown(45));
+        assert_eq!(FormVersion::from_u16(45).value(), 45);
+
+        // Special Edition masters mix versions, as not every record was saved again
+        let mut plugin = Plugin::new("Test.esm");
+        plugin.header_mut().unwrap().common.version = 44;
+        plugin
+            .tops
+            .extend(vec![record(40), record(43), record(44), record(44)]);
+        let mut ctx = ParseContext::default();
+        plugin.check_form_versions(&mut ctx);
+        assert!(ctx.diagnostics.is_empty());
+        let versions = plugin.form_versions();
+        assert_eq!(versions.get(&FormVersion::SpecialEdition), Some(&3));
+        assert_eq!(versions.get(&FormVersion::LegendaryEdition), Some(&1));
+
+        plugin.header_mut().unwrap().common.version = 43;
+        plugin.check_form_versions(&mut ctx);
+        assert_eq!(ctx.diagnostics.len(), 1);
+        assert_eq!(ctx.diagnostics[0].severity, Severity::Warning);
+        assert_eq!(ctx.diagnostics[0].record, None);
+        assert_eq!(
+            ctx.diagnostics[0].message,
+            "Test.esm has 2 records with form version 44 or newer, while its header has 43"
+        );
+
+        if let Top::Record(Record::Unknown(record)) = &plugin.tops[1] {
+            let mut record = record.clone();
+            record.common.version = 50;
+            ctx.enter_record(&record);
+            assert_eq!(ctx.diagnostics.len(), 2);
+            assert_eq!(ctx.diagnostics[1].message, "Unknown form version 50");
+        }
+    }
+
+    #[test]
+    fn test_global_formid() {
+        let mut plugin = Plugin::new("Patch.esp");
+        let header = plugin.header_mut().unwrap();
+        header.push_master(BString::from("Skyrim.esm"));
+        header.push_master(BString::from("Dawnguard.esm"));
+        let mut other = Plugin::new("Other.esp");
+        other
+            .header_mut()
+            .unwrap()
+            .push_master(BString::from("dawnguard.esm"));
+
+        let id = FormId::new(0x0100_2d5c);
+        let global = plugin.global_formid(id).unwrap();
+        assert_eq!(global, GlobalFormId::new("Dawnguard.esm", 0x2d5c));
+        assert_eq!(global.to_string(), "002D5C:Dawnguard.esm");
+        assert_eq!(plugin.local_formid(&global), Some(id));
+        assert_eq!(other.local_formid(&global), Some(FormId::new(0x2d5c)));
+        assert_eq!(
+            other.global_formid(FormId::new(0x2d5c)),
+            Some(GlobalFormId::new("DAWNGUARD.ESM", 0x2d5c))
+        );
+
+        let own = plugin.global_formid(FormId::new(0x0200_0801)).unwrap();
+        assert_eq!(own, GlobalFormId::new("Patch.esp", 0x801));
+        assert_eq!(other.local_formid(&own), None);
+        assert_eq!(plugin.global_formid(FormId::new(0x0300_0801)), None);
+    }
+
+    #[test]
+    fn test_unknown_groups() {
+        use crate::{
+            groups::common::{CommonGroupInfo, GeneralGroup, GroupEntry, GroupType},
+            util::Writable,
+        };
+
+        let mut common = CommonRecordInfo::test_default();
+        common.flags.flags = 0;
+        common.id = 0x801;
+        let cell = GeneralRecord {
+            type_name: b"CELL".as_bstr(),
+            common,
+            fields: Vec::new(),
+        };
+        // Groups of types that newer games add, at the top level and below a cell
+        let unknown =
+            |entries| GeneralGroup::new(GroupType::unknown(10, *b"WXYZ").unwrap(), entries);
+        let children = GeneralGroup::new(
+            GroupType::CellChildren(FormId::new(0x801)),
+            vec![unknown(Vec::new()).into()],
+        );
+        let mut header = Plugin::new("Test.esp");
+        header.refresh_header();
+        let mut data = header.to_vec().unwrap();
+        unknown(vec![GroupEntry::Record(cell.clone())])
+            .write_to(&mut data)
+            .unwrap();
+        TopGroup {
+            label: b"CELL".as_bstr(),
+            common: CommonGroupInfo::new(),
+            entries: vec![cell.into(), children.into()],
+        }
+        .write_to(&mut data)
+        .unwrap();
+
+        let mut ctx = ParseContext::default();
+        let plugin = Plugin::parse("Test.esp", &data, &mut ctx).unwrap();
+        assert!(matches!(
+            &plugin.tops[1],
+            Top::Group(Group::Unknown(group)) if group.group_type.code() == 10
+        ));
+        assert_eq!(plugin.to_vec().unwrap(), data);
+        assert!(plugin
+            .find_group(&GroupType::unknown(10, *b"WXYZ").unwrap())
+            .is_some());
+        assert!(ctx.diagnostics.is_empty());
+    }
+}
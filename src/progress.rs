@@ -0,0 +1,62 @@
+// Progress reporting for long operations, so that a GUI can show how far along parsing is and
+// let the user cancel it.
+// Parsing reports after each top-level entry is converted, and after each entry within a top
+// group as it is read, so that large groups such as CELL and WRLD show how far along they are.
+
+use bstr::BStr;
+
+/// How far along an operation is
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub struct ProgressState<'a> {
+    pub bytes_processed: usize,
+    pub bytes_total: usize,
+    /// Records converted (or checked, for analyses) so far, including those within groups
+    pub records_converted: usize,
+    /// Amount of records that will be converted or checked, when that is known ahead of time
+    pub records_total: Option<usize>,
+    /// Label of the top group that was last processed, None if it was a record
+    pub group: Option<&'a BStr>,
+}
+impl ProgressState<'_> {
+    /// Fraction of the work that has been done, from 0 to 1.
+    /// Goes by bytes, or by records for operations that don't work on bytes.
+    pub fn fraction(&self) -> f32 {
+        match (self.bytes_total, self.records_total) {
+            (0, Some(records_total)) if records_total != 0 => {
+                self.records_converted as f32 / records_total as f32
+            }
+            (0, _) => 1.0,
+            (bytes_total, _) => self.bytes_processed as f32 / bytes_total as f32,
+        }
+    }
+}
+
+/// The result of an operation that can be cancelled, which holds what was gathered before it
+/// stopped
+#[derive(Debug, Clone, PartialEq)]
+pub struct Partial<T> {
+    pub value: T,
+    /// Whether the operation finished, rather than being cancelled
+    pub complete: bool,
+}
+
+pub trait Progress {
+    fn report(&mut self, state: &ProgressState<'_>);
+
+    /// Checked before each step, stopping the operation if true
+    fn is_cancelled(&self) -> bool {
+        false
+    }
+}
+/// No reporting
+impl Progress for () {
+    fn report(&mut self, _state: &ProgressState<'_>) {}
+}
+impl<F> Progress for F
+where
+    F: FnMut(&ProgressState<'_>),
+{
+    fn report(&mut self, state: &ProgressState<'_>) {
+        self(state)
+    }
+}
@@ -1,14 +1,17 @@
 use super::{
-    common::{self, CommonRecordInfo, GeneralRecord, Index},
+    common::{self, CommonRecordInfo, FormId, FormIdVisitor, GeneralRecord, Index},
     fields::{
         common::{rgbu, GeneralField},
         edid,
     },
 };
 use crate::{
-    collect_one, dispatch_all, impl_from_field, impl_static_type_named, make_single_value_field,
+    collect_one,
+    context::ParseContext,
+    dispatch_all, impl_formid_visitor, impl_from_field, impl_no_formids, impl_redecode_unknowns,
+    impl_static_type_named, make_single_value_field,
     parse::PResult,
-    util::{DataSize, Writable},
+    util::{record_data_size, DataSize, Writable, WriteError},
 };
 use bstr::BStr;
 use common::{FromRecord, FromRecordError, TypeNamed};
@@ -28,14 +31,17 @@ pub struct AACTRecord<'data> {
     pub fields: Vec<AACTField<'data>>,
 }
 impl<'data> FromRecord<'data> for AACTRecord<'data> {
-    fn from_record(record: GeneralRecord<'data>) -> PResult<AACTRecord<'data>, FromRecordError> {
+    fn from_record(
+        record: GeneralRecord<'data>,
+        ctx: &mut ParseContext,
+    ) -> PResult<'data, AACTRecord<'data>, FromRecordError<'data>> {
         let mut edid_index = None;
         let mut cname_index = None;
         let mut fields = Vec::new();
         for field in record.fields {
             match field.type_name.as_ref() {
-                b"EDID" => collect_one!(edid::EDID, field => fields; edid_index),
-                b"CNAM" => collect_one!(CNAM, field => fields; cname_index),
+                b"EDID" => collect_one!(edid::EDID, field => fields; edid_index, ctx),
+                b"CNAM" => collect_one!(CNAM, field => fields; cname_index, ctx),
                 _ => fields.push(AACTField::Unknown(field)),
             }
         }
@@ -52,6 +58,8 @@ impl<'data> FromRecord<'data> for AACTRecord<'data> {
     }
 }
 impl_static_type_named!(AACTRecord<'_>, b"AACT");
+impl_redecode_unknowns!(AACTRecord, AACTField);
+impl_formid_visitor!(AACTRecord<'_>, [fields]);
 impl<'data> DataSize for AACTRecord<'data> {
     fn data_size(&self) -> usize {
         self.type_name().data_size() +
@@ -61,13 +69,12 @@ impl<'data> DataSize for AACTRecord<'data> {
     }
 }
 impl<'data> Writable for AACTRecord<'data> {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
         self.type_name().write_to(w)?;
-        // TODO: assert that size fits within a u32
-        (self.fields.data_size() as u32).write_to(w)?;
+        record_data_size(self.type_name(), self.fields.data_size())?.write_to(w)?;
         self.common.write_to(w)?;
         self.fields.write_to(w)
     }
@@ -84,6 +91,35 @@ impl<'data> TypeNamed<'data> for AACTField<'data> {
         dispatch_all!(AACTField, self, [EDID, CNAM, Unknown], x, { x.type_name() })
     }
 }
+impl FormIdVisitor for AACTField<'_> {
+    fn visit_formids(&mut self, visit: &mut dyn FnMut(&mut FormId)) {
+        match self {
+            AACTField::EDID(x) => x.visit_formids(visit),
+            AACTField::CNAM(x) => x.visit_formids(visit),
+            AACTField::Unknown(x) => x.visit_formids(visit),
+        }
+    }
+
+    fn for_each_formid(&self, visit: &mut dyn FnMut(FormId)) {
+        match self {
+            AACTField::EDID(x) => x.for_each_formid(visit),
+            AACTField::CNAM(x) => x.for_each_formid(visit),
+            AACTField::Unknown(x) => x.for_each_formid(visit),
+        }
+    }
+
+    fn for_each_field_formid<'name>(
+        &self,
+        name: &'name BStr,
+        visit: &mut dyn FnMut(&'name BStr, FormId),
+    ) {
+        match self {
+            AACTField::EDID(x) => x.for_each_field_formid(name, visit),
+            AACTField::CNAM(x) => x.for_each_field_formid(name, visit),
+            AACTField::Unknown(x) => x.for_each_field_formid(name, visit),
+        }
+    }
+}
 impl<'data> DataSize for AACTField<'data> {
     fn data_size(&self) -> usize {
         match self {
@@ -94,7 +130,7 @@ impl<'data> DataSize for AACTField<'data> {
     }
 }
 impl<'data> Writable for AACTField<'data> {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -107,6 +143,7 @@ impl<'data> Writable for AACTField<'data> {
 }
 
 make_single_value_field!([Debug, Copy, Clone, Eq, PartialEq], CNAM, color, rgbu::RGBU);
+impl_no_formids!(CNAM);
 impl_from_field!(CNAM, [color: rgbu::RGBU]);
 
 #[cfg(test)]
@@ -127,4 +164,84 @@ mod tests {
 
         assert_size_output!(record);
     }
+
+    #[test]
+    fn aactrecord_duplicate_edid() {
+        use crate::context::{ParseContext, ParseOptions};
+        use bstr::ByteSlice;
+        let record = GeneralRecord {
+            type_name: b"AACT".as_bstr(),
+            common: CommonRecordInfo::test_default(),
+            fields: vec![
+                GeneralField::new(b"EDID".as_bstr(), b"First\0"),
+                GeneralField::new(b"EDID".as_bstr(), b"Second\0"),
+            ],
+        };
+
+        let mut ctx = ParseContext::default();
+        assert_eq!(
+            AACTRecord::from_record(record.clone(), &mut ctx),
+            Err(FromRecordError::DuplicateField(b"edid::EDID".as_bstr()))
+        );
+
+        let mut ctx = ParseContext::new(ParseOptions::lenient());
+        ctx.enter_record(&record);
+        let (_, aact) = AACTRecord::from_record(record.clone(), &mut ctx).unwrap();
+        assert_eq!(aact.action_name_index, Some(0));
+        assert_eq!(
+            aact.fields[1],
+            AACTField::Unknown(GeneralField::new(b"EDID".as_bstr(), b"Second\0"))
+        );
+        assert_eq!(ctx.diagnostics.len(), 1);
+        assert_eq!(
+            ctx.diagnostics[0].record,
+            Some((
+                "AACT".to_string(),
+                crate::records::common::FormId::new(0x420)
+            ))
+        );
+        let data = assert_size_output!(aact);
+        assert_eq!(data.len(), record.data_size());
+    }
+
+    #[test]
+    fn aactrecord_redecode_unknowns() {
+        use crate::{context::ParseContext, records::common::RedecodeError};
+        use bstr::ByteSlice;
+        let mut record = AACTRecord {
+            common: CommonRecordInfo::test_default(),
+            action_name_index: None,
+            rgb_index: None,
+            fields: vec![
+                AACTField::Unknown(GeneralField::new(b"EDID".as_bstr(), b"Action\0")),
+                AACTField::Unknown(GeneralField::new(b"CNAM".as_bstr(), &[1, 2, 3, 0])),
+            ],
+        };
+        let mut ctx = ParseContext::default();
+        let mut buffer = Vec::new();
+        let (redecoded, decoded) = record.redecode_unknowns(&mut buffer, &mut ctx).unwrap();
+        assert_eq!(decoded, 2);
+        assert_eq!(redecoded.action_name_index, Some(0));
+        assert_eq!(redecoded.rgb_index, Some(1));
+        assert!(matches!(redecoded.fields[1], AACTField::CNAM(_)));
+        let mut again = Vec::new();
+        assert_eq!(
+            redecoded.redecode_unknowns(&mut again, &mut ctx).unwrap().1,
+            0
+        );
+
+        // A second EDID isn't decoded next to the first, it is refused as it would be on parsing
+        record.fields.push(AACTField::Unknown(GeneralField::new(
+            b"EDID".as_bstr(),
+            b"Other\0",
+        )));
+        assert!(matches!(
+            record.redecode_unknowns(&mut buffer, &mut ctx),
+            Err(RedecodeError::Record(FromRecordError::DuplicateField(_)))
+        ));
+        let mut ctx = ParseContext::new(crate::context::ParseOptions::lenient());
+        let (redecoded, decoded) = record.redecode_unknowns(&mut buffer, &mut ctx).unwrap();
+        assert_eq!(decoded, 2);
+        assert!(matches!(redecoded.fields[2], AACTField::Unknown(_)));
+    }
 }
@@ -1,15 +1,18 @@
 use super::{
-    common::{self, CommonRecordInfo, GeneralRecord, Index},
+    common::{self, CommonRecordInfo, FormIdVisitor, GeneralRecord, Index},
     fields::{
         common::{write_field_header, FromField, FromFieldError, GeneralField, FIELDH_SIZE},
         edid, vmad,
     },
 };
 use crate::{
-    collect_many, collect_one, dispatch_all, impl_from_field, impl_static_data_size,
-    impl_static_type_named, make_empty_field, make_formid_field, make_single_value_field,
+    collect_many, collect_one,
+    context::ParseContext,
+    dispatch_all, impl_formid_visitor, impl_from_field, impl_no_formids, impl_redecode_unknowns,
+    impl_static_data_size, impl_static_type_named, make_empty_field, make_formid_field,
+    make_single_value_field,
     parse::{take, PResult, Parse, ParseError},
-    util::{byte, DataSize, Position3, Writable},
+    util::{byte, record_data_size, DataSize, Position3, Writable, WriteError},
 };
 use bstr::{BStr, ByteSlice};
 use common::{FormId, FromRecord, FromRecordError, StaticTypeNamed, TypeNamed};
@@ -77,8 +80,26 @@ pub struct ACHRRecord<'data> {
 
     fields: Vec<ACHRField<'data>>,
 }
+impl<'data> ACHRRecord<'data> {
+    /// The fields in the order they are written. They are only changed through the methods, as
+    /// the indices point into them
+    pub fn fields(&self) -> &[ACHRField<'data>] {
+        &self.fields
+    }
+
+    pub fn editor_id_mut(&mut self) -> Option<&mut edid::EDID<'data>> {
+        let index = self.editor_id_index?;
+        match &mut self.fields[index] {
+            ACHRField::EDID(x) => Some(x),
+            _ => panic!("ILE: Unreachable"),
+        }
+    }
+}
 impl<'data> FromRecord<'data> for ACHRRecord<'data> {
-    fn from_record(record: GeneralRecord<'data>) -> PResult<Self, FromRecordError> {
+    fn from_record(
+        record: GeneralRecord<'data>,
+        ctx: &mut ParseContext,
+    ) -> PResult<'data, Self, FromRecordError<'data>> {
         let mut editor_id_index: Option<Index> = None;
         let mut script_index: Option<Index> = None;
         let mut base_npc_index: Option<Index> = None; // has to have value
@@ -107,31 +128,31 @@ impl<'data> FromRecord<'data> for ACHRRecord<'data> {
 
         for field in record.fields {
             match field.type_name.as_ref() {
-                b"EDID" => collect_one!(edid::EDID, field => fields; editor_id_index),
+                b"EDID" => collect_one!(edid::EDID, field => fields; editor_id_index, ctx),
                 b"VMAD" => {
-                    collect_one!(vmad::VMAD<'data, vmad::NoFragments>, field => fields; script_index)
+                    collect_one!(vmad::VMAD<'data, vmad::NoFragments>, field => fields; script_index, ctx)
                 }
-                b"NAME" => collect_one!(NAME, field => fields; base_npc_index),
-                b"XEZN" => collect_one!(XEZN, field => fields; encounter_zone_index),
-                b"XPRD" => collect_one!(XPRD, field => fields; patrol_idle_index),
-                b"XPPA" => collect_one!(XPPA, field => fields; unknown_xppa_index),
-                b"INAM" => collect_one!(INAM, field => fields; unknown_inam_index),
-                b"PDTO" => collect_one!(PDTO, field => fields; topic_data_index),
-                b"XRGD" => collect_one!(XRGD, field => fields; ragdoll_index),
-                b"XRGB" => collect_one!(XRGB, field => fields; unknown_xrgb),
-                b"XLCM" => collect_one!(XLCM, field => fields; leveled_creature_data),
-                b"XAPD" => collect_one!(XAPD, field => fields; activation_parent_flags_index),
-                b"XAPR" => collect_one!(XAPR, field => fields; activate_parent_index),
-                b"XLRT" => collect_many!(XLRT, field => fields; location_ref_type_indices),
-                b"XHOR" => collect_one!(XHOR, field => fields; horse_id_index),
-                b"XESP" => collect_one!(XESP, field => fields; enable_parent_index),
-                b"XOWN" => collect_one!(XOWN, field => fields; owner_index),
-                b"XLCN" => collect_one!(XLCN, field => fields; location_index),
-                b"XLKR" => collect_one!(XLKR, field => fields; location_route_index),
-                b"XIS2" => collect_one!(XIS2, field => fields; unknown_xis2_index),
-                b"XLRL" => collect_one!(XLRL, field => fields; unknown_xlrl_index),
-                b"XSCL" => collect_one!(XSCL, field => fields; scale_index),
-                b"DATA" => collect_one!(DATA, field => fields; coords_index),
+                b"NAME" => collect_one!(NAME, field => fields; base_npc_index, ctx),
+                b"XEZN" => collect_one!(XEZN, field => fields; encounter_zone_index, ctx),
+                b"XPRD" => collect_one!(XPRD, field => fields; patrol_idle_index, ctx),
+                b"XPPA" => collect_one!(XPPA, field => fields; unknown_xppa_index, ctx),
+                b"INAM" => collect_one!(INAM, field => fields; unknown_inam_index, ctx),
+                b"PDTO" => collect_one!(PDTO, field => fields; topic_data_index, ctx),
+                b"XRGD" => collect_one!(XRGD, field => fields; ragdoll_index, ctx),
+                b"XRGB" => collect_one!(XRGB, field => fields; unknown_xrgb, ctx),
+                b"XLCM" => collect_one!(XLCM, field => fields; leveled_creature_data, ctx),
+                b"XAPD" => collect_one!(XAPD, field => fields; activation_parent_flags_index, ctx),
+                b"XAPR" => collect_one!(XAPR, field => fields; activate_parent_index, ctx),
+                b"XLRT" => collect_many!(XLRT, field => fields; location_ref_type_indices, ctx),
+                b"XHOR" => collect_one!(XHOR, field => fields; horse_id_index, ctx),
+                b"XESP" => collect_one!(XESP, field => fields; enable_parent_index, ctx),
+                b"XOWN" => collect_one!(XOWN, field => fields; owner_index, ctx),
+                b"XLCN" => collect_one!(XLCN, field => fields; location_index, ctx),
+                b"XLKR" => collect_one!(XLKR, field => fields; location_route_index, ctx),
+                b"XIS2" => collect_one!(XIS2, field => fields; unknown_xis2_index, ctx),
+                b"XLRL" => collect_one!(XLRL, field => fields; unknown_xlrl_index, ctx),
+                b"XSCL" => collect_one!(XSCL, field => fields; scale_index, ctx),
+                b"DATA" => collect_one!(DATA, field => fields; coords_index, ctx),
                 _ => fields.push(ACHRField::Unknown(field)),
             }
         }
@@ -174,14 +195,15 @@ impl<'data> FromRecord<'data> for ACHRRecord<'data> {
     }
 }
 impl_static_type_named!(ACHRRecord<'_>, b"ACHR");
+impl_redecode_unknowns!(ACHRRecord, ACHRField);
+impl_formid_visitor!(ACHRRecord<'_>, [fields]);
 impl<'data> Writable for ACHRRecord<'data> {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
         self.type_name().write_to(w)?;
-        // TODO: assert that size fits within a u32
-        (self.fields.data_size() as u32).write_to(w)?;
+        record_data_size(self.type_name(), self.fields.data_size())?.write_to(w)?;
         self.common.write_to(w)?;
         self.fields.write_to(w)?;
         Ok(())
@@ -238,6 +260,50 @@ impl<'data> TypeNamed<'data> for ACHRField<'data> {
         )
     }
 }
+impl FormIdVisitor for ACHRField<'_> {
+    fn visit_formids(&mut self, visit: &mut dyn FnMut(&mut FormId)) {
+        dispatch_all!(
+            ACHRField,
+            self,
+            [
+                EDID, VMAD, NAME, XEZN, XPRD, XPPA, INAM, PDTO, XRGD, XRGB, XLCM, XAPD, XAPR, XLRT,
+                XHOR, XESP, XOWN, XLCN, XLKR, XIS2, XLRL, XSCL, DATA, Unknown
+            ],
+            x,
+            { x.visit_formids(visit) }
+        )
+    }
+
+    fn for_each_formid(&self, visit: &mut dyn FnMut(FormId)) {
+        dispatch_all!(
+            ACHRField,
+            self,
+            [
+                EDID, VMAD, NAME, XEZN, XPRD, XPPA, INAM, PDTO, XRGD, XRGB, XLCM, XAPD, XAPR, XLRT,
+                XHOR, XESP, XOWN, XLCN, XLKR, XIS2, XLRL, XSCL, DATA, Unknown
+            ],
+            x,
+            { x.for_each_formid(visit) }
+        )
+    }
+
+    fn for_each_field_formid<'name>(
+        &self,
+        name: &'name BStr,
+        visit: &mut dyn FnMut(&'name BStr, FormId),
+    ) {
+        dispatch_all!(
+            ACHRField,
+            self,
+            [
+                EDID, VMAD, NAME, XEZN, XPRD, XPPA, INAM, PDTO, XRGD, XRGB, XLCM, XAPD, XAPR, XLRT,
+                XHOR, XESP, XOWN, XLCN, XLKR, XIS2, XLRL, XSCL, DATA, Unknown
+            ],
+            x,
+            { x.for_each_field_formid(name, visit) }
+        )
+    }
+}
 impl<'data> DataSize for ACHRField<'data> {
     fn data_size(&self) -> usize {
         dispatch_all!(
@@ -253,7 +319,7 @@ impl<'data> DataSize for ACHRField<'data> {
     }
 }
 impl<'data> Writable for ACHRField<'data> {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -280,6 +346,7 @@ impl FromField<'_> for XPRD {
         Ok((data, XPRD { idle_time }))
     }
 }
+impl_no_formids!(XPRD);
 
 make_empty_field!(XPPA);
 
@@ -293,6 +360,7 @@ impl<'data> FromField<'data> for PDTO<'data> {
         Ok((data, PDTO { topic_type }))
     }
 }
+impl_formid_visitor!(PDTO<'_>, [topic_type]);
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum TopicType<'data> {
     /// 0
@@ -334,13 +402,26 @@ impl<'data> TopicType<'data> {
         }
     }
 }
+impl FormIdVisitor for TopicType<'_> {
+    fn visit_formids(&mut self, visit: &mut dyn FnMut(&mut FormId)) {
+        if let TopicType::Ref(formid) = self {
+            visit(formid)
+        }
+    }
+
+    fn for_each_formid(&self, visit: &mut dyn FnMut(FormId)) {
+        if let TopicType::Ref(formid) = self {
+            visit(*formid)
+        }
+    }
+}
 impl_static_data_size!(
     TopicType<'_>,
     u32::static_data_size() + // type integer
         FormId::static_data_size() // u32 size (formid | 4 char bstr)
 );
 impl<'data> Writable for TopicType<'data> {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -366,6 +447,7 @@ impl<'data> FromField<'data> for XRGD<'data> {
         Ok((&[], Self { data: field.data }))
     }
 }
+impl_no_formids!(XRGD<'_>);
 
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub struct XRGB {
@@ -374,6 +456,7 @@ pub struct XRGB {
     pub data: [f32; 3],
 }
 impl_static_type_named!(XRGB, b"XRGB");
+impl_no_formids!(XRGB);
 impl FromField<'_> for XRGB {
     fn from_field(field: GeneralField<'_>) -> PResult<Self, FromFieldError> {
         let (data, f1) = f32::parse(field.data)?;
@@ -384,7 +467,7 @@ impl FromField<'_> for XRGB {
 }
 impl_static_data_size!(XRGB, FIELDH_SIZE + (f32::static_data_size() * 3));
 impl Writable for XRGB {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -417,6 +500,7 @@ impl FromField<'_> for XLCM {
         ))
     }
 }
+impl_no_formids!(XLCM);
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 #[repr(u32)]
@@ -444,7 +528,7 @@ impl LevelModifier {
 }
 impl_static_data_size!(LevelModifier, u32::static_data_size());
 impl Writable for LevelModifier {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -454,6 +538,7 @@ impl Writable for LevelModifier {
 
 make_single_value_field!([Debug, Copy, Clone, Eq, PartialEq], XAPD, flags, XAPDFlags);
 impl_from_field!(XAPD, [flags: XAPDFlags]);
+impl_no_formids!(XAPD);
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub struct XAPDFlags {
     /// 0b1: parent activate only
@@ -476,7 +561,7 @@ impl Parse<'_> for XAPDFlags {
 }
 impl_static_data_size!(XAPDFlags, u8::static_data_size());
 impl Writable for XAPDFlags {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -493,6 +578,7 @@ pub struct XAPR {
 }
 impl_from_field!(XAPR, [formid: FormId, delay: f32]);
 impl_static_type_named!(XAPR, b"XAPR");
+impl_formid_visitor!(XAPR, [formid]);
 impl_static_data_size!(
     XAPR,
     FIELDH_SIZE +
@@ -500,7 +586,7 @@ impl_static_data_size!(
     f32::static_data_size() // delay
 );
 impl Writable for XAPR {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -528,6 +614,7 @@ pub struct XESP {
 }
 impl_from_field!(XESP, [parent: FormId, flags: XESPFlags]);
 impl_static_type_named!(XESP, b"XESP");
+impl_formid_visitor!(XESP, [parent]);
 impl_static_data_size!(
     XESP,
     FIELDH_SIZE +
@@ -535,7 +622,7 @@ impl_static_data_size!(
     XESPFlags::static_data_size()
 );
 impl Writable for XESP {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -572,7 +659,7 @@ impl Parse<'_> for XESPFlags {
 }
 impl_static_data_size!(XESPFlags, u32::static_data_size());
 impl Writable for XESPFlags {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -600,6 +687,7 @@ pub struct XLKR {
 }
 impl_from_field!(XLKR, [keyword: FormId, reference: FormId]);
 impl_static_type_named!(XLKR, b"XLKR");
+impl_formid_visitor!(XLKR, [keyword, reference]);
 impl_static_data_size!(
     XLKR,
     FIELDH_SIZE +
@@ -607,7 +695,7 @@ impl_static_data_size!(
     FormId::static_data_size() // reference
 );
 impl Writable for XLKR {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -626,6 +714,7 @@ make_formid_field!(XLRL);
 
 make_single_value_field!([Debug, Copy, Clone, PartialEq], XSCL, scale, f32);
 impl_from_field!(XSCL, [scale: f32]);
+impl_no_formids!(XSCL);
 
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub struct DATA {
@@ -637,6 +726,7 @@ pub struct DATA {
 }
 impl_from_field!(DATA, [position: Position3<f32>, rotation: Position3<f32>]);
 impl_static_type_named!(DATA, b"DATA");
+impl_no_formids!(DATA);
 impl_static_data_size!(
     DATA,
     FIELDH_SIZE +
@@ -644,7 +734,7 @@ impl_static_data_size!(
     Position3::<f32>::static_data_size() // rotation
 );
 impl Writable for DATA {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
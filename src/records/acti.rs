@@ -1,7 +1,7 @@
 use super::{
     common::{
-        lstring::LString, CommonRecordInfo, FromRecord, FromRecordError, GeneralRecord, Index,
-        StaticTypeNamed, TypeNamed,
+        lstring::LString, optional_editor_id, CommonRecordInfo, FormId, FormIdVisitor, FromRecord,
+        FromRecordError, GeneralRecord, Index, StaticTypeNamed, TypeNamed,
     },
     fields::{
         common::{object, rgbu, GeneralField},
@@ -9,10 +9,13 @@ use super::{
     },
 };
 use crate::{
-    collect_one, collect_one_collection, dispatch_all, impl_from_field, impl_static_type_named,
-    make_formid_field, make_single_value_field,
+    collect_one, collect_one_collection,
+    context::ParseContext,
+    dispatch_all, impl_formid_visitor, impl_from_field, impl_keyworded, impl_lstring_field,
+    impl_no_formids, impl_redecode_unknowns, impl_static_type_named, make_formid_field,
+    make_single_value_field,
     parse::PResult,
-    util::{DataSize, Writable},
+    util::{record_data_size, DataSize, Writable, WriteError},
 };
 use bstr::BStr;
 use derive_more::From;
@@ -23,7 +26,7 @@ pub struct ACTIRecord<'data> {
     pub common: CommonRecordInfo,
 
     /// EDID
-    pub editor_id_index: Index,
+    pub editor_id_index: Option<Index>,
     /// VMAD
     pub script_index: Option<Index>,
     /// OBND
@@ -34,7 +37,7 @@ pub struct ACTIRecord<'data> {
     pub model_collection_index: Option<Index>,
     /// DESTCollection
     pub destruction_collection_index: Option<Index>,
-    /// KWDACollection
+    /// KWDACollection. The first one, if there are several
     pub keyword_data_index: Option<Index>,
     /// PNAM
     pub marker_color_index: Option<Index>,
@@ -54,7 +57,10 @@ pub struct ACTIRecord<'data> {
     pub fields: Vec<ACTIField<'data>>,
 }
 impl<'data> FromRecord<'data> for ACTIRecord<'data> {
-    fn from_record(record: GeneralRecord<'data>) -> PResult<Self, FromRecordError> {
+    fn from_record(
+        record: GeneralRecord<'data>,
+        ctx: &mut ParseContext,
+    ) -> PResult<'data, Self, FromRecordError<'data>> {
         let mut edid_index = None;
         let mut vmad_index = None;
         let mut obnd_index = None;
@@ -75,28 +81,28 @@ impl<'data> FromRecord<'data> for ACTIRecord<'data> {
         let mut field_iter = record.fields.into_iter().peekable();
         while let Some(field) = field_iter.next() {
             match field.type_name.as_ref() {
-                b"EDID" => collect_one!(edid::EDID, field => fields; edid_index),
+                b"EDID" => collect_one!(edid::EDID, field => fields; edid_index, ctx),
                 b"VMAD" => {
-                    collect_one!(vmad::VMAD<'data, vmad::NoFragments>, field => fields; vmad_index)
+                    collect_one!(vmad::VMAD<'data, vmad::NoFragments>, field => fields; vmad_index, ctx)
                 }
-                b"OBND" => collect_one!(obnd::OBND, field => fields; obnd_index),
-                b"FULL" => collect_one!(object::FULL, field => fields; full_index),
+                b"OBND" => collect_one!(obnd::OBND, field => fields; obnd_index, ctx),
+                b"FULL" => collect_one!(object::FULL<'data>, field => fields; full_index, ctx),
                 b"MODL" => {
-                    collect_one_collection!(modl::MODL, modl::MODLCollection; field, field_iter => fields; modl_collection_index)
+                    collect_one_collection!(modl::MODL, modl::MODLCollection; field, field_iter => fields; modl_collection_index, ctx)
                 }
                 b"DEST" => {
-                    collect_one_collection!(dest::DEST, dest::DESTCollection; field, field_iter => fields; dest_collection_index)
+                    collect_one_collection!(dest::DEST, dest::DESTCollection; field, field_iter => fields; dest_collection_index, ctx)
                 }
                 b"KSIZ" => {
-                    collect_one_collection!(kwda::KSIZ, kwda::KWDACollection; field, field_iter => fields; keyword_data_index)
+                    collect_one_collection!(repeatable: kwda::KSIZ, kwda::KWDACollection; field, field_iter => fields; keyword_data_index)
                 }
-                b"PNAM" => collect_one!(PNAM, field => fields; pnam_index),
-                b"SNAM" => collect_one!(SNAM, field => fields; snam_index),
-                b"VNAM" => collect_one!(VNAM, field => fields; vnam_index),
-                b"WNAM" => collect_one!(WNAM, field => fields; wnam_index),
-                b"RNAM" => collect_one!(RNAM, field => fields; rnam_index),
-                b"FNAM" => collect_one!(FNAM, field => fields; fnam_index),
-                b"KNAM" => collect_one!(KNAM, field => fields; knam_index),
+                b"PNAM" => collect_one!(PNAM, field => fields; pnam_index, ctx),
+                b"SNAM" => collect_one!(SNAM, field => fields; snam_index, ctx),
+                b"VNAM" => collect_one!(VNAM, field => fields; vnam_index, ctx),
+                b"WNAM" => collect_one!(WNAM, field => fields; wnam_index, ctx),
+                b"RNAM" => collect_one!(RNAM, field => fields; rnam_index, ctx),
+                b"FNAM" => collect_one!(FNAM, field => fields; fnam_index, ctx),
+                b"KNAM" => collect_one!(KNAM, field => fields; knam_index, ctx),
                 _ => {
                     println!("Unknown field name: {}", field.type_name());
                     fields.push(ACTIField::Unknown(field));
@@ -104,8 +110,7 @@ impl<'data> FromRecord<'data> for ACTIRecord<'data> {
             }
         }
 
-        let edid_index = edid_index
-            .ok_or_else(|| FromRecordError::ExpectedField(edid::EDID::static_type_name()))?;
+        let edid_index = optional_editor_id(edid_index, ctx)?;
         let obnd_index = obnd_index
             .ok_or_else(|| FromRecordError::ExpectedField(obnd::OBND::static_type_name()))?;
 
@@ -135,6 +140,9 @@ impl<'data> FromRecord<'data> for ACTIRecord<'data> {
     }
 }
 impl_static_type_named!(ACTIRecord<'_>, b"ACTI");
+impl_redecode_unknowns!(ACTIRecord, ACTIField);
+impl_formid_visitor!(ACTIRecord<'_>, [fields]);
+impl_keyworded!(ACTIRecord, ACTIField);
 impl<'data> DataSize for ACTIRecord<'data> {
     fn data_size(&self) -> usize {
         self.type_name().data_size() +
@@ -144,13 +152,12 @@ impl<'data> DataSize for ACTIRecord<'data> {
     }
 }
 impl<'data> Writable for ACTIRecord<'data> {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
         self.type_name().write_to(w)?;
-        // TODO: assert size fits within
-        (self.fields.data_size() as u32).write_to(w)?;
+        record_data_size(self.type_name(), self.fields.data_size())?.write_to(w)?;
         self.common.write_to(w)?;
         self.fields.write_to(w)
     }
@@ -161,7 +168,7 @@ pub enum ACTIField<'data> {
     EDID(edid::EDID<'data>),
     VMAD(vmad::VMAD<'data, vmad::NoFragments>),
     OBND(obnd::OBND),
-    FULL(object::FULL),
+    FULL(object::FULL<'data>),
     MODLCollection(modl::MODLCollection<'data>),
     DESTCollection(dest::DESTCollection<'data>),
     KWDACollection(kwda::KWDACollection),
@@ -169,7 +176,7 @@ pub enum ACTIField<'data> {
     SNAM(SNAM),
     VNAM(VNAM),
     WNAM(WNAM),
-    RNAM(RNAM),
+    RNAM(RNAM<'data>),
     FNAM(FNAM),
     KNAM(KNAM),
     Unknown(GeneralField<'data>),
@@ -201,6 +208,89 @@ impl<'data> TypeNamed<'data> for ACTIField<'data> {
         )
     }
 }
+impl FormIdVisitor for ACTIField<'_> {
+    fn visit_formids(&mut self, visit: &mut dyn FnMut(&mut FormId)) {
+        dispatch_all!(
+            ACTIField,
+            self,
+            [
+                EDID,
+                VMAD,
+                OBND,
+                FULL,
+                MODLCollection,
+                DESTCollection,
+                KWDACollection,
+                PNAM,
+                SNAM,
+                VNAM,
+                WNAM,
+                RNAM,
+                FNAM,
+                KNAM,
+                Unknown
+            ],
+            x,
+            { x.visit_formids(visit) }
+        )
+    }
+
+    fn for_each_formid(&self, visit: &mut dyn FnMut(FormId)) {
+        dispatch_all!(
+            ACTIField,
+            self,
+            [
+                EDID,
+                VMAD,
+                OBND,
+                FULL,
+                MODLCollection,
+                DESTCollection,
+                KWDACollection,
+                PNAM,
+                SNAM,
+                VNAM,
+                WNAM,
+                RNAM,
+                FNAM,
+                KNAM,
+                Unknown
+            ],
+            x,
+            { x.for_each_formid(visit) }
+        )
+    }
+
+    fn for_each_field_formid<'name>(
+        &self,
+        name: &'name BStr,
+        visit: &mut dyn FnMut(&'name BStr, FormId),
+    ) {
+        dispatch_all!(
+            ACTIField,
+            self,
+            [
+                EDID,
+                VMAD,
+                OBND,
+                FULL,
+                MODLCollection,
+                DESTCollection,
+                KWDACollection,
+                PNAM,
+                SNAM,
+                VNAM,
+                WNAM,
+                RNAM,
+                FNAM,
+                KNAM,
+                Unknown
+            ],
+            x,
+            { x.for_each_field_formid(name, visit) }
+        )
+    }
+}
 impl<'data> DataSize for ACTIField<'data> {
     fn data_size(&self) -> usize {
         dispatch_all!(
@@ -229,7 +319,7 @@ impl<'data> DataSize for ACTIField<'data> {
     }
 }
 impl<'data> Writable for ACTIField<'data> {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -261,6 +351,7 @@ impl<'data> Writable for ACTIField<'data> {
 
 make_single_value_field!([Debug, Copy, Clone, Eq, PartialEq], PNAM, color, rgbu::RGBU);
 impl_from_field!(PNAM, [color: rgbu::RGBU]);
+impl_no_formids!(PNAM);
 
 make_formid_field!(
     /// ->SNDR uesp: 'nirnroot has the wow-wow sound here' (quality comment, I approve)
@@ -282,9 +373,11 @@ make_single_value_field!(
     RNAM,
     /// Verb string. Activate text override. Such as "Mine" or "Place" instead of "Activate"
     verb,
-    LString
+    LString,
+    'data
 );
-impl_from_field!(RNAM, [verb: LString]);
+impl_lstring_field!(RNAM, verb);
+impl_no_formids!(RNAM<'_>);
 
 make_single_value_field!(
     /// Flags
@@ -297,6 +390,7 @@ make_single_value_field!(
     u16
 );
 impl_from_field!(FNAM, [flags: u16]);
+impl_no_formids!(FNAM);
 
 make_formid_field!(
     /// ->KWYD form id for interaction purposes (??? What)
@@ -1,15 +1,17 @@
 use super::{
-    common::{self, CommonRecordInfo, GeneralRecord, Index},
+    common::{self, CommonRecordInfo, FormId, FormIdVisitor, GeneralRecord, Index},
     fields::{
         common::{write_field_header, GeneralField, FIELDH_SIZE},
         edid, modl, obnd,
     },
 };
 use crate::{
-    collect_one, collect_one_collection, dispatch_all, impl_from_field, impl_static_data_size,
-    impl_static_type_named, make_formid_field, make_single_value_field,
+    collect_one, collect_one_collection,
+    context::ParseContext,
+    dispatch_all, impl_formid_visitor, impl_from_field, impl_no_formids, impl_redecode_unknowns,
+    impl_static_data_size, impl_static_type_named, make_formid_field, make_single_value_field,
     parse::PResult,
-    util::{DataSize, Writable},
+    util::{record_data_size, DataSize, Writable, WriteError},
 };
 use bstr::BStr;
 use common::{FromRecord, FromRecordError, TypeNamed};
@@ -40,7 +42,10 @@ pub struct ADDNRecord<'data> {
     pub fields: Vec<ADDNField<'data>>,
 }
 impl<'data> FromRecord<'data> for ADDNRecord<'data> {
-    fn from_record(record: GeneralRecord<'data>) -> PResult<Self, FromRecordError> {
+    fn from_record(
+        record: GeneralRecord<'data>,
+        ctx: &mut ParseContext,
+    ) -> PResult<'data, Self, FromRecordError<'data>> {
         let mut edid_index = None;
         let mut obnd_index = None;
         let mut modl_collection_index = None;
@@ -53,15 +58,15 @@ impl<'data> FromRecord<'data> for ADDNRecord<'data> {
 
         while let Some(field) = field_iter.next() {
             match field.type_name.as_ref() {
-                b"EDID" => collect_one!(edid::EDID, field => fields; edid_index),
-                b"OBND" => collect_one!(obnd::OBND, field => fields; obnd_index),
+                b"EDID" => collect_one!(edid::EDID, field => fields; edid_index, ctx),
+                b"OBND" => collect_one!(obnd::OBND, field => fields; obnd_index, ctx),
                 b"MODL" => {
-                    collect_one_collection!(modl::MODL, modl::MODLCollection; field, field_iter => fields; modl_collection_index)
+                    collect_one_collection!(modl::MODL, modl::MODLCollection; field, field_iter => fields; modl_collection_index, ctx)
                 }
-                //b"MODT" => collect_one!(modl::MODT, field => fields; modt_index),
-                b"DATA" => collect_one!(DATA, field => fields; data_index),
-                b"SNAM" => collect_one!(SNAM, field => fields; snam_index),
-                b"DNAM" => collect_one!(DNAM, field => fields; dnam_index),
+                //b"MODT" => collect_one!(modl::MODT, field => fields; modt_index, ctx),
+                b"DATA" => collect_one!(DATA, field => fields; data_index, ctx),
+                b"SNAM" => collect_one!(SNAM, field => fields; snam_index, ctx),
+                b"DNAM" => collect_one!(DNAM, field => fields; dnam_index, ctx),
                 _ => fields.push(ADDNField::Unknown(field)),
             }
         }
@@ -82,6 +87,8 @@ impl<'data> FromRecord<'data> for ADDNRecord<'data> {
     }
 }
 impl_static_type_named!(ADDNRecord<'_>, b"ADDN");
+impl_redecode_unknowns!(ADDNRecord, ADDNField);
+impl_formid_visitor!(ADDNRecord<'_>, [fields]);
 impl<'data> DataSize for ADDNRecord<'data> {
     fn data_size(&self) -> usize {
         self.type_name().data_size() +
@@ -91,13 +98,12 @@ impl<'data> DataSize for ADDNRecord<'data> {
     }
 }
 impl<'data> Writable for ADDNRecord<'data> {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
         self.type_name().write_to(w)?;
-        // TODO: assert size fits within
-        (self.fields.data_size() as u32).write_to(w)?;
+        record_data_size(self.type_name(), self.fields.data_size())?.write_to(w)?;
         self.common.write_to(w)?;
         self.fields.write_to(w)
     }
@@ -124,6 +130,41 @@ impl<'data> TypeNamed<'data> for ADDNField<'data> {
         )
     }
 }
+impl FormIdVisitor for ADDNField<'_> {
+    fn visit_formids(&mut self, visit: &mut dyn FnMut(&mut FormId)) {
+        dispatch_all!(
+            ADDNField,
+            self,
+            [EDID, OBND, MODLCollection, DATA, SNAM, DNAM, Unknown],
+            x,
+            { x.visit_formids(visit) }
+        )
+    }
+
+    fn for_each_formid(&self, visit: &mut dyn FnMut(FormId)) {
+        dispatch_all!(
+            ADDNField,
+            self,
+            [EDID, OBND, MODLCollection, DATA, SNAM, DNAM, Unknown],
+            x,
+            { x.for_each_formid(visit) }
+        )
+    }
+
+    fn for_each_field_formid<'name>(
+        &self,
+        name: &'name BStr,
+        visit: &mut dyn FnMut(&'name BStr, FormId),
+    ) {
+        dispatch_all!(
+            ADDNField,
+            self,
+            [EDID, OBND, MODLCollection, DATA, SNAM, DNAM, Unknown],
+            x,
+            { x.for_each_field_formid(name, visit) }
+        )
+    }
+}
 impl<'data> DataSize for ADDNField<'data> {
     fn data_size(&self) -> usize {
         dispatch_all!(
@@ -136,7 +177,7 @@ impl<'data> DataSize for ADDNField<'data> {
     }
 }
 impl<'data> Writable for ADDNField<'data> {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -159,6 +200,7 @@ make_single_value_field!(
     u32
 );
 impl_from_field!(DATA, [addon_node_index: u32]);
+impl_no_formids!(DATA);
 
 make_formid_field!(
     /// FormId for a SOUN record
@@ -185,6 +227,7 @@ impl DNAM {
 }
 impl_from_field!(DNAM, [master_particle_system_cap: u16, flags: u16]);
 impl_static_type_named!(DNAM, b"DNAM");
+impl_no_formids!(DNAM);
 impl_static_data_size!(
     DNAM,
     FIELDH_SIZE +
@@ -192,7 +235,7 @@ impl_static_data_size!(
     u16::static_data_size() // flags
 );
 impl Writable for DNAM {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -1,7 +1,7 @@
 use super::{
     common::{
-        get_field, CommonRecordInfo, FormId, FromRecord, FromRecordError, GeneralRecord, Index,
-        StaticTypeNamed, TypeNamed,
+        get_field, optional_editor_id, CommonRecordInfo, FormId, FormIdVisitor, FromRecord,
+        FromRecordError, GeneralRecord, Index, StaticTypeNamed, TypeNamed,
     },
     fields::{
         common::{
@@ -12,10 +12,13 @@ use super::{
     },
 };
 use crate::{
-    collect_one, collect_one_collection, dispatch_all, impl_from_field, impl_static_data_size,
-    impl_static_type_named, make_formid_field, make_single_value_field,
+    collect_one, collect_one_collection,
+    context::ParseContext,
+    dispatch_all, impl_formid_visitor, impl_from_field, impl_keyworded, impl_no_formids,
+    impl_redecode_unknowns, impl_static_data_size, impl_static_type_named, make_formid_field,
+    make_single_value_field,
     parse::{PResult, Parse},
-    util::{DataSize, Writable},
+    util::{record_data_size, DataSize, Writable, WriteError},
 };
 use bstr::{BStr, ByteSlice};
 use derive_more::From;
@@ -25,12 +28,12 @@ use std::io::Write;
 pub struct ALCHRecord<'data> {
     pub common: CommonRecordInfo,
     /// EDID
-    pub editor_id_index: Index,
+    pub editor_id_index: Option<Index>,
     /// OBND
     pub object_bounds_index: Index,
     /// FULL. In game name
     pub full_name_index: Option<Index>,
-    /// KWDACollection
+    /// KWDACollection. The first one, if there are several
     pub keyword_collection_index: Option<Index>,
     /// MODLCollection
     pub model_collection_index: Option<Index>,
@@ -50,7 +53,10 @@ pub struct ALCHRecord<'data> {
     pub fields: Vec<ALCHField<'data>>,
 }
 impl<'data> FromRecord<'data> for ALCHRecord<'data> {
-    fn from_record(record: GeneralRecord<'data>) -> PResult<Self, FromRecordError<'data>> {
+    fn from_record(
+        record: GeneralRecord<'data>,
+        ctx: &mut ParseContext,
+    ) -> PResult<'data, Self, FromRecordError<'data>> {
         let mut editor_id_index = None;
         let mut object_bounds_index = None;
         let mut full_name_index = None;
@@ -68,29 +74,28 @@ impl<'data> FromRecord<'data> for ALCHRecord<'data> {
 
         while let Some(field) = field_iter.next() {
             match field.type_name.as_ref() {
-                b"EDID" => collect_one!(edid::EDID, field => fields; editor_id_index),
-                b"OBND" => collect_one!(obnd::OBND, field => fields; object_bounds_index),
-                b"FULL" => collect_one!(object::FULL, field => fields; full_name_index),
+                b"EDID" => collect_one!(edid::EDID, field => fields; editor_id_index, ctx),
+                b"OBND" => collect_one!(obnd::OBND, field => fields; object_bounds_index, ctx),
+                b"FULL" => collect_one!(object::FULL<'data>, field => fields; full_name_index, ctx),
                 b"KSIZ" => {
-                    collect_one_collection!(kwda::KSIZ, kwda::KWDACollection; field, field_iter => fields; keyword_collection_index)
+                    collect_one_collection!(repeatable: kwda::KSIZ, kwda::KWDACollection; field, field_iter => fields; keyword_collection_index)
                 }
                 b"MODL" => {
-                    collect_one_collection!(modl::MODL, modl::MODLCollection; field, field_iter => fields; model_collection_index)
+                    collect_one_collection!(modl::MODL, modl::MODLCollection; field, field_iter => fields; model_collection_index, ctx)
                 }
-                b"ICON" => collect_one!(item::ICON, field => fields; icon_index),
-                b"MICO" => collect_one!(item::MICO, field => fields; message_icon_index),
-                b"YNAM" => collect_one!(item::YNAM, field => fields; pickup_sound_index),
-                b"ZNAM" => collect_one!(item::ZNAM, field => fields; drop_sound_index),
-                b"DATA" => collect_one!(DATA, field => fields; weight_index),
+                b"ICON" => collect_one!(item::ICON, field => fields; icon_index, ctx),
+                b"MICO" => collect_one!(item::MICO, field => fields; message_icon_index, ctx),
+                b"YNAM" => collect_one!(item::YNAM, field => fields; pickup_sound_index, ctx),
+                b"ZNAM" => collect_one!(item::ZNAM, field => fields; drop_sound_index, ctx),
+                b"DATA" => collect_one!(DATA, field => fields; weight_index, ctx),
                 b"ENIT" => {
-                    collect_one_collection!(ENIT, EnchantedEffectCollection; field, field_iter => fields; enchanted_effect_collection_index)
+                    collect_one_collection!(ENIT, EnchantedEffectCollection; field, field_iter => fields; enchanted_effect_collection_index, ctx)
                 }
                 _ => fields.push(ALCHField::Unknown(field)),
             }
         }
 
-        let editor_id_index = editor_id_index
-            .ok_or_else(|| FromRecordError::ExpectedField(edid::EDID::static_type_name()))?;
+        let editor_id_index = optional_editor_id(editor_id_index, ctx)?;
         let object_bounds_index = object_bounds_index
             .ok_or_else(|| FromRecordError::ExpectedField(obnd::OBND::static_type_name()))?;
         let weight_index =
@@ -120,7 +125,32 @@ impl<'data> FromRecord<'data> for ALCHRecord<'data> {
         ))
     }
 }
+impl item::Item for ALCHRecord<'_> {
+    fn value(&self) -> Option<item::Gold> {
+        match &self.fields[self.enchanted_effect_collection_index] {
+            ALCHField::EnchantedEffectCollection(x) => {
+                Some(item::Gold::new(x.enchanted_item.potion_value))
+            }
+            _ => None,
+        }
+    }
+
+    fn weight(&self) -> Option<item::Weight> {
+        match &self.fields[self.weight_index] {
+            ALCHField::DATA(x) => Some(item::Weight::new(x.weight)),
+            _ => None,
+        }
+    }
+
+    /// There is no flag for this, as [record_flag::NON_PLAYABLE] is only known to be used by ARMO
+    fn is_playable(&self) -> bool {
+        true
+    }
+}
 impl_static_type_named!(ALCHRecord<'_>, b"ALCH");
+impl_redecode_unknowns!(ALCHRecord, ALCHField);
+impl_formid_visitor!(ALCHRecord<'_>, [fields]);
+impl_keyworded!(ALCHRecord, ALCHField);
 impl<'data> DataSize for ALCHRecord<'data> {
     fn data_size(&self) -> usize {
         self.type_name().data_size() +
@@ -130,13 +160,12 @@ impl<'data> DataSize for ALCHRecord<'data> {
     }
 }
 impl<'data> Writable for ALCHRecord<'data> {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
         self.type_name().write_to(w)?;
-        // TODO: assert size fits within
-        (self.fields.data_size() as u32).write_to(w)?;
+        record_data_size(self.type_name(), self.fields.data_size())?.write_to(w)?;
         self.common.write_to(w)?;
         self.fields.write_to(w)
     }
@@ -146,8 +175,8 @@ impl<'data> Writable for ALCHRecord<'data> {
 pub enum ALCHField<'data> {
     EDID(edid::EDID<'data>),
     OBND(obnd::OBND),
-    FULL(object::FULL),
-    // TODO: note: UESP says that there may be more than one KWDA entry? I didn't see that in a quick skim through a handful of skyrim entries
+    FULL(object::FULL<'data>),
+    // Note: UESP says that there may be more than one KWDA entry, so there may be multiple of these
     KWDACollection(kwda::KWDACollection),
     MODLCollection(modl::MODLCollection<'data>),
     ICON(item::ICON<'data>),
@@ -182,6 +211,80 @@ impl<'data> TypeNamed<'data> for ALCHField<'data> {
         )
     }
 }
+impl FormIdVisitor for ALCHField<'_> {
+    fn visit_formids(&mut self, visit: &mut dyn FnMut(&mut FormId)) {
+        dispatch_all!(
+            ALCHField,
+            self,
+            [
+                EDID,
+                OBND,
+                FULL,
+                KWDACollection,
+                MODLCollection,
+                ICON,
+                MICO,
+                YNAM,
+                ZNAM,
+                DATA,
+                EnchantedEffectCollection,
+                Unknown
+            ],
+            x,
+            { x.visit_formids(visit) }
+        )
+    }
+
+    fn for_each_formid(&self, visit: &mut dyn FnMut(FormId)) {
+        dispatch_all!(
+            ALCHField,
+            self,
+            [
+                EDID,
+                OBND,
+                FULL,
+                KWDACollection,
+                MODLCollection,
+                ICON,
+                MICO,
+                YNAM,
+                ZNAM,
+                DATA,
+                EnchantedEffectCollection,
+                Unknown
+            ],
+            x,
+            { x.for_each_formid(visit) }
+        )
+    }
+
+    fn for_each_field_formid<'name>(
+        &self,
+        name: &'name BStr,
+        visit: &mut dyn FnMut(&'name BStr, FormId),
+    ) {
+        dispatch_all!(
+            ALCHField,
+            self,
+            [
+                EDID,
+                OBND,
+                FULL,
+                KWDACollection,
+                MODLCollection,
+                ICON,
+                MICO,
+                YNAM,
+                ZNAM,
+                DATA,
+                EnchantedEffectCollection,
+                Unknown
+            ],
+            x,
+            { x.for_each_field_formid(name, visit) }
+        )
+    }
+}
 impl<'data> DataSize for ALCHField<'data> {
     fn data_size(&self) -> usize {
         dispatch_all!(
@@ -207,7 +310,7 @@ impl<'data> DataSize for ALCHField<'data> {
     }
 }
 impl<'data> Writable for ALCHField<'data> {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -242,6 +345,7 @@ make_single_value_field!(
     f32
 );
 impl_from_field!(DATA, [weight: f32]);
+impl_no_formids!(DATA);
 
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct ENIT {
@@ -264,6 +368,7 @@ impl_from_field!(
     ]
 );
 impl_static_type_named!(ENIT, b"ENIT");
+impl_formid_visitor!(ENIT, [addiction, use_sound]);
 impl_static_data_size!(
     ENIT,
     FIELDH_SIZE +
@@ -274,7 +379,7 @@ impl_static_data_size!(
 	FormId::static_data_size() // use sound
 );
 impl Writable for ENIT {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -320,7 +425,7 @@ impl Parse<'_> for ENITFlags {
 }
 impl_static_data_size!(ENITFlags, u32::static_data_size());
 impl Writable for ENITFlags {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -346,12 +451,13 @@ pub struct EFIT {
 // magnitude < 1 becomes 1
 impl_from_field!(EFIT, [magnitude: f32, area_of_effect: u32, duration: u32]);
 impl_static_type_named!(EFIT, b"EFIT");
+impl_no_formids!(EFIT);
 impl_static_data_size!(
     EFIT,
     FIELDH_SIZE + f32::static_data_size() + u32::static_data_size() + u32::static_data_size()
 );
 impl Writable for EFIT {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -408,6 +514,10 @@ impl<'data> CollectField<'data, ENIT> for EnchantedEffectCollection {
     }
 }
 impl_static_type_named!(EnchantedEffectCollection, ENIT::static_type_name());
+impl_formid_visitor!(
+    EnchantedEffectCollection,
+    [enchanted_item: ENIT, effect_id: EFID, conditions: ctda::CTDA]
+);
 impl DataSize for EnchantedEffectCollection {
     fn data_size(&self) -> usize {
         self.enchanted_item.data_size()
@@ -417,7 +527,7 @@ impl DataSize for EnchantedEffectCollection {
     }
 }
 impl Writable for EnchantedEffectCollection {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -440,7 +550,7 @@ mod tests {
         let body = assert_size_output!(data);
         assert_eq!(&body[..4], b"DATA");
         assert_eq!(body[4..6], 4u16.to_le_bytes());
-        assert_eq!(body[6..], (weight as f32).to_le_bytes());
+        assert_eq!(body[6..], weight.to_le_bytes());
     }
 
     #[test]
@@ -486,7 +596,7 @@ mod tests {
     fn test_alch_record() {
         let alch = ALCHRecord {
             common: CommonRecordInfo::test_default(),
-            editor_id_index: 0,
+            editor_id_index: Some(0),
             object_bounds_index: 1,
             full_name_index: None,
             keyword_collection_index: None,
@@ -1,7 +1,7 @@
 use super::{
     common::{
-        full_string, CommonRecordInfo, FormId, FromRecord, FromRecordError, GeneralRecord, Index,
-        StaticTypeNamed, TypeNamed,
+        full_string, optional_editor_id, CommonRecordInfo, FormId, FormIdVisitor, FromRecord,
+        FromRecordError, GeneralRecord, Index, StaticTypeNamed, TypeNamed,
     },
     fields::{
         common::{
@@ -11,10 +11,12 @@ use super::{
     },
 };
 use crate::{
-    collect_one, collect_one_collection, dispatch_all, impl_static_data_size,
-    impl_static_type_named, make_single_value_field,
+    collect_one, collect_one_collection,
+    context::ParseContext,
+    dispatch_all, impl_formid_visitor, impl_keyworded, impl_no_formids, impl_redecode_unknowns,
+    impl_static_data_size, impl_static_type_named, make_single_value_field,
     parse::{PResult, Parse, ParseError},
-    util::{DataSize, Writable},
+    util::{record_data_size, DataSize, StaticDataSize, Writable, WriteError},
 };
 use bstr::BStr;
 use derive_more::From;
@@ -26,7 +28,7 @@ pub struct AMMORecord<'data> {
     pub common: CommonRecordInfo,
 
     /// EDID
-    pub editor_id_index: Index,
+    pub editor_id_index: Option<Index>,
     /// OBND
     pub object_bounds_index: Index,
     /// FULL
@@ -48,7 +50,7 @@ pub struct AMMORecord<'data> {
     pub drop_sound_index: Option<Index>,
     /// DESC
     pub description_index: Option<Index>,
-    /// KWDACollection
+    /// KWDACollection. The first one, if there are several
     pub keyword_collection_index: Option<Index>,
     /// DATA
     pub data_index: Option<Index>,
@@ -58,7 +60,10 @@ pub struct AMMORecord<'data> {
     pub fields: Vec<AMMOField<'data>>,
 }
 impl<'data> FromRecord<'data> for AMMORecord<'data> {
-    fn from_record(record: GeneralRecord<'data>) -> PResult<Self, FromRecordError<'data>> {
+    fn from_record(
+        record: GeneralRecord<'data>,
+        ctx: &mut ParseContext,
+    ) -> PResult<'data, Self, FromRecordError<'data>> {
         let mut editor_id_index = None;
         let mut object_bounds_index = None;
         let mut item_name_index = None;
@@ -78,31 +83,30 @@ impl<'data> FromRecord<'data> for AMMORecord<'data> {
 
         while let Some(field) = field_iter.next() {
             match field.type_name.as_ref() {
-                b"EDID" => collect_one!(edid::EDID, field => fields; editor_id_index),
-                b"OBND" => collect_one!(obnd::OBND, field => fields; object_bounds_index),
-                b"FULL" => collect_one!(object::FULL, field => fields; item_name_index),
+                b"EDID" => collect_one!(edid::EDID, field => fields; editor_id_index, ctx),
+                b"OBND" => collect_one!(obnd::OBND, field => fields; object_bounds_index, ctx),
+                b"FULL" => collect_one!(object::FULL<'data>, field => fields; item_name_index, ctx),
                 b"MODL" => {
-                    collect_one_collection!(modl::MODL, modl::MODLCollection; field, field_iter => fields; model_collection_index)
+                    collect_one_collection!(modl::MODL, modl::MODLCollection; field, field_iter => fields; model_collection_index, ctx)
                 }
-                b"ICON" => collect_one!(item::ICON, field => fields; inventory_image_index),
-                b"MICO" => collect_one!(item::MICO, field => fields; message_image_index),
+                b"ICON" => collect_one!(item::ICON, field => fields; inventory_image_index, ctx),
+                b"MICO" => collect_one!(item::MICO, field => fields; message_image_index, ctx),
                 b"DEST" => {
-                    collect_one_collection!(dest::DEST, dest::DESTCollection; field, field_iter => fields; destruction_collection_index)
+                    collect_one_collection!(dest::DEST, dest::DESTCollection; field, field_iter => fields; destruction_collection_index, ctx)
                 }
-                b"YNAM" => collect_one!(item::YNAM, field => fields; pickup_sound_index),
-                b"ZNAM" => collect_one!(item::ZNAM, field => fields; drop_sound_index),
-                b"DESC" => collect_one!(item::DESC, field => fields; description_index),
+                b"YNAM" => collect_one!(item::YNAM, field => fields; pickup_sound_index, ctx),
+                b"ZNAM" => collect_one!(item::ZNAM, field => fields; drop_sound_index, ctx),
+                b"DESC" => collect_one!(item::DESC<'data>, field => fields; description_index, ctx),
                 b"KSIZ" => {
-                    collect_one_collection!(kwda::KSIZ, kwda::KWDACollection; field, field_iter => fields; keyword_collection_index)
+                    collect_one_collection!(repeatable: kwda::KSIZ, kwda::KWDACollection; field, field_iter => fields; keyword_collection_index)
                 }
-                b"DATA" => collect_one!(DATA, field => fields; data_index),
-                b"ONAM" => collect_one!(ONAM, field => fields; short_name_index),
+                b"DATA" => collect_one!(DATA, field => fields; data_index, ctx),
+                b"ONAM" => collect_one!(ONAM, field => fields; short_name_index, ctx),
                 _ => fields.push(AMMOField::Unknown(field)),
             }
         }
 
-        let editor_id_index = editor_id_index
-            .ok_or_else(|| FromRecordError::ExpectedField(edid::EDID::static_type_name()))?;
+        let editor_id_index = optional_editor_id(editor_id_index, ctx)?;
         let object_bounds_index = object_bounds_index
             .ok_or_else(|| FromRecordError::ExpectedField(obnd::OBND::static_type_name()))?;
 
@@ -128,7 +132,37 @@ impl<'data> FromRecord<'data> for AMMORecord<'data> {
         ))
     }
 }
+impl AMMORecord<'_> {
+    fn data(&self) -> Option<&DATA> {
+        match self.data_index.map(|i| &self.fields[i]) {
+            Some(AMMOField::DATA(x)) => Some(x),
+            _ => None,
+        }
+    }
+}
+impl item::Item for AMMORecord<'_> {
+    fn value(&self) -> Option<item::Gold> {
+        self.data().map(|x| item::Gold::new(x.le().value))
+    }
+
+    fn weight(&self) -> Option<item::Weight> {
+        match self.data() {
+            Some(DATA::SE(x)) => Some(item::Weight::new(x.weight)),
+            _ => None,
+        }
+    }
+
+    fn is_playable(&self) -> bool {
+        !self
+            .data()
+            .map(|x| x.le().flags.non_playable())
+            .unwrap_or(false)
+    }
+}
 impl_static_type_named!(AMMORecord<'_>, b"AMMO");
+impl_redecode_unknowns!(AMMORecord, AMMOField);
+impl_formid_visitor!(AMMORecord<'_>, [fields]);
+impl_keyworded!(AMMORecord, AMMOField);
 impl<'data> DataSize for AMMORecord<'data> {
     fn data_size(&self) -> usize {
         self.type_name().data_size() +
@@ -138,13 +172,12 @@ impl<'data> DataSize for AMMORecord<'data> {
     }
 }
 impl<'data> Writable for AMMORecord<'data> {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
         self.type_name().write_to(w)?;
-        // TODO: assert size fits within
-        (self.fields.data_size() as u32).write_to(w)?;
+        record_data_size(self.type_name(), self.fields.data_size())?.write_to(w)?;
         self.common.write_to(w)?;
         self.fields.write_to(w)
     }
@@ -154,14 +187,14 @@ impl<'data> Writable for AMMORecord<'data> {
 pub enum AMMOField<'data> {
     EDID(edid::EDID<'data>),
     OBND(obnd::OBND),
-    FULL(object::FULL),
+    FULL(object::FULL<'data>),
     MODLCollection(modl::MODLCollection<'data>),
     ICON(item::ICON<'data>),
     MICO(item::MICO<'data>),
     DESTCollection(dest::DESTCollection<'data>),
     YNAM(item::YNAM),
     ZNAM(item::ZNAM),
-    DESC(item::DESC),
+    DESC(item::DESC<'data>),
     KWDACollection(kwda::KWDACollection),
     DATA(DATA),
     ONAM(ONAM<'data>),
@@ -193,6 +226,86 @@ impl<'data> TypeNamed<'data> for AMMOField<'data> {
         )
     }
 }
+impl FormIdVisitor for AMMOField<'_> {
+    fn visit_formids(&mut self, visit: &mut dyn FnMut(&mut FormId)) {
+        dispatch_all!(
+            AMMOField,
+            self,
+            [
+                EDID,
+                OBND,
+                FULL,
+                MODLCollection,
+                ICON,
+                MICO,
+                DESTCollection,
+                YNAM,
+                ZNAM,
+                DESC,
+                KWDACollection,
+                DATA,
+                ONAM,
+                Unknown
+            ],
+            x,
+            { x.visit_formids(visit) }
+        )
+    }
+
+    fn for_each_formid(&self, visit: &mut dyn FnMut(FormId)) {
+        dispatch_all!(
+            AMMOField,
+            self,
+            [
+                EDID,
+                OBND,
+                FULL,
+                MODLCollection,
+                ICON,
+                MICO,
+                DESTCollection,
+                YNAM,
+                ZNAM,
+                DESC,
+                KWDACollection,
+                DATA,
+                ONAM,
+                Unknown
+            ],
+            x,
+            { x.for_each_formid(visit) }
+        )
+    }
+
+    fn for_each_field_formid<'name>(
+        &self,
+        name: &'name BStr,
+        visit: &mut dyn FnMut(&'name BStr, FormId),
+    ) {
+        dispatch_all!(
+            AMMOField,
+            self,
+            [
+                EDID,
+                OBND,
+                FULL,
+                MODLCollection,
+                ICON,
+                MICO,
+                DESTCollection,
+                YNAM,
+                ZNAM,
+                DESC,
+                KWDACollection,
+                DATA,
+                ONAM,
+                Unknown
+            ],
+            x,
+            { x.for_each_field_formid(name, visit) }
+        )
+    }
+}
 impl<'data> DataSize for AMMOField<'data> {
     fn data_size(&self) -> usize {
         dispatch_all!(
@@ -220,7 +333,7 @@ impl<'data> DataSize for AMMOField<'data> {
     }
 }
 impl<'data> Writable for AMMOField<'data> {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -273,6 +386,7 @@ impl Parse<'_> for DATALegendaryEdition {
         ))
     }
 }
+impl_formid_visitor!(DATALegendaryEdition, [projectile_id]);
 impl_static_data_size!(
     DATALegendaryEdition,
     FormId::static_data_size() + // projectile id
@@ -281,7 +395,7 @@ impl_static_data_size!(
     u32::static_data_size() // value
 );
 impl Writable for DATALegendaryEdition {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -303,12 +417,13 @@ impl Parse<'_> for DATASpecialEdition {
         Ok((data, Self { le, weight }))
     }
 }
+impl_formid_visitor!(DATASpecialEdition, [le]);
 impl_static_data_size!(
     DATASpecialEdition,
     DATALegendaryEdition::static_data_size() + f32::static_data_size()
 );
 impl Writable for DATASpecialEdition {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -324,14 +439,32 @@ pub enum DATA {
     /// Special Edition, 20 byte struct
     SE(DATASpecialEdition),
 }
-impl FromField<'_> for DATA {
-    fn from_field(field: GeneralField<'_>) -> PResult<Self, FromFieldError> {
-        if field.data.len() == 16 {
-            let (data, le) = DATALegendaryEdition::parse(field.data)?;
-            Ok((data, DATA::LE(le)))
-        } else if field.data.len() == 20 {
-            let (data, se) = DATASpecialEdition::parse(field.data)?;
-            Ok((data, DATA::SE(se)))
+impl DATA {
+    /// The part that both layouts share
+    pub fn le(&self) -> &DATALegendaryEdition {
+        match self {
+            DATA::LE(x) => x,
+            DATA::SE(x) => &x.le,
+        }
+    }
+
+    fn parse_le(data: &[u8]) -> PResult<'_, DATA> {
+        let (data, le) = DATALegendaryEdition::parse(data)?;
+        Ok((data, DATA::LE(le)))
+    }
+
+    fn parse_se(data: &[u8]) -> PResult<'_, DATA> {
+        let (data, se) = DATASpecialEdition::parse(data)?;
+        Ok((data, DATA::SE(se)))
+    }
+}
+impl<'data> FromField<'data> for DATA {
+    /// Without knowing the form version, the layout is guessed from the size
+    fn from_field(field: GeneralField<'data>) -> PResult<'data, Self, FromFieldError<'data>> {
+        if field.data.len() == DATALegendaryEdition::static_data_size() {
+            Ok(DATA::parse_le(field.data)?)
+        } else if field.data.len() == DATASpecialEdition::static_data_size() {
+            Ok(DATA::parse_se(field.data)?)
         } else {
             Err(FromFieldError::ParseError(ParseError::InvalidByteCount {
                 // expected: 16 | 20
@@ -339,8 +472,51 @@ impl FromField<'_> for DATA {
             }))
         }
     }
+
+    fn from_field_ctx(
+        field: GeneralField<'data>,
+        ctx: &mut ParseContext,
+    ) -> PResult<'data, Self, FromFieldError<'data>> {
+        let special_edition = match ctx.is_special_edition() {
+            Some(special_edition) => special_edition,
+            None => return DATA::from_field(field),
+        };
+        let expected = if special_edition {
+            DATASpecialEdition::static_data_size()
+        } else {
+            DATALegendaryEdition::static_data_size()
+        };
+        if field.data.len() != expected {
+            return Err(ParseError::ExpectedExact {
+                expected,
+                found: field.data.len(),
+            }
+            .into());
+        }
+
+        if special_edition {
+            Ok(DATA::parse_se(field.data)?)
+        } else {
+            Ok(DATA::parse_le(field.data)?)
+        }
+    }
 }
 impl_static_type_named!(DATA, b"DATA");
+impl FormIdVisitor for DATA {
+    fn visit_formids(&mut self, visit: &mut dyn FnMut(&mut FormId)) {
+        match self {
+            DATA::LE(x) => x.visit_formids(visit),
+            DATA::SE(x) => x.visit_formids(visit),
+        }
+    }
+
+    fn for_each_formid(&self, visit: &mut dyn FnMut(FormId)) {
+        match self {
+            DATA::LE(x) => x.for_each_formid(visit),
+            DATA::SE(x) => x.for_each_formid(visit),
+        }
+    }
+}
 impl DataSize for DATA {
     fn data_size(&self) -> usize {
         FIELDH_SIZE
@@ -351,7 +527,7 @@ impl DataSize for DATA {
     }
 }
 impl Writable for DATA {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -397,7 +573,7 @@ impl Parse<'_> for DATAFlags {
 }
 impl_static_data_size!(DATAFlags, u32::static_data_size());
 impl Writable for DATAFlags {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -409,11 +585,12 @@ make_single_value_field!(
     /// UESP says this exists, but I haven't seen it in either Skyrim.esm or Dawnguard.esm
     [Debug, Clone, Eq, PartialEq], ONAM, short_name, FullString, 'data);
 impl<'data> FromField<'data> for ONAM<'data> {
-    fn from_field(field: GeneralField<'data>) -> PResult<'data, Self, FromFieldError> {
+    fn from_field(field: GeneralField<'data>) -> PResult<'data, Self, FromFieldError<'data>> {
         let (data, short_name) = FullString::parse(field.data)?;
         Ok((data, Self { short_name }))
     }
 }
+impl_no_formids!(ONAM<'_>);
 
 #[cfg(test)]
 mod tests {
@@ -435,4 +612,30 @@ mod tests {
         let data_se = DATA::SE(DATASpecialEdition { le, weight: 0.1 });
         assert_size_output!(data_se);
     }
+
+    #[test]
+    fn test_data_form_version() {
+        use crate::context::{FORM_VERSION_LE, FORM_VERSION_SE};
+        use bstr::ByteSlice;
+        let data = [0u8; 16];
+        let field = GeneralField::new(b"DATA".as_bstr(), &data);
+
+        // Unknown form version, so guess by size
+        let mut ctx = ParseContext::default();
+        let (_, value) = DATA::from_field_ctx(field.clone(), &mut ctx).unwrap();
+        assert!(matches!(value, DATA::LE(_)));
+
+        ctx.form_version = Some(FORM_VERSION_LE);
+        let (_, value) = DATA::from_field_ctx(field.clone(), &mut ctx).unwrap();
+        assert!(matches!(value, DATA::LE(_)));
+
+        ctx.form_version = Some(FORM_VERSION_SE);
+        assert_eq!(
+            DATA::from_field_ctx(field, &mut ctx),
+            Err(FromFieldError::ParseError(ParseError::ExpectedExact {
+                expected: 20,
+                found: 16
+            }))
+        );
+    }
 }
@@ -1,15 +1,17 @@
 use super::{
     common::{
-        CommonRecordInfo, FromRecord, FromRecordError, GeneralRecord, Index, NullTerminatedString,
-        StaticTypeNamed, TypeNamed,
+        optional_editor_id, CommonRecordInfo, FormId, FormIdVisitor, FromRecord, FromRecordError,
+        GeneralRecord, Index, NullTerminatedString, StaticTypeNamed, TypeNamed,
     },
     fields::{common::GeneralField, edid, modl},
 };
 use crate::{
-    collect_one, collect_one_collection, dispatch_all, impl_from_field, impl_static_type_named,
-    make_single_value_field,
+    collect_one, collect_one_collection,
+    context::ParseContext,
+    dispatch_all, impl_formid_visitor, impl_from_field, impl_no_formids, impl_redecode_unknowns,
+    impl_static_type_named, make_single_value_field,
     parse::PResult,
-    util::{DataSize, Writable},
+    util::{record_data_size, DataSize, Writable, WriteError},
 };
 use bstr::BStr;
 use derive_more::From;
@@ -17,14 +19,17 @@ use derive_more::From;
 #[derive(Debug, Clone, PartialEq)]
 pub struct ANIORecord<'data> {
     pub common: CommonRecordInfo,
-    pub editor_id_index: Index,
+    pub editor_id_index: Option<Index>,
     pub model_collection_index: Index,
     pub unload_event_index: Option<Index>,
 
     pub fields: Vec<ANIOField<'data>>,
 }
 impl<'data> FromRecord<'data> for ANIORecord<'data> {
-    fn from_record(record: GeneralRecord<'data>) -> PResult<Self, FromRecordError<'data>> {
+    fn from_record(
+        record: GeneralRecord<'data>,
+        ctx: &mut ParseContext,
+    ) -> PResult<'data, Self, FromRecordError<'data>> {
         let mut editor_id_index = None;
         let mut model_collection_index = None;
         let mut unload_event_index = None;
@@ -34,17 +39,16 @@ impl<'data> FromRecord<'data> for ANIORecord<'data> {
 
         while let Some(field) = field_iter.next() {
             match field.type_name().as_ref() {
-                b"EDID" => collect_one!(edid::EDID, field => fields; editor_id_index),
+                b"EDID" => collect_one!(edid::EDID, field => fields; editor_id_index, ctx),
                 b"MODL" => {
-                    collect_one_collection!(modl::MODL, modl::MODLCollection; field, field_iter => fields; model_collection_index)
+                    collect_one_collection!(modl::MODL, modl::MODLCollection; field, field_iter => fields; model_collection_index, ctx)
                 }
-                b"BNAM" => collect_one!(BNAM, field => fields; unload_event_index),
+                b"BNAM" => collect_one!(BNAM, field => fields; unload_event_index, ctx),
                 _ => fields.push(ANIOField::Unknown(field)),
             }
         }
 
-        let editor_id_index = editor_id_index
-            .ok_or_else(|| FromRecordError::ExpectedField(edid::EDID::static_type_name()))?;
+        let editor_id_index = optional_editor_id(editor_id_index, ctx)?;
         let model_collection_index = model_collection_index.ok_or_else(|| {
             FromRecordError::ExpectedField(modl::MODLCollection::static_type_name())
         })?;
@@ -62,6 +66,8 @@ impl<'data> FromRecord<'data> for ANIORecord<'data> {
     }
 }
 impl_static_type_named!(ANIORecord<'_>, b"ANIO");
+impl_redecode_unknowns!(ANIORecord, ANIOField);
+impl_formid_visitor!(ANIORecord<'_>, [fields]);
 impl DataSize for ANIORecord<'_> {
     fn data_size(&self) -> usize {
         self.type_name().data_size() +
@@ -72,13 +78,12 @@ impl DataSize for ANIORecord<'_> {
 }
 
 impl Writable for ANIORecord<'_> {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: std::io::Write,
     {
         self.type_name().write_to(w)?;
-        // TODO: assert that size fits within a u32
-        (self.fields.data_size() as u32).write_to(w)?;
+        record_data_size(self.type_name(), self.fields.data_size())?.write_to(w)?;
         self.common.write_to(w)?;
         self.fields.write_to(w)?;
         Ok(())
@@ -99,6 +104,29 @@ impl<'data> TypeNamed<'data> for ANIOField<'data> {
         })
     }
 }
+impl FormIdVisitor for ANIOField<'_> {
+    fn visit_formids(&mut self, visit: &mut dyn FnMut(&mut FormId)) {
+        dispatch_all!(ANIOField, self, [EDID, MODLCollection, BNAM, Unknown], x, {
+            x.visit_formids(visit)
+        })
+    }
+
+    fn for_each_formid(&self, visit: &mut dyn FnMut(FormId)) {
+        dispatch_all!(ANIOField, self, [EDID, MODLCollection, BNAM, Unknown], x, {
+            x.for_each_formid(visit)
+        })
+    }
+
+    fn for_each_field_formid<'name>(
+        &self,
+        name: &'name BStr,
+        visit: &mut dyn FnMut(&'name BStr, FormId),
+    ) {
+        dispatch_all!(ANIOField, self, [EDID, MODLCollection, BNAM, Unknown], x, {
+            x.for_each_field_formid(name, visit)
+        })
+    }
+}
 impl DataSize for ANIOField<'_> {
     fn data_size(&self) -> usize {
         dispatch_all!(ANIOField, self, [EDID, MODLCollection, BNAM, Unknown], x, {
@@ -107,7 +135,7 @@ impl DataSize for ANIOField<'_> {
     }
 }
 impl Writable for ANIOField<'_> {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: std::io::Write,
     {
@@ -126,3 +154,36 @@ make_single_value_field!(
     'data
 );
 impl_from_field!(BNAM, 'data, [unload_event: NullTerminatedString]);
+impl_no_formids!(BNAM<'_>);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::ParseOptions;
+    use bstr::ByteSlice;
+
+    #[test]
+    fn test_missing_editor_id() {
+        let record = GeneralRecord {
+            type_name: b"ANIO".as_bstr(),
+            common: CommonRecordInfo::test_default(),
+            fields: vec![GeneralField::new(b"MODL".as_bstr(), b"a.nif\0")],
+        };
+
+        let (_, anio) = ANIORecord::from_record(record.clone(), &mut ParseContext::default())
+            .expect("EDID should be optional by default");
+        assert_eq!(anio.editor_id_index, None);
+        assert_eq!(anio.model_collection_index, 0);
+
+        let mut ctx = ParseContext::new(ParseOptions {
+            require_editor_id: true,
+            ..ParseOptions::default()
+        });
+        assert_eq!(
+            ANIORecord::from_record(record, &mut ctx),
+            Err(FromRecordError::ExpectedField(
+                edid::EDID::static_type_name()
+            ))
+        );
+    }
+}
@@ -1,7 +1,7 @@
 use super::{
     common::{
-        CommonRecordInfo, FromRecord, FromRecordError, GeneralRecord, Index, StaticTypeNamed,
-        TypeNamed,
+        optional_editor_id, CommonRecordInfo, FormId, FormIdVisitor, FromRecord, FromRecordError,
+        GeneralRecord, Index, StaticTypeNamed, TypeNamed,
     },
     fields::{
         common::{item, object, GeneralField},
@@ -9,9 +9,11 @@ use super::{
     },
 };
 use crate::{
-    collect_one, collect_one_collection, dispatch_all, impl_static_type_named,
+    collect_one, collect_one_collection,
+    context::ParseContext,
+    dispatch_all, impl_formid_visitor, impl_redecode_unknowns, impl_static_type_named,
     parse::PResult,
-    util::{DataSize, Writable},
+    util::{record_data_size, DataSize, Writable, WriteError},
 };
 use bstr::BStr;
 use derive_more::From;
@@ -23,7 +25,7 @@ use std::io::Write;
 pub struct APPARecord<'data> {
     pub common: CommonRecordInfo,
     /// EDID
-    pub editor_id_index: Index,
+    pub editor_id_index: Option<Index>,
     /// VMAD
     pub script_index: Option<Index>,
     /// OBND
@@ -52,7 +54,10 @@ pub struct APPARecord<'data> {
     pub fields: Vec<APPAField<'data>>,
 }
 impl<'data> FromRecord<'data> for APPARecord<'data> {
-    fn from_record(record: GeneralRecord<'data>) -> PResult<Self, FromRecordError<'data>> {
+    fn from_record(
+        record: GeneralRecord<'data>,
+        ctx: &mut ParseContext,
+    ) -> PResult<'data, Self, FromRecordError<'data>> {
         let mut editor_id_index = None;
         let mut script_index = None;
         let mut object_bounds_index = None;
@@ -72,31 +77,30 @@ impl<'data> FromRecord<'data> for APPARecord<'data> {
 
         while let Some(field) = field_iter.next() {
             match field.type_name().as_ref() {
-                b"EDID" => collect_one!(edid::EDID, field => fields; editor_id_index),
+                b"EDID" => collect_one!(edid::EDID, field => fields; editor_id_index, ctx),
                 b"VMAD" => {
-                    collect_one!(vmad::VMAD<'data, vmad::NoFragments>, field => fields; script_index)
+                    collect_one!(vmad::VMAD<'data, vmad::NoFragments>, field => fields; script_index, ctx)
                 }
-                b"OBND" => collect_one!(obnd::OBND, field => fields; object_bounds_index),
-                b"FULL" => collect_one!(object::FULL, field => fields; name_index),
+                b"OBND" => collect_one!(obnd::OBND, field => fields; object_bounds_index, ctx),
+                b"FULL" => collect_one!(object::FULL<'data>, field => fields; name_index, ctx),
                 b"MODL" => {
-                    collect_one_collection!(modl::MODL, modl::MODLCollection; field, field_iter => fields; model_collection_index)
+                    collect_one_collection!(modl::MODL, modl::MODLCollection; field, field_iter => fields; model_collection_index, ctx)
                 }
-                b"ICON" => collect_one!(item::ICON, field => fields; image_index),
-                b"MICO" => collect_one!(item::MICO, field => fields; message_image_index),
+                b"ICON" => collect_one!(item::ICON, field => fields; image_index, ctx),
+                b"MICO" => collect_one!(item::MICO, field => fields; message_image_index, ctx),
                 b"DEST" => {
-                    collect_one_collection!(dest::DEST, dest::DESTCollection; field, field_iter => fields; destruction_collection_index)
+                    collect_one_collection!(dest::DEST, dest::DESTCollection; field, field_iter => fields; destruction_collection_index, ctx)
                 }
-                b"YNAM" => collect_one!(item::YNAM, field => fields; pickup_sound_index),
-                b"ZNAM" => collect_one!(item::ZNAM, field => fields; drop_sound_index),
-                b"QUAL" => collect_one!(item::QUAL, field => fields; quality_index),
-                b"DESC" => collect_one!(item::DESC, field => fields; description_index),
-                b"DATA" => collect_one!(item::DATA, field => fields; data_index),
+                b"YNAM" => collect_one!(item::YNAM, field => fields; pickup_sound_index, ctx),
+                b"ZNAM" => collect_one!(item::ZNAM, field => fields; drop_sound_index, ctx),
+                b"QUAL" => collect_one!(item::QUAL, field => fields; quality_index, ctx),
+                b"DESC" => collect_one!(item::DESC<'data>, field => fields; description_index, ctx),
+                b"DATA" => collect_one!(item::DATA, field => fields; data_index, ctx),
                 _ => fields.push(field.into()),
             }
         }
 
-        let editor_id_index = editor_id_index
-            .ok_or_else(|| FromRecordError::ExpectedField(edid::EDID::static_type_name()))?;
+        let editor_id_index = optional_editor_id(editor_id_index, ctx)?;
         let object_bounds_index = object_bounds_index
             .ok_or_else(|| FromRecordError::ExpectedField(obnd::OBND::static_type_name()))?;
         let name_index = name_index
@@ -130,15 +134,36 @@ impl<'data> FromRecord<'data> for APPARecord<'data> {
         ))
     }
 }
+impl item::Item for APPARecord<'_> {
+    fn value(&self) -> Option<item::Gold> {
+        match &self.fields[self.data_index] {
+            APPAField::DATA(x) => Some(x.value()),
+            _ => None,
+        }
+    }
+
+    fn weight(&self) -> Option<item::Weight> {
+        match &self.fields[self.data_index] {
+            APPAField::DATA(x) => Some(x.weight()),
+            _ => None,
+        }
+    }
+
+    /// There is no flag for this, as [record_flag::NON_PLAYABLE] is only known to be used by ARMO
+    fn is_playable(&self) -> bool {
+        true
+    }
+}
 impl_static_type_named!(APPARecord<'_>, b"APPA");
+impl_redecode_unknowns!(APPARecord, APPAField);
+impl_formid_visitor!(APPARecord<'_>, [fields]);
 impl Writable for APPARecord<'_> {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
         self.type_name().write_to(w)?;
-        // TODO: assert that size fits within a u32
-        (self.fields.data_size() as u32).write_to(w)?;
+        record_data_size(self.type_name(), self.fields.data_size())?.write_to(w)?;
         self.common.write_to(w)?;
         self.fields.write_to(w)?;
         Ok(())
@@ -158,7 +183,7 @@ pub enum APPAField<'data> {
     EDID(edid::EDID<'data>),
     VMAD(vmad::VMAD<'data, vmad::NoFragments>),
     OBND(obnd::OBND),
-    FULL(object::FULL),
+    FULL(object::FULL<'data>),
     MODLCollection(modl::MODLCollection<'data>),
     ICON(item::ICON<'data>),
     MICO(item::MICO<'data>),
@@ -166,7 +191,7 @@ pub enum APPAField<'data> {
     YNAM(item::YNAM),
     ZNAM(item::ZNAM),
     QUAL(item::QUAL),
-    DESC(item::DESC),
+    DESC(item::DESC<'data>),
     DATA(item::DATA),
     Unknown(GeneralField<'data>),
 }
@@ -196,6 +221,86 @@ impl<'data> TypeNamed<'data> for APPAField<'data> {
         )
     }
 }
+impl FormIdVisitor for APPAField<'_> {
+    fn visit_formids(&mut self, visit: &mut dyn FnMut(&mut FormId)) {
+        dispatch_all!(
+            APPAField,
+            self,
+            [
+                EDID,
+                VMAD,
+                OBND,
+                FULL,
+                MODLCollection,
+                ICON,
+                MICO,
+                DESTCollection,
+                YNAM,
+                ZNAM,
+                QUAL,
+                DESC,
+                DATA,
+                Unknown
+            ],
+            x,
+            { x.visit_formids(visit) }
+        )
+    }
+
+    fn for_each_formid(&self, visit: &mut dyn FnMut(FormId)) {
+        dispatch_all!(
+            APPAField,
+            self,
+            [
+                EDID,
+                VMAD,
+                OBND,
+                FULL,
+                MODLCollection,
+                ICON,
+                MICO,
+                DESTCollection,
+                YNAM,
+                ZNAM,
+                QUAL,
+                DESC,
+                DATA,
+                Unknown
+            ],
+            x,
+            { x.for_each_formid(visit) }
+        )
+    }
+
+    fn for_each_field_formid<'name>(
+        &self,
+        name: &'name BStr,
+        visit: &mut dyn FnMut(&'name BStr, FormId),
+    ) {
+        dispatch_all!(
+            APPAField,
+            self,
+            [
+                EDID,
+                VMAD,
+                OBND,
+                FULL,
+                MODLCollection,
+                ICON,
+                MICO,
+                DESTCollection,
+                YNAM,
+                ZNAM,
+                QUAL,
+                DESC,
+                DATA,
+                Unknown
+            ],
+            x,
+            { x.for_each_field_formid(name, visit) }
+        )
+    }
+}
 impl DataSize for APPAField<'_> {
     fn data_size(&self) -> usize {
         dispatch_all!(
@@ -223,7 +328,7 @@ impl DataSize for APPAField<'_> {
     }
 }
 impl Writable for APPAField<'_> {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -1,7 +1,7 @@
 use super::{
     common::{
-        CommonRecordInfo, FieldList, FromRecord, FromRecordError, GeneralRecord, StaticTypeNamed,
-        TypeNamed,
+        CommonRecordInfo, FieldList, FormId, FormIdVisitor, FromRecord, FromRecordError,
+        GeneralRecord, Index, StaticTypeNamed, TypeNamed,
     },
     fields::{
         common::{item, write_field_header, GeneralField, FIELDH_SIZE},
@@ -9,11 +9,13 @@ use super::{
     },
 };
 use crate::{
-    collect_one, collect_one_collection, dispatch_all, impl_from_field, impl_static_data_size,
-    impl_static_type_named, make_field_getter, make_formid_field, make_model_fields,
-    make_single_value_field,
+    collect_one, collect_one_collection,
+    context::ParseContext,
+    dispatch_all, impl_formid_visitor, impl_from_field, impl_no_formids, impl_redecode_unknowns,
+    impl_static_data_size, impl_static_type_named, make_field_getter, make_formid_field,
+    make_model_fields, make_single_value_field,
     parse::{PResult, Parse},
-    util::{DataSize, Writable},
+    util::{record_data_size, DataSize, Writable, WriteError},
 };
 use derive_more::From;
 use std::{fmt::Debug, io::Write};
@@ -32,7 +34,38 @@ impl<'data> ARMARecord<'data> {
         edid::EDID<'data>
     );
 
-    // TODO: make BODT/BOD2 getter
+    pub fn body_template_index(&self) -> Option<Index> {
+        self.fields
+            .iter()
+            .position(|x| matches!(x, ARMAField::BODT(_) | ARMAField::BOD2(_)))
+    }
+
+    /// The BODT or BOD2 field, whichever the record has
+    pub fn body_template(&self) -> Option<item::BodyTemplate> {
+        self.body_template_index().map(|i| match &self.fields[i] {
+            ARMAField::BODT(x) => x.into(),
+            ARMAField::BOD2(x) => x.into(),
+            _ => panic!("ILE: Unreachable"),
+        })
+    }
+
+    /// Replaces a BODT field with the equivalent BOD2 field. Returns false if there was no BODT field.
+    /// If the BODT has flags (which BOD2 can't hold) then nothing is changed, and those flags are returned.
+    pub fn upgrade_bodt_to_bod2(&mut self) -> Result<bool, item::BODTFlags> {
+        let index = match self.body_template_index() {
+            Some(index) => index,
+            None => return Ok(false),
+        };
+        let bodt = match &self.fields[index] {
+            ARMAField::BODT(bodt) => bodt.clone(),
+            _ => return Ok(false),
+        };
+        if bodt.flags.flags != 0 {
+            return Err(bodt.flags);
+        }
+        self.fields[index] = ARMAField::BOD2(bodt.to_bod2());
+        Ok(true)
+    }
 
     make_field_getter!(
         primary_race_index,
@@ -129,7 +162,10 @@ impl<'data> ARMARecord<'data> {
     );
 }
 impl<'data> FromRecord<'data> for ARMARecord<'data> {
-    fn from_record(record: GeneralRecord<'data>) -> PResult<Self, FromRecordError<'data>> {
+    fn from_record(
+        record: GeneralRecord<'data>,
+        ctx: &mut ParseContext,
+    ) -> PResult<'data, Self, FromRecordError<'data>> {
         let mut edid_index = None;
         let mut bodt_index = None;
         let mut bod2_index = None;
@@ -151,32 +187,32 @@ impl<'data> FromRecord<'data> for ARMARecord<'data> {
         let mut field_iter = record.fields.into_iter().peekable();
         while let Some(field) = field_iter.next() {
             match field.type_name().as_ref() {
-                b"EDID" => collect_one!(edid::EDID, field => fields; edid_index),
-                b"BODT" => collect_one!(item::BODT, field => fields; bodt_index),
-                b"BOD2" => collect_one!(item::BOD2, field => fields; bod2_index),
-                b"RNAM" => collect_one!(RNAM, field => fields; rnam_index),
-                b"DNAM" => collect_one!(DNAM, field => fields; dnam_index),
+                b"EDID" => collect_one!(edid::EDID, field => fields; edid_index, ctx),
+                b"BODT" => collect_one!(item::BODT, field => fields; bodt_index, ctx),
+                b"BOD2" => collect_one!(item::BOD2, field => fields; bod2_index, ctx),
+                b"RNAM" => collect_one!(RNAM, field => fields; rnam_index, ctx),
+                b"DNAM" => collect_one!(DNAM, field => fields; dnam_index, ctx),
                 b"MOD2" => {
-                    collect_one_collection!(MOD2, MOD2Collection; field, field_iter => fields; mod2_index)
+                    collect_one_collection!(MOD2, MOD2Collection; field, field_iter => fields; mod2_index, ctx)
                 }
                 b"MOD3" => {
-                    collect_one_collection!(MOD3, MOD3Collection; field, field_iter => fields; mod3_index)
+                    collect_one_collection!(MOD3, MOD3Collection; field, field_iter => fields; mod3_index, ctx)
                 }
                 b"MOD4" => {
-                    collect_one_collection!(MOD4, MOD4Collection; field, field_iter => fields; mod4_index)
+                    collect_one_collection!(MOD4, MOD4Collection; field, field_iter => fields; mod4_index, ctx)
                 }
                 b"MOD5" => {
-                    collect_one_collection!(MOD5, MOD5Collection; field, field_iter => fields; mod5_index)
+                    collect_one_collection!(MOD5, MOD5Collection; field, field_iter => fields; mod5_index, ctx)
                 }
-                b"NAM0" => collect_one!(NAM0, field => fields; nam0_index),
-                b"NAM1" => collect_one!(NAM1, field => fields; nam1_index),
-                b"NAM2" => collect_one!(NAM2, field => fields; nam2_index),
-                b"NAM3" => collect_one!(NAM3, field => fields; nam3_index),
+                b"NAM0" => collect_one!(NAM0, field => fields; nam0_index, ctx),
+                b"NAM1" => collect_one!(NAM1, field => fields; nam1_index, ctx),
+                b"NAM2" => collect_one!(NAM2, field => fields; nam2_index, ctx),
+                b"NAM3" => collect_one!(NAM3, field => fields; nam3_index, ctx),
                 b"MODL" => {
-                    collect_one_collection!(MODL, MODLList; field, field_iter => fields; modl_list_index)
+                    collect_one_collection!(MODL, MODLList; field, field_iter => fields; modl_list_index, ctx)
                 }
-                b"SNDD" => collect_one!(SNDD, field => fields; sndd_index),
-                b"ONAM" => collect_one!(ONAM, field => fields; onam_index),
+                b"SNDD" => collect_one!(SNDD, field => fields; sndd_index, ctx),
+                b"ONAM" => collect_one!(ONAM, field => fields; onam_index, ctx),
                 _ => fields.push(ARMAField::Unknown(field)),
             }
         }
@@ -208,6 +244,8 @@ impl<'data> FromRecord<'data> for ARMARecord<'data> {
     }
 }
 impl_static_type_named!(ARMARecord<'_>, b"ARMA");
+impl_redecode_unknowns!(ARMARecord, ARMAField);
+impl_formid_visitor!(ARMARecord<'_>, [fields]);
 impl DataSize for ARMARecord<'_> {
     fn data_size(&self) -> usize {
         self.type_name().data_size() +
@@ -217,13 +255,12 @@ impl DataSize for ARMARecord<'_> {
     }
 }
 impl Writable for ARMARecord<'_> {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
         self.type_name().write_to(w)?;
-        // TODO: assert that size fits within a u32
-        (self.fields.data_size() as u32).write_to(w)?;
+        record_data_size(self.type_name(), self.fields.data_size())?.write_to(w)?;
         self.common.write_to(w)?;
         self.fields.write_to(w)
     }
@@ -278,6 +315,95 @@ impl<'data> TypeNamed<'data> for ARMAField<'data> {
         )
     }
 }
+impl FormIdVisitor for ARMAField<'_> {
+    fn visit_formids(&mut self, visit: &mut dyn FnMut(&mut FormId)) {
+        dispatch_all!(
+            ARMAField,
+            self,
+            [
+                EDID,
+                BODT,
+                BOD2,
+                RNAM,
+                DNAM,
+                MOD2Collection,
+                MOD3Collection,
+                MOD4Collection,
+                MOD5Collection,
+                NAM0,
+                NAM1,
+                NAM2,
+                NAM3,
+                MODLList,
+                SNDD,
+                ONAM,
+                Unknown
+            ],
+            x,
+            { x.visit_formids(visit) }
+        )
+    }
+
+    fn for_each_formid(&self, visit: &mut dyn FnMut(FormId)) {
+        dispatch_all!(
+            ARMAField,
+            self,
+            [
+                EDID,
+                BODT,
+                BOD2,
+                RNAM,
+                DNAM,
+                MOD2Collection,
+                MOD3Collection,
+                MOD4Collection,
+                MOD5Collection,
+                NAM0,
+                NAM1,
+                NAM2,
+                NAM3,
+                MODLList,
+                SNDD,
+                ONAM,
+                Unknown
+            ],
+            x,
+            { x.for_each_formid(visit) }
+        )
+    }
+
+    fn for_each_field_formid<'name>(
+        &self,
+        name: &'name bstr::BStr,
+        visit: &mut dyn FnMut(&'name bstr::BStr, FormId),
+    ) {
+        dispatch_all!(
+            ARMAField,
+            self,
+            [
+                EDID,
+                BODT,
+                BOD2,
+                RNAM,
+                DNAM,
+                MOD2Collection,
+                MOD3Collection,
+                MOD4Collection,
+                MOD5Collection,
+                NAM0,
+                NAM1,
+                NAM2,
+                NAM3,
+                MODLList,
+                SNDD,
+                ONAM,
+                Unknown
+            ],
+            x,
+            { x.for_each_field_formid(name, visit) }
+        )
+    }
+}
 impl DataSize for ARMAField<'_> {
     fn data_size(&self) -> usize {
         dispatch_all!(
@@ -308,7 +434,7 @@ impl DataSize for ARMAField<'_> {
     }
 }
 impl Writable for ARMAField<'_> {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -366,12 +492,13 @@ impl_from_field!(
     ]
 );
 impl_static_type_named!(DNAM, b"DNAM");
+impl_no_formids!(DNAM);
 impl_static_data_size!(
     DNAM,
     FIELDH_SIZE + (u8::static_data_size() * 4) + u32::static_data_size() + f32::static_data_size()
 );
 impl Writable for DNAM {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -1,26 +1,45 @@
 use super::{
+    arma,
     common::{
-        full_string::FullString, get_field, CommonRecordInfo, FieldList, FormId, FromRecord,
-        FromRecordError, GeneralRecord, NullTerminatedString, StaticTypeNamed, TypeNamed,
+        full_string::FullString, get_field, record_flag, CommonRecordInfo, FieldList, FormId,
+        FormIdVisitor, FromRecord, FromRecordError, GeneralRecord, Index, NullTerminatedString,
+        StaticTypeNamed, TypeNamed,
     },
     fields::{
         common::{item, object, CollectField, FromFieldError, GeneralField},
         dest, edid, kwda, modl, obnd, vmad,
     },
+    RecordRef,
 };
 use crate::{
-    collect_one, collect_one_collection, dispatch_all, impl_from_field, impl_static_type_named,
+    collect_one, collect_one_collection,
+    context::ParseContext,
+    dispatch_all, impl_formid_visitor, impl_from_field, impl_keyworded, impl_no_formids,
+    impl_redecode_unknowns, impl_static_type_named,
+    load_order::{LoadOrder, SourcedRecord},
     make_field_getter, make_formid_field, make_model_fields, make_single_value_field,
     parse::{PResult, Parse},
-    util::{DataSize, StaticDataSize, Writable},
+    util::{record_data_size, DataSize, StaticDataSize, Writable, WriteError},
 };
 use derive_more::From;
 use std::io::Write;
 
+/// A problem with an armature referenced by an ARMO
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ArmatureIssue {
+    /// No record with the form id was found
+    Unresolved(FormId),
+    /// The form id refers to a record that is not an ARMA
+    NotArmature(FormId),
+    /// The armature can't be used by the race of the armor.
+    /// [race] is the armature's primary race, relative to the plugin the armature is from.
+    RaceMismatch { armature: FormId, race: FormId },
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct ARMORecord<'data> {
-    common: CommonRecordInfo,
-    fields: Vec<ARMOField<'data>>,
+    pub common: CommonRecordInfo,
+    pub fields: Vec<ARMOField<'data>>,
 }
 impl<'data> ARMORecord<'data> {
     make_field_getter!(
@@ -78,7 +97,43 @@ impl<'data> ARMORecord<'data> {
         InventoryMO4LCollection<'data>
     );
 
-    // TODO: make getter for BODT|BOD2
+    pub fn body_template_index(&self) -> Option<Index> {
+        self.fields
+            .iter()
+            .position(|x| matches!(x, ARMOField::BODT(_) | ARMOField::BOD2(_)))
+    }
+
+    /// The BODT or BOD2 field, whichever the record has
+    pub fn body_template(&self) -> Option<item::BodyTemplate> {
+        self.body_template_index().map(|i| match &self.fields[i] {
+            ARMOField::BODT(x) => x.into(),
+            ARMOField::BOD2(x) => x.into(),
+            _ => panic!("ILE: Unreachable"),
+        })
+    }
+
+    /// Replaces a BODT field with the equivalent BOD2 field, moving the non-playable flag into
+    /// the record flags. Returns false if there was no BODT field.
+    /// If the BODT has flags that BOD2 can't hold then nothing is changed, and those flags are returned.
+    pub fn upgrade_bodt_to_bod2(&mut self) -> Result<bool, item::BODTFlags> {
+        let index = match self.body_template_index() {
+            Some(index) => index,
+            None => return Ok(false),
+        };
+        let bodt = match &self.fields[index] {
+            ARMOField::BODT(bodt) => bodt.clone(),
+            _ => return Ok(false),
+        };
+        let lost = bodt.flags.flags & !item::BODTFlags::NON_PLAYABLE;
+        if lost != 0 {
+            return Err(item::BODTFlags { flags: lost });
+        }
+        if bodt.flags.flags & item::BODTFlags::NON_PLAYABLE != 0 {
+            self.common.flags.flags |= record_flag::NON_PLAYABLE;
+        }
+        self.fields[index] = ARMOField::BOD2(bodt.to_bod2());
+        Ok(true)
+    }
 
     make_field_getter!(
         optional: destruction_index,
@@ -145,17 +200,128 @@ impl<'data> ARMORecord<'data> {
         description,
         description_mut,
         ARMOField::DESC,
-        item::DESC
+        item::DESC<'data>
     );
 
     make_field_getter!(
-        optional: armatures_index,
-        armatures,
-        armatures_mut,
+        optional: armature_list_index,
+        armature_list,
+        armature_list_mut,
         ARMOField::MODLList,
         MODLList<'data>
     );
 
+    pub fn has_armature(&self, armature: FormId) -> bool {
+        self.armature_list()
+            .map(|list| list.iter().any(|x| x.formid == armature))
+            .unwrap_or(false)
+    }
+
+    /// Adds a reference to the ARMA [armature]. Returns false if it was already referenced.
+    pub fn add_armature(&mut self, armature: FormId) -> bool {
+        if self.has_armature(armature) {
+            return false;
+        }
+        match self.armature_list_mut() {
+            Some(list) => list.push(MODL::new(armature)),
+            None => {
+                // The armatures come right before DATA
+                let index = self.data_index();
+                self.fields.insert(
+                    index,
+                    ARMOField::MODLList(MODLList::new(vec![MODL::new(armature)])),
+                );
+            }
+        }
+        true
+    }
+
+    /// Removes the reference to the ARMA [armature]. Returns false if it was not referenced.
+    pub fn remove_armature(&mut self, armature: FormId) -> bool {
+        let index = match self.armature_list_index() {
+            Some(index) if self.has_armature(armature) => index,
+            _ => return false,
+        };
+        if let ARMOField::MODLList(list) = &mut self.fields[index] {
+            list.retain(|x| x.formid != armature);
+            if list.is_empty() {
+                self.fields.remove(index);
+            }
+        }
+        true
+    }
+
+    /// The ARMA records this refers to, resolved through [load_order], along with the position
+    /// of the plugin that each came from.
+    /// [plugin] is the position of the plugin that this record is from.
+    /// Armatures which can't be resolved to an ARMA record are skipped.
+    pub fn armatures<'a, 'l>(
+        &self,
+        load_order: &'a LoadOrder<'l>,
+        plugin: usize,
+    ) -> Vec<(usize, &'a arma::ARMARecord<'l>)> {
+        self.armature_list()
+            .into_iter()
+            .flat_map(|list| list.iter())
+            .filter_map(|x| match load_order.resolve(plugin, x.formid) {
+                Some(SourcedRecord {
+                    source,
+                    record: RecordRef::ARMA(arma),
+                }) => Some((source.index, arma)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Checks that every armature exists, and that it can be used by the race of this armor.
+    /// An armature can be used if either its primary race or one of its additional races is
+    /// the race of this armor.
+    pub fn validate_armatures(
+        &self,
+        load_order: &LoadOrder<'_>,
+        plugin: usize,
+    ) -> Vec<ArmatureIssue> {
+        let race = load_order.origin(plugin, self.race().formid);
+        let list = match self.armature_list() {
+            Some(list) => list,
+            None => return Vec::new(),
+        };
+
+        let mut issues = Vec::new();
+        for armature in list.iter().map(|x| x.formid) {
+            let (index, arma) = match load_order.resolve(plugin, armature) {
+                Some(SourcedRecord {
+                    source,
+                    record: RecordRef::ARMA(arma),
+                }) => (source.index, arma),
+                Some(_) => {
+                    issues.push(ArmatureIssue::NotArmature(armature));
+                    continue;
+                }
+                None => {
+                    issues.push(ArmatureIssue::Unresolved(armature));
+                    continue;
+                }
+            };
+
+            let primary = arma.primary_race().formid;
+            let compatible = std::iter::once(primary)
+                .chain(
+                    arma.races()
+                        .into_iter()
+                        .flat_map(|x| x.iter().map(|x| x.formid)),
+                )
+                .any(|x| race.is_some() && load_order.origin(index, x) == race);
+            if !compatible {
+                issues.push(ArmatureIssue::RaceMismatch {
+                    armature,
+                    race: primary,
+                });
+            }
+        }
+        issues
+    }
+
     make_field_getter!(data_index, data, data_mut, ARMOField::DATA, item::DATA);
 
     make_field_getter!(
@@ -167,7 +333,10 @@ impl<'data> ARMORecord<'data> {
     );
 }
 impl<'data> FromRecord<'data> for ARMORecord<'data> {
-    fn from_record(record: GeneralRecord<'data>) -> PResult<Self, FromRecordError<'data>> {
+    fn from_record(
+        record: GeneralRecord<'data>,
+        ctx: &mut ParseContext,
+    ) -> PResult<'data, Self, FromRecordError<'data>> {
         let mut edid_index = None;
         let mut vmad_index = None;
         let mut obnd_index = None;
@@ -198,48 +367,48 @@ impl<'data> FromRecord<'data> for ARMORecord<'data> {
 
         while let Some(field) = field_iter.next() {
             match field.type_name().as_ref() {
-                b"EDID" => collect_one!(edid::EDID, field => fields; edid_index),
+                b"EDID" => collect_one!(edid::EDID, field => fields; edid_index, ctx),
                 b"VMAD" => {
-                    collect_one!(vmad::VMAD<'data, vmad::NoFragments>, field => fields; vmad_index)
+                    collect_one!(vmad::VMAD<'data, vmad::NoFragments>, field => fields; vmad_index, ctx)
                 }
-                b"OBND" => collect_one!(obnd::OBND, field => fields; obnd_index),
-                b"FULL" => collect_one!(object::FULL, field => fields; full_index),
+                b"OBND" => collect_one!(obnd::OBND, field => fields; obnd_index, ctx),
+                b"FULL" => collect_one!(object::FULL<'data>, field => fields; full_index, ctx),
                 b"EITM" => {
-                    collect_one_collection!(EITM, Enchantment; field, field_iter => fields; enchantment_index)
+                    collect_one_collection!(EITM, Enchantment; field, field_iter => fields; enchantment_index, ctx)
                 }
                 b"MODL" => {
                     // special handling because there's two fields with the same name in this... Honestly.
                     if field.data.len() == FormId::static_data_size() {
-                        collect_one_collection!(MODL, MODLList; field, field_iter => fields; modl_list_index)
+                        collect_one_collection!(MODL, MODLList; field, field_iter => fields; modl_list_index, ctx)
                     } else {
-                        collect_one_collection!(modl::MODL, modl::MODLCollection; field, field_iter => fields; modl_collection_index)
+                        collect_one_collection!(modl::MODL, modl::MODLCollection; field, field_iter => fields; modl_collection_index, ctx)
                     }
                 }
                 b"MOD2" => {
-                    collect_one_collection!(MOD2, InventoryMO2LCollection; field, field_iter => fields; inventory_mod2_index)
+                    collect_one_collection!(MOD2, InventoryMO2LCollection; field, field_iter => fields; inventory_mod2_index, ctx)
                 }
                 b"MOD4" => {
-                    collect_one_collection!(MOD4, InventoryMO4LCollection; field, field_iter => fields; inventory_mod4_index)
+                    collect_one_collection!(MOD4, InventoryMO4LCollection; field, field_iter => fields; inventory_mod4_index, ctx)
                 }
-                b"BODT" => collect_one!(item::BODT, field => fields; bodt_index),
-                b"BOD2" => collect_one!(item::BOD2, field => fields; bod2_index),
+                b"BODT" => collect_one!(item::BODT, field => fields; bodt_index, ctx),
+                b"BOD2" => collect_one!(item::BOD2, field => fields; bod2_index, ctx),
                 b"DEST" => {
-                    collect_one_collection!(dest::DEST, dest::DESTCollection; field, field_iter => fields; dest_collection_index)
+                    collect_one_collection!(dest::DEST, dest::DESTCollection; field, field_iter => fields; dest_collection_index, ctx)
                 }
-                b"YNAM" => collect_one!(item::YNAM, field => fields; ynam_index),
-                b"ZNAM" => collect_one!(item::ZNAM, field => fields; znam_index),
-                b"BMCT" => collect_one!(BMCT, field => fields; bmct_index),
-                b"ETYP" => collect_one!(ETYP, field => fields; etyp_index),
-                b"BIDS" => collect_one!(BIDS, field => fields; bids_index),
-                b"BAMT" => collect_one!(BAMT, field => fields; bamt_index),
-                b"RNAM" => collect_one!(RNAM, field => fields; rnam_index),
+                b"YNAM" => collect_one!(item::YNAM, field => fields; ynam_index, ctx),
+                b"ZNAM" => collect_one!(item::ZNAM, field => fields; znam_index, ctx),
+                b"BMCT" => collect_one!(BMCT, field => fields; bmct_index, ctx),
+                b"ETYP" => collect_one!(ETYP, field => fields; etyp_index, ctx),
+                b"BIDS" => collect_one!(BIDS, field => fields; bids_index, ctx),
+                b"BAMT" => collect_one!(BAMT, field => fields; bamt_index, ctx),
+                b"RNAM" => collect_one!(RNAM, field => fields; rnam_index, ctx),
                 b"KSIZ" => {
-                    collect_one_collection!(kwda::KSIZ, kwda::KWDACollection; field, field_iter => fields; kwda_collection_index)
+                    collect_one_collection!(repeatable: kwda::KSIZ, kwda::KWDACollection; field, field_iter => fields; kwda_collection_index)
                 }
-                b"DESC" => collect_one!(item::DESC, field => fields; desc_index),
-                b"DATA" => collect_one!(item::DATA, field => fields; data_index),
-                b"DNAM" => collect_one!(DNAM, field => fields; dnam_index),
-                b"TNAM" => collect_one!(TNAM, field => fields; tnam_index),
+                b"DESC" => collect_one!(item::DESC<'data>, field => fields; desc_index, ctx),
+                b"DATA" => collect_one!(item::DATA, field => fields; data_index, ctx),
+                b"DNAM" => collect_one!(DNAM, field => fields; dnam_index, ctx),
+                b"TNAM" => collect_one!(TNAM, field => fields; tnam_index, ctx),
                 _ => fields.push(field.into()),
             }
         }
@@ -271,7 +440,34 @@ impl<'data> FromRecord<'data> for ARMORecord<'data> {
         }
     }
 }
+impl item::Item for ARMORecord<'_> {
+    fn value(&self) -> Option<item::Gold> {
+        Some(self.data().value())
+    }
+
+    fn weight(&self) -> Option<item::Weight> {
+        Some(self.data().weight())
+    }
+
+    fn is_playable(&self) -> bool {
+        let bodt_non_playable = self
+            .body_template()
+            .and_then(|x| x.flags)
+            .map(|x| x.flags & item::BODTFlags::NON_PLAYABLE != 0)
+            .unwrap_or(false);
+        !self.common.flags.is(record_flag::NON_PLAYABLE) && !bodt_non_playable
+    }
+}
+impl item::Enchantable for ARMORecord<'_> {
+    fn enchantment_id(&self) -> Option<(FormId, Option<u16>)> {
+        self.enchantment()
+            .map(|x| (x.enchantment.formid, x.amount.map(|x| x.amount)))
+    }
+}
 impl_static_type_named!(ARMORecord<'_>, b"ARMO");
+impl_redecode_unknowns!(ARMORecord, ARMOField);
+impl_formid_visitor!(ARMORecord<'_>, [fields]);
+impl_keyworded!(ARMORecord, ARMOField);
 impl DataSize for ARMORecord<'_> {
     fn data_size(&self) -> usize {
         self.type_name().data_size() +
@@ -281,13 +477,12 @@ impl DataSize for ARMORecord<'_> {
     }
 }
 impl Writable for ARMORecord<'_> {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
         self.type_name().write_to(w)?;
-        // TODO: assert that size fits within a u32
-        (self.fields.data_size() as u32).write_to(w)?;
+        record_data_size(self.type_name(), self.fields.data_size())?.write_to(w)?;
         self.common.write_to(w)?;
         self.fields.write_to(w)
     }
@@ -298,7 +493,7 @@ pub enum ARMOField<'data> {
     EDID(edid::EDID<'data>),
     VMAD(vmad::VMAD<'data, vmad::NoFragments>),
     OBND(obnd::OBND),
-    FULL(object::FULL),
+    FULL(object::FULL<'data>),
     Enchantment(Enchantment),
     MODLCollection(modl::MODLCollection<'data>),
     InventoryMO2LCollection(InventoryMO2LCollection<'data>),
@@ -316,7 +511,7 @@ pub enum ARMOField<'data> {
     KWDACollection(kwda::KWDACollection),
     // TODO: what does non-standard mean?
     /// uesp: Usually 0 unless the enchantment is non-standard, like archmage robes
-    DESC(item::DESC),
+    DESC(item::DESC<'data>),
     MODLList(MODLList<'data>),
     DATA(item::DATA),
     DNAM(DNAM),
@@ -360,6 +555,119 @@ impl<'data> TypeNamed<'data> for ARMOField<'data> {
         )
     }
 }
+impl FormIdVisitor for ARMOField<'_> {
+    fn visit_formids(&mut self, visit: &mut dyn FnMut(&mut FormId)) {
+        dispatch_all!(
+            ARMOField,
+            self,
+            [
+                EDID,
+                VMAD,
+                OBND,
+                FULL,
+                Enchantment,
+                MODLCollection,
+                InventoryMO2LCollection,
+                InventoryMO4LCollection,
+                BODT,
+                BOD2,
+                DESTCollection,
+                YNAM,
+                ZNAM,
+                BMCT,
+                ETYP,
+                BIDS,
+                BAMT,
+                RNAM,
+                KWDACollection,
+                DESC,
+                MODLList,
+                DATA,
+                DNAM,
+                TNAM,
+                Unknown
+            ],
+            x,
+            { x.visit_formids(visit) }
+        )
+    }
+
+    fn for_each_formid(&self, visit: &mut dyn FnMut(FormId)) {
+        dispatch_all!(
+            ARMOField,
+            self,
+            [
+                EDID,
+                VMAD,
+                OBND,
+                FULL,
+                Enchantment,
+                MODLCollection,
+                InventoryMO2LCollection,
+                InventoryMO4LCollection,
+                BODT,
+                BOD2,
+                DESTCollection,
+                YNAM,
+                ZNAM,
+                BMCT,
+                ETYP,
+                BIDS,
+                BAMT,
+                RNAM,
+                KWDACollection,
+                DESC,
+                MODLList,
+                DATA,
+                DNAM,
+                TNAM,
+                Unknown
+            ],
+            x,
+            { x.for_each_formid(visit) }
+        )
+    }
+
+    fn for_each_field_formid<'name>(
+        &self,
+        name: &'name bstr::BStr,
+        visit: &mut dyn FnMut(&'name bstr::BStr, FormId),
+    ) {
+        dispatch_all!(
+            ARMOField,
+            self,
+            [
+                EDID,
+                VMAD,
+                OBND,
+                FULL,
+                Enchantment,
+                MODLCollection,
+                InventoryMO2LCollection,
+                InventoryMO4LCollection,
+                BODT,
+                BOD2,
+                DESTCollection,
+                YNAM,
+                ZNAM,
+                BMCT,
+                ETYP,
+                BIDS,
+                BAMT,
+                RNAM,
+                KWDACollection,
+                DESC,
+                MODLList,
+                DATA,
+                DNAM,
+                TNAM,
+                Unknown
+            ],
+            x,
+            { x.for_each_field_formid(name, visit) }
+        )
+    }
+}
 impl DataSize for ARMOField<'_> {
     fn data_size(&self) -> usize {
         dispatch_all!(
@@ -398,7 +706,7 @@ impl DataSize for ARMOField<'_> {
     }
 }
 impl Writable for ARMOField<'_> {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -463,6 +771,7 @@ impl<'data> CollectField<'data, EITM> for Enchantment {
     }
 }
 impl_static_type_named!(Enchantment, EITM::static_type_name());
+impl_formid_visitor!(Enchantment, [enchantment]);
 // TODO: EAMT could easily be statically sized.. but it's not due to the macro creating it
 impl DataSize for Enchantment {
     fn data_size(&self) -> usize {
@@ -470,7 +779,7 @@ impl DataSize for Enchantment {
     }
 }
 impl Writable for Enchantment {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -517,6 +826,23 @@ macro_rules! make_inventory_modl_collection {
                 ))
             }
 		}
+		impl<$life> FormIdVisitor for $invcol<$life> {
+			fn visit_formids(&mut self, visit: &mut dyn FnMut(&mut FormId)) {
+				self.model.visit_formids(visit)
+			}
+
+			fn for_each_formid(&self, visit: &mut dyn FnMut(FormId)) {
+				self.model.for_each_formid(visit)
+			}
+
+			fn for_each_field_formid<'name>(
+				&self,
+				name: &'name bstr::BStr,
+				visit: &mut dyn FnMut(&'name bstr::BStr, FormId),
+			) {
+				self.model.for_each_field_formid(name, visit)
+			}
+		}
 		impl<$life> StaticTypeNamed for $invcol<$life> {
 			fn static_type_name() -> &'static bstr::BStr {
 				<$modlcol>::static_type_name()
@@ -530,7 +856,7 @@ macro_rules! make_inventory_modl_collection {
             }
         }
         impl Writable for $invcol<'_> {
-            fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+            fn write_to<T>(&self, w: &mut T) -> Result<(), $crate::util::WriteError>
             where
                 T: std::io::Write,
             {
@@ -581,6 +907,7 @@ make_single_value_field!(
     'data
 );
 impl_from_field!(BMCT, 'data, [ragdoll: FullString]);
+impl_no_formids!(BMCT<'_>);
 
 make_formid_field!(
     /// ->EQUP (only shields)
@@ -610,8 +937,127 @@ make_single_value_field!(
     u32
 );
 impl_from_field!(DNAM, [armor_rating: u32]);
+impl_no_formids!(DNAM);
 
 make_formid_field!(
     /// -> ARMO to use as template
     TNAM
 );
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::records::Record;
+
+    #[test]
+    fn test_upgrade_bodt() {
+        let bodt = item::BODT {
+            part_node_flags: item::BodyPartNodeFlags { flags: 0x4 },
+            flags: item::BODTFlags {
+                flags: item::BODTFlags::NON_PLAYABLE,
+            },
+            unknown: [0, 0, 0],
+            skill: None,
+        };
+        let mut record = ARMORecord {
+            common: CommonRecordInfo::test_default(),
+            fields: vec![ARMOField::BODT(bodt.clone())],
+        };
+        record.common.flags.flags = 0;
+        let template = record.body_template().unwrap();
+        assert_eq!(template.flags, Some(bodt.flags));
+        assert_eq!(template.skill, item::ArmorSkill::None);
+
+        assert_eq!(record.upgrade_bodt_to_bod2(), Ok(true));
+        assert!(record.common.flags.is(record_flag::NON_PLAYABLE));
+        assert_eq!(
+            record.fields[0],
+            ARMOField::BOD2(item::BOD2 {
+                part_node_flags: item::BodyPartNodeFlags { flags: 0x4 },
+                skill: item::ArmorSkill::None,
+            })
+        );
+        assert_eq!(record.body_template().unwrap().flags, None);
+        // Nothing left to upgrade
+        assert_eq!(record.upgrade_bodt_to_bod2(), Ok(false));
+
+        // Flags that can't be kept
+        let mut record = ARMORecord {
+            common: CommonRecordInfo::test_default(),
+            fields: vec![ARMOField::BODT(item::BODT {
+                flags: item::BODTFlags { flags: 0x11 },
+                ..bodt
+            })],
+        };
+        assert_eq!(
+            record.upgrade_bodt_to_bod2(),
+            Err(item::BODTFlags { flags: 0x1 })
+        );
+        assert!(matches!(record.fields[0], ARMOField::BODT(_)));
+    }
+
+    fn arma(id: u32, race: u32, races: &[u32]) -> Record<'static> {
+        let mut fields = vec![arma::ARMAField::RNAM(arma::RNAM::new(FormId::new(race)))];
+        if !races.is_empty() {
+            let races = races.iter().map(|&x| arma::MODL::new(FormId::new(x)));
+            fields.push(arma::ARMAField::MODLList(FieldList::new(races.collect())));
+        }
+        let mut common = CommonRecordInfo::test_default();
+        common.id = id;
+        Record::ARMA(arma::ARMARecord { common, fields })
+    }
+
+    #[test]
+    fn test_armatures() {
+        use crate::{plugin::Plugin, records::fields::common::FromField, Top};
+        use bstr::ByteSlice;
+
+        let (_, data) =
+            item::DATA::from_field(GeneralField::new(b"DATA".as_bstr(), &[0; 8])).unwrap();
+        let mut record = ARMORecord {
+            common: CommonRecordInfo::test_default(),
+            fields: vec![
+                ARMOField::RNAM(RNAM::new(FormId::new(0x100))),
+                ARMOField::DATA(data),
+            ],
+        };
+        assert!(record.add_armature(FormId::new(0x200)));
+        assert!(!record.add_armature(FormId::new(0x200)));
+        assert!(matches!(record.fields[1], ARMOField::MODLList(_)));
+        for id in [0x201, 0x202, 0x203].iter() {
+            assert!(record.add_armature(FormId::new(*id)));
+        }
+
+        let load_order = LoadOrder::new(vec![Plugin::from_tops(
+            "Test.esp".to_string(),
+            vec![
+                Top::Record(arma(0x200, 0x100, &[])),
+                Top::Record(arma(0x201, 0x101, &[0x100])),
+                Top::Record(arma(0x202, 0x101, &[0x102])),
+            ],
+        )]);
+        let found: Vec<u32> = record
+            .armatures(&load_order, 0)
+            .iter()
+            .map(|(_, x)| x.common.id)
+            .collect();
+        assert_eq!(found, vec![0x200, 0x201, 0x202]);
+        assert_eq!(
+            record.validate_armatures(&load_order, 0),
+            vec![
+                ArmatureIssue::RaceMismatch {
+                    armature: FormId::new(0x202),
+                    race: FormId::new(0x101),
+                },
+                ArmatureIssue::Unresolved(FormId::new(0x203)),
+            ]
+        );
+
+        assert!(record.remove_armature(FormId::new(0x203)));
+        assert!(!record.remove_armature(FormId::new(0x203)));
+        for id in [0x200, 0x201, 0x202].iter() {
+            assert!(record.remove_armature(FormId::new(*id)));
+        }
+        assert_eq!(record.armature_list_index(), None);
+    }
+}
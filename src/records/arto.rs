@@ -1,7 +1,7 @@
 use super::{
     common::{
-        CommonRecordInfo, ConversionError, FromRecord, FromRecordError, GeneralRecord,
-        StaticTypeNamed, TypeNamed,
+        CommonRecordInfo, ConversionError, FormId, FormIdVisitor, FromRecord, FromRecordError,
+        GeneralRecord, StaticTypeNamed, TypeNamed,
     },
     fields::{
         common::{write_field_header, GeneralField, FIELDH_SIZE},
@@ -9,10 +9,12 @@ use super::{
     },
 };
 use crate::{
-    collect_one, collect_one_collection, dispatch_all, impl_from_field, impl_static_data_size,
-    impl_static_type_named, make_field_getter,
+    collect_one, collect_one_collection,
+    context::ParseContext,
+    dispatch_all, impl_formid_visitor, impl_from_field, impl_no_formids, impl_redecode_unknowns,
+    impl_static_data_size, impl_static_type_named, make_field_getter,
     parse::{PResult, Parse},
-    util::{DataSize, Writable},
+    util::{record_data_size, DataSize, Writable, WriteError},
 };
 use derive_more::From;
 use std::convert::{TryFrom, TryInto};
@@ -56,7 +58,10 @@ impl<'data> ARTORecord<'data> {
     );
 }
 impl<'data> FromRecord<'data> for ARTORecord<'data> {
-    fn from_record(record: GeneralRecord<'data>) -> PResult<Self, FromRecordError<'data>> {
+    fn from_record(
+        record: GeneralRecord<'data>,
+        ctx: &mut ParseContext,
+    ) -> PResult<'data, Self, FromRecordError<'data>> {
         let mut edid_index = None;
         let mut obnd_index = None;
         let mut modl_collection_index = None;
@@ -67,12 +72,12 @@ impl<'data> FromRecord<'data> for ARTORecord<'data> {
 
         while let Some(field) = field_iter.next() {
             match field.type_name().as_ref() {
-                b"EDID" => collect_one!(edid::EDID, field => fields; edid_index),
-                b"OBND" => collect_one!(obnd::OBND, field => fields; obnd_index),
+                b"EDID" => collect_one!(edid::EDID, field => fields; edid_index, ctx),
+                b"OBND" => collect_one!(obnd::OBND, field => fields; obnd_index, ctx),
                 b"MODL" => {
-                    collect_one_collection!(modl::MODL, modl::MODLCollection; field, field_iter => fields; modl_collection_index)
+                    collect_one_collection!(modl::MODL, modl::MODLCollection; field, field_iter => fields; modl_collection_index, ctx)
                 }
-                b"DNAM" => collect_one!(DNAM, field => fields; dnam_index),
+                b"DNAM" => collect_one!(DNAM, field => fields; dnam_index, ctx),
                 _ => fields.push(field.into()),
             }
         }
@@ -99,6 +104,8 @@ impl<'data> FromRecord<'data> for ARTORecord<'data> {
     }
 }
 impl_static_type_named!(ARTORecord<'_>, b"ARTO");
+impl_redecode_unknowns!(ARTORecord, ARTOField);
+impl_formid_visitor!(ARTORecord<'_>, [fields]);
 impl DataSize for ARTORecord<'_> {
     fn data_size(&self) -> usize {
         self.type_name().data_size() +
@@ -108,13 +115,12 @@ impl DataSize for ARTORecord<'_> {
     }
 }
 impl Writable for ARTORecord<'_> {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: std::io::Write,
     {
         self.type_name().write_to(w)?;
-        // TODO: assert size fits within
-        (self.fields.data_size() as u32).write_to(w)?;
+        record_data_size(self.type_name(), self.fields.data_size())?.write_to(w)?;
         self.common.write_to(w)?;
         self.fields.write_to(w)
     }
@@ -139,6 +145,41 @@ impl<'data> TypeNamed<'data> for ARTOField<'data> {
         )
     }
 }
+impl FormIdVisitor for ARTOField<'_> {
+    fn visit_formids(&mut self, visit: &mut dyn FnMut(&mut FormId)) {
+        dispatch_all!(
+            ARTOField,
+            self,
+            [EDID, OBND, MODLCollection, DNAM, Unknown],
+            x,
+            { x.visit_formids(visit) }
+        )
+    }
+
+    fn for_each_formid(&self, visit: &mut dyn FnMut(FormId)) {
+        dispatch_all!(
+            ARTOField,
+            self,
+            [EDID, OBND, MODLCollection, DNAM, Unknown],
+            x,
+            { x.for_each_formid(visit) }
+        )
+    }
+
+    fn for_each_field_formid<'name>(
+        &self,
+        name: &'name bstr::BStr,
+        visit: &mut dyn FnMut(&'name bstr::BStr, FormId),
+    ) {
+        dispatch_all!(
+            ARTOField,
+            self,
+            [EDID, OBND, MODLCollection, DNAM, Unknown],
+            x,
+            { x.for_each_field_formid(name, visit) }
+        )
+    }
+}
 impl DataSize for ARTOField<'_> {
     fn data_size(&self) -> usize {
         dispatch_all!(
@@ -151,7 +192,7 @@ impl DataSize for ARTOField<'_> {
     }
 }
 impl Writable for ARTOField<'_> {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: std::io::Write,
     {
@@ -171,9 +212,10 @@ pub struct DNAM {
 }
 impl_from_field!(DNAM, [art_type: ArtType]);
 impl_static_type_named!(DNAM, b"DNAM");
+impl_no_formids!(DNAM);
 impl_static_data_size!(DNAM, FIELDH_SIZE + ArtType::static_data_size());
 impl Writable for DNAM {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: std::io::Write,
     {
@@ -203,7 +245,7 @@ impl Parse<'_> for ArtType {
 }
 impl_static_data_size!(ArtType, u32::static_data_size());
 impl Writable for ArtType {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: std::io::Write,
     {
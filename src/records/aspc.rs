@@ -1,13 +1,17 @@
 use super::{
     common::{
-        CommonRecordInfo, FromRecord, FromRecordError, GeneralRecord, StaticTypeNamed, TypeNamed,
+        CommonRecordInfo, FormId, FormIdVisitor, FromRecord, FromRecordError, GeneralRecord,
+        StaticTypeNamed, TypeNamed,
     },
     fields::{common::GeneralField, edid, obnd},
 };
 use crate::{
-    collect_one, dispatch_all, impl_static_type_named, make_field_getter, make_formid_field,
+    collect_one,
+    context::ParseContext,
+    dispatch_all, impl_formid_visitor, impl_redecode_unknowns, impl_static_type_named,
+    make_field_getter, make_formid_field,
     parse::PResult,
-    util::{DataSize, Writable},
+    util::{record_data_size, DataSize, Writable, WriteError},
 };
 use derive_more::From;
 
@@ -58,7 +62,10 @@ impl<'data> ASPCRecord<'data> {
     );
 }
 impl<'data> FromRecord<'data> for ASPCRecord<'data> {
-    fn from_record(record: GeneralRecord<'data>) -> PResult<Self, FromRecordError<'data>> {
+    fn from_record(
+        record: GeneralRecord<'data>,
+        ctx: &mut ParseContext,
+    ) -> PResult<'data, Self, FromRecordError<'data>> {
         let mut edid_index = None;
         let mut obnd_index = None;
         let mut snam_index = None;
@@ -69,11 +76,11 @@ impl<'data> FromRecord<'data> for ASPCRecord<'data> {
 
         for field in record.fields {
             match field.type_name().as_ref() {
-                b"EDID" => collect_one!(edid::EDID, field => fields; edid_index),
-                b"OBND" => collect_one!(obnd::OBND, field => fields; obnd_index),
-                b"SNAM" => collect_one!(SNAM, field => fields; snam_index),
-                b"RDAT" => collect_one!(RDAT, field => fields; rdat_index),
-                b"BNAM" => collect_one!(BNAM, field => fields; bnam_index),
+                b"EDID" => collect_one!(edid::EDID, field => fields; edid_index, ctx),
+                b"OBND" => collect_one!(obnd::OBND, field => fields; obnd_index, ctx),
+                b"SNAM" => collect_one!(SNAM, field => fields; snam_index, ctx),
+                b"RDAT" => collect_one!(RDAT, field => fields; rdat_index, ctx),
+                b"BNAM" => collect_one!(BNAM, field => fields; bnam_index, ctx),
                 _ => fields.push(field.into()),
             }
         }
@@ -98,6 +105,8 @@ impl<'data> FromRecord<'data> for ASPCRecord<'data> {
     }
 }
 impl_static_type_named!(ASPCRecord<'_>, b"ASPC");
+impl_redecode_unknowns!(ASPCRecord, ASPCField);
+impl_formid_visitor!(ASPCRecord<'_>, [fields]);
 impl DataSize for ASPCRecord<'_> {
     fn data_size(&self) -> usize {
         self.type_name().data_size() +
@@ -107,13 +116,12 @@ impl DataSize for ASPCRecord<'_> {
     }
 }
 impl Writable for ASPCRecord<'_> {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: std::io::Write,
     {
         self.type_name().write_to(w)?;
-        // TODO: assert size fits within
-        (self.fields.data_size() as u32).write_to(w)?;
+        record_data_size(self.type_name(), self.fields.data_size())?.write_to(w)?;
         self.common.write_to(w)?;
         self.fields.write_to(w)
     }
@@ -139,6 +147,41 @@ impl<'data> TypeNamed<'data> for ASPCField<'data> {
         )
     }
 }
+impl FormIdVisitor for ASPCField<'_> {
+    fn visit_formids(&mut self, visit: &mut dyn FnMut(&mut FormId)) {
+        dispatch_all!(
+            ASPCField,
+            self,
+            [EDID, OBND, SNAM, RDAT, BNAM, Unknown],
+            x,
+            { x.visit_formids(visit) }
+        )
+    }
+
+    fn for_each_formid(&self, visit: &mut dyn FnMut(FormId)) {
+        dispatch_all!(
+            ASPCField,
+            self,
+            [EDID, OBND, SNAM, RDAT, BNAM, Unknown],
+            x,
+            { x.for_each_formid(visit) }
+        )
+    }
+
+    fn for_each_field_formid<'name>(
+        &self,
+        name: &'name bstr::BStr,
+        visit: &mut dyn FnMut(&'name bstr::BStr, FormId),
+    ) {
+        dispatch_all!(
+            ASPCField,
+            self,
+            [EDID, OBND, SNAM, RDAT, BNAM, Unknown],
+            x,
+            { x.for_each_field_formid(name, visit) }
+        )
+    }
+}
 impl DataSize for ASPCField<'_> {
     fn data_size(&self) -> usize {
         dispatch_all!(
@@ -151,7 +194,7 @@ impl DataSize for ASPCField<'_> {
     }
 }
 impl Writable for ASPCField<'_> {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: std::io::Write,
     {
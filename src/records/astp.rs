@@ -1,15 +1,17 @@
 use super::{
     common::{
-        CommonRecordInfo, FromRecord, FromRecordError, GeneralRecord, NullTerminatedString,
-        StaticTypeNamed, TypeNamed,
+        CommonRecordInfo, FormId, FormIdVisitor, FromRecord, FromRecordError, GeneralRecord,
+        NullTerminatedString, StaticTypeNamed, TypeNamed,
     },
     fields::{common::GeneralField, edid},
 };
 use crate::{
-    collect_one, dispatch_all, impl_from_field, impl_static_data_size, impl_static_type_named,
-    make_single_value_field,
+    collect_one,
+    context::ParseContext,
+    dispatch_all, impl_formid_visitor, impl_from_field, impl_no_formids, impl_redecode_unknowns,
+    impl_static_data_size, impl_static_type_named, make_single_value_field,
     parse::{PResult, Parse},
-    util::{DataSize, Writable},
+    util::{record_data_size, DataSize, Writable, WriteError},
 };
 use derive_more::From;
 use std::io::Write;
@@ -20,7 +22,10 @@ pub struct ASTPRecord<'data> {
     pub fields: Vec<ASTPField<'data>>,
 }
 impl<'data> FromRecord<'data> for ASTPRecord<'data> {
-    fn from_record(record: GeneralRecord<'data>) -> PResult<Self, FromRecordError<'data>> {
+    fn from_record(
+        record: GeneralRecord<'data>,
+        ctx: &mut ParseContext,
+    ) -> PResult<'data, Self, FromRecordError<'data>> {
         let mut edid_index = None;
         let mut mprt_index = None;
         let mut fprt_index = None;
@@ -32,12 +37,12 @@ impl<'data> FromRecord<'data> for ASTPRecord<'data> {
 
         for field in record.fields {
             match field.type_name().as_ref() {
-                b"EDID" => collect_one!(edid::EDID, field => fields; edid_index),
-                b"MPRT" => collect_one!(MPRT, field => fields; mprt_index),
-                b"FPRT" => collect_one!(FPRT, field => fields; fprt_index),
-                b"FCHT" => collect_one!(FCHT, field => fields; fcht_index),
-                b"MCHT" => collect_one!(MCHT, field => fields; mcht_index),
-                b"DATA" => collect_one!(DATA, field => fields; data_index),
+                b"EDID" => collect_one!(edid::EDID, field => fields; edid_index, ctx),
+                b"MPRT" => collect_one!(MPRT, field => fields; mprt_index, ctx),
+                b"FPRT" => collect_one!(FPRT, field => fields; fprt_index, ctx),
+                b"FCHT" => collect_one!(FCHT, field => fields; fcht_index, ctx),
+                b"MCHT" => collect_one!(MCHT, field => fields; mcht_index, ctx),
+                b"DATA" => collect_one!(DATA, field => fields; data_index, ctx),
                 _ => fields.push(field.into()),
             }
         }
@@ -64,6 +69,8 @@ impl<'data> FromRecord<'data> for ASTPRecord<'data> {
     }
 }
 impl_static_type_named!(ASTPRecord<'_>, b"ASTP");
+impl_redecode_unknowns!(ASTPRecord, ASTPField);
+impl_formid_visitor!(ASTPRecord<'_>, [fields]);
 impl DataSize for ASTPRecord<'_> {
     fn data_size(&self) -> usize {
         self.type_name().data_size() +
@@ -73,13 +80,12 @@ impl DataSize for ASTPRecord<'_> {
     }
 }
 impl Writable for ASTPRecord<'_> {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
         self.type_name().write_to(w)?;
-        // TODO: assert that size fits within a u32
-        (self.fields.data_size() as u32).write_to(w)?;
+        record_data_size(self.type_name(), self.fields.data_size())?.write_to(w)?;
         self.common.write_to(w)?;
         self.fields.write_to(w)
     }
@@ -106,6 +112,41 @@ impl<'data> TypeNamed<'data> for ASTPField<'data> {
         )
     }
 }
+impl FormIdVisitor for ASTPField<'_> {
+    fn visit_formids(&mut self, visit: &mut dyn FnMut(&mut FormId)) {
+        dispatch_all!(
+            ASTPField,
+            self,
+            [EDID, MPRT, FPRT, FCHT, MCHT, DATA, Unknown],
+            x,
+            { x.visit_formids(visit) }
+        )
+    }
+
+    fn for_each_formid(&self, visit: &mut dyn FnMut(FormId)) {
+        dispatch_all!(
+            ASTPField,
+            self,
+            [EDID, MPRT, FPRT, FCHT, MCHT, DATA, Unknown],
+            x,
+            { x.for_each_formid(visit) }
+        )
+    }
+
+    fn for_each_field_formid<'name>(
+        &self,
+        name: &'name bstr::BStr,
+        visit: &mut dyn FnMut(&'name bstr::BStr, FormId),
+    ) {
+        dispatch_all!(
+            ASTPField,
+            self,
+            [EDID, MPRT, FPRT, FCHT, MCHT, DATA, Unknown],
+            x,
+            { x.for_each_field_formid(name, visit) }
+        )
+    }
+}
 impl DataSize for ASTPField<'_> {
     fn data_size(&self) -> usize {
         dispatch_all!(
@@ -118,7 +159,7 @@ impl DataSize for ASTPField<'_> {
     }
 }
 impl Writable for ASTPField<'_> {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -141,6 +182,7 @@ make_single_value_field!(
     'data
 );
 impl_from_field!(MPRT, 'data, [label: NullTerminatedString<'data>]);
+impl_no_formids!(MPRT<'_>);
 
 make_single_value_field!(
     /// Female parent label
@@ -151,6 +193,7 @@ make_single_value_field!(
     'data
 );
 impl_from_field!(FPRT, 'data, [label: NullTerminatedString<'data>]);
+impl_no_formids!(FPRT<'_>);
 
 make_single_value_field!(
     /// Female child label
@@ -161,6 +204,7 @@ make_single_value_field!(
     'data
 );
 impl_from_field!(FCHT, 'data, [label: NullTerminatedString<'data>]);
+impl_no_formids!(FCHT<'_>);
 
 make_single_value_field!(
     /// Male child label
@@ -171,9 +215,11 @@ make_single_value_field!(
     'data
 );
 impl_from_field!(MCHT, 'data, [label: NullTerminatedString<'data>]);
+impl_no_formids!(MCHT<'_>);
 
 make_single_value_field!([Debug, Copy, Clone, Eq, PartialEq], DATA, flags, DATAFlags);
 impl_from_field!(DATA, [flags: DATAFlags]);
+impl_no_formids!(DATA);
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub struct DATAFlags {
@@ -187,7 +233,7 @@ impl Parse<'_> for DATAFlags {
 }
 impl_static_data_size!(DATAFlags, u32::static_data_size());
 impl Writable for DATAFlags {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -1,7 +1,8 @@
 use super::{
     common::{
-        get_field, CollectionList, CommonRecordInfo, ConversionError, FromRecord, FromRecordError,
-        GeneralRecord, NullTerminatedString, StaticTypeNamed, TypeNamed,
+        get_field, CollectionList, CommonRecordInfo, ConversionError, FormId, FormIdVisitor,
+        FromRecord, FromRecordError, GeneralRecord, NullTerminatedString, StaticTypeNamed,
+        TypeNamed,
     },
     fields::{
         common::{
@@ -12,13 +13,17 @@ use super::{
     },
 };
 use crate::{
-    collect_one, collect_one_collection, dispatch_all, impl_from_field, impl_static_data_size,
-    impl_static_type_named, make_field_getter, make_formid_field, make_single_value_field,
+    collect_one, collect_one_collection,
+    context::ParseContext,
+    dispatch_all, impl_formid_visitor, impl_from_field, impl_no_formids, impl_redecode_unknowns,
+    impl_static_data_size, impl_static_type_named, make_field_getter, make_formid_field,
+    make_single_value_field,
     parse::{PResult, Parse},
-    util::{DataSize, Writable},
+    util::{record_data_size, DataSize, Writable, WriteError},
 };
 use derive_more::From;
 use std::{
+    collections::{HashMap, HashSet, VecDeque},
     convert::{TryFrom, TryInto},
     io::Write,
 };
@@ -42,7 +47,7 @@ impl<'data> AVIFRecord<'data> {
         name,
         name_mut,
         AVIFField::FULL,
-        object::FULL
+        object::FULL<'data>
     );
 
     make_field_getter!(
@@ -50,7 +55,7 @@ impl<'data> AVIFRecord<'data> {
         description,
         description_mut,
         AVIFField::DESC,
-        item::DESC
+        item::DESC<'data>
     );
 
     make_field_getter!(
@@ -80,9 +85,149 @@ impl<'data> AVIFRecord<'data> {
         AVIFField::PerkList,
         PerkList<'data>
     );
+
+    /// The perk boxes as a graph, None if the skill has no perks
+    pub fn perk_tree(&self) -> Option<PerkTree<'_>> {
+        self.perks().map(PerkTree::new)
+    }
+
+    /// Adds a box for [perk] with an unused id, connected from each of [parents].
+    /// The perk list is created if the skill has none. Returns the id (INAM) of the new box.
+    pub fn add_perk(
+        &mut self,
+        perk: FormId,
+        position: PerkPosition,
+        parents: &[u32],
+    ) -> Result<u32, PerkTreeError> {
+        if let Some(tree) = self.perk_tree() {
+            if let Some(&parent) = parents.iter().find(|&&x| tree.get(x).is_none()) {
+                return Err(PerkTreeError::MissingPerk(parent));
+            }
+        } else if let Some(&parent) = parents.first() {
+            return Err(PerkTreeError::MissingPerk(parent));
+        }
+
+        let skill = SNAM::new(FormId::new(self.common.id));
+        let id = self
+            .perks()
+            .and_then(|perks| perks.iter().map(|x| x.id.id).max())
+            .map(|x| x + 1)
+            .unwrap_or(0);
+        let new = Perk {
+            perk: PNAM::new(perk),
+            flag: FNAM { flag: 0 },
+            x: XNAM {
+                x_coord: position.grid_x,
+            },
+            y: YNAM {
+                y_coord: position.grid_y,
+            },
+            horizontal: HNAM {
+                horiz_position: position.horizontal,
+            },
+            vertical: VNAM {
+                vert_position: position.vertical,
+            },
+            skill,
+            connecting: Vec::new(),
+            id: INAM { id },
+        };
+        match self.perks_mut() {
+            Some(perks) => perks.push(new),
+            // The perks come last
+            None => self
+                .fields
+                .push(AVIFField::PerkList(PerkList::new(vec![new]))),
+        }
+        for &parent in parents {
+            self.connect_perks(parent, id)?;
+        }
+        Ok(id)
+    }
+
+    /// Removes the box [id], along with every connection to it
+    pub fn remove_perk(&mut self, id: u32) -> Result<Perk, PerkTreeError> {
+        let perks = self.perks_mut().ok_or(PerkTreeError::MissingPerk(id))?;
+        let index = perks
+            .iter()
+            .position(|x| x.id.id == id)
+            .ok_or(PerkTreeError::MissingPerk(id))?;
+        let removed = perks.remove(index);
+        for perk in perks.iter_mut() {
+            perk.connecting.retain(|x| x.id != id);
+        }
+        if perks.is_empty() {
+            let index = self.perks_index().expect("ILE: Unreachable");
+            self.fields.remove(index);
+        }
+        Ok(removed)
+    }
+
+    /// Adds a line from box [from] to box [to]. Returns false if there already was one.
+    pub fn connect_perks(&mut self, from: u32, to: u32) -> Result<bool, PerkTreeError> {
+        {
+            let tree = self.perk_tree().ok_or(PerkTreeError::MissingPerk(from))?;
+            let target = tree.get(to).ok_or(PerkTreeError::MissingPerk(to))?;
+            let source = tree.get(from).ok_or(PerkTreeError::MissingPerk(from))?;
+            if source.connections().any(|x| x == to) {
+                return Ok(false);
+            }
+            if from == to || tree.reaches(target.id.id, from) {
+                return Err(PerkTreeError::Cycle { from, to });
+            }
+        }
+        let perk = self.perk_mut(from).expect("ILE: Unreachable");
+        perk.connecting.push(PerkCNAM { id: to });
+        Ok(true)
+    }
+
+    /// Removes the line from box [from] to box [to]. Returns false if there was none.
+    pub fn disconnect_perks(&mut self, from: u32, to: u32) -> Result<bool, PerkTreeError> {
+        let perk = self
+            .perk_mut(from)
+            .ok_or(PerkTreeError::MissingPerk(from))?;
+        let len = perk.connecting.len();
+        perk.connecting.retain(|x| x.id != to);
+        Ok(perk.connecting.len() != len)
+    }
+
+    /// Replaces the boxes that connect to [id] with [parents]
+    pub fn reconnect_perk(&mut self, id: u32, parents: &[u32]) -> Result<(), PerkTreeError> {
+        let old: Vec<u32> = {
+            let tree = self.perk_tree().ok_or(PerkTreeError::MissingPerk(id))?;
+            tree.get(id).ok_or(PerkTreeError::MissingPerk(id))?;
+            if let Some(&parent) = parents.iter().find(|&&x| tree.get(x).is_none()) {
+                return Err(PerkTreeError::MissingPerk(parent));
+            }
+            tree.parents(id).iter().map(|x| x.id.id).collect()
+        };
+        for &parent in old.iter() {
+            self.disconnect_perks(parent, id)?;
+        }
+        for &parent in parents {
+            if let Err(err) = self.connect_perks(parent, id) {
+                // Put back the way it was
+                for &parent in parents {
+                    self.disconnect_perks(parent, id)?;
+                }
+                for &parent in old.iter() {
+                    self.connect_perks(parent, id)?;
+                }
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    fn perk_mut(&mut self, id: u32) -> Option<&mut Perk> {
+        self.perks_mut()?.iter_mut().find(|x| x.id.id == id)
+    }
 }
 impl<'data> FromRecord<'data> for AVIFRecord<'data> {
-    fn from_record(record: GeneralRecord<'data>) -> PResult<Self, FromRecordError<'data>> {
+    fn from_record(
+        record: GeneralRecord<'data>,
+        ctx: &mut ParseContext,
+    ) -> PResult<'data, Self, FromRecordError<'data>> {
         let mut edid_index = None;
         let mut full_index = None;
         let mut desc_index = None;
@@ -96,14 +241,14 @@ impl<'data> FromRecord<'data> for AVIFRecord<'data> {
 
         while let Some(field) = field_iter.next() {
             match field.type_name().as_ref() {
-                b"EDID" => collect_one!(edid::EDID, field => fields; edid_index),
-                b"FULL" => collect_one!(object::FULL, field => fields; full_index),
-                b"DESC" => collect_one!(item::DESC, field => fields; desc_index),
-                b"ANAM" => collect_one!(ANAM, field => fields; anam_index),
-                b"CNAM" => collect_one!(CNAM, field => fields; cnam_index),
-                b"AVSK" => collect_one!(AVSK, field => fields; avsk_index),
+                b"EDID" => collect_one!(edid::EDID, field => fields; edid_index, ctx),
+                b"FULL" => collect_one!(object::FULL<'data>, field => fields; full_index, ctx),
+                b"DESC" => collect_one!(item::DESC<'data>, field => fields; desc_index, ctx),
+                b"ANAM" => collect_one!(ANAM, field => fields; anam_index, ctx),
+                b"CNAM" => collect_one!(CNAM, field => fields; cnam_index, ctx),
+                b"AVSK" => collect_one!(AVSK, field => fields; avsk_index, ctx),
                 b"PNAM" => {
-                    collect_one_collection!(PNAM, PerkList; field, field_iter => fields; perks_list_index)
+                    collect_one_collection!(PNAM, PerkList; field, field_iter => fields; perks_list_index, ctx)
                 }
                 _ => fields.push(field.into()),
             }
@@ -131,6 +276,8 @@ impl<'data> FromRecord<'data> for AVIFRecord<'data> {
     }
 }
 impl_static_type_named!(AVIFRecord<'_>, b"AVIF");
+impl_redecode_unknowns!(AVIFRecord, AVIFField);
+impl_formid_visitor!(AVIFRecord<'_>, [fields]);
 impl DataSize for AVIFRecord<'_> {
     fn data_size(&self) -> usize {
         self.type_name().data_size() +
@@ -140,13 +287,12 @@ impl DataSize for AVIFRecord<'_> {
     }
 }
 impl Writable for AVIFRecord<'_> {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
         self.type_name().write_to(w)?;
-        // TODO: assert that size fits within a u32
-        (self.fields.data_size() as u32).write_to(w)?;
+        record_data_size(self.type_name(), self.fields.data_size())?.write_to(w)?;
         self.common.write_to(w)?;
         self.fields.write_to(w)
     }
@@ -155,8 +301,8 @@ impl Writable for AVIFRecord<'_> {
 #[derive(Debug, Clone, PartialEq, From)]
 pub enum AVIFField<'data> {
     EDID(edid::EDID<'data>),
-    FULL(object::FULL),
-    DESC(item::DESC),
+    FULL(object::FULL<'data>),
+    DESC(item::DESC<'data>),
     ANAM(ANAM<'data>),
     CNAM(CNAM),
     AVSK(AVSK),
@@ -174,6 +320,41 @@ impl<'data> TypeNamed<'data> for AVIFField<'data> {
         )
     }
 }
+impl FormIdVisitor for AVIFField<'_> {
+    fn visit_formids(&mut self, visit: &mut dyn FnMut(&mut FormId)) {
+        dispatch_all!(
+            AVIFField,
+            self,
+            [EDID, FULL, DESC, ANAM, CNAM, AVSK, PerkList, Unknown],
+            x,
+            { x.visit_formids(visit) }
+        )
+    }
+
+    fn for_each_formid(&self, visit: &mut dyn FnMut(FormId)) {
+        dispatch_all!(
+            AVIFField,
+            self,
+            [EDID, FULL, DESC, ANAM, CNAM, AVSK, PerkList, Unknown],
+            x,
+            { x.for_each_formid(visit) }
+        )
+    }
+
+    fn for_each_field_formid<'name>(
+        &self,
+        name: &'name bstr::BStr,
+        visit: &mut dyn FnMut(&'name bstr::BStr, FormId),
+    ) {
+        dispatch_all!(
+            AVIFField,
+            self,
+            [EDID, FULL, DESC, ANAM, CNAM, AVSK, PerkList, Unknown],
+            x,
+            { x.for_each_field_formid(name, visit) }
+        )
+    }
+}
 impl DataSize for AVIFField<'_> {
     fn data_size(&self) -> usize {
         dispatch_all!(
@@ -186,7 +367,7 @@ impl DataSize for AVIFField<'_> {
     }
 }
 impl Writable for AVIFField<'_> {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -209,6 +390,7 @@ make_single_value_field!(
     'data
 );
 impl_from_field!(ANAM, 'data, [abbreviation: NullTerminatedString]);
+impl_no_formids!(ANAM<'_>);
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 #[repr(u32)]
@@ -231,7 +413,7 @@ impl Parse<'_> for SkillCategory {
 }
 impl_static_data_size!(SkillCategory, u32::static_data_size());
 impl Writable for SkillCategory {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -268,9 +450,10 @@ impl FromField<'_> for CNAM {
     }
 }
 impl_static_type_named!(CNAM, b"CNAM");
+impl_no_formids!(CNAM);
 impl_static_data_size!(CNAM, FIELDH_SIZE + u32::static_data_size());
 impl Writable for CNAM {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -299,9 +482,10 @@ impl_from_field!(
     ]
 );
 impl_static_type_named!(AVSK, b"AVSK");
+impl_no_formids!(AVSK);
 impl_static_data_size!(AVSK, FIELDH_SIZE + (f32::static_data_size() * 4));
 impl Writable for AVSK {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -380,7 +564,23 @@ impl<'data> CollectField<'data, PNAM> for Perk {
         ))
     }
 }
+impl Perk {
+    pub fn position(&self) -> PerkPosition {
+        PerkPosition {
+            grid_x: self.x.x_coord,
+            grid_y: self.y.y_coord,
+            horizontal: self.horizontal.horiz_position,
+            vertical: self.vertical.vert_position,
+        }
+    }
+
+    /// The ids (INAM) of the perk boxes that this box has lines to
+    pub fn connections(&self) -> impl Iterator<Item = u32> + '_ {
+        self.connecting.iter().map(|x| x.id)
+    }
+}
 impl_static_type_named!(Perk, PNAM::static_type_name());
+impl_formid_visitor!(Perk, [perk: PNAM, skill: SNAM]);
 impl DataSize for Perk {
     fn data_size(&self) -> usize {
         self.perk.data_size()
@@ -395,7 +595,7 @@ impl DataSize for Perk {
     }
 }
 impl Writable for Perk {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -411,6 +611,130 @@ impl Writable for Perk {
     }
 }
 
+/// Where a perk box is drawn
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PerkPosition {
+    pub grid_x: i32,
+    pub grid_y: i32,
+    /// Offset within the grid cell
+    pub horizontal: f32,
+    pub vertical: f32,
+}
+
+/// The perk boxes of a skill with their connections (CNAM -> INAM) resolved.
+/// Boxes are referred to by their id (INAM).
+#[derive(Debug, Clone)]
+pub struct PerkTree<'a> {
+    perks: Vec<&'a Perk>,
+    /// INAM -> position in [perks]
+    index: HashMap<u32, usize>,
+    /// INAM -> ids of the boxes that connect to it
+    parents: HashMap<u32, Vec<u32>>,
+}
+impl<'a> PerkTree<'a> {
+    pub fn new(perks: &'a PerkList<'_>) -> PerkTree<'a> {
+        let perks: Vec<&Perk> = perks.iter().collect();
+        let mut index = HashMap::new();
+        let mut parents: HashMap<u32, Vec<u32>> = HashMap::new();
+        for (i, perk) in perks.iter().enumerate() {
+            index.entry(perk.id.id).or_insert(i);
+            for child in perk.connections() {
+                parents.entry(child).or_default().push(perk.id.id);
+            }
+        }
+        PerkTree {
+            perks,
+            index,
+            parents,
+        }
+    }
+
+    pub fn get(&self, id: u32) -> Option<&'a Perk> {
+        self.index.get(&id).map(|&i| self.perks[i])
+    }
+
+    /// Every box, in the order they are stored
+    pub fn perks(&self) -> &[&'a Perk] {
+        &self.perks
+    }
+
+    /// Boxes that no other box connects to. Normally this is only the box with a PNAM of 0,
+    /// which the tree is drawn from.
+    pub fn roots(&self) -> Vec<&'a Perk> {
+        self.perks
+            .iter()
+            .filter(|perk| !self.parents.contains_key(&perk.id.id))
+            .copied()
+            .collect()
+    }
+
+    /// Boxes that [id] connects to. Connections to boxes that don't exist are skipped.
+    pub fn children(&self, id: u32) -> Vec<&'a Perk> {
+        self.get(id)
+            .map(|perk| perk.connections().filter_map(|x| self.get(x)).collect())
+            .unwrap_or_default()
+    }
+
+    /// Boxes that connect to [id]
+    pub fn parents(&self, id: u32) -> Vec<&'a Perk> {
+        self.parents
+            .get(&id)
+            .map(|parents| parents.iter().filter_map(|&x| self.get(x)).collect())
+            .unwrap_or_default()
+    }
+
+    pub fn position(&self, id: u32) -> Option<PerkPosition> {
+        self.get(id).map(Perk::position)
+    }
+
+    /// Connections (from, to) that point at boxes which don't exist
+    pub fn dangling(&self) -> Vec<(u32, u32)> {
+        self.perks
+            .iter()
+            .flat_map(|perk| perk.connections().map(move |x| (perk.id.id, x)))
+            .filter(|(_, to)| !self.index.contains_key(to))
+            .collect()
+    }
+
+    /// Whether box [to] can be reached by following lines from box [from]
+    pub fn reaches(&self, from: u32, to: u32) -> bool {
+        let mut seen = HashSet::new();
+        let mut stack = vec![from];
+        while let Some(id) = stack.pop() {
+            if id == to {
+                return true;
+            }
+            if seen.insert(id) {
+                stack.extend(self.get(id).into_iter().flat_map(Perk::connections));
+            }
+        }
+        false
+    }
+
+    /// Every box reachable from the roots, breadth first, each only once
+    pub fn traverse(&self) -> Vec<&'a Perk> {
+        let mut seen = HashSet::new();
+        let mut queue: VecDeque<&Perk> = self.roots().into_iter().collect();
+        let mut order = Vec::new();
+        while let Some(perk) = queue.pop_front() {
+            if !seen.insert(perk.id.id) {
+                continue;
+            }
+            order.push(perk);
+            queue.extend(self.children(perk.id.id));
+        }
+        order
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum PerkTreeError {
+    /// There is no perk box with this id
+    MissingPerk(u32),
+    /// Connecting the boxes would create a loop
+    Cycle { from: u32, to: u32 },
+}
+
 // TODO: make th is be an option (and special handle writing)?
 make_formid_field!(
     // ->PERK, or 0 for the first
@@ -489,3 +813,121 @@ make_single_value_field!(
     u32
 );
 impl_from_field!(INAM, [id: u32]);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn perk(id: u32, perk: u32, x: i32, connecting: &[u32]) -> Perk {
+        Perk {
+            perk: PNAM::new(FormId::new(perk)),
+            flag: FNAM { flag: 0 },
+            x: XNAM { x_coord: x },
+            y: YNAM { y_coord: id as i32 },
+            horizontal: HNAM {
+                horiz_position: 0.0,
+            },
+            vertical: VNAM { vert_position: 0.5 },
+            skill: SNAM::new(FormId::new(0x44C)),
+            connecting: connecting.iter().map(|&id| PerkCNAM { id }).collect(),
+            id: INAM { id },
+        }
+    }
+
+    #[test]
+    fn test_perk_tree() {
+        let perks = PerkList::new(vec![
+            perk(0, 0, 0, &[1, 2]),
+            perk(1, 0x100, 1, &[3]),
+            perk(2, 0x101, 2, &[3, 9]),
+            perk(3, 0x102, 3, &[]),
+        ]);
+        let tree = PerkTree::new(&perks);
+        let ids = |perks: Vec<&Perk>| perks.iter().map(|x| x.id.id).collect::<Vec<_>>();
+
+        assert_eq!(ids(tree.roots()), vec![0]);
+        assert_eq!(ids(tree.children(0)), vec![1, 2]);
+        assert_eq!(ids(tree.children(2)), vec![3]);
+        assert_eq!(ids(tree.parents(3)), vec![1, 2]);
+        assert_eq!(tree.position(2).map(|x| x.grid_x), Some(2));
+        assert_eq!(tree.dangling(), vec![(2, 9)]);
+        assert_eq!(ids(tree.traverse()), vec![0, 1, 2, 3]);
+        assert!(tree.get(9).is_none());
+    }
+
+    #[test]
+    fn test_perk_tree_mutation() {
+        let mut record = AVIFRecord {
+            common: CommonRecordInfo::test_default(),
+            fields: vec![AVIFField::CNAM(CNAM::Unknown(0))],
+        };
+        let position = PerkPosition {
+            grid_x: 0,
+            grid_y: 0,
+            horizontal: 0.0,
+            vertical: 0.0,
+        };
+        let root = record.add_perk(FormId::new(0), position, &[]).unwrap();
+        assert_eq!(root, 0);
+        assert_eq!(record.perks_index(), Some(1));
+        assert_eq!(
+            record.add_perk(FormId::new(0x100), position, &[5]),
+            Err(PerkTreeError::MissingPerk(5))
+        );
+        let a = record
+            .add_perk(FormId::new(0x100), position, &[root])
+            .unwrap();
+        let b = record.add_perk(FormId::new(0x101), position, &[a]).unwrap();
+        assert_eq!((a, b), (1, 2));
+        assert_eq!(
+            record
+                .perks()
+                .unwrap()
+                .iter()
+                .last()
+                .unwrap()
+                .skill
+                .formid
+                .id,
+            0x420
+        );
+
+        assert_eq!(record.connect_perks(root, b), Ok(true));
+        assert_eq!(record.connect_perks(root, b), Ok(false));
+        assert_eq!(
+            record.connect_perks(b, root),
+            Err(PerkTreeError::Cycle { from: b, to: root })
+        );
+        assert_eq!(record.disconnect_perks(root, b), Ok(true));
+        assert_eq!(record.disconnect_perks(root, b), Ok(false));
+
+        record.reconnect_perk(b, &[root]).unwrap();
+        let ids = |perks: Vec<&Perk>| perks.iter().map(|x| x.id.id).collect::<Vec<_>>();
+        assert_eq!(ids(record.perk_tree().unwrap().parents(b)), vec![root]);
+        // Would make a loop, so nothing changes
+        assert_eq!(
+            record.reconnect_perk(a, &[root, b, 7]),
+            Err(PerkTreeError::MissingPerk(7))
+        );
+        assert!(record.connect_perks(a, b).unwrap());
+        assert_eq!(
+            record.reconnect_perk(a, &[b]),
+            Err(PerkTreeError::Cycle { from: b, to: a })
+        );
+        assert_eq!(ids(record.perk_tree().unwrap().parents(a)), vec![root]);
+        assert_eq!(ids(record.perk_tree().unwrap().parents(b)), vec![root, a]);
+
+        let removed = record.remove_perk(a).unwrap();
+        assert_eq!(removed.id.id, a);
+        assert_eq!(ids(record.perk_tree().unwrap().children(root)), vec![b]);
+        // New ids never reuse one that is still in use
+        assert_eq!(record.add_perk(FormId::new(0x102), position, &[]), Ok(3));
+
+        for id in [root, b, 3].iter() {
+            record.remove_perk(*id).unwrap();
+        }
+        assert_eq!(record.perks_index(), None);
+        let data = crate::assert_size_output!(record);
+        assert_eq!(data.len(), record.data_size());
+    }
+}
@@ -1,7 +1,7 @@
 use super::{
     common::{
-        lstring::LString, CommonRecordInfo, FromRecord, FromRecordError, GeneralRecord,
-        StaticTypeNamed, TypeNamed,
+        lstring::LString, CommonRecordInfo, FormId, FormIdVisitor, FromRecord, FromRecordError,
+        GeneralRecord, StaticTypeNamed, TypeNamed,
     },
     fields::{
         common::{item, object, write_field_header, GeneralField, FIELDH_SIZE},
@@ -9,10 +9,13 @@ use super::{
     },
 };
 use crate::{
-    collect_one, collect_one_collection, dispatch_all, impl_from_field, impl_static_data_size,
-    impl_static_type_named, make_formid_field, make_single_value_field,
+    collect_one, collect_one_collection,
+    context::ParseContext,
+    dispatch_all, impl_formid_visitor, impl_from_field, impl_keyworded, impl_lstring_field,
+    impl_no_formids, impl_redecode_unknowns, impl_static_data_size, impl_static_type_named,
+    make_formid_field, make_single_value_field,
     parse::{PResult, Parse},
-    util::{DataSize, Writable},
+    util::{record_data_size, DataSize, Writable, WriteError},
 };
 use derive_more::From;
 use std::io::Write;
@@ -23,7 +26,10 @@ pub struct BOOKRecord<'data> {
     pub fields: Vec<BOOKField<'data>>,
 }
 impl<'data> FromRecord<'data> for BOOKRecord<'data> {
-    fn from_record(record: GeneralRecord<'data>) -> PResult<Self, FromRecordError<'data>> {
+    fn from_record(
+        record: GeneralRecord<'data>,
+        ctx: &mut ParseContext,
+    ) -> PResult<'data, Self, FromRecordError<'data>> {
         let mut edid_index = None;
         let mut vmad_index = None;
         let mut obnd_index = None;
@@ -45,29 +51,29 @@ impl<'data> FromRecord<'data> for BOOKRecord<'data> {
 
         while let Some(field) = field_iter.next() {
             match field.type_name().as_ref() {
-                b"EDID" => collect_one!(edid::EDID, field => fields; edid_index),
+                b"EDID" => collect_one!(edid::EDID, field => fields; edid_index, ctx),
                 b"VMAD" => {
-                    collect_one!(vmad::VMAD<'data, vmad::NoFragments>, field => fields; vmad_index)
+                    collect_one!(vmad::VMAD<'data, vmad::NoFragments>, field => fields; vmad_index, ctx)
                 }
-                b"OBND" => collect_one!(obnd::OBND, field => fields; obnd_index),
-                b"FULL" => collect_one!(object::FULL, field => fields; full_index),
+                b"OBND" => collect_one!(obnd::OBND, field => fields; obnd_index, ctx),
+                b"FULL" => collect_one!(object::FULL<'data>, field => fields; full_index, ctx),
                 b"MODL" => {
-                    collect_one_collection!(modl::MODL, modl::MODLCollection; field, field_iter => fields; modl_collection_index)
+                    collect_one_collection!(modl::MODL, modl::MODLCollection; field, field_iter => fields; modl_collection_index, ctx)
                 }
-                b"ICON" => collect_one!(item::ICON, field => fields; icon_index),
-                b"MICO" => collect_one!(item::MICO, field => fields; mico_index),
-                b"DESC" => collect_one!(item::DESC, field => fields; desc_index),
+                b"ICON" => collect_one!(item::ICON, field => fields; icon_index, ctx),
+                b"MICO" => collect_one!(item::MICO, field => fields; mico_index, ctx),
+                b"DESC" => collect_one!(item::DESC<'data>, field => fields; desc_index, ctx),
                 b"DEST" => {
-                    collect_one_collection!(dest::DEST, dest::DESTCollection; field, field_iter => fields; dest_collection_index)
+                    collect_one_collection!(dest::DEST, dest::DESTCollection; field, field_iter => fields; dest_collection_index, ctx)
                 }
-                b"YNAM" => collect_one!(item::YNAM, field => fields; ynam_index),
-                b"ZNAM" => collect_one!(item::ZNAM, field => fields; znam_index),
+                b"YNAM" => collect_one!(item::YNAM, field => fields; ynam_index, ctx),
+                b"ZNAM" => collect_one!(item::ZNAM, field => fields; znam_index, ctx),
                 b"KSIZ" => {
-                    collect_one_collection!(kwda::KSIZ, kwda::KWDACollection; field, field_iter => fields; kwda_collection_index)
+                    collect_one_collection!(repeatable: kwda::KSIZ, kwda::KWDACollection; field, field_iter => fields; kwda_collection_index)
                 }
-                b"DATA" => collect_one!(DATA, field => fields; data_index),
-                b"INAM" => collect_one!(INAM, field => fields; inam_index),
-                b"CNAM" => collect_one!(CNAM, field => fields; cnam_index),
+                b"DATA" => collect_one!(DATA, field => fields; data_index, ctx),
+                b"INAM" => collect_one!(INAM, field => fields; inam_index, ctx),
+                b"CNAM" => collect_one!(CNAM, field => fields; cnam_index, ctx),
                 _ => fields.push(field.into()),
             }
         }
@@ -97,7 +103,34 @@ impl<'data> FromRecord<'data> for BOOKRecord<'data> {
         }
     }
 }
+impl BOOKRecord<'_> {
+    fn data(&self) -> Option<&DATA> {
+        self.fields.iter().find_map(|x| match x {
+            BOOKField::DATA(x) => Some(x),
+            _ => None,
+        })
+    }
+}
+impl item::Item for BOOKRecord<'_> {
+    fn value(&self) -> Option<item::Gold> {
+        self.data().map(|x| x.value)
+    }
+
+    fn weight(&self) -> Option<item::Weight> {
+        self.data().map(|x| x.weight)
+    }
+
+    fn is_playable(&self) -> bool {
+        !self
+            .data()
+            .map(|x| x.flags.cant_be_taken())
+            .unwrap_or(false)
+    }
+}
 impl_static_type_named!(BOOKRecord<'_>, b"BOOK");
+impl_redecode_unknowns!(BOOKRecord, BOOKField);
+impl_formid_visitor!(BOOKRecord<'_>, [fields]);
+impl_keyworded!(BOOKRecord, BOOKField);
 impl DataSize for BOOKRecord<'_> {
     fn data_size(&self) -> usize {
         self.type_name().data_size() +
@@ -107,13 +140,12 @@ impl DataSize for BOOKRecord<'_> {
     }
 }
 impl Writable for BOOKRecord<'_> {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: std::io::Write,
     {
         self.type_name().write_to(w)?;
-        // TODO: assert size fits within
-        (self.fields.data_size() as u32).write_to(w)?;
+        record_data_size(self.type_name(), self.fields.data_size())?.write_to(w)?;
         self.common.write_to(w)?;
         self.fields.write_to(w)
     }
@@ -124,18 +156,18 @@ pub enum BOOKField<'data> {
     EDID(edid::EDID<'data>),
     VMAD(vmad::VMAD<'data, vmad::NoFragments>),
     OBND(obnd::OBND),
-    FULL(object::FULL),
+    FULL(object::FULL<'data>),
     MODLCollection(modl::MODLCollection<'data>),
     ICON(item::ICON<'data>),
     MICO(item::MICO<'data>),
-    DESC(item::DESC),
+    DESC(item::DESC<'data>),
     DESTCollection(dest::DESTCollection<'data>),
     YNAM(item::YNAM),
     ZNAM(item::ZNAM),
     KWDACollection(kwda::KWDACollection),
     DATA(DATA),
     INAM(INAM),
-    CNAM(CNAM),
+    CNAM(CNAM<'data>),
     Unknown(GeneralField<'data>),
 }
 impl<'data> TypeNamed<'data> for BOOKField<'data> {
@@ -166,6 +198,92 @@ impl<'data> TypeNamed<'data> for BOOKField<'data> {
         )
     }
 }
+impl FormIdVisitor for BOOKField<'_> {
+    fn visit_formids(&mut self, visit: &mut dyn FnMut(&mut FormId)) {
+        dispatch_all!(
+            BOOKField,
+            self,
+            [
+                EDID,
+                VMAD,
+                OBND,
+                FULL,
+                MODLCollection,
+                ICON,
+                MICO,
+                DESC,
+                DESTCollection,
+                YNAM,
+                ZNAM,
+                KWDACollection,
+                DATA,
+                INAM,
+                CNAM,
+                Unknown
+            ],
+            x,
+            { x.visit_formids(visit) }
+        )
+    }
+
+    fn for_each_formid(&self, visit: &mut dyn FnMut(FormId)) {
+        dispatch_all!(
+            BOOKField,
+            self,
+            [
+                EDID,
+                VMAD,
+                OBND,
+                FULL,
+                MODLCollection,
+                ICON,
+                MICO,
+                DESC,
+                DESTCollection,
+                YNAM,
+                ZNAM,
+                KWDACollection,
+                DATA,
+                INAM,
+                CNAM,
+                Unknown
+            ],
+            x,
+            { x.for_each_formid(visit) }
+        )
+    }
+
+    fn for_each_field_formid<'name>(
+        &self,
+        name: &'name bstr::BStr,
+        visit: &mut dyn FnMut(&'name bstr::BStr, FormId),
+    ) {
+        dispatch_all!(
+            BOOKField,
+            self,
+            [
+                EDID,
+                VMAD,
+                OBND,
+                FULL,
+                MODLCollection,
+                ICON,
+                MICO,
+                DESC,
+                DESTCollection,
+                YNAM,
+                ZNAM,
+                KWDACollection,
+                DATA,
+                INAM,
+                CNAM,
+                Unknown
+            ],
+            x,
+            { x.for_each_field_formid(name, visit) }
+        )
+    }
+}
 impl DataSize for BOOKField<'_> {
     fn data_size(&self) -> usize {
         dispatch_all!(
@@ -195,7 +313,7 @@ impl DataSize for BOOKField<'_> {
     }
 }
 impl Writable for BOOKField<'_> {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: std::io::Write,
     {
@@ -253,6 +371,22 @@ impl_from_field!(
     ]
 );
 impl_static_type_named!(DATA, b"DATA");
+impl FormIdVisitor for DATA {
+    /// The spell that is taught, as otherwise [teaches] is a skill
+    fn visit_formids(&mut self, visit: &mut dyn FnMut(&mut FormId)) {
+        if self.flags.teaches_spell() {
+            let mut spell = FormId::new(self.teaches);
+            visit(&mut spell);
+            self.teaches = spell.id;
+        }
+    }
+
+    fn for_each_formid(&self, visit: &mut dyn FnMut(FormId)) {
+        if self.flags.teaches_spell() {
+            visit(FormId::new(self.teaches));
+        }
+    }
+}
 impl_static_data_size!(
     DATA,
     FIELDH_SIZE
@@ -264,7 +398,7 @@ impl_static_data_size!(
         + item::Weight::static_data_size()
 );
 impl Writable for DATA {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -286,6 +420,15 @@ pub struct DATAFlags {
     /// 0b1000: Read. UESP guesses that this is what is set in the save file if the book has been read.
     pub flags: u8,
 }
+impl DATAFlags {
+    pub fn cant_be_taken(&self) -> bool {
+        (self.flags & 0b0010) != 0
+    }
+
+    pub fn teaches_spell(&self) -> bool {
+        (self.flags & 0b0100) != 0
+    }
+}
 impl Parse<'_> for DATAFlags {
     fn parse(data: &[u8]) -> PResult<Self> {
         let (data, flags) = u8::parse(data)?;
@@ -294,7 +437,7 @@ impl Parse<'_> for DATAFlags {
 }
 impl_static_data_size!(DATAFlags, u8::static_data_size());
 impl Writable for DATAFlags {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -309,9 +452,11 @@ make_formid_field!(
 
 make_single_value_field!(
     /// Description.
-    [Debug, Copy, Clone, Eq, PartialEq],
+    [Debug, Clone, Eq, PartialEq],
     CNAM,
     description,
-    LString
+    LString,
+    'data
 );
-impl_from_field!(CNAM, [description: LString]);
+impl_lstring_field!(CNAM, description);
+impl_no_formids!(CNAM<'_>);
@@ -0,0 +1,168 @@
+// The order that the Creation Kit writes the fields of each record type in. Records that were
+// built or edited in code get their fields in whatever order they were added, which the game
+// accepts but which makes them differ from what other tools write, so this puts them back in order
+// when writing.
+// Only the fields that start an entry are listed. A field that isn't listed is part of the entry
+// of the listed field before it, and moves along with it: such as the DATA after each MAST, or the
+// MODT after a MODL. Entries of the same field keep their order.
+
+use super::{
+    common::{record_flag, GeneralRecord},
+    fields::common::GeneralField,
+    RecordRef,
+};
+use crate::{
+    parse::Parse,
+    util::{Writable, WriteError},
+};
+use std::io::Write;
+
+/// Order of the fields of records of [type_name], or None if it isn't known. Types whose nested
+/// entries reuse the signatures of their top-level fields, such as the perk trees of AVIF, have
+/// none, as they can't be ordered going by signatures alone
+pub fn field_order(type_name: &[u8]) -> Option<&'static [&'static [u8; 4]]> {
+    Some(match type_name {
+        b"TES4" => &[
+            b"HEDR", b"OFST", b"DELE", b"CNAM", b"SNAM", b"MAST", b"ONAM", b"INTV", b"INCC",
+        ],
+        b"AACT" => &[b"EDID", b"CNAM"],
+        b"ACHR" => &[
+            b"EDID", b"VMAD", b"NAME", b"XEZN", b"XRGD", b"XRGB", b"XPRD", b"XPPA", b"INAM",
+            b"PDTO", b"XLCM", b"XAPD", b"XAPR", b"XLRT", b"XHOR", b"XESP", b"XOWN", b"XRNK",
+            b"XLCN", b"XLKR", b"XLRL", b"XSCL", b"DATA",
+        ],
+        b"ACTI" => &[
+            b"EDID", b"VMAD", b"OBND", b"FULL", b"MODL", b"DEST", b"KSIZ", b"KWDA", b"PNAM",
+            b"SNAM", b"VNAM", b"WNAM", b"RNAM", b"FNAM", b"KNAM",
+        ],
+        b"ADDN" => &[b"EDID", b"OBND", b"MODL", b"DATA", b"SNAM", b"DNAM"],
+        b"ALCH" => &[
+            b"EDID", b"OBND", b"FULL", b"KSIZ", b"KWDA", b"DESC", b"MODL", b"ICON", b"MICO",
+            b"YNAM", b"ZNAM", b"DATA", b"ENIT", b"EFID",
+        ],
+        b"AMMO" => &[
+            b"EDID", b"OBND", b"FULL", b"MODL", b"ICON", b"MICO", b"DEST", b"YNAM", b"ZNAM",
+            b"DESC", b"KSIZ", b"KWDA", b"DATA", b"ONAM",
+        ],
+        b"ANIO" => &[b"EDID", b"MODL", b"BNAM"],
+        b"APPA" => &[
+            b"EDID", b"VMAD", b"OBND", b"FULL", b"MODL", b"ICON", b"MICO", b"DEST", b"YNAM",
+            b"ZNAM", b"QUAL", b"DESC", b"DATA",
+        ],
+        b"ARMA" => &[
+            b"EDID", b"BODT", b"BOD2", b"RNAM", b"DNAM", b"MOD2", b"MOD3", b"MOD4", b"MOD5",
+            b"NAM0", b"NAM1", b"NAM2", b"NAM3", b"MODL", b"SNDD", b"ONAM",
+        ],
+        b"ARMO" => &[
+            b"EDID", b"VMAD", b"OBND", b"FULL", b"EITM", b"EAMT", b"MOD2", b"ICON", b"MICO",
+            b"MOD4", b"ICO2", b"MIC2", b"BODT", b"BOD2", b"DEST", b"YNAM", b"ZNAM", b"BMCT",
+            b"ETYP", b"BIDS", b"BAMT", b"RNAM", b"KSIZ", b"KWDA", b"DESC", b"MODL", b"DATA",
+            b"DNAM", b"TNAM",
+        ],
+        b"ARTO" => &[b"EDID", b"OBND", b"MODL", b"DNAM"],
+        b"ASPC" => &[b"EDID", b"OBND", b"SNAM", b"RDAT", b"BNAM"],
+        b"ASTP" => &[b"EDID", b"MPRT", b"FPRT", b"MCHT", b"FCHT", b"DATA"],
+        b"BOOK" => &[
+            b"EDID", b"VMAD", b"OBND", b"FULL", b"MODL", b"ICON", b"MICO", b"DESC", b"DEST",
+            b"YNAM", b"ZNAM", b"KSIZ", b"KWDA", b"DATA", b"INAM", b"CNAM",
+        ],
+        b"FLST" => &[b"EDID", b"LNAM"],
+        _ => return None,
+    })
+}
+
+/// Puts [fields] of a record of [type_name] in the order of [field_order]. Returns false if the
+/// order isn't known, or they were already in order
+pub fn sort_fields(type_name: &[u8], fields: &mut Vec<GeneralField<'_>>) -> bool {
+    let order = match field_order(type_name) {
+        Some(order) => order,
+        None => return false,
+    };
+    // Each entry is its position in the order, and the fields in it. Fields before the first
+    // listed field stay first
+    let mut entries: Vec<(usize, Vec<GeneralField<'_>>)> = Vec::new();
+    for field in fields.drain(..) {
+        match order.iter().position(|x| field.type_name == &x[..]) {
+            Some(position) => entries.push((position + 1, vec![field])),
+            None => match entries.last_mut() {
+                Some((_, entry)) => entry.push(field),
+                None => entries.push((0, vec![field])),
+            },
+        }
+    }
+    let sorted = entries.windows(2).all(|x| x[0].0 <= x[1].0);
+    entries.sort_by_key(|(position, _)| *position);
+    fields.extend(entries.into_iter().flat_map(|(_, entry)| entry));
+    !sorted
+}
+
+/// Writes [record] with its fields in the order of [field_order]. Compressed records and records
+/// of types with no known order are written as they are
+pub fn write_canonical<T: Write>(record: RecordRef<'_, '_>, w: &mut T) -> Result<(), WriteError> {
+    let buffer = record.to_vec()?;
+    if record.common().flags.is(record_flag::COMPRESSED) {
+        return Ok(w.write_all(&buffer)?);
+    }
+    if let Ok((_, mut general)) = GeneralRecord::parse(&buffer) {
+        if sort_fields(general.type_name, &mut general.fields) {
+            return general.write_to(w);
+        }
+    }
+    Ok(w.write_all(&buffer)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{records::common::CommonRecordInfo, util::DataSize};
+    use bstr::ByteSlice;
+
+    fn names(fields: &[GeneralField<'_>]) -> Vec<String> {
+        fields.iter().map(|x| x.type_name.to_string()).collect()
+    }
+
+    #[test]
+    fn test_sort_fields() {
+        let field = |name: &'static [u8]| GeneralField::new(name.as_bstr(), &[]);
+        let mut fields = vec![
+            field(b"INTV"),
+            field(b"MAST"),
+            field(b"DATA"),
+            field(b"HEDR"),
+            field(b"MAST"),
+            field(b"DATA"),
+            field(b"CNAM"),
+        ];
+        assert!(sort_fields(b"TES4", &mut fields));
+        assert_eq!(
+            names(&fields),
+            vec!["HEDR", "CNAM", "MAST", "DATA", "MAST", "DATA", "INTV"]
+        );
+        assert!(!sort_fields(b"TES4", &mut fields));
+        assert!(!sort_fields(b"AVIF", &mut fields));
+
+        // Unlisted fields before any listed one stay first
+        let mut fields = vec![field(b"ZZZZ"), field(b"CNAM"), field(b"EDID")];
+        assert!(sort_fields(b"AACT", &mut fields));
+        assert_eq!(names(&fields), vec!["ZZZZ", "EDID", "CNAM"]);
+    }
+
+    #[test]
+    fn test_write_canonical() {
+        let general = GeneralRecord {
+            type_name: b"FLST".as_bstr(),
+            common: CommonRecordInfo::test_default(),
+            fields: vec![
+                GeneralField::new(b"LNAM".as_bstr(), &[1, 0, 0, 0]),
+                GeneralField::new(b"EDID".as_bstr(), b"List\0"),
+                GeneralField::new(b"LNAM".as_bstr(), &[2, 0, 0, 0]),
+            ],
+        };
+        let mut data = Vec::new();
+        write_canonical(RecordRef::Unknown(&general), &mut data).unwrap();
+        let (_, written) = GeneralRecord::parse(&data).unwrap();
+        assert_eq!(names(&written.fields), vec!["EDID", "LNAM", "LNAM"]);
+        assert_eq!(written.fields[1].data, &[1, 0, 0, 0]);
+        assert_eq!(data.len(), general.data_size());
+    }
+}
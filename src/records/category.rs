@@ -0,0 +1,252 @@
+// Coarse groupings of record types along with readable names for them, as the Creation Kit shows
+// them, so that front-ends and reports can sort records without keeping their own tables.
+
+use super::{common::TypeNamed, Record, RecordRef};
+use std::fmt::{Display, Formatter};
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum RecordCategory {
+    /// Things that can be in an inventory, and the lists and recipes that produce them
+    Items,
+    /// NPCs and what defines them, such as races, factions and AI packages
+    Actors,
+    /// Cells, worldspaces, placed references and the objects that are placed in them
+    World,
+    /// Spells, enchantments, magic effects and shouts
+    Magic,
+    Audio,
+    /// Shaders, image spaces, art objects and other effects
+    Visual,
+    /// Dialogue topics and responses
+    Dialogue,
+    /// Quests, scenes and the story manager
+    Quests,
+    /// Perks, messages, projectiles and other rules of play
+    Gameplay,
+    /// The file header, game settings, globals, keywords and lists
+    Meta,
+    /// Types this table doesn't know of
+    Unknown,
+}
+impl Display for RecordCategory {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            RecordCategory::Items => "Items",
+            RecordCategory::Actors => "Actors",
+            RecordCategory::World => "World",
+            RecordCategory::Magic => "Magic",
+            RecordCategory::Audio => "Audio",
+            RecordCategory::Visual => "Visual",
+            RecordCategory::Dialogue => "Dialogue",
+            RecordCategory::Quests => "Quests",
+            RecordCategory::Gameplay => "Gameplay",
+            RecordCategory::Meta => "Meta",
+            RecordCategory::Unknown => "Unknown",
+        })
+    }
+}
+
+use RecordCategory::*;
+
+/// Each record type, its category, and its readable name
+const RECORD_TYPES: &[(&[u8; 4], RecordCategory, &str)] = &[
+    (b"TES4", Meta, "File Header"),
+    (b"AACT", Gameplay, "Action"),
+    (b"ACHR", World, "Placed NPC"),
+    (b"ACTI", World, "Activator"),
+    (b"ADDN", Visual, "Addon Node"),
+    (b"ALCH", Items, "Ingestible"),
+    (b"AMMO", Items, "Ammunition"),
+    (b"ANIO", Visual, "Animated Object"),
+    (b"APPA", Items, "Alchemical Apparatus"),
+    (b"ARMA", Items, "Armor Addon"),
+    (b"ARMO", Items, "Armor"),
+    (b"ARTO", Visual, "Art Object"),
+    (b"ASPC", Audio, "Acoustic Space"),
+    (b"ASTP", Actors, "Association Type"),
+    (b"AVIF", Actors, "Actor Value Information"),
+    (b"BOOK", Items, "Book"),
+    (b"BPTD", Actors, "Body Part Data"),
+    (b"CAMS", Visual, "Camera Shot"),
+    (b"CELL", World, "Cell"),
+    (b"CLAS", Actors, "Class"),
+    (b"CLFM", Visual, "Color"),
+    (b"CLMT", World, "Climate"),
+    (b"COBJ", Items, "Constructible Object"),
+    (b"COLL", World, "Collision Layer"),
+    (b"CONT", World, "Container"),
+    (b"CPTH", Visual, "Camera Path"),
+    (b"CSTY", Actors, "Combat Style"),
+    (b"DEBR", Visual, "Debris"),
+    (b"DIAL", Dialogue, "Dialog Topic"),
+    (b"DLBR", Dialogue, "Dialog Branch"),
+    (b"DLVW", Dialogue, "Dialog View"),
+    (b"DOBJ", Meta, "Default Object Manager"),
+    (b"DOOR", World, "Door"),
+    (b"DUAL", Magic, "Dual Cast Data"),
+    (b"ECZN", World, "Encounter Zone"),
+    (b"EFSH", Visual, "Effect Shader"),
+    (b"ENCH", Magic, "Object Effect"),
+    (b"EQUP", Items, "Equip Type"),
+    (b"EXPL", Visual, "Explosion"),
+    (b"EYES", Actors, "Eyes"),
+    (b"FACT", Actors, "Faction"),
+    (b"FLOR", World, "Flora"),
+    (b"FLST", Meta, "Form List"),
+    (b"FSTP", Audio, "Footstep"),
+    (b"FSTS", Audio, "Footstep Set"),
+    (b"FURN", World, "Furniture"),
+    (b"GLOB", Meta, "Global Variable"),
+    (b"GMST", Meta, "Game Setting"),
+    (b"GRAS", World, "Grass"),
+    (b"HAZD", World, "Hazard"),
+    (b"HDPT", Actors, "Head Part"),
+    (b"IDLE", Actors, "Idle Animation"),
+    (b"IDLM", World, "Idle Marker"),
+    (b"IMAD", Visual, "Image Space Adapter"),
+    (b"IMGS", Visual, "Image Space"),
+    (b"INFO", Dialogue, "Dialog Response"),
+    (b"INGR", Items, "Ingredient"),
+    (b"IPCT", Visual, "Impact"),
+    (b"IPDS", Visual, "Impact Data Set"),
+    (b"KEYM", Items, "Key"),
+    (b"KYWD", Meta, "Keyword"),
+    (b"LAND", World, "Landscape"),
+    (b"LCRT", World, "Location Reference Type"),
+    (b"LCTN", World, "Location"),
+    (b"LENS", Visual, "Lens Flare"),
+    (b"LGTM", Visual, "Lighting Template"),
+    (b"LIGH", World, "Light"),
+    (b"LSCR", Visual, "Load Screen"),
+    (b"LTEX", World, "Landscape Texture"),
+    (b"LVLI", Items, "Leveled Item"),
+    (b"LVLN", Actors, "Leveled NPC"),
+    (b"LVSP", Magic, "Leveled Spell"),
+    (b"MATO", Visual, "Material Object"),
+    (b"MATT", Visual, "Material Type"),
+    (b"MESG", Gameplay, "Message"),
+    (b"MGEF", Magic, "Magic Effect"),
+    (b"MISC", Items, "Misc. Item"),
+    (b"MOVT", Actors, "Movement Type"),
+    (b"MSTT", World, "Moveable Static"),
+    (b"MUSC", Audio, "Music Type"),
+    (b"MUST", Audio, "Music Track"),
+    (b"NAVI", World, "Navigation Mesh Info Map"),
+    (b"NAVM", World, "Navigation Mesh"),
+    (b"NPC_", Actors, "Non-Player Character"),
+    (b"OTFT", Items, "Outfit"),
+    (b"PACK", Actors, "Package"),
+    (b"PERK", Gameplay, "Perk"),
+    (b"PGRE", World, "Placed Projectile"),
+    (b"PHZD", World, "Placed Hazard"),
+    (b"PROJ", Gameplay, "Projectile"),
+    (b"QUST", Quests, "Quest"),
+    (b"RACE", Actors, "Race"),
+    (b"REFR", World, "Placed Object"),
+    (b"REGN", World, "Region"),
+    (b"RELA", Actors, "Relationship"),
+    (b"REVB", Audio, "Reverb Parameters"),
+    (b"RFCT", Visual, "Visual Effect"),
+    (b"SCEN", Quests, "Scene"),
+    (b"SCRL", Items, "Scroll"),
+    (b"SHOU", Magic, "Shout"),
+    (b"SLGM", Items, "Soul Gem"),
+    (b"SMBN", Quests, "Story Manager Branch Node"),
+    (b"SMEN", Quests, "Story Manager Event Node"),
+    (b"SMQN", Quests, "Story Manager Quest Node"),
+    (b"SNCT", Audio, "Sound Category"),
+    (b"SNDR", Audio, "Sound Descriptor"),
+    (b"SOPM", Audio, "Sound Output Model"),
+    (b"SOUN", Audio, "Sound Marker"),
+    (b"SPEL", Magic, "Spell"),
+    (b"SPGD", Visual, "Shader Particle Geometry"),
+    (b"STAT", World, "Static"),
+    (b"TACT", World, "Talking Activator"),
+    (b"TREE", World, "Tree"),
+    (b"TXST", Visual, "Texture Set"),
+    (b"VOLI", Visual, "Volumetric Lighting"),
+    (b"VTYP", Audio, "Voice Type"),
+    (b"WATR", World, "Water"),
+    (b"WEAP", Items, "Weapon"),
+    (b"WOOP", Magic, "Word of Power"),
+    (b"WRLD", World, "Worldspace"),
+    (b"WTHR", World, "Weather"),
+];
+
+fn lookup(type_name: &[u8]) -> Option<&'static (&'static [u8; 4], RecordCategory, &'static str)> {
+    RECORD_TYPES
+        .iter()
+        .find(|(name, _, _)| &name[..] == type_name)
+}
+
+/// The category of records of [type_name]
+pub fn type_category(type_name: &[u8]) -> RecordCategory {
+    lookup(type_name).map_or(RecordCategory::Unknown, |(_, category, _)| *category)
+}
+
+/// The readable name of records of [type_name], such as "Non-Player Character" for NPC_
+pub fn type_description(type_name: &[u8]) -> Option<&'static str> {
+    lookup(type_name).map(|(_, _, description)| *description)
+}
+
+impl RecordRef<'_, '_> {
+    pub fn category(self) -> RecordCategory {
+        type_category(self.type_name())
+    }
+
+    /// The readable name of the record's type, or the type name itself if it isn't known
+    pub fn type_description(self) -> String {
+        let type_name = self.type_name();
+        type_description(type_name).map_or_else(|| type_name.to_string(), str::to_string)
+    }
+}
+impl Record<'_> {
+    pub fn category(&self) -> RecordCategory {
+        self.as_record_ref().category()
+    }
+
+    /// See [RecordRef::type_description]
+    pub fn type_description(&self) -> String {
+        self.as_record_ref().type_description()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::records::{
+        common::{CommonRecordInfo, GeneralRecord},
+        flst::FLSTRecord,
+    };
+    use bstr::ByteSlice;
+
+    #[test]
+    fn test_categories() {
+        assert_eq!(type_category(b"NPC_"), RecordCategory::Actors);
+        assert_eq!(type_description(b"NPC_"), Some("Non-Player Character"));
+        assert_eq!(type_category(b"ZZZZ"), RecordCategory::Unknown);
+        assert_eq!(type_description(b"ZZZZ"), None);
+        for (i, (name, _, _)) in RECORD_TYPES.iter().enumerate() {
+            assert!(
+                RECORD_TYPES[..i].iter().all(|(x, _, _)| x != name),
+                "{} is listed twice",
+                name.as_bstr()
+            );
+        }
+
+        let list = Record::FLST(FLSTRecord {
+            common: CommonRecordInfo::test_default(),
+            editor_id_index: None,
+            fields: Vec::new(),
+        });
+        assert_eq!(list.category(), RecordCategory::Meta);
+        assert_eq!(list.type_description(), "Form List");
+        let unknown = Record::Unknown(GeneralRecord {
+            type_name: b"XXXX".as_bstr(),
+            common: CommonRecordInfo::test_default(),
+            fields: Vec::new(),
+        });
+        assert_eq!(unknown.category().to_string(), "Unknown");
+        assert_eq!(unknown.type_description(), "XXXX");
+    }
+}
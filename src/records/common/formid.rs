@@ -1,10 +1,27 @@
 use crate::{
+    error::{ErrorCode, VivecError},
     impl_static_data_size,
     parse::{PResult, Parse},
-    util::Writable,
+    util::{Writable, WriteError},
 };
+use bstr::{BStr, BString};
+use std::{
+    fmt::{Display, Formatter},
+    hash::{Hash, Hasher},
+    str::FromStr,
+};
+
+/// Object indices below this are reserved for the engine, so new records start at it
+pub const FIRST_OBJECT_INDEX: u32 = 0x800;
+/// The highest object index that a light plugin can give its new records
+pub const LAST_LIGHT_OBJECT_INDEX: u32 = 0xFFF;
+/// The highest object index that fits within a form id
+pub const LAST_OBJECT_INDEX: u32 = 0x00FF_FFFF;
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+/// The id of a record. The top byte is the mod index, which is the position of the plugin that
+/// the record belongs to within the masters of the plugin that holds the id, or the amount of
+/// masters if it belongs to that plugin itself. The lower 24 bits are the object index
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
 pub struct FormId {
     pub id: u32,
 }
@@ -13,6 +30,32 @@ impl FormId {
         FormId { id }
     }
 
+    /// The form id of [object_index], of which only the lower 24 bits are kept, in the plugin at
+    /// [mod_index]
+    pub fn from_parts(mod_index: u8, object_index: u32) -> FormId {
+        FormId::new((u32::from(mod_index) << 24) | (object_index & 0x00FF_FFFF))
+    }
+
+    pub fn mod_index(self) -> u8 {
+        (self.id >> 24) as u8
+    }
+
+    /// The lower 24 bits
+    pub fn object_index(self) -> u32 {
+        self.id & 0x00FF_FFFF
+    }
+
+    /// The same object in the plugin at [mod_index]
+    pub fn with_mod_index(self, mod_index: u8) -> FormId {
+        FormId::from_parts(mod_index, self.object_index())
+    }
+
+    /// Whether the object index is one that a light plugin can give new records, whatever the
+    /// version of its header, see [crate::plugin::Plugin::light_range]
+    pub fn is_in_esl_range(self) -> bool {
+        (FIRST_OBJECT_INDEX..=LAST_LIGHT_OBJECT_INDEX).contains(&self.object_index())
+    }
+
     pub fn from_bytes(id: [u8; 4]) -> FormId {
         FormId::new(u32::from_le_bytes(id))
     }
@@ -21,6 +64,22 @@ impl FormId {
         self.id.to_le_bytes()
     }
 }
+impl Display for FormId {
+    /// Like `0200129A`
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:08X}", self.id)
+    }
+}
+impl FromStr for FormId {
+    type Err = FormIdParseError;
+
+    /// Reads up to 8 hex digits, with or without a `0x` before them
+    fn from_str(text: &str) -> Result<FormId, FormIdParseError> {
+        parse_hex(text.trim())
+            .map(FormId::new)
+            .ok_or_else(|| FormIdParseError::InvalidHex(text.to_string()))
+    }
+}
 impl Parse<'_> for FormId {
     fn parse(data: &[u8]) -> PResult<FormId> {
         let (data, id) = u32::parse(data)?;
@@ -29,7 +88,7 @@ impl Parse<'_> for FormId {
 }
 impl_static_data_size!(FormId, u32::static_data_size());
 impl Writable for FormId {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: std::io::Write,
     {
@@ -37,6 +96,270 @@ impl Writable for FormId {
     }
 }
 
+/// Something that holds references to other records, such as a record or one of its fields.
+/// The id of a record itself (in its header) is not a reference, so it isn't visited
+pub trait FormIdVisitor {
+    /// Calls [visit] with each form id that is held, in the order they are written, so that
+    /// they can be looked at or rewritten
+    fn visit_formids(&mut self, visit: &mut dyn FnMut(&mut FormId));
+
+    /// Calls [visit] with each form id that is held, in the same order as
+    /// [FormIdVisitor::visit_formids], for when they only need to be looked at
+    fn for_each_formid(&self, visit: &mut dyn FnMut(FormId));
+
+    /// [FormIdVisitor::for_each_formid] along with the name of the field holding each form id.
+    /// [name] is the name this is written under, which collections of several fields replace
+    /// with the names of their own fields
+    fn for_each_field_formid<'name>(
+        &self,
+        name: &'name BStr,
+        visit: &mut dyn FnMut(&'name BStr, FormId),
+    ) {
+        self.for_each_formid(&mut |id| visit(name, id))
+    }
+
+    /// Every form id that is held, in order
+    fn formids(&self) -> Vec<FormId> {
+        let mut formids = Vec::new();
+        self.for_each_formid(&mut |formid| formids.push(formid));
+        formids
+    }
+}
+impl FormIdVisitor for FormId {
+    fn visit_formids(&mut self, visit: &mut dyn FnMut(&mut FormId)) {
+        visit(self)
+    }
+
+    fn for_each_formid(&self, visit: &mut dyn FnMut(FormId)) {
+        visit(*self)
+    }
+}
+impl<T: FormIdVisitor> FormIdVisitor for Option<T> {
+    fn visit_formids(&mut self, visit: &mut dyn FnMut(&mut FormId)) {
+        if let Some(x) = self {
+            x.visit_formids(visit)
+        }
+    }
+
+    fn for_each_formid(&self, visit: &mut dyn FnMut(FormId)) {
+        if let Some(x) = self {
+            x.for_each_formid(visit)
+        }
+    }
+
+    fn for_each_field_formid<'name>(
+        &self,
+        name: &'name BStr,
+        visit: &mut dyn FnMut(&'name BStr, FormId),
+    ) {
+        if let Some(x) = self {
+            x.for_each_field_formid(name, visit)
+        }
+    }
+}
+impl<T: FormIdVisitor> FormIdVisitor for Vec<T> {
+    fn visit_formids(&mut self, visit: &mut dyn FnMut(&mut FormId)) {
+        for x in self.iter_mut() {
+            x.visit_formids(visit)
+        }
+    }
+
+    fn for_each_formid(&self, visit: &mut dyn FnMut(FormId)) {
+        for x in self.iter() {
+            x.for_each_formid(visit)
+        }
+    }
+
+    fn for_each_field_formid<'name>(
+        &self,
+        name: &'name BStr,
+        visit: &mut dyn FnMut(&'name BStr, FormId),
+    ) {
+        for x in self.iter() {
+            x.for_each_field_formid(name, visit)
+        }
+    }
+}
+
+/// Up to 8 hex digits, optionally with a `0x` before them
+fn parse_hex(text: &str) -> Option<u32> {
+    let digits = text
+        .strip_prefix("0x")
+        .or_else(|| text.strip_prefix("0X"))
+        .unwrap_or(text);
+    if digits.is_empty() || digits.len() > 8 || !digits.bytes().all(|x| x.is_ascii_hexdigit()) {
+        return None;
+    }
+    u32::from_str_radix(digits, 16).ok()
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum FormIdParseError {
+    /// The text isn't a form id written in hex
+    InvalidHex(String),
+    /// The text doesn't have both a plugin and a form id, separated by a `:`
+    MissingPlugin(String),
+}
+impl Display for FormIdParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FormIdParseError::InvalidHex(text) => {
+                write!(
+                    f,
+                    "'{}' is not a form id, which is up to 8 hex digits",
+                    text
+                )
+            }
+            FormIdParseError::MissingPlugin(text) => write!(
+                f,
+                "'{}' is not a plugin and form id, such as Skyrim.esm:012D5C",
+                text
+            ),
+        }
+    }
+}
+impl std::error::Error for FormIdParseError {}
+impl VivecError for FormIdParseError {
+    fn code(&self) -> ErrorCode {
+        ErrorCode::InvalidFormId
+    }
+}
+
+/// Implements [FormIdVisitor] for types that don't hold any form ids
+#[macro_export]
+macro_rules! impl_no_formids {
+    ($($t:ty),* $(,)?) => {
+        $(
+            impl $crate::records::common::FormIdVisitor for $t {
+                fn visit_formids(
+                    &mut self,
+                    _visit: &mut dyn FnMut(&mut $crate::records::common::FormId),
+                ) {
+                }
+
+                fn for_each_formid(&self, _visit: &mut dyn FnMut($crate::records::common::FormId)) {
+                }
+            }
+        )*
+    };
+}
+
+/// Implements [FormIdVisitor] for a struct by visiting each of [$field] in turn. Collections
+/// give the type of each, so that form ids are reported under the name of the field holding them
+#[macro_export]
+macro_rules! impl_formid_visitor {
+    ($t:ty, [$($field:ident: $field_type:ty),* $(,)?]) => {
+        impl $crate::records::common::FormIdVisitor for $t {
+            fn visit_formids(
+                &mut self,
+                visit: &mut dyn FnMut(&mut $crate::records::common::FormId),
+            ) {
+                $(
+                    $crate::records::common::FormIdVisitor::visit_formids(&mut self.$field, visit);
+                )*
+            }
+
+            fn for_each_formid(&self, visit: &mut dyn FnMut($crate::records::common::FormId)) {
+                $(
+                    $crate::records::common::FormIdVisitor::for_each_formid(&self.$field, visit);
+                )*
+            }
+
+            fn for_each_field_formid<'name>(
+                &self,
+                _name: &'name bstr::BStr,
+                visit: &mut dyn FnMut(&'name bstr::BStr, $crate::records::common::FormId),
+            ) {
+                $(
+                    $crate::records::common::FormIdVisitor::for_each_field_formid(
+                        &self.$field,
+                        <$field_type as $crate::records::common::StaticTypeNamed>::static_type_name(),
+                        visit,
+                    );
+                )*
+            }
+        }
+    };
+    ($t:ty, [$($field:ident),* $(,)?]) => {
+        impl $crate::records::common::FormIdVisitor for $t {
+            fn visit_formids(
+                &mut self,
+                visit: &mut dyn FnMut(&mut $crate::records::common::FormId),
+            ) {
+                $(
+                    $crate::records::common::FormIdVisitor::visit_formids(&mut self.$field, visit);
+                )*
+            }
+
+            fn for_each_formid(&self, visit: &mut dyn FnMut($crate::records::common::FormId)) {
+                $(
+                    $crate::records::common::FormIdVisitor::for_each_formid(&self.$field, visit);
+                )*
+            }
+        }
+    };
+}
+
+/// A form id that doesn't depend on the masters of the plugin holding it: the name of the plugin
+/// that the record belongs to, along with its object index. So ids from different plugins of a
+/// load order can be compared, see [crate::plugin::Plugin::global_formid].
+/// Plugin names are compared ignoring ASCII case, as the game does
+#[derive(Debug, Clone)]
+pub struct GlobalFormId {
+    pub plugin: BString,
+    pub object_index: u32,
+}
+impl GlobalFormId {
+    /// Only the lower 24 bits of [object_index] are kept
+    pub fn new<S: Into<BString>>(plugin: S, object_index: u32) -> GlobalFormId {
+        GlobalFormId {
+            plugin: plugin.into(),
+            object_index: object_index & 0x00FF_FFFF,
+        }
+    }
+}
+impl PartialEq for GlobalFormId {
+    fn eq(&self, other: &GlobalFormId) -> bool {
+        self.object_index == other.object_index && self.plugin.eq_ignore_ascii_case(&other.plugin)
+    }
+}
+impl Eq for GlobalFormId {}
+impl Hash for GlobalFormId {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.object_index.hash(state);
+        for byte in self.plugin.iter() {
+            byte.to_ascii_lowercase().hash(state);
+        }
+    }
+}
+impl Display for GlobalFormId {
+    /// Like `012D5C:Skyrim.esm`
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:06X}:{}", self.object_index, self.plugin)
+    }
+}
+impl FromStr for GlobalFormId {
+    type Err = FormIdParseError;
+
+    /// Reads a plugin and a form id separated by a `:`, in either order, such as
+    /// `Skyrim.esm:012D5C` or `012D5C:Skyrim.esm`. The mod index of the form id is ignored
+    fn from_str(text: &str) -> Result<GlobalFormId, FormIdParseError> {
+        let text = text.trim();
+        let missing = || FormIdParseError::MissingPlugin(text.to_string());
+        let (plugin, id) = match (text.rsplit_once(':'), text.split_once(':')) {
+            (Some((plugin, id)), _) if parse_hex(id).is_some() => (plugin, id),
+            (_, Some((id, plugin))) if parse_hex(id).is_some() => (plugin, id),
+            (Some(_), _) => return Err(FormIdParseError::InvalidHex(text.to_string())),
+            (None, _) => return Err(missing()),
+        };
+        if plugin.is_empty() {
+            return Err(missing());
+        }
+        let id: FormId = id.parse()?;
+        Ok(GlobalFormId::new(plugin, id.object_index()))
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -45,8 +368,7 @@ mod test {
     fn test_formid() {
         let formid = FormId::new(0xaa44926b);
         assert_eq!(formid.data_size(), 4);
-        let mut data = Vec::new();
-        data.reserve(4);
+        let mut data = Vec::with_capacity(4);
         formid.write_to(&mut data).unwrap();
         println!("data: {:?}", data);
         assert_eq!(data.len(), 4);
@@ -55,4 +377,52 @@ mod test {
         assert_eq!(data[2], 0x44);
         assert_eq!(data[3], 0xaa);
     }
+
+    #[test]
+    fn test_formid_parts() {
+        let formid = FormId::new(0x0301_2d5c);
+        assert_eq!(formid.mod_index(), 3);
+        assert_eq!(formid.object_index(), 0x01_2d5c);
+        assert_eq!(FormId::from_parts(3, 0x01_2d5c), formid);
+        assert_eq!(FormId::from_parts(3, 0xff01_2d5c), formid);
+        assert_eq!(formid.with_mod_index(0xfe), FormId::new(0xfe01_2d5c));
+        assert!(!formid.is_in_esl_range());
+        assert!(FormId::new(0x0100_0800).is_in_esl_range());
+        assert!(FormId::new(0x0100_0fff).is_in_esl_range());
+        assert!(!FormId::new(0x0100_07ff).is_in_esl_range());
+        assert!(!FormId::new(0x0100_1000).is_in_esl_range());
+    }
+
+    #[test]
+    fn test_formid_text() {
+        let formid = FormId::new(0x0200_129a);
+        assert_eq!(formid.to_string(), "0200129A");
+        assert_eq!(formid.to_string().parse(), Ok(formid));
+        assert_eq!("0x0200129a".parse(), Ok(formid));
+        assert_eq!("0X200129A".parse(), Ok(formid));
+        assert_eq!("7".parse(), Ok(FormId::new(7)));
+        for text in ["", "0x", "+200129A", "10200129A", "Skyrim"] {
+            assert_eq!(
+                text.parse::<FormId>(),
+                Err(FormIdParseError::InvalidHex(text.to_string()))
+            );
+        }
+
+        let global = GlobalFormId::new("Skyrim.esm", 0x01_2d5c);
+        assert_eq!("Skyrim.esm:012D5C".parse(), Ok(global.clone()));
+        assert_eq!("skyrim.esm:0x00012d5c".parse(), Ok(global.clone()));
+        assert_eq!(global.to_string().parse(), Ok(global));
+        assert_eq!(
+            "012D5C".parse::<GlobalFormId>(),
+            Err(FormIdParseError::MissingPlugin("012D5C".to_string()))
+        );
+        assert_eq!(
+            ":012D5C".parse::<GlobalFormId>(),
+            Err(FormIdParseError::MissingPlugin(":012D5C".to_string()))
+        );
+        assert_eq!(
+            "Skyrim.esm:Iron".parse::<GlobalFormId>(),
+            Err(FormIdParseError::InvalidHex("Skyrim.esm:Iron".to_string()))
+        );
+    }
 }
@@ -1,9 +1,10 @@
-use super::BStrw;
+use super::{decode_windows1252, decode_windows1252_lossy, BStrw, TextEncoding};
 use crate::{
-    parse::{PResult, Parse},
-    util::{DataSize, Writable},
+    parse::{PResult, Parse, ParseError},
+    util::{DataSize, Writable, WriteError},
 };
 use bstr::{BStr, ByteSlice};
+use std::{borrow::Cow, str::FromStr};
 
 /// String that is just bytes.
 #[derive(Debug, Clone, Eq, PartialEq)]
@@ -21,6 +22,47 @@ impl<'data> FullString<'data> {
     pub fn from_ascii_bytes(value: &'data [u8]) -> Self {
         Self::new(value.as_bstr())
     }
+
+    /// The text, with bytes that Windows-1252 leaves undefined replaced by U+FFFD
+    pub fn to_string_lossy(&self) -> String {
+        decode_windows1252_lossy(&self.value)
+    }
+
+    /// The text, failing if it has bytes that Windows-1252 leaves undefined
+    pub fn to_string_strict(&self) -> Result<String, ParseError<'static>> {
+        decode_windows1252(&self.value)
+    }
+
+    /// The text, read as [encoding] rather than Windows-1252
+    pub fn to_string_lossy_in(&self, encoding: TextEncoding) -> String {
+        encoding.decode_lossy(&self.value)
+    }
+
+    /// See [FullString::to_string_lossy_in]
+    pub fn to_string_strict_in(
+        &self,
+        encoding: TextEncoding,
+    ) -> Result<String, ParseError<'static>> {
+        encoding.decode(&self.value)
+    }
+
+    /// [text] encoded in [encoding] rather than Windows-1252
+    pub fn from_str_in(
+        text: &str,
+        encoding: TextEncoding,
+    ) -> Result<FullString<'static>, WriteError> {
+        Ok(FullString {
+            value: Cow::Owned(encoding.encode(text)?),
+        })
+    }
+}
+impl FromStr for FullString<'static> {
+    type Err = WriteError;
+
+    /// Encodes [text] in Windows-1252
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        FullString::from_str_in(text, TextEncoding::Windows1252)
+    }
 }
 impl<'data> Parse<'data> for FullString<'data> {
     fn parse(data: &'data [u8]) -> PResult<'data, Self> {
@@ -33,7 +75,7 @@ impl<'data> DataSize for FullString<'data> {
     }
 }
 impl<'data> Writable for FullString<'data> {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: std::io::Write,
     {
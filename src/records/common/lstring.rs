@@ -1,23 +1,175 @@
-use crate::{impl_static_data_size, parse::Parse, util::Writable};
+use super::NullTerminatedString;
+use crate::{
+    parse::{PResult, Parse},
+    strings::{StringTable, StringsKind},
+    util::{DataSize, Writable, WriteError},
+};
+use bstr::BStr;
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
-pub struct LString {
-    /// An index into the string table.
-    /// If the file is localized (see TES4 record), then ???, otherwise it points to a null terminated string
-    pub index: u32,
+/// A string that is either kept in the string tables or in the field itself, depending on
+/// whether the plugin is localized (see the LOCALIZED flag of the TES4 record)
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum LString<'data> {
+    /// An index into the string table, for localized plugins. 0 is an empty string
+    Index(u32),
+    /// The text itself, for plugins that aren't localized
+    Inline(NullTerminatedString<'data>),
 }
-impl Parse<'_> for LString {
-    fn parse(data: &[u8]) -> crate::parse::PResult<Self> {
-        let (data, index) = u32::parse(data)?;
-        Ok((data, Self { index }))
+impl<'data> LString<'data> {
+    /// Parses [data] as an index if [localized], or else as a null terminated string
+    pub fn parse_as(data: &'data [u8], localized: bool) -> PResult<'data, Self> {
+        if localized {
+            let (data, index) = u32::parse(data)?;
+            Ok((data, LString::Index(index)))
+        } else {
+            let (data, text) = NullTerminatedString::parse(data)?;
+            Ok((data, LString::Inline(text)))
+        }
+    }
+
+    /// The index into the string tables, None if the text is inline
+    pub fn index(&self) -> Option<u32> {
+        match self {
+            LString::Index(index) => Some(*index),
+            LString::Inline(_) => None,
+        }
+    }
+
+    /// The text that [table] has for this string, or the text itself if it is inline
+    pub fn resolve<'a>(&'a self, table: &'a StringTable) -> Option<&'a BStr> {
+        match self {
+            LString::Index(index) => table.get(*index),
+            LString::Inline(text) => Some(text.value.as_ref()),
+        }
+    }
+}
+impl DataSize for LString<'_> {
+    fn data_size(&self) -> usize {
+        match self {
+            LString::Index(index) => index.data_size(),
+            LString::Inline(text) => text.data_size(),
+        }
     }
 }
-impl_static_data_size!(LString, u32::static_data_size());
-impl Writable for LString {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+impl Writable for LString<'_> {
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: std::io::Write,
     {
-        self.index.write_to(w)
+        match self {
+            LString::Index(index) => index.write_to(w),
+            LString::Inline(text) => text.write_to(w),
+        }
+    }
+}
+
+/// Implements [FromField] for a field that holds a single [LString] in [$field_name], picking
+/// its form from [ParseContext::localized]. Without a context it is read as inline
+#[macro_export]
+macro_rules! impl_lstring_field {
+    ($name:ident, $field_name:ident) => {
+        impl<'data> $crate::records::fields::common::FromField<'data> for $name<'data> {
+            fn from_field(
+                field: $crate::records::fields::common::GeneralField<'data>,
+            ) -> $crate::parse::PResult<'data, Self, $crate::records::fields::common::FromFieldError>
+            {
+                let (data, $field_name) =
+                    $crate::records::common::lstring::LString::parse_as(field.data, false)?;
+                Ok((data, $name { $field_name }))
+            }
+
+            fn from_field_ctx(
+                field: $crate::records::fields::common::GeneralField<'data>,
+                ctx: &mut $crate::context::ParseContext,
+            ) -> $crate::parse::PResult<'data, Self, $crate::records::fields::common::FromFieldError<'data>>
+            {
+                let (data, $field_name) =
+                    $crate::records::common::lstring::LString::parse_as(field.data, ctx.localized)?;
+                Ok((data, $name { $field_name }))
+            }
+        }
+    };
+}
+
+/// The type of record a field is in (None for any), its name, and the table its text is kept in
+type LStringField = (Option<&'static [u8; 4]>, &'static [u8; 4], StringsKind);
+
+/// Fields that are lstrings
+const LSTRING_FIELDS: &[LStringField] = &[
+    (None, b"FULL", StringsKind::Strings),
+    (None, b"DESC", StringsKind::DlStrings),
+    (Some(b"ACTI"), b"RNAM", StringsKind::Strings),
+    (Some(b"BOOK"), b"CNAM", StringsKind::DlStrings),
+    (Some(b"INFO"), b"NAM1", StringsKind::IlStrings),
+    (Some(b"INFO"), b"RNAM", StringsKind::Strings),
+    (Some(b"MESG"), b"ITXT", StringsKind::Strings),
+    (Some(b"MGEF"), b"DNAM", StringsKind::DlStrings),
+    (Some(b"NPC_"), b"SHRT", StringsKind::Strings),
+    (Some(b"QUST"), b"NNAM", StringsKind::DlStrings),
+    (Some(b"WOOP"), b"TNAM", StringsKind::Strings),
+];
+
+/// The table that [field] of a [record_type] record keeps its text in, None if it isn't an
+/// lstring
+pub fn lstring_kind(record_type: &[u8], field: &[u8]) -> Option<StringsKind> {
+    LSTRING_FIELDS
+        .iter()
+        .find(|(record, name, _)| {
+            &name[..] == field && record.map_or(true, |record| &record[..] == record_type)
+        })
+        .map(|(_, _, kind)| *kind)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        context::ParseContext,
+        records::{
+            common::{record_flag, CommonRecordInfo, GeneralRecord},
+            fields::common::{object::FULL, FromField, GeneralField},
+        },
+    };
+    use bstr::ByteSlice;
+
+    #[test]
+    fn test_lstring() {
+        let (rest, index) = LString::parse_as(&[5, 0, 0, 0], true).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(index, LString::Index(5));
+        assert_eq!(index.index(), Some(5));
+        let (rest, inline) = LString::parse_as(b"Iron Sword\0", false).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(inline.index(), None);
+        assert_eq!(inline.data_size(), 11);
+        assert_eq!(inline.to_vec().unwrap(), b"Iron Sword\0");
+        assert!(LString::parse_as(b"No end", false).is_err());
+
+        let mut table = StringTable::new(StringsKind::Strings);
+        table.insert(5, "Steel Sword");
+        assert_eq!(index.resolve(&table), Some(b"Steel Sword".as_bstr()));
+        assert_eq!(inline.resolve(&table), Some(b"Iron Sword".as_bstr()));
+
+        // The TES4 record picks the form that the lstrings of later records are read in
+        let mut ctx = ParseContext::default();
+        let mut header = GeneralRecord {
+            type_name: b"TES4".as_bstr(),
+            common: CommonRecordInfo::test_default(),
+            fields: Vec::new(),
+        };
+        header.common.flags.flags = record_flag::LOCALIZED;
+        ctx.enter_record(&header);
+        let field = GeneralField::new(b"FULL".as_bstr(), &[5, 0, 0, 0]);
+        let (_, name) = FULL::from_field_ctx(field, &mut ctx).unwrap();
+        assert_eq!(name.name, LString::Index(5));
+        header.common.flags.flags = 0;
+        ctx.enter_record(&header);
+        let field = GeneralField::new(b"FULL".as_bstr(), b"Iron Sword\0");
+        let (_, name) = FULL::from_field_ctx(field, &mut ctx).unwrap();
+        assert_eq!(name.name, inline);
+
+        assert_eq!(lstring_kind(b"BOOK", b"CNAM"), Some(StringsKind::DlStrings));
+        assert_eq!(lstring_kind(b"ARMO", b"CNAM"), None);
+        assert_eq!(lstring_kind(b"ARMO", b"FULL"), Some(StringsKind::Strings));
     }
 }
@@ -1,11 +1,21 @@
-use super::fields::common::{CollectField, FieldName, FromField, FromFieldError, GeneralField};
+use super::fields::{
+    common::{parse_fields, CollectField, FieldName, FromField, FromFieldError, GeneralField},
+    edid,
+};
 use crate::{
-    impl_static_data_size,
-    parse::{many, take, PResult, Parse, ParseError},
-    util::{DataSize, Writable},
+    context::{DuplicateFieldPolicy, FormVersion, ParseContext},
+    error::{ErrorCode, VivecError},
+    impl_no_formids, impl_static_data_size,
+    parse::{take, PResult, Parse, ParseError},
+    util::{fmt_data, record_data_size, DataSize, Writable, WriteError},
 };
 use bstr::{BStr, ByteSlice};
-use std::{fmt::Debug, io::Write};
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+use std::{
+    borrow::Cow,
+    fmt::Debug,
+    io::{Read, Write},
+};
 
 pub type Index = usize;
 /// Always four characters
@@ -15,60 +25,82 @@ pub mod formid;
 pub mod full_string;
 pub mod lstring;
 pub mod null_terminated_string;
+pub mod text_encoding;
 pub mod version_control_info;
 pub mod windows1252_string;
 
 pub use formid::*;
 pub use null_terminated_string::*;
+pub use text_encoding::*;
 pub use version_control_info::*;
 pub use windows1252_string::*;
 
-/// collect_one(FieldType, field_variable => field_vector; index_option)
+/// collect_one(FieldType, field_variable => field_vector; index_option, context)
 #[macro_export]
 macro_rules! collect_one {
-    ($s:ty, $field:expr => $fields:expr; $o:expr) => {{
+    ($s:ty, $field:expr => $fields:expr; $o:expr, $ctx:expr) => {{
         use $crate::records::fields::common::FromField;
         if $o.is_some() {
             use bstr::ByteSlice;
-            return Err($crate::records::common::FromRecordError::DuplicateField(
+            let field = $crate::records::common::duplicate_field(
                 stringify!($s).as_bytes().as_bstr(),
-            ));
+                $field,
+                $ctx,
+            )?;
+            $fields.push(field.into());
+        } else {
+            let (_, result) = <$s>::from_field_ctx($field, $ctx)?;
+            $o = Some($fields.len());
+            $fields.push(result.into());
         }
-
-        let (_, result) = <$s>::from_field($field)?;
-        $o = Some($fields.len());
-        $fields.push(result.into());
     }};
 }
 
-/// collect_one_collection!(OpeningFieldType, CollectionType; field_variable, field_iterator => field_vector; index_option);
+/// collect_one_collection!(OpeningFieldType, CollectionType; field_variable, field_iterator => field_vector; index_option, context);
+/// If the collection is a duplicate, then only the opening field is stored as unknown, the rest
+/// of the collection will then be handled as if they were standalone fields.
+/// collect_one_collection!(repeatable: OpeningFieldType, CollectionType; field_variable, field_iterator => field_vector; index_option);
+///   for collections that may appear multiple times, each is stored and the index refers to the first
 #[macro_export]
 macro_rules! collect_one_collection {
-    ($of:ty, $cf:ty; $field:expr, $field_iter:expr => $fields:expr; $o:expr; $collect_name:ident) => {{
+    (repeatable: $of:ty, $cf:ty; $field:expr, $field_iter:expr => $fields:expr; $o:expr) => {{
+        use $crate::records::fields::common::{CollectField, FromField};
+        let (_, opening_field) = <$of>::from_field($field)?;
+        let (_, collection) = <$cf>::collect(opening_field, &mut $field_iter)?;
+        if $o.is_none() {
+            $o = Some($fields.len());
+        }
+        $fields.push(collection.into());
+    }};
+    ($of:ty, $cf:ty; $field:expr, $field_iter:expr => $fields:expr; $o:expr, $ctx:expr; $collect_name:ident) => {{
         use $crate::records::fields::common::FromField;
         if $o.is_some() {
             use bstr::ByteSlice;
-            return Err($crate::records::common::FromRecordError::DuplicateField(
+            let field = $crate::records::common::duplicate_field(
                 stringify!($of).as_bytes().as_bstr(),
-            ));
+                $field,
+                $ctx,
+            )?;
+            $fields.push(field.into());
+        } else {
+            let (_, opening_field) = <$of>::from_field_ctx($field, $ctx)?;
+            let (_, collection) = <$cf>::$collect_name(opening_field, &mut $field_iter)?;
+            $o = Some($fields.len());
+            $fields.push(collection.into());
         }
-
-        let (_, opening_field) = <$of>::from_field($field)?;
-        let (_, collection) = <$cf>::$collect_name(opening_field, &mut $field_iter)?;
-        $o = Some($fields.len());
-        $fields.push(collection.into());
     }};
-    ($of:ty, $cf:ty; $field:expr, $field_iter:expr => $fields:expr; $o:expr) => {{
+    ($of:ty, $cf:ty; $field:expr, $field_iter:expr => $fields:expr; $o:expr, $ctx:expr) => {{
         use $crate::records::fields::common::CollectField;
-        collect_one_collection!($of, $cf; $field, $field_iter => $fields; $o; collect);
+        collect_one_collection!($of, $cf; $field, $field_iter => $fields; $o, $ctx; collect);
     }};
 }
 
-/// collect_many(field_type, field_variable => field_vector; index_vector)
+/// collect_many(field_type, field_variable => field_vector; index_vector, context)
 #[macro_export]
 macro_rules! collect_many {
-    ($s:ty, $field:expr => $fields:expr; $o:expr) => {{
-        let (_, result) = <$s>::from_field($field)?;
+    ($s:ty, $field:expr => $fields:expr; $o:expr, $ctx:expr) => {{
+        use $crate::records::fields::common::FromField;
+        let (_, result) = <$s>::from_field_ctx($field, $ctx)?;
         $o.push($fields.len());
         $fields.push(result.into());
     }};
@@ -83,6 +115,10 @@ pub mod record_flag {
     /// TES4,
     pub const MASTER: u32 = 0x1;
 
+    /// Non-playable. Replaces the non-playable flag of BODT, for records using BOD2
+    /// ARMO,
+    pub const NON_PLAYABLE: u32 = 0x4;
+
     /// Is it deleted
     /// All?
     pub const DELETED: u32 = 0x20;
@@ -222,6 +258,61 @@ pub mod record_flag {
     /// Multi bound
     /// REFR
     pub const MULTIBOUND: u32 = 0x80_000000;
+
+    /// Each flag above, and the record types it has a meaning for. An empty list is every type.
+    /// Flags sharing a bit are listed separately, as they are for different types.
+    /// Where a flag is unsure of its types, it is taken to be for every type
+    pub const TYPES: &[(u32, &[&[u8; 4]])] = &[
+        (MASTER, &[b"TES4"]),
+        (NON_PLAYABLE, &[b"ARMO"]),
+        (DELETED, &[]),
+        (CONSTANT, &[]),
+        (REFR, &[b"REFR"]),
+        (LOCALIZED, &[b"TES4"]),
+        (MUST_UPDATE_ANIMS, &[]),
+        (INACCESSIBLE, &[b"REFR"]),
+        (LIGHT_MASTER, &[b"TES4"]),
+        (STARTS_DEAD, &[b"ACHR"]),
+        (LOCAL_MAP_HIDDEN, &[b"REFR"]),
+        (MOTION_BLUR_CAST_SHADOWS, &[b"REFR"]),
+        (QUEST_ITEM, &[]),
+        (PERSISTENT_REFERENCE, &[]),
+        (DISPLAY_MAIN_MENU, &[b"LSCR"]),
+        (INITIALLY_DISABLED, &[]),
+        (IGNORED, &[]),
+        (VISIBLE_DISTANT, &[]),
+        (RANDOM_ANIMATION_START, &[b"ACTI"]),
+        (DANGEROUS, &[b"ACTI"]),
+        (OFF_LIMITS, &[b"CELL"]),
+        (COMPRESSED, &[]),
+        (NO_WAITING, &[]),
+        (IGNORE_OBJECT_INTERACTION, &[b"ACTI"]),
+        (MARKER, &[]),
+        (OBSTACLE, &[b"ACTI"]),
+        (NO_AI_ACQUIRE, &[b"REFR"]),
+        (NAVMESH_GEN_FILTER, &[]),
+        (NAVMESH_GEN_BOUNDING_BOX, &[]),
+        (MUST_EXIT_TO_TALK, &[b"FURN"]),
+        (REFLECT_AUTO_WATER, &[b"REFR"]),
+        (CHILD_CAN_USE, &[b"FURN", b"IDLM"]),
+        (NO_HAVOK_SETTLE, &[b"REFR"]),
+        (NAVMESH_GEN_GROUND, &[]),
+        (NO_RESPAWN, &[b"REFR"]),
+        (MULTIBOUND, &[b"REFR"]),
+    ];
+
+    /// Every bit that has a meaning for records of [type_name], going by [TYPES]
+    pub fn documented(type_name: &[u8]) -> u32 {
+        TYPES
+            .iter()
+            .filter(|(_, types)| types.is_empty() || types.iter().any(|x| &x[..] == type_name))
+            .fold(0, |mask, (flag, _)| mask | flag)
+    }
+
+    /// Every bit that has a meaning for at least one record type
+    pub fn documented_any() -> u32 {
+        TYPES.iter().fold(0, |mask, (flag, _)| mask | flag)
+    }
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
@@ -240,7 +331,7 @@ impl RecordFlags {
 }
 impl_static_data_size!(RecordFlags, u32::static_data_size());
 impl Writable for RecordFlags {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -277,6 +368,10 @@ impl CommonRecordInfo {
             unknown,
         }
     }
+    pub fn form_version(&self) -> FormVersion {
+        FormVersion::from_u16(self.version)
+    }
+
     /// Extracts the common record information from that record
     pub fn from_field(record: &GeneralRecord<'_>) -> CommonRecordInfo {
         record.common.clone()
@@ -307,7 +402,7 @@ impl_static_data_size!(
         + u16::static_data_size() // unknown
 );
 impl Writable for CommonRecordInfo {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -327,34 +422,41 @@ pub struct GeneralRecord<'data> {
     /// Stored in data
     pub fields: Vec<GeneralField<'data>>,
 }
+/// Parses a record header, returning the type name, the common info, and the record's data
+fn parse_record_header(data: &[u8]) -> PResult<'_, (RecordName<'_>, CommonRecordInfo, &'_ [u8])> {
+    let (data, type_name) = take(data, 4)?;
+    let type_name = type_name.as_bstr();
+
+    let (data, record_data_size) = u32::parse(data)?;
+    let (data, flags) = u32::parse(data)?;
+    let (data, id) = u32::parse(data)?;
+    let (data, version_control_info) = VersionControlInfo::parse(data)?;
+    // IDEA: Perhaps the version is a four bit integer, and so unkown is simpler the lower bits of it?
+    let (data, version) = u16::parse(data)?;
+    let (data, unknown) = u16::parse(data)?;
+
+    let (data, record_data) = take(data, record_data_size as usize)?;
+    let common = CommonRecordInfo::new(
+        RecordFlags::new(flags),
+        id,
+        version_control_info,
+        version,
+        unknown,
+    );
+    Ok((data, (type_name, common, record_data)))
+}
+
 impl<'data> Parse<'data> for GeneralRecord<'data> {
     fn parse(data: &'data [u8]) -> PResult<GeneralRecord<'data>> {
-        let (data, type_name) = take(data, 4)?;
-        let type_name = type_name.as_bstr();
-
-        let (data, record_data_size) = u32::parse(data)?;
-        let (data, flags) = u32::parse(data)?;
-        let (data, id) = u32::parse(data)?;
-        let (data, version_control_info) = VersionControlInfo::parse(data)?;
-        // IDEA: Perhaps the version is a four bit integer, and so unkown is simpler the lower bits of it?
-        let (data, version) = u16::parse(data)?;
-        let (data, unknown) = u16::parse(data)?;
-
+        let (data, (type_name, common, record_data)) = parse_record_header(data)?;
         // TODO: verify it's all been used
-        let (data, record_data) = take(data, record_data_size as usize)?;
-        let (_, fields) = many(record_data, GeneralField::parse)?;
+        let (_, fields) = parse_fields(record_data)?;
 
         Ok((
             data,
             GeneralRecord {
                 type_name,
-                common: CommonRecordInfo::new(
-                    RecordFlags::new(flags),
-                    id,
-                    version_control_info,
-                    version,
-                    unknown,
-                ),
+                common,
                 fields,
             },
         ))
@@ -365,6 +467,8 @@ impl<'data> TypeNamed<'data> for GeneralRecord<'data> {
         self.type_name
     }
 }
+// The fields of a general record are only known by their bytes, so it can't be visited
+impl_no_formids!(GeneralRecord<'_>);
 impl<'data> DataSize for GeneralRecord<'data> {
     fn data_size(&self) -> usize {
         self.type_name.len() +
@@ -374,18 +478,174 @@ impl<'data> DataSize for GeneralRecord<'data> {
     }
 }
 impl<'data> Writable for GeneralRecord<'data> {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
         self.type_name().write_to(w)?;
-        // TODO: assert fields_size is u32
-        (self.fields.data_size() as u32).write_to(w)?;
+        record_data_size(self.type_name(), self.fields.data_size())?.write_to(w)?;
         self.common.write_to(w)?;
         self.fields.write_to(w)
     }
 }
 
+/// A record flagged as compressed, whose data is kept as it is, as its fields can't be read
+/// without decompressing it, see [CompressedRecord::decompress]
+#[derive(Clone, Eq, PartialEq)]
+pub struct CompressedRecord<'data> {
+    pub type_name: RecordName<'data>,
+    pub common: CommonRecordInfo,
+    /// The size of the data once decompressed, followed by the zlib stream
+    pub data: Cow<'data, [u8]>,
+}
+
+/// How [CompressedRecord::recompress] writes a record that was decompressed
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Recompression {
+    /// Keep the original compressed data if the fields are unchanged, so that untouched records
+    /// are written back byte for byte. Changed records are compressed at the given level
+    KeepUnchanged(u32),
+    /// Always compress at the given zlib level, from 0 for none to 9 for the smallest
+    Level(u32),
+}
+impl Default for Recompression {
+    fn default() -> Recompression {
+        Recompression::KeepUnchanged(Compression::default().level())
+    }
+}
+
+impl<'data> CompressedRecord<'data> {
+    /// The size that the fields say they have once decompressed
+    pub fn uncompressed_size(&self) -> Option<u32> {
+        let (_, size) = u32::parse(&self.data).ok()?;
+        Some(size)
+    }
+
+    /// Decompresses the fields into [buffer], and reads them from it
+    pub fn decompress<'b>(
+        &self,
+        buffer: &'b mut Vec<u8>,
+    ) -> Result<GeneralRecord<'b>, ParseError<'b>>
+    where
+        'data: 'b,
+    {
+        let (stream, size) = u32::parse(&self.data).map_err(|_| ParseError::UnexpectedEOF)?;
+        buffer.clear();
+        buffer.reserve(size as usize);
+        ZlibDecoder::new(stream)
+            .read_to_end(buffer)
+            .map_err(|error| ParseError::Decompress(error.to_string()))?;
+        if buffer.len() != size as usize {
+            return Err(ParseError::Decompress(format!(
+                "Expected {} bytes, found {}",
+                size,
+                buffer.len()
+            )));
+        }
+        let (_, fields) = parse_fields(buffer)?;
+        let mut common = self.common.clone();
+        common.flags.flags &= !record_flag::COMPRESSED;
+        Ok(GeneralRecord {
+            type_name: self.type_name,
+            common,
+            fields,
+        })
+    }
+
+    /// Compresses the fields of [record] at zlib [level], flagging it as compressed
+    pub fn compress(
+        record: &GeneralRecord<'data>,
+        level: u32,
+    ) -> Result<CompressedRecord<'data>, WriteError> {
+        let size = record_data_size(record.type_name, record.fields.data_size())?;
+        let mut encoder = ZlibEncoder::new(size.to_le_bytes().to_vec(), Compression::new(level));
+        record.fields.write_to(&mut encoder)?;
+        let mut common = record.common.clone();
+        common.flags.flags |= record_flag::COMPRESSED;
+        Ok(CompressedRecord {
+            type_name: record.type_name,
+            common,
+            data: Cow::Owned(encoder.finish()?),
+        })
+    }
+
+    /// Compresses [record], which was decompressed from this record, following [mode].
+    /// The header is always that of [record]
+    pub fn recompress(
+        &self,
+        record: &GeneralRecord<'data>,
+        mode: Recompression,
+    ) -> Result<CompressedRecord<'data>, WriteError> {
+        let level = match mode {
+            Recompression::KeepUnchanged(level) => {
+                let mut buffer = Vec::new();
+                let unchanged = match self.decompress(&mut buffer) {
+                    Ok(original) => original.fields == record.fields,
+                    Err(_) => false,
+                };
+                if unchanged {
+                    let mut common = record.common.clone();
+                    common.flags.flags |= record_flag::COMPRESSED;
+                    return Ok(CompressedRecord {
+                        type_name: record.type_name,
+                        common,
+                        data: self.data.clone(),
+                    });
+                }
+                level
+            }
+            Recompression::Level(level) => level,
+        };
+        CompressedRecord::compress(record, level)
+    }
+}
+impl<'data> Parse<'data> for CompressedRecord<'data> {
+    fn parse(data: &'data [u8]) -> PResult<CompressedRecord<'data>> {
+        let (data, (type_name, common, record_data)) = parse_record_header(data)?;
+        Ok((
+            data,
+            CompressedRecord {
+                type_name,
+                common,
+                data: Cow::Borrowed(record_data),
+            },
+        ))
+    }
+}
+impl<'data> TypeNamed<'data> for CompressedRecord<'data> {
+    fn type_name(&self) -> &'data BStr {
+        self.type_name
+    }
+}
+impl DataSize for CompressedRecord<'_> {
+    fn data_size(&self) -> usize {
+        self.type_name.len() +
+            4 + // data_size
+            self.common.data_size() +
+            self.data.len()
+    }
+}
+impl Writable for CompressedRecord<'_> {
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
+    where
+        T: Write,
+    {
+        self.type_name().write_to(w)?;
+        record_data_size(self.type_name(), self.data.len())?.write_to(w)?;
+        self.common.write_to(w)?;
+        self.data.as_ref().write_to(w)
+    }
+}
+impl Debug for CompressedRecord<'_> {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut res = fmt.debug_struct("CompressedRecord");
+        res.field("type_name", &self.type_name);
+        res.field("common", &self.common);
+        fmt_data(&mut res, "data", &self.data, 10);
+        res.finish()
+    }
+}
+
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum ConversionError<T> {
     InvalidEnumerationValue(T),
@@ -427,9 +687,162 @@ impl<'data> From<ParseError<'data>> for FromRecordError<'data> {
         Self::ParseError(err)
     }
 }
+impl std::fmt::Display for FromRecordError<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FromRecordError::UnexpectedEnd => write!(f, "Unexpected end of fields"),
+            FromRecordError::ExpectedField(name) => write!(f, "Expected a {} field", name),
+            FromRecordError::ExpectedFieldGot { expected, found } => {
+                write!(f, "Expected a {} field, found {}", expected, found)
+            }
+            FromRecordError::UnexpectedField(name) => write!(f, "Unexpected {} field", name),
+            FromRecordError::DuplicateField(name) => {
+                write!(f, "Found more than one {} field", name)
+            }
+            FromRecordError::FromField(_) => write!(f, "Failed to convert field"),
+            FromRecordError::ParseError(_) => write!(f, "Failed to parse"),
+        }
+    }
+}
+impl std::error::Error for FromRecordError<'_> {}
+impl VivecError for FromRecordError<'_> {
+    fn code(&self) -> ErrorCode {
+        match self {
+            FromRecordError::UnexpectedEnd => ErrorCode::UnexpectedEndOfFields,
+            FromRecordError::ExpectedField(_) => ErrorCode::ExpectedField,
+            FromRecordError::ExpectedFieldGot { .. } => ErrorCode::ExpectedFieldGot,
+            FromRecordError::UnexpectedField(_) => ErrorCode::UnexpectedField,
+            FromRecordError::DuplicateField(_) => ErrorCode::DuplicateField,
+            FromRecordError::FromField(error) => error.code(),
+            FromRecordError::ParseError(error) => error.code(),
+        }
+    }
+
+    fn inner(&self) -> Option<&dyn VivecError> {
+        match self {
+            FromRecordError::FromField(error) => Some(error),
+            FromRecordError::ParseError(error) => Some(error),
+            _ => None,
+        }
+    }
+}
 
 pub trait FromRecord<'data>: Sized {
-    fn from_record(record: GeneralRecord<'data>) -> PResult<Self, FromRecordError<'data>>;
+    fn from_record(
+        record: GeneralRecord<'data>,
+        ctx: &mut ParseContext,
+    ) -> PResult<'data, Self, FromRecordError<'data>>;
+}
+
+/// Handles a field which was already collected, according to [ParseOptions::duplicate_fields].
+/// Returns the field to be stored as unknown, if it should be kept.
+pub fn duplicate_field<'data>(
+    name: FieldName<'data>,
+    field: GeneralField<'data>,
+    ctx: &mut ParseContext,
+) -> Result<GeneralField<'data>, FromRecordError<'data>> {
+    match ctx.options.duplicate_fields {
+        DuplicateFieldPolicy::Error => Err(FromRecordError::DuplicateField(name)),
+        DuplicateFieldPolicy::KeepFirst => {
+            ctx.warn(format!(
+                "Duplicate {} field, keeping the first occurrence",
+                field.type_name()
+            ));
+            Ok(field)
+        }
+    }
+}
+
+/// Checks for a missing EDID, which is only an error with [ParseOptions::require_editor_id]
+pub fn optional_editor_id<'data>(
+    index: Option<Index>,
+    ctx: &ParseContext,
+) -> Result<Option<Index>, FromRecordError<'data>> {
+    match index {
+        None if ctx.options.require_editor_id => Err(FromRecordError::ExpectedField(
+            edid::EDID::static_type_name(),
+        )),
+        index => Ok(index),
+    }
+}
+
+#[derive(Debug)]
+pub enum RedecodeError<'data> {
+    /// The record could not be written, to be read back
+    Write(WriteError),
+    Record(FromRecordError<'data>),
+}
+impl std::fmt::Display for RedecodeError<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RedecodeError::Write(_) => write!(f, "Failed to write record"),
+            RedecodeError::Record(_) => write!(f, "Failed to convert record"),
+        }
+    }
+}
+impl std::error::Error for RedecodeError<'_> {}
+impl VivecError for RedecodeError<'_> {
+    fn code(&self) -> ErrorCode {
+        match self {
+            RedecodeError::Write(error) => error.code(),
+            RedecodeError::Record(error) => error.code(),
+        }
+    }
+
+    fn inner(&self) -> Option<&dyn VivecError> {
+        match self {
+            RedecodeError::Write(error) => Some(error),
+            RedecodeError::Record(error) => Some(error),
+        }
+    }
+}
+
+/// Rebuilds [record] from scratch, by writing it into [buffer] and converting it back, so that
+/// the fields it holds as unknown which are now supported are decoded, and every index follows
+/// the fields as it would after parsing
+pub fn redecode_unknowns<'b, R, T>(
+    record: &R,
+    buffer: &'b mut Vec<u8>,
+    ctx: &mut ParseContext,
+) -> Result<T, RedecodeError<'b>>
+where
+    R: Writable,
+    T: FromRecord<'b>,
+{
+    buffer.clear();
+    record.write_to(buffer).map_err(RedecodeError::Write)?;
+    let (_, general) = GeneralRecord::parse(buffer).map_err(|x| RedecodeError::Record(x.into()))?;
+    ctx.enter_record(&general);
+    let (_, record) = T::from_record(general, ctx).map_err(RedecodeError::Record)?;
+    Ok(record)
+}
+/// Implements `redecode_unknowns` for a record, see [redecode_unknowns]
+#[macro_export]
+macro_rules! impl_redecode_unknowns {
+    ($record:ident, $field:ident) => {
+        impl<'data> $record<'data> {
+            /// Rebuilds the record, decoding the unknown fields that are supported now, for when
+            /// support for a field is added after the record was parsed. The rebuilt record
+            /// borrows from [buffer]. Returns it and the amount of fields that were decoded.
+            pub fn redecode_unknowns<'b>(
+                &self,
+                buffer: &'b mut Vec<u8>,
+                ctx: &mut $crate::context::ParseContext,
+            ) -> Result<($record<'b>, usize), $crate::records::common::RedecodeError<'b>> {
+                let unknowns = |fields: &[$field<'_>]| {
+                    fields
+                        .iter()
+                        .filter(|x| matches!(x, $field::Unknown(_)))
+                        .count()
+                };
+                let before = unknowns(&self.fields);
+                let record: $record<'b> =
+                    $crate::records::common::redecode_unknowns(self, buffer, ctx)?;
+                let decoded = before.saturating_sub(unknowns(&record.fields));
+                Ok((record, decoded))
+            }
+        }
+    };
 }
 
 pub trait TypeNamed<'aleph>: Sized {
@@ -488,6 +901,40 @@ pub struct FieldList<'data, T: StaticTypeNamed + DataSize> {
     // TODO: is this good a way to do this?
     _marker: std::marker::PhantomData<&'data [u8]>,
 }
+impl<'data, T> FieldList<'data, T>
+where
+    T: StaticTypeNamed + DataSize,
+{
+    pub fn new(list: Vec<T>) -> Self {
+        Self {
+            list,
+            _marker: std::marker::PhantomData,
+        }
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, T> {
+        self.list.iter()
+    }
+
+    pub fn push(&mut self, value: T) {
+        self.list.push(value)
+    }
+
+    pub fn retain<F>(&mut self, func: F)
+    where
+        F: FnMut(&T) -> bool,
+    {
+        self.list.retain(func)
+    }
+
+    pub fn len(&self) -> usize {
+        self.list.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+}
 // Implementation for fields
 impl<'data, T> CollectField<'data, T> for FieldList<'data, T>
 where
@@ -534,11 +981,31 @@ where
         self.list.data_size()
     }
 }
+impl<'data, T> FormIdVisitor for FieldList<'data, T>
+where
+    T: StaticTypeNamed + DataSize + FormIdVisitor,
+{
+    fn visit_formids(&mut self, visit: &mut dyn FnMut(&mut FormId)) {
+        self.list.visit_formids(visit)
+    }
+
+    fn for_each_formid(&self, visit: &mut dyn FnMut(FormId)) {
+        self.list.for_each_formid(visit)
+    }
+
+    fn for_each_field_formid<'name>(
+        &self,
+        name: &'name BStr,
+        visit: &mut dyn FnMut(&'name BStr, FormId),
+    ) {
+        self.list.for_each_field_formid(name, visit)
+    }
+}
 impl<'data, T> Writable for FieldList<'data, T>
 where
     T: Writable + StaticTypeNamed + DataSize,
 {
-    fn write_to<U>(&self, w: &mut U) -> std::io::Result<()>
+    fn write_to<U>(&self, w: &mut U) -> Result<(), WriteError>
     where
         U: Write,
     {
@@ -552,6 +1019,41 @@ pub struct CollectionList<'data, T: StaticTypeNamed + DataSize> {
     // TODO: is this a good way to do this
     _marker: std::marker::PhantomData<&'data [u8]>,
 }
+impl<'data, T> CollectionList<'data, T>
+where
+    T: StaticTypeNamed + DataSize,
+{
+    pub fn new(list: Vec<T>) -> Self {
+        Self {
+            list,
+            _marker: std::marker::PhantomData,
+        }
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, T> {
+        self.list.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> std::slice::IterMut<'_, T> {
+        self.list.iter_mut()
+    }
+
+    pub fn push(&mut self, value: T) {
+        self.list.push(value)
+    }
+
+    pub fn remove(&mut self, index: usize) -> T {
+        self.list.remove(index)
+    }
+
+    pub fn len(&self) -> usize {
+        self.list.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+}
 impl<'data, T, F> CollectField<'data, F> for CollectionList<'data, T>
 where
     T: StaticTypeNamed + DataSize + CollectField<'data, F>,
@@ -603,11 +1105,31 @@ where
         self.list.data_size()
     }
 }
+impl<'data, T> FormIdVisitor for CollectionList<'data, T>
+where
+    T: StaticTypeNamed + DataSize + FormIdVisitor,
+{
+    fn visit_formids(&mut self, visit: &mut dyn FnMut(&mut FormId)) {
+        self.list.visit_formids(visit)
+    }
+
+    fn for_each_formid(&self, visit: &mut dyn FnMut(FormId)) {
+        self.list.for_each_formid(visit)
+    }
+
+    fn for_each_field_formid<'name>(
+        &self,
+        name: &'name BStr,
+        visit: &mut dyn FnMut(&'name BStr, FormId),
+    ) {
+        self.list.for_each_field_formid(name, visit)
+    }
+}
 impl<'data, T> Writable for CollectionList<'data, T>
 where
     T: Writable + StaticTypeNamed + DataSize,
 {
-    fn write_to<W>(&self, w: &mut W) -> std::io::Result<()>
+    fn write_to<W>(&self, w: &mut W) -> Result<(), WriteError>
     where
         W: Write,
     {
@@ -687,4 +1209,62 @@ mod tests {
         let c = CommonRecordInfo::test_default();
         assert_size_output!(c);
     }
+
+    #[test]
+    fn test_recompress() {
+        let mut common = CommonRecordInfo::test_default();
+        common.flags = RecordFlags::new(0);
+        let record = GeneralRecord {
+            type_name: b"NPC_".as_bstr(),
+            common,
+            fields: vec![
+                GeneralField::new(b"EDID".as_bstr(), b"Someone\0"),
+                GeneralField::new(b"DATA".as_bstr(), &[7; 64]),
+            ],
+        };
+        let compressed = CompressedRecord::compress(&record, 9).unwrap();
+        assert!(compressed.common.flags.is(record_flag::COMPRESSED));
+        assert_eq!(
+            compressed.uncompressed_size(),
+            Some(record.fields.data_size() as u32)
+        );
+        let data = assert_size_output!(compressed);
+        let (_, parsed) = CompressedRecord::parse(&data).unwrap();
+        assert_eq!(parsed, compressed);
+        let mut buffer = Vec::new();
+        let mut decompressed = parsed.decompress(&mut buffer).unwrap();
+        assert_eq!(decompressed, record);
+
+        // Unchanged records keep their data, even though level 0 would compress it differently
+        let recompressed = parsed
+            .recompress(&decompressed, Recompression::KeepUnchanged(0))
+            .unwrap();
+        assert!(matches!(recompressed.data, Cow::Borrowed(_)));
+        assert_eq!(recompressed, parsed);
+        let recompressed = parsed
+            .recompress(&decompressed, Recompression::Level(0))
+            .unwrap();
+        assert!(recompressed.data.len() > parsed.data.len());
+
+        decompressed.fields.pop();
+        let recompressed = parsed
+            .recompress(&decompressed, Recompression::default())
+            .unwrap();
+        assert_eq!(recompressed.uncompressed_size(), Some(14));
+        let mut buffer = Vec::new();
+        assert_eq!(
+            recompressed.decompress(&mut buffer).unwrap().fields,
+            decompressed.fields
+        );
+
+        let broken = CompressedRecord {
+            data: Cow::Borrowed(&[15, 0, 0, 0, 1, 2, 3]),
+            ..parsed
+        };
+        let mut buffer = Vec::new();
+        assert_eq!(
+            broken.decompress(&mut buffer).unwrap_err().code(),
+            ErrorCode::Decompress
+        );
+    }
 }
@@ -1,9 +1,10 @@
-use super::BStrw;
+use super::{decode_windows1252, decode_windows1252_lossy, BStrw, TextEncoding};
 use crate::{
-    parse::{tag, take_until, PResult, Parse},
-    util::{DataSize, Writable},
+    parse::{tag, take_until, PResult, Parse, ParseError},
+    util::{DataSize, Writable, WriteError},
 };
 use bstr::{BStr, ByteSlice};
+use std::{borrow::Cow, str::FromStr};
 
 /// Null-terminated-string
 #[derive(Debug, Clone, Eq, PartialEq)]
@@ -21,6 +22,51 @@ impl<'data> NullTerminatedString<'data> {
     pub fn from_ascii_bytes(value: &'data [u8]) -> NullTerminatedString<'data> {
         NullTerminatedString::new(value.as_bstr())
     }
+
+    /// The text, with bytes that Windows-1252 leaves undefined replaced by U+FFFD
+    pub fn to_string_lossy(&self) -> String {
+        decode_windows1252_lossy(&self.value)
+    }
+
+    /// The text, failing if it has bytes that Windows-1252 leaves undefined
+    pub fn to_string_strict(&self) -> Result<String, ParseError<'static>> {
+        decode_windows1252(&self.value)
+    }
+
+    /// The text, read as [encoding] rather than Windows-1252
+    pub fn to_string_lossy_in(&self, encoding: TextEncoding) -> String {
+        encoding.decode_lossy(&self.value)
+    }
+
+    /// See [NullTerminatedString::to_string_lossy_in]
+    pub fn to_string_strict_in(
+        &self,
+        encoding: TextEncoding,
+    ) -> Result<String, ParseError<'static>> {
+        encoding.decode(&self.value)
+    }
+
+    /// [text] encoded in [encoding] rather than Windows-1252. It can't hold a null, as that would
+    /// end the string early
+    pub fn from_str_in(
+        text: &str,
+        encoding: TextEncoding,
+    ) -> Result<NullTerminatedString<'static>, WriteError> {
+        if text.contains('\0') {
+            return Err(WriteError::Encoding(text.to_string()));
+        }
+        Ok(NullTerminatedString {
+            value: Cow::Owned(encoding.encode(text)?),
+        })
+    }
+}
+impl FromStr for NullTerminatedString<'static> {
+    type Err = WriteError;
+
+    /// Encodes [text] in Windows-1252, see [NullTerminatedString::from_str_in]
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        NullTerminatedString::from_str_in(text, TextEncoding::Windows1252)
+    }
 }
 impl<'data> Parse<'data> for NullTerminatedString<'data> {
     fn parse(data: &'data [u8]) -> PResult<Self> {
@@ -35,7 +81,7 @@ impl<'data> DataSize for NullTerminatedString<'data> {
     }
 }
 impl<'data> Writable for NullTerminatedString<'data> {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: std::io::Write,
     {
@@ -61,4 +107,17 @@ mod tests {
         assert_eq!(data[3], b't');
         assert_eq!(data[4], 0x00);
     }
+
+    #[test]
+    fn test_nstring_encoding() {
+        let s: NullTerminatedString = "Déjà vu".parse().unwrap();
+        assert_eq!(s.value.as_ref(), b"D\xE9j\xE0 vu".as_bstr());
+        assert_eq!(s.data_size(), 8);
+        assert_eq!(s.to_string_strict().unwrap(), "Déjà vu");
+        assert!("a\0b".parse::<NullTerminatedString>().is_err());
+
+        let s = NullTerminatedString::new(b"\x8D".as_bstr());
+        assert_eq!(s.to_string_lossy(), "\u{FFFD}");
+        assert!(s.to_string_strict().is_err());
+    }
 }
@@ -0,0 +1,157 @@
+use super::{decode_windows1252, decode_windows1252_lossy, encode_windows1252};
+use crate::{parse::ParseError, util::WriteError};
+use bstr::BString;
+use encoding_rs::{DecoderResult, Encoding};
+use std::fmt::{Display, Formatter};
+
+/// How the text of string fields is encoded. The game reads it as Windows-1252, but many plugins
+/// made for Special Edition hold UTF-8, and translations are often saved in the code page of
+/// their language
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Hash)]
+pub enum TextEncoding {
+    #[default]
+    Windows1252,
+    Utf8,
+    /// Another single or multi byte encoding, such as windows-1251 for Russian translations
+    CodePage(&'static Encoding),
+}
+impl TextEncoding {
+    /// The encoding with the WHATWG [label], such as "utf-8", "windows-1250" or "gbk".
+    /// Encodings whose text isn't a superset of ASCII, such as UTF-16, can't be used
+    pub fn from_label(label: &str) -> Option<TextEncoding> {
+        let encoding = Encoding::for_label_no_replacement(label.trim().as_bytes())?;
+        if encoding == encoding_rs::WINDOWS_1252 {
+            Some(TextEncoding::Windows1252)
+        } else if encoding == encoding_rs::UTF_8 {
+            Some(TextEncoding::Utf8)
+        } else if encoding.is_ascii_compatible() {
+            Some(TextEncoding::CodePage(encoding))
+        } else {
+            None
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            TextEncoding::Windows1252 => "windows-1252",
+            TextEncoding::Utf8 => "UTF-8",
+            TextEncoding::CodePage(encoding) => encoding.name(),
+        }
+    }
+
+    /// Decodes [data], replacing what isn't valid with U+FFFD
+    pub fn decode_lossy(self, data: &[u8]) -> String {
+        match self {
+            TextEncoding::Windows1252 => decode_windows1252_lossy(data),
+            TextEncoding::Utf8 => String::from_utf8_lossy(data).into_owned(),
+            TextEncoding::CodePage(encoding) => {
+                encoding.decode_without_bom_handling(data).0.into_owned()
+            }
+        }
+    }
+
+    /// Decodes [data], failing at the first byte that isn't valid
+    pub fn decode(self, data: &[u8]) -> Result<String, ParseError<'static>> {
+        let encoding = match self {
+            TextEncoding::Windows1252 => return decode_windows1252(data),
+            TextEncoding::Utf8 => encoding_rs::UTF_8,
+            TextEncoding::CodePage(encoding) => encoding,
+        };
+        let mut decoder = encoding.new_decoder_without_bom_handling();
+        let capacity = decoder
+            .max_utf8_buffer_length_without_replacement(data.len())
+            .unwrap_or(usize::MAX);
+        let mut text = String::with_capacity(capacity);
+        match decoder.decode_to_string_without_replacement(data, &mut text, true) {
+            (DecoderResult::Malformed(bad, extra), read) => {
+                let position = read - usize::from(extra) - usize::from(bad);
+                Err(ParseError::Undecodable {
+                    byte: data[position],
+                    position,
+                })
+            }
+            _ => Ok(text),
+        }
+    }
+
+    /// Encodes [text], failing if it has characters that the encoding lacks
+    pub fn encode(self, text: &str) -> Result<BString, WriteError> {
+        match self {
+            TextEncoding::Windows1252 => encode_windows1252(text),
+            TextEncoding::Utf8 => Ok(BString::from(text)),
+            TextEncoding::CodePage(encoding) => {
+                let (data, _, had_errors) = encoding.encode(text);
+                if had_errors {
+                    Err(WriteError::Encoding(text.to_string()))
+                } else {
+                    Ok(BString::from(data.into_owned()))
+                }
+            }
+        }
+    }
+
+    /// The null terminated string [data] re-encoded from this into [to], or None if it reads the
+    /// same in both, as ASCII text does
+    pub fn transcode(self, data: &[u8], to: TextEncoding) -> Result<Option<Vec<u8>>, WriteError> {
+        let end = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+        let text = &data[..end];
+        if self == to || text.is_ascii() {
+            return Ok(None);
+        }
+        let text = self
+            .decode(text)
+            .map_err(|_| WriteError::Encoding(self.decode_lossy(text)))?;
+        let mut data: Vec<u8> = to.encode(&text)?.into();
+        data.push(0);
+        Ok(Some(data))
+    }
+}
+impl Display for TextEncoding {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bstr::ByteSlice;
+
+    #[test]
+    fn test_text_encoding() {
+        assert_eq!(
+            TextEncoding::from_label("cp1252"),
+            Some(TextEncoding::Windows1252)
+        );
+        assert_eq!(TextEncoding::from_label("UTF-8"), Some(TextEncoding::Utf8));
+        assert_eq!(TextEncoding::from_label("utf-16le"), None);
+        let russian = TextEncoding::from_label("windows-1251").unwrap();
+        assert_eq!(russian.to_string(), "windows-1251");
+
+        assert_eq!(russian.encode("Дом").unwrap(), b"\xC4\xEE\xEC".as_bstr());
+        assert_eq!(russian.decode(b"\xC4\xEE\xEC").unwrap(), "Дом");
+        assert!(TextEncoding::Windows1252.encode("Дом").is_err());
+        assert_eq!(
+            TextEncoding::Windows1252.decode_lossy(b"\xC4\xEE\xEC"),
+            "Äîì"
+        );
+
+        assert_eq!(TextEncoding::Utf8.encode("Дом").unwrap(), "Дом".as_bytes());
+        assert_eq!(
+            TextEncoding::Utf8.decode(b"ok\xC4 "),
+            Err(ParseError::Undecodable {
+                byte: 0xC4,
+                position: 2
+            })
+        );
+        assert_eq!(TextEncoding::Utf8.decode_lossy(b"ok\xC4"), "ok\u{FFFD}");
+
+        let utf8 = TextEncoding::Utf8;
+        assert_eq!(utf8.transcode(b"Iron\0", russian).unwrap(), None);
+        assert_eq!(
+            utf8.transcode("Дом\0".as_bytes(), russian).unwrap(),
+            Some(b"\xC4\xEE\xEC\0".to_vec())
+        );
+        assert!(utf8.transcode(b"\xC4\0", russian).is_err());
+    }
+}
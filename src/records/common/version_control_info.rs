@@ -1,7 +1,7 @@
 use crate::{
     impl_static_data_size,
     parse::{take, PResult, Parse},
-    util::Writable,
+    util::{Writable, WriteError},
 };
 
 /// Version Control User ID
@@ -53,7 +53,7 @@ impl_static_data_size!(
             + VUID::static_data_size() // current_user_id
 );
 impl Writable for VersionControlInfo {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: std::io::Write,
     {
@@ -1,13 +1,91 @@
-use super::BStrw;
+use super::{BStrw, TextEncoding};
 use crate::{
-    parse::{take, PResult, Parse},
-    util::{DataSize, Writable},
+    parse::{take_counted, PResult, Parse, ParseError},
+    util::{length_prefix, DataSize, Writable, WriteError},
 };
-use bstr::{BStr, ByteSlice};
+use bstr::{BStr, BString, ByteSlice};
+use std::{borrow::Cow, str::FromStr};
+
+/// The characters of the bytes 0x80 to 0x9F, where Windows-1252 differs from Latin-1.
+/// None for the five bytes that it leaves undefined
+const HIGH_CHARACTERS: [Option<char>; 32] = [
+    Some('\u{20AC}'),
+    None,
+    Some('\u{201A}'),
+    Some('\u{0192}'),
+    Some('\u{201E}'),
+    Some('\u{2026}'),
+    Some('\u{2020}'),
+    Some('\u{2021}'),
+    Some('\u{02C6}'),
+    Some('\u{2030}'),
+    Some('\u{0160}'),
+    Some('\u{2039}'),
+    Some('\u{0152}'),
+    None,
+    Some('\u{017D}'),
+    None,
+    None,
+    Some('\u{2018}'),
+    Some('\u{2019}'),
+    Some('\u{201C}'),
+    Some('\u{201D}'),
+    Some('\u{2022}'),
+    Some('\u{2013}'),
+    Some('\u{2014}'),
+    Some('\u{02DC}'),
+    Some('\u{2122}'),
+    Some('\u{0161}'),
+    Some('\u{203A}'),
+    Some('\u{0153}'),
+    None,
+    Some('\u{017E}'),
+    Some('\u{0178}'),
+];
+
+/// The character that [byte] is in Windows-1252
+fn decode_byte(byte: u8) -> Option<char> {
+    match byte {
+        0x80..=0x9F => HIGH_CHARACTERS[usize::from(byte - 0x80)],
+        _ => Some(char::from(byte)),
+    }
+}
+
+/// Decodes Windows-1252 [data], replacing the bytes it leaves undefined with U+FFFD
+pub fn decode_windows1252_lossy(data: &[u8]) -> String {
+    data.iter()
+        .map(|&byte| decode_byte(byte).unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect()
+}
+
+/// Decodes Windows-1252 [data], failing at the first byte that it leaves undefined
+pub fn decode_windows1252(data: &[u8]) -> Result<String, ParseError<'static>> {
+    data.iter()
+        .enumerate()
+        .map(|(position, &byte)| {
+            decode_byte(byte).ok_or(ParseError::Undecodable { byte, position })
+        })
+        .collect()
+}
+
+/// Encodes [text] in Windows-1252, failing if it has characters that Windows-1252 lacks
+pub fn encode_windows1252(text: &str) -> Result<BString, WriteError> {
+    text.chars()
+        .map(|character| match u32::from(character) {
+            0x80..=0x9F => None,
+            code @ 0..=0xFF => Some(code as u8),
+            _ => HIGH_CHARACTERS
+                .iter()
+                .position(|&x| x == Some(character))
+                .map(|index| 0x80 + index as u8),
+        })
+        .collect::<Option<Vec<u8>>>()
+        .map(BString::from)
+        .ok_or_else(|| WriteError::Encoding(text.to_string()))
+}
 
 /// A string that is prefixed by 2 bytes for the length
 /// and is encoded in Windows-1252
-/// TODO: for now we just store it as a Byte-string, rather than properly decoding/encoding it.
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct Windows1252String16<'data> {
     pub value: BStrw<'data>,
@@ -22,12 +100,52 @@ impl<'data> Windows1252String16<'data> {
     pub fn from_ascii_bytes(value: &'data [u8]) -> Windows1252String16<'data> {
         Windows1252String16::new(value.as_bstr())
     }
+
+    /// The text, with bytes that Windows-1252 leaves undefined replaced by U+FFFD
+    pub fn to_string_lossy(&self) -> String {
+        decode_windows1252_lossy(&self.value)
+    }
+
+    /// The text, failing if it has bytes that Windows-1252 leaves undefined
+    pub fn to_string_strict(&self) -> Result<String, ParseError<'static>> {
+        decode_windows1252(&self.value)
+    }
+
+    /// The text, read as [encoding] rather than Windows-1252
+    pub fn to_string_lossy_in(&self, encoding: TextEncoding) -> String {
+        encoding.decode_lossy(&self.value)
+    }
+
+    /// See [Windows1252String16::to_string_lossy_in]
+    pub fn to_string_strict_in(
+        &self,
+        encoding: TextEncoding,
+    ) -> Result<String, ParseError<'static>> {
+        encoding.decode(&self.value)
+    }
+
+    /// [text] encoded in [encoding] rather than Windows-1252
+    pub fn from_str_in(
+        text: &str,
+        encoding: TextEncoding,
+    ) -> Result<Windows1252String16<'static>, WriteError> {
+        Ok(Windows1252String16 {
+            value: Cow::Owned(encoding.encode(text)?),
+        })
+    }
+}
+impl FromStr for Windows1252String16<'static> {
+    type Err = WriteError;
+
+    /// Encodes [text] in Windows-1252
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        Windows1252String16::from_str_in(text, TextEncoding::Windows1252)
+    }
 }
 impl<'data> Parse<'data> for Windows1252String16<'data> {
     fn parse(data: &'data [u8]) -> PResult<Self> {
         // TODO: test that this is little endian
-        let (data, length) = u16::parse(data)?;
-        let (data, string) = take(data, length as usize)?;
+        let (data, string) = take_counted::<u16>(data)?;
         Ok((data, Windows1252String16::from_ascii_bytes(string)))
     }
 }
@@ -37,12 +155,11 @@ impl<'data> DataSize for Windows1252String16<'data> {
     }
 }
 impl<'data> Writable for Windows1252String16<'data> {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: std::io::Write,
     {
-        // TODO: assert length fits within usize
-        (self.value.len() as u16).write_to(w)?;
+        length_prefix::<u16>("Windows-1252 string", self.value.len())?.write_to(w)?;
         self.value.write_to(w)
     }
 }
@@ -65,4 +182,35 @@ mod tests {
         assert_eq!(data[4], b's');
         assert_eq!(data[5], b't');
     }
+
+    #[test]
+    fn test_windows1252() {
+        let data = b"Caf\xE9 \x80 \x96 \x9Cuvre\x99";
+        assert_eq!(decode_windows1252_lossy(data), "Café € – œuvre™");
+        assert_eq!(decode_windows1252(data).unwrap(), "Café € – œuvre™");
+        assert_eq!(
+            encode_windows1252("Café € – œuvre™").unwrap(),
+            data.as_bstr()
+        );
+        assert_eq!(
+            decode_windows1252(b"a\x81"),
+            Err(ParseError::Undecodable {
+                byte: 0x81,
+                position: 1
+            })
+        );
+        assert_eq!(decode_windows1252_lossy(b"a\x81"), "a\u{FFFD}");
+        assert!(matches!(
+            encode_windows1252("Дом"),
+            Err(WriteError::Encoding(text)) if text == "Дом"
+        ));
+        // C1 controls aren't characters of Windows-1252, whose bytes for them mean other things
+        assert!(encode_windows1252("\u{80}").is_err());
+
+        let w: Windows1252String16 = "Ædelmann".parse().unwrap();
+        assert_eq!(w.value.as_ref(), b"\xC6delmann".as_bstr());
+        assert_eq!(w.data_size(), 10);
+        assert_eq!(w.to_string_lossy(), "Ædelmann");
+        assert_eq!(w.to_string_strict().unwrap(), "Ædelmann");
+    }
 }
@@ -36,7 +36,7 @@ macro_rules! make_formid_field {
 			}
 		}
 		impl $crate::util::Writable for $name {
-			fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+			fn write_to<T>(&self, w: &mut T) -> Result<(), $crate::util::WriteError>
 			where
 				T: std::io::Write
 			{
@@ -45,6 +45,15 @@ macro_rules! make_formid_field {
 				Ok(())
 			}
 		}
+		impl $crate::records::common::FormIdVisitor for $name {
+			fn visit_formids(&mut self, visit: &mut dyn FnMut(&mut $crate::records::common::FormId)) {
+				visit(&mut self.formid)
+			}
+
+			fn for_each_formid(&self, visit: &mut dyn FnMut($crate::records::common::FormId)) {
+				visit(self.formid)
+			}
+		}
 	}
 }
 
@@ -2,19 +2,46 @@
 
 use super::{write_field_header, FromField, FromFieldError, GeneralField, FIELDH_SIZE};
 use crate::{
-    impl_from_field, impl_static_data_size, impl_static_type_named, make_formid_field,
-    make_single_value_field,
-    parse::{take, PResult, Parse, ParseError},
-    records::common::{lstring::LString, ConversionError, NullTerminatedString},
-    util::{DataSize, StaticDataSize, Writable},
+    impl_from_field, impl_lstring_field, impl_no_formids, impl_static_data_size,
+    impl_static_type_named, make_formid_field, make_single_value_field,
+    parse::{take_array, PResult, Parse, ParseError},
+    records::common::{lstring::LString, ConversionError, FormId, NullTerminatedString},
+    strings::{Localization, StringsKind},
+    util::{DataSize, StaticDataSize, Writable, WriteError},
 };
+use bstr::BStr;
 use std::{
     convert::{TryFrom, TryInto},
     io::Write,
 };
 
+/// Records for things that can be carried, which all have a value and (mostly) a weight
+pub trait Item {
+    /// Base value in gold
+    fn value(&self) -> Option<Gold>;
+    /// None if the record has no weight, such as ammunition before Special Edition
+    fn weight(&self) -> Option<Weight>;
+    /// Whether the player is meant to be able to have the item
+    fn is_playable(&self) -> bool;
+}
+
+/// Items which can be enchanted through an EITM field
+pub trait Enchantable {
+    /// Form id of the ENCH record, along with the enchantment amount (EAMT) if there is one
+    fn enchantment_id(&self) -> Option<(FormId, Option<u16>)>;
+}
+
 #[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
 pub struct Gold(u32);
+impl Gold {
+    pub fn new(value: u32) -> Gold {
+        Gold(value)
+    }
+
+    pub fn amount(self) -> u32 {
+        self.0
+    }
+}
 impl Parse<'_> for Gold {
     fn parse(data: &[u8]) -> PResult<Self> {
         let (data, value) = u32::parse(data)?;
@@ -23,7 +50,7 @@ impl Parse<'_> for Gold {
 }
 impl_static_data_size!(Gold, u32::static_data_size());
 impl Writable for Gold {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -32,6 +59,15 @@ impl Writable for Gold {
 }
 #[derive(Debug, Copy, Clone, PartialOrd, PartialEq)]
 pub struct Weight(f32);
+impl Weight {
+    pub fn new(value: f32) -> Weight {
+        Weight(value)
+    }
+
+    pub fn amount(self) -> f32 {
+        self.0
+    }
+}
 impl Parse<'_> for Weight {
     fn parse(data: &[u8]) -> PResult<Self> {
         let (data, value) = f32::parse(data)?;
@@ -40,7 +76,7 @@ impl Parse<'_> for Weight {
 }
 impl_static_data_size!(Weight, f32::static_data_size());
 impl Writable for Weight {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -57,6 +93,7 @@ make_single_value_field!(
     'data
 );
 impl_from_field!(ICON, 'data, [filename: NullTerminatedString]);
+impl_no_formids!(ICON<'_>);
 
 make_single_value_field!(
     /// Message icon filename
@@ -67,6 +104,7 @@ make_single_value_field!(
     'data
 );
 impl_from_field!(MICO, 'data, [filename: NullTerminatedString]);
+impl_no_formids!(MICO<'_>);
 
 make_formid_field!(
     /// Pickup ->SNDR
@@ -84,9 +122,10 @@ pub struct QUAL {
 }
 impl_from_field!(QUAL, [quality: Quality]);
 impl_static_type_named!(QUAL, b"QUAL");
+impl_no_formids!(QUAL);
 impl_static_data_size!(QUAL, FIELDH_SIZE + Quality::static_data_size());
 impl Writable for QUAL {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -113,7 +152,7 @@ impl Parse<'_> for Quality {
 }
 impl_static_data_size!(Quality, u32::static_data_size());
 impl Writable for Quality {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -136,12 +175,20 @@ impl TryFrom<u32> for Quality {
 
 make_single_value_field!(
     /// Description
-    [Debug, Copy, Clone, Eq, PartialEq],
+    [Debug, Clone, Eq, PartialEq],
     DESC,
     description,
-    LString
+    LString,
+    'data
 );
-impl_from_field!(DESC, [description: LString]);
+impl_lstring_field!(DESC, description);
+impl_no_formids!(DESC<'_>);
+impl DESC<'_> {
+    /// The description from the DLSTRINGS table, see [Localization::resolve]
+    pub fn text<'a>(&'a self, localization: &'a Localization) -> Option<&'a BStr> {
+        localization.resolve(&self.description, StringsKind::DlStrings)
+    }
+}
 
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct BODT {
@@ -157,8 +204,7 @@ impl FromField<'_> for BODT {
     fn from_field(field: GeneralField<'_>) -> PResult<Self, FromFieldError> {
         let (data, part_node_flags) = BodyPartNodeFlags::parse(field.data)?;
         let (data, flags) = BODTFlags::parse(data)?;
-        let (data, unknown) = take(data, 3)?;
-        let unknown = [unknown[0], unknown[1], unknown[2]];
+        let (data, unknown) = take_array::<3>(data)?;
         let (data, skill) = if !data.is_empty() {
             let (data, skill) = ArmorSkill::parse(data)?;
             (data, Some(skill))
@@ -177,7 +223,18 @@ impl FromField<'_> for BODT {
         ))
     }
 }
+impl BODT {
+    /// The BOD2 equivalent, which lacks the flags and the unknown bytes.
+    /// A missing skill becomes [ArmorSkill::None]
+    pub fn to_bod2(&self) -> BOD2 {
+        BOD2 {
+            part_node_flags: self.part_node_flags,
+            skill: self.skill.unwrap_or(ArmorSkill::None),
+        }
+    }
+}
 impl_static_type_named!(BODT, b"BODT");
+impl_no_formids!(BODT);
 impl DataSize for BODT {
     fn data_size(&self) -> usize {
         FIELDH_SIZE
@@ -188,7 +245,7 @@ impl DataSize for BODT {
     }
 }
 impl Writable for BODT {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -218,7 +275,7 @@ impl Parse<'_> for BodyPartNodeFlags {
 }
 impl_static_data_size!(BodyPartNodeFlags, u32::static_data_size());
 impl Writable for BodyPartNodeFlags {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -233,6 +290,12 @@ pub struct BODTFlags {
     /// 0x10: Non-playable (ARMO only)
     pub flags: u8,
 }
+impl BODTFlags {
+    /// ARMA only
+    pub const MODULATES_VOICE: u8 = 0x1;
+    /// ARMO only. With BOD2 this is a record flag instead
+    pub const NON_PLAYABLE: u8 = 0x10;
+}
 impl Parse<'_> for BODTFlags {
     fn parse(data: &[u8]) -> PResult<Self> {
         let (data, flags) = u8::parse(data)?;
@@ -241,7 +304,7 @@ impl Parse<'_> for BODTFlags {
 }
 impl_static_data_size!(BODTFlags, u8::static_data_size());
 impl Writable for BODTFlags {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -273,7 +336,7 @@ impl Parse<'_> for ArmorSkill {
 }
 impl_static_data_size!(ArmorSkill, u32::static_data_size());
 impl Writable for ArmorSkill {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -303,12 +366,13 @@ impl_from_field!(
     [part_node_flags: BodyPartNodeFlags, skill: ArmorSkill]
 );
 impl_static_type_named!(BOD2, b"BOD2");
+impl_no_formids!(BOD2);
 impl_static_data_size!(
     BOD2,
     FIELDH_SIZE + BodyPartNodeFlags::static_data_size() + ArmorSkill::static_data_size()
 );
 impl Writable for BOD2 {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -318,19 +382,56 @@ impl Writable for BOD2 {
     }
 }
 
+/// Normalized view over either a BODT or a BOD2 field
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct BodyTemplate {
+    pub part_node_flags: BodyPartNodeFlags,
+    /// Only BODT has flags
+    pub flags: Option<BODTFlags>,
+    pub skill: ArmorSkill,
+}
+impl From<&BODT> for BodyTemplate {
+    fn from(bodt: &BODT) -> BodyTemplate {
+        BodyTemplate {
+            part_node_flags: bodt.part_node_flags,
+            flags: Some(bodt.flags),
+            skill: bodt.skill.unwrap_or(ArmorSkill::None),
+        }
+    }
+}
+impl From<&BOD2> for BodyTemplate {
+    fn from(bod2: &BOD2) -> BodyTemplate {
+        BodyTemplate {
+            part_node_flags: bod2.part_node_flags,
+            flags: None,
+            skill: bod2.skill,
+        }
+    }
+}
+
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub struct DATA {
     value: Gold,
     weight: Weight,
 }
+impl DATA {
+    pub fn value(&self) -> Gold {
+        self.value
+    }
+
+    pub fn weight(&self) -> Weight {
+        self.weight
+    }
+}
 impl_from_field!(DATA, [value: Gold, weight: Weight]);
 impl_static_type_named!(DATA, b"DATA");
+impl_no_formids!(DATA);
 impl_static_data_size!(
     DATA,
     FIELDH_SIZE + u32::static_data_size() + f32::static_data_size()
 );
 impl Writable for DATA {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -1,11 +1,14 @@
 use crate::{
+    context::ParseContext,
+    error::{ErrorCode, VivecError},
+    impl_no_formids,
     parse::{take, PResult, Parse, ParseError},
     records::common::TypeNamed,
-    util::{fmt_data, DataSize, Writable},
+    util::{byte, fmt_data, DataSize, Writable, WriteError},
 };
 use bstr::{BStr, ByteSlice};
 use derive_more::From;
-use std::io::Write;
+use std::{convert::TryFrom, io::Write};
 
 pub mod formid_wrap;
 pub mod item;
@@ -18,7 +21,7 @@ pub type FieldName<'data> = &'data BStr;
 /// Field header size, (type_name_len + data_size_len)
 pub const FIELDH_SIZE: usize = 4 + 2;
 /// Writes the fields header to [writer]
-pub fn write_field_header<'data, T, W>(field: &T, writer: &mut W) -> std::io::Result<()>
+pub fn write_field_header<'data, T, W>(field: &T, writer: &mut W) -> Result<(), WriteError>
 where
     T: TypeNamed<'data> + DataSize,
     W: std::io::Write,
@@ -30,15 +33,49 @@ where
             field.type_name()
         )
     );
-    writer.write_all(field.type_name().as_bstr())?;
-    // TODO: assert that data_size fits wthin a u16
     // We subtract the FIELDH_SIZE, since the calculations shouldn't include that
     let data_size = field.data_size() - FIELDH_SIZE;
-    writer.write_all(&(data_size as u16).to_le_bytes())?;
+    let data_size =
+        u16::try_from(data_size).map_err(|_| field_too_large(field.type_name(), data_size))?;
+    writer.write_all(field.type_name().as_bstr())?;
+    writer.write_all(&data_size.to_le_bytes())?;
 
     Ok(())
 }
 
+fn field_too_large(type_name: &BStr, size: usize) -> WriteError {
+    WriteError::FieldTooLarge {
+        type_name: type_name.to_string(),
+        size,
+    }
+}
+
+/// Size of an XXXX field, which precedes a field whose data is too large for the u16 size in its
+/// header and holds the real size instead
+pub const XXXX_SIZE: usize = FIELDH_SIZE + 4;
+
+/// Parses the fields of a record. XXXX fields are folded into the field after them, as they only
+/// hold its size
+pub fn parse_fields(mut data: &[u8]) -> PResult<'_, Vec<GeneralField<'_>>> {
+    let mut fields = Vec::new();
+    while !data.is_empty() {
+        let (rest, field) = GeneralField::parse(data)?;
+        if field.type_name == "XXXX" && field.data.len() == 4 {
+            let size = u32::from_le_bytes(byte::as_4_bytes(field.data)) as usize;
+            let (rest, type_name) = take(rest, 4)?;
+            // The size in the header is unused, usually 0
+            let (rest, _) = u16::parse(rest)?;
+            let (rest, field_data) = take(rest, size)?;
+            fields.push(GeneralField::new(type_name.as_bstr(), field_data));
+            data = rest;
+        } else {
+            fields.push(field);
+            data = rest;
+        }
+    }
+    Ok((data, fields))
+}
+
 /// A general holder for fields which we don't know anything about
 /// may or may not be compressed
 #[derive(Clone, Eq, PartialEq)]
@@ -50,6 +87,15 @@ impl<'data> GeneralField<'data> {
     pub fn new(type_name: FieldName<'data>, data: &'data [u8]) -> GeneralField<'data> {
         GeneralField { type_name, data }
     }
+
+    /// Interprets the field as [T], for when it is known what a field is after it was parsed
+    pub fn decode_as<T>(&self) -> Result<T, FromFieldError<'data>>
+    where
+        T: FromField<'data>,
+    {
+        let (_, value) = T::from_field(self.clone())?;
+        Ok(value)
+    }
 }
 impl<'data> Parse<'data> for GeneralField<'data> {
     fn parse(data: &'data [u8]) -> PResult<GeneralField<'data>> {
@@ -67,21 +113,38 @@ impl<'data> TypeNamed<'data> for GeneralField<'data> {
     }
 }
 impl<'data> Writable for GeneralField<'data> {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
-        write_field_header(self, w)?;
+        if self.data.len() > u16::MAX as usize {
+            let data_size = u32::try_from(self.data.len())
+                .map_err(|_| field_too_large(self.type_name, self.data.len()))?;
+            w.write_all(b"XXXX")?;
+            4u16.write_to(w)?;
+            data_size.write_to(w)?;
+            w.write_all(self.type_name)?;
+            0u16.write_to(w)?;
+        } else {
+            write_field_header(self, w)?;
+        }
         w.write_all(self.data)?;
         Ok(())
     }
 }
 impl<'data> DataSize for GeneralField<'data> {
-    /// Only valid when data.len() is less than u16::MAX
+    /// Includes the XXXX field that is written before fields too large for their header
     fn data_size(&self) -> usize {
-        FIELDH_SIZE + self.data.len()
+        let xxxx = if self.data.len() > u16::MAX as usize {
+            XXXX_SIZE
+        } else {
+            0
+        };
+        xxxx + FIELDH_SIZE + self.data.len()
     }
 }
+// What the data of a general field holds isn't known, so it can't be visited
+impl_no_formids!(GeneralField<'_>);
 impl<'data> std::fmt::Debug for GeneralField<'data> {
     fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let mut res = fmt.debug_struct("GeneralField");
@@ -99,9 +162,44 @@ pub enum FromFieldError<'data> {
     ExpectedSpecificField(FieldName<'data>),
     ParseError(ParseError<'data>),
 }
+impl std::fmt::Display for FromFieldError<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FromFieldError::UnexpectedEnd => write!(f, "Unexpected end of fields"),
+            FromFieldError::ExpectedSpecificField(name) => write!(f, "Expected a {} field", name),
+            FromFieldError::ParseError(_) => write!(f, "Failed to parse"),
+        }
+    }
+}
+impl std::error::Error for FromFieldError<'_> {}
+impl VivecError for FromFieldError<'_> {
+    fn code(&self) -> ErrorCode {
+        match self {
+            FromFieldError::UnexpectedEnd => ErrorCode::UnexpectedEndOfFields,
+            FromFieldError::ExpectedSpecificField(_) => ErrorCode::ExpectedField,
+            FromFieldError::ParseError(error) => error.code(),
+        }
+    }
+
+    fn inner(&self) -> Option<&dyn VivecError> {
+        match self {
+            FromFieldError::ParseError(error) => Some(error),
+            _ => None,
+        }
+    }
+}
 
 pub trait FromField<'data>: Sized {
     fn from_field(field: GeneralField<'data>) -> PResult<'data, Self, FromFieldError>;
+
+    /// Used when converting the fields of a record, so that fields whose layout depends on
+    /// the form version can pick the layout explicitly. Defaults to [from_field]
+    fn from_field_ctx(
+        field: GeneralField<'data>,
+        _ctx: &mut ParseContext,
+    ) -> PResult<'data, Self, FromFieldError<'data>> {
+        Self::from_field(field)
+    }
 }
 
 pub trait CollectField<'data, C>: Sized {
@@ -141,7 +239,7 @@ macro_rules! make_empty_field {
             }
         }
         impl $crate::util::Writable for $name {
-            fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+            fn write_to<T>(&self, w: &mut T) -> Result<(), $crate::util::WriteError>
             where
                 T: std::io::Write
             {
@@ -149,6 +247,7 @@ macro_rules! make_empty_field {
                 Ok(())
             }
         }
+        $crate::impl_no_formids!($name);
     };
 }
 
@@ -175,7 +274,7 @@ macro_rules! make_single_value_field {
             }
         }
         impl $crate::util::Writable for $name {
-            fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+            fn write_to<T>(&self, w: &mut T) -> Result<(), $crate::util::WriteError>
             where
                 T: std::io::Write
             {
@@ -213,7 +312,7 @@ macro_rules! make_single_value_field {
             }
         }
         impl<$life> $crate::util::Writable for $name<$life> {
-            fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+            fn write_to<T>(&self, w: &mut T) -> Result<(), $crate::util::WriteError>
             where
                 T: std::io::Write
             {
@@ -256,9 +355,14 @@ macro_rules! impl_from_field {
 
 #[cfg(test)]
 mod test {
-    use super::GeneralField;
-    use crate::assert_size_output;
-    use bstr::ByteSlice;
+    use super::*;
+    use crate::{
+        assert_size_output,
+        records::{
+            common::{CommonRecordInfo, GeneralRecord, NullTerminatedString},
+            fields::edid::EDID,
+        },
+    };
 
     #[test]
     fn general_field_test() {
@@ -269,4 +373,42 @@ mod test {
 
         assert_size_output!(field);
     }
+
+    #[test]
+    fn test_xxxx_fields() {
+        let large = vec![7; 70000];
+        let field = GeneralField::new(b"OFST".as_bstr(), &large);
+        let mut data = Vec::new();
+        field.write_to(&mut data).unwrap();
+        assert_eq!(data.len(), field.data_size());
+        assert_eq!(&data[..6], b"XXXX\x04\x00");
+        assert_eq!(&data[6..10], &70000u32.to_le_bytes());
+        assert_eq!(&data[10..16], b"OFST\x00\x00");
+
+        let small = GeneralField::new(b"EDID".as_bstr(), b"Tamriel\0");
+        small.write_to(&mut data).unwrap();
+        let (rest, fields) = parse_fields(&data).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(fields, vec![field.clone(), small]);
+
+        let record = GeneralRecord {
+            type_name: b"WRLD".as_bstr(),
+            common: CommonRecordInfo::test_default(),
+            fields: vec![field],
+        };
+        let mut written = Vec::new();
+        record.write_to(&mut written).unwrap();
+        let (_, parsed) = GeneralRecord::parse(&written).unwrap();
+        assert_eq!(parsed, record);
+
+        // Typed fields have no XXXX form
+        let long = "x".repeat(70000);
+        let edid = EDID {
+            id: NullTerminatedString::new(long.as_bytes().as_bstr()),
+        };
+        assert!(matches!(
+            edid.write_to(&mut Vec::new()),
+            Err(WriteError::FieldTooLarge { size, .. }) if size == edid.data_size() - FIELDH_SIZE
+        ));
+    }
 }
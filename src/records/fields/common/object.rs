@@ -1,10 +1,23 @@
-use crate::{impl_from_field, make_single_value_field, records::common::lstring::LString};
+use crate::{
+    impl_lstring_field, impl_no_formids, make_single_value_field,
+    records::common::lstring::LString,
+    strings::{Localization, StringsKind},
+};
+use bstr::BStr;
 
 make_single_value_field!(
     /// Ingame name
-    [Debug, Copy, Clone, Eq, PartialEq],
+    [Debug, Clone, Eq, PartialEq],
     FULL,
     name,
-    LString
+    LString,
+    'data
 );
-impl_from_field!(FULL, [name: LString]);
+impl_lstring_field!(FULL, name);
+impl_no_formids!(FULL<'_>);
+impl FULL<'_> {
+    /// The name from the STRINGS table, see [Localization::resolve]
+    pub fn text<'a>(&'a self, localization: &'a Localization) -> Option<&'a BStr> {
+        localization.resolve(&self.name, StringsKind::Strings)
+    }
+}
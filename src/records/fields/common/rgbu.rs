@@ -1,6 +1,6 @@
 use crate::{
     parse::{single, PResult, Parse},
-    util::{DataSize, Writable},
+    util::{DataSize, Writable, WriteError},
 };
 
 /// An RGB structure with an unused (?) third component
@@ -41,7 +41,7 @@ impl DataSize for RGBU {
     }
 }
 impl Writable for RGBU {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: std::io::Write,
     {
@@ -4,9 +4,9 @@
 use super::common::{write_field_header, FromField, FromFieldError, GeneralField, FIELDH_SIZE};
 use crate::{
     impl_static_data_size, impl_static_type_named,
-    parse::{single, take, PResult, Parse, ParseError},
-    records::common::{ConversionError, FormId},
-    util::Writable,
+    parse::{single, take_array, PResult, Parse, ParseError},
+    records::common::{ConversionError, FormId, FormIdVisitor},
+    util::{Writable, WriteError},
 };
 use std::io::Write;
 
@@ -35,8 +35,7 @@ pub struct CTDA {
 impl FromField<'_> for CTDA {
     fn from_field(field: GeneralField<'_>) -> PResult<Self, FromFieldError> {
         let (data, op_data) = OperatorData::parse(field.data)?;
-        let (data, unknown) = take(data, 3)?;
-        let unknown = [unknown[0], unknown[1], unknown[2]];
+        let (data, unknown) = take_array::<3>(data)?;
         let (data, comp_value) = ComparisonValue::parse(data, op_data.flags)?;
         let (data, function_index) = u16::parse(data)?;
         let (data, padding) = u16::parse(data)?;
@@ -61,6 +60,55 @@ impl FromField<'_> for CTDA {
     }
 }
 impl_static_type_named!(CTDA, b"CTDA");
+impl CTDA {
+    /// Which of the parameters are form ids. Neither are when the flags say that they are quest
+    /// aliases or package data instead, see [formid_parameters]
+    pub fn formid_parameters(&self) -> [bool; 2] {
+        let flags = self.op_data.flags;
+        if flags.use_aliases() || flags.use_pack_data() {
+            [false, false]
+        } else {
+            formid_parameters(self.function_index)
+        }
+    }
+}
+impl FormIdVisitor for CTDA {
+    /// The global compared against, the parameters that are form ids, and the reference when the
+    /// condition runs on one
+    fn visit_formids(&mut self, visit: &mut dyn FnMut(&mut FormId)) {
+        if let ComparisonValue::Glob(global) = &mut self.comp_value {
+            visit(global);
+        }
+        let [first_id, second_id] = self.formid_parameters();
+        let Parameters::Normal { first, second } = &mut self.parameters;
+        for (parameter, is_id) in [(first, first_id), (second, second_id)] {
+            if is_id {
+                let mut id = FormId::new(*parameter);
+                visit(&mut id);
+                *parameter = id.id;
+            }
+        }
+        if self.run_on == RunOn::Reference {
+            visit(&mut self.reference);
+        }
+    }
+
+    fn for_each_formid(&self, visit: &mut dyn FnMut(FormId)) {
+        if let ComparisonValue::Glob(global) = self.comp_value {
+            visit(global);
+        }
+        let [first_id, second_id] = self.formid_parameters();
+        let Parameters::Normal { first, second } = self.parameters;
+        for (parameter, is_id) in [(first, first_id), (second, second_id)] {
+            if is_id {
+                visit(FormId::new(parameter));
+            }
+        }
+        if self.run_on == RunOn::Reference {
+            visit(self.reference);
+        }
+    }
+}
 impl_static_data_size!(
     CTDA,
     FIELDH_SIZE +
@@ -75,7 +123,7 @@ impl_static_data_size!(
 	i32::static_data_size()
 );
 impl Writable for CTDA {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -143,6 +191,18 @@ impl Operator {
     pub fn bits(&self) -> u8 {
         self.code() << 5
     }
+
+    /// [left] [operator] [right]
+    pub fn compare(&self, left: f32, right: f32) -> bool {
+        match self {
+            Operator::Equal => left == right,
+            Operator::NotEqual => left != right,
+            Operator::GreaterThan => left > right,
+            Operator::GreaterThanEqual => left >= right,
+            Operator::LessThan => left < right,
+            Operator::LessThanEqual => left <= right,
+        }
+    }
 }
 /// repr lower 5 bits
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
@@ -203,7 +263,7 @@ impl Parse<'_> for OperatorData {
 }
 impl_static_data_size!(OperatorData, u8::static_data_size());
 impl Writable for OperatorData {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -234,7 +294,7 @@ impl_static_data_size!(
     FormId::static_data_size().max(f32::static_data_size())
 );
 impl Writable for ComparisonValue {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -259,7 +319,7 @@ impl Parameters {
     }
 }
 impl Writable for Parameters {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -273,6 +333,91 @@ impl Writable for Parameters {
 }
 impl_static_data_size!(Parameters, u64::static_data_size());
 
+/// Condition functions that take a form id as either of their parameters, by their index, along
+/// with which of the two do
+const FORMID_PARAMETERS: &[(FunctionIndex, [bool; 2])] = &[
+    (1, [true, false]),   // GetDistance
+    (27, [true, false]),  // GetLineOfSight
+    (32, [true, false]),  // GetInSameCell
+    (42, [true, false]),  // SameFaction
+    (43, [true, false]),  // SameRace
+    (44, [true, false]),  // SameSex
+    (45, [true, false]),  // GetDetected
+    (47, [true, false]),  // GetItemCount
+    (53, [true, false]),  // GetScriptVariable
+    (56, [true, false]),  // GetQuestRunning
+    (58, [true, false]),  // GetStage
+    (59, [true, false]),  // GetStageDone
+    (60, [true, true]),   // GetFactionRankDifference
+    (67, [true, false]),  // GetInCell
+    (68, [true, false]),  // GetIsClass
+    (69, [true, false]),  // GetIsRace
+    (71, [true, false]),  // GetInFaction
+    (72, [true, false]),  // GetIsID
+    (73, [true, false]),  // GetFactionRank
+    (74, [true, false]),  // GetGlobalValue
+    (79, [true, false]),  // GetQuestVariable
+    (84, [true, false]),  // GetDeadCount
+    (99, [true, false]),  // GetHeadingAngle
+    (122, [true, false]), // GetCrime
+    (129, [true, false]), // GetPCIsClass
+    (130, [true, false]), // GetPCIsRace
+    (132, [true, false]), // GetPCInFaction
+    (136, [true, false]), // GetIsReference
+    (149, [true, false]), // GetIsCurrentWeather
+    (152, [true, false]), // GetIsCrimeFaction
+    (161, [true, false]), // GetIsCurrentPackage
+    (162, [true, false]), // IsCurrentFurnitureRef
+    (163, [true, false]), // IsCurrentFurnitureObj
+    (182, [true, false]), // GetEquipped
+    (214, [true, false]), // HasMagicEffect
+    (223, [true, false]), // IsSpellTarget
+    (228, [true, false]), // GetIsClassDefault
+    (230, [true, true]),  // GetInCellParam
+    (248, [true, false]), // IsScenePlaying
+    (250, [true, false]), // GetLocationCleared
+    (258, [true, true]),  // HasAssociationType
+    (259, [true, false]), // HasFamilyRelationship
+    (261, [true, false]), // HasParentRelationship
+    (264, [true, false]), // HasSpell
+    (278, [true, false]), // IsOwner
+    (280, [true, true]),  // IsCellOwner
+    (310, [true, false]), // GetInWorldspace
+    (359, [true, false]), // GetInCurrentLoc
+    (372, [true, false]), // IsInList
+    (378, [true, false]), // HasShout
+    (403, [true, false]), // GetRelationshipRank
+    (426, [true, false]), // GetIsVoiceType
+    (444, [true, false]), // GetInCurrentLocFormList
+    (448, [true, false]), // HasPerk
+    (449, [true, false]), // GetFactionRelation
+    (465, [true, false]), // GetKeywordItemCount
+    (543, [true, false]), // GetQuestCompleted
+    (560, [true, false]), // HasKeyword
+    (561, [true, false]), // HasRefType
+    (562, [true, false]), // LocationHasKeyword
+    (563, [true, false]), // LocationHasRefType
+    (565, [true, false]), // GetIsEditorLocation
+    (579, [true, false]), // GetEquippedShout
+    (595, [true, false]), // IsCurrentSpell
+    (596, [false, true]), // SpellHasKeyword
+    (603, [true, true]),  // IsInSameCurrentLocAsRef
+    (624, [true, false]), // GetInContainer
+    (625, [true, false]), // IsLocationLoaded
+    (629, [true, false]), // GetVMQuestVariable
+    (650, [true, true]),  // IsLinkedTo
+    (682, [true, false]), // WornHasKeyword
+    (699, [true, false]), // HasMagicEffectKeyword
+    (707, [true, false]), // GetCombatTargetHasKeyword
+];
+
+/// Which of the two parameters of the condition function at [function] are form ids
+pub fn formid_parameters(function: FunctionIndex) -> [bool; 2] {
+    FORMID_PARAMETERS
+        .binary_search_by_key(&function, |(index, _)| *index)
+        .map_or([false, false], |x| FORMID_PARAMETERS[x].1)
+}
+
 /// The method of applying the condition
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 #[repr(u32)]
@@ -320,7 +465,7 @@ impl Parse<'_> for RunOn {
 }
 impl_static_data_size!(RunOn, u32::static_data_size());
 impl Writable for RunOn {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -352,5 +497,26 @@ mod tests {
             unknown2: -1,
         };
         assert_size_output!(ctda);
+
+        // GetInFaction(0x13) != 1.0
+        let mut ctda = ctda;
+        ctda.function_index = 71;
+        ctda.parameters = Parameters::Normal {
+            first: 0x13,
+            second: 0,
+        };
+        assert_eq!(ctda.formids(), vec![FormId::new(0x13)]);
+        ctda.visit_formids(&mut |id| id.id += 0x0100_0000);
+        assert_eq!(
+            ctda.parameters,
+            Parameters::Normal {
+                first: 0x0100_0013,
+                second: 0
+            }
+        );
+        // The parameter is a quest alias instead
+        ctda.op_data.flags = Flags::from_byte(0b10);
+        assert!(ctda.formids().is_empty());
+        assert!(FORMID_PARAMETERS.windows(2).all(|x| x[0].0 < x[1].0));
     }
 }
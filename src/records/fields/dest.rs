@@ -1,10 +1,10 @@
 use super::common::{write_field_header, CollectField, FromFieldError, GeneralField, FIELDH_SIZE};
 use crate::{
-    impl_from_field, impl_static_data_size, impl_static_type_named, make_empty_field,
-    make_model_fields, make_single_value_field,
+    impl_formid_visitor, impl_from_field, impl_no_formids, impl_static_data_size,
+    impl_static_type_named, make_empty_field, make_model_fields, make_single_value_field,
     parse::{take, PResult, Parse},
     records::common::{get_field, FormId, StaticTypeNamed},
-    util::{DataSize, Writable},
+    util::{DataSize, Writable, WriteError},
 };
 use bstr::{BStr, ByteSlice};
 use std::io::Write;
@@ -22,12 +22,13 @@ pub struct DEST {
 }
 impl_from_field!(DEST, [health: u32, count: u8, flags: u8, unknown: u16]);
 impl_static_type_named!(DEST, b"DEST");
+impl_no_formids!(DEST);
 impl_static_data_size!(
     DEST,
     FIELDH_SIZE + u32::static_data_size() + (u8::static_data_size() * 4)
 );
 impl Writable for DEST {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -66,6 +67,7 @@ impl_from_field!(
     ]
 );
 impl_static_type_named!(DSTD, b"DSTD");
+impl_formid_visitor!(DSTD, [explosion_id, debris_id]);
 impl_static_data_size!(
     DSTD,
     FIELDH_SIZE +
@@ -78,7 +80,7 @@ impl_static_data_size!(
 	u32::static_data_size() // debris count
 );
 impl Writable for DSTD {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -125,7 +127,7 @@ impl Parse<'_> for DSTDFlags {
 }
 impl_static_data_size!(DSTDFlags, u8::static_data_size());
 impl Writable for DSTDFlags {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -167,13 +169,14 @@ impl<'data> CollectField<'data, DEST> for DESTCollection<'data> {
     }
 }
 impl_static_type_named!(DESTCollection<'_>, DEST::static_type_name());
+impl_formid_visitor!(DESTCollection<'_>, [stage_data: DSTDCollection<'_>]);
 impl<'data> DataSize for DESTCollection<'data> {
     fn data_size(&self) -> usize {
         self.destruction.data_size() + self.stage_data.data_size()
     }
 }
 impl<'data> Writable for DESTCollection<'data> {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -226,13 +229,14 @@ impl<'data> DSTDCollection<'data> {
     }
 }
 impl_static_type_named!(DSTDCollection<'_>, DSTD::static_type_name());
+impl_formid_visitor!(DSTDCollection<'_>, [stage: DSTD, model: DMDLCollection<'_>]);
 impl<'data> DataSize for DSTDCollection<'data> {
     fn data_size(&self) -> usize {
         self.stage.data_size() + self.model.data_size() + self.end.data_size()
     }
 }
 impl<'data> Writable for DSTDCollection<'data> {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -1,9 +1,13 @@
-use crate::{impl_from_field, make_single_value_field, records::common::NullTerminatedString};
+use crate::{
+    impl_from_field, impl_no_formids, make_single_value_field,
+    records::common::NullTerminatedString,
+};
 
 make_single_value_field!(
     /// MUST BE NAMED EDID, currently this value is hardcoded.
     [Debug, Clone, Eq, PartialEq], EDID, id, NullTerminatedString, 'data);
 impl_from_field!(EDID, 'data, [id: NullTerminatedString]);
+impl_no_formids!(EDID<'_>);
 
 #[cfg(test)]
 mod test {
@@ -1,9 +1,9 @@
 use super::common::{CollectField, FromFieldError, GeneralField};
 use crate::{
-    impl_from_field, impl_static_type_named, make_single_value_field,
+    impl_formid_visitor, impl_from_field, impl_static_type_named, make_single_value_field,
     parse::{count, PResult, Parse},
-    records::common::{FormId, StaticTypeNamed, TypeNamed},
-    util::{DataSize, Writable},
+    records::common::{get_field, FormId, StaticTypeNamed, TypeNamed},
+    util::{DataSize, Writable, WriteError},
 };
 
 make_single_value_field!(
@@ -24,6 +24,7 @@ make_single_value_field!(
     keywords,
     Vec<FormId>
 );
+impl_formid_visitor!(KWDA, [keywords]);
 //impl FromField<'_> for KWDA {
 impl KWDA {
     pub fn from_field(field: GeneralField<'_>, amount: u32) -> PResult<Self, FromFieldError> {
@@ -33,24 +34,81 @@ impl KWDA {
 }
 
 /// KWDACollection
+/// UESP notes that there may be more than one KSIZ/KWDA pair. Pairs directly following each other
+/// are kept in the same collection (as separate runs, so that they are written back the same way).
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct KWDACollection {
     // Note: we don't keep the KSIZ instance in here, since it can be generated from the KWDA instance :]
-    keywords: KWDA,
+    runs: Vec<KWDA>,
 }
 impl KWDACollection {
-    pub fn create_ksiz(&self) -> KSIZ {
+    pub fn new(keywords: Vec<FormId>) -> KWDACollection {
+        KWDACollection {
+            runs: vec![KWDA { keywords }],
+        }
+    }
+
+    /// The KSIZ/KWDA pairs, in order
+    pub fn runs(&self) -> &[KWDA] {
+        &self.runs
+    }
+
+    pub fn runs_mut(&mut self) -> &mut Vec<KWDA> {
+        &mut self.runs
+    }
+
+    /// All keywords, across every run
+    pub fn keywords(&self) -> impl Iterator<Item = &FormId> {
+        self.runs.iter().flat_map(|run| run.keywords.iter())
+    }
+
+    pub fn len(&self) -> usize {
+        self.runs.iter().map(|run| run.keywords.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn contains(&self, id: FormId) -> bool {
+        self.keywords().any(|x| *x == id)
+    }
+
+    /// Adds [id] to the last run. Returns false if it was already there
+    pub fn add(&mut self, id: FormId) -> bool {
+        if self.contains(id) {
+            return false;
+        }
+        match self.runs.last_mut() {
+            Some(run) => run.keywords.push(id),
+            None => self.runs.push(KWDA { keywords: vec![id] }),
+        }
+        true
+    }
+
+    /// Removes [id] from every run, dropping runs that become empty.
+    /// Returns false if it was not there
+    pub fn remove(&mut self, id: FormId) -> bool {
+        let len = self.len();
+        for run in self.runs.iter_mut() {
+            run.keywords.retain(|x| *x != id);
+        }
+        self.runs.retain(|run| !run.keywords.is_empty());
+        self.len() != len
+    }
+
+    fn create_ksiz(run: &KWDA) -> KSIZ {
         // TODO: check that it fits
         KSIZ {
-            amount: self.keywords.keywords.len() as u32,
+            amount: run.keywords.len() as u32,
         }
     }
-}
-impl<'data> CollectField<'data, KSIZ> for KWDACollection {
-    fn collect<I>(
+
+    /// Reads the KWDA that must follow [ksiz]
+    fn collect_run<'data, I>(
         ksiz: KSIZ,
         field_iter: &mut std::iter::Peekable<I>,
-    ) -> PResult<'data, Self, FromFieldError<'data>>
+    ) -> PResult<'data, KWDA, FromFieldError<'data>>
     where
         I: std::iter::Iterator<Item = GeneralField<'data>>,
     {
@@ -66,22 +124,143 @@ impl<'data> CollectField<'data, KSIZ> for KWDACollection {
         } else {
             let field = field_iter.next().unwrap();
             let (_, field) = KWDA::from_field(field, ksiz.amount)?;
-            Ok((&[], KWDACollection { keywords: field }))
+            Ok((&[], field))
         }
     }
 }
+/// Records that can have keywords
+pub trait Keyworded {
+    /// The keywords fields of the record, in order. KSIZ/KWDA pairs that aren't next to each
+    /// other are in separate collections. Empty if the record has no keywords field
+    fn keyword_collections(&self) -> Vec<&KWDACollection>;
+    fn keyword_collections_mut(&mut self) -> Vec<&mut KWDACollection>;
+
+    /// All keywords, across every collection
+    fn keywords(&self) -> Vec<FormId> {
+        self.keyword_collections()
+            .into_iter()
+            .flat_map(|x| x.keywords().copied())
+            .collect()
+    }
+
+    /// Adds [id] to the last collection. Returns false if a collection already has it, or if
+    /// there is no keywords field to add it to
+    fn add_keyword(&mut self, id: FormId) -> bool {
+        let mut collections = self.keyword_collections_mut();
+        if collections.iter().any(|x| x.contains(id)) {
+            return false;
+        }
+        collections.last_mut().map_or(false, |x| x.add(id))
+    }
+
+    /// Removes [id] from every collection. Returns false if none of them had it
+    fn remove_keyword(&mut self, id: FormId) -> bool {
+        let mut removed = false;
+        for collection in self.keyword_collections_mut() {
+            removed |= collection.remove(id);
+        }
+        removed
+    }
+}
+/// Implements [Keyworded] for a record whose field enum has a `KWDACollection` variant
+#[macro_export]
+macro_rules! impl_keyworded {
+    ($record:ident, $field:ident) => {
+        impl $crate::records::fields::kwda::Keyworded for $record<'_> {
+            fn keyword_collections(&self) -> Vec<&$crate::records::fields::kwda::KWDACollection> {
+                self.fields
+                    .iter()
+                    .filter_map(|x| match x {
+                        $field::KWDACollection(x) => Some(x),
+                        _ => None,
+                    })
+                    .collect()
+            }
+
+            fn keyword_collections_mut(
+                &mut self,
+            ) -> Vec<&mut $crate::records::fields::kwda::KWDACollection> {
+                self.fields
+                    .iter_mut()
+                    .filter_map(|x| match x {
+                        $field::KWDACollection(x) => Some(x),
+                        _ => None,
+                    })
+                    .collect()
+            }
+        }
+    };
+}
+
+impl<'data> CollectField<'data, KSIZ> for KWDACollection {
+    fn collect<I>(
+        ksiz: KSIZ,
+        field_iter: &mut std::iter::Peekable<I>,
+    ) -> PResult<'data, Self, FromFieldError<'data>>
+    where
+        I: std::iter::Iterator<Item = GeneralField<'data>>,
+    {
+        let (_, first) = KWDACollection::collect_run(ksiz, field_iter)?;
+        let mut runs = vec![first];
+        loop {
+            let (_, ksiz): (_, Option<KSIZ>) = get_field(field_iter, KSIZ::static_type_name())?;
+            match ksiz {
+                Some(ksiz) => runs.push(KWDACollection::collect_run(ksiz, field_iter)?.1),
+                None => break,
+            }
+        }
+        Ok((&[], KWDACollection { runs }))
+    }
+}
 impl_static_type_named!(KWDACollection, KSIZ::static_type_name());
+impl_formid_visitor!(KWDACollection, [runs: KWDA]);
 impl DataSize for KWDACollection {
     fn data_size(&self) -> usize {
-        self.create_ksiz().data_size() + self.keywords.data_size()
+        self.runs
+            .iter()
+            .map(|run| KWDACollection::create_ksiz(run).data_size() + run.data_size())
+            .sum()
     }
 }
 impl Writable for KWDACollection {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: std::io::Write,
     {
-        self.create_ksiz().write_to(w)?;
-        self.keywords.write_to(w)
+        for run in self.runs.iter() {
+            KWDACollection::create_ksiz(run).write_to(w)?;
+            run.write_to(w)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assert_size_output;
+    use bstr::ByteSlice;
+
+    #[test]
+    fn kwda_multiple_runs() {
+        let fields = vec![
+            GeneralField::new(b"KWDA".as_bstr(), &[1, 0, 0, 0, 2, 0, 0, 0]),
+            GeneralField::new(b"KSIZ".as_bstr(), &[1, 0, 0, 0]),
+            GeneralField::new(b"KWDA".as_bstr(), &[3, 0, 0, 0]),
+            GeneralField::new(b"EDID".as_bstr(), b"\0"),
+        ];
+        let mut field_iter = fields.into_iter().peekable();
+        let (_, collection) = KWDACollection::collect(KSIZ { amount: 2 }, &mut field_iter).unwrap();
+        assert_eq!(collection.runs().len(), 2);
+        assert_eq!(collection.len(), 3);
+        assert_eq!(
+            collection.keywords().map(|x| x.id).collect::<Vec<_>>(),
+            vec![1, 2, 3]
+        );
+        // The following field was left alone
+        assert_eq!(field_iter.next().unwrap().type_name(), b"EDID".as_bstr());
+
+        let data = assert_size_output!(collection);
+        assert_eq!(data.len(), (6 + 4 + 6 + 8) + (6 + 4 + 6 + 4));
     }
 }
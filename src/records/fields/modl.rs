@@ -1,8 +1,8 @@
 use crate::{
-    make_single_value_field,
+    impl_formid_visitor, make_single_value_field,
     parse::{take, PResult, Parse},
     records::common::FormId,
-    util::{DataSize, Writable},
+    util::{length_prefix, DataSize, Writable, WriteError},
 };
 use bstr::{BStr, ByteSlice};
 
@@ -40,13 +40,13 @@ impl<'data> DataSize for AlternateTexture<'data> {
             self.index_3d.data_size()
     }
 }
+impl_formid_visitor!(AlternateTexture<'_>, [texture_set]);
 impl<'data> Writable for AlternateTexture<'data> {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: std::io::Write,
     {
-        // TODO: assert that string length fits
-        (self.name_3d.len() as u32).write_to(w)?;
+        length_prefix::<u32>("3d object name", self.name_3d.len())?.write_to(w)?;
         self.name_3d.write_to(w)?;
         self.texture_set.write_to(w)?;
         self.index_3d.write_to(w)
@@ -110,6 +110,8 @@ macro_rules! make_model_fields {
                 Ok((data, Self { alternate_textures }))
             }
         }
+        $crate::impl_no_formids!($modl<'_>, $modt<'_>);
+        $crate::impl_formid_visitor!($mods<'_>, [alternate_textures]);
         impl<'data> $crate::records::common::StaticTypeNamed for $mods<'data> {
             fn static_type_name() -> &'static bstr::BStr {
                 use bstr::ByteSlice;
@@ -125,13 +127,16 @@ macro_rules! make_model_fields {
             }
         }
         impl<'data> $crate::util::Writable for $mods<'data> {
-            fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+            fn write_to<T>(&self, w: &mut T) -> Result<(), $crate::util::WriteError>
             where
                 T: std::io::Write,
             {
                 $crate::records::fields::common::write_field_header(self, w)?;
-                // TODO: assert that it fits within
-                (self.alternate_textures.len() as u32).write_to(w)?;
+                $crate::util::length_prefix::<u32>(
+                    "Alternate texture list",
+                    self.alternate_textures.len(),
+                )?
+                .write_to(w)?;
                 self.alternate_textures.write_to(w)
             }
         }
@@ -175,6 +180,7 @@ macro_rules! make_model_fields {
                 }
             }
         }
+        $crate::impl_formid_visitor!($collection<'_>, [alternate_textures: $mods<'_>]);
         // TODO: this is rather hacky, since a collection doesn't have a name :/
         impl<'data> $crate::records::common::StaticTypeNamed for $collection<'data> {
             fn static_type_name() -> &'static bstr::BStr {
@@ -187,7 +193,7 @@ macro_rules! make_model_fields {
             }
         }
         impl<'data> $crate::util::Writable for $collection<'data> {
-            fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+            fn write_to<T>(&self, w: &mut T) -> Result<(), $crate::util::WriteError>
             where
                 T: std::io::Write,
             {
@@ -1,7 +1,7 @@
 use super::common::{write_field_header, FIELDH_SIZE};
 use crate::{
-    impl_from_field, impl_static_data_size, impl_static_type_named,
-    util::{Position3, Writable},
+    impl_from_field, impl_no_formids, impl_static_data_size, impl_static_type_named,
+    util::{Position3, Writable, WriteError},
 };
 
 /// Object Bounds
@@ -19,9 +19,10 @@ impl OBND {
 }
 impl_from_field!(OBND, [p1: Position3<i16>, p2: Position3<i16>]);
 impl_static_type_named!(OBND, b"OBND");
+impl_no_formids!(OBND);
 impl_static_data_size!(OBND, FIELDH_SIZE + Position3::<i16>::static_data_size() * 2);
 impl Writable for OBND {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: std::io::Write,
     {
@@ -1,9 +1,11 @@
 use super::common::{write_field_header, FromField, FromFieldError, GeneralField, FIELDH_SIZE};
 use crate::{
-    dispatch_all, impl_static_data_size,
-    parse::{count, many, take, PResult, Parse, ParseError},
-    records::common::{ConversionError, FormId, StaticTypeNamed, Windows1252String16},
-    util::{DataSize, Writable},
+    dispatch_all, impl_formid_visitor, impl_no_formids, impl_static_data_size,
+    parse::{count, count_bounded, many, take, PResult, Parse, ParseError},
+    records::common::{
+        ConversionError, FormId, FormIdVisitor, StaticTypeNamed, Windows1252String16,
+    },
+    util::{DataSize, Writable, WriteError},
 };
 use bstr::{BStr, ByteSlice};
 use std::{convert::TryFrom, io::Write};
@@ -35,6 +37,18 @@ where
         b"VMAD".as_bstr()
     }
 }
+impl<'data, Fragment> FormIdVisitor for VMAD<'data, Fragment>
+where
+    Fragment: ParseFragments<'data> + FormIdVisitor,
+{
+    fn visit_formids(&mut self, visit: &mut dyn FnMut(&mut FormId)) {
+        self.primary.visit_formids(visit)
+    }
+
+    fn for_each_formid(&self, visit: &mut dyn FnMut(FormId)) {
+        self.primary.for_each_formid(visit)
+    }
+}
 impl<'data, Fragment> DataSize for VMAD<'data, Fragment>
 where
     Fragment: ParseFragments<'data>,
@@ -47,7 +61,7 @@ impl<'data, Fragment> Writable for VMAD<'data, Fragment>
 where
     Fragment: ParseFragments<'data>,
 {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -100,6 +114,20 @@ where
         ))
     }
 }
+impl<'data, Fragment> FormIdVisitor for VMADPrimarySection<'data, Fragment>
+where
+    Fragment: ParseFragments<'data> + FormIdVisitor,
+{
+    fn visit_formids(&mut self, visit: &mut dyn FnMut(&mut FormId)) {
+        self.scripts.visit_formids(visit);
+        self.fragments.visit_formids(visit)
+    }
+
+    fn for_each_formid(&self, visit: &mut dyn FnMut(FormId)) {
+        self.scripts.for_each_formid(visit);
+        self.fragments.for_each_formid(visit)
+    }
+}
 impl<'data, Fragment> DataSize for VMADPrimarySection<'data, Fragment>
 where
     Fragment: ParseFragments<'data>,
@@ -117,7 +145,7 @@ impl<'data, Fragment> Writable for VMADPrimarySection<'data, Fragment>
 where
     Fragment: ParseFragments<'data>,
 {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -169,7 +197,7 @@ impl TryFrom<u16> for VMADObjectFormat {
 }
 impl_static_data_size!(VMADObjectFormat, u16::static_data_size());
 impl Writable for VMADObjectFormat {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -193,7 +221,7 @@ pub struct VMADScript<'data> {
 impl<'data> VMADScript<'data> {
     pub fn parse(data: &'data [u8], object_format: VMADObjectFormat) -> PResult<Self> {
         let (data, name) = Windows1252String16::parse(data)?;
-        let (data, status) = take(data, 1usize)?;
+        let (data, status) = u8::parse(data)?;
         let (data, property_count) = u16::parse(data)?;
         let (data, properties) = count(
             data,
@@ -204,13 +232,13 @@ impl<'data> VMADScript<'data> {
             data,
             VMADScript {
                 name,
-                status: status[0],
+                status,
                 properties,
             },
         ))
     }
 
-    fn write_to<T>(&self, w: &mut T, object_format: VMADObjectFormat) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T, object_format: VMADObjectFormat) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -224,6 +252,7 @@ impl<'data> VMADScript<'data> {
         Ok(())
     }
 }
+impl_formid_visitor!(VMADScript<'_>, [properties]);
 impl<'data> DataSize for VMADScript<'data> {
     fn data_size(&self) -> usize {
         self.name.data_size() +
@@ -289,8 +318,8 @@ impl<'data> VMADPropertyData<'data> {
                 Ok((data, VMADPropertyData::Float(value)))
             }
             5 => {
-                let (data, value) = take(data, 1usize)?;
-                let value = value[0] != 0;
+                let (data, value) = u8::parse(data)?;
+                let value = value != 0;
                 Ok((data, VMADPropertyData::Boolean(value)))
             }
 
@@ -307,29 +336,31 @@ impl<'data> VMADPropertyData<'data> {
             }
             12 => {
                 let (data, amount) = u32::parse(data)?;
-                let (data, items) = count(data, Windows1252String16::parse, amount as usize)?;
+                let (data, items) =
+                    count_bounded(data, Windows1252String16::parse, amount as usize, 2)?;
                 Ok((data, VMADPropertyData::Windows1252String16Array(items)))
             }
             13 => {
                 let (data, amount) = u32::parse(data)?;
-                let (data, items) = count(data, i32::parse, amount as usize)?;
+                let (data, items) = count_bounded(data, i32::parse, amount as usize, 4)?;
                 Ok((data, VMADPropertyData::Int32Array(items)))
             }
             14 => {
                 let (data, amount) = u32::parse(data)?;
-                let (data, items) = count(data, f32::parse, amount as usize)?;
+                let (data, items) = count_bounded(data, f32::parse, amount as usize, 4)?;
                 Ok((data, VMADPropertyData::FloatArray(items)))
             }
             15 => {
                 let (data, amount) = u32::parse(data)?;
                 // TODO: I hate it
-                let (data, items) = count(
+                let (data, items) = count_bounded(
                     data,
                     |x: &[u8]| -> PResult<bool> {
                         let (data, value) = u8::parse(x)?;
                         Ok((data, value != 0))
                     },
                     amount as usize,
+                    1,
                 )?;
                 Ok((data, VMADPropertyData::BooleanArray(items)))
             }
@@ -366,7 +397,7 @@ impl<'data> VMADPropertyData<'data> {
 
     // There would be a u8 (status) between the type and the data, so we have to make it in separate steps :/
 
-    pub fn write_type_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    pub fn write_type_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -377,7 +408,7 @@ impl<'data> VMADPropertyData<'data> {
         &self,
         w: &mut T,
         object_format: VMADObjectFormat,
-    ) -> std::io::Result<()>
+    ) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -401,6 +432,23 @@ impl<'data> VMADPropertyData<'data> {
     }
 }
 // DataSize isn't entirely meaningful for VMADPropertyData
+impl FormIdVisitor for VMADPropertyData<'_> {
+    fn visit_formids(&mut self, visit: &mut dyn FnMut(&mut FormId)) {
+        match self {
+            VMADPropertyData::Object(x) => x.visit_formids(visit),
+            VMADPropertyData::ObjectArray(x) => x.visit_formids(visit),
+            _ => {}
+        }
+    }
+
+    fn for_each_formid(&self, visit: &mut dyn FnMut(FormId)) {
+        match self {
+            VMADPropertyData::Object(x) => x.for_each_formid(visit),
+            VMADPropertyData::ObjectArray(x) => x.for_each_formid(visit),
+            _ => {}
+        }
+    }
+}
 impl<'data> DataSize for VMADPropertyData<'data> {
     fn data_size(&self) -> usize {
         self.code().data_size()
@@ -467,7 +515,7 @@ impl VMADPropertyObject {
     }
 
     /// Fake Writable impl, since it needs extra info :/
-    pub fn write_to<T>(&self, w: &mut T, object_format: VMADObjectFormat) -> std::io::Result<()>
+    pub fn write_to<T>(&self, w: &mut T, object_format: VMADObjectFormat) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -485,6 +533,7 @@ impl VMADPropertyObject {
         }
     }
 }
+impl_formid_visitor!(VMADPropertyObject, [formid]);
 impl_static_data_size!(
     VMADPropertyObject,
     FormId::static_data_size() + // formid
@@ -506,10 +555,8 @@ pub struct VMADProperty<'data> {
 impl<'data> VMADProperty<'data> {
     pub fn parse(data: &'data [u8], object_format: VMADObjectFormat) -> PResult<Self> {
         let (data, name) = Windows1252String16::parse(data)?;
-        let (data, property_type) = take(data, 1usize)?;
-        let property_type = property_type[0];
-        let (data, status) = take(data, 1usize)?;
-        let status = status[0];
+        let (data, property_type) = u8::parse(data)?;
+        let (data, status) = u8::parse(data)?;
         let (data, property_data) = VMADPropertyData::parse(data, object_format, property_type)?;
         Ok((
             data,
@@ -521,7 +568,7 @@ impl<'data> VMADProperty<'data> {
         ))
     }
 
-    pub fn write_to<T>(&self, w: &mut T, object_format: VMADObjectFormat) -> std::io::Result<()>
+    pub fn write_to<T>(&self, w: &mut T, object_format: VMADObjectFormat) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -531,6 +578,7 @@ impl<'data> VMADProperty<'data> {
         self.data.write_data_to(w, object_format)
     }
 }
+impl_formid_visitor!(VMADProperty<'_>, [data]);
 impl<'data> DataSize for VMADProperty<'data> {
     fn data_size(&self) -> usize {
         self.name.data_size() + self.status.data_size() + self.data.data_size()
@@ -545,8 +593,9 @@ impl<'data> ParseFragments<'data> for NoFragments {
     }
 }
 impl_static_data_size!(NoFragments, 0);
+impl_no_formids!(NoFragments);
 impl Writable for NoFragments {
-    fn write_to<T>(&self, _w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, _w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -571,8 +620,7 @@ pub struct INFORecordFragments<'data> {
 }
 impl<'data> ParseFragments<'data> for INFORecordFragments<'data> {
     fn parse_fragments(data: &'data [u8]) -> PResult<Self> {
-        let (data, unknown) = take(data, 1usize)?;
-        let unknown = unknown[0];
+        let (data, unknown) = u8::parse(data)?;
         assert_eq!(unknown, 2);
         let (data, flags) = INFORecordFragmentsFlags::parse(data)?;
         let (data, filename) = Windows1252String16::parse(data)?;
@@ -589,6 +637,9 @@ impl<'data> ParseFragments<'data> for INFORecordFragments<'data> {
         ))
     }
 }
+// Fragments only name the scripts and functions that they run. The form ids that a script uses
+// are in the properties of [VMADPrimarySection::scripts], which are visited for every record type
+impl_no_formids!(INFORecordFragments<'_>);
 impl<'data> DataSize for INFORecordFragments<'data> {
     fn data_size(&self) -> usize {
         self.unknown.data_size()
@@ -598,7 +649,7 @@ impl<'data> DataSize for INFORecordFragments<'data> {
     }
 }
 impl<'data> Writable for INFORecordFragments<'data> {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -635,13 +686,13 @@ impl INFORecordFragmentsFlags {
 }
 impl Parse<'_> for INFORecordFragmentsFlags {
     fn parse(data: &[u8]) -> PResult<Self> {
-        let (data, flags) = take(data, 1usize)?;
-        Ok((data, Self::new(flags[0])))
+        let (data, flags) = u8::parse(data)?;
+        Ok((data, Self::new(flags)))
     }
 }
 impl_static_data_size!(INFORecordFragmentsFlags, u8::static_data_size());
 impl Writable for INFORecordFragmentsFlags {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -659,8 +710,7 @@ pub struct FragmentInfo<'data> {
 }
 impl<'data> Parse<'data> for FragmentInfo<'data> {
     fn parse(data: &'data [u8]) -> PResult<Self> {
-        let (data, unknown) = take(data, 1usize)?;
-        let unknown = unknown[0];
+        let (data, unknown) = u8::parse(data)?;
         let (data, script_name) = Windows1252String16::parse(data)?;
         let (data, fragment_name) = Windows1252String16::parse(data)?;
         Ok((
@@ -679,7 +729,7 @@ impl<'data> DataSize for FragmentInfo<'data> {
     }
 }
 impl<'data> Writable for FragmentInfo<'data> {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -703,8 +753,7 @@ pub struct PACKRecordFragments<'data> {
 }
 impl<'data> ParseFragments<'data> for PACKRecordFragments<'data> {
     fn parse_fragments(data: &'data [u8]) -> PResult<Self> {
-        let (data, unknown) = take(data, 1usize)?;
-        let unknown = unknown[0];
+        let (data, unknown) = u8::parse(data)?;
         assert_eq!(unknown, 2);
         let (data, flags) = PACKRecordFragmentsFlags::parse(data)?;
         let (data, filename) = Windows1252String16::parse(data)?;
@@ -720,6 +769,8 @@ impl<'data> ParseFragments<'data> for PACKRecordFragments<'data> {
         ))
     }
 }
+// Names only, as with [INFORecordFragments]
+impl_no_formids!(PACKRecordFragments<'_>);
 impl<'data> DataSize for PACKRecordFragments<'data> {
     fn data_size(&self) -> usize {
         self.unknown.data_size()
@@ -729,7 +780,7 @@ impl<'data> DataSize for PACKRecordFragments<'data> {
     }
 }
 impl<'data> Writable for PACKRecordFragments<'data> {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -770,13 +821,13 @@ impl PACKRecordFragmentsFlags {
 }
 impl Parse<'_> for PACKRecordFragmentsFlags {
     fn parse(data: &[u8]) -> PResult<Self> {
-        let (data, flags) = take(data, 1usize)?;
-        Ok((data, Self::new(flags[0])))
+        let (data, flags) = u8::parse(data)?;
+        Ok((data, Self::new(flags)))
     }
 }
 impl_static_data_size!(PACKRecordFragmentsFlags, u8::static_data_size());
 impl Writable for PACKRecordFragmentsFlags {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -794,8 +845,7 @@ pub struct PERKRecordFragments<'data> {
 }
 impl<'data> ParseFragments<'data> for PERKRecordFragments<'data> {
     fn parse_fragments(data: &'data [u8]) -> PResult<Self> {
-        let (data, unknown) = take(data, 1usize)?;
-        let unknown = unknown[0];
+        let (data, unknown) = u8::parse(data)?;
         let (data, filename) = Windows1252String16::parse(data)?;
         let (data, fragment_count) = u16::parse(data)?;
         let (data, fragments) =
@@ -810,13 +860,15 @@ impl<'data> ParseFragments<'data> for PERKRecordFragments<'data> {
         ))
     }
 }
+// Names only, as with [INFORecordFragments]
+impl_no_formids!(PERKRecordFragments<'_>);
 impl<'data> DataSize for PERKRecordFragments<'data> {
     fn data_size(&self) -> usize {
         self.unknown.data_size() + self.filename.data_size() + 2 + self.fragments.data_size()
     }
 }
 impl<'data> Writable for PERKRecordFragments<'data> {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -842,8 +894,7 @@ impl<'data> Parse<'data> for PERKRecordFragmentInfo<'data> {
     fn parse(data: &'data [u8]) -> PResult<Self> {
         let (data, index) = u16::parse(data)?;
         let (data, unknown) = u16::parse(data)?;
-        let (data, unknown2) = take(data, 1usize)?;
-        let unknown2 = unknown2[0];
+        let (data, unknown2) = u8::parse(data)?;
         let (data, script_name) = Windows1252String16::parse(data)?;
         let (data, fragment_name) = Windows1252String16::parse(data)?;
         Ok((
@@ -868,7 +919,7 @@ impl<'data> DataSize for PERKRecordFragmentInfo<'data> {
     }
 }
 impl<'data> Writable for PERKRecordFragmentInfo<'data> {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -891,8 +942,7 @@ pub struct QUSTRecordFragments<'data> {
 }
 impl<'data> ParseFragments<'data> for QUSTRecordFragments<'data> {
     fn parse_fragments(data: &'data [u8]) -> PResult<Self> {
-        let (data, unknown) = take(data, 1usize)?;
-        let unknown = unknown[0];
+        let (data, unknown) = u8::parse(data)?;
         assert_eq!(unknown, 2);
         let (data, fragment_count) = u16::parse(data)?;
         let (data, filename) = Windows1252String16::parse(data)?;
@@ -911,6 +961,7 @@ impl<'data> ParseFragments<'data> for QUSTRecordFragments<'data> {
         ))
     }
 }
+impl_formid_visitor!(QUSTRecordFragments<'_>, [aliases]);
 impl<'data> DataSize for QUSTRecordFragments<'data> {
     fn data_size(&self) -> usize {
         self.unknown.data_size()
@@ -922,7 +973,7 @@ impl<'data> DataSize for QUSTRecordFragments<'data> {
     }
 }
 impl<'data> Writable for QUSTRecordFragments<'data> {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -959,8 +1010,7 @@ impl<'data> Parse<'data> for QUSTRecordFragmentInfo<'data> {
         let (data, unknown) = u16::parse(data)?;
         assert_eq!(unknown, 0);
         let (data, log_entry) = i32::parse(data)?;
-        let (data, unknown2) = take(data, 1usize)?;
-        let unknown2 = unknown2[0];
+        let (data, unknown2) = u8::parse(data)?;
         let (data, script_name) = Windows1252String16::parse(data)?;
         let (data, fragment_name) = Windows1252String16::parse(data)?;
         Ok((
@@ -987,7 +1037,7 @@ impl<'data> DataSize for QUSTRecordFragmentInfo<'data> {
     }
 }
 impl<'data> Writable for QUSTRecordFragmentInfo<'data> {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -1040,13 +1090,14 @@ impl<'data> Parse<'data> for FragmentAlias<'data> {
         ))
     }
 }
+impl_formid_visitor!(FragmentAlias<'_>, [object, scripts]);
 impl<'data> DataSize for FragmentAlias<'data> {
     fn data_size(&self) -> usize {
         self.object.data_size() + self.version.data_size()
     }
 }
 impl<'data> Writable for FragmentAlias<'data> {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -1082,8 +1133,7 @@ pub struct SCENRecordFragments<'data> {
 }
 impl<'data> ParseFragments<'data> for SCENRecordFragments<'data> {
     fn parse_fragments(data: &'data [u8]) -> PResult<Self> {
-        let (data, unknown) = take(data, 1usize)?;
-        let unknown = unknown[0];
+        let (data, unknown) = u8::parse(data)?;
         assert_eq!(unknown, 2);
         let (data, flags) = SCENRecordFragmentsFlags::parse(data)?;
         let (data, filename) = Windows1252String16::parse(data)?;
@@ -1102,6 +1152,8 @@ impl<'data> ParseFragments<'data> for SCENRecordFragments<'data> {
         ))
     }
 }
+// Names only, as with [INFORecordFragments]
+impl_no_formids!(SCENRecordFragments<'_>);
 impl<'data> DataSize for SCENRecordFragments<'data> {
     fn data_size(&self) -> usize {
         self.unknown.data_size()
@@ -1113,7 +1165,7 @@ impl<'data> DataSize for SCENRecordFragments<'data> {
     }
 }
 impl<'data> Writable for SCENRecordFragments<'data> {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -1140,8 +1192,7 @@ pub struct BEFragmentInfo<'data> {
 }
 impl<'data> Parse<'data> for BEFragmentInfo<'data> {
     fn parse(data: &'data [u8]) -> PResult<Self> {
-        let (data, unknown) = take(data, 1usize)?;
-        let unknown = unknown[0];
+        let (data, unknown) = u8::parse(data)?;
         let (data, script_name) = Windows1252String16::parse(data)?;
         let (data, fragment_name) = Windows1252String16::parse(data)?;
         Ok((
@@ -1160,7 +1211,7 @@ impl<'data> DataSize for BEFragmentInfo<'data> {
     }
 }
 impl<'data> Writable for BEFragmentInfo<'data> {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -1183,11 +1234,9 @@ pub struct PhaseInfo<'data> {
 }
 impl<'data> Parse<'data> for PhaseInfo<'data> {
     fn parse(data: &'data [u8]) -> PResult<Self> {
-        let (data, unknown) = take(data, 1usize)?;
-        let unknown = unknown[0];
+        let (data, unknown) = u8::parse(data)?;
         let (data, phase) = u32::parse(data)?;
-        let (data, unknown2) = take(data, 1usize)?;
-        let unknown2 = unknown2[0];
+        let (data, unknown2) = u8::parse(data)?;
         let (data, script_name) = Windows1252String16::parse(data)?;
         let (data, fragment_name) = Windows1252String16::parse(data)?;
         Ok((
@@ -1212,7 +1261,7 @@ impl<'data> DataSize for PhaseInfo<'data> {
     }
 }
 impl<'data> Writable for PhaseInfo<'data> {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -0,0 +1,201 @@
+use super::{
+    common::{
+        optional_editor_id, CommonRecordInfo, FormId, FormIdVisitor, FromRecord, FromRecordError,
+        GeneralRecord, Index, TypeNamed,
+    },
+    fields::{common::GeneralField, edid},
+};
+use crate::{
+    collect_one,
+    context::ParseContext,
+    dispatch_all, impl_formid_visitor, impl_redecode_unknowns, impl_static_type_named,
+    make_formid_field,
+    parse::PResult,
+    util::{record_data_size, DataSize, Writable, WriteError},
+};
+use bstr::BStr;
+use derive_more::From;
+use std::io::Write;
+
+/// Form list, an ordered list of forms that scripts and conditions can check membership of
+#[derive(Debug, Clone, PartialEq)]
+pub struct FLSTRecord<'data> {
+    pub common: CommonRecordInfo,
+    pub editor_id_index: Option<Index>,
+    pub fields: Vec<FLSTField<'data>>,
+}
+impl<'data> FLSTRecord<'data> {
+    /// The forms in the list, in order
+    pub fn entries(&self) -> impl Iterator<Item = FormId> + '_ {
+        self.fields.iter().filter_map(|x| match x {
+            FLSTField::LNAM(x) => Some(x.formid),
+            _ => None,
+        })
+    }
+
+    pub fn contains(&self, id: FormId) -> bool {
+        self.entries().any(|x| x == id)
+    }
+
+    /// Adds [id] to the end of the list. Returns false if it was already in it
+    pub fn add_entry(&mut self, id: FormId) -> bool {
+        if self.contains(id) {
+            false
+        } else {
+            // The entries are the last fields
+            self.fields.push(LNAM::new(id).into());
+            true
+        }
+    }
+
+    /// Removes every occurrence of [id]. Returns false if it was not in the list
+    pub fn remove_entry(&mut self, id: FormId) -> bool {
+        let len = self.fields.len();
+        self.fields.retain(|x| match x {
+            FLSTField::LNAM(x) => x.formid != id,
+            _ => true,
+        });
+        self.fields.len() != len
+    }
+}
+impl<'data> FromRecord<'data> for FLSTRecord<'data> {
+    fn from_record(
+        record: GeneralRecord<'data>,
+        ctx: &mut ParseContext,
+    ) -> PResult<'data, Self, FromRecordError<'data>> {
+        let mut editor_id_index = None;
+
+        let mut fields = Vec::new();
+        for field in record.fields {
+            match field.type_name().as_ref() {
+                b"EDID" => collect_one!(edid::EDID, field => fields; editor_id_index, ctx),
+                // Can be repeated
+                b"LNAM" => fields.push(field.decode_as::<LNAM>()?.into()),
+                _ => fields.push(field.into()),
+            }
+        }
+
+        let editor_id_index = optional_editor_id(editor_id_index, ctx)?;
+
+        Ok((
+            &[],
+            Self {
+                common: record.common,
+                editor_id_index,
+                fields,
+            },
+        ))
+    }
+}
+impl_static_type_named!(FLSTRecord<'_>, b"FLST");
+impl_redecode_unknowns!(FLSTRecord, FLSTField);
+impl_formid_visitor!(FLSTRecord<'_>, [fields]);
+impl DataSize for FLSTRecord<'_> {
+    fn data_size(&self) -> usize {
+        self.type_name().data_size() +
+            4 + // data size len
+            self.common.data_size() +
+            self.fields.data_size()
+    }
+}
+impl Writable for FLSTRecord<'_> {
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
+    where
+        T: Write,
+    {
+        self.type_name().write_to(w)?;
+        record_data_size(self.type_name(), self.fields.data_size())?.write_to(w)?;
+        self.common.write_to(w)?;
+        self.fields.write_to(w)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, From)]
+pub enum FLSTField<'data> {
+    EDID(edid::EDID<'data>),
+    LNAM(LNAM),
+    Unknown(GeneralField<'data>),
+}
+impl<'data> TypeNamed<'data> for FLSTField<'data> {
+    fn type_name(&self) -> &'data BStr {
+        dispatch_all!(FLSTField, self, [EDID, LNAM, Unknown], x, { x.type_name() })
+    }
+}
+impl FormIdVisitor for FLSTField<'_> {
+    fn visit_formids(&mut self, visit: &mut dyn FnMut(&mut FormId)) {
+        dispatch_all!(FLSTField, self, [EDID, LNAM, Unknown], x, {
+            x.visit_formids(visit)
+        })
+    }
+
+    fn for_each_formid(&self, visit: &mut dyn FnMut(FormId)) {
+        dispatch_all!(FLSTField, self, [EDID, LNAM, Unknown], x, {
+            x.for_each_formid(visit)
+        })
+    }
+
+    fn for_each_field_formid<'name>(
+        &self,
+        name: &'name BStr,
+        visit: &mut dyn FnMut(&'name BStr, FormId),
+    ) {
+        dispatch_all!(FLSTField, self, [EDID, LNAM, Unknown], x, {
+            x.for_each_field_formid(name, visit)
+        })
+    }
+}
+impl DataSize for FLSTField<'_> {
+    fn data_size(&self) -> usize {
+        dispatch_all!(FLSTField, self, [EDID, LNAM, Unknown], x, { x.data_size() })
+    }
+}
+impl Writable for FLSTField<'_> {
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
+    where
+        T: Write,
+    {
+        dispatch_all!(FLSTField, self, [EDID, LNAM, Unknown], x, { x.write_to(w) })
+    }
+}
+
+make_formid_field!(
+    /// An entry of the list
+    LNAM
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assert_size_output;
+    use bstr::ByteSlice;
+
+    #[test]
+    fn test_flst() {
+        let record = GeneralRecord {
+            type_name: b"FLST".as_bstr(),
+            common: CommonRecordInfo::test_default(),
+            fields: vec![
+                GeneralField::new(b"EDID".as_bstr(), b"Ores\0"),
+                GeneralField::new(b"LNAM".as_bstr(), &[0x01, 0x02, 0, 0]),
+                GeneralField::new(b"LNAM".as_bstr(), &[0x02, 0x02, 0, 0]),
+            ],
+        };
+        let (_, mut flst) = FLSTRecord::from_record(record, &mut ParseContext::default()).unwrap();
+        assert_eq!(flst.editor_id_index, Some(0));
+        assert_eq!(
+            flst.entries().collect::<Vec<_>>(),
+            vec![FormId::new(0x201), FormId::new(0x202)]
+        );
+
+        assert!(!flst.add_entry(FormId::new(0x201)));
+        assert!(flst.add_entry(FormId::new(0x203)));
+        assert!(flst.remove_entry(FormId::new(0x201)));
+        assert!(!flst.remove_entry(FormId::new(0x201)));
+        assert_eq!(
+            flst.entries().collect::<Vec<_>>(),
+            vec![FormId::new(0x202), FormId::new(0x203)]
+        );
+        let data = assert_size_output!(flst);
+        assert_eq!(data.len(), flst.data_size());
+    }
+}
@@ -1,10 +1,20 @@
 use crate::{
+    context::ParseContext,
     dispatch_all,
-    util::{DataSize, Writable},
+    parse::Parse,
+    util::{DataSize, Writable, WriteError},
 };
 use bstr::BStr;
-use common::TypeNamed;
+use common::{
+    record_flag, CommonRecordInfo, FormId, FormIdVisitor, FromRecord, FromRecordError,
+    GeneralRecord, NullTerminatedString, TypeNamed,
+};
 use derive_more::From;
+use fields::{
+    common::item::{Enchantable, Item},
+    edid,
+    kwda::Keyworded,
+};
 
 pub mod aact;
 pub mod achr;
@@ -21,6 +31,9 @@ pub mod aspc;
 pub mod astp;
 pub mod avif;
 pub mod book;
+pub mod canonical;
+pub mod category;
+pub mod flst;
 
 pub mod tes4;
 
@@ -34,7 +47,8 @@ pub enum Record<'data> {
     AACT(aact::AACTRecord<'data>),
     ACTI(acti::ACTIRecord<'data>),
     ADDN(addn::ADDNRecord<'data>),
-    ACHR(achr::ACHRRecord<'data>),
+    /// Boxed, as it is far larger than any other record
+    ACHR(Box<achr::ACHRRecord<'data>>),
     ALCH(alch::ALCHRecord<'data>),
     AMMO(ammo::AMMORecord<'data>),
     ANIO(anio::ANIORecord<'data>),
@@ -46,7 +60,134 @@ pub enum Record<'data> {
     ASTP(astp::ASTPRecord<'data>),
     AVIF(avif::AVIFRecord<'data>),
     BOOK(book::BOOKRecord<'data>),
+    FLST(flst::FLSTRecord<'data>),
     Unknown(common::GeneralRecord<'data>),
+    /// A record flagged as deleted or ignored. These often have truncated or empty bodies,
+    /// so they are not converted into their typed form. See [Record::undelete]
+    #[from(ignore)]
+    Deleted(common::GeneralRecord<'data>),
+}
+impl<'data> Record<'data> {
+    /// Converts [record] into its typed form, if we know of one.
+    /// Deleted and ignored records are kept as [Record::Deleted].
+    pub fn from_general(
+        record: GeneralRecord<'data>,
+        ctx: &mut ParseContext,
+    ) -> Result<Record<'data>, FromRecordError<'data>> {
+        if record
+            .common
+            .flags
+            .is(record_flag::DELETED | record_flag::IGNORED)
+        {
+            return Ok(Record::Deleted(record));
+        }
+
+        Ok(match record.type_name.as_ref() {
+            b"TES4" => tes4::TES4Record::from_record(record, ctx)?.1.into(),
+            b"AACT" => aact::AACTRecord::from_record(record, ctx)?.1.into(),
+            b"ADDN" => addn::ADDNRecord::from_record(record, ctx)?.1.into(),
+            b"ACHR" => Record::ACHR(Box::new(achr::ACHRRecord::from_record(record, ctx)?.1)),
+            b"ACTI" => acti::ACTIRecord::from_record(record, ctx)?.1.into(),
+            b"ALCH" => alch::ALCHRecord::from_record(record, ctx)?.1.into(),
+            b"AMMO" => ammo::AMMORecord::from_record(record, ctx)?.1.into(),
+            b"ANIO" => anio::ANIORecord::from_record(record, ctx)?.1.into(),
+            b"APPA" => appa::APPARecord::from_record(record, ctx)?.1.into(),
+            b"ARMA" => arma::ARMARecord::from_record(record, ctx)?.1.into(),
+            b"ARMO" => armo::ARMORecord::from_record(record, ctx)?.1.into(),
+            b"ARTO" => arto::ARTORecord::from_record(record, ctx)?.1.into(),
+            b"ASPC" => aspc::ASPCRecord::from_record(record, ctx)?.1.into(),
+            b"ASTP" => astp::ASTPRecord::from_record(record, ctx)?.1.into(),
+            b"AVIF" => avif::AVIFRecord::from_record(record, ctx)?.1.into(),
+            b"BOOK" => book::BOOKRecord::from_record(record, ctx)?.1.into(),
+            b"FLST" => flst::FLSTRecord::from_record(record, ctx)?.1.into(),
+            _ => record.into(),
+        })
+    }
+
+    /// Clears the deleted and ignored flags of a [Record::Deleted], converting it into its typed
+    /// form. Other records are returned as they are.
+    pub fn undelete(self, ctx: &mut ParseContext) -> Result<Record<'data>, FromRecordError<'data>> {
+        match self {
+            Record::Deleted(mut record) => {
+                record.common.flags.flags &= !(record_flag::DELETED | record_flag::IGNORED);
+                ctx.enter_record(&record);
+                Record::from_general(record, ctx)
+            }
+            record => Ok(record),
+        }
+    }
+
+    /// Demotes the record back to its raw fields, so that fields which aren't modelled well can
+    /// be changed byte by byte. Typed records are written into [buffer] and read back, so their
+    /// fields borrow from it, while Unknown and Deleted records are returned as they are.
+    /// [Record::from_general] converts it back
+    pub fn into_general<'b>(self, buffer: &'b mut Vec<u8>) -> Result<GeneralRecord<'b>, WriteError>
+    where
+        'data: 'b,
+    {
+        match self {
+            Record::Unknown(record) | Record::Deleted(record) => Ok(record),
+            record => {
+                buffer.clear();
+                record.write_to(buffer)?;
+                let (_, general) = GeneralRecord::parse(buffer)
+                    .expect("Failed to read back a record that was just written");
+                Ok(general)
+            }
+        }
+    }
+
+    pub fn as_record_mut(&mut self) -> RecordMut<'_, 'data> {
+        match self {
+            Record::TES4(x) => RecordMut::TES4(x),
+            Record::AACT(x) => RecordMut::AACT(x),
+            Record::ACTI(x) => RecordMut::ACTI(x),
+            Record::ADDN(x) => RecordMut::ADDN(x),
+            Record::ACHR(x) => RecordMut::ACHR(x),
+            Record::ALCH(x) => RecordMut::ALCH(x),
+            Record::AMMO(x) => RecordMut::AMMO(x),
+            Record::ANIO(x) => RecordMut::ANIO(x),
+            Record::APPA(x) => RecordMut::APPA(x),
+            Record::ARMA(x) => RecordMut::ARMA(x),
+            Record::ARMO(x) => RecordMut::ARMO(x),
+            Record::ARTO(x) => RecordMut::ARTO(x),
+            Record::ASPC(x) => RecordMut::ASPC(x),
+            Record::ASTP(x) => RecordMut::ASTP(x),
+            Record::AVIF(x) => RecordMut::AVIF(x),
+            Record::BOOK(x) => RecordMut::BOOK(x),
+            Record::FLST(x) => RecordMut::FLST(x),
+            Record::Unknown(x) => RecordMut::Unknown(x),
+            Record::Deleted(x) => RecordMut::Deleted(x),
+        }
+    }
+
+    pub fn as_record_ref(&self) -> RecordRef<'_, 'data> {
+        match self {
+            Record::TES4(x) => RecordRef::TES4(x),
+            Record::AACT(x) => RecordRef::AACT(x),
+            Record::ACTI(x) => RecordRef::ACTI(x),
+            Record::ADDN(x) => RecordRef::ADDN(x),
+            Record::ACHR(x) => RecordRef::ACHR(x),
+            Record::ALCH(x) => RecordRef::ALCH(x),
+            Record::AMMO(x) => RecordRef::AMMO(x),
+            Record::ANIO(x) => RecordRef::ANIO(x),
+            Record::APPA(x) => RecordRef::APPA(x),
+            Record::ARMA(x) => RecordRef::ARMA(x),
+            Record::ARMO(x) => RecordRef::ARMO(x),
+            Record::ARTO(x) => RecordRef::ARTO(x),
+            Record::ASPC(x) => RecordRef::ASPC(x),
+            Record::ASTP(x) => RecordRef::ASTP(x),
+            Record::AVIF(x) => RecordRef::AVIF(x),
+            Record::BOOK(x) => RecordRef::BOOK(x),
+            Record::FLST(x) => RecordRef::FLST(x),
+            Record::Unknown(x) => RecordRef::Unknown(x),
+            Record::Deleted(x) => RecordRef::Deleted(x),
+        }
+    }
+
+    pub fn common(&self) -> &CommonRecordInfo {
+        self.as_record_ref().common()
+    }
 }
 impl<'data> TypeNamed<'data> for Record<'data> {
     fn type_name(&self) -> &'data BStr {
@@ -55,13 +196,41 @@ impl<'data> TypeNamed<'data> for Record<'data> {
             self,
             [
                 TES4, AACT, ACTI, ADDN, ACHR, ALCH, AMMO, ANIO, APPA, ARMA, ARMO, ARTO, ASPC, ASTP,
-                AVIF, BOOK, Unknown
+                AVIF, BOOK, FLST, Unknown, Deleted
             ],
             x,
             { x.type_name() }
         )
     }
 }
+impl<'data> FormIdVisitor for Record<'data> {
+    /// Unknown and Deleted records are not visited, as what their fields hold isn't known
+    fn visit_formids(&mut self, visit: &mut dyn FnMut(&mut FormId)) {
+        dispatch_all!(
+            Record,
+            self,
+            [
+                TES4, AACT, ACTI, ADDN, ACHR, ALCH, AMMO, ANIO, APPA, ARMA, ARMO, ARTO, ASPC, ASTP,
+                AVIF, BOOK, FLST, Unknown, Deleted
+            ],
+            x,
+            { x.visit_formids(visit) }
+        )
+    }
+
+    fn for_each_formid(&self, visit: &mut dyn FnMut(FormId)) {
+        dispatch_all!(
+            Record,
+            self,
+            [
+                TES4, AACT, ACTI, ADDN, ACHR, ALCH, AMMO, ANIO, APPA, ARMA, ARMO, ARTO, ASPC, ASTP,
+                AVIF, BOOK, FLST, Unknown, Deleted
+            ],
+            x,
+            { x.for_each_formid(visit) }
+        )
+    }
+}
 impl<'data> DataSize for Record<'data> {
     fn data_size(&self) -> usize {
         dispatch_all!(
@@ -69,7 +238,7 @@ impl<'data> DataSize for Record<'data> {
             self,
             [
                 TES4, AACT, ACTI, ADDN, ACHR, ALCH, AMMO, ANIO, APPA, ARMA, ARMO, ARTO, ASPC, ASTP,
-                AVIF, BOOK, Unknown
+                AVIF, BOOK, FLST, Unknown, Deleted
             ],
             x,
             { x.data_size() }
@@ -77,7 +246,7 @@ impl<'data> DataSize for Record<'data> {
     }
 }
 impl<'data> Writable for Record<'data> {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: std::io::Write,
     {
@@ -86,10 +255,500 @@ impl<'data> Writable for Record<'data> {
             self,
             [
                 TES4, AACT, ACTI, ADDN, ACHR, ALCH, AMMO, ANIO, APPA, ARMA, ARMO, ARTO, ASPC, ASTP,
-                AVIF, BOOK, Unknown
+                AVIF, BOOK, FLST, Unknown, Deleted
+            ],
+            x,
+            { x.write_to(w) }
+        )
+    }
+}
+
+/// Borrowed version of [Record], so that records held within groups can be treated the same
+/// as top-level records
+#[derive(Debug, Copy, Clone, PartialEq, From)]
+pub enum RecordRef<'a, 'data> {
+    TES4(&'a tes4::TES4Record<'data>),
+    AACT(&'a aact::AACTRecord<'data>),
+    ACTI(&'a acti::ACTIRecord<'data>),
+    ADDN(&'a addn::ADDNRecord<'data>),
+    ACHR(&'a achr::ACHRRecord<'data>),
+    ALCH(&'a alch::ALCHRecord<'data>),
+    AMMO(&'a ammo::AMMORecord<'data>),
+    ANIO(&'a anio::ANIORecord<'data>),
+    APPA(&'a appa::APPARecord<'data>),
+    ARMA(&'a arma::ARMARecord<'data>),
+    ARMO(&'a armo::ARMORecord<'data>),
+    ARTO(&'a arto::ARTORecord<'data>),
+    ASPC(&'a aspc::ASPCRecord<'data>),
+    ASTP(&'a astp::ASTPRecord<'data>),
+    AVIF(&'a avif::AVIFRecord<'data>),
+    BOOK(&'a book::BOOKRecord<'data>),
+    FLST(&'a flst::FLSTRecord<'data>),
+    Unknown(&'a common::GeneralRecord<'data>),
+    #[from(ignore)]
+    Deleted(&'a common::GeneralRecord<'data>),
+}
+impl<'a, 'data> RecordRef<'a, 'data> {
+    pub fn common(self) -> &'a CommonRecordInfo {
+        dispatch_all!(
+            RecordRef,
+            self,
+            [
+                TES4, AACT, ACTI, ADDN, ACHR, ALCH, AMMO, ANIO, APPA, ARMA, ARMO, ARTO, ASPC, ASTP,
+                AVIF, BOOK, FLST, Unknown, Deleted
+            ],
+            x,
+            { &x.common }
+        )
+    }
+
+    pub fn formid(self) -> FormId {
+        FormId::new(self.common().id)
+    }
+
+    /// The record as an [Item], if it is a type of item
+    pub fn as_item(self) -> Option<&'a dyn Item> {
+        match self {
+            RecordRef::ALCH(x) => Some(x),
+            RecordRef::AMMO(x) => Some(x),
+            RecordRef::APPA(x) => Some(x),
+            RecordRef::ARMO(x) => Some(x),
+            RecordRef::BOOK(x) => Some(x),
+            _ => None,
+        }
+    }
+
+    /// The record as an [Enchantable], if it is a type of item that can be enchanted
+    pub fn as_enchantable(self) -> Option<&'a dyn Enchantable> {
+        match self {
+            RecordRef::ARMO(x) => Some(x),
+            _ => None,
+        }
+    }
+
+    /// The record as a [Keyworded], if its type can have keywords
+    pub fn as_keyworded(self) -> Option<&'a dyn Keyworded> {
+        match self {
+            RecordRef::ACTI(x) => Some(x),
+            RecordRef::ALCH(x) => Some(x),
+            RecordRef::AMMO(x) => Some(x),
+            RecordRef::ARMO(x) => Some(x),
+            RecordRef::BOOK(x) => Some(x),
+            _ => None,
+        }
+    }
+
+    /// Writes the record into [buffer] and reads it back as general fields, so that any type of
+    /// record can be looked at field by field
+    pub fn to_general<'b>(self, buffer: &'b mut Vec<u8>) -> Option<GeneralRecord<'b>> {
+        buffer.clear();
+        self.write_to(buffer).ok()?;
+        GeneralRecord::parse(buffer).ok().map(|(_, record)| record)
+    }
+
+    /// A copy of the record
+    pub fn to_record(self) -> Record<'data> {
+        match self {
+            RecordRef::TES4(x) => Record::TES4(x.clone()),
+            RecordRef::AACT(x) => Record::AACT(x.clone()),
+            RecordRef::ACTI(x) => Record::ACTI(x.clone()),
+            RecordRef::ADDN(x) => Record::ADDN(x.clone()),
+            RecordRef::ACHR(x) => Record::ACHR(Box::new(x.clone())),
+            RecordRef::ALCH(x) => Record::ALCH(x.clone()),
+            RecordRef::AMMO(x) => Record::AMMO(x.clone()),
+            RecordRef::ANIO(x) => Record::ANIO(x.clone()),
+            RecordRef::APPA(x) => Record::APPA(x.clone()),
+            RecordRef::ARMA(x) => Record::ARMA(x.clone()),
+            RecordRef::ARMO(x) => Record::ARMO(x.clone()),
+            RecordRef::ARTO(x) => Record::ARTO(x.clone()),
+            RecordRef::ASPC(x) => Record::ASPC(x.clone()),
+            RecordRef::ASTP(x) => Record::ASTP(x.clone()),
+            RecordRef::AVIF(x) => Record::AVIF(x.clone()),
+            RecordRef::BOOK(x) => Record::BOOK(x.clone()),
+            RecordRef::FLST(x) => Record::FLST(x.clone()),
+            RecordRef::Unknown(x) => Record::Unknown(x.clone()),
+            RecordRef::Deleted(x) => Record::Deleted(x.clone()),
+        }
+    }
+
+    /// The form ids that the record refers to, see [FormIdVisitor]
+    pub fn formids(self) -> Vec<FormId> {
+        dispatch_all!(
+            RecordRef,
+            self,
+            [
+                TES4, AACT, ACTI, ADDN, ACHR, ALCH, AMMO, ANIO, APPA, ARMA, ARMO, ARTO, ASPC, ASTP,
+                AVIF, BOOK, FLST, Unknown, Deleted
+            ],
+            x,
+            { x.formids() }
+        )
+    }
+
+    /// The form ids that the record refers to, each along with the name of the field holding it.
+    /// Unknown and Deleted records have none, as with [FormIdVisitor]
+    pub fn field_formids(self) -> Vec<(&'data BStr, FormId)> {
+        fn of_fields<'data, F>(fields: &[F]) -> Vec<(&'data BStr, FormId)>
+        where
+            F: TypeNamed<'data> + FormIdVisitor,
+        {
+            let mut formids = Vec::new();
+            for field in fields {
+                field.for_each_field_formid(field.type_name(), &mut |name, id| {
+                    formids.push((name, id))
+                });
+            }
+            formids
+        }
+
+        match self {
+            RecordRef::TES4(x) => of_fields(&x.fields),
+            RecordRef::AACT(x) => of_fields(&x.fields),
+            RecordRef::ACTI(x) => of_fields(&x.fields),
+            RecordRef::ADDN(x) => of_fields(&x.fields),
+            RecordRef::ACHR(x) => of_fields(x.fields()),
+            RecordRef::ALCH(x) => of_fields(&x.fields),
+            RecordRef::AMMO(x) => of_fields(&x.fields),
+            RecordRef::ANIO(x) => of_fields(&x.fields),
+            RecordRef::APPA(x) => of_fields(&x.fields),
+            RecordRef::ARMA(x) => of_fields(&x.fields),
+            RecordRef::ARMO(x) => of_fields(&x.fields),
+            RecordRef::ARTO(x) => of_fields(&x.fields),
+            RecordRef::ASPC(x) => of_fields(&x.fields),
+            RecordRef::ASTP(x) => of_fields(&x.fields),
+            RecordRef::AVIF(x) => of_fields(&x.fields),
+            RecordRef::BOOK(x) => of_fields(&x.fields),
+            RecordRef::FLST(x) => of_fields(&x.fields),
+            RecordRef::Unknown(_) | RecordRef::Deleted(_) => Vec::new(),
+        }
+    }
+
+    /// The editor id (EDID) of the record, if it has one
+    pub fn editor_id(self) -> Option<String> {
+        let mut buffer = Vec::new();
+        let record = self.to_general(&mut buffer)?;
+        let field = record.fields.iter().find(|x| x.type_name == "EDID")?;
+        let edid = field.decode_as::<edid::EDID>().ok()?;
+        Some(edid.id.value.to_string())
+    }
+}
+impl<'a, 'data> TypeNamed<'data> for RecordRef<'a, 'data> {
+    fn type_name(&self) -> &'data BStr {
+        dispatch_all!(
+            RecordRef,
+            self,
+            [
+                TES4, AACT, ACTI, ADDN, ACHR, ALCH, AMMO, ANIO, APPA, ARMA, ARMO, ARTO, ASPC, ASTP,
+                AVIF, BOOK, FLST, Unknown, Deleted
+            ],
+            x,
+            { x.type_name() }
+        )
+    }
+}
+impl DataSize for RecordRef<'_, '_> {
+    fn data_size(&self) -> usize {
+        dispatch_all!(
+            RecordRef,
+            self,
+            [
+                TES4, AACT, ACTI, ADDN, ACHR, ALCH, AMMO, ANIO, APPA, ARMA, ARMO, ARTO, ASPC, ASTP,
+                AVIF, BOOK, FLST, Unknown, Deleted
+            ],
+            x,
+            { x.data_size() }
+        )
+    }
+}
+impl Writable for RecordRef<'_, '_> {
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
+    where
+        T: std::io::Write,
+    {
+        dispatch_all!(
+            RecordRef,
+            self,
+            [
+                TES4, AACT, ACTI, ADDN, ACHR, ALCH, AMMO, ANIO, APPA, ARMA, ARMO, ARTO, ASPC, ASTP,
+                AVIF, BOOK, FLST, Unknown, Deleted
             ],
             x,
             { x.write_to(w) }
         )
     }
 }
+
+/// Mutable version of [RecordRef]
+#[derive(Debug, PartialEq, From)]
+pub enum RecordMut<'a, 'data> {
+    TES4(&'a mut tes4::TES4Record<'data>),
+    AACT(&'a mut aact::AACTRecord<'data>),
+    ACTI(&'a mut acti::ACTIRecord<'data>),
+    ADDN(&'a mut addn::ADDNRecord<'data>),
+    ACHR(&'a mut achr::ACHRRecord<'data>),
+    ALCH(&'a mut alch::ALCHRecord<'data>),
+    AMMO(&'a mut ammo::AMMORecord<'data>),
+    ANIO(&'a mut anio::ANIORecord<'data>),
+    APPA(&'a mut appa::APPARecord<'data>),
+    ARMA(&'a mut arma::ARMARecord<'data>),
+    ARMO(&'a mut armo::ARMORecord<'data>),
+    ARTO(&'a mut arto::ARTORecord<'data>),
+    ASPC(&'a mut aspc::ASPCRecord<'data>),
+    ASTP(&'a mut astp::ASTPRecord<'data>),
+    AVIF(&'a mut avif::AVIFRecord<'data>),
+    BOOK(&'a mut book::BOOKRecord<'data>),
+    FLST(&'a mut flst::FLSTRecord<'data>),
+    Unknown(&'a mut common::GeneralRecord<'data>),
+    #[from(ignore)]
+    Deleted(&'a mut common::GeneralRecord<'data>),
+}
+impl<'a, 'data> From<&'a mut Record<'data>> for RecordMut<'a, 'data> {
+    fn from(record: &'a mut Record<'data>) -> RecordMut<'a, 'data> {
+        record.as_record_mut()
+    }
+}
+impl<'a, 'data> TypeNamed<'data> for RecordMut<'a, 'data> {
+    fn type_name(&self) -> &'data BStr {
+        dispatch_all!(
+            RecordMut,
+            self,
+            [
+                TES4, AACT, ACTI, ADDN, ACHR, ALCH, AMMO, ANIO, APPA, ARMA, ARMO, ARTO, ASPC, ASTP,
+                AVIF, BOOK, FLST, Unknown, Deleted
+            ],
+            x,
+            { x.type_name() }
+        )
+    }
+}
+impl FormIdVisitor for RecordMut<'_, '_> {
+    /// Unknown and Deleted records are not visited, as what their fields hold isn't known
+    fn visit_formids(&mut self, visit: &mut dyn FnMut(&mut FormId)) {
+        dispatch_all!(
+            RecordMut,
+            self,
+            [
+                TES4, AACT, ACTI, ADDN, ACHR, ALCH, AMMO, ANIO, APPA, ARMA, ARMO, ARTO, ASPC, ASTP,
+                AVIF, BOOK, FLST, Unknown, Deleted
+            ],
+            x,
+            { x.visit_formids(visit) }
+        )
+    }
+
+    fn for_each_formid(&self, visit: &mut dyn FnMut(FormId)) {
+        dispatch_all!(
+            RecordMut,
+            self,
+            [
+                TES4, AACT, ACTI, ADDN, ACHR, ALCH, AMMO, ANIO, APPA, ARMA, ARMO, ARTO, ASPC, ASTP,
+                AVIF, BOOK, FLST, Unknown, Deleted
+            ],
+            x,
+            { x.for_each_formid(visit) }
+        )
+    }
+}
+impl<'a, 'data> RecordMut<'a, 'data> {
+    pub fn common(&self) -> &CommonRecordInfo {
+        dispatch_all!(
+            RecordMut,
+            self,
+            [
+                TES4, AACT, ACTI, ADDN, ACHR, ALCH, AMMO, ANIO, APPA, ARMA, ARMO, ARTO, ASPC, ASTP,
+                AVIF, BOOK, FLST, Unknown, Deleted
+            ],
+            x,
+            { &x.common }
+        )
+    }
+
+    pub fn common_mut(&mut self) -> &mut CommonRecordInfo {
+        dispatch_all!(
+            RecordMut,
+            self,
+            [
+                TES4, AACT, ACTI, ADDN, ACHR, ALCH, AMMO, ANIO, APPA, ARMA, ARMO, ARTO, ASPC, ASTP,
+                AVIF, BOOK, FLST, Unknown, Deleted
+            ],
+            x,
+            { &mut x.common }
+        )
+    }
+
+    pub fn formid(&self) -> FormId {
+        FormId::new(self.common().id)
+    }
+
+    /// The editor id (EDID) of the record, to be changed. None if it has none, or if the record
+    /// wasn't converted, as its fields can't be changed
+    pub fn editor_id_mut(&mut self) -> Option<&mut NullTerminatedString<'data>> {
+        match self {
+            RecordMut::AACT(x) => x.fields.iter_mut().find_map(|field| match field {
+                aact::AACTField::EDID(edid) => Some(&mut edid.id),
+                _ => None,
+            }),
+            RecordMut::ACTI(x) => x.fields.iter_mut().find_map(|field| match field {
+                acti::ACTIField::EDID(edid) => Some(&mut edid.id),
+                _ => None,
+            }),
+            RecordMut::ADDN(x) => x.fields.iter_mut().find_map(|field| match field {
+                addn::ADDNField::EDID(edid) => Some(&mut edid.id),
+                _ => None,
+            }),
+            RecordMut::ACHR(x) => x.editor_id_mut().map(|edid| &mut edid.id),
+            RecordMut::ALCH(x) => x.fields.iter_mut().find_map(|field| match field {
+                alch::ALCHField::EDID(edid) => Some(&mut edid.id),
+                _ => None,
+            }),
+            RecordMut::AMMO(x) => x.fields.iter_mut().find_map(|field| match field {
+                ammo::AMMOField::EDID(edid) => Some(&mut edid.id),
+                _ => None,
+            }),
+            RecordMut::ANIO(x) => x.fields.iter_mut().find_map(|field| match field {
+                anio::ANIOField::EDID(edid) => Some(&mut edid.id),
+                _ => None,
+            }),
+            RecordMut::APPA(x) => x.fields.iter_mut().find_map(|field| match field {
+                appa::APPAField::EDID(edid) => Some(&mut edid.id),
+                _ => None,
+            }),
+            RecordMut::ARMA(x) => x.fields.iter_mut().find_map(|field| match field {
+                arma::ARMAField::EDID(edid) => Some(&mut edid.id),
+                _ => None,
+            }),
+            RecordMut::ARMO(x) => x.fields.iter_mut().find_map(|field| match field {
+                armo::ARMOField::EDID(edid) => Some(&mut edid.id),
+                _ => None,
+            }),
+            RecordMut::ARTO(x) => x.fields.iter_mut().find_map(|field| match field {
+                arto::ARTOField::EDID(edid) => Some(&mut edid.id),
+                _ => None,
+            }),
+            RecordMut::ASPC(x) => x.fields.iter_mut().find_map(|field| match field {
+                aspc::ASPCField::EDID(edid) => Some(&mut edid.id),
+                _ => None,
+            }),
+            RecordMut::ASTP(x) => x.fields.iter_mut().find_map(|field| match field {
+                astp::ASTPField::EDID(edid) => Some(&mut edid.id),
+                _ => None,
+            }),
+            RecordMut::AVIF(x) => x.fields.iter_mut().find_map(|field| match field {
+                avif::AVIFField::EDID(edid) => Some(&mut edid.id),
+                _ => None,
+            }),
+            RecordMut::BOOK(x) => x.fields.iter_mut().find_map(|field| match field {
+                book::BOOKField::EDID(edid) => Some(&mut edid.id),
+                _ => None,
+            }),
+            RecordMut::FLST(x) => x.fields.iter_mut().find_map(|field| match field {
+                flst::FLSTField::EDID(edid) => Some(&mut edid.id),
+                _ => None,
+            }),
+            RecordMut::TES4(_) | RecordMut::Unknown(_) | RecordMut::Deleted(_) => None,
+        }
+    }
+
+    /// The record as a [Keyworded], if its type can have keywords
+    pub fn into_keyworded(self) -> Option<&'a mut dyn Keyworded> {
+        match self {
+            RecordMut::ACTI(x) => Some(x),
+            RecordMut::ALCH(x) => Some(x),
+            RecordMut::AMMO(x) => Some(x),
+            RecordMut::ARMO(x) => Some(x),
+            RecordMut::BOOK(x) => Some(x),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::records::fields::common::GeneralField;
+    use bstr::ByteSlice;
+
+    #[test]
+    fn test_into_general() {
+        let general = GeneralRecord {
+            type_name: b"FLST".as_bstr(),
+            common: CommonRecordInfo::test_default(),
+            fields: vec![
+                GeneralField::new(b"EDID".as_bstr(), b"Ores\0"),
+                GeneralField::new(b"LNAM".as_bstr(), &[0x01, 0x02, 0, 0]),
+                GeneralField::new(b"XXXX".as_bstr(), &[1, 2, 3]),
+            ],
+        };
+        let mut ctx = ParseContext::default();
+        let record = Record::from_general(general.clone(), &mut ctx).unwrap();
+        assert!(matches!(record, Record::FLST(_)));
+
+        let mut buffer = Vec::new();
+        let mut demoted = record.clone().into_general(&mut buffer).unwrap();
+        assert_eq!(demoted, general);
+        let patched = [0x05, 0x02, 0, 0];
+        demoted.fields[1].data = &patched;
+        let promoted = Record::from_general(demoted, &mut ctx).unwrap();
+        match &promoted {
+            Record::FLST(flst) => {
+                assert_eq!(flst.entries().collect::<Vec<_>>(), vec![FormId::new(0x205)])
+            }
+            _ => panic!("Expected FLST"),
+        }
+        assert_eq!(promoted.data_size(), record.data_size());
+
+        let unknown = Record::Unknown(GeneralRecord {
+            type_name: b"ZZZZ".as_bstr(),
+            ..general.clone()
+        });
+        let mut buffer = Vec::new();
+        assert_eq!(
+            unknown.into_general(&mut buffer).unwrap().type_name,
+            b"ZZZZ".as_bstr()
+        );
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn test_visit_formids() {
+        let general = GeneralRecord {
+            type_name: b"ACHR".as_bstr(),
+            common: CommonRecordInfo::test_default(),
+            fields: vec![
+                GeneralField::new(b"EDID".as_bstr(), b"Guard\0"),
+                GeneralField::new(b"NAME".as_bstr(), &[0x01, 0x02, 0, 0x01]),
+                GeneralField::new(b"XESP".as_bstr(), &[0x02, 0x02, 0, 0, 1, 0, 0, 0]),
+                GeneralField::new(b"XLKR".as_bstr(), &[0x03, 0x02, 0, 0x01, 0x04, 0x02, 0, 0]),
+                GeneralField::new(b"DATA".as_bstr(), &[0; 24]),
+            ],
+        };
+        let mut ctx = ParseContext::default();
+        let mut record = Record::from_general(general.clone(), &mut ctx).unwrap();
+        let formids = vec![
+            FormId::new(0x0100_0201),
+            FormId::new(0x202),
+            FormId::new(0x0100_0203),
+            FormId::new(0x204),
+        ];
+        assert_eq!(record.formids(), formids);
+        assert_eq!(record.as_record_ref().formids(), formids);
+
+        // Move the references to the second master into the first
+        record.as_record_mut().visit_formids(&mut |formid| {
+            if formid.mod_index() == 1 {
+                *formid = formid.with_mod_index(0);
+            }
+        });
+        assert!(record.formids().iter().all(|x| x.mod_index() == 0));
+        let mut buffer = Vec::new();
+        let written = record.into_general(&mut buffer).unwrap();
+        assert_eq!(written.fields[1].data, &[0x01, 0x02, 0, 0]);
+        assert_eq!(
+            written.fields[3].data,
+            &[0x03, 0x02, 0, 0, 0x04, 0x02, 0, 0]
+        );
+
+        // The fields of unknown records can't be read
+        assert!(Record::Unknown(general).formids().is_empty());
+    }
+}
@@ -1,21 +1,24 @@
 use super::{
     common::{
-        get_field, CommonRecordInfo, FormId, FromRecord, FromRecordError, GeneralRecord, Index,
-        NullTerminatedString, StaticTypeNamed, TypeNamed,
+        get_field, CommonRecordInfo, FormId, FormIdVisitor, FromRecord, FromRecordError,
+        GeneralRecord, Index, NullTerminatedString, RecordFlags, StaticTypeNamed, TypeNamed,
+        VersionControlInfo,
     },
     fields::common::{
         write_field_header, CollectField, FromField, FromFieldError, GeneralField, FIELDH_SIZE,
     },
 };
 use crate::{
-    collect_one, collect_one_collection, dispatch_all, impl_from_field, impl_static_data_size,
-    impl_static_type_named, make_field_getter, make_single_value_field,
+    collect_one, collect_one_collection,
+    context::{ParseContext, FORM_VERSION_SE},
+    dispatch_all, impl_formid_visitor, impl_from_field, impl_no_formids, impl_redecode_unknowns,
+    impl_static_data_size, impl_static_type_named, make_field_getter, make_single_value_field,
     parse::{many, PResult, Parse},
-    util::{fmt_data, DataSize, Writable},
+    util::{fmt_data, record_data_size, DataSize, Writable, WriteError},
 };
-use bstr::BStr;
+use bstr::{BStr, BString};
 use derive_more::From;
-use std::io::Write;
+use std::{borrow::Cow, io::Write};
 
 /// Header record for mod file
 #[derive(Debug, Clone, PartialEq)]
@@ -24,6 +27,24 @@ pub struct TES4Record<'data> {
     pub fields: Vec<TES4Field<'data>>,
 }
 impl<'data> TES4Record<'data> {
+    /// Header of a new plugin, with no masters and no records
+    pub fn new() -> TES4Record<'data> {
+        TES4Record {
+            common: CommonRecordInfo::new(
+                RecordFlags::new(0),
+                0,
+                VersionControlInfo::new(0, 0, 0, 0),
+                FORM_VERSION_SE,
+                0,
+            ),
+            fields: vec![TES4Field::HEDR(HEDR {
+                version: 1.7,
+                record_count: 0,
+                next_object_id: 0x800,
+            })],
+        }
+    }
+
     make_field_getter!(header_index, header, header_mut, TES4Field::HEDR, HEDR);
 
     make_field_getter!(
@@ -59,6 +80,76 @@ impl<'data> TES4Record<'data> {
         ONAM
     );
 
+    /// Sets the author, adding a CNAM after the HEDR if there isn't one.
+    /// The game reads at most 511 bytes of it
+    pub fn set_author(&mut self, author: &'data BStr) {
+        let author = NullTerminatedString::new(author);
+        match self.author_mut() {
+            Some(cnam) => cnam.author = author,
+            None => {
+                let index = self.header_index() + 1;
+                self.fields.insert(index, CNAM { author }.into());
+            }
+        }
+    }
+
+    /// Sets the description, adding a SNAM after the author or HEDR if there isn't one.
+    /// The game reads at most 511 bytes of it
+    pub fn set_description(&mut self, description: &'data BStr) {
+        let description = NullTerminatedString::new(description);
+        match self.description_mut() {
+            Some(snam) => snam.description = description,
+            None => {
+                let index = self.author_index().unwrap_or_else(|| self.header_index()) + 1;
+                self.fields.insert(index, SNAM { description }.into());
+            }
+        }
+    }
+
+    /// Adds [filename] to the end of the masters. Form ids aren't changed, so any that referred
+    /// to this plugin itself refer to the new master until they are renumbered
+    pub fn push_master(&mut self, filename: BString) {
+        let master = MASTCollection {
+            master: MAST {
+                filename: NullTerminatedString {
+                    value: Cow::Owned(filename),
+                },
+            },
+            data: DATA { value: 0 },
+        };
+        match self.masters_mut() {
+            Some(masters) => masters.masters.push(master),
+            None => {
+                let index = self
+                    .description_index()
+                    .or_else(|| self.author_index())
+                    .unwrap_or_else(|| self.header_index())
+                    + 1;
+                let masters = MasterCollection {
+                    masters: vec![master],
+                };
+                self.fields.insert(index, masters.into());
+            }
+        }
+    }
+
+    /// Removes the master at [index], returning its filename. The MAST fields are removed
+    /// entirely if it was the last. Form ids aren't changed, so those of later masters and this
+    /// plugin refer to the master before them until they are renumbered
+    pub fn remove_master(&mut self, index: usize) -> Option<BString> {
+        let masters = self.masters_mut()?;
+        if index >= masters.masters.len() {
+            return None;
+        }
+        let master = masters.masters.remove(index);
+        if masters.masters.is_empty() {
+            if let Some(index) = self.masters_index() {
+                self.fields.remove(index);
+            }
+        }
+        Some(master.master.filename.value.into_owned())
+    }
+
     make_field_getter!(
         optional: internal_version_index,
         internal_version,
@@ -67,8 +158,16 @@ impl<'data> TES4Record<'data> {
         INTV
     );
 }
+impl Default for TES4Record<'_> {
+    fn default() -> Self {
+        TES4Record::new()
+    }
+}
 impl<'data> FromRecord<'data> for TES4Record<'data> {
-    fn from_record(record: GeneralRecord<'data>) -> PResult<TES4Record, FromRecordError<'data>> {
+    fn from_record(
+        record: GeneralRecord<'data>,
+        ctx: &mut ParseContext,
+    ) -> PResult<'data, TES4Record<'data>, FromRecordError<'data>> {
         let mut fields = Vec::new();
         let mut hedr_index: Option<Index> = None;
         let mut cnam_index: Option<Index> = None;
@@ -82,18 +181,19 @@ impl<'data> FromRecord<'data> for TES4Record<'data> {
         let mut field_iter = record.fields.into_iter().peekable();
         while let Some(field) = field_iter.next() {
             match field.type_name.as_ref() {
-                b"HEDR" => collect_one!(HEDR, field => fields; hedr_index),
-                b"CNAM" => collect_one!(CNAM, field => fields; cnam_index),
-                b"SNAM" => collect_one!(SNAM, field => fields; snam_index),
-                b"ONAM" => collect_one!(ONAM, field => fields; onam_index),
-                b"INTV" => collect_one!(INTV, field => fields; intv_index),
-                b"INCC" => collect_one!(INCC, field => fields; incc_index),
+                b"HEDR" => collect_one!(HEDR, field => fields; hedr_index, ctx),
+                b"CNAM" => collect_one!(CNAM, field => fields; cnam_index, ctx),
+                b"SNAM" => collect_one!(SNAM, field => fields; snam_index, ctx),
+                b"ONAM" => collect_one!(ONAM, field => fields; onam_index, ctx),
+                b"INTV" => collect_one!(INTV, field => fields; intv_index, ctx),
+                b"INCC" => collect_one!(INCC, field => fields; incc_index, ctx),
                 b"MAST" => {
-                    collect_one_collection!(MAST, MasterCollection; field, field_iter => fields; mast_collection_index)
+                    collect_one_collection!(MAST, MasterCollection; field, field_iter => fields; mast_collection_index, ctx)
                 }
                 b"DATA" => {
-                    // TODO: continue, just add this to the list
-                    panic!("[WARN] Found DATA field in TES4 that did not have a MAST before it.");
+                    // Kept as unknown, so that it is still written back out
+                    ctx.warn("DATA field in TES4 that did not have a MAST before it");
+                    fields.push(TES4Field::Unknown(field));
                 }
                 _ => fields.push(TES4Field::Unknown(field)),
             }
@@ -113,6 +213,8 @@ impl<'data> FromRecord<'data> for TES4Record<'data> {
     }
 }
 impl_static_type_named!(TES4Record<'_>, b"TES4");
+impl_redecode_unknowns!(TES4Record, TES4Field);
+impl_formid_visitor!(TES4Record<'_>, [fields]);
 impl<'data> DataSize for TES4Record<'data> {
     fn data_size(&self) -> usize {
         self.type_name().data_size() +
@@ -122,13 +224,12 @@ impl<'data> DataSize for TES4Record<'data> {
     }
 }
 impl<'data> Writable for TES4Record<'data> {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
         self.type_name().write_to(w)?;
-        // TODO: assert that size fits within a u32
-        (self.fields.data_size() as u32).write_to(w)?;
+        record_data_size(self.type_name(), self.fields.data_size())?.write_to(w)?;
         self.common.write_to(w)?;
         self.fields.write_to(w)
     }
@@ -165,6 +266,68 @@ impl<'data> TypeNamed<'data> for TES4Field<'data> {
         )
     }
 }
+impl FormIdVisitor for TES4Field<'_> {
+    fn visit_formids(&mut self, visit: &mut dyn FnMut(&mut FormId)) {
+        dispatch_all!(
+            TES4Field,
+            self,
+            [
+                HEDR,
+                CNAM,
+                SNAM,
+                ONAM,
+                INTV,
+                INCC,
+                MasterCollection,
+                Unknown
+            ],
+            x,
+            { x.visit_formids(visit) }
+        )
+    }
+
+    fn for_each_formid(&self, visit: &mut dyn FnMut(FormId)) {
+        dispatch_all!(
+            TES4Field,
+            self,
+            [
+                HEDR,
+                CNAM,
+                SNAM,
+                ONAM,
+                INTV,
+                INCC,
+                MasterCollection,
+                Unknown
+            ],
+            x,
+            { x.for_each_formid(visit) }
+        )
+    }
+
+    fn for_each_field_formid<'name>(
+        &self,
+        name: &'name BStr,
+        visit: &mut dyn FnMut(&'name BStr, FormId),
+    ) {
+        dispatch_all!(
+            TES4Field,
+            self,
+            [
+                HEDR,
+                CNAM,
+                SNAM,
+                ONAM,
+                INTV,
+                INCC,
+                MasterCollection,
+                Unknown
+            ],
+            x,
+            { x.for_each_field_formid(name, visit) }
+        )
+    }
+}
 impl<'data> DataSize for TES4Field<'data> {
     fn data_size(&self) -> usize {
         dispatch_all!(
@@ -186,7 +349,7 @@ impl<'data> DataSize for TES4Field<'data> {
     }
 }
 impl<'data> Writable for TES4Field<'data> {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -220,6 +383,7 @@ pub struct HEDR {
 }
 impl_from_field!(HEDR, [version: f32, record_count: u32, next_object_id: u32]);
 impl_static_type_named!(HEDR, b"HEDR");
+impl_no_formids!(HEDR);
 impl_static_data_size!(
     HEDR,
     FIELDH_SIZE +
@@ -228,7 +392,7 @@ impl_static_data_size!(
     u32::static_data_size() // next object id
 );
 impl Writable for HEDR {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -246,6 +410,7 @@ make_single_value_field!([Debug, Clone, Eq, PartialEq], CNAM,
     'data
 );
 impl_from_field!(CNAM, 'data, [author: NullTerminatedString]);
+impl_no_formids!(CNAM<'_>);
 
 make_single_value_field!([Debug, Clone, Eq, PartialEq], SNAM,
     /// max-size: 512 bytes (including null!)
@@ -254,6 +419,7 @@ make_single_value_field!([Debug, Clone, Eq, PartialEq], SNAM,
     'data
 );
 impl_from_field!(SNAM, 'data, [description: NullTerminatedString]);
+impl_no_formids!(SNAM<'_>);
 
 /// Holds a MAST,DATA pair
 #[derive(Debug, Clone, PartialEq)]
@@ -283,7 +449,7 @@ impl DataSize for MASTCollection<'_> {
     }
 }
 impl Writable for MASTCollection<'_> {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -321,15 +487,23 @@ impl<'data> MasterCollection<'data> {
 
         Ok((&[], Self { masters }))
     }
+
+    /// Filenames of the masters, in the order that their load order indices are given out
+    pub fn filenames(&self) -> impl Iterator<Item = &BStr> + '_ {
+        self.masters
+            .iter()
+            .map(|x| x.master.filename.value.as_ref())
+    }
 }
 impl_static_type_named!(MasterCollection<'_>, MASTCollection::static_type_name());
+impl_no_formids!(MasterCollection<'_>);
 impl DataSize for MasterCollection<'_> {
     fn data_size(&self) -> usize {
         self.masters.data_size()
     }
 }
 impl Writable for MasterCollection<'_> {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -365,6 +539,7 @@ impl FromField<'_> for ONAM {
         Ok((field_data, ONAM { overrides }))
     }
 }
+impl_formid_visitor!(ONAM, [overrides]);
 impl std::fmt::Debug for ONAM {
     fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let mut res = fmt.debug_struct("ONAM");
@@ -375,9 +550,11 @@ impl std::fmt::Debug for ONAM {
 
 make_single_value_field!([Debug, Clone, Eq, PartialEq], INTV, value, u32);
 impl_from_field!(INTV, [value: u32]);
+impl_no_formids!(INTV);
 
 make_single_value_field!([Debug, Clone, Eq, PartialEq], INCC, value, u32);
 impl_from_field!(INCC, [value: u32]);
+impl_no_formids!(INCC);
 
 #[cfg(test)]
 mod tests {
@@ -414,4 +591,52 @@ mod tests {
         };
         assert_size_output!(tes4);
     }
+
+    #[test]
+    fn test_new_tes4() {
+        use bstr::ByteSlice;
+        let mut tes4 = TES4Record::new();
+        assert_eq!(tes4.header().version, 1.7);
+        assert!(tes4.masters().is_none());
+        tes4.set_description(b"Fixes things".as_bstr());
+        tes4.set_author(b"Someone".as_bstr());
+        tes4.set_author(b"Someone Else".as_bstr());
+        let names: Vec<_> = tes4.fields.iter().map(|x| x.type_name()).collect();
+        assert_eq!(names, vec!["HEDR", "CNAM", "SNAM"]);
+        assert_eq!(
+            tes4.author().unwrap().author.value,
+            b"Someone Else".as_bstr()
+        );
+        assert_size_output!(tes4);
+    }
+
+    #[test]
+    fn test_tes4_stray_data() {
+        use bstr::ByteSlice;
+        let mut hedr = Vec::new();
+        HEDR {
+            version: 1.7,
+            record_count: 0,
+            next_object_id: 0x800,
+        }
+        .write_to(&mut hedr)
+        .unwrap();
+        let record = GeneralRecord {
+            type_name: b"TES4".as_bstr(),
+            common: CommonRecordInfo::test_default(),
+            fields: vec![
+                GeneralField::new(b"HEDR".as_bstr(), &hedr[FIELDH_SIZE..]),
+                GeneralField::new(b"DATA".as_bstr(), &[0; 8]),
+            ],
+        };
+        let mut ctx = ParseContext::default();
+        let (_, tes4) = TES4Record::from_record(record.clone(), &mut ctx).unwrap();
+        assert_eq!(ctx.diagnostics.len(), 1);
+        assert_eq!(
+            tes4.fields[1],
+            TES4Field::Unknown(GeneralField::new(b"DATA".as_bstr(), &[0; 8]))
+        );
+        let data = assert_size_output!(tes4);
+        assert_eq!(data.len(), record.data_size());
+    }
 }
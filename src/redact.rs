@@ -0,0 +1,300 @@
+// Scrubbing of identifying information from plugins, so that a plugin which reproduces a bug
+// can be shared without also sharing the author's name or their content.
+// Only bytes are replaced, never added or removed, so the structure and every size stays the same.
+
+use crate::{
+    analysis::{group_type_of, RECORDH_SIZE},
+    config::Config,
+    groups::common::{check_group_size, GROUPH_SIZE},
+    parse::{take, ParseError},
+    records::{
+        common::{lstring::lstring_kind, record_flag},
+        fields::common::FIELDH_SIZE,
+    },
+};
+use bstr::ByteSlice;
+
+/// Offset of the version control info within both record and group headers
+const VCS_OFFSET: usize = 16;
+
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct RedactOptions {
+    /// Also blank out every field that looks like a string, such as editor ids, names and model paths
+    pub strings: bool,
+}
+
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct RedactStats {
+    /// Record and group headers which had their version control user ids cleared
+    pub headers: usize,
+    /// Fields which had their contents replaced
+    pub fields: usize,
+    /// Compressed records, whose fields could not be redacted
+    pub compressed: usize,
+}
+
+/// Returns a copy of [data] with the author (TES4 CNAM), the description (TES4 SNAM),
+/// and the version control user ids of every record and group removed.
+pub fn redact<'data>(
+    data: &'data [u8],
+    options: &RedactOptions,
+) -> Result<(Vec<u8>, RedactStats), ParseError<'data>> {
+    let mut output = data.to_vec();
+    let mut stats = RedactStats::default();
+    // The lstrings of a localized plugin hold ids, which can look like strings by chance
+    let localized = data.len() >= RECORDH_SIZE && &data[..4] == b"TES4" && {
+        let flags = u32::from_le_bytes([data[8], data[9], data[10], data[11]]);
+        flags & record_flag::LOCALIZED != 0
+    };
+    redact_entries(data, 0, localized, &mut output, options, &mut stats)?;
+    Ok((output, stats))
+}
+
+/// Redacts the records and groups in [data], which starts at [offset] within [output]
+fn redact_entries<'data>(
+    mut data: &'data [u8],
+    mut offset: usize,
+    localized: bool,
+    output: &mut [u8],
+    options: &RedactOptions,
+    stats: &mut RedactStats,
+) -> Result<(), ParseError<'data>> {
+    while !data.is_empty() {
+        let (_, header) = take(data, RECORDH_SIZE)?;
+        let type_name = header[..4].as_bstr();
+        let size = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+
+        clear_user_ids(&mut output[offset..], stats);
+        if type_name == "GRUP" {
+            check_group_size(&group_type_of(header), size, data.len() - GROUPH_SIZE)?;
+            let (rest, group) = take(data, size)?;
+            redact_entries(
+                &group[GROUPH_SIZE..],
+                offset + GROUPH_SIZE,
+                localized,
+                output,
+                options,
+                stats,
+            )?;
+            data = rest;
+            offset += size;
+        } else {
+            let flags = u32::from_le_bytes([header[8], header[9], header[10], header[11]]);
+            let (rest, record) = take(data, RECORDH_SIZE + size)?;
+            if flags & record_flag::COMPRESSED != 0 {
+                stats.compressed += 1;
+            } else {
+                redact_fields(
+                    &record[RECORDH_SIZE..],
+                    offset + RECORDH_SIZE,
+                    type_name,
+                    localized,
+                    output,
+                    options,
+                    stats,
+                )?;
+            }
+            data = rest;
+            offset += RECORDH_SIZE + size;
+        }
+    }
+    Ok(())
+}
+
+/// Redacts the fields of a [record_type] record in [data], which starts at [offset] within [output].
+/// The size given by an XXXX field is used for the field after it
+fn redact_fields<'data>(
+    mut data: &'data [u8],
+    mut offset: usize,
+    record_type: &[u8],
+    localized: bool,
+    output: &mut [u8],
+    options: &RedactOptions,
+    stats: &mut RedactStats,
+) -> Result<(), ParseError<'data>> {
+    let mut extended_size = None;
+    while !data.is_empty() {
+        let (rest, header) = take(data, FIELDH_SIZE)?;
+        let type_name = header[..4].as_bstr();
+        let size = u16::from_le_bytes([header[4], header[5]]) as usize;
+        if type_name == "XXXX" {
+            let (rest, value) = take(rest, 4)?;
+            extended_size = Some(u32::from_le_bytes([value[0], value[1], value[2], value[3]]));
+            data = rest;
+            offset += FIELDH_SIZE + 4;
+            continue;
+        }
+
+        let size = extended_size.take().map_or(size, |size| size as usize);
+        let (rest, field) = take(rest, size)?;
+
+        let start = offset + FIELDH_SIZE;
+        let author = record_type == b"TES4" && (type_name == "CNAM" || type_name == "SNAM");
+        let string = match lstring_kind(record_type, type_name) {
+            Some(_) => !localized && !field.is_empty(),
+            None => looks_like_string(field),
+        };
+        if author || (options.strings && string) {
+            blank_string(&mut output[start..start + size], stats.fields);
+            stats.fields += 1;
+        }
+
+        data = rest;
+        offset += FIELDH_SIZE + size;
+    }
+    Ok(())
+}
+
+fn clear_user_ids(header: &mut [u8], stats: &mut RedactStats) {
+    // day and month are left alone, as they only say when the record was last edited
+    header[VCS_OFFSET + 2] = 0;
+    header[VCS_OFFSET + 3] = 0;
+    stats.headers += 1;
+}
+
+/// Whether the field is plausibly a null terminated string
+fn looks_like_string(data: &[u8]) -> bool {
+    match data.split_last() {
+        Some((0, text)) if !text.is_empty() => text
+            .iter()
+            .all(|&b| b >= 0x20 || b == b'\t' || b == b'\r' || b == b'\n'),
+        _ => false,
+    }
+}
+
+/// Replaces the text with filler, keeping the null terminator if there is one.
+/// The filler ends in [counter] (in hex) so that redacted editor ids stay unique when they fit.
+fn blank_string(data: &mut [u8], counter: usize) {
+    let len = match data.last() {
+        Some(0) => data.len() - 1,
+        _ => data.len(),
+    };
+    let text = &mut data[..len];
+    let suffix = format!("{:x}", counter);
+    for b in text.iter_mut() {
+        *b = b'x';
+    }
+    if suffix.len() < text.len() {
+        let start = text.len() - suffix.len();
+        text[start..].copy_from_slice(suffix.as_bytes());
+    }
+}
+
+/// `vivec redact <input> <output> [--strings]`
+pub fn redact_command(args: &[String], config: &Config) {
+    let mut paths = Vec::new();
+    let mut options = RedactOptions::default();
+    for arg in args {
+        match arg.as_str() {
+            "--strings" => options.strings = true,
+            _ => paths.push(arg),
+        }
+    }
+    if paths.len() != 2 {
+        panic!("Expected an input and an output plugin");
+    }
+
+    let input = config.plugin_path(paths[0]);
+    let data = std::fs::read(input).expect("Failed to read data from file");
+    let (output, stats) = redact(&data, &options).expect("Failed to parse");
+    let output_path = config.output_path(paths[1]);
+    std::fs::write(output_path, output).expect("Failed to write redacted file");
+    println!(
+        "Cleared {} headers, redacted {} fields",
+        stats.headers, stats.fields
+    );
+    if stats.compressed != 0 {
+        println!(
+            "{} compressed records were left as they are, apart from their header",
+            stats.compressed
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fixtures, records::fields::common::GeneralField};
+
+    fn record(type_name: &'static [u8], fields: Vec<GeneralField<'static>>) -> Vec<u8> {
+        fixtures::record(type_name, 0x800, fields)
+    }
+
+    #[test]
+    fn test_redact() {
+        let mut data = record(
+            b"TES4",
+            vec![
+                GeneralField::new(b"CNAM".as_bstr(), b"Author Name\0"),
+                GeneralField::new(b"SNAM".as_bstr(), b"About\0"),
+            ],
+        );
+        let header_len = data.len();
+        let edid = record(
+            b"AACT",
+            vec![GeneralField::new(b"EDID".as_bstr(), b"SecretAction\0")],
+        );
+        let mut group = fixtures::group(b"AACT", 0, &[edid]);
+        group[VCS_OFFSET..VCS_OFFSET + 4].copy_from_slice(&[1, 2, 7, 8]);
+        data.extend_from_slice(&group);
+
+        let (output, stats) = redact(&data, &RedactOptions::default()).unwrap();
+        assert_eq!(output.len(), data.len());
+        assert_eq!(stats.headers, 3);
+        assert_eq!(stats.fields, 2);
+        assert_eq!(&output[18..20], &[0, 0]);
+        assert_eq!(&output[16..18], &[9, 7]);
+        assert!(output.find(b"Author").is_none());
+        assert!(output.find(b"About").is_none());
+        assert!(output.find(b"SecretAction").is_some());
+        // The group's user ids
+        assert_eq!(&output[header_len + 18..header_len + 20], &[0, 0]);
+
+        let (output, stats) = redact(&data, &RedactOptions { strings: true }).unwrap();
+        assert_eq!(output.len(), data.len());
+        assert_eq!(stats.fields, 3);
+        assert!(output.find(b"SecretAction").is_none());
+        assert!(output.find(b"xxxxxxxxxxx2\0").is_some());
+
+        // Lstrings are only text when the plugin isn't localized
+        let message = record(
+            b"MESG",
+            vec![GeneralField::new(b"FULL".as_bstr(), b"ABC\0")],
+        );
+        let mut data = record(b"TES4", Vec::new());
+        data.extend_from_slice(&message);
+        let (output, stats) = redact(&data, &RedactOptions { strings: true }).unwrap();
+        assert_eq!(stats.fields, 1);
+        assert!(output.find(b"ABC").is_none());
+        data[8..12].copy_from_slice(&record_flag::LOCALIZED.to_le_bytes());
+        let (output, stats) = redact(&data, &RedactOptions { strings: true }).unwrap();
+        assert_eq!(stats.fields, 0);
+        assert!(output.find(b"ABC\0").is_some());
+    }
+
+    #[test]
+    fn test_redact_oversized() {
+        // Binary data too large for the field header, which holds what looks like a field header
+        // and a string
+        let mut offsets = vec![0; 0x10000];
+        offsets[..4].copy_from_slice(b"EDID");
+        offsets[4..6].copy_from_slice(&4u16.to_le_bytes());
+        offsets[6..10].copy_from_slice(b"abc\0");
+        let world = fixtures::record(
+            b"WRLD",
+            0x800,
+            vec![
+                GeneralField::new(b"OFST".as_bstr(), &offsets),
+                GeneralField::new(b"EDID".as_bstr(), b"World\0"),
+            ],
+        );
+        let data = [record(b"TES4", Vec::new()), world].concat();
+        assert!(data.find(b"XXXX").is_some());
+
+        let (output, stats) = redact(&data, &RedactOptions { strings: true }).unwrap();
+        assert_eq!(output.len(), data.len());
+        assert_eq!(stats.fields, 1);
+        assert!(output.find(b"abc\0").is_some());
+        assert!(output.find(b"World").is_none());
+    }
+}
@@ -0,0 +1,495 @@
+// Where records are referred to from. The form ids found by the FormIdVisitor are gathered into
+// a reverse index from the record they point at to each record (and field) pointing at it, the
+// "referenced by" view of a record. Form ids are resolved against the masters of the plugin that
+// holds them, so references from plugins of a whole load order can be gathered together.
+// Null references, and references to masters past the end of the plugin's list, are left out.
+// Records that weren't converted, or are compressed, can't be looked into, so they are counted
+// instead, much as renumbering does.
+// References that don't lead to any loaded record are reported as dangling, and form ids whose
+// mod index is past the masters of their plugin are reported on their own, as they are a sign of
+// corruption rather than of a missing record.
+
+use crate::{
+    context::{Diagnostic, Severity},
+    groups::{
+        common::{walk_group_entries, GroupEntry},
+        Group,
+    },
+    load_order::{LoadOrder, Source},
+    plugin::Plugin,
+    records::{
+        common::{FormId, GlobalFormId, TypeNamed},
+        RecordRef,
+    },
+    walk::EntryRef,
+    Top,
+};
+use bstr::{BStr, BString};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::{Display, Formatter},
+};
+
+/// A field of a record that refers to another record
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct RefSite<'a> {
+    /// The plugin holding the referring record
+    pub source: Source<'a>,
+    /// Id of the referring record, as written in its plugin
+    pub record: FormId,
+    pub record_type: &'a BStr,
+    /// Name of the field holding the reference
+    pub field: &'a BStr,
+}
+
+/// The records referring to each record, see [ReferenceGraph::refs_to]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ReferenceGraph<'a> {
+    refs: HashMap<GlobalFormId, Vec<RefSite<'a>>>,
+    hidden: usize,
+}
+impl<'a> ReferenceGraph<'a> {
+    /// The references made by the records of [plugin]
+    pub fn of_plugin(plugin: &'a Plugin<'_>) -> ReferenceGraph<'a> {
+        let mut graph = ReferenceGraph::default();
+        graph.add_plugin(0, plugin);
+        graph
+    }
+
+    /// The references made by the records of every plugin in [load_order]. Overrides are kept
+    /// apart, so a record that is overridden has sites in each plugin that has a version of it
+    pub fn of_load_order(load_order: &'a LoadOrder<'_>) -> ReferenceGraph<'a> {
+        let mut graph = ReferenceGraph::default();
+        for (index, plugin) in load_order.plugins.iter().enumerate() {
+            graph.add_plugin(index, plugin);
+        }
+        graph
+    }
+
+    fn add_plugin(&mut self, index: usize, plugin: &'a Plugin<'_>) {
+        let refs = &mut self.refs;
+        self.hidden += for_each_ref(index, plugin, |site, id| {
+            if let Some(target) = plugin.global_formid(id) {
+                refs.entry(target).or_default().push(site);
+            }
+        });
+    }
+
+    /// Every field referring to [id], in the order the plugins and their records are in
+    pub fn refs_to(&self, id: &GlobalFormId) -> &[RefSite<'a>] {
+        self.refs.get(id).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// The records that are referred to
+    pub fn referenced(&self) -> impl Iterator<Item = &GlobalFormId> + '_ {
+        self.refs.keys()
+    }
+
+    /// Records that weren't converted or are compressed, so the references they make are missing
+    pub fn hidden(&self) -> usize {
+        self.hidden
+    }
+}
+
+/// Calls [func] with each non-null reference made by the records of [plugin], at [index] in its
+/// load order, along with the form id as it is written. Returns the amount of records whose
+/// references couldn't be read
+fn for_each_ref<'a, F>(index: usize, plugin: &'a Plugin<'_>, mut func: F) -> usize
+where
+    F: FnMut(RefSite<'a>, FormId),
+{
+    let source = Source {
+        index,
+        name: &plugin.name,
+    };
+    let mut hidden = 0;
+    for (entry, _) in plugin.iter_all_records() {
+        let record = match entry {
+            EntryRef::Record(RecordRef::Unknown(_) | RecordRef::Deleted(_))
+            | EntryRef::Compressed(_) => {
+                hidden += 1;
+                continue;
+            }
+            EntryRef::Record(record) => record,
+        };
+        let record_type = record.type_name();
+        for (field, id) in record.field_formids() {
+            if id.id == 0 {
+                continue;
+            }
+            let site = RefSite {
+                source,
+                record: record.formid(),
+                record_type,
+                field,
+            };
+            func(site, id);
+        }
+    }
+    hidden
+}
+
+/// Why a reference doesn't lead to a record
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum DanglingKind {
+    /// The mod index is past the masters of the plugin and the plugin itself
+    BadModIndex,
+    /// The master that the form id belongs to isn't in the load order
+    MissingMaster(BString),
+    /// No loaded plugin has a record with the form id
+    MissingRecord(GlobalFormId),
+}
+
+/// A reference to a record that doesn't exist
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct DanglingRef<'a> {
+    pub site: RefSite<'a>,
+    /// The form id as it is written in the referring record
+    pub target: FormId,
+    pub kind: DanglingKind,
+}
+impl DanglingRef<'_> {
+    /// The problem as a diagnostic about the referring record
+    pub fn to_diagnostic(&self) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Error,
+            record: Some((self.site.record_type.to_string(), self.site.record)),
+            message: self.to_string(),
+        }
+    }
+}
+impl Display for DanglingRef<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} in {} refers to [{:08X}], ",
+            self.site.field, self.site.source.name, self.target.id
+        )?;
+        match &self.kind {
+            DanglingKind::BadModIndex => write!(f, "whose mod index is past its masters"),
+            DanglingKind::MissingMaster(master) => {
+                write!(f, "which belongs to {}, but it isn't loaded", master)
+            }
+            DanglingKind::MissingRecord(id) => write!(f, "but {} doesn't exist", id),
+        }
+    }
+}
+
+/// Every reference made within [load_order] that doesn't lead to a record. A record counts as
+/// existing if any plugin has a version of it, including in groups that weren't converted, so
+/// records injected into a master by a later plugin are found
+pub fn dangling_references<'a>(load_order: &'a LoadOrder<'_>) -> Vec<DanglingRef<'a>> {
+    let mut existing = HashSet::new();
+    for plugin in load_order.plugins.iter() {
+        let (ids, _) = plugin.formids_and_groups();
+        existing.extend(ids.into_iter().filter_map(|id| plugin.global_formid(id)));
+    }
+
+    let mut dangling = Vec::new();
+    for (index, plugin) in load_order.plugins.iter().enumerate() {
+        for_each_ref(index, plugin, |site, target| {
+            let kind = match plugin.global_formid(target) {
+                None => DanglingKind::BadModIndex,
+                Some(id) if load_order.index_of(&id.plugin).is_none() => {
+                    DanglingKind::MissingMaster(id.plugin)
+                }
+                Some(id) if !existing.contains(&id) => DanglingKind::MissingRecord(id),
+                Some(_) => return,
+            };
+            dangling.push(DanglingRef { site, target, kind });
+        });
+    }
+    dangling
+}
+
+/// A form id whose mod index is past the masters of its plugin and the plugin itself, known as
+/// a HITME (higher index than master entries). These are a sign of a corrupted plugin, or of one
+/// that had a master removed without its form ids being renumbered
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct BadModIndex<'a> {
+    pub record_type: &'a BStr,
+    /// Id of the record holding the form id
+    pub record: FormId,
+    /// The field holding the form id, None if it is the id of the record itself
+    pub field: Option<&'a BStr>,
+    pub id: FormId,
+}
+impl BadModIndex<'_> {
+    /// The problem as a diagnostic about the record holding it
+    pub fn to_diagnostic(self) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Error,
+            record: Some((self.record_type.to_string(), self.record)),
+            message: self.to_string(),
+        }
+    }
+}
+impl Display for BadModIndex<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let mod_index = self.id.mod_index();
+        match self.field {
+            Some(field) => write!(
+                f,
+                "{} refers to [{:08X}], whose mod index {:02X} is past the masters of the plugin",
+                field, self.id.id, mod_index
+            ),
+            None => write!(
+                f,
+                "Record id [{:08X}] has mod index {:02X}, past the masters of the plugin",
+                self.id.id, mod_index
+            ),
+        }
+    }
+}
+
+impl Plugin<'_> {
+    /// Every record id and reference whose mod index is past this plugin's masters and the
+    /// plugin itself. Only the ids of records in groups that weren't converted can be checked
+    pub fn bad_mod_indices(&self) -> Vec<BadModIndex<'_>> {
+        let own_index = self.masters().len();
+        let is_bad = |id: FormId| usize::from(id.mod_index()) > own_index;
+        let mut bad = Vec::new();
+        for record in self.records() {
+            let record_type = record.type_name();
+            let record_id = record.formid();
+            let fields = std::iter::once((None, record_id)).chain(
+                record
+                    .field_formids()
+                    .into_iter()
+                    .map(|(field, id)| (Some(field), id)),
+            );
+            for (field, id) in fields.filter(|(_, id)| is_bad(*id)) {
+                bad.push(BadModIndex {
+                    record_type,
+                    record: record_id,
+                    field,
+                    id,
+                });
+            }
+        }
+        for top in self.tops().iter() {
+            let entries = match top {
+                Top::Group(Group::Unknown(group)) => &group.entries,
+                Top::Group(Group::UnknownTop(group)) => &group.entries,
+                _ => continue,
+            };
+            walk_group_entries(entries, &mut |entry| {
+                let (record_type, id) = match entry {
+                    GroupEntry::Record(record) => (record.type_name, FormId::new(record.common.id)),
+                    GroupEntry::Compressed(record) => {
+                        (record.type_name, FormId::new(record.common.id))
+                    }
+                    GroupEntry::Group(_) => return,
+                };
+                if is_bad(id) {
+                    bad.push(BadModIndex {
+                        record_type,
+                        record: id,
+                        field: None,
+                        id,
+                    });
+                }
+            });
+        }
+        bad
+    }
+
+    /// Every field of this plugin referring to [id], which is relative to this plugin's masters.
+    /// Each call goes through every record, so when looking up many records, build a
+    /// [ReferenceGraph] once instead
+    pub fn refs_to(&self, id: FormId) -> Vec<RefSite<'_>> {
+        let target = match self.global_formid(id) {
+            Some(target) => target,
+            None => return Vec::new(),
+        };
+        let mut sites = Vec::new();
+        for_each_ref(0, self, |site, id| {
+            if self.global_formid(id).as_ref() == Some(&target) {
+                sites.push(site);
+            }
+        });
+        sites
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        context::ParseContext,
+        fixtures::{general, list, top},
+        records::{common::CompressedRecord, fields::common::GeneralField, Record},
+    };
+    use bstr::{BString, ByteSlice};
+
+    #[test]
+    fn test_refs_to() {
+        let mut base = Plugin::new("Base.esm");
+        base.push_top(list(0x801, &[]));
+        base.push_top(list(0x802, &[&[1, 8, 0, 0], &[0, 0, 0, 0]]));
+        let mut patch = Plugin::new("Patch.esp");
+        patch
+            .header_mut()
+            .unwrap()
+            .push_master(BString::from("Base.esm"));
+        patch.push_top(list(0x0100_0800, &[&[1, 8, 0, 0], &[2, 8, 0, 2]]));
+
+        let sites = base.refs_to(FormId::new(0x801));
+        assert_eq!(
+            sites,
+            vec![RefSite {
+                source: Source {
+                    index: 0,
+                    name: "Base.esm"
+                },
+                record: FormId::new(0x802),
+                record_type: b"FLST".as_bstr(),
+                field: b"LNAM".as_bstr(),
+            }]
+        );
+        assert!(base.refs_to(FormId::new(0x802)).is_empty());
+
+        let load_order = LoadOrder::new(vec![base, patch]);
+        let graph = ReferenceGraph::of_load_order(&load_order);
+        let sites: Vec<(usize, FormId)> = graph
+            .refs_to(&GlobalFormId::new("base.esm", 0x801))
+            .iter()
+            .map(|site| (site.source.index, site.record))
+            .collect();
+        assert_eq!(
+            sites,
+            vec![(0, FormId::new(0x802)), (1, FormId::new(0x0100_0800))]
+        );
+        // Null references and those past the masters of the plugin are left out
+        assert_eq!(graph.referenced().count(), 1);
+    }
+
+    #[test]
+    fn test_refs_in_collections() {
+        let mut plugin = Plugin::new("Base.esm");
+        plugin.push_top(list(0x801, &[]));
+        let activator = general(
+            b"ACTI",
+            0x802,
+            vec![
+                GeneralField::new(b"OBND".as_bstr(), &[0; 12]),
+                GeneralField::new(b"KSIZ".as_bstr(), &[1, 0, 0, 0]),
+                GeneralField::new(b"KWDA".as_bstr(), &[1, 8, 0, 0]),
+            ],
+        );
+        let activator = Record::from_general(activator, &mut ParseContext::default()).unwrap();
+        plugin.push_top(Top::Record(activator));
+        let compressed = general(
+            b"MISC",
+            0x804,
+            vec![GeneralField::new(b"KWDA".as_bstr(), &[1, 8, 0, 0])],
+        );
+        let compressed = CompressedRecord::compress(&compressed, 9).unwrap();
+        plugin.push_top(top(
+            b"MISC",
+            vec![
+                general(b"MISC", 0x803, Vec::new()).into(),
+                compressed.into(),
+            ],
+        ));
+
+        // The keywords are in KWDA, although the collection starts with KSIZ
+        let fields: Vec<(FormId, &BStr)> = plugin
+            .refs_to(FormId::new(0x801))
+            .iter()
+            .map(|site| (site.record, site.field))
+            .collect();
+        assert_eq!(fields, vec![(FormId::new(0x802), b"KWDA".as_bstr())]);
+        // The records in the MISC group weren't converted, so what they refer to isn't known
+        assert_eq!(ReferenceGraph::of_plugin(&plugin).hidden(), 2);
+    }
+
+    #[test]
+    fn test_dangling_references() {
+        let mut base = Plugin::new("Base.esm");
+        base.push_top(list(0x801, &[&[2, 8, 0, 0]]));
+        let mut patch = Plugin::new("Patch.esp");
+        let header = patch.header_mut().unwrap();
+        header.push_master(BString::from("Base.esm"));
+        header.push_master(BString::from("Missing.esm"));
+        patch.push_top(list(
+            0x0200_0800,
+            &[&[1, 8, 0, 0], &[3, 8, 0, 0], &[5, 8, 0, 1], &[1, 8, 0, 3]],
+        ));
+        // Injected into Base.esm
+        patch.push_top(list(0x0000_0803, &[]));
+        // Held by a group that wasn't converted
+        patch.push_top(list(0x0200_0801, &[&[4, 8, 0, 2]]));
+        patch.push_top(top(
+            b"MISC",
+            vec![general(b"MISC", 0x0200_0804, Vec::new()).into()],
+        ));
+        let load_order = LoadOrder::new(vec![base, patch]);
+
+        let dangling = dangling_references(&load_order);
+        let kinds: Vec<(usize, FormId, DanglingKind)> = dangling
+            .iter()
+            .map(|x| (x.site.source.index, x.target, x.kind.clone()))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (
+                    0,
+                    FormId::new(0x802),
+                    DanglingKind::MissingRecord(GlobalFormId::new("Base.esm", 0x802))
+                ),
+                (
+                    1,
+                    FormId::new(0x0100_0805),
+                    DanglingKind::MissingMaster(BString::from("Missing.esm"))
+                ),
+                (1, FormId::new(0x0300_0801), DanglingKind::BadModIndex),
+            ]
+        );
+        let diagnostic = dangling[0].to_diagnostic();
+        assert_eq!(
+            diagnostic.record,
+            Some(("FLST".to_string(), FormId::new(0x801)))
+        );
+        assert_eq!(
+            diagnostic.to_string(),
+            "error: FLST [00000801]: LNAM in Base.esm refers to [00000802], but \
+             000802:Base.esm doesn't exist"
+        );
+    }
+
+    #[test]
+    fn test_bad_mod_indices() {
+        let mut plugin = Plugin::new("Patch.esp");
+        plugin
+            .header_mut()
+            .unwrap()
+            .push_master(BString::from("Skyrim.esm"));
+        plugin.push_top(list(0x0100_0800, &[&[1, 8, 0, 1], &[2, 8, 0, 2]]));
+        plugin.push_top(list(0x0500_0800, &[]));
+        let bad = plugin.bad_mod_indices();
+        assert_eq!(
+            bad,
+            vec![
+                BadModIndex {
+                    record_type: b"FLST".as_bstr(),
+                    record: FormId::new(0x0100_0800),
+                    field: Some(b"LNAM".as_bstr()),
+                    id: FormId::new(0x0200_0802),
+                },
+                BadModIndex {
+                    record_type: b"FLST".as_bstr(),
+                    record: FormId::new(0x0500_0800),
+                    field: None,
+                    id: FormId::new(0x0500_0800),
+                },
+            ]
+        );
+        assert_eq!(
+            bad[0].to_diagnostic().to_string(),
+            "error: FLST [01000800]: LNAM refers to [02000802], whose mod index 02 is past \
+             the masters of the plugin"
+        );
+    }
+}
@@ -0,0 +1,259 @@
+// Renumbering the records of a plugin. Given the new id for some of its form ids, the records with
+// those ids are given their new ones, and every reference to them is changed to match: the fields
+// found by the FormIdVisitor, and the labels of the groups holding the children of a WRLD, CELL
+// or DIAL. This is what merging plugins and compacting them into the light range build upon.
+// Records that weren't converted, whether held by groups that weren't or compressed, only have
+// their own id changed, as the form ids within their fields aren't known. They are counted, so
+// that callers can tell when references may have been left behind.
+
+use crate::{
+    groups::{
+        common::{GroupEntry, GroupType},
+        Group,
+    },
+    injected::InjectedPolicy,
+    plugin::Plugin,
+    records::{
+        common::{FormId, FormIdVisitor},
+        RecordMut,
+    },
+    walk::EntryMut,
+    Top,
+};
+use std::collections::HashMap;
+
+/// The new form id for each form id that is changed
+pub type FormIdMap = HashMap<FormId, FormId>;
+
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct RemapStats {
+    /// Records that were given a new id
+    pub records: usize,
+    /// References within the fields of records that were changed
+    pub references: usize,
+    /// Groups whose parent was changed
+    pub groups: usize,
+    /// Records that weren't converted, so the references within their fields weren't changed
+    pub hidden: usize,
+}
+
+/// Gives the new form id for a form id, None if it isn't changed
+type Remapper<'a> = dyn FnMut(FormId) -> Option<FormId> + 'a;
+
+/// Changes [id] to what [map] gives for it, returning whether it was changed
+fn remap(id: &mut FormId, map: &mut Remapper<'_>) -> bool {
+    match map(*id) {
+        Some(new_id) if new_id != *id => {
+            *id = new_id;
+            true
+        }
+        _ => false,
+    }
+}
+
+fn remap_group_type(
+    group_type: &mut GroupType<'_>,
+    map: &mut Remapper<'_>,
+    stats: &mut RemapStats,
+) {
+    group_type.visit_formids(&mut |id| {
+        if remap(id, map) {
+            stats.groups += 1;
+        }
+    });
+}
+
+/// Changes the parents of the groups within [entries], descending into them
+fn remap_groups(entries: &mut [GroupEntry<'_>], map: &mut Remapper<'_>, stats: &mut RemapStats) {
+    for entry in entries.iter_mut() {
+        if let GroupEntry::Group(group) = entry {
+            remap_group_type(&mut group.group_type, map, stats);
+            remap_groups(&mut group.entries, map, stats);
+        }
+    }
+}
+
+/// Changes the record id [id] to what [map] gives for it
+fn remap_record(id: &mut u32, map: &mut Remapper<'_>, stats: &mut RemapStats) {
+    let mut formid = FormId::new(*id);
+    if remap(&mut formid, map) {
+        *id = formid.id;
+        stats.records += 1;
+    }
+}
+
+impl Plugin<'_> {
+    /// Gives each record whose id is in [map] its new id, and changes every reference to it.
+    /// Ids are relative to this plugin's masters, as they are held. The ids that [map] gives
+    /// should not already be used by other records. Records known to be injected into a master,
+    /// see [Plugin::set_injected], keep their ids unless [Plugin::injected_policy] says otherwise
+    pub fn remap_formids(&mut self, map: &FormIdMap) -> RemapStats {
+        if self.injected_policy == InjectedPolicy::Preserve && !self.injected.is_empty() {
+            let injected = self.injected.clone();
+            return self.remap_formids_with(|id| {
+                if injected.contains(&id) {
+                    None
+                } else {
+                    map.get(&id).copied()
+                }
+            });
+        }
+        self.remap_formids_with(|id| map.get(&id).copied())
+    }
+
+    /// [Plugin::remap_formids] with the new ids given by [map], for changes that are easier to
+    /// describe than to list, such as moving every form id of a master. Null form ids are
+    /// passed to [map] as well
+    pub fn remap_formids_with<F>(&mut self, mut map: F) -> RemapStats
+    where
+        F: FnMut(FormId) -> Option<FormId>,
+    {
+        let map: &mut Remapper<'_> = &mut map;
+        let mut stats = RemapStats::default();
+        for top in self.tops_mut().iter_mut() {
+            match top {
+                Top::Group(Group::Unknown(group)) => {
+                    remap_group_type(&mut group.group_type, map, &mut stats);
+                    remap_groups(&mut group.entries, map, &mut stats);
+                }
+                Top::Group(Group::UnknownTop(group)) => {
+                    remap_groups(&mut group.entries, map, &mut stats)
+                }
+                _ => {}
+            }
+        }
+        for (entry, _) in self.iter_all_records_mut() {
+            match entry {
+                EntryMut::Record(mut record) => {
+                    remap_record(&mut record.common_mut().id, map, &mut stats);
+                    if matches!(record, RecordMut::Unknown(_) | RecordMut::Deleted(_)) {
+                        stats.hidden += 1;
+                    }
+                    record.visit_formids(&mut |id| {
+                        if remap(id, map) {
+                            stats.references += 1;
+                        }
+                    });
+                }
+                EntryMut::Compressed(record) => {
+                    remap_record(&mut record.common.id, map, &mut stats);
+                    stats.hidden += 1;
+                }
+            }
+        }
+        // Records renumbered into the plugin itself are no longer injected
+        let own_index = self.masters().len();
+        self.injected = self
+            .injected
+            .iter()
+            .map(|id| map(*id).unwrap_or(*id))
+            .filter(|id| usize::from(id.mod_index()) < own_index)
+            .collect();
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        context::ParseContext,
+        fixtures::{group, record},
+        groups::common::walk_group_entries,
+        records::{fields::common::GeneralField, RecordRef},
+        util::Writable,
+    };
+    use bstr::ByteSlice;
+
+    #[test]
+    fn test_remap_formids() {
+        let list = |id, entry: &'static [u8]| {
+            record(
+                b"FLST",
+                id,
+                vec![
+                    GeneralField::new(b"EDID".as_bstr(), b"List\0"),
+                    GeneralField::new(b"LNAM".as_bstr(), entry),
+                ],
+            )
+        };
+        let mut header = Plugin::new("Base.esm");
+        header.refresh_header();
+        let data = [
+            header.to_vec().unwrap(),
+            group(
+                b"FLST",
+                0,
+                &[list(0x801, &[2, 8, 0, 0]), list(0x802, &[1, 8, 0, 0])],
+            ),
+            group(
+                b"CELL",
+                0,
+                &[
+                    record(b"CELL", 0x803, Vec::new()),
+                    group(
+                        &0x803u32.to_le_bytes(),
+                        6,
+                        &[record(b"REFR", 0x804, Vec::new())],
+                    ),
+                ],
+            ),
+        ]
+        .concat();
+        let mut ctx = ParseContext::default();
+        let mut plugin = Plugin::parse("Base.esm", &data, &mut ctx).unwrap();
+
+        let map: FormIdMap = vec![
+            (FormId::new(0x801), FormId::new(0xA01)),
+            (FormId::new(0x803), FormId::new(0xA03)),
+            (FormId::new(0x805), FormId::new(0xA05)),
+        ]
+        .into_iter()
+        .collect();
+        let stats = plugin.remap_formids(&map);
+        assert_eq!(
+            stats,
+            RemapStats {
+                records: 2,
+                references: 1,
+                groups: 1,
+                hidden: 2,
+            }
+        );
+
+        let lists: Vec<(FormId, Vec<FormId>)> = plugin
+            .records()
+            .into_iter()
+            .filter_map(|record| match record {
+                RecordRef::FLST(flst) => Some((record.formid(), flst.entries().collect())),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            lists,
+            vec![
+                (FormId::new(0xA01), vec![FormId::new(0x802)]),
+                (FormId::new(0x802), vec![FormId::new(0xA01)]),
+            ]
+        );
+        match plugin.tops().get(2) {
+            Some(Top::Group(Group::UnknownTop(group))) => {
+                let mut ids = Vec::new();
+                walk_group_entries(&group.entries, &mut |entry| match entry {
+                    GroupEntry::Group(group) => ids.push(group.group_type.formids()[0]),
+                    entry => ids.extend(entry.formid()),
+                });
+                assert_eq!(
+                    ids,
+                    vec![FormId::new(0xA03), FormId::new(0xA03), FormId::new(0x804)]
+                );
+            }
+            top => panic!("Expected the CELL group, got {:?}", top),
+        }
+
+        // Remapping back gives the plugin that was parsed
+        let back: FormIdMap = map.iter().map(|(old, new)| (*new, *old)).collect();
+        plugin.remap_formids(&back);
+        assert_eq!(plugin.to_vec().unwrap(), data);
+    }
+}
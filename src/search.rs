@@ -0,0 +1,271 @@
+// Searching records for text, as xEdit's search does.
+// Converted records are written back out and read as general fields, so that every record type
+// can be searched without each one having to list its string fields. As that is slow, a
+// [TextIndex] does it once for any amount of searches.
+
+use crate::{
+    plugin::Plugin,
+    records::{
+        common::{
+            lstring::lstring_kind, record_flag, FormId, GeneralRecord, TextEncoding, TypeNamed,
+        },
+        fields::common::GeneralField,
+        RecordRef,
+    },
+    walk::EntryRef,
+};
+use bstr::ByteSlice;
+use std::convert::TryFrom;
+
+/// Looks up the text of an LString index in the loaded string tables
+pub type StringLookup<'a> = &'a dyn Fn(u32) -> Option<String>;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum TextKind {
+    /// The EDID field
+    EditorId,
+    /// A null terminated string stored in the field itself
+    ZString,
+    /// A string from the string tables, with its index
+    LString(u32),
+}
+
+/// Text that matched a search, along with where it was found
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct TextHit {
+    pub record_type: String,
+    pub formid: FormId,
+    pub field: String,
+    /// Position of the field within the record
+    pub field_index: usize,
+    pub kind: TextKind,
+    pub text: String,
+}
+
+/// How the text of a plugin is read
+#[derive(Copy, Clone)]
+pub struct TextSource<'a> {
+    /// Whether lstring fields hold indices into the string tables
+    pub localized: bool,
+    /// The encoding of the strings held in fields
+    pub encoding: TextEncoding,
+    /// Looks up the lstrings of a localized plugin. Without it they aren't searched
+    pub strings: Option<StringLookup<'a>>,
+}
+
+/// Every piece of text of a plugin, so that it can be searched many times over without writing
+/// out its records each time
+#[derive(Debug, Clone, Default)]
+pub struct TextIndex {
+    texts: Vec<TextHit>,
+    /// The text of each of [TextIndex::texts] in lowercase
+    lowercase: Vec<String>,
+}
+impl TextIndex {
+    /// Collects the text of every record of [plugin], including those within groups that
+    /// weren't converted. Compressed records are skipped, as their fields can't be read
+    pub fn new(plugin: &Plugin<'_>, source: TextSource<'_>) -> TextIndex {
+        let mut index = TextIndex::default();
+        let mut buffer = Vec::new();
+        for (record, _) in plugin.iter_all_records() {
+            let record = match record {
+                EntryRef::Record(record) => record,
+                EntryRef::Compressed(_) => continue,
+            };
+            match record {
+                RecordRef::Unknown(general) | RecordRef::Deleted(general) => {
+                    index.add(general, source)
+                }
+                record => {
+                    if let Some(general) = record.to_general(&mut buffer) {
+                        index.add(&general, source);
+                    }
+                }
+            }
+        }
+        index
+    }
+
+    fn add(&mut self, record: &GeneralRecord<'_>, source: TextSource<'_>) {
+        if record.common.flags.is(record_flag::COMPRESSED) {
+            return;
+        }
+        for (field_index, field) in record.fields.iter().enumerate() {
+            let (kind, text) = match field_text(record.type_name.as_bytes(), field, source) {
+                Some(found) => found,
+                None => continue,
+            };
+            self.lowercase.push(text.to_lowercase());
+            self.texts.push(TextHit {
+                record_type: record.type_name.to_string(),
+                formid: FormId::new(record.common.id),
+                field: field.type_name().to_string(),
+                field_index,
+                kind,
+                text,
+            });
+        }
+    }
+
+    /// The text that contains [query], ignoring case
+    pub fn search(&self, query: &str) -> Vec<TextHit> {
+        let query = query.to_lowercase();
+        self.lowercase
+            .iter()
+            .zip(&self.texts)
+            .filter(|(lowercase, _)| lowercase.contains(&query))
+            .map(|(_, hit)| hit.clone())
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.texts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.texts.is_empty()
+    }
+}
+
+/// Finds the text in [record] that contains [query], ignoring case.
+/// Compressed records are not searched, as their fields can't be read.
+pub fn search_record(
+    record: RecordRef<'_, '_>,
+    query: &str,
+    source: TextSource<'_>,
+) -> Vec<TextHit> {
+    let mut index = TextIndex::default();
+    match record {
+        RecordRef::Unknown(general) | RecordRef::Deleted(general) => index.add(general, source),
+        record => {
+            let mut buffer = Vec::new();
+            if let Some(general) = record.to_general(&mut buffer) {
+                index.add(&general, source);
+            }
+        }
+    }
+    index.search(query)
+}
+
+fn field_text(
+    record_type: &[u8],
+    field: &GeneralField<'_>,
+    source: TextSource<'_>,
+) -> Option<(TextKind, String)> {
+    let name = field.type_name.as_bytes();
+    if source.localized && lstring_kind(record_type, name).is_some() {
+        let data = <[u8; 4]>::try_from(field.data).ok()?;
+        let index = u32::from_le_bytes(data);
+        let text = source.strings?(index)?;
+        return Some((TextKind::LString(index), text));
+    }
+    let text = zstring(field.data, source.encoding)?;
+    let kind = if name == b"EDID" {
+        TextKind::EditorId
+    } else {
+        TextKind::ZString
+    };
+    Some((kind, text))
+}
+
+/// The text of [data] if it looks like a single null terminated string
+fn zstring(data: &[u8], encoding: TextEncoding) -> Option<String> {
+    let (&last, text) = data.split_last()?;
+    let printable = |x: &u8| *x >= 0x20 || *x == b'\t' || *x == b'\n' || *x == b'\r';
+    if last != 0 || text.is_empty() || !text.iter().all(printable) {
+        return None;
+    }
+    Some(encoding.decode_lossy(text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::records::{common::CommonRecordInfo, Record};
+    use bstr::ByteSlice;
+
+    #[test]
+    fn test_search_record() {
+        let record = GeneralRecord {
+            type_name: b"MISC".as_bstr(),
+            common: CommonRecordInfo::test_default(),
+            fields: vec![
+                GeneralField::new(b"EDID".as_bstr(), b"WhiterunKey\0"),
+                GeneralField::new(b"FULL".as_bstr(), &[7, 0, 0, 0]),
+                GeneralField::new(b"MODL".as_bstr(), b"Clutter\\whiterun.nif\0"),
+                GeneralField::new(b"DATA".as_bstr(), &[b'W', b'h', 0, 0]),
+            ],
+        };
+        let record = RecordRef::Unknown(&record);
+        let source = TextSource {
+            localized: false,
+            encoding: TextEncoding::default(),
+            strings: None,
+        };
+
+        let hits = search_record(record, "whiterun", source);
+        assert_eq!(hits.len(), 2);
+        assert_eq!(hits[0].kind, TextKind::EditorId);
+        assert_eq!(hits[0].text, "WhiterunKey");
+        assert_eq!(hits[1].field, "MODL");
+        assert_eq!(hits[1].field_index, 2);
+        assert_eq!(hits[1].kind, TextKind::ZString);
+        assert_eq!(hits[1].formid, FormId::new(0x420));
+
+        let strings = |index| match index {
+            7 => Some("Whiterun House Key".to_string()),
+            _ => None,
+        };
+        let localized = TextSource {
+            localized: true,
+            strings: Some(&strings),
+            ..source
+        };
+        let hits = search_record(record, "HOUSE", localized);
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].kind, TextKind::LString(7));
+        assert_eq!(hits[0].field, "FULL");
+        let without = TextSource {
+            strings: None,
+            ..localized
+        };
+        assert!(search_record(record, "house", without).is_empty());
+    }
+
+    #[test]
+    fn test_text_index() {
+        use crate::{fixtures, Top};
+        // The INFO is in a group that wasn't converted, with an lstring that only INFO has
+        let info = GeneralRecord {
+            type_name: b"INFO".as_bstr(),
+            common: CommonRecordInfo::test_default(),
+            fields: vec![GeneralField::new(b"NAM1".as_bstr(), &[3, 0, 0, 0])],
+        };
+        let misc = GeneralRecord {
+            type_name: b"MISC".as_bstr(),
+            common: CommonRecordInfo::test_default(),
+            fields: vec![GeneralField::new(
+                b"EDID".as_bstr(),
+                "Jarl'sKrügerBell\0".as_bytes(),
+            )],
+        };
+        let mut plugin = Plugin::new("Test.esp");
+        plugin.push_top(fixtures::top(b"DIAL", vec![info.into()]));
+        plugin.push_top(Top::Record(Record::Unknown(misc)));
+        plugin.encoding = TextEncoding::Utf8;
+        plugin.header_mut().unwrap().common.flags.flags |= record_flag::LOCALIZED;
+
+        let strings = |index| match index {
+            3 => Some("Greetings, Krüger".to_string()),
+            _ => None,
+        };
+        let index = plugin.text_index(Some(&strings));
+        let hits = index.search("KRÜGER");
+        assert_eq!(hits.len(), 2);
+        assert_eq!(hits[0].kind, TextKind::LString(3));
+        assert_eq!(hits[0].field, "NAM1");
+        assert_eq!(hits[1].text, "Jarl'sKrügerBell");
+        assert_eq!(index.search("greetings").len(), 1);
+        assert!(index.search("whiterun").is_empty());
+    }
+}
@@ -0,0 +1,286 @@
+// Snapshots of a plugin, for undo and for comparing against the state that was loaded.
+// A snapshot holds each top-level entry behind an Rc, and entries that are unchanged from the
+// snapshot before it are shared rather than cloned again, so a history of small edits to a large
+// plugin costs about one copy of the plugin plus the groups that were edited.
+// The plugin gives each of its entries a new revision whenever it is changed, so entries that
+// kept their revision are shared without comparing them.
+
+use crate::{plugin::Plugin, Top};
+use std::{
+    fmt::{self, Debug, Formatter},
+    rc::Rc,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// A revision that no other entry of any plugin has had
+fn next_revision() -> u64 {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    COUNTER.fetch_add(1, Ordering::Relaxed)
+}
+
+/// The revision of each of the top-level entries of a plugin, which changes whenever the entry
+/// may have been changed. Two entries with the same revision are the same, even in different
+/// plugins, as a clone keeps the revisions of what it was cloned from. Entries changed through
+/// [Plugin::tops_mut] can't be told apart, so they all get new revisions. It is left out when
+/// comparing plugins
+#[derive(Clone, Default)]
+pub(crate) struct Revisions {
+    /// None when every entry may have changed, until they are given new revisions
+    tops: Option<Vec<u64>>,
+}
+impl Revisions {
+    pub(crate) fn mark(&mut self, index: usize) {
+        if let Some(tops) = &mut self.tops {
+            let len = tops.len();
+            match tops.get_mut(index) {
+                Some(revision) => *revision = next_revision(),
+                None if index == len => tops.push(next_revision()),
+                None => self.tops = None,
+            }
+        }
+    }
+
+    pub(crate) fn mark_all(&mut self) {
+        self.tops = None;
+    }
+
+    /// Whether the entries have revisions, rather than all of them possibly having changed
+    pub(crate) fn is_known(&self) -> bool {
+        self.tops.is_some()
+    }
+
+    /// The revision of the entry at [index], None if it isn't known
+    pub(crate) fn get(&self, index: usize) -> Option<u64> {
+        self.tops.as_ref()?.get(index).copied()
+    }
+
+    /// The revisions of the [len] entries, giving each of them a new one if they aren't known
+    pub(crate) fn current(&mut self, len: usize) -> &[u64] {
+        let tops = self
+            .tops
+            .get_or_insert_with(|| (0..len).map(|_| next_revision()).collect());
+        tops.resize_with(len, next_revision);
+        tops
+    }
+}
+impl PartialEq for Revisions {
+    fn eq(&self, _other: &Revisions) -> bool {
+        true
+    }
+}
+impl Debug for Revisions {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str("Revisions")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot<'data> {
+    name: String,
+    tops: Vec<Rc<Top<'data>>>,
+    /// Revision of each of [tops], see [Revisions]
+    revisions: Vec<u64>,
+}
+impl<'data> Snapshot<'data> {
+    /// Copies the state of [plugin], sharing the entries that are the same as in [previous].
+    /// Entries that kept the revision they had in [previous] are shared without comparing them,
+    /// and the rest are compared only if [plugin] doesn't know which of them changed
+    pub fn take(plugin: &mut Plugin<'data>, previous: Option<&Snapshot<'data>>) -> Snapshot<'data> {
+        let known = plugin.revisions.is_known();
+        let len = plugin.tops().len();
+        let revisions = plugin.revisions.current(len).to_vec();
+        let tops = plugin
+            .tops()
+            .iter()
+            .zip(revisions.iter())
+            .enumerate()
+            .map(|(index, (top, revision))| {
+                let shared = previous.and_then(|x| Some((x.tops.get(index)?, x.revisions[index])));
+                match shared {
+                    Some((shared, previous)) if previous == *revision => shared.clone(),
+                    Some((shared, _)) if !known && **shared == *top => shared.clone(),
+                    _ => Rc::new(top.clone()),
+                }
+            })
+            .collect();
+        Snapshot {
+            name: plugin.name.clone(),
+            tops,
+            revisions,
+        }
+    }
+
+    /// The plugin as it was when the snapshot was taken
+    pub fn restore(&self) -> Plugin<'data> {
+        let mut plugin = Plugin::from_tops(
+            self.name.clone(),
+            self.tops.iter().map(|x| (**x).clone()).collect(),
+        );
+        plugin.revisions = self.revisions();
+        plugin
+    }
+
+    /// Returns [plugin] to the state of the snapshot, where [current] is a snapshot of it as it
+    /// is now, taken with this one as the previous. Only the entries that differ are copied
+    fn restore_into(&self, plugin: &mut Plugin<'data>, current: &Snapshot<'data>) {
+        plugin.name = self.name.clone();
+        let tops = plugin.tops_mut();
+        tops.truncate(self.tops.len());
+        for (index, top) in self.tops.iter().enumerate() {
+            match current.tops.get(index) {
+                Some(now) if Rc::ptr_eq(now, top) => {}
+                Some(_) => tops[index] = (**top).clone(),
+                None => tops.push((**top).clone()),
+            }
+        }
+        plugin.revisions = self.revisions();
+    }
+
+    /// [Revisions] of a plugin holding the entries of the snapshot
+    fn revisions(&self) -> Revisions {
+        Revisions {
+            tops: Some(self.revisions.clone()),
+        }
+    }
+
+    /// Positions of the top-level entries of [plugin] that differ from the snapshot, including
+    /// those that were added or removed. Entries that were changed since the snapshot are
+    /// counted whether or not they ended up different, unless [plugin] doesn't know which of
+    /// its entries changed, in which case they are compared
+    pub fn changed(&self, plugin: &Plugin<'data>) -> Vec<usize> {
+        let tops = plugin.tops();
+        let len = self.tops.len().max(tops.len());
+        (0..len)
+            .filter(|index| match (self.tops.get(*index), tops.get(*index)) {
+                (Some(_), Some(_))
+                    if plugin.revisions.get(*index) == Some(self.revisions[*index]) =>
+                {
+                    false
+                }
+                (Some(shared), Some(top)) => plugin.revisions.is_known() || **shared != *top,
+                _ => true,
+            })
+            .collect()
+    }
+
+    /// Amount of entries that are shared with [other] rather than copied
+    pub fn shared_with(&self, other: &Snapshot<'data>) -> usize {
+        self.tops
+            .iter()
+            .zip(other.tops.iter())
+            .filter(|(a, b)| Rc::ptr_eq(a, b))
+            .count()
+    }
+}
+
+/// Undo and redo history of a plugin
+#[derive(Debug, Clone, Default)]
+pub struct History<'data> {
+    undo: Vec<Snapshot<'data>>,
+    redo: Vec<Snapshot<'data>>,
+}
+impl<'data> History<'data> {
+    pub fn new() -> History<'data> {
+        History::default()
+    }
+
+    /// Records the state of [plugin] before it is changed. Clears the redo history
+    pub fn checkpoint(&mut self, plugin: &mut Plugin<'data>) {
+        let snapshot = Snapshot::take(plugin, self.undo.last());
+        self.undo.push(snapshot);
+        self.redo.clear();
+    }
+
+    /// Returns [plugin] to the last checkpoint. Returns false if there is none
+    pub fn undo(&mut self, plugin: &mut Plugin<'data>) -> bool {
+        match self.undo.pop() {
+            Some(snapshot) => {
+                let current = Snapshot::take(plugin, Some(&snapshot));
+                snapshot.restore_into(plugin, &current);
+                self.redo.push(current);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Reverts the last undo. Returns false if there is nothing to redo
+    pub fn redo(&mut self, plugin: &mut Plugin<'data>) -> bool {
+        match self.redo.pop() {
+            Some(snapshot) => {
+                let current = Snapshot::take(plugin, Some(&snapshot));
+                snapshot.restore_into(plugin, &current);
+                self.undo.push(current);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::records::{
+        common::{CommonRecordInfo, GeneralRecord},
+        Record,
+    };
+    use bstr::ByteSlice;
+
+    fn record(id: u32) -> Top<'static> {
+        let mut common = CommonRecordInfo::test_default();
+        common.id = id;
+        Top::Record(Record::Unknown(GeneralRecord {
+            type_name: b"MISC".as_bstr(),
+            common,
+            fields: Vec::new(),
+        }))
+    }
+
+    #[test]
+    fn test_history() {
+        let mut plugin = Plugin::from_tops(
+            "Test.esp".to_string(),
+            vec![record(1), record(2), record(3)],
+        );
+        let loaded = Snapshot::take(&mut plugin, None);
+        let mut history = History::new();
+
+        history.checkpoint(&mut plugin);
+        *plugin.top_mut(1).unwrap() = record(20);
+        assert_eq!(loaded.changed(&plugin), vec![1]);
+        assert_eq!(history.undo[0].changed(&plugin), vec![1]);
+        history.checkpoint(&mut plugin);
+        plugin.push_top(record(4));
+        assert_eq!(loaded.changed(&plugin), vec![1, 3]);
+        assert_eq!(history.undo[1].shared_with(&history.undo[0]), 2);
+
+        assert!(history.undo(&mut plugin));
+        assert_eq!(plugin.tops().len(), 3);
+        assert!(history.undo(&mut plugin));
+        assert!(loaded.changed(&plugin).is_empty());
+        assert!(!history.undo(&mut plugin));
+
+        assert!(history.redo(&mut plugin));
+        assert_eq!(plugin.tops()[1], record(20));
+        // The entries that weren't undone are still those of the later snapshot
+        assert_eq!(history.redo[0].shared_with(&history.undo[0]), 2);
+        history.checkpoint(&mut plugin);
+        assert!(!history.redo(&mut plugin));
+        assert_eq!(loaded.restore().tops(), [record(1), record(2), record(3)]);
+
+        // Entries that are taken to be changed are copied, even if they weren't. Those that may
+        // have been changed without being marked are compared
+        let last = Snapshot::take(&mut plugin, None);
+        plugin.top_mut(0);
+        assert_eq!(last.changed(&plugin), vec![0]);
+        let next = Snapshot::take(&mut plugin, Some(&last));
+        assert_eq!(next.shared_with(&last), 2);
+        plugin.tops_mut();
+        assert!(next.changed(&plugin).is_empty());
+        assert_eq!(
+            Snapshot::take(&mut plugin, Some(&next)).shared_with(&next),
+            3
+        );
+    }
+}
@@ -0,0 +1,669 @@
+// Holding many records without keeping them all in memory, for operations such as merging
+// several large masters. Records are stored serialized, and once there are more than the policy
+// allows in memory, the rest are written to a temporary file and read back when needed.
+// [merge_files] merges plugins this way, reading only one of them at a time.
+
+use crate::{
+    config::Config,
+    context::ParseContext,
+    edit::EditError,
+    error::{report, ErrorCode, VivecError},
+    groups::{
+        common::{GeneralGroup, GroupEntry},
+        ConflictPolicy,
+    },
+    load_order::{OpenError, PluginFile},
+    parse::Parse,
+    records::{
+        common::{FormId, GeneralRecord},
+        Record,
+    },
+    util::{Writable, WriteError},
+    GeneralTop, TopIterator,
+};
+use std::{
+    collections::HashMap,
+    convert::{TryFrom, TryInto},
+    fmt::{Display, Formatter},
+    fs::{File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// How much an operation may keep in memory
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResourcePolicy {
+    /// Records kept in memory before the rest are spilled to disk, None for no limit
+    pub max_records_in_memory: Option<usize>,
+    /// Directory for the temporary spill files
+    pub spill_dir: PathBuf,
+}
+impl Default for ResourcePolicy {
+    fn default() -> ResourcePolicy {
+        ResourcePolicy {
+            max_records_in_memory: None,
+            spill_dir: std::env::temp_dir(),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Location {
+    /// Index into the records held in memory
+    Memory(usize),
+    /// Offset and length within the spill file
+    Spilled(u64, usize),
+}
+
+/// Serialized records in the order they were added, following a [ResourcePolicy]
+#[derive(Debug)]
+pub struct RecordStore {
+    policy: ResourcePolicy,
+    memory: Vec<Vec<u8>>,
+    locations: Vec<Location>,
+    spill: Option<(PathBuf, File)>,
+    spill_len: u64,
+}
+impl RecordStore {
+    pub fn new(policy: ResourcePolicy) -> RecordStore {
+        RecordStore {
+            policy,
+            memory: Vec::new(),
+            locations: Vec::new(),
+            spill: None,
+            spill_len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.locations.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.locations.is_empty()
+    }
+
+    /// Amount of records that were written to the spill file
+    pub fn spilled(&self) -> usize {
+        self.locations.len() - self.memory.len()
+    }
+
+    /// Size of the serialized record at [index]
+    pub fn size(&self, index: usize) -> Option<usize> {
+        match self.locations.get(index)? {
+            Location::Memory(index) => Some(self.memory[*index].len()),
+            Location::Spilled(_, len) => Some(*len),
+        }
+    }
+
+    /// Serializes [record] and stores it, returning its index
+    pub fn push<W: Writable>(&mut self, record: &W) -> std::io::Result<usize> {
+        let mut data = Vec::new();
+        record.write_to(&mut data)?;
+        self.push_bytes(data)
+    }
+
+    /// Stores an already serialized record, returning its index
+    pub fn push_bytes(&mut self, data: Vec<u8>) -> std::io::Result<usize> {
+        let in_memory = self
+            .policy
+            .max_records_in_memory
+            .map_or(true, |max| self.memory.len() < max);
+        let location = if in_memory {
+            self.memory.push(data);
+            Location::Memory(self.memory.len() - 1)
+        } else {
+            let offset = self.spill_len;
+            let file = self.spill_file()?;
+            file.seek(SeekFrom::Start(offset))?;
+            file.write_all(&data)?;
+            self.spill_len += data.len() as u64;
+            Location::Spilled(offset, data.len())
+        };
+        self.locations.push(location);
+        Ok(self.locations.len() - 1)
+    }
+
+    /// The serialized record at [index], which can be read with `GeneralRecord::parse`
+    pub fn get(&mut self, index: usize) -> std::io::Result<Option<Vec<u8>>> {
+        match self.locations.get(index) {
+            Some(Location::Memory(index)) => Ok(Some(self.memory[*index].clone())),
+            Some(&Location::Spilled(offset, len)) => {
+                let file = self.spill_file()?;
+                file.seek(SeekFrom::Start(offset))?;
+                let mut data = vec![0; len];
+                file.read_exact(&mut data)?;
+                Ok(Some(data))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Writes every record out in order, reading the spilled ones back a record at a time
+    pub fn write_all_to<T: Write>(&mut self, w: &mut T) -> std::io::Result<()> {
+        for index in 0..self.len() {
+            if let Some(data) = self.get(index)? {
+                w.write_all(&data)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn spill_file(&mut self) -> std::io::Result<&mut File> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        if self.spill.is_none() {
+            let path = self.policy.spill_dir.join(format!(
+                "vivec_spill_{}_{}",
+                std::process::id(),
+                COUNTER.fetch_add(1, Ordering::Relaxed)
+            ));
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create_new(true)
+                .open(&path)?;
+            self.spill = Some((path, file));
+        }
+        Ok(&mut self.spill.as_mut().unwrap().1)
+    }
+}
+impl Drop for RecordStore {
+    fn drop(&mut self) {
+        if let Some((path, _)) = self.spill.take() {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum MergeFilesError {
+    Open(OpenError<'static>),
+    /// The plugin [name] failed to parse at [offset]. The error borrows from the plugin's data,
+    /// which is gone by the time it is returned, so only its code and report are kept
+    Parse {
+        name: String,
+        offset: usize,
+        code: ErrorCode,
+        message: String,
+    },
+    Edit(EditError),
+    Write(WriteError),
+}
+impl Display for MergeFilesError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MergeFilesError::Open(error) => error.fmt(f),
+            MergeFilesError::Parse {
+                name,
+                offset,
+                message,
+                ..
+            } => write!(
+                f,
+                "Failed to parse {} at offset {}: {}",
+                name, offset, message
+            ),
+            MergeFilesError::Edit(error) => error.fmt(f),
+            MergeFilesError::Write(error) => error.fmt(f),
+        }
+    }
+}
+impl std::error::Error for MergeFilesError {}
+impl VivecError for MergeFilesError {
+    fn code(&self) -> ErrorCode {
+        match self {
+            MergeFilesError::Open(error) => error.code(),
+            MergeFilesError::Parse { code, .. } => *code,
+            MergeFilesError::Edit(error) => error.code(),
+            MergeFilesError::Write(error) => error.code(),
+        }
+    }
+
+    fn inner(&self) -> Option<&dyn VivecError> {
+        match self {
+            MergeFilesError::Open(error) => error.inner(),
+            MergeFilesError::Parse { .. } => None,
+            MergeFilesError::Edit(error) => error.inner(),
+            MergeFilesError::Write(error) => error.inner(),
+        }
+    }
+}
+impl From<EditError> for MergeFilesError {
+    fn from(error: EditError) -> MergeFilesError {
+        MergeFilesError::Edit(error)
+    }
+}
+impl From<WriteError> for MergeFilesError {
+    fn from(error: WriteError) -> MergeFilesError {
+        MergeFilesError::Write(error)
+    }
+}
+impl From<std::io::Error> for MergeFilesError {
+    fn from(error: std::io::Error) -> MergeFilesError {
+        MergeFilesError::Write(WriteError::Io(error))
+    }
+}
+
+/// What a record or group is known by when merging
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+enum EntryKey {
+    Record(FormId),
+    /// The label and type of the group, as they are written in its header
+    Group([u8; 8]),
+}
+
+#[derive(Debug)]
+enum MergedEntry {
+    /// Index of the record in the [RecordStore]
+    Record(usize),
+    Group(MergedGroup),
+}
+
+/// A group being merged, whose records are held by a [RecordStore]
+#[derive(Debug, Default)]
+struct MergedGroup {
+    /// Header of the group, whose size is filled in when it is written. Empty for the top level
+    header: Vec<u8>,
+    entries: Vec<MergedEntry>,
+    /// Position of each entry within [entries]
+    positions: HashMap<EntryKey, usize>,
+}
+impl MergedGroup {
+    /// Merges [entry] into this group, recursing into groups with the same type and label
+    fn merge(
+        &mut self,
+        entry: GroupEntry<'_>,
+        store: &mut RecordStore,
+        policy: ConflictPolicy,
+    ) -> Result<(), MergeFilesError> {
+        let (key, group) = match entry {
+            GroupEntry::Group(group) => {
+                let mut header = Vec::new();
+                GeneralGroup {
+                    entries: Vec::new(),
+                    ..group
+                }
+                .write_to(&mut header)?;
+                let key = EntryKey::Group(header[8..16].try_into().unwrap());
+                (key, Ok((header, group.entries)))
+            }
+            // Records always have an id
+            record => (EntryKey::Record(record.formid().unwrap()), Err(record)),
+        };
+        match (group, self.positions.get(&key)) {
+            (Ok((_, entries)), Some(&position)) => {
+                if let MergedEntry::Group(merged) = &mut self.entries[position] {
+                    for entry in entries {
+                        merged.merge(entry, store, policy)?;
+                    }
+                }
+            }
+            (Ok((header, entries)), None) => {
+                let mut merged = MergedGroup {
+                    header,
+                    ..MergedGroup::default()
+                };
+                for entry in entries {
+                    merged.merge(entry, store, policy)?;
+                }
+                self.push(key, MergedEntry::Group(merged));
+            }
+            (Err(record), Some(&position)) => match policy {
+                ConflictPolicy::KeepLast => {
+                    self.entries[position] = MergedEntry::Record(store.push(&record)?)
+                }
+                ConflictPolicy::KeepFirst => {}
+                ConflictPolicy::Error => {
+                    return Err(EditError::Exists(record.formid().unwrap()).into())
+                }
+            },
+            (Err(record), None) => {
+                let index = store.push(&record)?;
+                self.push(key, MergedEntry::Record(index));
+            }
+        }
+        Ok(())
+    }
+
+    fn push(&mut self, key: EntryKey, entry: MergedEntry) {
+        self.positions.insert(key, self.entries.len());
+        self.entries.push(entry);
+    }
+
+    /// Size of the group, including its header
+    fn size(&self, store: &RecordStore) -> usize {
+        let entries: usize = self
+            .entries
+            .iter()
+            .map(|entry| match entry {
+                MergedEntry::Record(index) => store.size(*index).unwrap_or(0),
+                MergedEntry::Group(group) => group.size(store),
+            })
+            .sum();
+        self.header.len() + entries
+    }
+
+    /// Amount of records and groups within this group, as (records, groups)
+    fn count(&self) -> (usize, usize) {
+        self.entries
+            .iter()
+            .map(|entry| match entry {
+                MergedEntry::Record(_) => (1, 0),
+                MergedEntry::Group(group) => {
+                    let (records, groups) = group.count();
+                    (records, groups + 1)
+                }
+            })
+            .fold((0, 0), |(a, b), (c, d)| (a + c, b + d))
+    }
+
+    fn write_to<W: Write>(&self, store: &mut RecordStore, w: &mut W) -> Result<(), WriteError> {
+        if !self.header.is_empty() {
+            let size = self.size(store);
+            let size = u32::try_from(size).map_err(|_| WriteError::GroupTooLarge { size })?;
+            w.write_all(&self.header[..4])?;
+            w.write_all(&size.to_le_bytes())?;
+            w.write_all(&self.header[8..])?;
+        }
+        for entry in self.entries.iter() {
+            match entry {
+                MergedEntry::Record(index) => {
+                    if let Some(data) = store.get(*index)? {
+                        w.write_all(&data)?;
+                    }
+                }
+                MergedEntry::Group(group) => group.write_to(store, w)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Merges the plugins at [paths] into one written to [w], as [crate::plugin::Plugin::merge]
+/// would, without holding them all in memory. Only one plugin is read at a time, and the records
+/// taken from it are kept serialized in a [RecordStore] following [policy]. Groups are merged
+/// by their type and label, and records by their form id, with [conflicts] deciding which version
+/// is kept. The records aren't converted, and so are written as they were read.
+/// The TES4 record is that of the first plugin, with its record count and next object id
+/// refreshed. Every plugin should list the same masters in the same order.
+/// Returns the amount of records written, apart from the TES4 record
+pub fn merge_files<P, W>(
+    paths: &[P],
+    policy: ResourcePolicy,
+    conflicts: ConflictPolicy,
+    w: &mut W,
+) -> Result<usize, MergeFilesError>
+where
+    P: AsRef<Path>,
+    W: Write,
+{
+    let mut store = RecordStore::new(policy);
+    let mut merged = MergedGroup::default();
+    let mut header: Option<Vec<u8>> = None;
+    let mut masters: Option<Vec<String>> = None;
+    let mut next_object_id = 0;
+    for path in paths {
+        let file = PluginFile::read(path).map_err(MergeFilesError::Open)?;
+        let mut tops = TopIterator::new(&file.data);
+        loop {
+            let offset = tops.offset();
+            let top = match tops.next() {
+                Some(top) => top,
+                None => break,
+            };
+            let parse_error = |error: &dyn VivecError| MergeFilesError::Parse {
+                name: file.name.clone(),
+                offset,
+                code: error.code(),
+                message: report(error),
+            };
+            let entry = match top.map_err(|error| parse_error(&error.error))? {
+                GeneralTop::Record(record) if record.type_name == b"TES4".as_slice() => {
+                    let mut data = Vec::new();
+                    record.write_to(&mut data)?;
+                    let tes4 = match Record::from_general(record, &mut ParseContext::default()) {
+                        Ok(Record::TES4(tes4)) => tes4,
+                        Ok(_) => continue,
+                        Err(error) => return Err(parse_error(&error)),
+                    };
+                    let names: Vec<String> = tes4
+                        .masters()
+                        .map(|x| x.filenames().map(|x| x.to_string()).collect())
+                        .unwrap_or_default();
+                    match &masters {
+                        Some(masters) => {
+                            if let Some((_, name)) = names.iter().enumerate().find(|(index, x)| {
+                                masters
+                                    .get(*index)
+                                    .map_or(true, |y| !y.eq_ignore_ascii_case(x))
+                            }) {
+                                return Err(EditError::UnknownMaster(name.clone()).into());
+                            }
+                        }
+                        None => masters = Some(names),
+                    }
+                    next_object_id = next_object_id.max(tes4.header().next_object_id);
+                    header.get_or_insert(data);
+                    continue;
+                }
+                GeneralTop::Record(record) => GroupEntry::Record(record),
+                GeneralTop::Group(group) => GroupEntry::Group(group),
+            };
+            merged.merge(entry, &mut store, conflicts)?;
+        }
+    }
+
+    let (records, groups) = merged.count();
+    // The header was written out as it was read, so it converts as it did then
+    if let Some(Ok((_, record))) = header.as_deref().map(GeneralRecord::parse) {
+        if let Ok(Record::TES4(mut tes4)) =
+            Record::from_general(record, &mut ParseContext::default())
+        {
+            let hedr = tes4.header_mut();
+            hedr.record_count = (records + groups) as u32;
+            hedr.next_object_id = next_object_id;
+            tes4.write_to(w)?;
+        }
+    }
+    merged.write_to(&mut store, w)?;
+    Ok(records)
+}
+
+/// `merge [--max-records N] <output> <plugins...>`, merging the plugins in order into the output
+pub fn merge_command(args: &[String], config: &Config) {
+    let mut policy = ResourcePolicy::default();
+    let mut paths = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--max-records" => {
+                let max = args
+                    .next()
+                    .and_then(|x| x.parse().ok())
+                    .expect("Expected an amount of records after --max-records");
+                policy.max_records_in_memory = Some(max);
+            }
+            _ => paths.push(arg),
+        }
+    }
+    if paths.len() < 2 {
+        panic!("Expected an output plugin followed by the plugins to merge");
+    }
+
+    let plugins: Vec<PathBuf> = paths[1..].iter().map(|x| config.plugin_path(x)).collect();
+    let mut output = Vec::new();
+    let records = merge_files(&plugins, policy, ConflictPolicy::default(), &mut output)
+        .unwrap_or_else(|e| panic!("{}", report(&e)));
+    std::fs::write(config.output_path(paths[0]), output).expect("Failed to write merged file");
+    println!("Merged {} records from {} plugins", records, plugins.len());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        groups::common::GroupType,
+        records::{common::CommonRecordInfo, fields::common::GeneralField, tes4::TES4Record},
+    };
+    use bstr::ByteSlice;
+
+    fn misc(id: u32, name: &[u8]) -> GroupEntry<'_> {
+        let mut common = CommonRecordInfo::test_default();
+        common.id = id;
+        GroupEntry::Record(GeneralRecord {
+            type_name: b"MISC".as_bstr(),
+            common,
+            fields: vec![GeneralField::new(b"EDID".as_bstr(), name)],
+        })
+    }
+
+    fn group<'data>(
+        group_type: GroupType<'data>,
+        entries: Vec<GroupEntry<'data>>,
+    ) -> GroupEntry<'data> {
+        GroupEntry::Group(GeneralGroup::new(group_type, entries))
+    }
+
+    #[test]
+    fn test_record_store() {
+        let names: Vec<Vec<u8>> = (0..5)
+            .map(|i| format!("Record{}\0", i).into_bytes())
+            .collect();
+        let records: Vec<GeneralRecord> = names
+            .iter()
+            .map(|name| GeneralRecord {
+                type_name: b"MISC".as_bstr(),
+                common: CommonRecordInfo::test_default(),
+                fields: vec![GeneralField::new(b"EDID".as_bstr(), name)],
+            })
+            .collect();
+
+        let mut store = RecordStore::new(ResourcePolicy {
+            max_records_in_memory: Some(2),
+            ..ResourcePolicy::default()
+        });
+        for record in records.iter() {
+            store.push(record).unwrap();
+        }
+        assert_eq!(store.len(), 5);
+        assert_eq!(store.spilled(), 3);
+        let path = store.spill.as_ref().unwrap().0.clone();
+        assert!(path.exists());
+
+        for (index, record) in records.iter().enumerate().rev() {
+            let data = store.get(index).unwrap().unwrap();
+            let (_, parsed) = GeneralRecord::parse(&data).unwrap();
+            assert_eq!(&parsed, record);
+        }
+        assert_eq!(store.get(5).unwrap(), None);
+
+        let mut expected = Vec::new();
+        for record in records.iter() {
+            record.write_to(&mut expected).unwrap();
+        }
+        let mut written = Vec::new();
+        store.write_all_to(&mut written).unwrap();
+        assert_eq!(written, expected);
+
+        drop(store);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_merge_files() {
+        let dir = std::env::temp_dir().join(format!("vivec_merge_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let plugins = [
+            vec![
+                group(
+                    GroupType::Top(b"MISC".as_bstr()),
+                    vec![misc(0x800, b"A\0"), misc(0x801, b"B\0")],
+                ),
+                group(
+                    GroupType::Top(b"CELL".as_bstr()),
+                    vec![group(
+                        GroupType::InteriorCellBlock(0),
+                        vec![misc(0x900, b"Cell\0")],
+                    )],
+                ),
+            ],
+            vec![
+                group(
+                    GroupType::Top(b"MISC".as_bstr()),
+                    vec![misc(0x801, b"B2\0"), misc(0x802, b"C\0")],
+                ),
+                group(
+                    GroupType::Top(b"CELL".as_bstr()),
+                    vec![group(
+                        GroupType::InteriorCellBlock(0),
+                        vec![misc(0x901, b"Cell2\0")],
+                    )],
+                ),
+            ],
+        ];
+        let mut paths = Vec::new();
+        for (index, entries) in plugins.iter().enumerate() {
+            let mut data = Vec::new();
+            TES4Record::new().write_to(&mut data).unwrap();
+            entries.write_to(&mut data).unwrap();
+            let path = dir.join(format!("Plugin{}.esp", index));
+            std::fs::write(&path, data).unwrap();
+            paths.push(path);
+        }
+
+        let policy = ResourcePolicy {
+            max_records_in_memory: Some(1),
+            spill_dir: dir.clone(),
+        };
+        let mut data = Vec::new();
+        let records = merge_files(&paths, policy.clone(), ConflictPolicy::default(), &mut data);
+        assert_eq!(records.unwrap(), 5);
+        let tops: Vec<GeneralTop> = TopIterator::new(&data).map(Result::unwrap).collect();
+        match &tops[0] {
+            GeneralTop::Record(record) => {
+                let header = Record::from_general(record.clone(), &mut ParseContext::default());
+                match header.unwrap() {
+                    // 5 records and 3 groups
+                    Record::TES4(header) => assert_eq!(header.header().record_count, 8),
+                    x => panic!("Unexpected record {:?}", x),
+                }
+            }
+            x => panic!("Unexpected entry {:?}", x),
+        }
+        let expected = vec![
+            group(
+                GroupType::Top(b"MISC".as_bstr()),
+                vec![
+                    misc(0x800, b"A\0"),
+                    misc(0x801, b"B2\0"),
+                    misc(0x802, b"C\0"),
+                ],
+            ),
+            group(
+                GroupType::Top(b"CELL".as_bstr()),
+                vec![group(
+                    GroupType::InteriorCellBlock(0),
+                    vec![misc(0x900, b"Cell\0"), misc(0x901, b"Cell2\0")],
+                )],
+            ),
+        ];
+        let entries: Vec<GroupEntry> = tops[1..]
+            .iter()
+            .map(|top| match top {
+                GeneralTop::Group(group) => GroupEntry::Group(group.clone()),
+                GeneralTop::Record(record) => GroupEntry::Record(record.clone()),
+            })
+            .collect();
+        assert_eq!(entries, expected);
+
+        let mut data = Vec::new();
+        match merge_files(&paths, policy, ConflictPolicy::Error, &mut data) {
+            Err(MergeFilesError::Edit(EditError::Exists(id))) => assert_eq!(id, FormId::new(0x801)),
+            x => panic!("Unexpected result {:?}", x),
+        }
+        // The spill files are removed once the merge is done
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,109 @@
+// Loose record streams: records one after another with no groups around them, and no TES4
+// header. Some tools emit these, and save game change forms hold similar bodies, so this allows
+// working on such fragments without a whole plugin.
+
+use crate::{
+    context::ParseContext,
+    parse::{many_indexed, PResult, Parse, ParseError},
+    records::{
+        common::{FromRecordError, GeneralRecord},
+        Record,
+    },
+    util::{Writable, WriteError},
+};
+use std::io::Write;
+
+/// Parses every record in [data]. A group anywhere in it is an error, reported as the
+/// [ParseError::Element] that it is
+pub fn parse_record_stream(data: &[u8]) -> PResult<'_, Vec<GeneralRecord<'_>>> {
+    many_indexed(data, |data| {
+        if data.starts_with(b"GRUP") {
+            Err(ParseError::UnexpectedGroup)
+        } else {
+            GeneralRecord::parse(data)
+        }
+    })
+}
+
+/// [parse_record_stream], converting each record into its typed form
+pub fn parse_record_stream_with<'data>(
+    data: &'data [u8],
+    ctx: &mut ParseContext,
+) -> Result<Vec<Record<'data>>, FromRecordError<'data>> {
+    let (_, records) = parse_record_stream(data)?;
+    let mut converted = Vec::with_capacity(records.len());
+    for mut record in records {
+        ctx.enter_record(&record);
+        let mut record = if ctx.apply_field_rules(&mut record) {
+            Record::Unknown(record)
+        } else {
+            Record::from_general(record, ctx)?
+        };
+        if ctx.hooks.run(&mut record) {
+            converted.push(record);
+        }
+    }
+    ctx.leave_record();
+    Ok(converted)
+}
+
+/// Writes [records] one after another, without any groups
+pub fn write_record_stream<T, W>(records: &[T], w: &mut W) -> Result<(), WriteError>
+where
+    T: Writable,
+    W: Write,
+{
+    for record in records {
+        record.write_to(w)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::records::{common::CommonRecordInfo, fields::common::GeneralField};
+    use bstr::ByteSlice;
+
+    #[test]
+    fn test_record_stream() {
+        let mut common = CommonRecordInfo::test_default();
+        common.flags.flags = 0;
+        let records = vec![
+            GeneralRecord {
+                type_name: b"AACT".as_bstr(),
+                common: common.clone(),
+                fields: vec![GeneralField::new(b"EDID".as_bstr(), b"Action\0")],
+            },
+            GeneralRecord {
+                type_name: b"MISC".as_bstr(),
+                common,
+                fields: Vec::new(),
+            },
+        ];
+        let mut data = Vec::new();
+        write_record_stream(&records, &mut data).unwrap();
+
+        let (rest, parsed) = parse_record_stream(&data).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(parsed, records);
+
+        let typed = parse_record_stream_with(&data, &mut ParseContext::default()).unwrap();
+        assert!(matches!(typed[..], [Record::AACT(_), Record::Unknown(_)]));
+        let mut written = Vec::new();
+        write_record_stream(&typed, &mut written).unwrap();
+        assert_eq!(written, data);
+
+        let offset = data.len();
+        data.extend_from_slice(b"GRUP");
+        data.extend_from_slice(&[0; 20]);
+        assert_eq!(
+            parse_record_stream(&data),
+            Err(ParseError::Element {
+                index: 2,
+                offset,
+                error: Box::new(ParseError::UnexpectedGroup),
+            })
+        );
+    }
+}
@@ -0,0 +1,610 @@
+// The string tables of localized plugins. A localized plugin keeps the text of its LString fields
+// in three files per language within the Strings folder of the Data folder, named like
+// `Skyrim_english.STRINGS`. Each starts with the amount of strings and the size of the string
+// data, then a directory of (id, offset) pairs.
+// Offsets in the directory are from the start of the string data, which follows the directory.
+// Languages are compared by the ids that their directories hold, as a language that is missing
+// ids shows the text as blank in game.
+
+use crate::{
+    error::{ErrorCode, VivecError},
+    parse::{many, take, PResult, Parse, ParseError},
+    plugin::Plugin,
+    records::{common::lstring::LString, fields::common::GeneralField},
+    util::{length_prefix, DataSize, Writable, WriteError},
+};
+use bstr::{BStr, BString, ByteSlice};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt::{Display, Formatter},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+/// The language used when none is chosen
+pub const DEFAULT_LANGUAGE: &str = "english";
+
+/// Which of the three string tables of a language
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum StringsKind {
+    /// Names and other short text, such as FULL
+    Strings,
+    /// Descriptions, such as DESC, whose strings are prefixed by their length
+    DlStrings,
+    /// Dialogue, such as INFO NAM1, whose strings are prefixed by their length
+    IlStrings,
+}
+impl StringsKind {
+    pub const ALL: [StringsKind; 3] = [
+        StringsKind::Strings,
+        StringsKind::DlStrings,
+        StringsKind::IlStrings,
+    ];
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            StringsKind::Strings => "STRINGS",
+            StringsKind::DlStrings => "DLSTRINGS",
+            StringsKind::IlStrings => "ILSTRINGS",
+        }
+    }
+
+    fn from_extension(extension: &str) -> Option<StringsKind> {
+        StringsKind::ALL
+            .iter()
+            .copied()
+            .find(|x| x.extension().eq_ignore_ascii_case(extension))
+    }
+
+    /// Whether the strings of this table are prefixed by their length, including the null
+    pub fn is_length_prefixed(self) -> bool {
+        self != StringsKind::Strings
+    }
+}
+
+/// Where the game looks for the [kind] table of [plugin] in [language]
+pub fn strings_path(
+    data_folder: &Path,
+    plugin: &str,
+    language: &str,
+    kind: StringsKind,
+) -> PathBuf {
+    data_folder.join("Strings").join(format!(
+        "{}_{}.{}",
+        plugin_stem(plugin),
+        language,
+        kind.extension()
+    ))
+}
+
+fn plugin_stem(plugin: &str) -> &str {
+    match plugin.rfind('.') {
+        Some(dot) => &plugin[..dot],
+        None => plugin,
+    }
+}
+
+/// The [kind] table of [plugin] in [language] as it is named within [data_folder], ignoring case
+/// as the game does. None if there isn't one
+pub fn find_strings_file(
+    data_folder: &Path,
+    plugin: &str,
+    language: &str,
+    kind: StringsKind,
+) -> std::io::Result<Option<PathBuf>> {
+    let path = strings_path(data_folder, plugin, language, kind);
+    if path.is_file() {
+        return Ok(Some(path));
+    }
+    let dir = data_folder.join("Strings");
+    if !dir.is_dir() {
+        return Ok(None);
+    }
+    let name = path.file_name().unwrap_or_default();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_name().eq_ignore_ascii_case(name) {
+            return Ok(Some(entry.path()));
+        }
+    }
+    Ok(None)
+}
+
+/// The languages that [plugin] has any string tables for in [data_folder], lowercase and sorted
+pub fn languages(data_folder: &Path, plugin: &str) -> std::io::Result<Vec<String>> {
+    let dir = data_folder.join("Strings");
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let prefix = format!("{}_", plugin_stem(plugin)).to_ascii_lowercase();
+    let mut languages = BTreeSet::new();
+    for entry in std::fs::read_dir(dir)? {
+        let name = entry?.file_name().to_string_lossy().to_ascii_lowercase();
+        let (stem, extension) = match name.rsplit_once('.') {
+            Some(x) => x,
+            None => continue,
+        };
+        if StringsKind::from_extension(extension).is_none() {
+            continue;
+        }
+        if let Some(language) = stem.strip_prefix(&prefix) {
+            if !language.is_empty() {
+                languages.insert(language.to_string());
+            }
+        }
+    }
+    Ok(languages.into_iter().collect())
+}
+
+/// The ids in the directory of the string table [data]
+pub fn string_ids(data: &[u8]) -> Result<Vec<u32>, ParseError<'_>> {
+    let (rest, count) = u32::parse(data)?;
+    let (rest, _data_size) = u32::parse(rest)?;
+    let (_, directory) = take(rest, count as usize * 8)?;
+    let (_, entries) = many(directory, |data| -> PResult<u32> {
+        let (data, id) = u32::parse(data)?;
+        let (data, _offset) = u32::parse(data)?;
+        Ok((data, id))
+    })?;
+    Ok(entries)
+}
+
+/// The strings of one table, by id. Strings are kept without their null terminator
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct StringTable {
+    pub kind: StringsKind,
+    pub strings: BTreeMap<u32, BString>,
+}
+impl StringTable {
+    pub fn new(kind: StringsKind) -> StringTable {
+        StringTable {
+            kind,
+            strings: BTreeMap::new(),
+        }
+    }
+
+    /// Parses the table [data], which is of [kind]
+    pub fn parse(data: &[u8], kind: StringsKind) -> Result<StringTable, ParseError<'_>> {
+        let (rest, count) = u32::parse(data)?;
+        let (rest, data_size) = u32::parse(rest)?;
+        let (rest, directory) = take(rest, count as usize * 8)?;
+        let (_, string_data) = take(rest, data_size as usize)?;
+        let (_, entries) = many(directory, |data| -> PResult<(u32, u32)> {
+            let (data, id) = u32::parse(data)?;
+            let (data, offset) = u32::parse(data)?;
+            Ok((data, (id, offset)))
+        })?;
+
+        let mut table = StringTable::new(kind);
+        for (id, offset) in entries {
+            let data = string_data
+                .get(offset as usize..)
+                .ok_or(ParseError::UnexpectedEOF)?;
+            let data = if kind.is_length_prefixed() {
+                let (data, length) = u32::parse(data)?;
+                let (_, data) = take(data, length as usize)?;
+                data
+            } else {
+                data
+            };
+            let end = data.find_byte(0).ok_or(ParseError::UnexpectedEOF)?;
+            table.strings.insert(id, BString::from(&data[..end]));
+        }
+        Ok(table)
+    }
+
+    pub fn get(&self, id: u32) -> Option<&BStr> {
+        self.strings.get(&id).map(|x| x.as_bstr())
+    }
+
+    /// Sets the string with [id], returning the one it replaced
+    pub fn insert<S: Into<BString>>(&mut self, id: u32, text: S) -> Option<BString> {
+        self.strings.insert(id, text.into())
+    }
+
+    /// The string data as it is written, pushing the id and offset of each string onto
+    /// [directory]. Identical strings are written once and share their offset, as the game's
+    /// tables do
+    fn string_data(&self, directory: &mut Vec<(u32, u32)>) -> Result<Vec<u8>, WriteError> {
+        let mut data = Vec::new();
+        let mut offsets: HashMap<&BStr, u32> = HashMap::new();
+        for (id, text) in self.strings.iter() {
+            let text = text.as_bstr();
+            let offset = match offsets.get(text) {
+                Some(offset) => *offset,
+                None => {
+                    let offset = length_prefix::<u32>("String table", data.len())?;
+                    if self.kind.is_length_prefixed() {
+                        length_prefix::<u32>("String", text.len() + 1)?.write_to(&mut data)?;
+                    }
+                    data.extend_from_slice(text);
+                    data.push(0);
+                    offsets.insert(text, offset);
+                    offset
+                }
+            };
+            directory.push((*id, offset));
+        }
+        Ok(data)
+    }
+}
+impl DataSize for StringTable {
+    fn data_size(&self) -> usize {
+        let prefix = if self.kind.is_length_prefixed() { 4 } else { 0 };
+        let mut seen = BTreeSet::new();
+        let strings: usize = self
+            .strings
+            .values()
+            .filter(|x| seen.insert(x.as_bstr()))
+            .map(|x| prefix + x.len() + 1)
+            .sum();
+        8 + self.strings.len() * 8 + strings
+    }
+}
+impl Writable for StringTable {
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
+    where
+        T: Write,
+    {
+        let mut directory = Vec::with_capacity(self.strings.len());
+        let data = self.string_data(&mut directory)?;
+        length_prefix::<u32>("String table", directory.len())?.write_to(w)?;
+        length_prefix::<u32>("String table", data.len())?.write_to(w)?;
+        for (id, offset) in directory {
+            id.write_to(w)?;
+            offset.write_to(w)?;
+        }
+        w.write_all(&data)?;
+        Ok(())
+    }
+}
+
+/// The string tables of a plugin in one language, as they were read
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct StringFiles {
+    pub language: String,
+    /// Contents of the table of each kind, in the order of [StringsKind::ALL].
+    /// None if the file doesn't exist
+    pub tables: [Option<Vec<u8>>; 3],
+}
+impl StringFiles {
+    /// Reads the tables of [plugin] in [language]. Tables that don't exist are None
+    pub fn read(data_folder: &Path, plugin: &str, language: &str) -> std::io::Result<StringFiles> {
+        let mut files = StringFiles {
+            language: language.to_ascii_lowercase(),
+            tables: [None, None, None],
+        };
+        for (kind, table) in StringsKind::ALL.iter().zip(files.tables.iter_mut()) {
+            if let Some(path) = find_strings_file(data_folder, plugin, language, *kind)? {
+                *table = Some(std::fs::read(path)?);
+            }
+        }
+        Ok(files)
+    }
+
+    pub fn table(&self, kind: StringsKind) -> Option<&[u8]> {
+        let index = StringsKind::ALL.iter().position(|x| *x == kind)?;
+        self.tables[index].as_deref()
+    }
+
+    /// Parses the table of [kind], None if it doesn't exist
+    pub fn string_table(&self, kind: StringsKind) -> Option<Result<StringTable, ParseError<'_>>> {
+        self.table(kind).map(|data| StringTable::parse(data, kind))
+    }
+
+    /// The ids held by each table, which are empty for tables that don't exist
+    pub fn ids(&self) -> Result<BTreeSet<(StringsKind, u32)>, ParseError<'_>> {
+        let mut ids = BTreeSet::new();
+        for kind in StringsKind::ALL.iter().copied() {
+            if let Some(table) = self.table(kind) {
+                ids.extend(string_ids(table)?.into_iter().map(|id| (kind, id)));
+            }
+        }
+        Ok(ids)
+    }
+
+    /// Compares the ids of this language against those of [other], such as a translation
+    /// against the original
+    pub fn coverage<'a>(
+        &'a self,
+        other: &'a StringFiles,
+    ) -> Result<LanguageCoverage, ParseError<'a>> {
+        let ids = self.ids()?;
+        let other_ids = other.ids()?;
+        Ok(LanguageCoverage {
+            language: other.language.clone(),
+            shared: ids.intersection(&other_ids).count(),
+            missing: ids.difference(&other_ids).copied().collect(),
+            extra: other_ids.difference(&ids).copied().collect(),
+        })
+    }
+}
+
+/// Where the text of a plugin's lstrings is, see [Plugin::localization]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Localization {
+    /// The plugin isn't localized, so each lstring is a null terminated string in its field
+    Inline,
+    /// The string tables of the plugin in [language], None for those that don't exist
+    Tables {
+        language: String,
+        tables: [Option<StringTable>; 3],
+    },
+}
+impl Localization {
+    pub fn table(&self, kind: StringsKind) -> Option<&StringTable> {
+        match self {
+            Localization::Inline => None,
+            Localization::Tables { tables, .. } => {
+                let index = StringsKind::ALL.iter().position(|x| *x == kind)?;
+                tables[index].as_ref()
+            }
+        }
+    }
+
+    /// The text of [lstring]: looked up in the table of [kind] if it is an index, or else the
+    /// text that it holds
+    pub fn resolve<'a>(&'a self, lstring: &'a LString<'_>, kind: StringsKind) -> Option<&'a BStr> {
+        match lstring {
+            LString::Index(_) => lstring.resolve(self.table(kind)?),
+            LString::Inline(text) => Some(text.value.as_ref()),
+        }
+    }
+
+    /// The text of the lstring [field]: looked up in the table of [kind] if the plugin is
+    /// localized, or else the string that the field holds
+    pub fn field_text<'a>(
+        &'a self,
+        field: &GeneralField<'a>,
+        kind: StringsKind,
+    ) -> Option<&'a BStr> {
+        match self {
+            Localization::Inline => {
+                let end = field.data.find_byte(0).unwrap_or(field.data.len());
+                Some(field.data[..end].as_bstr())
+            }
+            Localization::Tables { .. } => {
+                let (_, index) = u32::parse(field.data).ok()?;
+                self.table(kind)?.get(index)
+            }
+        }
+    }
+}
+
+impl Plugin<'_> {
+    /// Where the text of this plugin's lstrings is. If the plugin is localized then its string
+    /// tables in [language] are read from the Strings folder of [data_folder]
+    pub fn localization(
+        &self,
+        data_folder: &Path,
+        language: &str,
+    ) -> Result<Localization, StringsError> {
+        if !self.is_localized() {
+            return Ok(Localization::Inline);
+        }
+        let files = StringFiles::read(data_folder, &self.name, language).map_err(|error| {
+            StringsError::Io {
+                path: data_folder.join("Strings"),
+                error,
+            }
+        })?;
+        let mut tables = [None, None, None];
+        for (kind, table) in StringsKind::ALL.iter().zip(tables.iter_mut()) {
+            if let Some(parsed) = files.string_table(*kind) {
+                *table = Some(parsed.map_err(|error| StringsError::Parse {
+                    path: strings_path(data_folder, &self.name, &files.language, *kind),
+                    code: error.code(),
+                    message: error.to_string(),
+                })?);
+            }
+        }
+        Ok(Localization::Tables {
+            language: files.language,
+            tables,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum StringsError {
+    Io {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    /// The string table at [path] is malformed
+    Parse {
+        path: PathBuf,
+        code: ErrorCode,
+        message: String,
+    },
+}
+impl Display for StringsError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StringsError::Io { path, error } => {
+                write!(f, "Failed to read {}: {}", path.display(), error)
+            }
+            StringsError::Parse { path, message, .. } => {
+                write!(f, "Failed to parse {}: {}", path.display(), message)
+            }
+        }
+    }
+}
+impl std::error::Error for StringsError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StringsError::Io { error, .. } => Some(error),
+            StringsError::Parse { .. } => None,
+        }
+    }
+}
+impl VivecError for StringsError {
+    fn code(&self) -> ErrorCode {
+        match self {
+            StringsError::Io { .. } => ErrorCode::Io,
+            StringsError::Parse { code, .. } => *code,
+        }
+    }
+}
+
+/// How much of one language another language covers, see [StringFiles::coverage]
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct LanguageCoverage {
+    /// The language being compared
+    pub language: String,
+    /// Amount of ids that both languages have
+    pub shared: usize,
+    /// Ids that only the first language has, so the game shows nothing for them in [language]
+    pub missing: Vec<(StringsKind, u32)>,
+    /// Ids that only [language] has, which are usually left over from removed records
+    pub extra: Vec<(StringsKind, u32)>,
+}
+impl LanguageCoverage {
+    /// Fraction of the first language's ids that [language] has, 1.0 when it has no ids
+    pub fn ratio(&self) -> f64 {
+        let total = self.shared + self.missing.len();
+        if total == 0 {
+            1.0
+        } else {
+            self.shared as f64 / total as f64
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::records::{common::record_flag, fields::common::object::FULL};
+
+    fn table(ids: &[u32]) -> Vec<u8> {
+        let mut data = (ids.len() as u32).to_le_bytes().to_vec();
+        data.extend_from_slice(&(ids.len() as u32 * 2).to_le_bytes());
+        for (i, id) in ids.iter().enumerate() {
+            data.extend_from_slice(&id.to_le_bytes());
+            data.extend_from_slice(&(i as u32 * 2).to_le_bytes());
+        }
+        for _ in ids {
+            data.extend_from_slice(b"a\0");
+        }
+        data
+    }
+
+    #[test]
+    fn test_languages() {
+        let dir = std::env::temp_dir().join(format!("vivec_strings_{}", std::process::id()));
+        let strings = dir.join("Strings");
+        std::fs::create_dir_all(&strings).unwrap();
+        std::fs::write(strings.join("Patch_English.STRINGS"), table(&[1, 2, 3])).unwrap();
+        std::fs::write(strings.join("Patch_english.DLSTRINGS"), table(&[4])).unwrap();
+        std::fs::write(strings.join("patch_french.strings"), table(&[1, 3, 9])).unwrap();
+        std::fs::write(strings.join("Other_german.STRINGS"), table(&[1])).unwrap();
+        std::fs::write(strings.join("Patch_notes.txt"), b"").unwrap();
+
+        assert_eq!(
+            languages(&dir, "Patch.esp").unwrap(),
+            vec!["english", "french"]
+        );
+        assert_eq!(
+            strings_path(&dir, "Patch.esp", "english", StringsKind::IlStrings),
+            strings.join("Patch_english.ILSTRINGS")
+        );
+
+        let english = StringFiles::read(&dir, "Patch.esp", "English").unwrap();
+        assert_eq!(english.language, "english");
+        assert!(english.table(StringsKind::IlStrings).is_none());
+        assert_eq!(
+            string_ids(english.table(StringsKind::Strings).unwrap()).unwrap(),
+            vec![1, 2, 3]
+        );
+        let french = StringFiles::read(&dir, "Patch.esp", "french").unwrap();
+        let coverage = english.coverage(&french).unwrap();
+        assert_eq!(coverage.shared, 2);
+        assert_eq!(
+            coverage.missing,
+            vec![(StringsKind::Strings, 2), (StringsKind::DlStrings, 4)]
+        );
+        assert_eq!(coverage.extra, vec![(StringsKind::Strings, 9)]);
+        assert_eq!(coverage.ratio(), 0.5);
+        let strings = english.string_table(StringsKind::Strings).unwrap().unwrap();
+        assert_eq!(strings.get(2), Some(b"a".as_bstr()));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_string_table() {
+        let mut table = StringTable::new(StringsKind::DlStrings);
+        table.insert(1, "Iron Sword");
+        table.insert(7, "A description");
+        table.insert(3, "Iron Sword");
+        table.insert(4, "");
+        let mut data = Vec::new();
+        table.write_to(&mut data).unwrap();
+        assert_eq!(data.len(), table.data_size());
+        // The second Iron Sword shares the first's offset
+        assert_eq!(&data[..8], &[4, 0, 0, 0, 38, 0, 0, 0]);
+        assert_eq!(
+            &data[8..24],
+            &[1, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0]
+        );
+        assert_eq!(&data[32..40], &[7, 0, 0, 0, 20, 0, 0, 0]);
+        assert_eq!(StringTable::parse(&data, StringsKind::DlStrings), Ok(table));
+
+        let mut table = StringTable::new(StringsKind::Strings);
+        table.insert(0x10, "Hello");
+        let data = table.to_vec().unwrap();
+        assert_eq!(data, b"\x01\0\0\0\x06\0\0\0\x10\0\0\0\0\0\0\0Hello\0");
+        assert_eq!(StringTable::parse(&data, StringsKind::Strings), Ok(table));
+        assert!(StringTable::parse(&data[..data.len() - 1], StringsKind::Strings).is_err());
+    }
+
+    #[test]
+    fn test_localization() {
+        let dir = std::env::temp_dir().join(format!("vivec_localization_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("Strings")).unwrap();
+        let mut names = StringTable::new(StringsKind::Strings);
+        names.insert(5, "Iron Sword");
+        std::fs::write(
+            strings_path(&dir, "Patch.esp", "french", StringsKind::Strings),
+            names.to_vec().unwrap(),
+        )
+        .unwrap();
+
+        let mut plugin = Plugin::new("Patch.esp");
+        assert_eq!(
+            plugin.localization(&dir, "french").unwrap(),
+            Localization::Inline
+        );
+        let inline = GeneralField::new(b"FULL".as_bstr(), b"Iron Dagger\0");
+        assert_eq!(
+            Localization::Inline.field_text(&inline, StringsKind::Strings),
+            Some(b"Iron Dagger".as_bstr())
+        );
+
+        plugin.header_mut().unwrap().common.flags.flags |= record_flag::LOCALIZED;
+        let localization = plugin.localization(&dir, "French").unwrap();
+        assert_eq!(localization.table(StringsKind::Strings), Some(&names));
+        assert_eq!(localization.table(StringsKind::DlStrings), None);
+        let name = FULL {
+            name: LString::Index(5),
+        };
+        assert_eq!(name.text(&localization), Some(b"Iron Sword".as_bstr()));
+        let field = GeneralField::new(b"FULL".as_bstr(), &[5, 0, 0, 0]);
+        assert_eq!(
+            localization.field_text(&field, StringsKind::Strings),
+            Some(b"Iron Sword".as_bstr())
+        );
+        assert_eq!(
+            localization.field_text(&field, StringsKind::DlStrings),
+            None
+        );
+
+        std::fs::write(
+            strings_path(&dir, "Patch.esp", "french", StringsKind::DlStrings),
+            [9, 0, 0, 0],
+        )
+        .unwrap();
+        let error = plugin.localization(&dir, "french").unwrap_err();
+        assert_eq!(error.code(), ErrorCode::UnexpectedEof);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
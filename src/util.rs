@@ -1,9 +1,11 @@
 use crate::{
+    error::{ErrorCode, VivecError},
     parse::{PResult, Parse},
-    records::common::BStrw,
+    records::common::{BStrw, FormId},
+    verify::WriteMismatch,
 };
-use bstr::ByteSlice;
-use std::io::Write;
+use bstr::{BStr, ByteSlice};
+use std::{convert::TryFrom, io::Write};
 
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub struct Position<T: Copy + Clone + PartialEq> {
@@ -73,6 +75,19 @@ pub mod byte {
     }
 }
 
+/// Standard CRC-32 (IEEE, reflected, as used by zlib)
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc: u32 = 0xFFFF_FFFF;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (!(crc & 1)).wrapping_add(1);
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
 pub fn fmt_data<T: std::fmt::Debug>(
     debug_struct: &mut std::fmt::DebugStruct,
     name: &str,
@@ -114,13 +129,163 @@ macro_rules! dispatch_all {
     };
 }
 
+/// Why a value could not be written
+#[derive(Debug)]
+pub enum WriteError {
+    Io(std::io::Error),
+    /// A record's data is larger than the u32 size in its header can hold
+    RecordTooLarge {
+        type_name: String,
+        size: usize,
+    },
+    /// A group is larger than the u32 size in its header can hold
+    GroupTooLarge {
+        size: usize,
+    },
+    /// A field's data is larger than the size in its header can hold
+    FieldTooLarge {
+        type_name: String,
+        size: usize,
+    },
+    /// A string or list is longer than its length prefix can hold
+    TooLong {
+        what: &'static str,
+        length: usize,
+        max: usize,
+    },
+    /// Text that can't be represented in the encoding it is written in
+    Encoding(String),
+    /// Records that were read back differently than they were written, see [crate::verify]
+    Unverified(Vec<WriteMismatch>),
+    /// New records of a light plugin whose object ids are outside of the range it can hold
+    LightRange(Vec<FormId>),
+    /// A plugin with this extension lacks a flag that the game loads it with anyway
+    ExtensionFlags {
+        extension: String,
+        flag: &'static str,
+    },
+}
+impl From<std::io::Error> for WriteError {
+    fn from(error: std::io::Error) -> WriteError {
+        WriteError::Io(error)
+    }
+}
+/// So that writers which only deal in IO errors can still use `?` on [Writable::write_to]
+impl From<WriteError> for std::io::Error {
+    fn from(error: WriteError) -> std::io::Error {
+        match error {
+            WriteError::Io(error) => error,
+            error => std::io::Error::new(std::io::ErrorKind::InvalidData, error.to_string()),
+        }
+    }
+}
+impl std::fmt::Display for WriteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WriteError::Io(error) => write!(f, "{}", error),
+            WriteError::RecordTooLarge { type_name, size } => write!(
+                f,
+                "{} record has {} bytes of data, more than its size can hold",
+                type_name, size
+            ),
+            WriteError::GroupTooLarge { size } => {
+                write!(f, "Group is {} bytes, more than its size can hold", size)
+            }
+            WriteError::FieldTooLarge { type_name, size } => write!(
+                f,
+                "{} field has {} bytes of data, more than its size can hold",
+                type_name, size
+            ),
+            WriteError::TooLong { what, length, max } => {
+                write!(f, "{} has length {}, the most is {}", what, length, max)
+            }
+            WriteError::Encoding(text) => write!(f, "{:?} can't be encoded", text),
+            WriteError::Unverified(mismatches) => {
+                write!(f, "{} records failed to verify", mismatches.len())?;
+                for mismatch in mismatches.iter() {
+                    write!(f, "\n{}", mismatch)?;
+                }
+                Ok(())
+            }
+            WriteError::LightRange(ids) => write!(
+                f,
+                "{} new records have object ids that a light plugin can't hold, such as [{:08X}]",
+                ids.len(),
+                ids.first().map_or(0, |x| x.id)
+            ),
+            WriteError::ExtensionFlags { extension, flag } => {
+                write!(f, ".{} plugin is missing the {} flag", extension, flag)
+            }
+        }
+    }
+}
+impl std::error::Error for WriteError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            WriteError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+impl VivecError for WriteError {
+    fn code(&self) -> ErrorCode {
+        match self {
+            WriteError::Io(_) => ErrorCode::Io,
+            WriteError::RecordTooLarge { .. } => ErrorCode::RecordTooLarge,
+            WriteError::GroupTooLarge { .. } => ErrorCode::GroupTooLarge,
+            WriteError::FieldTooLarge { .. } => ErrorCode::FieldTooLarge,
+            WriteError::TooLong { .. } => ErrorCode::TooLong,
+            WriteError::Encoding(_) => ErrorCode::Encoding,
+            WriteError::Unverified(_) => ErrorCode::Unverified,
+            WriteError::LightRange(_) => ErrorCode::LightRange,
+            WriteError::ExtensionFlags { .. } => ErrorCode::ExtensionFlags,
+        }
+    }
+}
+
+/// [size] as the u32 that record headers hold
+pub fn record_data_size(type_name: &BStr, size: usize) -> Result<u32, WriteError> {
+    u32::try_from(size).map_err(|_| WriteError::RecordTooLarge {
+        type_name: type_name.to_string(),
+        size,
+    })
+}
+
+/// [length] as the prefix of a length-prefixed value, such as a u16 or u32
+pub fn length_prefix<L>(what: &'static str, length: usize) -> Result<L, WriteError>
+where
+    L: TryFrom<usize>,
+{
+    L::try_from(length).map_err(|_| WriteError::TooLong {
+        what,
+        length,
+        max: ((1u128 << (8 * std::mem::size_of::<L>())) - 1).min(usize::MAX as u128) as usize,
+    })
+}
+
 pub trait Writable {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write;
+
+    /// Writes to a new buffer, reserving [DataSize::data_size] up front
+    fn to_vec(&self) -> Result<Vec<u8>, WriteError>
+    where
+        Self: DataSize,
+    {
+        self.write_to_vec_with_capacity(self.data_size())
+    }
+
+    /// Writes to a new buffer with room for [capacity] bytes, for values whose size is known
+    /// without [DataSize], or that are only a part of what the buffer will hold
+    fn write_to_vec_with_capacity(&self, capacity: usize) -> Result<Vec<u8>, WriteError> {
+        let mut data = Vec::with_capacity(capacity);
+        self.write_to(&mut data)?;
+        Ok(data)
+    }
 }
 impl Writable for bool {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -128,87 +293,97 @@ impl Writable for bool {
     }
 }
 impl Writable for u8 {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
-        w.write_all(&[*self])
+        w.write_all(&[*self])?;
+        Ok(())
     }
 }
 impl Writable for i8 {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
-        w.write_all(&self.to_le_bytes())
+        w.write_all(&self.to_le_bytes())?;
+        Ok(())
     }
 }
 impl Writable for u16 {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
-        w.write_all(&self.to_le_bytes())
+        w.write_all(&self.to_le_bytes())?;
+        Ok(())
     }
 }
 impl Writable for i16 {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
-        w.write_all(&self.to_le_bytes())
+        w.write_all(&self.to_le_bytes())?;
+        Ok(())
     }
 }
 impl Writable for u32 {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
-        w.write_all(&self.to_le_bytes())
+        w.write_all(&self.to_le_bytes())?;
+        Ok(())
     }
 }
 impl Writable for i32 {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
-        w.write_all(&self.to_le_bytes())
+        w.write_all(&self.to_le_bytes())?;
+        Ok(())
     }
 }
 impl Writable for u64 {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
-        w.write_all(&self.to_le_bytes())
+        w.write_all(&self.to_le_bytes())?;
+        Ok(())
     }
 }
 impl Writable for i64 {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
-        w.write_all(&self.to_le_bytes())
+        w.write_all(&self.to_le_bytes())?;
+        Ok(())
     }
 }
 impl Writable for f32 {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
-        w.write_all(&self.to_le_bytes())
+        w.write_all(&self.to_le_bytes())?;
+        Ok(())
     }
 }
 impl<'aleph> Writable for &'aleph bstr::BStr {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
-        w.write_all(self)
+        w.write_all(self)?;
+        Ok(())
     }
 }
 impl<'aleph> Writable for BStrw<'aleph> {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -220,7 +395,7 @@ where
     U: Writable,
 {
     /// Note: this does not include the size of the slice!
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -235,7 +410,7 @@ where
     U: Writable,
 {
     /// Note: this does not include the size of the vector!
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -246,7 +421,7 @@ impl<U> Writable for Position<U>
 where
     U: Sized + Copy + Clone + PartialEq + Writable,
 {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -258,7 +433,7 @@ impl<U> Writable for Position3<U>
 where
     U: Sized + Copy + Clone + PartialEq + Writable,
 {
-    fn write_to<T>(&self, w: &mut T) -> std::io::Result<()>
+    fn write_to<T>(&self, w: &mut T) -> Result<(), WriteError>
     where
         T: Write,
     {
@@ -395,4 +570,48 @@ mod tests {
         let data: Vec<u32> = vec![42, 92, 5, 4, 92];
         assert_eq!(data.data_size(), 20);
     }
+
+    #[test]
+    fn write_to_vec() {
+        let data: Vec<u32> = vec![42, 92];
+        let written = data.to_vec().unwrap();
+        assert_eq!(written, vec![42, 0, 0, 0, 92, 0, 0, 0]);
+        assert_eq!(written.capacity(), data.data_size());
+        let written = 5u16.write_to_vec_with_capacity(16).unwrap();
+        assert_eq!(written, vec![5, 0]);
+        assert!(written.capacity() >= 16);
+    }
+
+    #[test]
+    fn crc32_check() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn write_error_sizes() {
+        assert_eq!(record_data_size(b"MISC".as_bstr(), 20).unwrap(), 20);
+        let size = u32::MAX as usize + 1;
+        assert!(matches!(
+            record_data_size(b"MISC".as_bstr(), size),
+            Err(WriteError::RecordTooLarge { ref type_name, size: found }) if type_name == "MISC" && found == size
+        ));
+
+        assert_eq!(length_prefix::<u16>("List", 3).unwrap(), 3);
+        let error = length_prefix::<u16>("List", 70000).unwrap_err();
+        assert!(matches!(
+            error,
+            WriteError::TooLong {
+                what: "List",
+                length: 70000,
+                max: 65535
+            }
+        ));
+        let error = std::io::Error::from(error);
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(
+            error.to_string(),
+            "List has length 70000, the most is 65535"
+        );
+    }
 }
@@ -0,0 +1,574 @@
+// Checking a plugin against a set of rules, each looking at every record or at the plugin as a
+// whole, so that problems which the game or the Creation Kit would stumble over are reported
+// together rather than failing the first time one is read. Every record is looked at field by
+// field as a general record, including those within groups that weren't converted, and those that
+// are compressed once decompressed.
+// Rules are kept by a Validator, which has the rules below by default, and more can be added by
+// implementing Rule.
+
+use crate::{
+    context::Severity,
+    parse::Parse,
+    plugin::Plugin,
+    records::common::{record_flag, FormId, GeneralRecord, TypeNamed, FIRST_OBJECT_INDEX},
+    walk::EntryRef,
+};
+use bstr::{BStr, ByteSlice};
+use std::fmt::{self, Display, Formatter};
+
+/// A problem that a [Rule] found
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Finding {
+    pub severity: Severity,
+    /// Name of the rule that found it, see [Rule::name]
+    pub rule: &'static str,
+    /// Type name and form id of the record, if it is about one
+    pub record: Option<(String, FormId)>,
+    /// Signature of the field, if it is about one
+    pub field: Option<String>,
+    pub message: String,
+}
+impl Display for Finding {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: ", self.severity)?;
+        if let Some((type_name, id)) = &self.record {
+            write!(f, "{} [{:08X}]: ", type_name, id.id)?;
+        }
+        if let Some(field) = &self.field {
+            write!(f, "{}: ", field)?;
+        }
+        write!(f, "{} ({})", self.message, self.rule)
+    }
+}
+
+/// Where a [Rule] puts what it finds, which are filled in with the rule and the record that is
+/// being checked
+#[derive(Debug, Default)]
+pub struct Findings {
+    rule: &'static str,
+    record: Option<(String, FormId)>,
+    findings: Vec<Finding>,
+}
+impl Findings {
+    pub fn add<S: Into<String>>(&mut self, severity: Severity, field: Option<&BStr>, message: S) {
+        self.findings.push(Finding {
+            severity,
+            rule: self.rule,
+            record: self.record.clone(),
+            field: field.map(|x| x.to_string()),
+            message: message.into(),
+        });
+    }
+}
+
+/// A check run by a [Validator]
+pub trait Rule {
+    /// Short name that findings are tagged with
+    fn name(&self) -> &'static str;
+
+    /// Checks a single record
+    fn check_record(&self, _record: &GeneralRecord<'_>, _findings: &mut Findings) {}
+
+    /// Checks the plugin as a whole, after every record was checked
+    fn check_plugin(&self, _plugin: &Plugin<'_>, _findings: &mut Findings) {}
+}
+
+/// Fields that records of a type must have. Deleted records aren't checked, as they have none
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequiredFields {
+    /// Record type, and the signature of a field that it must have
+    pub fields: Vec<([u8; 4], [u8; 4])>,
+}
+impl Default for RequiredFields {
+    fn default() -> RequiredFields {
+        let fields: &[(&[u8; 4], &[&[u8; 4]])] = &[
+            (b"TES4", &[b"HEDR"]),
+            (b"GMST", &[b"EDID", b"DATA"]),
+            (b"GLOB", &[b"EDID", b"FNAM", b"FLTV"]),
+            (b"KYWD", &[b"EDID"]),
+            (b"ALCH", &[b"DATA", b"ENIT"]),
+            (b"AMMO", &[b"DATA"]),
+            (b"ARMO", &[b"DATA", b"DNAM"]),
+            (b"BOOK", &[b"DATA"]),
+            (b"INGR", &[b"DATA", b"ENIT"]),
+            (b"KEYM", &[b"DATA"]),
+            (b"MISC", &[b"DATA"]),
+            (b"WEAP", &[b"DATA", b"DNAM"]),
+        ];
+        RequiredFields {
+            fields: fields
+                .iter()
+                .flat_map(|(record, fields)| fields.iter().map(move |field| (**record, **field)))
+                .collect(),
+        }
+    }
+}
+impl Rule for RequiredFields {
+    fn name(&self) -> &'static str {
+        "required-fields"
+    }
+
+    fn check_record(&self, record: &GeneralRecord<'_>, findings: &mut Findings) {
+        if record.common.flags.is(record_flag::DELETED) {
+            return;
+        }
+        let required = self
+            .fields
+            .iter()
+            .filter(|(type_name, _)| record.type_name == &type_name[..]);
+        for (_, field) in required {
+            if !record.fields.iter().any(|x| x.type_name == &field[..]) {
+                findings.add(
+                    Severity::Error,
+                    Some(field.as_bstr()),
+                    "Missing required field",
+                );
+            }
+        }
+    }
+}
+
+/// How large a field must be, see [FieldSizes]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum FieldSize {
+    Exact(usize),
+    /// A list of entries of this size
+    MultipleOf(usize),
+}
+
+/// Fields whose size is known, so that one of another size was written wrong
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldSizes {
+    /// Record type, or None for fields that are the same in every record, the signature of the
+    /// field, and how large it must be
+    pub sizes: Vec<(Option<[u8; 4]>, [u8; 4], FieldSize)>,
+}
+impl Default for FieldSizes {
+    fn default() -> FieldSizes {
+        FieldSizes {
+            sizes: vec![
+                (Some(*b"TES4"), *b"HEDR", FieldSize::Exact(12)),
+                (None, *b"OBND", FieldSize::Exact(12)),
+                (None, *b"KSIZ", FieldSize::Exact(4)),
+                (None, *b"KWDA", FieldSize::MultipleOf(4)),
+            ],
+        }
+    }
+}
+impl Rule for FieldSizes {
+    fn name(&self) -> &'static str {
+        "field-sizes"
+    }
+
+    fn check_record(&self, record: &GeneralRecord<'_>, findings: &mut Findings) {
+        for field in record.fields.iter() {
+            let size = self.sizes.iter().find(|(type_name, name, _)| {
+                type_name.map_or(true, |x| record.type_name == &x[..])
+                    && field.type_name == &name[..]
+            });
+            let len = field.data.len();
+            match size {
+                Some((_, _, FieldSize::Exact(size))) if len != *size => findings.add(
+                    Severity::Error,
+                    Some(field.type_name),
+                    format!("Expected {} bytes, found {}", size, len),
+                ),
+                Some((_, _, FieldSize::MultipleOf(size))) if len % size != 0 => findings.add(
+                    Severity::Error,
+                    Some(field.type_name),
+                    format!("Expected a multiple of {} bytes, found {}", size, len),
+                ),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Record flags that no record type is known to use, going by [record_flag::TYPES]
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct Flags;
+impl Rule for Flags {
+    fn name(&self) -> &'static str {
+        "flags"
+    }
+
+    fn check_record(&self, record: &GeneralRecord<'_>, findings: &mut Findings) {
+        let flags = record.common.flags.flags;
+        let undocumented = flags & !record_flag::documented_any();
+        if undocumented != 0 {
+            findings.add(
+                Severity::Warning,
+                None,
+                format!("Has undocumented flags {:08X}", undocumented),
+            );
+        }
+    }
+}
+
+/// Flags set on records of types that they don't belong to, such as STARTS_DEAD on a BOOK. Bits
+/// mean different things for different types, so a bit is only suspicious on a type that it has no
+/// meaning for at all
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlagTypes {
+    /// Flags, and the only record types that use them
+    pub only: Vec<(u32, Vec<[u8; 4]>)>,
+    /// Flags, and record types that can't have them
+    pub never: Vec<(u32, Vec<[u8; 4]>)>,
+}
+impl Default for FlagTypes {
+    /// Going by [record_flag::TYPES], merging the flags that share a bit. Bits that have a
+    /// meaning for every type are left out
+    fn default() -> FlagTypes {
+        let mut only: Vec<(u32, Vec<[u8; 4]>)> = Vec::new();
+        for (flag, types) in record_flag::TYPES.iter() {
+            let everywhere = record_flag::TYPES
+                .iter()
+                .any(|(other, types)| other == flag && types.is_empty());
+            if everywhere {
+                continue;
+            }
+            match only.iter_mut().find(|(other, _)| other == flag) {
+                Some((_, known)) => {
+                    for type_name in types.iter() {
+                        if !known.contains(type_name) {
+                            known.push(**type_name);
+                        }
+                    }
+                }
+                None => only.push((*flag, types.iter().map(|x| **x).collect())),
+            }
+        }
+        let never: &[(u32, &[u8; 4])] = &[
+            (record_flag::COMPRESSED, b"TES4"),
+            (record_flag::DELETED, b"TES4"),
+        ];
+        FlagTypes {
+            only,
+            never: never.iter().map(|(flag, x)| (*flag, vec![**x])).collect(),
+        }
+    }
+}
+impl Rule for FlagTypes {
+    fn name(&self) -> &'static str {
+        "flag-types"
+    }
+
+    fn check_record(&self, record: &GeneralRecord<'_>, findings: &mut Findings) {
+        let flags = record.common.flags.flags;
+        let has_type = |types: &[[u8; 4]]| types.iter().any(|x| record.type_name == &x[..]);
+        for (flag, types) in self.only.iter() {
+            if flags & flag != 0 && !has_type(types) {
+                let types: Vec<String> = types.iter().map(|x| x.as_bstr().to_string()).collect();
+                findings.add(
+                    Severity::Warning,
+                    None,
+                    format!("Has flag {:08X}, which only {} use", flag, types.join(", ")),
+                );
+            }
+        }
+        for (flag, types) in self.never.iter() {
+            if flags & flag != 0 && has_type(types) {
+                findings.add(
+                    Severity::Error,
+                    None,
+                    format!("Has flag {:08X}, which this type can't have", flag),
+                );
+            }
+        }
+    }
+}
+
+/// Counts that are kept alongside what they count: the KSIZ of a record's keywords, and the
+/// record count and next object id of the TES4 record's HEDR, see [Plugin::refresh_header]
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct Counts;
+impl Rule for Counts {
+    fn name(&self) -> &'static str {
+        "counts"
+    }
+
+    fn check_record(&self, record: &GeneralRecord<'_>, findings: &mut Findings) {
+        let field = |name: &str| record.fields.iter().find(|x| x.type_name == name);
+        let count = field("KSIZ")
+            .and_then(|x| u32::parse(x.data).ok())
+            .map(|(_, x)| x);
+        let keywords = field("KWDA").map(|x| x.data.len() / 4);
+        match (count, keywords) {
+            (Some(count), keywords) if count as usize != keywords.unwrap_or(0) => findings.add(
+                Severity::Error,
+                Some(b"KSIZ".as_bstr()),
+                format!(
+                    "Counts {} keywords, but there are {}",
+                    count,
+                    keywords.unwrap_or(0)
+                ),
+            ),
+            (None, Some(_)) => findings.add(
+                Severity::Error,
+                Some(b"KWDA".as_bstr()),
+                "Keywords without a KSIZ before them",
+            ),
+            _ => {}
+        }
+    }
+
+    fn check_plugin(&self, plugin: &Plugin<'_>, findings: &mut Findings) {
+        let hedr = match plugin.header() {
+            Some(header) => header.header(),
+            None => return findings.add(Severity::Error, None, "The plugin has no TES4 record"),
+        };
+        let own_index = plugin.masters().len();
+        let (ids, groups) = plugin.formids_and_groups();
+        if hedr.record_count as usize != ids.len() + groups {
+            findings.add(
+                Severity::Warning,
+                Some(b"HEDR".as_bstr()),
+                format!(
+                    "Counts {} records and groups, but there are {}",
+                    hedr.record_count,
+                    ids.len() + groups
+                ),
+            );
+        }
+        let next_object_id = ids
+            .iter()
+            .filter(|id| usize::from(id.mod_index()) == own_index)
+            .map(|id| id.object_index() + 1)
+            .fold(FIRST_OBJECT_INDEX, u32::max);
+        if hedr.next_object_id < next_object_id {
+            findings.add(
+                Severity::Warning,
+                Some(b"HEDR".as_bstr()),
+                format!(
+                    "Next object id is {:06X}, which is already used",
+                    hedr.next_object_id
+                ),
+            );
+        }
+    }
+}
+
+/// The rules that a plugin is checked against
+pub struct Validator {
+    rules: Vec<Box<dyn Rule>>,
+}
+impl Validator {
+    /// A validator without any rules
+    pub fn new() -> Validator {
+        Validator { rules: Vec::new() }
+    }
+
+    pub fn add<R: Rule + 'static>(&mut self, rule: R) {
+        self.rules.push(Box::new(rule));
+    }
+
+    /// Runs every rule on every record of [plugin], in the order they are written, and then on
+    /// the plugin itself. Records that can't be looked at field by field, such as those that
+    /// fail to decompress, are reported as errors of their own
+    pub fn validate(&self, plugin: &Plugin<'_>) -> Vec<Finding> {
+        let mut findings = Findings::default();
+        let mut buffer = Vec::new();
+        for (entry, _) in plugin.iter_all_records() {
+            findings.record = Some((entry.type_name().to_string(), entry.formid()));
+            let record = match entry {
+                EntryRef::Record(record) => record.to_general(&mut buffer).ok_or_else(|| {
+                    "Couldn't be written to be looked at field by field".to_string()
+                }),
+                EntryRef::Compressed(record) => record
+                    .decompress(&mut buffer)
+                    .map_err(|error| format!("Couldn't be decompressed: {}", error)),
+            };
+            match record {
+                Ok(record) => {
+                    for rule in self.rules.iter() {
+                        findings.rule = rule.name();
+                        rule.check_record(&record, &mut findings);
+                    }
+                }
+                Err(message) => {
+                    findings.rule = "records";
+                    findings.add(Severity::Error, None, message);
+                }
+            }
+        }
+        findings.record = None;
+        for rule in self.rules.iter() {
+            findings.rule = rule.name();
+            rule.check_plugin(plugin, &mut findings);
+        }
+        findings.findings
+    }
+}
+impl Default for Validator {
+    /// A validator with [RequiredFields], [FieldSizes], [Flags], [FlagTypes] and [Counts]
+    fn default() -> Validator {
+        let mut validator = Validator::new();
+        validator.add(RequiredFields::default());
+        validator.add(FieldSizes::default());
+        validator.add(Flags);
+        validator.add(FlagTypes::default());
+        validator.add(Counts);
+        validator
+    }
+}
+
+impl<'data> Plugin<'data> {
+    /// Checks the plugin against the rules of [Validator::default]
+    pub fn validate(&self) -> Vec<Finding> {
+        Validator::default().validate(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        context::ParseContext,
+        fixtures,
+        groups::{
+            common::{GroupEntry, TopGroup},
+            Group,
+        },
+        records::{common::CompressedRecord, fields::common::GeneralField, Record},
+        Top,
+    };
+    use std::borrow::Cow;
+
+    fn general<'data>(
+        type_name: &'static [u8],
+        id: u32,
+        flags: u32,
+        fields: Vec<GeneralField<'data>>,
+    ) -> GeneralRecord<'data> {
+        let mut record = fixtures::general(type_name, id, fields);
+        record.common.flags.flags = flags;
+        record
+    }
+
+    fn summary(findings: &[Finding]) -> Vec<(&'static str, Option<u32>, Option<&str>)> {
+        findings
+            .iter()
+            .map(|x| {
+                let id = x.record.as_ref().map(|(_, id)| id.id);
+                (x.rule, id, x.field.as_deref())
+            })
+            .collect()
+    }
+
+    struct NoLists;
+    impl Rule for NoLists {
+        fn name(&self) -> &'static str {
+            "no-lists"
+        }
+
+        fn check_record(&self, record: &GeneralRecord<'_>, findings: &mut Findings) {
+            if record.type_name == "FLST" {
+                findings.add(Severity::Info, None, "Is a list");
+            }
+        }
+    }
+
+    #[test]
+    fn test_flag_types() {
+        let mut plugin = Plugin::new("Base.esm");
+        plugin.header_mut().unwrap().common.flags.flags |= record_flag::COMPRESSED;
+        let records = vec![
+            general(b"BOOK", 0x800, record_flag::STARTS_DEAD, Vec::new()),
+            // The same bit means something else for these
+            general(b"ACHR", 0x801, record_flag::STARTS_DEAD, Vec::new()),
+            general(b"CELL", 0x802, record_flag::OFF_LIMITS, Vec::new()),
+            general(
+                b"ACTI",
+                0x803,
+                record_flag::DANGEROUS | record_flag::MULTIBOUND,
+                Vec::new(),
+            ),
+        ];
+        let records = records.into_iter().map(GroupEntry::from).collect();
+        plugin.push_top(fixtures::top(b"MISC", records));
+
+        let mut validator = Validator::new();
+        validator.add(FlagTypes::default());
+        let findings = validator.validate(&plugin);
+        assert_eq!(
+            summary(&findings),
+            vec![
+                ("flag-types", Some(0), None),
+                ("flag-types", Some(0x800), None),
+                ("flag-types", Some(0x803), None),
+            ]
+        );
+        assert_eq!(findings[0].severity, Severity::Error);
+        assert_eq!(
+            findings[1].to_string(),
+            "warning: BOOK [00000800]: Has flag 00000200, which only TES4, ACHR, REFR use \
+             (flag-types)"
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut plugin = Plugin::new("Base.esm");
+        let field = |name: &'static [u8], data| GeneralField::new(name.as_bstr(), data);
+        let item = general(
+            b"MISC",
+            0x800,
+            record_flag::MASTER | 0x2000,
+            vec![
+                field(b"OBND", &[0; 6]),
+                field(b"KSIZ", &[2, 0, 0, 0]),
+                field(b"KWDA", &[1, 0, 0, 0]),
+            ],
+        );
+        let list = general(b"FLST", 0x900, 0, vec![field(b"KWDA", &[1, 0, 0])]);
+        let list = Record::from_general(list, &mut ParseContext::default()).unwrap();
+        let mut broken =
+            CompressedRecord::compress(&general(b"MISC", 0x802, 0, Vec::new()), 9).unwrap();
+        broken.data = Cow::Borrowed(&[4, 0, 0, 0, 1, 2]);
+        let compressed = general(b"MISC", 0x801, 0, vec![field(b"DATA", &[0; 8])]);
+        let items = vec![Record::Unknown(item)];
+        let mut items = TopGroup::from_records(b"MISC".as_bstr(), items).unwrap();
+        items
+            .entries
+            .push(CompressedRecord::compress(&compressed, 9).unwrap().into());
+        items.entries.push(broken.into());
+        plugin.push_top(Top::Group(Group::UnknownTop(items)));
+        plugin.push_top(Top::Group(Group::from_record(list).unwrap()));
+
+        let findings = plugin.validate();
+        assert_eq!(
+            summary(&findings),
+            vec![
+                ("required-fields", Some(0x800), Some("DATA")),
+                ("field-sizes", Some(0x800), Some("OBND")),
+                ("flags", Some(0x800), None),
+                ("flag-types", Some(0x800), None),
+                ("counts", Some(0x800), Some("KSIZ")),
+                ("records", Some(0x802), None),
+                ("field-sizes", Some(0x900), Some("KWDA")),
+                ("counts", Some(0x900), Some("KWDA")),
+                ("counts", None, Some("HEDR")),
+                ("counts", None, Some("HEDR")),
+            ]
+        );
+        assert_eq!(
+            findings[4].to_string(),
+            "error: MISC [00000800]: KSIZ: Counts 2 keywords, but there are 1 (counts)"
+        );
+        assert_eq!(findings[2].severity, Severity::Warning);
+
+        plugin.refresh_header();
+        let mut validator = Validator::new();
+        validator.add(Counts);
+        validator.add(NoLists);
+        assert_eq!(
+            summary(&validator.validate(&plugin)),
+            vec![
+                ("counts", Some(0x800), Some("KSIZ")),
+                ("records", Some(0x802), None),
+                ("counts", Some(0x900), Some("KWDA")),
+                ("no-lists", Some(0x900), None),
+            ]
+        );
+    }
+}
@@ -0,0 +1,632 @@
+// Checking records as they are written, by reading each one back with its typed parser and
+// comparing it to the original. This catches a record type whose DataSize or Writable has drifted
+// from its parser before the plugin it would corrupt is written, rather than when it is next
+// loaded.
+// [verify_roundtrip] does the same for a whole plugin file, comparing the bytes that are written
+// against those that were read.
+
+use crate::{
+    analysis::{group_type_of, RECORDH_SIZE},
+    context::{ParseContext, ParseOptions},
+    error::{report, ErrorCode, VivecError},
+    groups::common::{GroupType, GROUPH_SIZE},
+    parse::Parse,
+    parse_file,
+    records::{
+        common::{record_flag, FormId, GeneralRecord, TypeNamed},
+        fields::common::FIELDH_SIZE,
+        Record, RecordRef,
+    },
+    util::{DataSize, Writable, WriteError},
+    walk::walk_records,
+    GeneralError,
+};
+use bstr::ByteSlice;
+use derive_more::From;
+use std::fmt::{Display, Formatter};
+
+/// Bytes shown on each side of where written data differs
+const WINDOW: usize = 8;
+
+/// How a record that was written differs from what was read back
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Mismatch {
+    /// The record's DataSize disagrees with the amount of bytes it wrote
+    Size { expected: usize, written: usize },
+    /// The written record couldn't be read back
+    Unreadable(String),
+    /// The record read back is different. Holds the first line of their debug output that
+    /// differs, counting from 1
+    Changed {
+        line: usize,
+        original: String,
+        reparsed: String,
+    },
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct WriteMismatch {
+    pub record_type: String,
+    pub formid: FormId,
+    pub mismatch: Mismatch,
+}
+impl Display for WriteMismatch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {:08X}: ", self.record_type, self.formid.id)?;
+        match &self.mismatch {
+            Mismatch::Size { expected, written } => write!(
+                f,
+                "Expected to write {} bytes, but wrote {}",
+                expected, written
+            ),
+            Mismatch::Unreadable(error) => write!(f, "Could not be read back: {}", error),
+            Mismatch::Changed {
+                line,
+                original,
+                reparsed,
+            } => write!(
+                f,
+                "Read back differently, at line {}: {} became {}",
+                line, original, reparsed
+            ),
+        }
+    }
+}
+
+/// Writes [record] and reads it back with [options], returning how it differs if it does
+pub fn verify_record(
+    record: RecordRef<'_, '_>,
+    options: &ParseOptions,
+) -> Result<Option<WriteMismatch>, WriteError> {
+    let mismatch = |mismatch| {
+        Ok(Some(WriteMismatch {
+            record_type: record.type_name().to_string(),
+            formid: record.formid(),
+            mismatch,
+        }))
+    };
+
+    let mut data = Vec::new();
+    record.write_to(&mut data)?;
+    if data.len() != record.data_size() {
+        return mismatch(Mismatch::Size {
+            expected: record.data_size(),
+            written: data.len(),
+        });
+    }
+
+    let general = match GeneralRecord::parse(&data) {
+        Ok((_, general)) => general,
+        Err(error) => return mismatch(Mismatch::Unreadable(report(&error))),
+    };
+    let mut ctx = ParseContext::new(options.clone());
+    let reparsed = match Record::from_general(general, &mut ctx) {
+        Ok(reparsed) => reparsed,
+        Err(error) => return mismatch(Mismatch::Unreadable(report(&error))),
+    };
+    if reparsed.as_record_ref() == record {
+        return Ok(None);
+    }
+
+    let original = format!("{:#?}", record);
+    let reparsed = format!("{:#?}", reparsed.as_record_ref());
+    let mut original_lines = original.lines().map(str::trim);
+    let mut reparsed_lines = reparsed.lines().map(str::trim);
+    let mut line = 1;
+    loop {
+        match (original_lines.next(), reparsed_lines.next()) {
+            (Some(a), Some(b)) if a == b => line += 1,
+            (a, b) => {
+                return mismatch(Mismatch::Changed {
+                    line,
+                    original: a.unwrap_or("nothing").to_string(),
+                    reparsed: b.unwrap_or("nothing").to_string(),
+                })
+            }
+        }
+    }
+}
+
+/// The record or group that data differs within
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum DivergenceEntry {
+    Record {
+        record_type: String,
+        formid: FormId,
+        offset: usize,
+    },
+    Group {
+        group_type: String,
+        offset: usize,
+    },
+}
+
+/// Where data that was written first differs from the data it was read from
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Divergence {
+    pub offset: usize,
+    /// The innermost group or record holding [offset], going by the original data, or by the
+    /// written data if it is past the end of the original
+    pub entry: Option<DivergenceEntry>,
+    /// The signature of the field holding [offset] and where the field starts. None if it is in a
+    /// header, or the record is compressed
+    pub field: Option<(String, usize)>,
+    /// The original data around [offset], starting at [window_start]
+    pub expected: Vec<u8>,
+    /// The written data around [offset], starting at [window_start]
+    pub actual: Vec<u8>,
+    pub window_start: usize,
+}
+impl Display for Divergence {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Written data differs at offset {:#X}", self.offset)?;
+        match &self.entry {
+            Some(DivergenceEntry::Record {
+                record_type,
+                formid,
+                offset,
+            }) => write!(
+                f,
+                ", in {} record {:08X} at {:#X}",
+                record_type, formid.id, offset
+            )?,
+            Some(DivergenceEntry::Group { group_type, offset }) => {
+                write!(f, ", in {} group header at {:#X}", group_type, offset)?
+            }
+            None => {}
+        }
+        match &self.field {
+            Some((name, offset)) => write!(f, ", in {} field at {:#X}", name, offset)?,
+            None if matches!(self.entry, Some(DivergenceEntry::Record { .. })) => {
+                write!(f, ", in the record header or compressed data")?
+            }
+            None => {}
+        }
+        write!(
+            f,
+            "\nExpected {:#X}: {}",
+            self.window_start,
+            hex(&self.expected)
+        )?;
+        write!(
+            f,
+            "\nActual   {:#X}: {}",
+            self.window_start,
+            hex(&self.actual)
+        )
+    }
+}
+
+impl Divergence {
+    /// The divergence as if the data it was found in started at [base]
+    fn shifted(mut self, base: usize) -> Divergence {
+        self.offset += base;
+        self.window_start += base;
+        match &mut self.entry {
+            Some(DivergenceEntry::Record { offset, .. })
+            | Some(DivergenceEntry::Group { offset, .. }) => *offset += base,
+            None => {}
+        }
+        if let Some((_, offset)) = &mut self.field {
+            *offset += base;
+        }
+        self
+    }
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter()
+        .map(|x| format!("{:02X}", x))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Compares data that was [written] against the [original] data that was read, reporting the
+/// first difference along with the record and field it is in. None if they're the same
+pub fn diff_written(original: &[u8], written: &[u8]) -> Option<Divergence> {
+    let offset = original
+        .iter()
+        .zip(written.iter())
+        .position(|(a, b)| a != b)
+        .or_else(|| {
+            if original.len() == written.len() {
+                None
+            } else {
+                Some(original.len().min(written.len()))
+            }
+        })?;
+
+    let layout = if offset < original.len() {
+        original
+    } else {
+        written
+    };
+    let (entry, field) = locate(layout, 0, offset);
+    let window_start = offset.saturating_sub(WINDOW);
+    let window = |data: &[u8]| {
+        data[window_start.min(data.len())..(offset + WINDOW).min(data.len())].to_vec()
+    };
+    Some(Divergence {
+        offset,
+        entry,
+        field,
+        expected: window(original),
+        actual: window(written),
+        window_start,
+    })
+}
+
+/// The result of parsing a plugin and writing it back
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct RoundTripReport {
+    pub original_size: usize,
+    pub written_size: usize,
+    /// Where the written data differs, at most one per record, in the order of the original data
+    pub divergences: Vec<Divergence>,
+    /// Whether there were more divergences than were asked for
+    pub truncated: bool,
+}
+impl RoundTripReport {
+    /// Whether the plugin was written back exactly as it was read
+    pub fn is_equivalent(&self) -> bool {
+        self.divergences.is_empty()
+    }
+}
+impl Display for RoundTripReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.is_equivalent() {
+            return write!(f, "Wrote back all {} bytes unchanged", self.original_size);
+        }
+        write!(
+            f,
+            "Read {} bytes and wrote {} bytes",
+            self.original_size, self.written_size
+        )?;
+        for divergence in self.divergences.iter() {
+            write!(f, "\n{}", divergence)?;
+        }
+        if self.truncated {
+            write!(f, "\nFurther differences weren't reported")?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses the plugin file [data], writes it back and reports the first [max] records that differ
+/// from how they were read
+pub fn verify_roundtrip(data: &[u8], max: usize) -> Result<RoundTripReport, RoundTripError<'_>> {
+    let (_, tops) = parse_file(data)?;
+    let mut written = Vec::with_capacity(data.len());
+    for top in tops {
+        top.write_to(&mut written)?;
+    }
+    let (divergences, truncated) = diff_records(data, &written, max);
+    Ok(RoundTripReport {
+        original_size: data.len(),
+        written_size: written.len(),
+        divergences,
+        truncated,
+    })
+}
+
+#[derive(Debug, From)]
+pub enum RoundTripError<'data> {
+    Parse(GeneralError<'data>),
+    Write(WriteError),
+}
+impl Display for RoundTripError<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RoundTripError::Parse(_) => write!(f, "Failed to read the plugin"),
+            RoundTripError::Write(_) => write!(f, "Failed to write the plugin back"),
+        }
+    }
+}
+impl std::error::Error for RoundTripError<'_> {}
+impl VivecError for RoundTripError<'_> {
+    fn code(&self) -> ErrorCode {
+        match self {
+            RoundTripError::Parse(error) => error.code(),
+            RoundTripError::Write(error) => error.code(),
+        }
+    }
+
+    fn inner(&self) -> Option<&dyn VivecError> {
+        match self {
+            RoundTripError::Parse(error) => Some(error),
+            RoundTripError::Write(error) => Some(error),
+        }
+    }
+}
+
+/// The first [max] records of [written] that differ from the record at the same position in
+/// [original], and whether there were more. Records are paired by position rather than offset so
+/// that a record changing size doesn't hide the records after it. Differences outside of records,
+/// such as in group headers, are reported on their own if no record differs
+fn diff_records(original: &[u8], written: &[u8], max: usize) -> (Vec<Divergence>, bool) {
+    if original == written {
+        return (Vec::new(), false);
+    }
+    let records = |data| {
+        let mut records = Vec::new();
+        let _ = walk_records(data, &mut |record| records.push(record.raw));
+        records
+    };
+    let (original_records, written_records) = (records(original), records(written));
+    let mut divergences = original_records
+        .iter()
+        .zip(written_records.iter())
+        .filter_map(|(a, b)| {
+            let base = a.as_ptr() as usize - original.as_ptr() as usize;
+            diff_written(a, b).map(|x| x.shifted(base))
+        });
+    let found: Vec<_> = divergences.by_ref().take(max).collect();
+    if found.is_empty() && max != 0 {
+        return (diff_written(original, written).into_iter().collect(), false);
+    }
+    let truncated = divergences.next().is_some();
+    (found, truncated)
+}
+
+/// Finds the innermost entry and field of [data] holding [target]. [data] starts at [base]
+fn locate(
+    mut data: &[u8],
+    mut base: usize,
+    target: usize,
+) -> (Option<DivergenceEntry>, Option<(String, usize)>) {
+    while data.len() >= RECORDH_SIZE {
+        let header = &data[..RECORDH_SIZE];
+        let size = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        let is_group = &header[..4] == b"GRUP";
+        let total = if is_group { size } else { RECORDH_SIZE + size };
+        let total = total.clamp(RECORDH_SIZE, data.len());
+        if target >= base + total {
+            data = &data[total..];
+            base += total;
+            continue;
+        }
+
+        if is_group {
+            if target < base + GROUPH_SIZE {
+                let group_type = group_type_of(header);
+                return (
+                    Some(DivergenceEntry::Group {
+                        group_type: group_type_name(&group_type),
+                        offset: base,
+                    }),
+                    None,
+                );
+            }
+            return locate(&data[GROUPH_SIZE..total], base + GROUPH_SIZE, target);
+        }
+
+        let flags = u32::from_le_bytes([header[8], header[9], header[10], header[11]]);
+        let entry = Some(DivergenceEntry::Record {
+            record_type: header[..4].as_bstr().to_string(),
+            formid: FormId::from_bytes([header[12], header[13], header[14], header[15]]),
+            offset: base,
+        });
+        if target < base + RECORDH_SIZE || flags & record_flag::COMPRESSED != 0 {
+            return (entry, None);
+        }
+        let fields = &data[RECORDH_SIZE..total];
+        return (entry, locate_field(fields, base + RECORDH_SIZE, target));
+    }
+    (None, None)
+}
+
+/// The signature and start of the field in [data] holding [target], following XXXX fields
+fn locate_field(mut data: &[u8], mut base: usize, target: usize) -> Option<(String, usize)> {
+    let mut next_size = None;
+    while data.len() >= FIELDH_SIZE {
+        let name = data[..4].as_bstr().to_string();
+        let size = next_size
+            .take()
+            .unwrap_or_else(|| u16::from_le_bytes([data[4], data[5]]) as usize);
+        let total = (FIELDH_SIZE + size).min(data.len());
+        if name == "XXXX" && size == 4 && total == FIELDH_SIZE + 4 {
+            next_size = Some(u32::from_le_bytes([data[6], data[7], data[8], data[9]]) as usize);
+        }
+        if target < base + total {
+            return Some((name, base));
+        }
+        data = &data[total..];
+        base += total;
+    }
+    None
+}
+
+fn group_type_name(group_type: &GroupType<'_>) -> String {
+    match group_type {
+        GroupType::Top(label) => label.to_string(),
+        group_type => format!("{:?}", group_type),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::records::{
+        common::CommonRecordInfo,
+        fields::{common::GeneralField, edid::EDID},
+        flst::{FLSTField, FLSTRecord, LNAM},
+    };
+    use bstr::ByteSlice;
+
+    #[test]
+    fn test_verify_record() {
+        let mut ctx = ParseContext::default();
+        let general = GeneralRecord {
+            type_name: b"FLST".as_bstr(),
+            common: CommonRecordInfo::test_default(),
+            fields: vec![
+                GeneralField::new(b"EDID".as_bstr(), b"List\0"),
+                GeneralField::new(b"LNAM".as_bstr(), &[0x01, 0x02, 0, 0]),
+            ],
+        };
+        let record = Record::from_general(general, &mut ctx).unwrap();
+        let options = ParseOptions::default();
+        assert_eq!(
+            verify_record(record.as_record_ref(), &options).unwrap(),
+            None
+        );
+
+        // The editor id index is stale, so it doesn't match what is read back
+        let edid = GeneralField::new(b"EDID".as_bstr(), b"List\0");
+        let stale = FLSTRecord {
+            common: CommonRecordInfo::test_default(),
+            editor_id_index: Some(0),
+            fields: vec![
+                FLSTField::LNAM(LNAM::new(FormId::new(0x201))),
+                FLSTField::EDID(edid.decode_as::<EDID>().unwrap()),
+            ],
+        };
+        let mismatch = verify_record(RecordRef::FLST(&stale), &options)
+            .unwrap()
+            .unwrap();
+        assert_eq!(mismatch.record_type, "FLST");
+        assert_eq!(mismatch.formid, FormId::new(0x420));
+        match mismatch.mismatch {
+            Mismatch::Changed {
+                original, reparsed, ..
+            } => {
+                assert_eq!(original, "0,");
+                assert_eq!(reparsed, "1,");
+            }
+            x => panic!("Expected the record to change, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn test_diff_written() {
+        let record = GeneralRecord {
+            type_name: b"MISC".as_bstr(),
+            common: CommonRecordInfo::test_default(),
+            fields: vec![
+                GeneralField::new(b"EDID".as_bstr(), b"Coin\0"),
+                GeneralField::new(b"DATA".as_bstr(), &[1, 0, 0, 0, 0, 0, 0x80, 0x3F]),
+            ],
+        };
+        let mut data = Vec::new();
+        record.write_to(&mut data).unwrap();
+        let mut original = b"GRUP".to_vec();
+        original.extend_from_slice(&(GROUPH_SIZE + data.len()).to_le_bytes()[..4]);
+        original.extend_from_slice(b"MISC");
+        original.extend_from_slice(&[0; 12]);
+        original.extend_from_slice(&data);
+        assert_eq!(diff_written(&original, &original), None);
+
+        // Change the weight
+        let mut written = original.clone();
+        let data_offset = original.len() - 8;
+        written[data_offset + 6] = 0x40;
+        let divergence = diff_written(&original, &written).unwrap();
+        assert_eq!(divergence.offset, data_offset + 6);
+        assert_eq!(
+            divergence.entry,
+            Some(DivergenceEntry::Record {
+                record_type: "MISC".to_string(),
+                formid: FormId::new(0x420),
+                offset: GROUPH_SIZE,
+            })
+        );
+        assert_eq!(
+            divergence.field,
+            Some(("DATA".to_string(), data_offset - FIELDH_SIZE))
+        );
+        assert_eq!(divergence.window_start, divergence.offset - WINDOW);
+        assert_eq!(divergence.expected[WINDOW], 0x80);
+        assert_eq!(divergence.actual[WINDOW], 0x40);
+        assert!(divergence.to_string().contains("in DATA field"));
+
+        // Written data that is cut short differs where it ends
+        let divergence = diff_written(&original, &original[..10]).unwrap();
+        assert_eq!(divergence.offset, 10);
+        assert_eq!(
+            divergence.entry,
+            Some(DivergenceEntry::Group {
+                group_type: "MISC".to_string(),
+                offset: 0,
+            })
+        );
+    }
+
+    fn misc(id: u32, value: u8) -> Vec<u8> {
+        let mut common = CommonRecordInfo::test_default();
+        common.id = id;
+        let value = [value, 0, 0, 0];
+        let record = GeneralRecord {
+            type_name: b"MISC".as_bstr(),
+            common,
+            fields: vec![GeneralField::new(b"DATA".as_bstr(), &value)],
+        };
+        let mut data = Vec::new();
+        record.write_to(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn test_diff_records() {
+        let group = |records: &[Vec<u8>]| {
+            let records = records.concat();
+            let mut data = b"GRUP".to_vec();
+            data.extend_from_slice(&(GROUPH_SIZE + records.len()).to_le_bytes()[..4]);
+            data.extend_from_slice(b"MISC");
+            data.extend_from_slice(&[0; 12]);
+            data.extend_from_slice(&records);
+            data
+        };
+        let original = group(&[misc(1, 1), misc(2, 2), misc(3, 3)]);
+        let record_size = misc(1, 1).len();
+
+        // The first record growing doesn't hide the third record changing
+        let mut longer = misc(1, 1);
+        longer.extend_from_slice(b"NAM0\0\0");
+        longer[4] += 6;
+        let written = group(&[longer, misc(2, 2), misc(3, 4)]);
+        let (divergences, truncated) = diff_records(&original, &written, 10);
+        assert!(!truncated);
+        assert_eq!(divergences.len(), 2);
+        assert_eq!(divergences[0].offset, GROUPH_SIZE + 4);
+        assert_eq!(divergences[1].offset, original.len() - 4);
+        assert_eq!(
+            divergences[1].entry,
+            Some(DivergenceEntry::Record {
+                record_type: "MISC".to_string(),
+                formid: FormId::new(3),
+                offset: GROUPH_SIZE + record_size * 2,
+            })
+        );
+        assert_eq!(
+            divergences[1].field,
+            Some(("DATA".to_string(), original.len() - 10))
+        );
+        let (divergences, truncated) = diff_records(&original, &written, 1);
+        assert_eq!(divergences.len(), 1);
+        assert!(truncated);
+
+        // Only the group header differs
+        let mut written = original.clone();
+        written[20] = 1;
+        let (divergences, _) = diff_records(&original, &written, 10);
+        assert_eq!(divergences.len(), 1);
+        assert_eq!(divergences[0].offset, 20);
+        assert_eq!(diff_records(&original, &original, 10), (Vec::new(), false));
+    }
+
+    #[test]
+    fn test_verify_roundtrip() {
+        let header = GeneralRecord {
+            type_name: b"TES4".as_bstr(),
+            common: CommonRecordInfo::test_default(),
+            fields: vec![GeneralField::new(b"HEDR".as_bstr(), &[0; 12])],
+        };
+        let mut data = Vec::new();
+        header.write_to(&mut data).unwrap();
+        data.extend(misc(0x801, 1));
+        let report = verify_roundtrip(&data, 10).unwrap();
+        assert!(report.is_equivalent());
+        assert_eq!(report.written_size, data.len());
+        assert!(verify_roundtrip(&data[..data.len() - 1], 10).is_err());
+    }
+}
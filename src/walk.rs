@@ -0,0 +1,486 @@
+// Visiting every record of a plugin in one loop. Plugin::records only holds the records of the top
+// level and of typed top groups, while this also goes into the groups that weren't converted, such
+// as the blocks of cells and the children of each cell, in the order that they are written. Each
+// record comes with the path of groups that hold it, from the top group down, so that the cell or
+// topic that it belongs to can be told. The bytes of a plugin file can also be walked without
+// parsing it, for what only needs the record headers.
+
+use crate::{
+    analysis::{group_type_of, RECORDH_SIZE},
+    groups::{
+        common::{check_group_size, GroupEntry, GroupType, GROUPH_SIZE},
+        Group,
+    },
+    parse::{take, ParseError},
+    plugin::Plugin,
+    records::{
+        common::{
+            record_flag, CommonRecordInfo, CompressedRecord, FormId, GeneralRecord, RecordName,
+            TypeNamed,
+        },
+        RecordMut, RecordRef,
+    },
+    Top,
+};
+use bstr::{BStr, ByteSlice};
+use std::rc::Rc;
+
+/// The groups holding a record, from the top group down. Empty for records at the top level.
+/// The records of a group share the same list, so that it is cheap to clone
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct GroupPath<'data> {
+    pub groups: Rc<[GroupType<'data>]>,
+}
+impl Default for GroupPath<'_> {
+    fn default() -> Self {
+        GroupPath {
+            groups: Rc::from(Vec::new()),
+        }
+    }
+}
+impl<'data> GroupPath<'data> {
+    /// The path of the records within [group_type], which is within this path
+    pub fn child(&self, group_type: GroupType<'data>) -> GroupPath<'data> {
+        let mut groups = self.groups.to_vec();
+        groups.push(group_type);
+        GroupPath {
+            groups: Rc::from(groups),
+        }
+    }
+
+    pub fn depth(&self) -> usize {
+        self.groups.len()
+    }
+
+    /// The label of the top group, if the record is within one
+    pub fn top_label(&self) -> Option<RecordName<'data>> {
+        match self.groups.first() {
+            Some(GroupType::Top(label)) => Some(*label),
+            _ => None,
+        }
+    }
+
+    /// The form id of the record that the innermost group of children belongs to, such as the
+    /// CELL of a reference or the DIAL of an INFO
+    pub fn parent(&self) -> Option<FormId> {
+        self.groups
+            .iter()
+            .rev()
+            .find_map(|group_type| match group_type {
+                GroupType::WorldChildren(id)
+                | GroupType::CellChildren(id)
+                | GroupType::TopicChildren(id)
+                | GroupType::CellPersistentChildren(id)
+                | GroupType::CellTemporaryChildren(id) => Some(*id),
+                _ => None,
+            })
+    }
+}
+
+/// A record anywhere in a plugin. Records within groups that weren't converted are
+/// [RecordRef::Unknown], or [RecordRef::Deleted] if they are flagged as such, unless they are
+/// compressed
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum EntryRef<'a, 'data> {
+    Record(RecordRef<'a, 'data>),
+    Compressed(&'a CompressedRecord<'data>),
+}
+impl<'a, 'data> EntryRef<'a, 'data> {
+    pub fn common(self) -> &'a CommonRecordInfo {
+        match self {
+            EntryRef::Record(record) => record.common(),
+            EntryRef::Compressed(record) => &record.common,
+        }
+    }
+
+    pub fn formid(self) -> FormId {
+        FormId::new(self.common().id)
+    }
+}
+impl<'data> TypeNamed<'data> for EntryRef<'_, 'data> {
+    fn type_name(&self) -> &'data BStr {
+        match self {
+            EntryRef::Record(record) => record.type_name(),
+            EntryRef::Compressed(record) => record.type_name(),
+        }
+    }
+}
+
+/// Mutable version of [EntryRef]
+#[derive(Debug, PartialEq)]
+pub enum EntryMut<'a, 'data> {
+    Record(RecordMut<'a, 'data>),
+    Compressed(&'a mut CompressedRecord<'data>),
+}
+impl<'a, 'data> EntryMut<'a, 'data> {
+    pub fn common(&self) -> &CommonRecordInfo {
+        match self {
+            EntryMut::Record(record) => record.common(),
+            EntryMut::Compressed(record) => &record.common,
+        }
+    }
+
+    pub fn common_mut(&mut self) -> &mut CommonRecordInfo {
+        match self {
+            EntryMut::Record(record) => record.common_mut(),
+            EntryMut::Compressed(record) => &mut record.common,
+        }
+    }
+
+    pub fn formid(&self) -> FormId {
+        FormId::new(self.common().id)
+    }
+}
+impl<'data> TypeNamed<'data> for EntryMut<'_, 'data> {
+    fn type_name(&self) -> &'data BStr {
+        match self {
+            EntryMut::Record(record) => record.type_name(),
+            EntryMut::Compressed(record) => record.type_name(),
+        }
+    }
+}
+
+pub(crate) fn is_deleted(record: &GeneralRecord<'_>) -> bool {
+    record
+        .common
+        .flags
+        .is(record_flag::DELETED | record_flag::IGNORED)
+}
+
+/// Iterator of [Plugin::iter_all_records], which goes through the groups as it is advanced
+pub struct AllRecords<'a, 'data> {
+    tops: std::slice::Iter<'a, Top<'data>>,
+    /// The entries left in each group that is being gone through, innermost last, along with the
+    /// path of the records directly within it
+    groups: Vec<(std::slice::Iter<'a, GroupEntry<'data>>, GroupPath<'data>)>,
+    /// The records left in the typed top group that is being gone through, and their path
+    records: std::vec::IntoIter<EntryRef<'a, 'data>>,
+    records_path: GroupPath<'data>,
+}
+impl<'a, 'data> Iterator for AllRecords<'a, 'data> {
+    type Item = (EntryRef<'a, 'data>, GroupPath<'data>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(record) = self.records.next() {
+                return Some((record, self.records_path.clone()));
+            }
+            if let Some((entries, path)) = self.groups.last_mut() {
+                let record = match entries.next() {
+                    Some(GroupEntry::Record(record)) => EntryRef::Record(if is_deleted(record) {
+                        RecordRef::Deleted(record)
+                    } else {
+                        RecordRef::Unknown(record)
+                    }),
+                    Some(GroupEntry::Compressed(record)) => EntryRef::Compressed(record),
+                    Some(GroupEntry::Group(group)) => {
+                        let path = path.child(group.group_type);
+                        self.groups.push((group.entries.iter(), path));
+                        continue;
+                    }
+                    None => {
+                        self.groups.pop();
+                        continue;
+                    }
+                };
+                return Some((record, path.clone()));
+            }
+
+            let group = match self.tops.next()? {
+                Top::Record(record) => {
+                    return Some((
+                        EntryRef::Record(record.as_record_ref()),
+                        GroupPath::default(),
+                    ))
+                }
+                Top::Group(group) => group,
+            };
+            let path = GroupPath::default().child(group.group_type());
+            match group {
+                Group::Unknown(group) => self.groups.push((group.entries.iter(), path)),
+                Group::UnknownTop(group) => self.groups.push((group.entries.iter(), path)),
+                group => {
+                    self.records = group.entries().into_iter();
+                    self.records_path = path;
+                }
+            }
+        }
+    }
+}
+
+/// Iterator of [Plugin::iter_all_records_mut]
+pub struct AllRecordsMut<'a, 'data> {
+    tops: std::slice::IterMut<'a, Top<'data>>,
+    groups: Vec<(std::slice::IterMut<'a, GroupEntry<'data>>, GroupPath<'data>)>,
+    records: std::vec::IntoIter<EntryMut<'a, 'data>>,
+    records_path: GroupPath<'data>,
+}
+impl<'a, 'data> Iterator for AllRecordsMut<'a, 'data> {
+    type Item = (EntryMut<'a, 'data>, GroupPath<'data>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(record) = self.records.next() {
+                return Some((record, self.records_path.clone()));
+            }
+            if let Some((entries, path)) = self.groups.last_mut() {
+                let record = match entries.next() {
+                    Some(GroupEntry::Record(record)) => EntryMut::Record(if is_deleted(record) {
+                        RecordMut::Deleted(record)
+                    } else {
+                        RecordMut::Unknown(record)
+                    }),
+                    Some(GroupEntry::Compressed(record)) => EntryMut::Compressed(record),
+                    Some(GroupEntry::Group(group)) => {
+                        let path = path.child(group.group_type);
+                        self.groups.push((group.entries.iter_mut(), path));
+                        continue;
+                    }
+                    None => {
+                        self.groups.pop();
+                        continue;
+                    }
+                };
+                return Some((record, path.clone()));
+            }
+
+            let group = match self.tops.next()? {
+                Top::Record(record) => {
+                    return Some((
+                        EntryMut::Record(record.as_record_mut()),
+                        GroupPath::default(),
+                    ))
+                }
+                Top::Group(group) => group,
+            };
+            let path = GroupPath::default().child(group.group_type());
+            match group {
+                Group::Unknown(group) => self.groups.push((group.entries.iter_mut(), path)),
+                Group::UnknownTop(group) => self.groups.push((group.entries.iter_mut(), path)),
+                group => {
+                    self.records = group.entries_mut().into_iter();
+                    self.records_path = path;
+                }
+            }
+        }
+    }
+}
+
+impl<'data> Plugin<'data> {
+    /// Every record of the plugin in the order they are written, depth first, along with the
+    /// groups holding each. Records are found as the iterator is advanced, and the records within
+    /// the same group share their path
+    pub fn iter_all_records(&self) -> AllRecords<'_, 'data> {
+        AllRecords {
+            tops: self.tops().iter(),
+            groups: Vec::new(),
+            records: Vec::new().into_iter(),
+            records_path: GroupPath::default(),
+        }
+    }
+
+    /// Mutable version of [Plugin::iter_all_records], regardless of [Plugin::edit_policy].
+    /// See [Plugin::edit_records]
+    pub(crate) fn iter_all_records_mut(&mut self) -> AllRecordsMut<'_, 'data> {
+        AllRecordsMut {
+            tops: self.tops_mut().iter_mut(),
+            groups: Vec::new(),
+            records: Vec::new().into_iter(),
+            records_path: GroupPath::default(),
+        }
+    }
+
+    /// Amount of records that weren't converted or are compressed, so the form ids within their
+    /// fields can't be seen or changed
+    pub fn hidden_records(&self) -> usize {
+        self.iter_all_records()
+            .filter(|(entry, _)| match entry {
+                EntryRef::Record(RecordRef::Unknown(_) | RecordRef::Deleted(_)) => true,
+                EntryRef::Record(_) => false,
+                EntryRef::Compressed(_) => true,
+            })
+            .count()
+    }
+}
+
+/// A record that has only had its header read
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct RawRecord<'data> {
+    pub type_name: &'data BStr,
+    pub flags: u32,
+    pub id: FormId,
+    /// The field data, which may be compressed
+    pub data: &'data [u8],
+    /// The whole record, header included
+    pub raw: &'data [u8],
+}
+
+/// A record or the start of a group, as found by [walk_entries]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum RawEntry<'data> {
+    Record(RawRecord<'data>),
+    /// A group, whose entries come after it
+    Group(GroupType<'data>),
+}
+
+/// Walks over every record in [data], descending into groups.
+/// This never parses the record's fields, so it works for compressed records as well.
+pub fn walk_records<'data, F>(data: &'data [u8], func: &mut F) -> Result<(), ParseError<'data>>
+where
+    F: FnMut(RawRecord<'data>),
+{
+    walk_entries(data, &mut |entry| {
+        if let RawEntry::Record(record) = entry {
+            func(record)
+        }
+    })
+}
+
+/// [walk_records], also passing each group to [func] before its entries
+pub fn walk_entries<'data, F>(mut data: &'data [u8], func: &mut F) -> Result<(), ParseError<'data>>
+where
+    F: FnMut(RawEntry<'data>),
+{
+    while !data.is_empty() {
+        let (_, header) = take(data, RECORDH_SIZE)?;
+        let type_name = header[..4].as_bstr();
+        let size = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        if type_name == "GRUP" {
+            let group_type = group_type_of(header);
+            check_group_size(&group_type, size, data.len() - GROUPH_SIZE)?;
+            let (rest, group) = take(data, size)?;
+            func(RawEntry::Group(group_type));
+            walk_entries(&group[GROUPH_SIZE..], func)?;
+            data = rest;
+        } else {
+            let flags = u32::from_le_bytes([header[8], header[9], header[10], header[11]]);
+            let id = FormId::from_bytes([header[12], header[13], header[14], header[15]]);
+            let (rest, record) = take(data, RECORDH_SIZE + size)?;
+            func(RawEntry::Record(RawRecord {
+                type_name,
+                flags,
+                id,
+                data: &record[RECORDH_SIZE..],
+                raw: record,
+            }));
+            data = rest;
+        }
+    }
+    Ok(())
+}
+
+/// Walks over every record header in [data], descending into groups.
+/// [func] receives the record type and the record flags.
+pub fn walk_record_headers<'data, F>(
+    data: &'data [u8],
+    func: &mut F,
+) -> Result<(), ParseError<'data>>
+where
+    F: FnMut(&'data BStr, u32),
+{
+    walk_records(data, &mut |record| func(record.type_name, record.flags))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        context::ParseContext,
+        groups::common::{CommonGroupInfo, GeneralGroup, TopGroup},
+        records::Record,
+    };
+    use bstr::ByteSlice;
+
+    fn general(type_name: &'static [u8], id: u32, flags: u32) -> GeneralRecord<'static> {
+        let mut common = CommonRecordInfo::test_default();
+        common.flags.flags = flags;
+        common.id = id;
+        GeneralRecord {
+            type_name: type_name.as_bstr(),
+            common,
+            fields: Vec::new(),
+        }
+    }
+
+    fn group<'data>(
+        group_type: GroupType<'data>,
+        entries: Vec<GroupEntry<'data>>,
+    ) -> GroupEntry<'data> {
+        GroupEntry::Group(GeneralGroup {
+            group_type,
+            common: CommonGroupInfo::new(),
+            entries,
+        })
+    }
+
+    #[test]
+    fn test_iter_all_records() {
+        let mut plugin = Plugin::new("Base.esm");
+        let list = general(b"FLST", 0x800, 0);
+        let list = Record::from_general(list, &mut ParseContext::default()).unwrap();
+        plugin.push_top(Top::Group(Group::from_record(list).unwrap()));
+        let cell = FormId::new(0x801);
+        let navmesh = CompressedRecord::compress(&general(b"NAVM", 0x803, 0), 9).unwrap();
+        let children = group(
+            GroupType::CellChildren(cell),
+            vec![group(
+                GroupType::CellTemporaryChildren(cell),
+                vec![
+                    general(b"REFR", 0x802, record_flag::DELETED).into(),
+                    navmesh.into(),
+                ],
+            )],
+        );
+        let block = group(
+            GroupType::InteriorCellBlock(1),
+            vec![group(
+                GroupType::InteriorSubCellBlock(0),
+                vec![general(b"CELL", 0x801, 0).into(), children],
+            )],
+        );
+        plugin.push_top(Top::Group(Group::UnknownTop(TopGroup {
+            label: b"CELL".as_bstr(),
+            common: CommonGroupInfo::new(),
+            entries: vec![block],
+        })));
+
+        let records: Vec<(String, u32, usize, Option<FormId>)> = plugin
+            .iter_all_records()
+            .map(|(record, path)| {
+                let type_name = record.type_name().to_string();
+                (type_name, record.formid().id, path.depth(), path.parent())
+            })
+            .collect();
+        assert_eq!(
+            records,
+            vec![
+                ("TES4".to_string(), 0, 0, None),
+                ("FLST".to_string(), 0x800, 1, None),
+                ("CELL".to_string(), 0x801, 3, None),
+                ("REFR".to_string(), 0x802, 5, Some(cell)),
+                ("NAVM".to_string(), 0x803, 5, Some(cell)),
+            ]
+        );
+        let (reference, path) = plugin.iter_all_records().nth(3).unwrap();
+        assert!(matches!(reference, EntryRef::Record(RecordRef::Deleted(_))));
+        assert_eq!(path.top_label(), Some(b"CELL".as_bstr()));
+        let (_, navmesh_path) = plugin.iter_all_records().nth(4).unwrap();
+        assert_eq!(path, navmesh_path);
+        // Only the groups are copied, not the path of each record
+        let mut records = plugin.iter_all_records().skip(3);
+        let (_, first) = records.next().unwrap();
+        let (_, second) = records.next().unwrap();
+        assert!(Rc::ptr_eq(&first.groups, &second.groups));
+
+        for (mut record, path) in plugin.iter_all_records_mut() {
+            if path.parent() == Some(cell) {
+                record.common_mut().id += 0x100;
+            }
+        }
+        let ids: Vec<u32> = plugin
+            .iter_all_records()
+            .map(|(record, _)| record.formid().id)
+            .collect();
+        assert_eq!(ids, vec![0, 0x800, 0x801, 0x902, 0x903]);
+    }
+}
@@ -0,0 +1,500 @@
+use crate::{
+    config::Config, context::ParseContext, plugin::Plugin, records::common::FormId, util::crc32,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+/// Cheap identity of a plugin file, used to decide if it has to be parsed again.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct FileStamp {
+    pub modified: Option<SystemTime>,
+    pub len: u64,
+    /// Crc of the file contents, so that touched-but-unchanged files are not re-checked
+    pub crc: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    /// Parsed without error, holding the amount of top-level entries, and what is wrong with it
+    /// going by the other plugins in the directory
+    Valid {
+        entries: usize,
+        problems: Vec<Problem>,
+    },
+    /// Failed to parse
+    Invalid(String),
+    /// Validation was not requested, so the file was not parsed
+    Unchecked,
+}
+
+/// A problem with a plugin that comes from the plugins it depends on
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Problem {
+    /// A master that isn't in the directory
+    MissingMaster(String),
+    /// An override of a record that its master doesn't have, as the plugin numbers it
+    MissingRecord { master: String, id: FormId },
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Change {
+    Added(PathBuf),
+    Modified(PathBuf),
+    Removed(PathBuf),
+    /// Checked again without being parsed, as one of its masters changed
+    Revalidated(PathBuf),
+}
+impl Change {
+    pub fn path(&self) -> &Path {
+        match self {
+            Change::Added(path)
+            | Change::Modified(path)
+            | Change::Removed(path)
+            | Change::Revalidated(path) => path,
+        }
+    }
+}
+
+/// What is kept of a parsed plugin, so that it and the plugins depending on it can be checked
+/// again without parsing it
+#[derive(Debug, Clone, Default, PartialEq)]
+struct PluginIndex {
+    masters: Vec<String>,
+    /// Object indices of the records that the plugin adds
+    records: HashSet<u32>,
+    /// Records that override those of the masters
+    overrides: Vec<FormId>,
+}
+impl PluginIndex {
+    fn new(plugin: &Plugin<'_>) -> PluginIndex {
+        let masters: Vec<String> = plugin.masters().iter().map(|x| x.to_string()).collect();
+        let (ids, _) = plugin.formids_and_groups();
+        let (records, overrides): (Vec<FormId>, Vec<FormId>) = ids
+            .into_iter()
+            .partition(|id| usize::from(id.mod_index()) >= masters.len());
+        PluginIndex {
+            masters,
+            records: records.into_iter().map(|x| x.object_index()).collect(),
+            overrides,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct CacheEntry {
+    stamp: FileStamp,
+    outcome: Outcome,
+    /// None if the plugin wasn't parsed, or failed to
+    index: Option<PluginIndex>,
+}
+
+/// Remembers the state of every plugin in a directory, so that a refresh only re-parses
+/// (and re-validates) the plugins that changed since the last one.
+/// Masters rarely change during mod development, so they are effectively only checked once.
+/// Plugins are checked against the index kept of each of their masters, and when a master
+/// changes, the plugins depending on it are checked again with their own kept index.
+#[derive(Debug, Clone, Default)]
+pub struct WatchCache {
+    /// Whether changed plugins should be parsed
+    pub validate: bool,
+    entries: HashMap<PathBuf, CacheEntry>,
+}
+impl WatchCache {
+    pub fn new(validate: bool) -> WatchCache {
+        WatchCache {
+            validate,
+            entries: HashMap::new(),
+        }
+    }
+
+    pub fn outcome(&self, path: &Path) -> Option<&Outcome> {
+        self.entries.get(path).map(|entry| &entry.outcome)
+    }
+
+    pub fn stamp(&self, path: &Path) -> Option<&FileStamp> {
+        self.entries.get(path).map(|entry| &entry.stamp)
+    }
+
+    /// Checks every plugin within [dir], returning what changed since the last refresh.
+    pub fn refresh(&mut self, dir: &Path) -> std::io::Result<Vec<Change>> {
+        let mut changes = Vec::new();
+        let mut seen = Vec::new();
+
+        for path in plugin_paths(dir)? {
+            let metadata = std::fs::metadata(&path)?;
+            let modified = metadata.modified().ok();
+            let len = metadata.len();
+            seen.push(path.clone());
+
+            if let Some(entry) = self.entries.get(&path) {
+                if entry.stamp.modified == modified && entry.stamp.len == len {
+                    continue;
+                }
+            }
+
+            let data = std::fs::read(&path)?;
+            let stamp = FileStamp {
+                modified,
+                len,
+                crc: crc32(&data),
+            };
+
+            let change = match self.entries.get_mut(&path) {
+                Some(entry) if entry.stamp.crc == stamp.crc => {
+                    // Only the timestamp changed
+                    entry.stamp = stamp;
+                    continue;
+                }
+                Some(_) => Change::Modified(path.clone()),
+                None => Change::Added(path.clone()),
+            };
+
+            let (outcome, index) = if self.validate {
+                match check(&file_name(&path), &data) {
+                    Ok((entries, index)) => (
+                        Outcome::Valid {
+                            entries,
+                            problems: Vec::new(),
+                        },
+                        Some(index),
+                    ),
+                    Err(err) => (Outcome::Invalid(err), None),
+                }
+            } else {
+                (Outcome::Unchecked, None)
+            };
+            self.entries.insert(
+                path,
+                CacheEntry {
+                    stamp,
+                    outcome,
+                    index,
+                },
+            );
+            changes.push(change);
+        }
+
+        let mut removed: Vec<PathBuf> = self
+            .entries
+            .keys()
+            .filter(|path| !seen.contains(path))
+            .cloned()
+            .collect();
+        removed.sort();
+        for path in removed {
+            self.entries.remove(&path);
+            changes.push(Change::Removed(path));
+        }
+
+        self.revalidate(&mut changes);
+        Ok(changes)
+    }
+
+    /// Checks the plugins that changed, and those whose masters changed, against the indices
+    /// kept of their masters, adding the latter to [changes]
+    fn revalidate(&mut self, changes: &mut Vec<Change>) {
+        let changed: HashSet<String> = changes.iter().map(|x| file_name(x.path())).collect();
+        let by_name: HashMap<String, &CacheEntry> = self
+            .entries
+            .iter()
+            .map(|(path, entry)| (file_name(path), entry))
+            .collect();
+        let mut checked = Vec::new();
+        for (path, entry) in self.entries.iter() {
+            let index = match &entry.index {
+                Some(index) => index,
+                None => continue,
+            };
+            let own_change = changed.contains(&file_name(path));
+            let depends = index
+                .masters
+                .iter()
+                .any(|x| changed.contains(&x.to_lowercase()));
+            if !own_change && !depends {
+                continue;
+            }
+            let mut problems = Vec::new();
+            for master in index.masters.iter() {
+                if !by_name.contains_key(&master.to_lowercase()) {
+                    problems.push(Problem::MissingMaster(master.clone()));
+                }
+            }
+            for id in index.overrides.iter() {
+                let master = &index.masters[usize::from(id.mod_index())];
+                let master_index = by_name
+                    .get(&master.to_lowercase())
+                    .and_then(|x| x.index.as_ref());
+                if let Some(master_index) = master_index {
+                    if !master_index.records.contains(&id.object_index()) {
+                        problems.push(Problem::MissingRecord {
+                            master: master.clone(),
+                            id: *id,
+                        });
+                    }
+                }
+            }
+            checked.push((path.clone(), problems, !own_change));
+        }
+
+        checked.sort_by(|a, b| a.0.cmp(&b.0));
+        for (path, new_problems, dependent) in checked {
+            if let Some(CacheEntry {
+                outcome: Outcome::Valid { problems, .. },
+                ..
+            }) = self.entries.get_mut(&path)
+            {
+                *problems = new_problems;
+            }
+            if dependent {
+                changes.push(Change::Revalidated(path));
+            }
+        }
+    }
+}
+
+/// Lowercase filename of [path], which plugins refer to their masters by
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|x| x.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+}
+
+/// Parses the plugin, returning the amount of top-level entries and the index kept of it
+fn check(name: &str, data: &[u8]) -> Result<(usize, PluginIndex), String> {
+    match Plugin::parse(name, data, &mut ParseContext::default()) {
+        Ok(plugin) => Ok((plugin.tops().len(), PluginIndex::new(&plugin))),
+        Err(err) => Err(format!("{:?}", err)),
+    }
+}
+
+pub fn is_plugin_path(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| {
+            let ext = ext.to_ascii_lowercase();
+            ext == "esp" || ext == "esm" || ext == "esl"
+        })
+        .unwrap_or(false)
+}
+
+/// All plugin files directly within [dir], sorted by path.
+pub fn plugin_paths(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file() && is_plugin_path(&path) {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+/// `vivec watch [dir] [--validate] [--interval <milliseconds>]`, where the directory defaults to
+/// the data folder of the config
+pub fn watch_command(args: &[String], config: &Config) {
+    let mut dir = config.data_folder.clone();
+    let mut validate = config.validation.watch;
+    let mut interval = Duration::from_millis(1000);
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--validate" => validate = true,
+            "--interval" => {
+                let millis = args
+                    .next()
+                    .and_then(|x| x.parse().ok())
+                    .expect("Expected milliseconds after --interval");
+                interval = Duration::from_millis(millis);
+            }
+            _ => dir = Some(PathBuf::from(arg)),
+        }
+    }
+    let dir = dir.expect("Expected directory to watch");
+
+    let mut cache = WatchCache::new(validate);
+    loop {
+        let changes = cache
+            .refresh(&dir)
+            .expect("Failed to read watched directory");
+        for change in changes.iter() {
+            let kind = match change {
+                Change::Added(_) => "added",
+                Change::Modified(_) => "modified",
+                Change::Removed(_) => "removed",
+                Change::Revalidated(_) => "master changed",
+            };
+            match cache.outcome(change.path()) {
+                Some(Outcome::Valid { entries, problems }) if problems.is_empty() => println!(
+                    "{} ({}): ok, {} entries",
+                    change.path().display(),
+                    kind,
+                    entries
+                ),
+                Some(Outcome::Valid { problems, .. }) => {
+                    println!("{} ({}):", change.path().display(), kind);
+                    for problem in problems {
+                        match problem {
+                            Problem::MissingMaster(master) => {
+                                println!("  missing master {}", master)
+                            }
+                            Problem::MissingRecord { master, id } => {
+                                println!(
+                                    "  overrides [{:08X}], which {} doesn't have",
+                                    id.id, master
+                                )
+                            }
+                        }
+                    }
+                }
+                Some(Outcome::Invalid(err)) => {
+                    println!("{} ({}): invalid: {}", change.path().display(), kind, err)
+                }
+                Some(Outcome::Unchecked) | None => {
+                    println!("{} ({})", change.path().display(), kind)
+                }
+            }
+        }
+        std::thread::sleep(interval);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        context::WriteOptions,
+        groups::{common::TopGroup, Group},
+        records::{
+            common::{CommonRecordInfo, GeneralRecord},
+            Record,
+        },
+        Top,
+    };
+    use bstr::{BString, ByteSlice};
+
+    fn write_plugin(path: &Path, masters: &[&str], ids: &[u32]) {
+        let mut plugin = Plugin::new(file_name(path));
+        for master in masters {
+            plugin
+                .header_mut()
+                .unwrap()
+                .push_master(BString::from(*master));
+        }
+        let records = ids
+            .iter()
+            .map(|id| {
+                let mut common = CommonRecordInfo::test_default();
+                common.flags.flags = 0;
+                common.id = *id;
+                GeneralRecord {
+                    type_name: b"CELL".as_bstr(),
+                    common,
+                    fields: Vec::new(),
+                }
+            })
+            .map(Record::Unknown)
+            .collect();
+        let cells = TopGroup::from_records(b"CELL".as_bstr(), records).unwrap();
+        plugin.push_top(Top::Group(Group::UnknownTop(cells)));
+        plugin
+            .write_to_file(path, &WriteOptions::default())
+            .unwrap();
+    }
+
+    #[test]
+    fn test_refresh() {
+        let dir = std::env::temp_dir().join(format!("vivec_watch_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let plugin = dir.join("Test.esp");
+        std::fs::write(&plugin, b"").unwrap();
+        std::fs::write(dir.join("readme.txt"), b"not a plugin").unwrap();
+
+        let mut cache = WatchCache::new(true);
+        let changes = cache.refresh(&dir).unwrap();
+        assert_eq!(changes, vec![Change::Added(plugin.clone())]);
+        assert_eq!(
+            cache.outcome(&plugin),
+            Some(&Outcome::Valid {
+                entries: 0,
+                problems: Vec::new()
+            })
+        );
+
+        // Nothing changed
+        assert!(cache.refresh(&dir).unwrap().is_empty());
+
+        std::fs::write(&plugin, b"TES4").unwrap();
+        let changes = cache.refresh(&dir).unwrap();
+        assert_eq!(changes, vec![Change::Modified(plugin.clone())]);
+        assert!(matches!(cache.outcome(&plugin), Some(Outcome::Invalid(_))));
+
+        std::fs::remove_file(&plugin).unwrap();
+        let changes = cache.refresh(&dir).unwrap();
+        assert_eq!(changes, vec![Change::Removed(plugin.clone())]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_revalidate() {
+        let dir = std::env::temp_dir().join(format!("vivec_revalidate_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let base = dir.join("Base.esp");
+        let patch = dir.join("Patch.esp");
+        write_plugin(&base, &[], &[0x800]);
+        write_plugin(&patch, &["Base.esp"], &[0x800, 0x801, 0x0100_0800]);
+
+        let mut cache = WatchCache::new(true);
+        cache.refresh(&dir).unwrap();
+        let missing = Problem::MissingRecord {
+            master: "Base.esp".to_string(),
+            id: FormId::new(0x801),
+        };
+        assert_eq!(
+            cache.outcome(&patch),
+            Some(&Outcome::Valid {
+                entries: 2,
+                problems: vec![missing]
+            })
+        );
+
+        // Only the master is parsed again, the patch is checked with what was kept of it
+        write_plugin(&base, &[], &[0x800, 0x801]);
+        let changes = cache.refresh(&dir).unwrap();
+        assert_eq!(
+            changes,
+            vec![
+                Change::Modified(base.clone()),
+                Change::Revalidated(patch.clone())
+            ]
+        );
+        assert_eq!(
+            cache.outcome(&patch),
+            Some(&Outcome::Valid {
+                entries: 2,
+                problems: Vec::new()
+            })
+        );
+
+        std::fs::remove_file(&base).unwrap();
+        let changes = cache.refresh(&dir).unwrap();
+        assert_eq!(
+            changes,
+            vec![Change::Removed(base), Change::Revalidated(patch.clone())]
+        );
+        assert_eq!(
+            cache.outcome(&patch),
+            Some(&Outcome::Valid {
+                entries: 2,
+                problems: vec![Problem::MissingMaster("Base.esp".to_string())]
+            })
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}